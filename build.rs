@@ -188,6 +188,45 @@ mod cuda {
     }
 }
 
+#[cfg(feature = "vulkan")]
+mod vulkan {
+    pub fn build_spirv() {
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let shader_paths: Vec<std::path::PathBuf> = glob::glob("src/**/*.comp")
+            .unwrap()
+            .map(|p| p.unwrap())
+            .collect();
+
+        for path in &shader_paths {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        for path in &shader_paths {
+            let mut spirv_path: std::path::PathBuf = out_dir.clone().into();
+            spirv_path.push(path.file_stem().unwrap());
+            spirv_path.set_extension("spv");
+
+            #[cfg(feature = "ci-check")]
+            std::fs::File::create(spirv_path).unwrap();
+
+            #[cfg(not(feature = "ci-check"))]
+            {
+                let output = std::process::Command::new("glslc")
+                    .args(["--target-env=vulkan1.1", "-O"])
+                    .arg(path)
+                    .arg("-o")
+                    .arg(&spirv_path)
+                    .output()
+                    .expect("glslc not found, it ships with the Vulkan SDK");
+                assert!(
+                    output.status.success(),
+                    "glslc error while compiling {path:?}: {output:?}",
+                );
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), BuildError> {
     println!("cargo:rerun-if-changed=build.rs");
 
@@ -207,6 +246,12 @@ fn main() -> Result<(), BuildError> {
     #[cfg(feature = "cblas")]
     println!("cargo:rustc-link-lib={link_type}=cblas");
 
+    #[cfg(feature = "openblas")]
+    println!("cargo:rustc-link-lib={link_type}=openblas");
+
+    #[cfg(feature = "accelerate")]
+    println!("cargo:rustc-link-lib=framework=Accelerate");
+
     #[cfg(feature = "intel-mkl")]
     {
         let root = std::env::var("ONEAPI_ROOT").unwrap_or_else(|_| DEFAULT_ONEAPI_ROOT.to_string());
@@ -291,5 +336,8 @@ fn main() -> Result<(), BuildError> {
     #[cfg(feature = "cuda")]
     cuda::build_ptx();
 
+    #[cfg(feature = "vulkan")]
+    vulkan::build_spirv();
+
     Ok(())
 }
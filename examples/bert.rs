@@ -1,20 +1,30 @@
 use clap::Parser;
 use memmap2::MmapOptions;
 use safetensors::{
-    tensor::{Dtype, SafeTensorError, TensorView},
+    tensor::{SafeTensorError, TensorView},
     SafeTensors,
 };
 use serde::Deserialize;
 
-#[cfg(feature = "cpu")]
-use smelte_rs::cpu::f32::{Device, Tensor};
-#[cfg(feature = "cuda")]
-use smelte_rs::gpu::f32::{Device, Tensor};
-
-use smelte_rs::nn::layers::{Embedding, LayerNorm, Linear};
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use smelte_rs::backend::{Device, Tensor};
+
+use smelte_rs::checkpoint::{checkpoint_dtype, to_f32, DtypePolicy};
+use smelte_rs::nn::ids::Ids;
+use smelte_rs::nn::layers::{Dropout, Embedding, LayerNorm, Linear, LogBucketPositions};
 use smelte_rs::nn::models::bert::{
     Bert, BertAttention, BertClassifier, BertEmbeddings, BertEncoder, BertLayer, BertPooler, Mlp,
 };
+use smelte_rs::nn::models::{albert, deberta, distilbert, electra};
+use smelte_rs::nn::quantize::Quantizer;
+use smelte_rs::traits::Precision;
 use smelte_rs::SmeltError;
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -38,14 +48,50 @@ pub enum BertError {
 
 #[derive(Clone, Deserialize)]
 pub struct Config {
+    #[serde(alias = "n_heads")]
     num_attention_heads: usize,
+    #[serde(alias = "activation")]
+    hidden_act: Option<String>,
     id2label: Option<HashMap<String, String>>,
+    max_position_embeddings: Option<usize>,
+    max_relative_positions: Option<isize>,
+    model_type: Option<String>,
+    num_hidden_layers: Option<usize>,
+    pad_token_id: Option<usize>,
+    position_buckets: Option<usize>,
 }
 
 impl Config {
     pub fn id2label(&self) -> Option<&HashMap<String, String>> {
         self.id2label.as_ref()
     }
+
+    /// The position ids of `input_ids`, numbered after the pad token for RoBERTa.
+    pub fn position_ids(&self, input_ids: &Ids) -> Ids {
+        match self.model_type.as_deref() {
+            Some("roberta" | "xlm-roberta" | "camembert") => {
+                Ids::padded_positions(input_ids, self.pad_token_id.unwrap_or(1))
+            }
+            _ => Ids::positions(input_ids.shape().to_vec()),
+        }
+    }
+}
+
+/// The `hidden_dropout_prob` and `attention_probs_dropout_prob` of the configurations of
+/// BERT and RoBERTa, the dropout before the classifier being the former.
+const HIDDEN_DROPOUT: f32 = 0.1;
+const ATTENTION_DROPOUT: f32 = 0.1;
+
+/// The prefix of the tensors of the encoder, `roberta` for the RoBERTa checkpoints.
+fn model_prefix(tensors: &SafeTensors<'_>) -> &'static str {
+    if tensors
+        .tensor("roberta.embeddings.word_embeddings.weight")
+        .is_ok()
+    {
+        "roberta"
+    } else {
+        "bert"
+    }
 }
 
 pub fn get_label(id2label: Option<&HashMap<String, String>>, i: usize) -> Option<String> {
@@ -62,36 +108,14 @@ pub trait FromSafetensors<'a> {
 
 fn to_tensor<'data>(view: TensorView<'data>, device: &Device) -> Result<Tensor, SmeltError> {
     let shape = view.shape().to_vec();
-    let data = to_f32(view);
-    #[cfg(feature = "cuda")]
-    {
-        Tensor::from_cpu(&data, shape, device)
-    }
-
-    #[cfg(feature = "cpu")]
-    {
-        Tensor::from_cpu(data, shape, device)
-    }
-}
-
-pub fn to_f32(view: TensorView) -> Cow<'static, [f32]> {
-    assert_eq!(view.dtype(), Dtype::F32);
-    let v = view.data();
-    if (v.as_ptr() as usize) % 4 == 0 {
-        // SAFETY This is safe because we just checked that this
-        // was correctly aligned.
-        let data: &[f32] =
-            unsafe { std::slice::from_raw_parts(v.as_ptr() as *const f32, v.len() / 4) };
-        Cow::Borrowed(data)
-    } else {
-        let mut c = Vec::with_capacity(v.len() / 4);
-        let mut i = 0;
-        while i < v.len() {
-            c.push(f32::from_le_bytes([v[i], v[i + 1], v[i + 2], v[i + 3]]));
-            i += 4;
+    let data = match to_f32(&view)? {
+        // SAFETY The mmaped checkpoint outlives the model for the whole run.
+        Cow::Borrowed(data) => {
+            Cow::Borrowed(unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) })
         }
-        Cow::Owned(c)
-    }
+        Cow::Owned(data) => Cow::Owned(data),
+    };
+    Tensor::from_cpu(data, shape, device)
 }
 
 fn linear_from<'a>(
@@ -126,8 +150,16 @@ impl<'a> FromSafetensors<'a> for BertClassifier<Tensor> {
     where
         Self: Sized,
     {
-        let pooler = BertPooler::from_tensors(tensors, device);
         let bert = Bert::from_tensors(tensors, device);
+        // The head of RoBERTa is a pooler on the first token, without the encoder prefix,
+        // then the classifier.
+        if tensors.tensor("classifier.out_proj.weight").is_ok() {
+            let pooler = BertPooler::new(linear_from_prefix("classifier.dense", tensors, device));
+            let classifier = linear_from_prefix("classifier.out_proj", tensors, device);
+            return Self::new(bert, pooler, classifier)
+                .with_dropout(Dropout::new(HIDDEN_DROPOUT).unwrap());
+        }
+        let pooler = BertPooler::from_tensors(tensors, device);
         let (weight, bias) = if let (Ok(weight), Ok(bias)) = (
             tensors.tensor("classifier.weight"),
             tensors.tensor("classifier.bias"),
@@ -140,7 +172,7 @@ impl<'a> FromSafetensors<'a> for BertClassifier<Tensor> {
             )
         };
         let classifier = linear_from(weight, bias, device);
-        Self::new(bert, pooler, classifier)
+        Self::new(bert, pooler, classifier).with_dropout(Dropout::new(HIDDEN_DROPOUT).unwrap())
     }
 }
 impl<'a> FromSafetensors<'a> for BertPooler<Tensor> {
@@ -148,11 +180,8 @@ impl<'a> FromSafetensors<'a> for BertPooler<Tensor> {
     where
         Self: Sized,
     {
-        let pooler = linear_from(
-            tensors.tensor("bert.pooler.dense.weight").unwrap(),
-            tensors.tensor("bert.pooler.dense.bias").unwrap(),
-            device,
-        );
+        let prefix = model_prefix(tensors);
+        let pooler = linear_from_prefix(&format!("{prefix}.pooler.dense"), tensors, device);
         Self::new(pooler)
     }
 }
@@ -173,32 +202,36 @@ impl<'a> FromSafetensors<'a> for BertEmbeddings<Tensor> {
     where
         Self: Sized,
     {
+        let prefix = model_prefix(tensors);
         let input_embeddings = embedding_from(
             tensors
-                .tensor("bert.embeddings.word_embeddings.weight")
+                .tensor(&format!("{prefix}.embeddings.word_embeddings.weight"))
                 .unwrap(),
             device,
         );
         let position_embeddings = embedding_from(
             tensors
-                .tensor("bert.embeddings.position_embeddings.weight")
-                .unwrap(),
-            device,
-        );
-        let type_embeddings = embedding_from(
-            tensors
-                .tensor("bert.embeddings.token_type_embeddings.weight")
+                .tensor(&format!("{prefix}.embeddings.position_embeddings.weight"))
                 .unwrap(),
             device,
         );
-
-        let layer_norm = layer_norm_from_prefix("bert.embeddings.LayerNorm", &tensors, device);
-        BertEmbeddings::new(
-            input_embeddings,
-            position_embeddings,
-            type_embeddings,
-            layer_norm,
-        )
+        let layer_norm =
+            layer_norm_from_prefix(&format!("{prefix}.embeddings.LayerNorm"), tensors, device);
+        let type_embeddings = format!("{prefix}.embeddings.token_type_embeddings.weight");
+        let embeddings = match tensors.tensor(&type_embeddings) {
+            Ok(type_embeddings) => BertEmbeddings::new(
+                input_embeddings,
+                position_embeddings,
+                embedding_from(type_embeddings, device),
+                layer_norm,
+            ),
+            Err(_) => BertEmbeddings::without_type_embeddings(
+                input_embeddings,
+                position_embeddings,
+                layer_norm,
+            ),
+        };
+        embeddings.with_dropout(Dropout::new(HIDDEN_DROPOUT).unwrap())
     }
 }
 
@@ -216,32 +249,36 @@ fn bert_attention_from_tensors<'a>(
     tensors: &'a SafeTensors<'a>,
     device: &Device,
 ) -> BertAttention<Tensor> {
+    let prefix = model_prefix(tensors);
     let query = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.attention.self.query"),
+        &format!("{prefix}.encoder.layer.{index}.attention.self.query"),
         tensors,
         device,
     );
     let key = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.attention.self.key"),
+        &format!("{prefix}.encoder.layer.{index}.attention.self.key"),
         tensors,
         device,
     );
     let value = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.attention.self.value"),
+        &format!("{prefix}.encoder.layer.{index}.attention.self.value"),
         tensors,
         device,
     );
     let output = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.attention.output.dense"),
+        &format!("{prefix}.encoder.layer.{index}.attention.output.dense"),
         tensors,
         device,
     );
     let output_ln = layer_norm_from_prefix(
-        &format!("bert.encoder.layer.{index}.attention.output.LayerNorm"),
+        &format!("{prefix}.encoder.layer.{index}.attention.output.LayerNorm"),
         &tensors,
         device,
     );
-    BertAttention::new(query, key, value, output, output_ln)
+    BertAttention::new(query, key, value, output, output_ln).with_dropout(
+        Dropout::new(ATTENTION_DROPOUT).unwrap(),
+        Dropout::new(HIDDEN_DROPOUT).unwrap(),
+    )
 }
 
 fn bert_mlp_from_tensors<'a>(
@@ -249,22 +286,23 @@ fn bert_mlp_from_tensors<'a>(
     tensors: &'a SafeTensors<'a>,
     device: &Device,
 ) -> Mlp<Tensor> {
+    let prefix = model_prefix(tensors);
     let intermediate = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.intermediate.dense"),
+        &format!("{prefix}.encoder.layer.{index}.intermediate.dense"),
         tensors,
         device,
     );
     let output = linear_from_prefix(
-        &format!("bert.encoder.layer.{index}.output.dense"),
+        &format!("{prefix}.encoder.layer.{index}.output.dense"),
         tensors,
         device,
     );
     let output_ln = layer_norm_from_prefix(
-        &format!("bert.encoder.layer.{index}.output.LayerNorm"),
+        &format!("{prefix}.encoder.layer.{index}.output.LayerNorm"),
         &tensors,
         device,
     );
-    Mlp::new(intermediate, output, output_ln)
+    Mlp::new(intermediate, output, output_ln).with_dropout(Dropout::new(HIDDEN_DROPOUT).unwrap())
 }
 
 fn layer_norm_from_prefix<'a>(
@@ -310,21 +348,74 @@ impl<'a> FromSafetensors<'a> for BertEncoder<Tensor> {
 
 #[derive(Parser)]
 struct Args {
+    /// Model to run, a BERT, RoBERTa, DistilBERT, ALBERT, ELECTRA or DeBERTa sequence classifier
+    #[arg(short, long, default_value_t = String::from("Narsil/finbert"))]
+    model_id: String,
     /// Prompt to run
     #[arg(short, long, default_value_t = String::from("Stocks rallied and the British pound gained"))]
     prompt: String,
     /// Number of times to run the prompt
     #[arg(short, long, default_value_t = 1)]
     number: u8,
+    /// Device to run on (cpu, cuda:0, metal:0, wgpu:0, opencl:0, vulkan:0...)
+    #[arg(short, long, default_value_t = String::from(DEFAULT_DEVICE))]
+    device: String,
+    /// Records the model once and replays it for every run (cuda only)
+    #[arg(short, long)]
+    capture: bool,
+    /// Runs the `number` prompts at once, spread over one worker per cpu
+    #[arg(long)]
+    parallel: bool,
+    /// Quantizes the linear layers to int8 (cpu only)
+    #[arg(short, long)]
+    quantize: bool,
+    /// Quantizes the linear layers and their activations to int8, calibrated on the
+    /// prompt (cpu only)
+    #[arg(long)]
+    calibrate: bool,
+    /// Precision of the matmuls of the linear layers (f32, f16, bf16), the rest stays in f32.
+    /// Follows the dtype of the checkpoint by default.
+    #[arg(long)]
+    precision: Option<String>,
 }
 
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: &str = "cuda:0";
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+const DEFAULT_DEVICE: &str = "metal:0";
+#[cfg(all(feature = "wgpu", not(any(feature = "cuda", feature = "metal"))))]
+const DEFAULT_DEVICE: &str = "wgpu:0";
+#[cfg(all(
+    feature = "opencl",
+    not(any(feature = "cuda", feature = "metal", feature = "wgpu"))
+))]
+const DEFAULT_DEVICE: &str = "opencl:0";
+#[cfg(all(
+    feature = "vulkan",
+    not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl"
+    ))
+))]
+const DEFAULT_DEVICE: &str = "vulkan:0";
+#[cfg(not(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+)))]
+const DEFAULT_DEVICE: &str = "cpu";
+
 pub fn run() -> Result<(), BertError> {
     let start = std::time::Instant::now();
     let args = Args::parse();
     let string = args.prompt;
     let n = args.number;
 
-    let model_id = "Narsil/finbert";
+    let model_id = &args.model_id;
 
     let model_id_slug = model_id.replace('/', "-");
 
@@ -366,14 +457,47 @@ pub fn run() -> Result<(), BertError> {
     let config_str: String = std::fs::read_to_string(filename).expect("Could not read config");
     let config: Config = serde_json::from_str(&config_str).expect("Could not parse Config");
 
-    #[cfg(feature = "cuda")]
-    let device = Device::new(0).unwrap();
+    let device: Device = args.device.parse().unwrap();
 
-    #[cfg(feature = "cpu")]
-    let device = Device {};
-
-    let mut bert = BertClassifier::from_tensors(&tensors, &device);
+    let num_heads = config.num_attention_heads;
+    let mut bert = match config.model_type.as_deref() {
+        Some("distilbert") => distilbert::classifier_from(&tensors, num_heads, &device).unwrap(),
+        Some("electra") => electra::classifier_from(&tensors, num_heads, &device).unwrap(),
+        Some("albert") => {
+            let num_layers = config.num_hidden_layers.unwrap_or(12);
+            albert::classifier_from(&tensors, num_heads, num_layers, &device).unwrap()
+        }
+        Some("deberta-v2") => {
+            // A `max_relative_positions` of -1 is the `max_position_embeddings`.
+            let max_position = match config.max_relative_positions {
+                Some(max_position) if max_position > 0 => max_position as usize,
+                _ => config.max_position_embeddings.unwrap_or(512),
+            };
+            let buckets = config.position_buckets.unwrap_or(256);
+            let positions = LogBucketPositions::new(buckets, max_position).unwrap();
+            deberta::classifier_from(&tensors, num_heads, positions, &device).unwrap()
+        }
+        _ => BertClassifier::from_tensors(&tensors, &device),
+    };
     bert.set_num_heads(config.num_attention_heads);
+    if let Some(hidden_act) = &config.hidden_act {
+        bert.set_activation(hidden_act.parse().unwrap());
+    }
+    if args.quantize {
+        bert.quantize_dynamic().unwrap();
+    } else {
+        let policy = match args.precision.as_deref() {
+            None => DtypePolicy::Auto,
+            Some("f32") => DtypePolicy::Convert(Precision::F32),
+            Some("f16") => DtypePolicy::Convert(Precision::F16),
+            Some("bf16") => DtypePolicy::Convert(Precision::BF16),
+            Some(precision) => panic!("Unsupported precision {precision:?}"),
+        };
+        let dtype = checkpoint_dtype(&tensors);
+        let precision = policy.precision(dtype);
+        println!("Checkpoint in {dtype:?}, matmuls in {precision:?}");
+        bert.set_precision(precision).unwrap();
+    }
 
     println!("Loaded {:?}", start.elapsed());
 
@@ -382,23 +506,64 @@ pub fn run() -> Result<(), BertError> {
 
     println!("Loaded & encoded {:?}", start.elapsed());
 
+    let shape = vec![encoded.get_ids().len()];
+    let input_ids = Ids::from_u32(encoded.get_ids(), shape.clone()).unwrap();
+    let position_ids = config.position_ids(&input_ids);
+    let type_ids = Ids::from_u32(encoded.get_type_ids(), shape).unwrap();
+
+    if args.calibrate {
+        let (input_ids, position_ids, type_ids) = (
+            input_ids.data().to_vec(),
+            position_ids.data().to_vec(),
+            type_ids.data().to_vec(),
+        );
+        let mut quantizer = Quantizer::new();
+        quantizer
+            .observe(&mut bert, &[(input_ids, position_ids, type_ids)])
+            .unwrap();
+        quantizer.quantize(&mut bert).unwrap();
+        println!("Calibrated {:?}", start.elapsed());
+    }
+
+    if args.capture {
+        bert.capture(encoded.get_ids().len()).unwrap();
+        println!("Captured {:?}", start.elapsed());
+    }
+
+    if args.parallel {
+        println!("Running bert inference on {n} copies of {string:?}");
+        let inference_start = std::time::Instant::now();
+        let batch = (0..n)
+            .map(|_| {
+                (
+                    input_ids.data().to_vec(),
+                    position_ids.data().to_vec(),
+                    type_ids.data().to_vec(),
+                )
+            })
+            .collect();
+        let probs = bert.run_batch(batch, 0).unwrap();
+        println!("Probs {:?}", probs[0].cpu_data().unwrap());
+        println!("Inference in {:?}", inference_start.elapsed());
+        return Ok(());
+    }
+
     for _ in 0..n {
         println!("Running bert inference on {string:?}");
         let inference_start = std::time::Instant::now();
-        let input_ids: Vec<_> = encoded.get_ids().iter().map(|i| *i as usize).collect();
-        let position_ids: Vec<_> = (0..input_ids.len()).collect();
-        let type_ids: Vec<_> = encoded.get_type_ids().iter().map(|i| *i as usize).collect();
-        let probs = bert.run(input_ids, position_ids, type_ids).unwrap();
+        let probs = bert
+            .run_ids(&input_ids, &position_ids, &type_ids, None)
+            .unwrap()
+            .remove(0);
 
         let id2label = config.id2label();
-        let mut outputs: Vec<_> = probs
-            .cpu_data()
-            .unwrap()
-            .iter()
-            .enumerate()
-            .map(|(i, &p)| (get_label(id2label, i).unwrap_or(format!("LABEL_{}", i)), p))
+        let dim = probs.shape().len() - 1;
+        let (probs, labels) = probs.topk(probs.shape()[dim], dim).unwrap();
+        let outputs: Vec<_> = labels
+            .into_iter()
+            .zip(probs.cpu_data().unwrap())
+            .map(|(i, p)| (get_label(id2label, i).unwrap_or(format!("LABEL_{}", i)), p))
             .collect();
-        outputs.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
         println!("Probs {:?}", outputs);
         println!("Inference in {:?}", inference_start.elapsed());
     }
@@ -407,9 +572,23 @@ pub fn run() -> Result<(), BertError> {
 }
 
 fn main() {
-    #[cfg(not(any(feature = "cuda", feature = "cpu")))]
-    unreachable!("Requires cuda/cpu feature");
-
-    #[cfg(any(feature = "cuda", feature = "cpu"))]
+    #[cfg(not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    )))]
+    unreachable!("Requires cuda/metal/wgpu/opencl/vulkan/cpu feature");
+
+    #[cfg(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    ))]
     run().unwrap()
 }
@@ -0,0 +1,226 @@
+use clap::Parser;
+use memmap2::MmapOptions;
+use safetensors::{tensor::SafeTensorError, SafeTensors};
+use serde::Deserialize;
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use smelte_rs::backend::{Device, Tensor};
+
+use image::imageops::FilterType;
+use smelte_rs::nn::models::clip::{clip_from, ClipConfig};
+use smelte_rs::traits::TensorSoftmax;
+use std::fs::File;
+use thiserror::Error;
+use tokenizers::Tokenizer;
+
+#[derive(Debug, Error)]
+pub enum ClipError {
+    #[error("i/o error")]
+    IOError(#[from] std::io::Error),
+    #[error("safetensor error")]
+    SafeTensorError(#[from] SafeTensorError),
+    #[error("image error")]
+    ImageError(#[from] image::ImageError),
+    #[error("JSON parsing error")]
+    JSONError(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TowerConfig {
+    num_attention_heads: usize,
+    hidden_act: Option<String>,
+    image_size: Option<usize>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    text_config: TowerConfig,
+    vision_config: TowerConfig,
+}
+
+/// The normalization of the pixels, the `preprocessor_config.json` of the checkpoint.
+#[derive(Clone, Deserialize)]
+pub struct PreprocessorConfig {
+    image_mean: [f32; 3],
+    image_std: [f32; 3],
+}
+
+impl Default for PreprocessorConfig {
+    fn default() -> Self {
+        Self {
+            image_mean: [0.4815, 0.4578, 0.4082],
+            image_std: [0.2686, 0.2613, 0.2758],
+        }
+    }
+}
+
+/// The pixels (3, image_size, image_size) of the image `filename`, resized and center
+/// cropped then normalized like the image processor of transformers.
+fn pixel_values(
+    filename: &str,
+    image_size: usize,
+    preprocessor: &PreprocessorConfig,
+) -> Result<Vec<f32>, ClipError> {
+    let size = image_size as u32;
+    let image = image::open(filename)?
+        .resize_to_fill(size, size, FilterType::CatmullRom)
+        .to_rgb8();
+    let mut pixels = vec![0.0; 3 * image_size * image_size];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let (x, y) = (x as usize, y as usize);
+        for (c, &value) in pixel.0.iter().enumerate() {
+            let (mean, std) = (preprocessor.image_mean[c], preprocessor.image_std[c]);
+            pixels[(c * image_size + y) * image_size + x] = (value as f32 / 255.0 - mean) / std;
+        }
+    }
+    Ok(pixels)
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Model to run, a CLIP dual encoder
+    #[arg(short, long, default_value_t = String::from("openai/clip-vit-base-patch32"))]
+    model_id: String,
+    /// Image to classify
+    #[arg(short, long)]
+    image: String,
+    /// The candidate labels of the image, separated by commas
+    #[arg(short, long, default_value_t = String::from("a photo of a cat,a photo of a dog"))]
+    labels: String,
+    /// Device to run on (cpu, cuda:0, metal:0, wgpu:0, opencl:0, vulkan:0...)
+    #[arg(short, long, default_value_t = String::from(DEFAULT_DEVICE))]
+    device: String,
+}
+
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: &str = "cuda:0";
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+const DEFAULT_DEVICE: &str = "metal:0";
+#[cfg(all(feature = "wgpu", not(any(feature = "cuda", feature = "metal"))))]
+const DEFAULT_DEVICE: &str = "wgpu:0";
+#[cfg(all(
+    feature = "opencl",
+    not(any(feature = "cuda", feature = "metal", feature = "wgpu"))
+))]
+const DEFAULT_DEVICE: &str = "opencl:0";
+#[cfg(all(
+    feature = "vulkan",
+    not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl"
+    ))
+))]
+const DEFAULT_DEVICE: &str = "vulkan:0";
+#[cfg(not(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+)))]
+const DEFAULT_DEVICE: &str = "cpu";
+
+pub fn run() -> Result<(), ClipError> {
+    let start = std::time::Instant::now();
+    let args = Args::parse();
+
+    let model_id = &args.model_id;
+    let model_id_slug = model_id.replace('/', "-");
+
+    let filename = format!("model-{model_id_slug}.safetensors");
+    if !std::path::Path::new(&filename).exists() {
+        println!(
+            r#"Model not found, try downloading it with \n
+    `curl https://huggingface.co/{model_id}/resolve/main/model.safetensors -o model-{model_id_slug}.safetensors -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/tokenizer.json -o tokenizer-{model_id_slug}.json -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/config.json -o config-{model_id_slug}.json -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/preprocessor_config.json -o preprocessor-{model_id_slug}.json -L`
+    "#
+        );
+    }
+
+    let file = File::open(filename)?;
+    let buffer = unsafe { MmapOptions::new().map(&file)? };
+    let tensors = SafeTensors::deserialize(&buffer)?;
+    println!("Safetensors {:?}", start.elapsed());
+
+    let filename = format!("tokenizer-{model_id_slug}.json");
+    let tokenizer = Tokenizer::from_file(filename).unwrap();
+
+    let filename = format!("config-{model_id_slug}.json");
+    let config_str: String = std::fs::read_to_string(filename).expect("Could not read config");
+    let config: Config = serde_json::from_str(&config_str)?;
+
+    // The mean and std of the checkpoints of OpenAI by default.
+    let filename = format!("preprocessor-{model_id_slug}.json");
+    let preprocessor = match std::fs::read_to_string(filename) {
+        Ok(preprocessor_str) => serde_json::from_str(&preprocessor_str)?,
+        Err(_) => PreprocessorConfig::default(),
+    };
+
+    let mut clip_config = ClipConfig::new(
+        config.text_config.num_attention_heads,
+        config.vision_config.num_attention_heads,
+    );
+    if let Some(hidden_act) = &config.text_config.hidden_act {
+        clip_config.activation = hidden_act.parse().unwrap();
+    }
+    let device: Device = args.device.parse().unwrap();
+    let model = clip_from(&tensors, &clip_config, &device).unwrap();
+    println!("Loaded {:?}", start.elapsed());
+
+    let labels: Vec<&str> = args.labels.split(',').map(str::trim).collect();
+    let texts: Vec<Vec<usize>> = labels
+        .iter()
+        .map(|&label| {
+            let encoded = tokenizer.encode(label, true).unwrap();
+            encoded.get_ids().iter().map(|&id| id as usize).collect()
+        })
+        .collect();
+    let image_size = config.vision_config.image_size.unwrap_or(224);
+    let pixels = pixel_values(&args.image, image_size, &preprocessor)?;
+    let shape = vec![1, 3, image_size, image_size];
+    let pixel_values = Tensor::from_cpu(pixels, shape, &device).unwrap();
+    println!("Loaded & preprocessed {:?}", start.elapsed());
+
+    println!("Running clip inference on {:?}", args.image);
+    let inference_start = std::time::Instant::now();
+    let logits_per_text = model.similarity(&texts, &[pixel_values]).unwrap();
+    let mut probs = logits_per_text.reshape(vec![1, labels.len()]).unwrap();
+    Tensor::softmax(&mut probs).unwrap();
+    let outputs: Vec<_> = labels.into_iter().zip(probs.cpu_data().unwrap()).collect();
+    println!("Probs {:?}", outputs);
+    println!("Inference in {:?}", inference_start.elapsed());
+    Ok(())
+}
+
+fn main() {
+    #[cfg(not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    )))]
+    unreachable!("Requires cuda/metal/wgpu/opencl/vulkan/cpu feature");
+
+    #[cfg(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    ))]
+    run().unwrap()
+}
@@ -1,17 +1,23 @@
 use clap::Parser;
 use memmap2::MmapOptions;
 use safetensors::{
-    tensor::{Dtype, SafeTensorError, TensorView},
+    tensor::{SafeTensorError, TensorView},
     SafeTensors,
 };
 use serde::Deserialize;
 
-#[cfg(feature = "cpu")]
-use smelte_rs::cpu::f32::{Device, Tensor};
-#[cfg(feature = "cuda")]
-use smelte_rs::gpu::f32::{Device, Tensor};
-
-use smelte_rs::nn::layers::{Embedding, LayerNorm, LinearT, UnbiasedLinear};
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use smelte_rs::backend::{Device, Tensor};
+
+use smelte_rs::checkpoint::to_f32;
+use smelte_rs::nn::layers::{Dropout, Embedding, LayerNorm, LinearT, UnbiasedLinear};
 use smelte_rs::nn::models::gpt2::{Gpt2, Gpt2Attention, Gpt2Layer, Gpt2Model, Mlp};
 use smelte_rs::SmeltError;
 use std::borrow::Cow;
@@ -60,36 +66,14 @@ pub trait FromSafetensors<'a> {
 
 fn to_tensor<'data>(view: TensorView<'data>, device: &Device) -> Result<Tensor, SmeltError> {
     let shape = view.shape().to_vec();
-    let data = to_f32(view);
-    #[cfg(feature = "cuda")]
-    {
-        Tensor::from_cpu(&data, shape, device)
-    }
-
-    #[cfg(feature = "cpu")]
-    {
-        Tensor::from_cpu(data, shape, device)
-    }
-}
-
-pub fn to_f32(view: TensorView) -> Cow<'static, [f32]> {
-    assert_eq!(view.dtype(), Dtype::F32);
-    let v = view.data();
-    if (v.as_ptr() as usize) % 4 == 0 {
-        // SAFETY This is safe because we just checked that this
-        // was correctly aligned.
-        let data: &[f32] =
-            unsafe { std::slice::from_raw_parts(v.as_ptr() as *const f32, v.len() / 4) };
-        Cow::Borrowed(data)
-    } else {
-        let mut c = Vec::with_capacity(v.len() / 4);
-        let mut i = 0;
-        while i < v.len() {
-            c.push(f32::from_le_bytes([v[i], v[i + 1], v[i + 2], v[i + 3]]));
-            i += 4;
+    let data = match to_f32(&view)? {
+        // SAFETY The mmaped checkpoint outlives the model for the whole run.
+        Cow::Borrowed(data) => {
+            Cow::Borrowed(unsafe { std::slice::from_raw_parts(data.as_ptr(), data.len()) })
         }
-        Cow::Owned(c)
-    }
+        Cow::Owned(data) => Cow::Owned(data),
+    };
+    Tensor::from_cpu(data, shape, device)
 }
 
 fn linear_from<'a>(
@@ -103,10 +87,6 @@ fn linear_from<'a>(
     )
 }
 
-fn unbiased_linear_from<'a>(weights: TensorView<'a>, device: &Device) -> UnbiasedLinear<Tensor> {
-    UnbiasedLinear::new(to_tensor(weights, device).unwrap())
-}
-
 fn linear_from_prefix<'a>(
     prefix: &str,
     tensors: &'a SafeTensors<'a>,
@@ -119,6 +99,11 @@ fn linear_from_prefix<'a>(
     )
 }
 
+/// The `embd_pdrop`, `attn_pdrop` and `resid_pdrop` of the configurations of GPT-2.
+const EMBD_PDROP: f32 = 0.1;
+const ATTN_PDROP: f32 = 0.1;
+const RESID_PDROP: f32 = 0.1;
+
 fn embedding_from<'a>(weights: TensorView<'a>, device: &Device) -> Embedding<Tensor> {
     Embedding::new(to_tensor(weights, device).unwrap())
 }
@@ -132,9 +117,10 @@ impl<'a> FromSafetensors<'a> for Gpt2<Tensor> {
         let wpe = embedding_from(tensors.tensor("wpe.weight").unwrap(), device);
         let h = Gpt2Model::from_tensors(tensors, device);
         let ln_f = layer_norm_from_prefix("ln_f", &tensors, device);
-        let lm_head = unbiased_linear_from(tensors.tensor("wte.weight").unwrap(), device);
+        // The head is tied to the input embeddings.
+        let lm_head = UnbiasedLinear::tied(&wte);
         // TODO number of heads
-        Gpt2::new(wte, wpe, h, ln_f, lm_head, 12)
+        Gpt2::new(wte, wpe, h, ln_f, lm_head, 12).with_dropout(Dropout::new(EMBD_PDROP).unwrap())
     }
 }
 
@@ -156,7 +142,10 @@ fn gpt2_attention_from_tensors<'a>(
 ) -> Gpt2Attention<Tensor> {
     let c_attn = linear_from_prefix(&format!("h.{index}.attn.c_attn"), tensors, device);
     let c_proj = linear_from_prefix(&format!("h.{index}.attn.c_proj"), tensors, device);
-    Gpt2Attention::new(c_attn, c_proj)
+    Gpt2Attention::new(c_attn, c_proj).with_dropout(
+        Dropout::new(ATTN_PDROP).unwrap(),
+        Dropout::new(RESID_PDROP).unwrap(),
+    )
 }
 
 fn gpt2_mlp_from_tensors<'a>(
@@ -166,7 +155,7 @@ fn gpt2_mlp_from_tensors<'a>(
 ) -> Mlp<Tensor> {
     let c_fc = linear_from_prefix(&format!("h.{index}.mlp.c_fc"), tensors, device);
     let c_proj = linear_from_prefix(&format!("h.{index}.mlp.c_proj"), tensors, device);
-    Mlp::new(c_fc, c_proj)
+    Mlp::new(c_fc, c_proj).with_dropout(Dropout::new(RESID_PDROP).unwrap())
 }
 
 fn layer_norm_from_prefix<'a>(
@@ -218,8 +207,41 @@ struct Args {
     /// Number of times to run the prompt
     #[arg(short, long, default_value_t = 1)]
     number: u8,
+    /// Device to run on (cpu, cuda:0, metal:0, wgpu:0, opencl:0, vulkan:0...)
+    #[arg(short, long, default_value_t = String::from(DEFAULT_DEVICE))]
+    device: String,
 }
 
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: &str = "cuda:0";
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+const DEFAULT_DEVICE: &str = "metal:0";
+#[cfg(all(feature = "wgpu", not(any(feature = "cuda", feature = "metal"))))]
+const DEFAULT_DEVICE: &str = "wgpu:0";
+#[cfg(all(
+    feature = "opencl",
+    not(any(feature = "cuda", feature = "metal", feature = "wgpu"))
+))]
+const DEFAULT_DEVICE: &str = "opencl:0";
+#[cfg(all(
+    feature = "vulkan",
+    not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl"
+    ))
+))]
+const DEFAULT_DEVICE: &str = "vulkan:0";
+#[cfg(not(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+)))]
+const DEFAULT_DEVICE: &str = "cpu";
+
 pub fn run() -> Result<(), Gpt2Error> {
     let start = std::time::Instant::now();
     let args = Args::parse();
@@ -268,11 +290,7 @@ pub fn run() -> Result<(), Gpt2Error> {
     let config_str: String = std::fs::read_to_string(filename).expect("Could not read config");
     let config: Config = serde_json::from_str(&config_str).expect("Could not parse Config");
 
-    #[cfg(feature = "cuda")]
-    let device = Device::new(0).unwrap();
-
-    #[cfg(feature = "cpu")]
-    let device = Device {};
+    let device: Device = args.device.parse().unwrap();
 
     let mut gpt2 = Gpt2::from_tensors(&tensors, &device);
     gpt2.set_num_heads(config.n_head);
@@ -307,9 +325,23 @@ pub fn run() -> Result<(), Gpt2Error> {
 }
 
 fn main() {
-    #[cfg(not(any(feature = "cuda", feature = "cpu")))]
-    unreachable!("Requires cuda/cpu feature");
-
-    #[cfg(any(feature = "cuda", feature = "cpu"))]
+    #[cfg(not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    )))]
+    unreachable!("Requires cuda/metal/wgpu/opencl/vulkan/cpu feature");
+
+    #[cfg(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    ))]
     run().unwrap()
 }
@@ -0,0 +1,321 @@
+use clap::Parser;
+use memmap2::MmapOptions;
+use safetensors::{tensor::SafeTensorError, SafeTensors};
+use serde::Deserialize;
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use smelte_rs::backend::Device;
+
+use smelte_rs::nn::models::llama::{causal_lm_from_shards, LlamaConfig};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use thiserror::Error;
+use tokenizers::Tokenizer;
+
+#[derive(Debug, Error)]
+pub enum LlamaError {
+    #[error("i/o error")]
+    IOError(#[from] std::io::Error),
+    #[error("safetensor error")]
+    SafeTensorError(#[from] SafeTensorError),
+    #[error("JSON parsing error")]
+    JSONError(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RopeScaling {
+    #[serde(rename = "type")]
+    kind: String,
+    factor: f32,
+}
+
+/// A token, or a list of tokens like the chat checkpoints of Llama-3.
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TokenIds {
+    One(usize),
+    Many(Vec<usize>),
+}
+
+/// The `model.safetensors.index.json` of the checkpoints split in several files.
+#[derive(Clone, Deserialize)]
+pub struct ShardIndex {
+    weight_map: HashMap<String, String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    num_attention_heads: usize,
+    num_key_value_heads: Option<usize>,
+    num_hidden_layers: usize,
+    rms_norm_eps: f32,
+    rope_theta: Option<f32>,
+    rope_scaling: Option<RopeScaling>,
+    max_position_embeddings: usize,
+    hidden_act: Option<String>,
+    tie_word_embeddings: Option<bool>,
+    sliding_window: Option<usize>,
+    eos_token_id: Option<TokenIds>,
+    attention_dropout: Option<f32>,
+}
+
+impl Config {
+    fn llama_config(&self) -> LlamaConfig {
+        let mut config = LlamaConfig {
+            num_kv_heads: self.num_key_value_heads.unwrap_or(self.num_attention_heads),
+            num_hidden_layers: self.num_hidden_layers,
+            rms_norm_eps: self.rms_norm_eps,
+            max_position_embeddings: self.max_position_embeddings,
+            tie_word_embeddings: self.tie_word_embeddings.unwrap_or(false),
+            sliding_window: self.sliding_window,
+            attention_dropout: self.attention_dropout.unwrap_or(0.0),
+            ..LlamaConfig::new(self.num_attention_heads)
+        };
+        if let Some(theta) = self.rope_theta {
+            config.rotary.theta = theta;
+        }
+        if let Some(hidden_act) = &self.hidden_act {
+            config.activation = hidden_act.parse().unwrap();
+        }
+        match &self.eos_token_id {
+            Some(TokenIds::One(id)) => config.eos_token_ids = vec![*id],
+            Some(TokenIds::Many(ids)) => config.eos_token_ids = ids.clone(),
+            None => (),
+        }
+        match &self.rope_scaling {
+            Some(scaling) => config
+                .with_rope_scaling(&scaling.kind, scaling.factor)
+                .unwrap(),
+            None => config,
+        }
+    }
+}
+
+/// The prompt of the `history` of (user message, assistant reply) in the chat template
+/// `template`, up to the reply to the last message.
+fn chat_prompt(template: &str, system: &str, history: &[(String, String)]) -> String {
+    let mut prompt = String::new();
+    if template == "llama2" || template == "mistral" {
+        // The tokenizer adds the bos token of the first message, Mistral doesn't repeat it
+        // and has no system message.
+        for (i, (message, reply)) in history.iter().enumerate() {
+            if i > 0 && template == "llama2" {
+                prompt.push_str("<s>");
+            }
+            if i == 0 && template == "llama2" && !system.is_empty() {
+                prompt.push_str(&format!(
+                    "[INST] <<SYS>>\n{system}\n<</SYS>>\n\n{message} [/INST]"
+                ));
+            } else {
+                prompt.push_str(&format!("[INST] {message} [/INST]"));
+            }
+            if !reply.is_empty() {
+                prompt.push_str(&format!(" {reply} </s>"));
+            }
+        }
+    } else {
+        if !system.is_empty() {
+            prompt.push_str(&format!("<|system|>\n{system}</s>\n"));
+        }
+        for (message, reply) in history {
+            prompt.push_str(&format!("<|user|>\n{message}</s>\n<|assistant|>\n"));
+            if !reply.is_empty() {
+                prompt.push_str(&format!("{reply}</s>\n"));
+            }
+        }
+    }
+    prompt
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Model to run, a LLaMA or Mistral checkpoint in a single safetensors file or in the
+    /// shards of a `model.safetensors.index.json`
+    #[arg(short, long, default_value_t = String::from("TinyLlama/TinyLlama-1.1B-Chat-v1.0"))]
+    model_id: String,
+    /// The text to complete, the model chats on the standard input without it
+    #[arg(short, long)]
+    prompt: Option<String>,
+    /// The chat template, "zephyr" (TinyLlama, Zephyr), "llama2" or "mistral" ([INST]
+    /// messages)
+    #[arg(short, long, default_value_t = String::from("zephyr"))]
+    template: String,
+    /// The system message of the chat
+    #[arg(short, long, default_value_t = String::from("You are a friendly assistant."))]
+    system: String,
+    /// Maximum number of generated tokens
+    #[arg(short, long, default_value_t = 128)]
+    number: usize,
+    /// Quantizes the weights to int8 on the cpu
+    #[arg(short, long)]
+    quantize: bool,
+    /// Device to run on (cpu, cuda:0, metal:0, wgpu:0, opencl:0, vulkan:0...)
+    #[arg(short, long, default_value_t = String::from(DEFAULT_DEVICE))]
+    device: String,
+}
+
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: &str = "cuda:0";
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+const DEFAULT_DEVICE: &str = "metal:0";
+#[cfg(all(feature = "wgpu", not(any(feature = "cuda", feature = "metal"))))]
+const DEFAULT_DEVICE: &str = "wgpu:0";
+#[cfg(all(
+    feature = "opencl",
+    not(any(feature = "cuda", feature = "metal", feature = "wgpu"))
+))]
+const DEFAULT_DEVICE: &str = "opencl:0";
+#[cfg(all(
+    feature = "vulkan",
+    not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl"
+    ))
+))]
+const DEFAULT_DEVICE: &str = "vulkan:0";
+#[cfg(not(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+)))]
+const DEFAULT_DEVICE: &str = "cpu";
+
+pub fn run() -> Result<(), LlamaError> {
+    let start = std::time::Instant::now();
+    let args = Args::parse();
+
+    let model_id = &args.model_id;
+    let model_id_slug = model_id.replace('/', "-");
+
+    // The larger checkpoints are split in shards listed by an index, every shard being
+    // downloaded next to it as `{model_id_slug}-{shard}`.
+    let filename = format!("model-{model_id_slug}.safetensors");
+    let index_filename = format!("model-{model_id_slug}.safetensors.index.json");
+    let filenames = if std::path::Path::new(&filename).exists() {
+        vec![filename]
+    } else if std::path::Path::new(&index_filename).exists() {
+        let index: ShardIndex = serde_json::from_str(&std::fs::read_to_string(index_filename)?)?;
+        let shards: BTreeSet<_> = index.weight_map.into_values().collect();
+        shards
+            .into_iter()
+            .map(|shard| format!("{model_id_slug}-{shard}"))
+            .collect()
+    } else {
+        println!(
+            r#"Model not found, try downloading it with \n
+    `curl https://huggingface.co/{model_id}/resolve/main/model.safetensors -o model-{model_id_slug}.safetensors -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/tokenizer.json -o tokenizer-{model_id_slug}.json -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/config.json -o config-{model_id_slug}.json -L`
+    For the sharded checkpoints, download the index and every shard it lists
+    `curl https://huggingface.co/{model_id}/resolve/main/model.safetensors.index.json -o model-{model_id_slug}.safetensors.index.json -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/model-00001-of-00002.safetensors -o {model_id_slug}-model-00001-of-00002.safetensors -L`
+    "#
+        );
+        vec![filename]
+    };
+
+    let buffers = filenames
+        .iter()
+        .map(|filename| {
+            let file = File::open(filename)?;
+            unsafe { MmapOptions::new().map(&file) }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let shards = buffers
+        .iter()
+        .map(|buffer| SafeTensors::deserialize(buffer))
+        .collect::<Result<Vec<_>, _>>()?;
+    println!("Safetensors {:?}", start.elapsed());
+
+    let filename = format!("tokenizer-{model_id_slug}.json");
+    let tokenizer = Tokenizer::from_file(filename).unwrap();
+
+    let filename = format!("config-{model_id_slug}.json");
+    let config_str: String = std::fs::read_to_string(filename).expect("Could not read config");
+    let config: Config = serde_json::from_str(&config_str)?;
+
+    let device: Device = args.device.parse().unwrap();
+    let mut model = causal_lm_from_shards(&shards, &config.llama_config(), &device).unwrap();
+    if args.quantize {
+        model.quantize_dynamic().unwrap();
+    }
+    println!("Loaded {:?}", start.elapsed());
+
+    let generate = |prompt: &str| {
+        let encoded = tokenizer.encode(prompt, true).unwrap();
+        let input_ids: Vec<_> = encoded.get_ids().iter().map(|&id| id as usize).collect();
+        let inference_start = std::time::Instant::now();
+        let output_ids = model.generate(&input_ids, args.number).unwrap();
+        let output_ids: Vec<_> = output_ids.iter().map(|&id| id as u32).collect();
+        let elapsed = inference_start.elapsed();
+        let tokens_per_second = output_ids.len() as f64 / elapsed.as_secs_f64();
+        let text = tokenizer.decode(&output_ids, true).unwrap();
+        (text, tokens_per_second)
+    };
+
+    if let Some(prompt) = &args.prompt {
+        println!("Running llama inference on {prompt:?}");
+        let (text, tokens_per_second) = generate(prompt.as_str());
+        println!("{prompt}{text}");
+        println!("{tokens_per_second:.2} tokens/s");
+        return Ok(());
+    }
+
+    let mut history: Vec<(String, String)> = vec![];
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut message = String::new();
+        if stdin.lock().read_line(&mut message)? == 0 {
+            return Ok(());
+        }
+        let message = message.trim();
+        if message.is_empty() {
+            continue;
+        }
+        history.push((message.to_string(), String::new()));
+        let prompt = chat_prompt(&args.template, &args.system, &history);
+        let (reply, tokens_per_second) = generate(prompt.as_str());
+        let reply = reply.trim().to_string();
+        println!("{reply}");
+        println!("({tokens_per_second:.2} tokens/s)");
+        if let Some(last) = history.last_mut() {
+            last.1 = reply;
+        }
+    }
+}
+
+fn main() {
+    #[cfg(not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    )))]
+    unreachable!("Requires cuda/metal/wgpu/opencl/vulkan/cpu feature");
+
+    #[cfg(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    ))]
+    run().unwrap()
+}
@@ -0,0 +1,215 @@
+use clap::Parser;
+use memmap2::MmapOptions;
+use safetensors::{tensor::SafeTensorError, SafeTensors};
+use serde::Deserialize;
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use smelte_rs::backend::{Device, Tensor};
+
+use image::imageops::FilterType;
+use smelte_rs::nn::models::vit::classifier_from;
+use smelte_rs::traits::TensorSoftmax;
+use std::collections::HashMap;
+use std::fs::File;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ViTError {
+    #[error("i/o error")]
+    IOError(#[from] std::io::Error),
+    #[error("safetensor error")]
+    SafeTensorError(#[from] SafeTensorError),
+    #[error("image error")]
+    ImageError(#[from] image::ImageError),
+    #[error("JSON parsing error")]
+    JSONError(#[from] serde_json::Error),
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Config {
+    num_attention_heads: usize,
+    image_size: usize,
+    id2label: Option<HashMap<String, String>>,
+}
+
+/// The normalization of the pixels, the `preprocessor_config.json` of the checkpoint.
+#[derive(Clone, Deserialize)]
+pub struct PreprocessorConfig {
+    image_mean: [f32; 3],
+    image_std: [f32; 3],
+}
+
+impl Default for PreprocessorConfig {
+    fn default() -> Self {
+        Self {
+            image_mean: [0.5; 3],
+            image_std: [0.5; 3],
+        }
+    }
+}
+
+pub fn get_label(id2label: Option<&HashMap<String, String>>, i: usize) -> Option<String> {
+    let id2label: &HashMap<String, String> = id2label?;
+    let label: String = id2label.get(&format!("{}", i))?.to_string();
+    Some(label)
+}
+
+/// The pixels (3, image_size, image_size) of the image `filename`, resized and
+/// normalized like the image processor of transformers.
+fn pixel_values(
+    filename: &str,
+    image_size: usize,
+    preprocessor: &PreprocessorConfig,
+) -> Result<Vec<f32>, ViTError> {
+    let size = image_size as u32;
+    let image = image::open(filename)?
+        .resize_exact(size, size, FilterType::Triangle)
+        .to_rgb8();
+    let mut pixels = vec![0.0; 3 * image_size * image_size];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let (x, y) = (x as usize, y as usize);
+        for (c, &value) in pixel.0.iter().enumerate() {
+            let (mean, std) = (preprocessor.image_mean[c], preprocessor.image_std[c]);
+            pixels[(c * image_size + y) * image_size + x] = (value as f32 / 255.0 - mean) / std;
+        }
+    }
+    Ok(pixels)
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Model to run, a ViT image classifier
+    #[arg(short, long, default_value_t = String::from("google/vit-base-patch16-224"))]
+    model_id: String,
+    /// Image to classify
+    #[arg(short, long)]
+    image: String,
+    /// Number of labels to print
+    #[arg(short, long, default_value_t = 5)]
+    top_k: usize,
+    /// Device to run on (cpu, cuda:0, metal:0, wgpu:0, opencl:0, vulkan:0...)
+    #[arg(short, long, default_value_t = String::from(DEFAULT_DEVICE))]
+    device: String,
+}
+
+#[cfg(feature = "cuda")]
+const DEFAULT_DEVICE: &str = "cuda:0";
+#[cfg(all(feature = "metal", not(feature = "cuda")))]
+const DEFAULT_DEVICE: &str = "metal:0";
+#[cfg(all(feature = "wgpu", not(any(feature = "cuda", feature = "metal"))))]
+const DEFAULT_DEVICE: &str = "wgpu:0";
+#[cfg(all(
+    feature = "opencl",
+    not(any(feature = "cuda", feature = "metal", feature = "wgpu"))
+))]
+const DEFAULT_DEVICE: &str = "opencl:0";
+#[cfg(all(
+    feature = "vulkan",
+    not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl"
+    ))
+))]
+const DEFAULT_DEVICE: &str = "vulkan:0";
+#[cfg(not(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+)))]
+const DEFAULT_DEVICE: &str = "cpu";
+
+pub fn run() -> Result<(), ViTError> {
+    let start = std::time::Instant::now();
+    let args = Args::parse();
+
+    let model_id = &args.model_id;
+    let model_id_slug = model_id.replace('/', "-");
+
+    let filename = format!("model-{model_id_slug}.safetensors");
+    if !std::path::Path::new(&filename).exists() {
+        println!(
+            r#"Model not found, try downloading it with \n
+    `curl https://huggingface.co/{model_id}/resolve/main/model.safetensors -o model-{model_id_slug}.safetensors -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/config.json -o config-{model_id_slug}.json -L`
+    `curl https://huggingface.co/{model_id}/resolve/main/preprocessor_config.json -o preprocessor-{model_id_slug}.json -L`
+    "#
+        );
+    }
+
+    let file = File::open(filename)?;
+    let buffer = unsafe { MmapOptions::new().map(&file)? };
+    let tensors = SafeTensors::deserialize(&buffer)?;
+    println!("Safetensors {:?}", start.elapsed());
+
+    let filename = format!("config-{model_id_slug}.json");
+    let config_str: String = std::fs::read_to_string(filename).expect("Could not read config");
+    let config: Config = serde_json::from_str(&config_str)?;
+
+    // The mean and std of 0.5 of the ViT checkpoints of Google by default.
+    let filename = format!("preprocessor-{model_id_slug}.json");
+    let preprocessor = match std::fs::read_to_string(filename) {
+        Ok(preprocessor_str) => serde_json::from_str(&preprocessor_str)?,
+        Err(_) => PreprocessorConfig::default(),
+    };
+
+    let device: Device = args.device.parse().unwrap();
+    let model = classifier_from(&tensors, config.num_attention_heads, &device).unwrap();
+    println!("Loaded {:?}", start.elapsed());
+
+    let image_size = config.image_size;
+    let pixels = pixel_values(&args.image, image_size, &preprocessor)?;
+    let shape = vec![1, 3, image_size, image_size];
+    let pixel_values = Tensor::from_cpu(pixels, shape, &device).unwrap();
+    println!("Loaded & preprocessed {:?}", start.elapsed());
+
+    println!("Running vit inference on {:?}", args.image);
+    let inference_start = std::time::Instant::now();
+    let mut probs = model.forward(&pixel_values).unwrap();
+    Tensor::softmax(&mut probs).unwrap();
+    let top_k = args.top_k.min(probs.shape()[1]);
+    let (probs, labels) = probs.topk(top_k, 1).unwrap();
+    let outputs: Vec<_> = labels
+        .into_iter()
+        .zip(probs.cpu_data().unwrap())
+        .map(|(i, p)| {
+            let label = get_label(config.id2label.as_ref(), i);
+            (label.unwrap_or(format!("LABEL_{}", i)), p)
+        })
+        .collect();
+    println!("Probs {:?}", outputs);
+    println!("Inference in {:?}", inference_start.elapsed());
+    Ok(())
+}
+
+fn main() {
+    #[cfg(not(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    )))]
+    unreachable!("Requires cuda/metal/wgpu/opencl/vulkan/cpu feature");
+
+    #[cfg(any(
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan",
+        feature = "cpu"
+    ))]
+    run().unwrap()
+}
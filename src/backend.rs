@@ -0,0 +1,2088 @@
+use crate::traits::{
+    Activation, Conv1dConfig, Conv2dConfig, Device as DeviceTrait, DeviceCapture,
+    FusedAttentionConfig, Precision, QuantizedWeight, RangeObserver, Replay, Tensor as TensorTrait,
+    TensorActivation, TensorAdd, TensorArgmax, TensorClamp, TensorCompare, TensorConv1d,
+    TensorConv2d, TensorCopy, TensorCumsum, TensorFusedAttention, TensorGelu, TensorMask,
+    TensorMatmul, TensorMatmulT, TensorMul, TensorMulScalar, TensorNarrow, TensorNormalize,
+    TensorOps, TensorPad, TensorPositionMask, TensorPrecision, TensorQuantize, TensorReduce,
+    TensorReshape, TensorRotary, TensorSelect, TensorSoftmax, TensorSoftmaxDim, TensorSub,
+    TensorTanh, TensorToDevice, TensorTopK, TensorTopP, TensorTriangle, TensorUnary,
+};
+use crate::SmeltError;
+use std::borrow::Cow;
+use std::ops::RangeBounds;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[cfg(feature = "cpu")]
+use crate::cpu::f32 as cpu_f32;
+#[cfg(feature = "cuda")]
+use crate::gpu::f32 as cuda_f32;
+#[cfg(feature = "metal")]
+use crate::gpu::metal::f32 as metal_f32;
+#[cfg(feature = "opencl")]
+use crate::gpu::opencl::f32 as opencl_f32;
+#[cfg(feature = "vulkan")]
+use crate::gpu::vulkan::f32 as vulkan_f32;
+#[cfg(feature = "wgpu")]
+use crate::gpu::wgpu::f32 as wgpu_f32;
+
+/// A device chosen at runtime, among all the backends compiled in.
+/// ```
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use smelte_rs::backend::{Device, Tensor};
+///
+/// let device: Device = "cpu".parse().unwrap();
+/// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+/// assert_eq!(tensor.shape(), vec![2, 2]);
+/// # }
+/// ```
+#[derive(Clone)]
+pub enum Device {
+    /// A cpu device
+    #[cfg(feature = "cpu")]
+    Cpu(cpu_f32::Device),
+    /// A cuda device
+    #[cfg(feature = "cuda")]
+    Cuda(cuda_f32::Device),
+    /// A metal device
+    #[cfg(feature = "metal")]
+    Metal(metal_f32::Device),
+    /// A wgpu device
+    #[cfg(feature = "wgpu")]
+    Wgpu(wgpu_f32::Device),
+    /// An OpenCL device
+    #[cfg(feature = "opencl")]
+    OpenCl(opencl_f32::Device),
+    /// A Vulkan device
+    #[cfg(feature = "vulkan")]
+    Vulkan(vulkan_f32::Device),
+}
+
+/// The data of a [Tensor], the tensor of the underlying backend.
+#[derive(Clone)]
+pub enum Storage {
+    /// Data on the cpu
+    #[cfg(feature = "cpu")]
+    Cpu(cpu_f32::Tensor),
+    /// Data on a cuda device
+    #[cfg(feature = "cuda")]
+    Cuda(cuda_f32::Tensor),
+    /// Data on a metal device
+    #[cfg(feature = "metal")]
+    Metal(metal_f32::Tensor),
+    /// Data on a wgpu device
+    #[cfg(feature = "wgpu")]
+    Wgpu(wgpu_f32::Tensor),
+    /// Data on an OpenCL device
+    #[cfg(feature = "opencl")]
+    OpenCl(opencl_f32::Tensor),
+    /// Data on a Vulkan device
+    #[cfg(feature = "vulkan")]
+    Vulkan(vulkan_f32::Tensor),
+}
+
+/// A tensor living on a [Device] chosen at runtime.
+#[derive(Clone)]
+pub struct Tensor {
+    device: Device,
+    storage: Storage,
+}
+
+/// A boolean mask living on a [Device], see [TensorMask]. Only the cpu and cuda
+/// backends have masks.
+/// ```
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use smelte_rs::backend::{Device, Mask, Tensor};
+/// use smelte_rs::traits::TensorMask;
+///
+/// let device = Device::cpu();
+/// let mut x = Tensor::from_cpu(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+/// let padding = Mask::from_bools(&[false, true], vec![2], &device).unwrap();
+/// Tensor::masked_fill(&mut x, &padding, f32::NEG_INFINITY).unwrap();
+/// Tensor::masked_softmax(&mut x, &padding).unwrap();
+/// assert_eq!(x.cpu_data().unwrap(), [1.0, 0.0, 1.0, 0.0]);
+/// # }
+/// ```
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+#[derive(Clone)]
+pub enum Mask {
+    /// A mask on the cpu
+    #[cfg(feature = "cpu")]
+    Cpu(crate::cpu::Mask),
+    /// A mask on a cuda device
+    #[cfg(feature = "cuda")]
+    Cuda(cuda_f32::Mask),
+}
+
+impl Device {
+    /// The cpu device
+    #[cfg(feature = "cpu")]
+    pub fn cpu() -> Self {
+        Self::Cpu(cpu_f32::Device::new())
+    }
+
+    /// The cuda device `device_id`
+    #[cfg(feature = "cuda")]
+    pub fn cuda(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self::Cuda(cuda_f32::Device::new(device_id)?))
+    }
+
+    /// The metal device `device_id`
+    #[cfg(feature = "metal")]
+    pub fn metal(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self::Metal(metal_f32::Device::new(device_id)?))
+    }
+
+    /// The wgpu device `device_id`
+    #[cfg(feature = "wgpu")]
+    pub fn wgpu(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self::Wgpu(wgpu_f32::Device::new(device_id)?))
+    }
+
+    /// The OpenCL device `device_id`
+    #[cfg(feature = "opencl")]
+    pub fn opencl(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self::OpenCl(opencl_f32::Device::new(device_id)?))
+    }
+
+    /// The Vulkan device `device_id`
+    #[cfg(feature = "vulkan")]
+    pub fn vulkan(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self::Vulkan(vulkan_f32::Device::new(device_id)?))
+    }
+
+    /// The name of the backend of this device
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(_) => "cpu",
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => "cuda",
+            #[cfg(feature = "metal")]
+            Self::Metal(_) => "metal",
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(_) => "wgpu",
+            #[cfg(feature = "opencl")]
+            Self::OpenCl(_) => "opencl",
+            #[cfg(feature = "vulkan")]
+            Self::Vulkan(_) => "vulkan",
+        }
+    }
+}
+
+impl FromStr for Device {
+    type Err = SmeltError;
+
+    /// Parses `cpu`, `cuda`, `cuda:1`, `metal:0`, `wgpu`, `opencl:0`, `vulkan:0`... The device id defaults to 0.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || SmeltError::InvalidDevice(name.to_string());
+        let (backend, device_id) = match name.split_once(':') {
+            Some((backend, id)) => (backend, id.parse::<usize>().map_err(|_| invalid())?),
+            None => (name, 0),
+        };
+        match backend {
+            #[cfg(feature = "cpu")]
+            "cpu" if device_id == 0 => Ok(Self::cpu()),
+            #[cfg(feature = "cuda")]
+            "cuda" => Self::cuda(device_id),
+            #[cfg(feature = "metal")]
+            "metal" => Self::metal(device_id),
+            #[cfg(feature = "wgpu")]
+            "wgpu" => Self::wgpu(device_id),
+            #[cfg(feature = "opencl")]
+            "opencl" => Self::opencl(device_id),
+            #[cfg(feature = "vulkan")]
+            "vulkan" => Self::vulkan(device_id),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Storage {
+    /// The name of the backend holding the data
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(_) => "cpu",
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => "cuda",
+            #[cfg(feature = "metal")]
+            Self::Metal(_) => "metal",
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(_) => "wgpu",
+            #[cfg(feature = "opencl")]
+            Self::OpenCl(_) => "opencl",
+            #[cfg(feature = "vulkan")]
+            Self::Vulkan(_) => "vulkan",
+        }
+    }
+
+    fn shape(&self) -> &[usize] {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(t) => t.shape(),
+            #[cfg(feature = "cuda")]
+            Self::Cuda(t) => t.shape(),
+            #[cfg(feature = "metal")]
+            Self::Metal(t) => t.shape(),
+            #[cfg(feature = "wgpu")]
+            Self::Wgpu(t) => t.shape(),
+            #[cfg(feature = "opencl")]
+            Self::OpenCl(t) => t.shape(),
+            #[cfg(feature = "vulkan")]
+            Self::Vulkan(t) => t.shape(),
+        }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    pub fn shape(&self) -> &[usize] {
+        self.storage.shape()
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The backend tensor
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// A mutable borrow of the backend tensor
+    pub fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Creates a new nulled tensor with given shape
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let storage = match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(d) => Storage::Cpu(cpu_f32::Tensor::zeros_on(shape, d)?),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Storage::Cuda(cuda_f32::Tensor::zeros(shape, d)?),
+            #[cfg(feature = "metal")]
+            Device::Metal(d) => Storage::Metal(metal_f32::Tensor::zeros(shape, d)?),
+            #[cfg(feature = "wgpu")]
+            Device::Wgpu(d) => Storage::Wgpu(wgpu_f32::Tensor::zeros(shape, d)?),
+            #[cfg(feature = "opencl")]
+            Device::OpenCl(d) => Storage::OpenCl(opencl_f32::Tensor::zeros(shape, d)?),
+            #[cfg(feature = "vulkan")]
+            Device::Vulkan(d) => Storage::Vulkan(vulkan_f32::Tensor::zeros(shape, d)?),
+        };
+        Ok(Self {
+            device: device.clone(),
+            storage,
+        })
+    }
+
+    /// Creates a tensor from cpu data. The cpu backend keeps borrowed data as is, unless
+    /// the NUMA policy of the device moves it.
+    pub fn from_cpu<T>(data: T, shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError>
+    where
+        T: Into<Cow<'static, [f32]>>,
+    {
+        let data = data.into();
+        let storage = match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(d) => Storage::Cpu(cpu_f32::Tensor::from_cpu(data, shape, d)?),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Storage::Cuda(cuda_f32::Tensor::from_cpu(&data, shape, d)?),
+            #[cfg(feature = "metal")]
+            Device::Metal(d) => Storage::Metal(metal_f32::Tensor::from_cpu(&data, shape, d)?),
+            #[cfg(feature = "wgpu")]
+            Device::Wgpu(d) => Storage::Wgpu(wgpu_f32::Tensor::from_cpu(&data, shape, d)?),
+            #[cfg(feature = "opencl")]
+            Device::OpenCl(d) => Storage::OpenCl(opencl_f32::Tensor::from_cpu(&data, shape, d)?),
+            #[cfg(feature = "vulkan")]
+            Device::Vulkan(d) => Storage::Vulkan(vulkan_f32::Tensor::from_cpu(&data, shape, d)?),
+        };
+        Ok(Self {
+            device: device.clone(),
+            storage,
+        })
+    }
+
+    /// A tensor of uniform items in `[0, 1)`, the same for a given `seed` on the cpu and
+    /// cuda (see [crate::random::next_seed]).
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let tensor = Tensor::rand_uniform(vec![2, 3], 42, &device).unwrap();
+    /// assert!(tensor.cpu_data().unwrap().iter().all(|v| (0.0..1.0).contains(v)));
+    /// # }
+    /// ```
+    pub fn rand_uniform(shape: Vec<usize>, seed: u64, device: &Device) -> Result<Self, SmeltError> {
+        Self::rand(shape, seed, device, false)
+    }
+
+    /// A tensor of standard normal items, see [Tensor::rand_uniform].
+    pub fn rand_normal(shape: Vec<usize>, seed: u64, device: &Device) -> Result<Self, SmeltError> {
+        Self::rand(shape, seed, device, true)
+    }
+
+    fn rand(
+        shape: Vec<usize>,
+        seed: u64,
+        device: &Device,
+        normal: bool,
+    ) -> Result<Self, SmeltError> {
+        let storage = match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(d) => {
+                let mut tensor = cpu_f32::Tensor::zeros_on(shape, d)?;
+                if normal {
+                    crate::random::fill_normal(tensor.data_mut(), seed);
+                } else {
+                    crate::random::fill_uniform(tensor.data_mut(), seed);
+                }
+                Storage::Cpu(tensor)
+            }
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) if normal => {
+                Storage::Cuda(cuda_f32::Tensor::rand_normal(shape, seed, d)?)
+            }
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Storage::Cuda(cuda_f32::Tensor::rand_uniform(shape, seed, d)?),
+            #[allow(unreachable_patterns)]
+            device => {
+                return Err(SmeltError::Unsupported {
+                    operation: "rand",
+                    backend: device.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: device.clone(),
+            storage,
+        })
+    }
+
+    /// Copies the tensor onto `device`, which can belong to another backend.
+    /// Copies between cuda devices stay on the gpus, the others go through the host.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let tensor = Tensor::from_cpu(vec![1.0, 2.0], vec![2], &device).unwrap();
+    /// let copy = tensor.to_device(&device).unwrap();
+    /// assert_eq!(copy.cpu_data().unwrap(), [1.0, 2.0]);
+    /// # }
+    /// ```
+    pub fn to_device(&self, device: &Device) -> Result<Self, SmeltError> {
+        let storage = match (&self.storage, device) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(t), Device::Cpu(_)) => Storage::Cpu(t.clone()),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(t), Device::Cuda(d)) => Storage::Cuda(t.to_device(d)?),
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Self::from_cpu(self.cpu_data()?, self.shape().to_vec(), device);
+            }
+        };
+        Ok(Self {
+            device: device.clone(),
+            storage,
+        })
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Ok(t.data().to_vec()),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => t.cpu_data(),
+            #[cfg(feature = "metal")]
+            Storage::Metal(t) => t.cpu_data(),
+            #[cfg(feature = "wgpu")]
+            Storage::Wgpu(t) => t.cpu_data(),
+            #[cfg(feature = "opencl")]
+            Storage::OpenCl(t) => t.cpu_data(),
+            #[cfg(feature = "vulkan")]
+            Storage::Vulkan(t) => t.cpu_data(),
+        }
+    }
+
+    /// The items `start..start + len` of the dimension `dim`, the other dimensions are
+    /// kept whole. Only the cpu and cuda backends implement it, the cpu one borrows
+    /// contiguous slices of borrowed tensors.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let data = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    /// let tensor = Tensor::from_cpu(data, vec![3, 2], &device).unwrap();
+    /// let cls = tensor.narrow(0, 0, 1).unwrap();
+    /// assert_eq!(cls.cpu_data().unwrap(), [1.0, 2.0]);
+    /// # }
+    /// ```
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.narrow(dim, start, len)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.narrow(dim, start, len)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "narrow",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// The parts of `sizes` items of the dimension `dim`, see [Tensor::narrow]. The
+    /// sizes must add up to the size of the dimension, like `[768, 768, 768]` to split
+    /// the fused query, key and value of GPT-2.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let qkv = Tensor::zeros(vec![4, 3 * 8], &device).unwrap();
+    /// let parts = qkv.chunk(1, 3).unwrap();
+    /// assert_eq!(parts.len(), 3);
+    /// assert_eq!(parts[2].shape(), [4, 8]);
+    /// # }
+    /// ```
+    pub fn split(&self, dim: usize, sizes: &[usize]) -> Result<Vec<Self>, SmeltError> {
+        let parts: Vec<Storage> = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => t.split(dim, sizes)?.into_iter().map(Storage::Cpu).collect(),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => t
+                .split(dim, sizes)?
+                .into_iter()
+                .map(Storage::Cuda)
+                .collect(),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "split",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(self.siblings(parts))
+    }
+
+    /// [Tensor::split] in `chunks` parts of the same size, except the last one which can
+    /// be smaller.
+    pub fn chunk(&self, dim: usize, chunks: usize) -> Result<Vec<Self>, SmeltError> {
+        let parts: Vec<Storage> = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => t
+                .chunk(dim, chunks)?
+                .into_iter()
+                .map(Storage::Cpu)
+                .collect(),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => t
+                .chunk(dim, chunks)?
+                .into_iter()
+                .map(Storage::Cuda)
+                .collect(),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "chunk",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(self.siblings(parts))
+    }
+
+    /// Wraps the backend tensors `parts` on the device of `self`.
+    fn siblings(&self, parts: Vec<Storage>) -> Vec<Self> {
+        parts
+            .into_iter()
+            .map(|storage| Self {
+                device: self.device.clone(),
+                storage,
+            })
+            .collect()
+    }
+
+    /// The tensor with its dimensions reordered, the dimension `i` of the result being
+    /// the dimension `dims[i]` of `self`, like the (batch_size, sequence_length,
+    /// num_heads, head_dim) to (batch_size, num_heads, sequence_length, head_dim) of the
+    /// attention. The data is copied. Only the cpu and cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let tensor = Tensor::zeros(vec![1, 5, 12, 64], &device).unwrap();
+    /// let heads = tensor.permute(&[0, 2, 1, 3]).unwrap();
+    /// assert_eq!(heads.shape(), [1, 12, 5, 64]);
+    /// # }
+    /// ```
+    pub fn permute(&self, dims: &[usize]) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.permute(dims)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.permute(dims)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "permute",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// [Tensor::permute] swapping the dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Self, SmeltError> {
+        let mut dims: Vec<usize> = (0..self.shape().len()).collect();
+        if dim0 < dims.len() && dim1 < dims.len() {
+            dims.swap(dim0, dim1);
+        }
+        self.permute(&dims)
+    }
+
+    /// The tensor expanded to `shape`: the dimensions of 1 are repeated and new dimensions
+    /// can be added on the left, like a single key head shared by the query heads. The
+    /// views of the cpu and cuda backends expand without copies, this copies the items.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let key = Tensor::zeros(vec![1, 1, 5, 64], &device).unwrap();
+    /// let key = key.expand(&[1, 12, 5, 64]).unwrap();
+    /// assert_eq!(key.shape(), [1, 12, 5, 64]);
+    /// # }
+    /// ```
+    pub fn expand(&self, shape: &[usize]) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.expand(shape)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.expand(shape)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "expand",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// The tensor tiled `repeats` times along every dimension, like torch. Extra repeats
+    /// add dimensions on the left.
+    pub fn repeat(&self, repeats: &[usize]) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.repeat(repeats)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.repeat(repeats)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "repeat",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// The same data with the shape `shape`, which must hold as many items. Nothing is
+    /// copied. Only the cpu and cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let tensor = Tensor::zeros(vec![2, 5, 768], &device).unwrap();
+    /// let tensor = tensor.reshape(vec![10, 768]).unwrap();
+    /// assert_eq!(tensor.shape(), [10, 768]);
+    /// # }
+    /// ```
+    pub fn reshape(self, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        let storage = match self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.reshape(shape)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.reshape(shape)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "reshape",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device,
+            storage,
+        })
+    }
+
+    /// The items `indices` of the dimension `dim`, in that order and possibly repeated,
+    /// like the rows of an embedding or the beams kept by a search. Only the cpu and
+    /// cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let data = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    /// let tensor = Tensor::from_cpu(data, vec![3, 2], &device).unwrap();
+    /// let rows = tensor.index_select(0, &[2, 0]).unwrap();
+    /// assert_eq!(rows.cpu_data().unwrap(), [5.0, 6.0, 1.0, 2.0]);
+    /// # }
+    /// ```
+    pub fn index_select(&self, dim: usize, indices: &[usize]) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.index_select(dim, indices)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.index_select(dim, indices)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "index_select",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// Writes the items of `values` into the items `indices` of the dimension `dim`, the
+    /// inverse of [Tensor::index_select], like the update of a key value cache. Only the
+    /// cpu and cuda backends implement it.
+    pub fn index_put(
+        &mut self,
+        dim: usize,
+        indices: &[usize],
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        match (&mut self.storage, &values.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(t), Storage::Cpu(values)) => t.index_put(dim, indices, values),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(t), Storage::Cuda(values)) => t.index_put(dim, indices, values),
+            #[allow(unreachable_patterns)]
+            (t, values) if t.name() == values.name() => Err(SmeltError::Unsupported {
+                operation: "index_put",
+                backend: t.name(),
+            }),
+            #[allow(unreachable_patterns)]
+            (t, values) => Err(SmeltError::BackendMismatch {
+                expected: t.name(),
+                got: values.name(),
+            }),
+        }
+    }
+
+    /// Writes the items of `values` into the items `start..start + values.shape()[dim]`
+    /// of the dimension `dim`, the inverse of [Tensor::narrow], like the new tokens of a
+    /// preallocated cache. Only the cpu and cuda backends implement it.
+    pub fn narrow_put(
+        &mut self,
+        dim: usize,
+        start: usize,
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        match (&mut self.storage, &values.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(t), Storage::Cpu(values)) => t.narrow_put(dim, start, values),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(t), Storage::Cuda(values)) => t.narrow_put(dim, start, values),
+            #[allow(unreachable_patterns)]
+            (t, values) if t.name() == values.name() => Err(SmeltError::Unsupported {
+                operation: "narrow_put",
+                backend: t.name(),
+            }),
+            #[allow(unreachable_patterns)]
+            (t, values) => Err(SmeltError::BackendMismatch {
+                expected: t.name(),
+                got: values.name(),
+            }),
+        }
+    }
+
+    /// The index of the largest item along the dimension `dim`, for every other index,
+    /// computed on the device. Only the cpu and cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let probs = Tensor::from_cpu(vec![0.1, 0.7, 0.2], vec![1, 3], &device).unwrap();
+    /// assert_eq!(probs.argmax(1).unwrap(), [1]);
+    /// # }
+    /// ```
+    pub fn argmax(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => t.argmax(dim),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => t.argmax(dim),
+            #[allow(unreachable_patterns)]
+            storage => Err(SmeltError::Unsupported {
+                operation: "argmax",
+                backend: storage.name(),
+            }),
+        }
+    }
+
+    /// The index of the smallest item along the dimension `dim`, see [Tensor::argmax].
+    pub fn argmin(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => t.argmin(dim),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => t.argmin(dim),
+            #[allow(unreachable_patterns)]
+            storage => Err(SmeltError::Unsupported {
+                operation: "argmin",
+                backend: storage.name(),
+            }),
+        }
+    }
+
+    /// The `k` largest items along the dimension `dim` in decreasing order, and their
+    /// indices, computed on the device. Only the cpu and cuda backends implement it.
+    pub fn topk(&self, k: usize, dim: usize) -> Result<(Self, Vec<usize>), SmeltError> {
+        let (storage, indices) = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => {
+                let (values, indices) = t.topk(k, dim)?;
+                (Storage::Cpu(values), indices)
+            }
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => {
+                let (values, indices) = t.topk(k, dim)?;
+                (Storage::Cuda(values), indices)
+            }
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "topk",
+                    backend: storage.name(),
+                })
+            }
+        };
+        let values = Self {
+            device: self.device.clone(),
+            storage,
+        };
+        Ok((values, indices))
+    }
+
+    /// The items sorted along the dimension `dim` on the device, and their indices in
+    /// `self`. The sort is stable. Only the cpu and cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let probs = Tensor::from_cpu(vec![0.1, 0.6, 0.3], vec![3], &device).unwrap();
+    /// let (values, indices) = probs.sort(0, true).unwrap();
+    /// assert_eq!(values.cpu_data().unwrap(), [0.6, 0.3, 0.1]);
+    /// assert_eq!(indices, [1, 2, 0]);
+    /// # }
+    /// ```
+    pub fn sort(&self, dim: usize, descending: bool) -> Result<(Self, Vec<usize>), SmeltError> {
+        let (storage, indices) = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => {
+                let (values, indices) = t.sort(dim, descending)?;
+                (Storage::Cpu(values), indices)
+            }
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => {
+                let (values, indices) = t.sort(dim, descending)?;
+                (Storage::Cuda(values), indices)
+            }
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "sort",
+                    backend: storage.name(),
+                })
+            }
+        };
+        let values = Self {
+            device: self.device.clone(),
+            storage,
+        };
+        Ok((values, indices))
+    }
+
+    /// The concatenation of `tensors` along `dim`, they must live on the same backend.
+    /// Only the cpu and cuda backends implement it.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let past = Tensor::from_cpu(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+    /// let present = Tensor::from_cpu(vec![5.0, 6.0], vec![1, 2], &device).unwrap();
+    /// let keys = Tensor::cat(&[&past, &present], 0).unwrap();
+    /// assert_eq!(keys.shape(), [3, 2]);
+    /// # }
+    /// ```
+    pub fn cat(tensors: &[&Self], dim: usize) -> Result<Self, SmeltError> {
+        let first = tensors
+            .first()
+            .ok_or(SmeltError::VectorTooSmall { minimum: 1 })?;
+        if let Some(tensor) = tensors
+            .iter()
+            .find(|t| t.storage.name() != first.storage.name())
+        {
+            return Err(SmeltError::BackendMismatch {
+                expected: first.storage.name(),
+                got: tensor.storage.name(),
+            });
+        }
+        let storage = match &first.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(_) => {
+                let tensors: Vec<_> = tensors
+                    .iter()
+                    .filter_map(|t| match &t.storage {
+                        Storage::Cpu(t) => Some(t),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    })
+                    .collect();
+                Storage::Cpu(cpu_f32::Tensor::cat(&tensors, dim)?)
+            }
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(_) => {
+                let tensors: Vec<_> = tensors
+                    .iter()
+                    .filter_map(|t| match &t.storage {
+                        Storage::Cuda(t) => Some(t),
+                        #[allow(unreachable_patterns)]
+                        _ => None,
+                    })
+                    .collect();
+                Storage::Cuda(cuda_f32::Tensor::cat(&tensors, dim)?)
+            }
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "cat",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: first.device.clone(),
+            storage,
+        })
+    }
+
+    /// [Tensor::narrow] with a range, like `tensor.slice(0, ..1)` for the CLS token.
+    pub fn slice(&self, dim: usize, range: impl RangeBounds<usize>) -> Result<Self, SmeltError> {
+        let storage = match &self.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(t) => Storage::Cpu(t.slice(dim, range)?),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(t) => Storage::Cuda(t.slice(dim, range)?),
+            #[allow(unreachable_patterns)]
+            storage => {
+                return Err(SmeltError::Unsupported {
+                    operation: "slice",
+                    backend: storage.name(),
+                })
+            }
+        };
+        Ok(Self {
+            device: self.device.clone(),
+            storage,
+        })
+    }
+
+    /// Batched matrix multiplication of `self` (batch, m, k) by `other` (batch, k, n)
+    /// into a new (batch, m, n) tensor, like the scores of every attention head at once.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let query = Tensor::zeros(vec![12, 5, 64], &device).unwrap();
+    /// let key_t = Tensor::zeros(vec![12, 64, 7], &device).unwrap();
+    /// let scores = query.bmm(&key_t).unwrap();
+    /// assert_eq!(scores.shape(), [12, 5, 7]);
+    /// # }
+    /// ```
+    pub fn bmm(&self, other: &Self) -> Result<Self, SmeltError> {
+        let (a, b) = (self.shape(), other.shape());
+        if a.len() != 3 || b.len() != 3 {
+            return Err(SmeltError::InvalidRank { expected_rank: 3 });
+        }
+        let mut out = Self::zeros(vec![a[0], a[1], b[2]], &self.device)?;
+        match (&self.storage, &other.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b), Storage::Cpu(c)) => cpu_f32::bmm(a, b, c)?,
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b), Storage::Cuda(c)) => cuda_f32::bmm(a, b, c)?,
+            // The other backends batch their matmuls over the leading dimensions.
+            #[allow(unreachable_patterns)]
+            _ => Self::matmul(self, other, &mut out)?,
+        }
+        Ok(out)
+    }
+
+    /// Batched matrix multiplication of `self` (batch, m, k) by the transposed `other`
+    /// (batch, n, k) into a new (batch, m, n) tensor, without copying `other` into a
+    /// transposed layout. The keys of an attention are stored this way.
+    /// ```
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use smelte_rs::backend::{Device, Tensor};
+    ///
+    /// let device = Device::cpu();
+    /// let query = Tensor::zeros(vec![12, 5, 64], &device).unwrap();
+    /// let key = Tensor::zeros(vec![12, 7, 64], &device).unwrap();
+    /// let scores = query.bmm_t(&key).unwrap();
+    /// assert_eq!(scores.shape(), [12, 5, 7]);
+    /// # }
+    /// ```
+    pub fn bmm_t(&self, other: &Self) -> Result<Self, SmeltError> {
+        let (a, b) = (self.shape(), other.shape());
+        if a.len() != 3 || b.len() != 3 {
+            return Err(SmeltError::InvalidRank { expected_rank: 3 });
+        }
+        let mut out = Self::zeros(vec![a[0], a[1], b[1]], &self.device)?;
+        Self::matmul_t(self, other, &mut out)?;
+        Ok(out)
+    }
+}
+
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+impl Mask {
+    /// Creates a new mask with given shape on `device`. Can fail if data doesn't match
+    /// the shape, or if the backend has no masks.
+    pub fn from_bools(
+        data: &[bool],
+        shape: Vec<usize>,
+        device: &Device,
+    ) -> Result<Self, SmeltError> {
+        match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(_) => Ok(Self::Cpu(crate::cpu::Mask::from_bools(data, shape)?)),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Ok(Self::Cuda(cuda_f32::Mask::from_bools(data, shape, d)?)),
+            #[allow(unreachable_patterns)]
+            device => Err(SmeltError::Unsupported {
+                operation: "mask",
+                backend: device.name(),
+            }),
+        }
+    }
+
+    /// The (length, length) mask of a causal attention on `device`, true above the
+    /// diagonal. The cuda masks are built once per length and device.
+    pub fn causal(length: usize, device: &Device) -> Result<Self, SmeltError> {
+        match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(_) => Ok(Self::Cpu(crate::cpu::Mask::causal(length))),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Ok(Self::Cuda(cuda_f32::Mask::causal(length, d)?)),
+            #[allow(unreachable_patterns)]
+            device => Err(SmeltError::Unsupported {
+                operation: "mask",
+                backend: device.name(),
+            }),
+        }
+    }
+
+    /// Creates a mask of `shape` with every item false on `device`
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        match device {
+            #[cfg(feature = "cpu")]
+            Device::Cpu(_) => Ok(Self::Cpu(crate::cpu::Mask::zeros(shape))),
+            #[cfg(feature = "cuda")]
+            Device::Cuda(d) => Ok(Self::Cuda(cuda_f32::Mask::zeros(shape, d)?)),
+            #[allow(unreachable_patterns)]
+            device => Err(SmeltError::Unsupported {
+                operation: "mask",
+                backend: device.name(),
+            }),
+        }
+    }
+
+    /// Returns a cpu vec containing copied data from the mask, `0` is false.
+    pub fn cpu_data(&self) -> Result<Vec<u8>, SmeltError> {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(m) => Ok(m.data().to_vec()),
+            #[cfg(feature = "cuda")]
+            Self::Cuda(m) => m.cpu_data(),
+        }
+    }
+
+    /// The shape of the mask
+    pub fn shape(&self) -> &[usize] {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(m) => m.shape(),
+            #[cfg(feature = "cuda")]
+            Self::Cuda(m) => m.shape(),
+        }
+    }
+
+    /// The name of the backend holding the mask
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "cpu")]
+            Self::Cpu(_) => "cpu",
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => "cuda",
+        }
+    }
+}
+
+/// Calls `$f` with the backend tensors of `$x`.
+macro_rules! unary {
+    ($x: expr, $f: path $(, $arg: expr)*) => {
+        match &mut $x.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "metal")]
+            Storage::Metal(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "wgpu")]
+            Storage::Wgpu(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "opencl")]
+            Storage::OpenCl(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "vulkan")]
+            Storage::Vulkan(x) => $f(x $(, $arg)*),
+        }
+    };
+}
+
+/// Same as [unary] for the operations only implemented by the cpu and cuda backends,
+/// the others fail with [SmeltError::Unsupported].
+macro_rules! unary_cpu_cuda {
+    ($operation: literal, $x: expr, $f: path $(, $arg: expr)*) => {
+        match &mut $x.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(x) => $f(x $(, $arg)*),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(x) => $f(x $(, $arg)*),
+            #[allow(unreachable_patterns)]
+            x => Err(SmeltError::Unsupported {
+                operation: $operation,
+                backend: x.name(),
+            }),
+        }
+    };
+}
+
+/// Calls `$f` with the backend tensors of `$a` and `$b`, which need to share the same
+/// backend.
+macro_rules! binary {
+    ($a: expr, $b: expr, $f: path $(, $arg: expr)*) => {
+        match (&$a.storage, &mut $b.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "metal")]
+            (Storage::Metal(a), Storage::Metal(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "wgpu")]
+            (Storage::Wgpu(a), Storage::Wgpu(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "opencl")]
+            (Storage::OpenCl(a), Storage::OpenCl(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "vulkan")]
+            (Storage::Vulkan(a), Storage::Vulkan(b)) => $f(a, b $(, $arg)*),
+            #[allow(unreachable_patterns)]
+            (a, b) => Err(SmeltError::BackendMismatch {
+                expected: a.name(),
+                got: b.name(),
+            }),
+        }
+    };
+}
+
+/// Same as [binary] for 3 tensors.
+macro_rules! ternary {
+    ($a: expr, $b: expr, $c: expr, $f: path $(, $arg: expr)*) => {
+        match (&$a.storage, &$b.storage, &mut $c.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b), Storage::Cpu(c)) => $f(a, b, c $(, $arg)*),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b), Storage::Cuda(c)) => $f(a, b, c $(, $arg)*),
+            #[cfg(feature = "metal")]
+            (Storage::Metal(a), Storage::Metal(b), Storage::Metal(c)) => $f(a, b, c $(, $arg)*),
+            #[cfg(feature = "wgpu")]
+            (Storage::Wgpu(a), Storage::Wgpu(b), Storage::Wgpu(c)) => $f(a, b, c $(, $arg)*),
+            #[cfg(feature = "opencl")]
+            (Storage::OpenCl(a), Storage::OpenCl(b), Storage::OpenCl(c)) => $f(a, b, c $(, $arg)*),
+            #[cfg(feature = "vulkan")]
+            (Storage::Vulkan(a), Storage::Vulkan(b), Storage::Vulkan(c)) => $f(a, b, c $(, $arg)*),
+            #[allow(unreachable_patterns)]
+            (a, b, c) => Err(SmeltError::BackendMismatch {
+                expected: a.name(),
+                got: if a.name() != b.name() {
+                    b.name()
+                } else {
+                    c.name()
+                },
+            }),
+        }
+    };
+}
+
+/// Same as [binary] for the operations only implemented by the cpu and cuda backends,
+/// the others fail with [SmeltError::Unsupported].
+macro_rules! binary_cpu_cuda {
+    ($operation: literal, $a: expr, $b: expr, $f: path $(, $arg: expr)*) => {
+        match (&$a.storage, &mut $b.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b)) => $f(a, b $(, $arg)*),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b)) => $f(a, b $(, $arg)*),
+            #[allow(unreachable_patterns)]
+            (a, b) if a.name() == b.name() => Err(SmeltError::Unsupported {
+                operation: $operation,
+                backend: a.name(),
+            }),
+            #[allow(unreachable_patterns)]
+            (a, b) => Err(SmeltError::BackendMismatch {
+                expected: a.name(),
+                got: b.name(),
+            }),
+        }
+    };
+}
+
+/// The trait calls with the tensor type inferred from the arguments, usable by the
+/// dispatch macros.
+mod generic {
+    use super::*;
+
+    pub fn copy<T: TensorCopy<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::copy(a, b)
+    }
+    pub fn add<T: TensorAdd<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::add(a, b)
+    }
+    pub fn broadcast_add<T: TensorAdd<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::broadcast_add(a, b)
+    }
+    pub fn sub<T: TensorSub<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::sub(a, b)
+    }
+    pub fn broadcast_sub<T: TensorSub<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::broadcast_sub(a, b)
+    }
+    pub fn mul<T: TensorMul<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::mul(a, b)
+    }
+    pub fn broadcast_mul<T: TensorMul<T>>(a: &T, b: &mut T) -> Result<(), SmeltError> {
+        T::broadcast_mul(a, b)
+    }
+    pub fn mul_scalar<T: TensorMulScalar<T>>(x: &mut T, factor: f32) -> Result<(), SmeltError> {
+        T::mul_scalar(x, factor)
+    }
+    pub fn normalize<T: TensorNormalize<T>>(x: &mut T, epsilon: f32) -> Result<(), SmeltError> {
+        T::normalize(x, epsilon)
+    }
+    pub fn matmul<T: TensorMatmul<T>>(a: &T, b: &T, c: &mut T) -> Result<(), SmeltError> {
+        T::matmul(a, b, c)
+    }
+    pub fn matmul_t<T: TensorMatmulT<T>>(a: &T, b: &T, c: &mut T) -> Result<(), SmeltError> {
+        T::matmul_t(a, b, c)
+    }
+    pub fn select<T: TensorSelect<T>>(w: &T, o: &mut T, ids: &[usize]) -> Result<(), SmeltError> {
+        T::select(ids, w, o)
+    }
+    pub fn exp<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::exp(x)
+    }
+    pub fn log<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::log(x)
+    }
+    pub fn sqrt<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::sqrt(x)
+    }
+    pub fn rsqrt<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::rsqrt(x)
+    }
+    pub fn sigmoid<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::sigmoid(x)
+    }
+    pub fn erf<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::erf(x)
+    }
+    pub fn neg<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::neg(x)
+    }
+    pub fn abs<T: TensorUnary<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::abs(x)
+    }
+    pub fn clamp<T: TensorClamp<T>>(x: &mut T, min: f32, max: f32) -> Result<(), SmeltError> {
+        T::clamp(x, min, max)
+    }
+    pub fn top_k<T: TensorTopK<T>>(x: &mut T, k: usize) -> Result<(), SmeltError> {
+        T::top_k(x, k)
+    }
+    pub fn top_p<T: TensorTopP<T>>(x: &mut T, p: f32) -> Result<(), SmeltError> {
+        T::top_p(x, p)
+    }
+    pub fn tril<T: TensorTriangle<T>>(x: &mut T, diagonal: isize) -> Result<(), SmeltError> {
+        T::tril(x, diagonal)
+    }
+    pub fn triu<T: TensorTriangle<T>>(x: &mut T, diagonal: isize) -> Result<(), SmeltError> {
+        T::triu(x, diagonal)
+    }
+    pub fn position_mask<T: TensorPositionMask<T>>(
+        x: &mut T,
+        offset: usize,
+        length: usize,
+        causal: bool,
+        sliding_window: Option<usize>,
+    ) -> Result<(), SmeltError> {
+        T::position_mask(x, offset, length, causal, sliding_window)
+    }
+    pub fn gelu<T: TensorGelu<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::gelu(x)
+    }
+    pub fn activation<T: TensorActivation<T>>(
+        x: &mut T,
+        activation: Activation,
+    ) -> Result<(), SmeltError> {
+        T::activation(x, activation)
+    }
+    pub fn tanh<T: TensorTanh<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::tanh(x)
+    }
+    pub fn softmax<T: TensorSoftmax<T>>(x: &mut T) -> Result<(), SmeltError> {
+        T::softmax(x)
+    }
+    pub fn cumsum<T: TensorCumsum<T>>(x: &mut T, dim: usize) -> Result<(), SmeltError> {
+        T::cumsum(x, dim)
+    }
+    pub fn softmax_dim<T: TensorSoftmaxDim<T>>(x: &mut T, dim: usize) -> Result<(), SmeltError> {
+        T::softmax_dim(x, dim)
+    }
+    pub fn softmax_t<T: TensorSoftmaxDim<T>>(
+        x: &mut T,
+        dim: usize,
+        temperature: f32,
+    ) -> Result<(), SmeltError> {
+        T::softmax_t(x, dim, temperature)
+    }
+}
+
+impl TensorTrait for Tensor {
+    type Device = Device;
+
+    fn shape(&self) -> &[usize] {
+        self.shape()
+    }
+
+    fn device(&self) -> &Device {
+        self.device()
+    }
+}
+
+impl DeviceTrait for Device {
+    type Tensor = Tensor;
+
+    fn zeros(&self, shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
+        Self::Tensor::zeros(shape, self)
+    }
+
+    fn tensor(&self, data: &[f32], shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
+        Self::Tensor::from_cpu(data.to_vec(), shape, self)
+    }
+}
+
+impl DeviceCapture for Device {
+    fn capture(&self, f: &mut dyn FnMut() -> Result<(), SmeltError>) -> Result<Replay, SmeltError> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(d) => DeviceCapture::capture(d, f),
+            #[allow(unreachable_patterns)]
+            _ => Err(SmeltError::Unsupported {
+                operation: "capture",
+                backend: self.name(),
+            }),
+        }
+    }
+}
+
+impl TensorCopy<Tensor> for Tensor {
+    fn copy(src: &Self, dst: &mut Self) -> Result<(), SmeltError> {
+        binary!(src, dst, generic::copy)
+    }
+}
+
+impl TensorAdd<Tensor> for Tensor {
+    fn add(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary!(x, y, generic::add)
+    }
+    fn broadcast_add(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary!(x, y, generic::broadcast_add)
+    }
+}
+
+impl TensorSub<Tensor> for Tensor {
+    fn sub(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary_cpu_cuda!("sub", x, y, generic::sub)
+    }
+    fn broadcast_sub(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary_cpu_cuda!("broadcast_sub", x, y, generic::broadcast_sub)
+    }
+}
+
+impl TensorMul<Tensor> for Tensor {
+    fn mul(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary!(x, y, generic::mul)
+    }
+    fn broadcast_mul(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        binary!(x, y, generic::broadcast_mul)
+    }
+}
+
+impl TensorMulScalar<Tensor> for Tensor {
+    fn mul_scalar(x: &mut Self, factor: f32) -> Result<(), SmeltError> {
+        unary!(x, generic::mul_scalar, factor)
+    }
+}
+
+impl TensorNormalize<Tensor> for Tensor {
+    fn normalize(x: &mut Self, epsilon: f32) -> Result<(), SmeltError> {
+        unary!(x, generic::normalize, epsilon)
+    }
+}
+
+impl TensorMatmul<Tensor> for Tensor {
+    fn matmul(x: &Self, y: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        ternary!(x, y, out, generic::matmul)
+    }
+}
+
+impl TensorMatmulT<Tensor> for Tensor {
+    fn matmul_t(x: &Self, y: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        ternary!(x, y, out, generic::matmul_t)
+    }
+}
+
+impl TensorSelect<Tensor> for Tensor {
+    fn select(x: &[usize], weight: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        binary!(weight, out, generic::select, x)
+    }
+}
+
+impl TensorGelu<Tensor> for Tensor {
+    fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary!(x, generic::gelu)
+    }
+}
+
+impl TensorActivation<Tensor> for Tensor {
+    fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+        unary!(x, generic::activation, activation)
+    }
+}
+
+impl TensorTanh<Tensor> for Tensor {
+    fn tanh(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary!(x, generic::tanh)
+    }
+}
+
+impl TensorPad<Tensor> for Tensor {
+    fn pad(
+        x: &Self,
+        padding: &[(isize, isize)],
+        value: f32,
+        out: &mut Self,
+    ) -> Result<(), SmeltError> {
+        match (&x.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(o)) => cpu_f32::pad(x, padding, value, o),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(o)) => cuda_f32::pad(x, padding, value, o),
+            #[allow(unreachable_patterns)]
+            (x, o) => Err(storage_mismatch("pad", x, [&*o])),
+        }
+    }
+}
+
+impl TensorReshape<Tensor> for Tensor {
+    fn reshape(x: Self, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        x.reshape(shape)
+    }
+}
+
+impl TensorNarrow<Tensor> for Tensor {
+    fn narrow(x: &Self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        x.narrow(dim, start, len)
+    }
+    fn narrow_put(x: &mut Self, dim: usize, start: usize, values: &Self) -> Result<(), SmeltError> {
+        x.narrow_put(dim, start, values)
+    }
+}
+
+impl TensorReduce<Tensor> for Tensor {
+    fn sum(x: &Self, dim: usize, keepdim: bool, out: &mut Self) -> Result<(), SmeltError> {
+        match (&x.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(o)) => cpu_f32::sum(x, dim, keepdim, o),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(o)) => cuda_f32::sum(x, dim, keepdim, o),
+            #[allow(unreachable_patterns)]
+            (x, o) => Err(storage_mismatch("sum", x, [&*o])),
+        }
+    }
+    fn mean(x: &Self, dim: usize, keepdim: bool, out: &mut Self) -> Result<(), SmeltError> {
+        match (&x.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(o)) => cpu_f32::mean(x, dim, keepdim, o),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(o)) => cuda_f32::mean(x, dim, keepdim, o),
+            #[allow(unreachable_patterns)]
+            (x, o) => Err(storage_mismatch("mean", x, [&*o])),
+        }
+    }
+    fn max(x: &Self, dim: usize, keepdim: bool, out: &mut Self) -> Result<(), SmeltError> {
+        match (&x.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(o)) => cpu_f32::max(x, dim, keepdim, o),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(o)) => cuda_f32::max(x, dim, keepdim, o),
+            #[allow(unreachable_patterns)]
+            (x, o) => Err(storage_mismatch("max", x, [&*o])),
+        }
+    }
+    fn var(x: &Self, dim: usize, keepdim: bool, out: &mut Self) -> Result<(), SmeltError> {
+        match (&x.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(o)) => cpu_f32::var(x, dim, keepdim, o),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(o)) => cuda_f32::var(x, dim, keepdim, o),
+            #[allow(unreachable_patterns)]
+            (x, o) => Err(storage_mismatch("var", x, [&*o])),
+        }
+    }
+}
+
+impl TensorRotary<Tensor> for Tensor {
+    fn rotary(x: &mut Tensor, cos: &Tensor, sin: &Tensor, offset: usize) -> Result<(), SmeltError> {
+        match (&mut x.storage, &cos.storage, &sin.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(c), Storage::Cpu(s)) => cpu_f32::rotary(x, c, s, offset),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(c), Storage::Cuda(s)) => {
+                cuda_f32::rotary(x, c, s, offset)
+            }
+            #[allow(unreachable_patterns)]
+            (x, c, s) => Err(storage_mismatch("rotary", x, [c, s])),
+        }
+    }
+}
+
+impl TensorTopK<Tensor> for Tensor {
+    fn top_k(x: &mut Tensor, k: usize) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("top_k", x, generic::top_k, k)
+    }
+}
+
+impl TensorArgmax<Tensor> for Tensor {
+    fn argmax(x: &Tensor, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        x.argmax(dim)
+    }
+}
+
+impl TensorTopP<Tensor> for Tensor {
+    fn top_p(x: &mut Tensor, p: f32) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("top_p", x, generic::top_p, p)
+    }
+}
+
+impl TensorClamp<Tensor> for Tensor {
+    fn clamp(x: &mut Tensor, min: f32, max: f32) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("clamp", x, generic::clamp, min, max)
+    }
+}
+
+impl TensorUnary<Tensor> for Tensor {
+    fn exp(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("exp", x, generic::exp)
+    }
+
+    fn log(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("log", x, generic::log)
+    }
+
+    fn sqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("sqrt", x, generic::sqrt)
+    }
+
+    fn rsqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("rsqrt", x, generic::rsqrt)
+    }
+
+    fn sigmoid(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("sigmoid", x, generic::sigmoid)
+    }
+
+    fn erf(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("erf", x, generic::erf)
+    }
+
+    fn neg(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("neg", x, generic::neg)
+    }
+
+    fn abs(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("abs", x, generic::abs)
+    }
+}
+
+impl TensorSoftmax<Tensor> for Tensor {
+    fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+        unary!(x, generic::softmax)
+    }
+}
+
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+impl TensorMask<Tensor> for Tensor {
+    type Mask = Mask;
+    fn masked_fill(x: &mut Self, mask: &Mask, value: f32) -> Result<(), SmeltError> {
+        match (&mut x.storage, mask) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Mask::Cpu(mask)) => cpu_f32::Tensor::masked_fill(x, mask, value),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Mask::Cuda(mask)) => cuda_f32::masked_fill(x, mask, value),
+            #[allow(unreachable_patterns)]
+            (x, mask) => Err(mask_mismatch("masked_fill", x, mask)),
+        }
+    }
+    fn masked_softmax(x: &mut Self, mask: &Mask) -> Result<(), SmeltError> {
+        match (&mut x.storage, mask) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Mask::Cpu(mask)) => cpu_f32::Tensor::masked_softmax(x, mask),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Mask::Cuda(mask)) => cuda_f32::masked_softmax(x, mask),
+            #[allow(unreachable_patterns)]
+            (x, mask) => Err(mask_mismatch("masked_softmax", x, mask)),
+        }
+    }
+    fn causal_mask(length: usize, device: &Device) -> Result<Mask, SmeltError> {
+        Mask::causal(length, device)
+    }
+}
+
+impl TensorTriangle<Tensor> for Tensor {
+    fn tril(x: &mut Self, diagonal: isize) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("tril", x, generic::tril, diagonal)
+    }
+    fn triu(x: &mut Self, diagonal: isize) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("triu", x, generic::triu, diagonal)
+    }
+}
+
+impl TensorPositionMask<Tensor> for Tensor {
+    fn position_mask(
+        x: &mut Self,
+        offset: usize,
+        length: usize,
+        causal: bool,
+        sliding_window: Option<usize>,
+    ) -> Result<(), SmeltError> {
+        unary_cpu_cuda!(
+            "position_mask",
+            x,
+            generic::position_mask,
+            offset,
+            length,
+            causal,
+            sliding_window
+        )
+    }
+}
+
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+impl TensorCompare<Tensor> for Tensor {
+    fn gt(a: &Self, b: &Self, out: &mut Mask) -> Result<(), SmeltError> {
+        match (&a.storage, &b.storage, out) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b), Mask::Cpu(out)) => cpu_f32::gt(a, b, out),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b), Mask::Cuda(out)) => cuda_f32::gt(a, b, out),
+            #[allow(unreachable_patterns)]
+            (a, b, out) => Err(compare_mismatch("gt", a, [b], out)),
+        }
+    }
+    fn lt(a: &Self, b: &Self, out: &mut Mask) -> Result<(), SmeltError> {
+        match (&a.storage, &b.storage, out) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b), Mask::Cpu(out)) => cpu_f32::lt(a, b, out),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b), Mask::Cuda(out)) => cuda_f32::lt(a, b, out),
+            #[allow(unreachable_patterns)]
+            (a, b, out) => Err(compare_mismatch("lt", a, [b], out)),
+        }
+    }
+    fn eq(a: &Self, b: &Self, out: &mut Mask) -> Result<(), SmeltError> {
+        match (&a.storage, &b.storage, out) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(a), Storage::Cpu(b), Mask::Cpu(out)) => cpu_f32::eq(a, b, out),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(a), Storage::Cuda(b), Mask::Cuda(out)) => cuda_f32::eq(a, b, out),
+            #[allow(unreachable_patterns)]
+            (a, b, out) => Err(compare_mismatch("eq", a, [b], out)),
+        }
+    }
+    fn where_cond(mask: &Mask, a: &Self, b: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        match (mask, &a.storage, &b.storage, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Mask::Cpu(m), Storage::Cpu(a), Storage::Cpu(b), Storage::Cpu(o)) => {
+                cpu_f32::where_cond(m, a, b, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Mask::Cuda(m), Storage::Cuda(a), Storage::Cuda(b), Storage::Cuda(o)) => {
+                cuda_f32::where_cond(m, a, b, o)
+            }
+            #[allow(unreachable_patterns)]
+            (mask, a, b, o) => Err(compare_mismatch("where_cond", a, [b, &*o], mask)),
+        }
+    }
+}
+
+/// The error of a mask used with the tensor `x` of another backend.
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+fn mask_mismatch(operation: &'static str, x: &Storage, mask: &Mask) -> SmeltError {
+    match x.name() {
+        "cpu" | "cuda" => SmeltError::BackendMismatch {
+            expected: x.name(),
+            got: mask.name(),
+        },
+        backend => SmeltError::Unsupported { operation, backend },
+    }
+}
+
+/// The error of an operation of the cpu and cuda backends called with the tensors `x`
+/// and `others` and the mask `mask`, see [storage_mismatch] and [mask_mismatch].
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+fn compare_mismatch<'a>(
+    operation: &'static str,
+    x: &Storage,
+    others: impl IntoIterator<Item = &'a Storage>,
+    mask: &Mask,
+) -> SmeltError {
+    match storage_mismatch(operation, x, others) {
+        SmeltError::Unsupported { .. } => mask_mismatch(operation, x, mask),
+        err => err,
+    }
+}
+
+/// The error of an operation of the cpu and cuda backends called with the tensors `x`
+/// and `others`: they come from different backends, or from another one.
+fn storage_mismatch<'a>(
+    operation: &'static str,
+    x: &Storage,
+    others: impl IntoIterator<Item = &'a Storage>,
+) -> SmeltError {
+    match others.into_iter().find(|other| other.name() != x.name()) {
+        Some(other) => SmeltError::BackendMismatch {
+            expected: x.name(),
+            got: other.name(),
+        },
+        None => SmeltError::Unsupported {
+            operation,
+            backend: x.name(),
+        },
+    }
+}
+
+impl TensorSoftmaxDim<Tensor> for Tensor {
+    fn softmax_dim(x: &mut Self, dim: usize) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("softmax_dim", x, generic::softmax_dim, dim)
+    }
+    fn softmax_t(x: &mut Self, dim: usize, temperature: f32) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("softmax_t", x, generic::softmax_t, dim, temperature)
+    }
+}
+
+impl TensorConv1d<Tensor> for Tensor {
+    fn conv1d(
+        x: &Self,
+        weight: &Self,
+        bias: Option<&Self>,
+        config: &Conv1dConfig,
+        out: &mut Self,
+    ) -> Result<(), SmeltError> {
+        let bias = bias.map(|b| &b.storage);
+        match (&x.storage, &weight.storage, bias, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(w), None, Storage::Cpu(o)) => {
+                cpu_f32::conv1d(x, w, None, config, o)
+            }
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(w), Some(Storage::Cpu(b)), Storage::Cpu(o)) => {
+                cpu_f32::conv1d(x, w, Some(b), config, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(w), None, Storage::Cuda(o)) => {
+                cuda_f32::conv1d(x, w, None, config, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(w), Some(Storage::Cuda(b)), Storage::Cuda(o)) => {
+                cuda_f32::conv1d(x, w, Some(b), config, o)
+            }
+            #[allow(unreachable_patterns)]
+            (x, w, b, o) => Err(storage_mismatch("conv1d", x, [w, &*o].into_iter().chain(b))),
+        }
+    }
+}
+
+impl TensorConv2d<Tensor> for Tensor {
+    fn conv2d(
+        x: &Self,
+        weight: &Self,
+        bias: Option<&Self>,
+        config: &Conv2dConfig,
+        out: &mut Self,
+    ) -> Result<(), SmeltError> {
+        let bias = bias.map(|b| &b.storage);
+        match (&x.storage, &weight.storage, bias, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(w), None, Storage::Cpu(o)) => {
+                cpu_f32::conv2d(x, w, None, config, o)
+            }
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(x), Storage::Cpu(w), Some(Storage::Cpu(b)), Storage::Cpu(o)) => {
+                cpu_f32::conv2d(x, w, Some(b), config, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(w), None, Storage::Cuda(o)) => {
+                cuda_f32::conv2d(x, w, None, config, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(x), Storage::Cuda(w), Some(Storage::Cuda(b)), Storage::Cuda(o)) => {
+                cuda_f32::conv2d(x, w, Some(b), config, o)
+            }
+            #[allow(unreachable_patterns)]
+            (x, w, b, o) => Err(storage_mismatch("conv2d", x, [w, &*o].into_iter().chain(b))),
+        }
+    }
+}
+
+impl TensorFusedAttention<Tensor> for Tensor {
+    fn fused_attention(
+        query: &Self,
+        key: &Self,
+        value: &Self,
+        mask: Option<&Self>,
+        slopes: Option<&Self>,
+        config: &FusedAttentionConfig,
+        out: &mut Self,
+    ) -> Result<(), SmeltError> {
+        let optional = [mask, slopes].map(|t| t.map(|t| &t.storage));
+        let (q, k, v) = (&query.storage, &key.storage, &value.storage);
+        match (q, k, v, &mut out.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(q), Storage::Cpu(k), Storage::Cpu(v), Storage::Cpu(o)) => {
+                let [mask, slopes] = optional.map(|t| match t {
+                    Some(Storage::Cpu(t)) => Ok(Some(t)),
+                    None => Ok(None),
+                    #[allow(unreachable_patterns)]
+                    Some(other) => Err(SmeltError::BackendMismatch {
+                        expected: "cpu",
+                        got: other.name(),
+                    }),
+                });
+                cpu_f32::fused_attention(q, k, v, mask?, slopes?, config, o)
+            }
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(q), Storage::Cuda(k), Storage::Cuda(v), Storage::Cuda(o)) => {
+                let [mask, slopes] = optional.map(|t| match t {
+                    Some(Storage::Cuda(t)) => Ok(Some(t)),
+                    None => Ok(None),
+                    #[allow(unreachable_patterns)]
+                    Some(other) => Err(SmeltError::BackendMismatch {
+                        expected: "cuda",
+                        got: other.name(),
+                    }),
+                });
+                cuda_f32::fused_attention(q, k, v, mask?, slopes?, config, o)
+            }
+            #[allow(unreachable_patterns)]
+            (q, k, v, o) => Err(storage_mismatch(
+                "fused_attention",
+                q,
+                [k, v, &*o]
+                    .into_iter()
+                    .chain(optional.into_iter().flatten()),
+            )),
+        }
+    }
+}
+
+impl TensorCumsum<Tensor> for Tensor {
+    fn cumsum(x: &mut Self, dim: usize) -> Result<(), SmeltError> {
+        unary_cpu_cuda!("cumsum", x, generic::cumsum, dim)
+    }
+}
+
+impl TensorToDevice for Tensor {
+    fn to_device(&self, device: &Device) -> Result<Self, SmeltError> {
+        self.to_device(device)
+    }
+    fn copy_to_device(src: &Self, dst: &mut Self) -> Result<(), SmeltError> {
+        match (&src.storage, &mut dst.storage) {
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(src), Storage::Cuda(dst)) => src.copy_to_device(dst),
+            #[allow(unreachable_patterns)]
+            _ => {
+                *dst = src.to_device(dst.device())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A weight quantized (or cast to a reduced precision) on the cpu, the only backend able
+/// to quantize for now.
+#[cfg(feature = "cpu")]
+struct CpuQuantized(Arc<dyn QuantizedWeight<cpu_f32::Tensor>>);
+
+#[cfg(feature = "cpu")]
+impl QuantizedWeight<Tensor> for CpuQuantized {
+    fn shape(&self) -> &[usize] {
+        self.0.shape()
+    }
+
+    fn addmm(&self, x: &Tensor, bias: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        match (&x.storage, &bias.storage, &mut out.storage) {
+            (Storage::Cpu(x), Storage::Cpu(bias), Storage::Cpu(out)) => self.0.addmm(x, bias, out),
+            #[allow(unreachable_patterns)]
+            (x, _, _) => Err(SmeltError::BackendMismatch {
+                expected: "cpu",
+                got: x.name(),
+            }),
+        }
+    }
+}
+
+#[cfg(feature = "cpu")]
+impl Tensor {
+    /// The quantized `weight` of the cpu tensors applied to the tensors on the cpu, like
+    /// the 4-bit [cpu_f32::Int4Tensor] of a [crate::nn::layers::Linear::quantized].
+    /// ```
+    /// use smelte_rs::backend::{Device, Tensor};
+    /// use smelte_rs::cpu::f32::{Int4Tensor, Tensor as CpuTensor};
+    /// use smelte_rs::nn::layers::Linear;
+    /// use std::sync::Arc;
+    ///
+    /// let device = Device::cpu();
+    /// let weight = CpuTensor::new(vec![0.0, 7.5, 15.0, 1.0], vec![2, 2]).unwrap();
+    /// let weight = Tensor::cpu_quantized(Arc::new(Int4Tensor::quantize(&weight, 2).unwrap()));
+    /// let linear = Linear::quantized(weight, Tensor::zeros(vec![2], &device).unwrap());
+    /// let x = Tensor::from_cpu(vec![1.0, 0.0], vec![1, 2], &device).unwrap();
+    /// let mut out = Tensor::zeros(vec![1, 2], &device).unwrap();
+    /// linear.forward(&x, &mut out).unwrap();
+    /// assert_eq!(out.cpu_data().unwrap(), [0.0, 15.0]);
+    /// ```
+    pub fn cpu_quantized(
+        weight: Arc<dyn QuantizedWeight<cpu_f32::Tensor>>,
+    ) -> Arc<dyn QuantizedWeight<Self>> {
+        Arc::new(CpuQuantized(weight))
+    }
+}
+
+/// A weight cast to a reduced precision on a cuda device.
+#[cfg(feature = "cuda")]
+struct CudaQuantized(Arc<dyn QuantizedWeight<cuda_f32::Tensor>>);
+
+#[cfg(feature = "cuda")]
+impl QuantizedWeight<Tensor> for CudaQuantized {
+    fn shape(&self) -> &[usize] {
+        self.0.shape()
+    }
+
+    fn addmm(&self, x: &Tensor, bias: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        match (&x.storage, &bias.storage, &mut out.storage) {
+            (Storage::Cuda(x), Storage::Cuda(bias), Storage::Cuda(out)) => {
+                self.0.addmm(x, bias, out)
+            }
+            #[allow(unreachable_patterns)]
+            (x, _, _) => Err(SmeltError::BackendMismatch {
+                expected: "cuda",
+                got: x.name(),
+            }),
+        }
+    }
+}
+
+impl TensorPrecision for Tensor {
+    fn cast_weight(
+        weight: &Self,
+        precision: Precision,
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        match &weight.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(weight) => Ok(Arc::new(CpuQuantized(cpu_f32::Tensor::cast_weight(
+                weight, precision,
+            )?))),
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(weight) => Ok(Arc::new(CudaQuantized(cuda_f32::Tensor::cast_weight(
+                weight, precision,
+            )?))),
+            #[allow(unreachable_patterns)]
+            storage => Err(SmeltError::Unsupported {
+                operation: "set_precision",
+                backend: storage.name(),
+            }),
+        }
+    }
+}
+
+/// Records the activation ranges of tensors on any backend, from their copy on the host.
+#[derive(Default)]
+struct HostObserver {
+    ranges: std::sync::Mutex<(f32, f32)>,
+}
+
+impl RangeObserver<Tensor> for HostObserver {
+    fn observe(&self, input: &Tensor, output: &Tensor) -> Result<(), SmeltError> {
+        let abs_max = |tensor: &Tensor| -> Result<f32, SmeltError> {
+            Ok(tensor
+                .cpu_data()?
+                .iter()
+                .fold(0.0f32, |max, v| max.max(v.abs())))
+        };
+        let (input, output) = (abs_max(input)?, abs_max(output)?);
+        let mut ranges = self.ranges.lock().unwrap();
+        ranges.0 = ranges.0.max(input);
+        ranges.1 = ranges.1.max(output);
+        Ok(())
+    }
+
+    fn ranges(&self) -> (f32, f32) {
+        *self.ranges.lock().unwrap()
+    }
+}
+
+impl TensorQuantize for Tensor {
+    fn quantize_dynamic(weight: &Self) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        match &weight.storage {
+            #[cfg(feature = "cpu")]
+            Storage::Cpu(weight) => Ok(Arc::new(CpuQuantized(cpu_f32::Tensor::quantize_dynamic(
+                weight,
+            )?))),
+            #[allow(unreachable_patterns)]
+            storage => Err(SmeltError::Unsupported {
+                operation: "quantize_dynamic",
+                backend: storage.name(),
+            }),
+        }
+    }
+
+    fn observer() -> Arc<dyn RangeObserver<Self>> {
+        Arc::new(HostObserver::default())
+    }
+
+    fn quantize_static(
+        weight: &Self,
+        bias: &Self,
+        ranges: (f32, f32),
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        match (&weight.storage, &bias.storage) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(weight), Storage::Cpu(bias)) => Ok(Arc::new(CpuQuantized(
+                cpu_f32::Tensor::quantize_static(weight, bias, ranges)?,
+            ))),
+            #[allow(unreachable_patterns)]
+            (storage, _) => Err(SmeltError::Unsupported {
+                operation: "quantize_static",
+                backend: storage.name(),
+            }),
+        }
+    }
+}
+
+impl TensorOps<Tensor> for Tensor {}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_device() {
+        let device: Device = "cpu".parse().unwrap();
+        assert_eq!(device.name(), "cpu");
+        assert!("cpu:1".parse::<Device>().is_err());
+        assert!("tpu".parse::<Device>().is_err());
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let device = Device::cpu();
+        let a = Tensor::from_cpu(vec![1.0, 2.0], vec![2], &device).unwrap();
+        let mut b = Tensor::from_cpu(vec![1.0; 6], vec![3, 2], &device).unwrap();
+        Tensor::broadcast_add(&a, &mut b).unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [2.0, 3.0, 2.0, 3.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_to_device() {
+        let device = Device::cpu();
+        let a = Tensor::from_cpu(vec![1.0, 2.0], vec![2], &device).unwrap();
+        let mut b = a.to_device(&device).unwrap();
+        Tensor::add(&a, &mut b).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), [1.0, 2.0]);
+        assert_eq!(b.cpu_data().unwrap(), [2.0, 4.0]);
+    }
+}
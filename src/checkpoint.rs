@@ -0,0 +1,519 @@
+use crate::nn::layers::BatchNormStats;
+use crate::traits::Precision;
+use crate::SmeltError;
+use safetensors::tensor::{Dtype, TensorView};
+use safetensors::SafeTensors;
+use std::borrow::Cow;
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use crate::backend::{Device, Tensor};
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+use crate::{
+    nn::layers::{Conv2d, LayerNorm, Linear, LoraLinear},
+    traits::Conv2dConfig,
+};
+
+/// The f32 values of a safetensors tensor, whatever its dtype.
+/// F32 data is borrowed when it is aligned, F16, BF16, F64 and I64 are converted.
+/// ```
+/// use safetensors::tensor::{Dtype, TensorView};
+/// use smelte_rs::checkpoint::to_f32;
+///
+/// // 1.0 and -2.0 in bf16
+/// let data = [0x80, 0x3f, 0x00, 0xc0];
+/// let view = TensorView::new(Dtype::BF16, vec![2], &data).unwrap();
+/// assert_eq!(*to_f32(&view).unwrap(), [1.0, -2.0]);
+/// ```
+pub fn to_f32<'data>(view: &TensorView<'data>) -> Result<Cow<'data, [f32]>, SmeltError> {
+    let v = view.data();
+    let data = match view.dtype() {
+        Dtype::F32 => {
+            if (v.as_ptr() as usize).is_multiple_of(std::mem::align_of::<f32>()) {
+                // SAFETY This is safe because we just checked that this
+                // was correctly aligned.
+                let data: &'data [f32] =
+                    unsafe { std::slice::from_raw_parts(v.as_ptr() as *const f32, v.len() / 4) };
+                return Ok(Cow::Borrowed(data));
+            }
+            v.chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        }
+        Dtype::F16 => v
+            .chunks_exact(2)
+            .map(|b| half::f16::from_bits(u16::from_le_bytes([b[0], b[1]])).to_f32())
+            .collect(),
+        // A bf16 is the upper half of the f32 with the same value.
+        Dtype::BF16 => v
+            .chunks_exact(2)
+            .map(|b| f32::from_bits((u16::from_le_bytes([b[0], b[1]]) as u32) << 16))
+            .collect(),
+        Dtype::F64 => v
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        // Used by the buffers of some checkpoints, like the `position_ids`.
+        Dtype::I64 => v
+            .chunks_exact(8)
+            .map(|b| i64::from_le_bytes(b.try_into().unwrap()) as f32)
+            .collect(),
+        dtype => return Err(SmeltError::UnsupportedDtype(format!("{dtype:?}"))),
+    };
+    Ok(Cow::Owned(data))
+}
+
+/// The [BatchNormStats] of the tensors `{prefix}.running_mean` and `{prefix}.running_var`
+/// and, when the checkpoint has them, `{prefix}.weight` and `{prefix}.bias`. The `eps`
+/// is not stored in the checkpoints, it is the default of torch.
+/// ```no_run
+/// use safetensors::SafeTensors;
+/// use smelte_rs::checkpoint::batch_norm_stats;
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let stats = batch_norm_stats(&tensors, "resnet.embedder.embedder.normalization").unwrap();
+/// ```
+pub fn batch_norm_stats(
+    tensors: &SafeTensors<'_>,
+    prefix: &str,
+) -> Result<BatchNormStats, SmeltError> {
+    let data = |name: &str| match tensors.tensor(&format!("{prefix}.{name}")) {
+        Ok(view) => Ok(Some(to_f32(&view)?.into_owned())),
+        Err(_) => Ok(None),
+    };
+    let required = |name: &str| {
+        data(name)?.ok_or_else(|| SmeltError::MissingTensor(format!("{prefix}.{name}")))
+    };
+    let mut stats = BatchNormStats::new(required("running_mean")?, required("running_var")?);
+    stats.weight = data("weight")?;
+    stats.bias = data("bias")?;
+    Ok(stats)
+}
+
+/// Creates a [Tensor] on `device` from a safetensors tensor, converted with [to_f32].
+/// The data is always copied, see [Tensor::from_cpu] to keep borrowing the
+/// checkpoint on the cpu.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn to_tensor(view: &TensorView<'_>, device: &Device) -> Result<Tensor, SmeltError> {
+    let shape = view.shape().to_vec();
+    let data = to_f32(view)?.into_owned();
+    Tensor::from_cpu(data, shape, device)
+}
+
+/// Creates a [Tensor] on `device` from the tensor `name` of the checkpoint, see
+/// [to_tensor], [SmeltError::MissingTensor] when the checkpoint doesn't have it.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn load_tensor(
+    tensors: &SafeTensors<'_>,
+    name: &str,
+    device: &Device,
+) -> Result<Tensor, SmeltError> {
+    match tensors.tensor(name) {
+        Ok(view) => to_tensor(&view, device),
+        Err(_) => Err(SmeltError::MissingTensor(name.to_string())),
+    }
+}
+
+/// Creates a [Linear] on `device` from the tensors `{prefix}.weight` and `{prefix}.bias`.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn load_linear(
+    tensors: &SafeTensors<'_>,
+    prefix: &str,
+    device: &Device,
+) -> Result<Linear<Tensor>, SmeltError> {
+    let weight = load_tensor(tensors, &format!("{prefix}.weight"), device)?;
+    let bias = load_tensor(tensors, &format!("{prefix}.bias"), device)?;
+    Ok(Linear::new(weight, bias))
+}
+
+/// Creates a [LayerNorm] of `epsilon` on `device` from the tensors `{prefix}.weight` and
+/// `{prefix}.bias`. The `epsilon` is not stored in the checkpoints, it comes from the
+/// configuration of the model.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn load_layer_norm(
+    tensors: &SafeTensors<'_>,
+    prefix: &str,
+    epsilon: f32,
+    device: &Device,
+) -> Result<LayerNorm<Tensor>, SmeltError> {
+    let weight = load_tensor(tensors, &format!("{prefix}.weight"), device)?;
+    let bias = load_tensor(tensors, &format!("{prefix}.bias"), device)?;
+    Ok(LayerNorm::new(weight, bias, epsilon))
+}
+
+/// Creates a [Conv2d] on `device` from the tensors `{prefix}.weight` and, when the
+/// checkpoint has one, `{prefix}.bias`, like the patch embeddings of a ViT.
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::checkpoint::conv2d_from;
+/// use smelte_rs::traits::Conv2dConfig;
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let config = Conv2dConfig { stride: [16, 16], ..Default::default() };
+/// let prefix = "vit.embeddings.patch_embeddings.projection";
+/// let patches = conv2d_from(&tensors, prefix, config, &Device::cpu()).unwrap();
+/// # }
+/// ```
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn conv2d_from(
+    tensors: &SafeTensors<'_>,
+    prefix: &str,
+    config: Conv2dConfig,
+    device: &Device,
+) -> Result<Conv2d<Tensor>, SmeltError> {
+    let weight = load_tensor(tensors, &format!("{prefix}.weight"), device)?;
+    let bias = match tensors.tensor(&format!("{prefix}.bias")) {
+        Ok(view) => Some(to_tensor(&view, device)?),
+        Err(_) => None,
+    };
+    Ok(Conv2d::new(weight, bias, config))
+}
+
+/// The LoRA adapters of a peft `adapter_model.safetensors` on `device`, with their
+/// module names without the `base_model.model.` prefix, for example
+/// `bert.encoder.layer.0.attention.self.query`. `alpha` is the `lora_alpha` of the
+/// `adapter_config.json`.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub fn lora_adapters(
+    tensors: &SafeTensors<'_>,
+    alpha: f32,
+    device: &Device,
+) -> Result<Vec<(String, LoraLinear<Tensor>)>, SmeltError> {
+    let mut names: Vec<_> = tensors
+        .names()
+        .into_iter()
+        .filter_map(|name| name.strip_suffix(".lora_A.weight"))
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|prefix| {
+            let a = load_tensor(tensors, &format!("{prefix}.lora_A.weight"), device)?;
+            let b = load_tensor(tensors, &format!("{prefix}.lora_B.weight"), device)?;
+            let name = prefix.strip_prefix("base_model.model.").unwrap_or(prefix);
+            Ok((name.to_string(), LoraLinear::new(a, b, alpha)?))
+        })
+        .collect()
+}
+
+/// The packing of the 4-bit weights of a checkpoint, the `quant_method` of the
+/// `quantization_config` of its `config.json`.
+/// ```
+/// use smelte_rs::checkpoint::Int4Format;
+///
+/// assert_eq!("awq".parse::<Int4Format>().unwrap(), Int4Format::Awq);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Int4Format {
+    /// The layout of GPTQ, see [crate::cpu::f32::Int4Tensor::from_gptq]
+    Gptq,
+    /// The layout of AWQ, see [crate::cpu::f32::Int4Tensor::from_awq]
+    Awq,
+}
+
+impl std::str::FromStr for Int4Format {
+    type Err = SmeltError;
+
+    fn from_str(quant_method: &str) -> Result<Self, Self::Err> {
+        match quant_method {
+            "gptq" => Ok(Self::Gptq),
+            "awq" => Ok(Self::Awq),
+            _ => Err(SmeltError::InvalidConfig(format!(
+                "unsupported quantization {quant_method}"
+            ))),
+        }
+    }
+}
+
+impl Int4Format {
+    /// The format of the 4-bit weight `{prefix}` of a checkpoint: GPTQ stores the group of
+    /// every input (`{prefix}.g_idx`), AWQ doesn't.
+    pub fn detect(tensors: &SafeTensors<'_>, prefix: &str) -> Self {
+        if tensors.tensor(&format!("{prefix}.g_idx")).is_ok() {
+            Self::Gptq
+        } else {
+            Self::Awq
+        }
+    }
+}
+
+/// The i32 values of a safetensors tensor, like the packed weights of the 4-bit
+/// checkpoints.
+#[cfg(feature = "cpu")]
+fn to_i32(view: &TensorView<'_>) -> Result<Vec<i32>, SmeltError> {
+    match view.dtype() {
+        Dtype::I32 => Ok(view
+            .data()
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()),
+        dtype => Err(SmeltError::UnsupportedDtype(format!("{dtype:?}"))),
+    }
+}
+
+/// Creates a [Linear] with the 4-bit weight of the tensors
+/// `{prefix}.qweight`, `{prefix}.qzeros` and `{prefix}.scales` packed in `format`, and
+/// `{prefix}.bias` when the checkpoint has one. The 4-bit matmuls only run on the cpu.
+/// ```no_run
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::checkpoint::{int4_linear_from, Int4Format};
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let prefix = "model.layers.0.self_attn.q_proj";
+/// let q_proj = int4_linear_from(&tensors, prefix, Int4Format::Gptq, &Device::cpu()).unwrap();
+/// ```
+#[cfg(feature = "cpu")]
+pub fn int4_linear_from(
+    tensors: &SafeTensors<'_>,
+    prefix: &str,
+    format: Int4Format,
+    device: &Device,
+) -> Result<Linear<Tensor>, SmeltError> {
+    use crate::cpu::f32::Int4Tensor;
+    use std::sync::Arc;
+
+    if !matches!(device, Device::Cpu(_)) {
+        return Err(SmeltError::Unsupported {
+            operation: "int4_linear_from",
+            backend: device.name(),
+        });
+    }
+    let view = |name: &str| {
+        let name = format!("{prefix}.{name}");
+        tensors
+            .tensor(&name)
+            .map_err(|_| SmeltError::MissingTensor(name))
+    };
+    let qweight = view("qweight")?;
+    let [rows, columns] = qweight.shape() else {
+        return Err(SmeltError::InvalidRank { expected_rank: 2 });
+    };
+    let qzeros = to_i32(&view("qzeros")?)?;
+    let scales = to_f32(&view("scales")?)?;
+    let weight = match format {
+        Int4Format::Gptq => {
+            let (out_features, in_features) = (*columns, rows * 8);
+            // The inputs reordered by `desc_act` aren't supported.
+            if let Ok(g_idx) = view("g_idx") {
+                let group_size = in_features / (scales.len() / out_features.max(1)).max(1);
+                let sequential = to_i32(&g_idx)?
+                    .iter()
+                    .enumerate()
+                    .all(|(j, &g)| g as usize == j / group_size);
+                if !sequential {
+                    return Err(SmeltError::Unsupported {
+                        operation: "int4_linear_from",
+                        backend: "desc_act",
+                    });
+                }
+            }
+            Int4Tensor::from_gptq(
+                &to_i32(&qweight)?,
+                &qzeros,
+                &scales,
+                out_features,
+                in_features,
+            )?
+        }
+        Int4Format::Awq => {
+            let (out_features, in_features) = (columns * 8, *rows);
+            Int4Tensor::from_awq(
+                &to_i32(&qweight)?,
+                &qzeros,
+                &scales,
+                out_features,
+                in_features,
+            )?
+        }
+    };
+    let bias = match tensors.tensor(&format!("{prefix}.bias")) {
+        Ok(view) => to_tensor(&view, device)?,
+        Err(_) => Tensor::zeros(vec![weight.shape()[0]], device)?,
+    };
+    Ok(Linear::quantized(
+        Tensor::cpu_quantized(Arc::new(weight)),
+        bias,
+    ))
+}
+
+/// The float dtype holding most of the weights of a checkpoint, `None` without float
+/// tensors.
+pub fn checkpoint_dtype(tensors: &SafeTensors<'_>) -> Option<Dtype> {
+    main_dtype(
+        tensors
+            .tensors()
+            .iter()
+            .map(|(_, view)| (view.dtype(), view.shape().iter().product())),
+    )
+}
+
+fn main_dtype(tensors: impl Iterator<Item = (Dtype, usize)>) -> Option<Dtype> {
+    let mut counts: Vec<(Dtype, usize)> = vec![];
+    for (dtype, numel) in tensors {
+        if !matches!(dtype, Dtype::F32 | Dtype::F16 | Dtype::BF16 | Dtype::F64) {
+            continue;
+        }
+        match counts.iter_mut().find(|(d, _)| *d == dtype) {
+            Some((_, count)) => *count += numel,
+            None => counts.push((dtype, numel)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(dtype, _)| dtype)
+}
+
+/// How the [Precision] of a model follows the dtype of its checkpoint.
+/// ```
+/// use safetensors::tensor::Dtype;
+/// use smelte_rs::checkpoint::DtypePolicy;
+/// use smelte_rs::traits::Precision;
+///
+/// let policy = DtypePolicy::Convert(Precision::F32);
+/// assert_eq!(policy.precision(Some(Dtype::BF16)), Precision::F32);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DtypePolicy {
+    /// The matmuls run in the dtype of the checkpoint when it is compiled in (features
+    /// `f16` and `bf16`), in f32 otherwise.
+    #[default]
+    Auto,
+    /// The matmuls run in the given precision, whatever the checkpoint.
+    Convert(Precision),
+}
+
+impl DtypePolicy {
+    /// The precision for a checkpoint of `dtype`, see [checkpoint_dtype].
+    pub fn precision(&self, dtype: Option<Dtype>) -> Precision {
+        match (self, dtype) {
+            (Self::Convert(precision), _) => *precision,
+            (Self::Auto, Some(Dtype::F16)) if cfg!(feature = "f16") => Precision::F16,
+            (Self::Auto, Some(Dtype::BF16)) if cfg!(feature = "bf16") => Precision::BF16,
+            (Self::Auto, _) => Precision::F32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_f32() {
+        // 1.0 and -2.0
+        let data = [0x00, 0x3c, 0x00, 0xc0];
+        let view = TensorView::new(Dtype::F16, vec![2], &data).unwrap();
+        assert_eq!(*to_f32(&view).unwrap(), [1.0, -2.0]);
+
+        let data: Vec<u8> = [1.5f64, -3.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let view = TensorView::new(Dtype::F64, vec![2], &data).unwrap();
+        assert_eq!(*to_f32(&view).unwrap(), [1.5, -3.0]);
+
+        let data: Vec<u8> = [0i64, 511].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let view = TensorView::new(Dtype::I64, vec![1, 2], &data).unwrap();
+        assert_eq!(*to_f32(&view).unwrap(), [0.0, 511.0]);
+
+        let data = [1, 0];
+        let view = TensorView::new(Dtype::U8, vec![2], &data).unwrap();
+        assert!(matches!(
+            to_f32(&view),
+            Err(SmeltError::UnsupportedDtype(_))
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_dtype() {
+        // The position ids and a small f32 bias do not count.
+        let tensors = [
+            (Dtype::I64, 512),
+            (Dtype::F32, 768),
+            (Dtype::BF16, 768 * 768),
+            (Dtype::BF16, 768),
+        ];
+        let dtype = main_dtype(tensors.into_iter());
+        assert_eq!(dtype, Some(Dtype::BF16));
+        assert_eq!(main_dtype([(Dtype::I64, 2)].into_iter()), None);
+
+        let precision = DtypePolicy::Auto.precision(dtype);
+        if cfg!(feature = "bf16") {
+            assert_eq!(precision, Precision::BF16);
+        } else {
+            assert_eq!(precision, Precision::F32);
+        }
+        assert_eq!(DtypePolicy::Auto.precision(None), Precision::F32);
+    }
+
+    #[test]
+    fn test_unaligned_f32() {
+        let mut data = vec![0];
+        data.extend(2.5f32.to_le_bytes());
+        let view = TensorView::new(Dtype::F32, vec![1], &data[1..]).unwrap();
+        assert_eq!(*to_f32(&view).unwrap(), [2.5]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Same as [crate::cpu::f16], the bfloat16 ops keep the storage in bf16 but compute in
+//! f32. Converting a bf16 to f32 is only a shift, the rounding back is barely more.
+use crate::cpu::bf16::tensor::Tensor;
+use crate::cpu::f32::{self as full, Tensor as F32Tensor};
+use crate::traits::Activation;
+use crate::SmeltError;
+
+/// Runs the f32 kernel `f` on the f32 copy of `x`, and stores the result back into `x`.
+fn in_f32<F>(x: &mut Tensor, f: F) -> Result<(), SmeltError>
+where
+    F: FnOnce(&mut F32Tensor) -> Result<(), SmeltError>,
+{
+    let mut x32 = x.to_f32();
+    f(&mut x32)?;
+    x.copy_from_f32(&x32)
+}
+
+/// Operation for selecting entire rows within tensor `weights`. Each `id` is the index
+/// of the row. The rows are copied without conversion.
+pub fn select(ids: &[usize], weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let sequence_length = ids.len();
+    let vocab_size = weights.shape()[0];
+    let hidden_dim = weights.shape()[1];
+    if out.shape() != [sequence_length, hidden_dim] {
+        return Err(SmeltError::DimensionMismatch {
+            expected: vec![sequence_length, hidden_dim],
+            got: out.shape().to_vec(),
+        });
+    }
+    for (i, id) in ids.iter().enumerate() {
+        let id = *id;
+        if id >= vocab_size {
+            return Err(SmeltError::OutOfVocabulary { vocab_size, id });
+        }
+        let weight_offset = id * hidden_dim;
+        let data_offset = i * hidden_dim;
+        out.data_mut()[data_offset..data_offset + hidden_dim]
+            .copy_from_slice(&weights.data()[weight_offset..weight_offset + hidden_dim]);
+    }
+    Ok(())
+}
+
+/// Copy tensor into another tensor
+pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    if weights.shape() != out.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: out.shape().to_vec(),
+            got: weights.shape().to_vec(),
+        });
+    }
+    out.data_mut().copy_from_slice(weights.data());
+    Ok(())
+}
+
+/// Regular matrix multiplication
+pub fn matmul(a: &Tensor, b: &Tensor, c: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(c, |c| full::matmul(&a.to_f32(), &b.to_f32(), c))
+}
+
+/// Matrix multiplication matmul(A, B.transposed())
+pub fn matmul_t(a: &Tensor, b: &Tensor, c: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(c, |c| full::matmul_t(&a.to_f32(), &b.to_f32(), c))
+}
+
+/// tensor elementwise addition. b += a.
+pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(b, |b| full::add(&a.to_f32(), b))
+}
+
+/// broacasted tensor elementwise addition. b += a.
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(b, |b| full::broadcast_add(&a.to_f32(), b))
+}
+
+/// tensor elementwise multiplication. b *= a.
+pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(b, |b| full::mul(&a.to_f32(), b))
+}
+
+/// broadcasted tensor elementwise multiplication. b *= a.
+pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(b, |b| full::broadcast_mul(&a.to_f32(), b))
+}
+
+/// x = x * factor
+pub fn mul_scalar(x: &mut Tensor, factor: f32) -> Result<(), SmeltError> {
+    in_f32(x, |x| {
+        full::mul_scalar(x, factor);
+        Ok(())
+    })
+}
+
+/// Basic operation for the layernorm.
+/// x = (x - x.mean()) / (x.var() + epsilon)
+pub fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
+    in_f32(x, |x| full::normalize(x, epsilon))
+}
+
+/// Softmax on the last dimension for tensor `x`
+pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(x, full::softmax)
+}
+
+/// Causal softmax on the last dimension for tensor `x`, see [full::causal_softmax].
+pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
+    in_f32(x, |x| full::causal_softmax(x, past_sequence_length))
+}
+
+/// Argmax of the last dimension of tensor `x `.
+pub fn special_argmax(x: &Tensor) -> Result<usize, SmeltError> {
+    full::special_argmax(&x.to_f32())
+}
+
+/// [full::gelu] of every item of the tensor.
+pub fn apply_gelu(x: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(x, |x| {
+        full::apply_gelu(x);
+        Ok(())
+    })
+}
+
+/// [full::apply_activation] of every item of the tensor.
+pub fn apply_activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+    in_f32(x, |x| {
+        full::apply_activation(x, activation);
+        Ok(())
+    })
+}
+
+/// [full::inline_tanh] of every item of the tensor.
+pub fn apply_tanh(x: &mut Tensor) -> Result<(), SmeltError> {
+    in_f32(x, |x| {
+        full::apply_tanh(x);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::simplify;
+
+    fn tensor(data: &[f32], shape: Vec<usize>) -> Tensor {
+        Tensor::from_f32(&F32Tensor::new(data.to_vec(), shape).unwrap())
+    }
+
+    #[test]
+    fn simple_matmul() {
+        let a = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        let b = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        let mut c = Tensor::zeros(vec![2, 2]);
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), [7.0, 10.0, 15.0, 22.0]);
+
+        let data: Vec<_> = (0..12).map(|i| i as f32).collect();
+        let a = tensor(&data, vec![2, 2, 3]);
+        let data: Vec<_> = (0..12).map(|i| (i + 2) as f32).collect();
+        let b = tensor(&data, vec![2, 3, 2]);
+        let mut c = Tensor::zeros(vec![2, 2, 2]);
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(
+            c.cpu_data().unwrap(),
+            [16., 19., 52., 64., 214., 235., 304., 334.]
+        );
+    }
+
+    #[test]
+    fn simple_matmul_t() {
+        let a = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        // A.T
+        let b = tensor(&[1.0, 3.0, 2.0, 4.0], vec![2, 2]);
+        let mut c = Tensor::zeros(vec![2, 2]);
+        matmul_t(&a, &b, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), [7.0, 10.0, 15.0, 22.0]);
+    }
+
+    #[test]
+    fn simple_softmax() {
+        let mut a = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        softmax(&mut a).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            // Values obtained through python, rounded to bf16
+            [0.2695, 0.7305, 0.2695, 0.7305]
+        );
+    }
+
+    #[test]
+    fn simple_select() {
+        let a = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        let mut out = Tensor::zeros(vec![3, 2]);
+        select(&[1, 0, 0], &a, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [3.0, 4.0, 1.0, 2.0, 1.0, 2.0]);
+        assert!(select(&[2], &a, &mut Tensor::zeros(vec![1, 2])).is_err());
+    }
+
+    #[test]
+    fn simple_normalize() {
+        let mut a = tensor(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]);
+        normalize(&mut a, 1e-5).unwrap();
+        assert_eq!(simplify(&a.cpu_data().unwrap()), [-1.0, 1.0, -1.0, 1.0]);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::cpu::f32::Tensor as F32Tensor;
+use crate::SmeltError;
+use half::bf16;
+use half::slice::HalfFloatSliceExt;
+use std::borrow::Cow;
+
+/// Tensor, can own, or borrow the underlying tensor.
+/// The data is stored in bfloat16, the ops compute in f32 (see [crate::cpu::bf16]).
+pub type Tensor = crate::cpu::Tensor<bf16>;
+
+/// The CPU device of the bfloat16 tensors
+pub type Device = crate::cpu::Device<bf16>;
+
+impl Tensor {
+    /// The data of the tensor, converted to f32.
+    /// ```
+    /// use smelte_rs::cpu::bf16::Tensor;
+    ///
+    /// let tensor = Tensor::zeros(vec![2, 2]);
+    /// assert_eq!(tensor.cpu_data().unwrap(), vec![0.0; 4]);
+    /// ```
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        Ok(self.data.to_f32_vec())
+    }
+
+    /// The bfloat16 copy of an f32 tensor, rounding to the nearest.
+    /// ```
+    /// use smelte_rs::cpu::bf16::Tensor;
+    /// use smelte_rs::cpu::f32::Tensor as F32Tensor;
+    ///
+    /// let tensor = F32Tensor::new(vec![1.0, 2.0], vec![2]).unwrap();
+    /// let tensor = Tensor::from_f32(&tensor);
+    /// assert_eq!(tensor.cpu_data().unwrap(), [1.0, 2.0]);
+    /// ```
+    pub fn from_f32(tensor: &F32Tensor) -> Self {
+        let mut data = vec![bf16::ZERO; tensor.data().len()];
+        data.convert_from_f32_slice(tensor.data());
+        Self {
+            shape: tensor.shape().to_vec(),
+            device: Device::new(),
+            data: Cow::Owned(data),
+        }
+    }
+
+    /// The f32 copy of the tensor.
+    pub fn to_f32(&self) -> F32Tensor {
+        F32Tensor::new(self.data.to_f32_vec(), self.shape.clone())
+            .expect("The buffer matches the shape")
+    }
+
+    /// Overwrites the tensor with the data of `tensor`, which needs the same shape.
+    pub fn copy_from_f32(&mut self, tensor: &F32Tensor) -> Result<(), SmeltError> {
+        if self.shape != tensor.shape() {
+            return Err(SmeltError::DimensionMismatch {
+                expected: self.shape.clone(),
+                got: tensor.shape().to_vec(),
+            });
+        }
+        self.data_mut().convert_from_f32_slice(tensor.data());
+        Ok(())
+    }
+}
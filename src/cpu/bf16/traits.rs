@@ -0,0 +1,142 @@
+use super::ops;
+use super::tensor::{Device, Tensor};
+use crate::cpu::f32::{self as full, Tensor as F32Tensor};
+use crate::traits::QuantizedWeight;
+use crate::traits::{
+    Activation, Device as DeviceTrait, Tensor as TensorTrait, TensorActivation, TensorAdd,
+    TensorCopy, TensorGelu, TensorMatmul, TensorMatmulT, TensorMul, TensorMulScalar,
+    TensorNormalize, TensorOps, TensorSelect, TensorSoftmax, TensorTanh, TensorToDevice,
+};
+use crate::SmeltError;
+
+impl TensorTrait for Tensor {
+    type Device = Device;
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+}
+
+impl DeviceTrait for Device {
+    type Tensor = Tensor;
+
+    fn zeros(&self, shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
+        Ok(Self::Tensor::zeros(shape))
+    }
+
+    fn tensor(&self, data: &[f32], shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
+        Ok(Self::Tensor::from_f32(&crate::cpu::f32::Tensor::new(
+            data.to_vec(),
+            shape,
+        )?))
+    }
+}
+
+impl TensorCopy<Tensor> for Tensor {
+    fn copy(src: &Self, dst: &mut Self) -> Result<(), SmeltError> {
+        ops::copy(src, dst)
+    }
+}
+
+impl TensorAdd<Tensor> for Tensor {
+    fn add(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::add(x, y)
+    }
+    fn broadcast_add(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::broadcast_add(x, y)
+    }
+}
+
+impl TensorMul<Tensor> for Tensor {
+    fn mul(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::mul(x, y)
+    }
+    fn broadcast_mul(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::broadcast_mul(x, y)
+    }
+}
+
+impl TensorMulScalar<Tensor> for Tensor {
+    fn mul_scalar(x: &mut Self, factor: f32) -> Result<(), SmeltError> {
+        ops::mul_scalar(x, factor)
+    }
+}
+
+impl TensorNormalize<Tensor> for Tensor {
+    fn normalize(x: &mut Self, epsilon: f32) -> Result<(), SmeltError> {
+        ops::normalize(x, epsilon)
+    }
+}
+
+impl TensorMatmul<Tensor> for Tensor {
+    fn matmul(x: &Self, y: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        ops::matmul(x, y, out)
+    }
+}
+
+impl TensorMatmulT<Tensor> for Tensor {
+    fn matmul_t(x: &Self, y: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        ops::matmul_t(x, y, out)
+    }
+}
+
+impl TensorSelect<Tensor> for Tensor {
+    fn select(x: &[usize], weight: &Self, out: &mut Self) -> Result<(), SmeltError> {
+        ops::select(x, weight, out)
+    }
+}
+
+impl TensorGelu<Tensor> for Tensor {
+    fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_gelu(x)
+    }
+}
+
+impl TensorActivation<Tensor> for Tensor {
+    fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+        ops::apply_activation(x, activation)
+    }
+}
+
+impl TensorTanh<Tensor> for Tensor {
+    fn tanh(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_tanh(x)
+    }
+}
+
+impl TensorSoftmax<Tensor> for Tensor {
+    fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::softmax(x)
+    }
+}
+
+impl TensorToDevice for Tensor {
+    fn to_device(&self, _device: &Device) -> Result<Self, SmeltError> {
+        Ok(self.clone())
+    }
+}
+
+impl TensorOps<Tensor> for Tensor {}
+
+/// The weight of a [crate::nn::layers::Linear] running in mixed precision, see
+/// [crate::traits::TensorPrecision].
+impl QuantizedWeight<F32Tensor> for Tensor {
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn addmm(
+        &self,
+        x: &F32Tensor,
+        bias: &F32Tensor,
+        out: &mut F32Tensor,
+    ) -> Result<(), SmeltError> {
+        let mut c = Tensor::zeros(out.shape().to_vec());
+        ops::matmul_t(&Tensor::from_f32(x), self, &mut c)?;
+        out.data_mut().copy_from_slice(c.to_f32().data());
+        full::broadcast_add(bias, out)
+    }
+}
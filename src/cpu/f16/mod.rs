@@ -3,5 +3,8 @@ mod ops;
 /// The Tensor struct
 mod tensor;
 
+/// The Tensor trait implementations
+mod traits;
+
 pub use ops::*;
-pub use tensor::Tensor;
+pub use tensor::{Device, Tensor};
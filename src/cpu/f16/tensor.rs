@@ -1,91 +1,62 @@
-use crate::TensorError;
+use crate::cpu::f32::Tensor as F32Tensor;
+use crate::SmeltError;
 use half::f16;
+use half::slice::HalfFloatSliceExt;
 use std::borrow::Cow;
 
-/// Tensor, can own, or borrow the underlying tensor
-#[derive(Clone)]
-pub struct Tensor<'data> {
-    shape: Vec<usize>,
-    data: Cow<'data, [f16]>,
-}
+/// Tensor, can own, or borrow the underlying tensor.
+/// The data is stored in half precision, the ops compute in f32 (see [crate::cpu::f16]).
+pub type Tensor = crate::cpu::Tensor<f16>;
 
-impl<'data> Tensor<'data> {
-    /// The shape of the tensor
-    /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
-    ///
-    /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// assert_eq!(tensor.shape(), vec![2, 2]);
-    /// ```
-    pub fn shape(&self) -> &[usize] {
-        &self.shape
-    }
+/// The CPU device of the half precision tensors
+pub type Device = crate::cpu::Device<f16>;
 
-    /// A slice to the underlying tensor data
+impl Tensor {
+    /// The data of the tensor, converted to f32.
     /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
+    /// use smelte_rs::cpu::f16::Tensor;
     ///
     /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// assert_eq!(tensor.data(), vec![0.0; 4]);
-    /// ```
-    pub fn data(&self) -> &[f16] {
-        self.data.as_ref()
-    }
-
-    /// A mutable slice to the underlying tensor data
-    /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
-    ///
-    /// let mut tensor = Tensor::zeros(vec![2, 2]);
-    /// tensor.data_mut().iter_mut().for_each(|v| *v += 1.0);
-    /// assert_eq!(tensor.data(), vec![1.0; 4]);
+    /// assert_eq!(tensor.cpu_data().unwrap(), vec![0.0; 4]);
     /// ```
-    pub fn data_mut(&mut self) -> &mut [f16] {
-        self.data.to_mut()
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        Ok(self.data.to_f32_vec())
     }
 
-    /// Creates a new nulled tensor with given shape
+    /// The half precision copy of an f32 tensor, rounding to the nearest.
     /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
+    /// use smelte_rs::cpu::f16::Tensor;
+    /// use smelte_rs::cpu::f32::Tensor as F32Tensor;
     ///
-    /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// ```
-    pub fn zeros(shape: Vec<usize>) -> Self {
-        let nelement: usize = shape.iter().product();
-        let data = Cow::Owned(vec![f16::from_f32(0.0); nelement]);
-        Self { shape, data }
+    /// let tensor = F32Tensor::new(vec![1.0, 2.0], vec![2]).unwrap();
+    /// let tensor = Tensor::from_f32(&tensor);
+    /// assert_eq!(tensor.cpu_data().unwrap(), [1.0, 2.0]);
+    /// ```
+    pub fn from_f32(tensor: &F32Tensor) -> Self {
+        let mut data = vec![f16::ZERO; tensor.data().len()];
+        data.convert_from_f32_slice(tensor.data());
+        Self {
+            shape: tensor.shape().to_vec(),
+            device: Device::new(),
+            data: Cow::Owned(data),
+        }
     }
 
-    /// Creates a new borrowed tensor with given shape. Can fail if data doesn't match the shape
-    /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
-    ///
-    /// let data = [1.0, 2.0, 3.0, 4.0];
-    /// let tensor = Tensor::borrowed(&data, vec![2, 2]).unwrap();
-    /// ```
-    pub fn borrowed(data: &'data [f16], shape: Vec<usize>) -> Result<Self, TensorError> {
-        let cow: Cow<'data, [f16]> = data.into();
-        Self::new(cow, shape)
+    /// The f32 copy of the tensor.
+    pub fn to_f32(&self) -> F32Tensor {
+        F32Tensor::new(self.data.to_f32_vec(), self.shape.clone())
+            .expect("The buffer matches the shape")
     }
 
-    /// Creates a new tensor with given shape. Can fail if data doesn't match the shape
-    /// ```
-    /// use smelte-rs::cpu::f16::Tensor;
-    ///
-    /// let data = vec![1.0, 2.0, 3.0, 4.0];
-    /// let tensor = Tensor::new(data, vec![2, 2]).unwrap();
-    /// ```
-    pub fn new<T>(data: T, shape: Vec<usize>) -> Result<Self, TensorError>
-    where
-        T: Into<Cow<'data, [f16]>>,
-    {
-        let data = data.into();
-        if data.len() != shape.iter().product::<usize>() {
-            return Err(TensorError::InvalidBuffer {
-                buffer_size: data.len(),
-                shape,
+    /// Overwrites the tensor with the data of `tensor`, which needs the same shape.
+    pub fn copy_from_f32(&mut self, tensor: &F32Tensor) -> Result<(), SmeltError> {
+        if self.shape != tensor.shape() {
+            return Err(SmeltError::DimensionMismatch {
+                expected: self.shape.clone(),
+                got: tensor.shape().to_vec(),
             });
         }
-        Ok(Self { shape, data })
+        self.data_mut().convert_from_f32_slice(tensor.data());
+        Ok(())
     }
 }
@@ -0,0 +1,287 @@
+use crate::cpu::f32::{simd, Tensor};
+use crate::cpu::threads;
+use crate::shape;
+use crate::traits::{Conv1dConfig, Conv2dConfig};
+use crate::SmeltError;
+use rayon::prelude::*;
+
+/// 1d convolution of `x` (batch_size, in_channels, length) by `weight` (out_channels,
+/// in_channels / groups, kernel_size), plus the optional `bias` (out_channels), see
+/// [conv2d].
+/// ```
+/// use smelte_rs::cpu::f32::{conv1d, Tensor};
+/// use smelte_rs::traits::Conv1dConfig;
+///
+/// let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![1, 1, 4]).unwrap();
+/// let weight = Tensor::new(vec![1.0, -1.0], vec![1, 1, 2]).unwrap();
+/// let mut out = Tensor::zeros(vec![1, 1, 3]);
+/// conv1d(&x, &weight, None, &Conv1dConfig::default(), &mut out).unwrap();
+/// assert_eq!(out.data(), [-1.0, -1.0, -1.0]);
+/// ```
+pub fn conv1d(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv1dConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let expected = shape::conv1d(x.shape(), weight.shape(), bias.map(|b| b.shape()), config)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    let [_, _, length] = x.shape().try_into().unwrap();
+    let kernel_size = weight.shape()[2];
+    conv(
+        x,
+        weight,
+        bias,
+        &Conv2dConfig::from(*config),
+        [1, length],
+        [1, kernel_size],
+        out,
+    )
+}
+
+/// 2d convolution of `x` (batch_size, in_channels, height, width) by `weight`
+/// (out_channels, in_channels / groups, kernel_height, kernel_width), plus the optional
+/// `bias` (out_channels). The input is unfolded in columns (im2col) so that every group
+/// runs as one gemm.
+/// ```
+/// use smelte_rs::cpu::f32::{conv2d, Tensor};
+/// use smelte_rs::traits::Conv2dConfig;
+///
+/// // The sums of the 2x2 patches of a 4x4 image.
+/// let data: Vec<f32> = (0..16).map(|i| i as f32).collect();
+/// let x = Tensor::new(data, vec![1, 1, 4, 4]).unwrap();
+/// let weight = Tensor::new(vec![1.0; 4], vec![1, 1, 2, 2]).unwrap();
+/// let config = Conv2dConfig { stride: [2, 2], ..Default::default() };
+/// let mut out = Tensor::zeros(vec![1, 1, 2, 2]);
+/// conv2d(&x, &weight, None, &config, &mut out).unwrap();
+/// assert_eq!(out.data(), [10.0, 18.0, 42.0, 50.0]);
+/// ```
+pub fn conv2d(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv2dConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let expected = shape::conv2d(x.shape(), weight.shape(), bias.map(|b| b.shape()), config)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    let size = [x.shape()[2], x.shape()[3]];
+    let kernel_size = [weight.shape()[2], weight.shape()[3]];
+    conv(x, weight, bias, config, size, kernel_size, out)
+}
+
+/// The convolution of the images of `size` in `x` by the kernels of `kernel_size` in
+/// `weight`, the shapes being already checked.
+fn conv(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv2dConfig,
+    [height, width]: [usize; 2],
+    kernel_size: [usize; 2],
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (in_channels, out_channels) = (x.shape()[1], weight.shape()[0]);
+    let [out_height, out_width] = config.output_size([height, width], kernel_size).unwrap();
+    let kernel_numel = kernel_size[0] * kernel_size[1];
+    let out_numel = out_height * out_width;
+    let groups = config.groups;
+    let (m, n, k) = (
+        out_channels / groups,
+        out_numel,
+        in_channels / groups * kernel_numel,
+    );
+    out.data_mut().iter_mut().for_each(|v| *v = 0.0);
+    if m * n * k == 0 {
+        return Ok(());
+    }
+    if groups == in_channels {
+        return depthwise(x, weight, bias, config, [height, width], kernel_size, out);
+    }
+    threads::pool()?.install(|| {
+        out.data_mut()
+            .par_chunks_mut(out_channels * out_numel)
+            .zip(x.data().par_chunks(in_channels * height * width))
+            .for_each(|(out, x)| {
+                // The items of `x` under the kernel, (in_channels * kernel_numel, out_numel).
+                let mut columns = vec![0.0; in_channels * kernel_numel * out_numel];
+                for (row, column) in columns.chunks_exact_mut(out_numel).enumerate() {
+                    let channel = row / kernel_numel;
+                    let (ki, kj) = (row % kernel_numel / kernel_size[1], row % kernel_size[1]);
+                    let x = &x[channel * height * width..(channel + 1) * height * width];
+                    for (t, v) in column.iter_mut().enumerate() {
+                        let i = (t / out_width) * config.stride[0] + ki * config.dilation[0];
+                        let j = (t % out_width) * config.stride[1] + kj * config.dilation[1];
+                        match (
+                            i.checked_sub(config.padding[0]),
+                            j.checked_sub(config.padding[1]),
+                        ) {
+                            (Some(i), Some(j)) if i < height && j < width => *v = x[i * width + j],
+                            _ => (),
+                        }
+                    }
+                }
+                out.par_chunks_mut(m * n)
+                    .zip(weight.data().par_chunks(m * k))
+                    .zip(columns.par_chunks(k * n))
+                    .for_each(|((out, weight), columns)| simd::gemm(weight, columns, out, m, n, k));
+                if let Some(bias) = bias {
+                    out.chunks_exact_mut(out_numel)
+                        .zip(bias.data())
+                        .for_each(|(row, b)| row.iter_mut().for_each(|v| *v += b));
+                }
+            });
+    });
+    Ok(())
+}
+
+/// The depthwise case of [conv], every input channel having its own kernels: the planes
+/// of `out` are computed directly, the columns of a gemm would be as large as the input
+/// times the kernel for a single output row.
+fn depthwise(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv2dConfig,
+    [height, width]: [usize; 2],
+    kernel_size: [usize; 2],
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (in_channels, out_channels) = (x.shape()[1], weight.shape()[0]);
+    let [out_height, out_width] = config.output_size([height, width], kernel_size).unwrap();
+    let kernel_numel = kernel_size[0] * kernel_size[1];
+    let multiplier = out_channels / in_channels;
+    threads::pool()?.install(|| {
+        out.data_mut()
+            .par_chunks_mut(out_height * out_width)
+            .enumerate()
+            .for_each(|(plane, out)| {
+                let (b, oc) = (plane / out_channels, plane % out_channels);
+                let channel = b * in_channels + oc / multiplier;
+                let x = &x.data()[channel * height * width..(channel + 1) * height * width];
+                let kernel = &weight.data()[oc * kernel_numel..(oc + 1) * kernel_numel];
+                let b = bias.map_or(0.0, |bias| bias.data()[oc]);
+                for (t, v) in out.iter_mut().enumerate() {
+                    let (oi, oj) = (t / out_width, t % out_width);
+                    let mut sum = b;
+                    for (w, kernel) in kernel.iter().enumerate() {
+                        let (ki, kj) = (w / kernel_size[1], w % kernel_size[1]);
+                        let i = oi * config.stride[0] + ki * config.dilation[0];
+                        let j = oj * config.stride[1] + kj * config.dilation[1];
+                        match (
+                            i.checked_sub(config.padding[0]),
+                            j.checked_sub(config.padding[1]),
+                        ) {
+                            (Some(i), Some(j)) if i < height && j < width => {
+                                sum += x[i * width + j] * kernel
+                            }
+                            _ => (),
+                        }
+                    }
+                    *v = sum;
+                }
+            });
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conv1d() {
+        // Two groups of two channels, each output sums its group under a kernel of 3.
+        let data: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        let x = Tensor::new(data, vec![1, 4, 5]).unwrap();
+        let weight = Tensor::new(vec![1.0; 2 * 2 * 3], vec![2, 2, 3]).unwrap();
+        let bias = Tensor::new(vec![0.5, -0.5], vec![2]).unwrap();
+        let config = Conv1dConfig {
+            stride: 2,
+            padding: 1,
+            dilation: 1,
+            groups: 2,
+        };
+        let mut out = Tensor::zeros(vec![1, 2, 3]);
+        conv1d(&x, &weight, Some(&bias), &config, &mut out).unwrap();
+        // The group 0 holds the channels [0 1 2 3 4] and [5 6 7 8 9], padded by a zero.
+        assert_eq!(out.data(), [12.5, 27.5, 24.5, 51.5, 86.5, 63.5]);
+
+        let mut out = Tensor::zeros(vec![1, 2, 5]);
+        assert!(matches!(
+            conv1d(&x, &weight, None, &config, &mut out),
+            Err(SmeltError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_conv2d() {
+        // Two channels in two groups, padded by one on the width only.
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let x = Tensor::new(data, vec![1, 2, 2, 3]).unwrap();
+        let weight = Tensor::new(
+            vec![1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0],
+            vec![2, 1, 2, 2],
+        )
+        .unwrap();
+        let config = Conv2dConfig {
+            stride: [1, 2],
+            padding: [0, 1],
+            dilation: [1, 1],
+            groups: 2,
+        };
+        let mut out = Tensor::zeros(vec![1, 2, 1, 2]);
+        conv2d(&x, &weight, None, &config, &mut out).unwrap();
+        assert_eq!(out.data(), [3.0, 6.0, 6.0, 18.0]);
+    }
+
+    #[test]
+    fn test_depthwise_conv2d() {
+        // Two kernels per channel, compared to the same convolution split in groups of
+        // one output channel, which goes through the gemm.
+        let data: Vec<f32> = (0..2 * 2 * 4 * 5).map(|i| (i % 7) as f32 - 3.0).collect();
+        let x = Tensor::new(data, vec![2, 2, 4, 5]).unwrap();
+        let kernels: Vec<f32> = (0..4 * 9).map(|i| (i % 5) as f32 * 0.5).collect();
+        let weight = Tensor::new(kernels.clone(), vec![4, 1, 3, 3]).unwrap();
+        let bias = Tensor::new(vec![1.0, -1.0, 0.5, 0.0], vec![4]).unwrap();
+        let config = Conv2dConfig {
+            stride: [2, 1],
+            padding: [1, 1],
+            dilation: [1, 2],
+            groups: 2,
+        };
+        let mut out = Tensor::zeros(vec![2, 4, 2, 3]);
+        conv2d(&x, &weight, Some(&bias), &config, &mut out).unwrap();
+
+        let mut expected = Tensor::zeros(vec![2, 4, 2, 3]);
+        for b in 0..2 {
+            for oc in 0..4 {
+                let plane = &x.data()[(b * 2 + oc / 2) * 20..(b * 2 + oc / 2 + 1) * 20];
+                let x = Tensor::new(plane.to_vec(), vec![1, 1, 4, 5]).unwrap();
+                let kernel = kernels[oc * 9..(oc + 1) * 9].to_vec();
+                let weight = Tensor::new(kernel, vec![1, 1, 3, 3]).unwrap();
+                let bias = Tensor::new(vec![bias.data()[oc]], vec![1]).unwrap();
+                let config = Conv2dConfig {
+                    groups: 1,
+                    ..config
+                };
+                let mut plane = Tensor::zeros(vec![1, 1, 2, 3]);
+                conv2d(&x, &weight, Some(&bias), &config, &mut plane).unwrap();
+                let offset = (b * 4 + oc) * 6;
+                expected.data_mut()[offset..offset + 6].copy_from_slice(plane.data());
+            }
+        }
+        assert_eq!(out.data(), expected.data());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::cpu::f32::ops::broadcast_add;
+use crate::cpu::f32::tensor::Tensor;
+use crate::cpu::threads;
+use crate::traits::QuantizedWeight;
+use crate::SmeltError;
+use rayon::prelude::*;
+
+/// The position of the 8 columns in the nibbles of an AWQ int32, from lowest to highest.
+const AWQ_ORDER: [usize; 8] = [0, 4, 1, 5, 2, 6, 3, 7];
+
+/// The 4-bit weight of a matmul, quantized asymmetrically by groups of `group_size`
+/// consecutive inputs: `weight[i][j] ~= (data[i][j] - zeros[i][g]) * scales[i][g]` with
+/// `g = j / group_size`. Two values are packed per byte, the lowest nibble first.
+#[derive(Clone)]
+pub struct Int4Tensor {
+    shape: Vec<usize>,
+    group_size: usize,
+    data: Vec<u8>,
+    scales: Vec<f32>,
+    zeros: Vec<u8>,
+}
+
+/// Unpacks the nibble `i` of `packed`.
+#[inline]
+fn nibble(packed: i32, i: usize) -> u8 {
+    ((packed >> (4 * i)) & 0xF) as u8
+}
+
+impl Int4Tensor {
+    fn check(
+        out_features: usize,
+        in_features: usize,
+        group_size: usize,
+    ) -> Result<usize, SmeltError> {
+        if group_size == 0
+            || !group_size.is_multiple_of(2)
+            || !in_features.is_multiple_of(group_size)
+        {
+            return Err(SmeltError::InvalidLength {
+                expected: group_size,
+                got: in_features,
+            });
+        }
+        if out_features == 0 {
+            return Err(SmeltError::InvalidLength {
+                expected: 1,
+                got: out_features,
+            });
+        }
+        Ok(in_features / group_size)
+    }
+
+    /// Builds the tensor from the unpacked values `q(out_feature, in_feature)` and
+    /// `zero(out_feature, group)`, the scales being `(out_features, groups)`.
+    fn from_fn(
+        out_features: usize,
+        in_features: usize,
+        group_size: usize,
+        scales: Vec<f32>,
+        q: impl Fn(usize, usize) -> u8,
+        zero: impl Fn(usize, usize) -> u8,
+    ) -> Self {
+        let groups = in_features / group_size;
+        let data = (0..out_features)
+            .flat_map(|i| (0..in_features).step_by(2).map(move |j| (i, j)))
+            .map(|(i, j)| q(i, j) | (q(i, j + 1) << 4))
+            .collect();
+        let zeros = (0..out_features)
+            .flat_map(|i| (0..groups).map(move |g| (i, g)))
+            .map(|(i, g)| zero(i, g))
+            .collect();
+        Self {
+            shape: vec![out_features, in_features],
+            group_size,
+            data,
+            scales,
+            zeros,
+        }
+    }
+
+    /// Quantizes the 2d `weight` by groups of `group_size` inputs (round to nearest,
+    /// between the minimum and the maximum of every group).
+    /// ```
+    /// use smelte_rs::cpu::f32::{Int4Tensor, Tensor};
+    ///
+    /// let weight = Tensor::new(vec![0.0, 7.5, 15.0, 1.0], vec![1, 4]).unwrap();
+    /// let weight = Int4Tensor::quantize(&weight, 2).unwrap();
+    /// assert_eq!(weight.dequantize().data(), [0.0, 7.5, 15.0, 1.0]);
+    /// ```
+    pub fn quantize(weight: &Tensor, group_size: usize) -> Result<Self, SmeltError> {
+        if weight.shape().len() != 2 {
+            return Err(SmeltError::InvalidRank { expected_rank: 2 });
+        }
+        let (n, k) = (weight.shape()[0], weight.shape()[1]);
+        Self::check(n, k, group_size)?;
+        let data = weight.data();
+        let mut scales = Vec::with_capacity(data.len() / group_size);
+        let mut zeros = Vec::with_capacity(data.len() / group_size);
+        for group in data.chunks(group_size) {
+            let min = group.iter().fold(0.0f32, |min, &v| min.min(v));
+            let max = group.iter().fold(0.0f32, |max, &v| max.max(v));
+            let scale = if max > min { (max - min) / 15.0 } else { 1.0 };
+            scales.push(scale);
+            zeros.push((-min / scale).round().clamp(0.0, 15.0) as u8);
+        }
+        let groups = k / group_size;
+        let q = |i: usize, j: usize| {
+            let g = i * groups + j / group_size;
+            let q = data[i * k + j] / scales[g] + zeros[g] as f32;
+            q.round().clamp(0.0, 15.0) as u8
+        };
+        let tensor = Self::from_fn(n, k, group_size, scales.clone(), q, |i, g| {
+            zeros[i * groups + g]
+        });
+        Ok(tensor)
+    }
+
+    /// Loads the layout of GPTQ checkpoints, for a weight of `(out_features, in_features)`:
+    /// - `qweight`: `(in_features / 8, out_features)`, 8 inputs packed per int32.
+    /// - `qzeros`: `(groups, out_features / 8)`, 8 outputs packed per int32, minus one.
+    /// - `scales`: `(groups, out_features)`, converted to f32.
+    ///
+    /// The checkpoints quantized with `desc_act` (a `g_idx` which is not sequential) are
+    /// not supported.
+    pub fn from_gptq(
+        qweight: &[i32],
+        qzeros: &[i32],
+        scales: &[f32],
+        out_features: usize,
+        in_features: usize,
+    ) -> Result<Self, SmeltError> {
+        let group_size = Self::packed_group_size(scales, out_features, in_features)?;
+        let groups = Self::check(out_features, in_features, group_size)?;
+        let zeros_stride = out_features.div_ceil(8);
+        Self::check_packed(qweight, in_features.div_ceil(8) * out_features)?;
+        Self::check_packed(qzeros, groups * zeros_stride)?;
+        let q = |i: usize, j: usize| nibble(qweight[j / 8 * out_features + i], j % 8);
+        // GPTQ stores the zero points minus one.
+        let zero = |i: usize, g: usize| nibble(qzeros[g * zeros_stride + i / 8], i % 8) + 1;
+        let scales = transpose(scales, groups, out_features);
+        Ok(Self::from_fn(
+            out_features,
+            in_features,
+            group_size,
+            scales,
+            q,
+            zero,
+        ))
+    }
+
+    /// Loads the layout of AWQ checkpoints, for a weight of `(out_features, in_features)`:
+    /// - `qweight`: `(in_features, out_features / 8)`, 8 outputs packed per int32 in the
+    ///   interleaved AWQ order.
+    /// - `qzeros`: `(groups, out_features / 8)`, packed like `qweight`.
+    /// - `scales`: `(groups, out_features)`, converted to f32.
+    pub fn from_awq(
+        qweight: &[i32],
+        qzeros: &[i32],
+        scales: &[f32],
+        out_features: usize,
+        in_features: usize,
+    ) -> Result<Self, SmeltError> {
+        let group_size = Self::packed_group_size(scales, out_features, in_features)?;
+        let groups = Self::check(out_features, in_features, group_size)?;
+        let stride = out_features.div_ceil(8);
+        Self::check_packed(qweight, in_features * stride)?;
+        Self::check_packed(qzeros, groups * stride)?;
+        let q = |i: usize, j: usize| nibble(qweight[j * stride + i / 8], AWQ_ORDER[i % 8]);
+        let zero = |i: usize, g: usize| nibble(qzeros[g * stride + i / 8], AWQ_ORDER[i % 8]);
+        let scales = transpose(scales, groups, out_features);
+        Ok(Self::from_fn(
+            out_features,
+            in_features,
+            group_size,
+            scales,
+            q,
+            zero,
+        ))
+    }
+
+    fn packed_group_size(
+        scales: &[f32],
+        out_features: usize,
+        in_features: usize,
+    ) -> Result<usize, SmeltError> {
+        let groups = scales.len() / out_features.max(1);
+        if groups == 0 || groups * out_features != scales.len() {
+            return Err(SmeltError::InvalidLength {
+                expected: out_features,
+                got: scales.len(),
+            });
+        }
+        Ok(in_features / groups)
+    }
+
+    fn check_packed(packed: &[i32], expected: usize) -> Result<(), SmeltError> {
+        if packed.len() != expected {
+            return Err(SmeltError::InvalidLength {
+                expected,
+                got: packed.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The shape of the tensor
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The number of consecutive inputs sharing a scale and a zero point
+    pub fn group_size(&self) -> usize {
+        self.group_size
+    }
+
+    /// The packed values, two per byte
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The scale of every group, (out_features, groups)
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    /// The zero point of every group, (out_features, groups)
+    pub fn zeros(&self) -> &[u8] {
+        &self.zeros
+    }
+
+    /// The f32 approximation of the original weight.
+    pub fn dequantize(&self) -> Tensor {
+        let k = self.shape[1];
+        let mut data = vec![0.0; self.shape[0] * k];
+        data.chunks_mut(k)
+            .enumerate()
+            .for_each(|(i, row)| self.dequantize_row(i, row));
+        Tensor::new(data, self.shape.clone()).expect("The buffer matches the shape")
+    }
+
+    /// Dequantizes the row `i` into `out`.
+    #[inline]
+    fn dequantize_row(&self, i: usize, out: &mut [f32]) {
+        let k = self.shape[1];
+        let groups = k / self.group_size;
+        let packed = &self.data[i * k / 2..(i + 1) * k / 2];
+        out.chunks_mut(self.group_size)
+            .zip(packed.chunks(self.group_size / 2))
+            .enumerate()
+            .for_each(|(g, (out, packed))| {
+                let scale = self.scales[i * groups + g];
+                let zero = self.zeros[i * groups + g] as f32;
+                out.chunks_mut(2).zip(packed).for_each(|(out, byte)| {
+                    out[0] = ((byte & 0xF) as f32 - zero) * scale;
+                    out[1] = ((byte >> 4) as f32 - zero) * scale;
+                });
+            });
+    }
+}
+
+/// The (cols, rows) copy of a (rows, cols) matrix.
+fn transpose(data: &[f32], rows: usize, cols: usize) -> Vec<f32> {
+    (0..cols)
+        .flat_map(|j| (0..rows).map(move |i| data[i * cols + j]))
+        .collect()
+}
+
+/// Matrix multiplication matmul(A, B.transposed()) with 4-bit weights `b`. Every row of
+/// `b` is dequantized on the fly, into a buffer reused across the rows.
+pub fn matmul_t_int4(a: &Tensor, b: &Int4Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let n = b.shape[0];
+    let k = b.shape[1];
+    let mut expected = a.shape().to_vec();
+    expected[dim - 1] = k;
+    if a.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: a.shape().to_vec(),
+        });
+    }
+    expected[dim - 1] = n;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if k == 0 {
+        out.data_mut().iter_mut().for_each(|v| *v = 0.0);
+        return Ok(());
+    }
+
+    let m = a.data().len() / k;
+    let a = a.data();
+    let out = out.data_mut();
+    // Every output feature is a column of `out`, the threads split the features so that
+    // each row of `b` is dequantized once.
+    let mut columns = vec![0.0; n * m];
+    threads::pool()?.install(|| {
+        columns.par_chunks_mut(m).enumerate().for_each_init(
+            || vec![0.0; k],
+            |row, (i, column)| {
+                b.dequantize_row(i, row);
+                column
+                    .iter_mut()
+                    .zip(a.chunks(k))
+                    .for_each(|(v, a)| *v = a.iter().zip(row.iter()).map(|(x, y)| x * y).sum());
+            },
+        );
+    });
+    out.chunks_mut(n).enumerate().for_each(|(r, out)| {
+        out.iter_mut()
+            .enumerate()
+            .for_each(|(i, v)| *v = columns[i * m + r]);
+    });
+    Ok(())
+}
+
+impl QuantizedWeight<Tensor> for Int4Tensor {
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn addmm(&self, x: &Tensor, bias: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        matmul_t_int4(x, self, out)?;
+        broadcast_add(bias, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::matmul_t;
+
+    #[test]
+    fn simple_matmul_t_int4() {
+        let data: Vec<_> = (0..24).map(|i| (i as f32 * 0.7).sin()).collect();
+        let b = Tensor::new(data, vec![3, 8]).unwrap();
+        let data: Vec<_> = (0..16).map(|i| (i as f32 * 0.3).cos()).collect();
+        let a = Tensor::new(data, vec![2, 8]).unwrap();
+        let mut expected = Tensor::zeros(vec![2, 3]);
+        matmul_t(&a, &b, &mut expected).unwrap();
+
+        let b = Int4Tensor::quantize(&b, 4).unwrap();
+        assert_eq!(b.scales().len(), 6);
+        let mut out = Tensor::zeros(vec![2, 3]);
+        matmul_t_int4(&a, &b, &mut out).unwrap();
+        for (v, e) in out.data().iter().zip(expected.data()) {
+            assert!((v - e).abs() < 0.2, "{v} != {e}");
+        }
+        assert!(Int4Tensor::quantize(&Tensor::zeros(vec![3, 6]), 4).is_err());
+    }
+
+    #[test]
+    fn simple_packed_layouts() {
+        // A (8, 2) weight, one group, q(i, j) = i + 8 * j, zero = 3, scale = 0.5.
+        let expected: Vec<f32> = (0..8)
+            .flat_map(|i| (0..2).map(move |j| (i + 8 * j) as f32 - 3.0))
+            .map(|v| v * 0.5)
+            .collect();
+        let scales = [0.5; 8];
+
+        // GPTQ packs the inputs, the zero points are stored minus one.
+        let pack = |values: [i32; 8]| (0..8).fold(0, |p, i| p | (values[i] << (4 * i)));
+        let qweight: Vec<_> = (0..8).map(|i| pack([i, i + 8, 0, 0, 0, 0, 0, 0])).collect();
+        let qzeros = [pack([2; 8])];
+        let weight = Int4Tensor::from_gptq(&qweight, &qzeros, &scales, 8, 2).unwrap();
+        assert_eq!(weight.group_size(), 2);
+        assert_eq!(weight.dequantize().data(), expected);
+
+        // AWQ packs the outputs, interleaved.
+        let pack = |values: [i32; 8]| (0..8).fold(0, |p, i| p | (values[i] << (4 * AWQ_ORDER[i])));
+        let qweight = [
+            pack([0, 1, 2, 3, 4, 5, 6, 7]),
+            pack([8, 9, 10, 11, 12, 13, 14, 15]),
+        ];
+        let qzeros = [pack([3; 8])];
+        let weight = Int4Tensor::from_awq(&qweight, &qzeros, &scales, 8, 2).unwrap();
+        assert_eq!(weight.dequantize().data(), expected);
+
+        assert!(Int4Tensor::from_awq(&qweight[..1], &qzeros, &scales, 8, 2).is_err());
+    }
+}
@@ -1,10 +1,33 @@
+/// The convolutions
+mod conv;
+/// The packed 4-bit weights of the quantized matmuls
+mod int4;
 /// The various ops
 mod ops;
+/// The int8 weights of the quantized matmuls
+mod quantized;
+/// Vectorized kernels (AVX2, AVX-512, NEON), picked at runtime
+// The gemm kernels are unused when a BLAS library runs the matmuls.
+#[cfg_attr(
+    any(
+        feature = "rblas",
+        feature = "cblas",
+        feature = "intel-mkl",
+        feature = "openblas",
+        feature = "accelerate"
+    ),
+    allow(dead_code)
+)]
+mod simd;
 /// The Tensor struct
 mod tensor;
 
 /// The Tensor trait implementations
 mod traits;
 
+pub use conv::{conv1d, conv2d};
+pub use int4::{matmul_t_int4, Int4Tensor};
 pub use ops::*;
+pub use quantized::{int8_isa, int8_linear, matmul_t_int8, Int8Linear, Int8Tensor};
+pub use simd::{Int8Isa, Isa};
 pub use tensor::{Device, Tensor};
@@ -1,5 +1,10 @@
+use crate::cpu::f32::simd::{self, Isa};
 use crate::cpu::f32::tensor::Tensor;
+use crate::cpu::{threads, Mask};
+use crate::shape;
+use crate::traits::{Activation, FusedAttentionConfig};
 use crate::SmeltError;
+use rayon::prelude::*;
 
 #[cfg(feature = "matrixmultiply")]
 use matrixmultiply::sgemm;
@@ -7,7 +12,12 @@ use matrixmultiply::sgemm;
 #[cfg(feature = "rblas")]
 use rblas::{batched_sgemm, batched_sgemm_t};
 
-#[cfg(any(feature = "cblas", feature = "intel-mkl"))]
+#[cfg(any(
+    feature = "cblas",
+    feature = "intel-mkl",
+    feature = "openblas",
+    feature = "accelerate"
+))]
 use cblas_sys::{
     cblas_sgemm as sgemm, CblasColMajor as ColMajor, CblasNoTrans as NoTr,
     CblasRowMajor as RowMajor, CblasTrans as Tr,
@@ -44,6 +54,45 @@ pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
+/// The library running [matmul] and [matmul_t], see [gemm_provider].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GemmProvider {
+    /// The kernels of this crate, vectorized with the given instruction set
+    Rust(Isa),
+    /// `rblas`
+    Rblas,
+    /// Intel MKL
+    Mkl,
+    /// Apple Accelerate
+    Accelerate,
+    /// OpenBLAS
+    OpenBlas,
+    /// Any other cblas implementation
+    Cblas,
+}
+
+/// The gemm provider picked by the enabled features, the crate's own kernels by default.
+/// ```
+/// use smelte_rs::cpu::f32::{gemm_provider, GemmProvider};
+///
+/// println!("Matmuls run on {:?}", gemm_provider());
+/// ```
+pub fn gemm_provider() -> GemmProvider {
+    if cfg!(feature = "rblas") {
+        GemmProvider::Rblas
+    } else if cfg!(feature = "intel-mkl") {
+        GemmProvider::Mkl
+    } else if cfg!(feature = "accelerate") {
+        GemmProvider::Accelerate
+    } else if cfg!(feature = "openblas") {
+        GemmProvider::OpenBlas
+    } else if cfg!(feature = "cblas") {
+        GemmProvider::Cblas
+    } else {
+        GemmProvider::Rust(simd::isa())
+    }
+}
+
 /// Regular matrix multiplication
 pub fn matmul(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
     g_matmul::<false>(a, b, out)
@@ -54,6 +103,24 @@ pub fn matmul_t(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltErr
     g_matmul::<true>(a, b, out)
 }
 
+/// Batched matrix multiplication of `a` (batch, m, k) by `b` (batch, k, n) into `out`
+/// (batch, m, n).
+/// ```
+/// use smelte_rs::cpu::f32::{bmm, Tensor};
+///
+/// let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 1, 2]).unwrap();
+/// let b = Tensor::new(vec![1.0, 1.0, 2.0, 0.0], vec![2, 2, 1]).unwrap();
+/// let mut out = Tensor::zeros(vec![2, 1, 1]);
+/// bmm(&a, &b, &mut out).unwrap();
+/// assert_eq!(out.data(), [3.0, 6.0]);
+/// ```
+pub fn bmm(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape().len() != 3 {
+        return Err(SmeltError::InvalidRank { expected_rank: 3 });
+    }
+    g_matmul::<false>(a, b, out)
+}
+
 #[inline]
 fn g_matmul<const TRANSPOSE: bool>(
     a: &Tensor,
@@ -147,7 +214,54 @@ fn g_matmul<const TRANSPOSE: bool>(
         Ok(())
     }
 
-    #[cfg(not(feature = "rblas"))]
+    #[cfg(not(any(
+        feature = "rblas",
+        feature = "matrixmultiply",
+        feature = "cblas",
+        feature = "intel-mkl",
+        feature = "openblas",
+        feature = "accelerate"
+    )))]
+    {
+        if c_skip == 0 {
+            return Ok(());
+        }
+        let (a, b) = (a.data(), b.data());
+        threads::pool()?.install(|| {
+            // One task per batch (attention head), split again in blocks of rows.
+            let rows = m.div_ceil(rayon::current_num_threads());
+            c.data_mut()
+                .par_chunks_mut(c_skip)
+                .enumerate()
+                .for_each(|(step, cp)| {
+                    let ap = &a[step * a_skip..(step + 1) * a_skip];
+                    let bp = &b[step * b_skip..(step + 1) * b_skip];
+                    cp.par_chunks_mut(rows * n)
+                        .enumerate()
+                        .for_each(|(block, cp)| {
+                            let block_m = cp.len() / n;
+                            let ap = &ap[block * rows * k..(block * rows + block_m) * k];
+                            if TRANSPOSE {
+                                simd::gemm_t(ap, bp, cp, block_m, n, k);
+                            } else {
+                                simd::gemm(ap, bp, cp, block_m, n, k);
+                            }
+                        });
+                });
+        });
+        Ok(())
+    }
+
+    #[cfg(all(
+        not(feature = "rblas"),
+        any(
+            feature = "matrixmultiply",
+            feature = "cblas",
+            feature = "intel-mkl",
+            feature = "openblas",
+            feature = "accelerate"
+        )
+    ))]
     {
         let ar = k as isize;
         let ac = 1;
@@ -184,7 +298,12 @@ fn g_matmul<const TRANSPOSE: bool>(
                 );
             }
 
-            #[cfg(any(feature = "cblas", feature = "intel-mkl"))]
+            #[cfg(any(
+                feature = "cblas",
+                feature = "intel-mkl",
+                feature = "openblas",
+                feature = "accelerate"
+            ))]
             unsafe {
                 let (m, n, k) = (m as libc::c_int, n as libc::c_int, k as libc::c_int);
                 let (layout, a_tr, b_tr, lda, ldb, ldc) = if cr < cc {
@@ -237,26 +356,79 @@ pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
-/// broacasted tensor elementwise addition. b += a.
-pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
-    if &b.shape()[1..] != a.shape() {
+/// tensor elementwise subtraction. b -= a.
+pub fn sub(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape() != b.shape() {
         return Err(SmeltError::DimensionMismatch {
             expected: b.shape().to_vec(),
             got: a.shape().to_vec(),
         });
     }
-    let n = b.shape()[0];
-    let skip: usize = a.shape().iter().product();
-    (0..n).for_each(|i| {
-        a.data()
-            .iter()
-            .zip(b.data_mut().iter_mut().skip(i * skip))
-            .for_each(|(left, right)| *right += left);
-    });
+    a.data()
+        .iter()
+        .zip(b.data_mut().iter_mut())
+        .for_each(|(left, right)| *right -= left);
     Ok(())
 }
 
-/// tensor elementwise multiplication. b += a.
+/// Applies `f(b, a)` on every item of `b`, `a` being broadcasted to the shape of `b`
+/// like in NumPy.
+fn broadcast_op(a: &Tensor, b: &mut Tensor, f: impl Fn(&mut f32, f32)) -> Result<(), SmeltError> {
+    let strides = shape::broadcast_strides(a.shape(), b.shape())?;
+    let repeated = shape::is_repeated(a.shape(), b.shape());
+    let shape = b.shape().to_vec();
+    let a = a.data();
+    if a.is_empty() {
+        return Ok(());
+    }
+    if repeated {
+        b.data_mut().chunks_mut(a.len()).for_each(|chunk| {
+            chunk
+                .iter_mut()
+                .zip(a)
+                .for_each(|(right, &left)| f(right, left))
+        });
+    } else {
+        // Walks the indices of `b`, keeping the offset in `a` up to date.
+        let mut index = vec![0; shape.len()];
+        let mut offset = 0;
+        for right in b.data_mut() {
+            f(right, a[offset]);
+            for d in (0..shape.len()).rev() {
+                index[d] += 1;
+                offset += strides[d];
+                if index[d] < shape[d] {
+                    break;
+                }
+                offset -= strides[d] * shape[d];
+                index[d] = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// broacasted tensor elementwise addition. b += a.
+/// `a` is broadcasted to the shape of `b` like in NumPy: the shapes are aligned on the
+/// right and the dimensions of size 1 are repeated.
+/// ```
+/// use smelte_rs::cpu::f32::{broadcast_add, Tensor};
+///
+/// let a = Tensor::new(vec![10.0, 20.0], vec![2, 1]).unwrap();
+/// let mut b = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+/// broadcast_add(&a, &mut b).unwrap();
+/// assert_eq!(b.data(), [11.0, 12.0, 23.0, 24.0]);
+/// ```
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op(a, b, |right, left| *right += left)
+}
+
+/// broacasted tensor elementwise subtraction. b -= a, see [broadcast_add].
+pub fn broadcast_sub(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op(a, b, |right, left| *right -= left)
+}
+
+/// tensor elementwise multiplication. b *= a.
 pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     if a.shape() != b.shape() {
         return Err(SmeltError::DimensionMismatch {
@@ -271,23 +443,9 @@ pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
-/// broacasted tensor elementwise multiplication. b += a.
+/// broacasted tensor elementwise multiplication. b *= a, see [broadcast_add].
 pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
-    if &b.shape()[1..] != a.shape() {
-        return Err(SmeltError::DimensionMismatch {
-            expected: b.shape().to_vec(),
-            got: a.shape().to_vec(),
-        });
-    }
-    let n = b.shape()[0];
-    let skip: usize = a.shape().iter().product();
-    (0..n).for_each(|i| {
-        a.data()
-            .iter()
-            .zip(b.data_mut().iter_mut().skip(i * skip))
-            .for_each(|(left, right)| *right *= left);
-    });
-    Ok(())
+    broadcast_op(a, b, |right, left| *right *= left)
 }
 
 /// Basic operation for the layernorm.
@@ -297,14 +455,10 @@ pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
 pub fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
     let dim = x.shape().len();
     let size = x.shape()[dim - 1];
-    x.data_mut().chunks_mut(size).for_each(|chunk| {
-        let sum: f32 = chunk.iter().sum();
-        let mean = sum / size as f32;
-        chunk.iter_mut().for_each(|v| *v -= mean);
-        let var: f32 = chunk.iter().map(|v| v * v).sum();
-        let var = var / size as f32;
-        let stddev: f32 = (var + epsilon).sqrt();
-        chunk.iter_mut().for_each(|v| *v /= stddev);
+    threads::pool()?.install(|| {
+        x.data_mut()
+            .par_chunks_mut(size)
+            .for_each(|chunk| simd::normalize(chunk, epsilon));
     });
     Ok(())
 }
@@ -319,48 +473,712 @@ fn g_softmax<const CAUSAL: bool>(
     let m = x.shape()[dim - 2];
     let n = x.shape()[dim - 1];
 
+    threads::pool()?.install(|| {
+        x.data_mut()
+            .par_chunks_mut(n)
+            .enumerate()
+            .for_each(|(i, chunk)| {
+                let i = i % m;
+                let valid = if CAUSAL {
+                    n.min(i + past_sequence_length + 1)
+                } else {
+                    n
+                };
+                let (row, masked) = chunk.split_at_mut(valid);
+                simd::softmax(row);
+                masked.iter_mut().for_each(|v| *v = 0.0);
+            });
+    });
+    Ok(())
+}
+
+/// Softmax on the last dimension for tensor `x`
+pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    g_softmax::<false>(x, 0)
+}
+
+/// Causal softmax on the last dimension for tensor `x`. The causality is determined by the
+/// shape of `x` and `past_sequence_length` which defines how big is the missing part of the
+/// square.
+pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
+    g_softmax::<true>(x, past_sequence_length)
+}
+
+/// Softmax on the dimension `dim` for tensor `x`.
+/// ```
+/// use smelte_rs::cpu::f32::{softmax_dim, Tensor};
+///
+/// let mut x = Tensor::new(vec![1.0, 2.0, 1.0, 2.0], vec![2, 2]).unwrap();
+/// softmax_dim(&mut x, 0).unwrap();
+/// assert_eq!(x.data(), [0.5; 4]);
+/// ```
+pub fn softmax_dim(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+    softmax_t(x, dim, 1.0)
+}
+
+/// Softmax of `x / temperature` on the dimension `dim` for tensor `x`.
+pub fn softmax_t(x: &mut Tensor, dim: usize, temperature: f32) -> Result<(), SmeltError> {
+    if temperature.is_nan() || temperature <= 0.0 {
+        return Err(SmeltError::InvalidTemperature(temperature));
+    }
+    let (_, size, inner) = shape::narrow(x.shape(), dim, 0, 0)?;
+    if size * inner == 0 {
+        return Ok(());
+    }
+    let scale = 1.0 / temperature;
+    threads::pool()?.install(|| {
+        if inner == 1 {
+            x.data_mut().par_chunks_mut(size).for_each(|row| {
+                if scale != 1.0 {
+                    row.iter_mut().for_each(|v| *v *= scale);
+                }
+                simd::softmax(row);
+            });
+        } else {
+            // The items of a row are `inner` apart, they are gathered to run the kernel.
+            x.data_mut().par_chunks_mut(size * inner).for_each(|chunk| {
+                let mut row = vec![0.0; size];
+                for i in 0..inner {
+                    row.iter_mut()
+                        .enumerate()
+                        .for_each(|(j, v)| *v = chunk[j * inner + i] * scale);
+                    simd::softmax(&mut row);
+                    row.iter()
+                        .enumerate()
+                        .for_each(|(j, &v)| chunk[j * inner + i] = v);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Rotary position embedding of `x` in place, see [crate::traits::TensorRotary].
+/// ```
+/// use smelte_rs::cpu::f32::{rotary, Tensor};
+///
+/// // A quarter turn at the position 1.
+/// let cos = Tensor::new(vec![1.0, 0.0], vec![2, 1]).unwrap();
+/// let sin = Tensor::new(vec![0.0, 1.0], vec![2, 1]).unwrap();
+/// let mut x = Tensor::new(vec![1.0, 2.0], vec![1, 2]).unwrap();
+/// rotary(&mut x, &cos, &sin, 1).unwrap();
+/// assert_eq!(x.data(), [-2.0, 1.0]);
+/// ```
+pub fn rotary(x: &mut Tensor, cos: &Tensor, sin: &Tensor, offset: usize) -> Result<(), SmeltError> {
+    let (length, half) = shape::rotary(x.shape(), cos.shape(), sin.shape(), offset)?;
+    if x.data().is_empty() || half == 0 {
+        return Ok(());
+    }
+    let (cos, sin) = (cos.data(), sin.data());
     x.data_mut()
-        .chunks_mut(n)
+        .chunks_exact_mut(2 * half)
         .enumerate()
-        .for_each(|(i, chunk)| {
-            let i = i % m;
-            let mut current_max = f32::NEG_INFINITY;
-            for (j, &v) in chunk.iter().enumerate() {
-                if (!CAUSAL || i + past_sequence_length >= j) && v > current_max {
-                    current_max = v;
+        .for_each(|(i, row)| {
+            let position = offset + i % length;
+            let cos = &cos[position * half..(position + 1) * half];
+            let sin = &sin[position * half..(position + 1) * half];
+            let (x1, x2) = row.split_at_mut(half);
+            for j in 0..half {
+                let (a, b) = (x1[j], x2[j]);
+                x1[j] = a * cos[j] - b * sin[j];
+                x2[j] = b * cos[j] + a * sin[j];
+            }
+        });
+    Ok(())
+}
+
+/// The queries of a block of [fused_attention], attending to the same tiles of keys.
+const QUERY_BLOCK: usize = 16;
+/// The keys of a tile of [fused_attention], small enough for the tile and its values to
+/// stay in the cache for the queries of a block.
+const KEY_BLOCK: usize = 64;
+
+/// Scaled dot-product attention without the scores, see
+/// [crate::traits::TensorFusedAttention]. The heads run in parallel, the queries by
+/// blocks over the tiles of keys.
+/// ```
+/// use smelte_rs::cpu::f32::{fused_attention, Tensor};
+/// use smelte_rs::traits::FusedAttentionConfig;
+///
+/// // The second key is masked, the query gets the first value.
+/// let query = Tensor::new(vec![1.0, 0.0], vec![1, 1, 2]).unwrap();
+/// let key = Tensor::new(vec![1.0, 0.0, 0.0, 1.0], vec![1, 2, 2]).unwrap();
+/// let value = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![1, 2, 2]).unwrap();
+/// let mask = Tensor::new(vec![0.0, f32::NEG_INFINITY], vec![2]).unwrap();
+/// let config = FusedAttentionConfig::default();
+/// let mut out = Tensor::zeros(vec![1, 1, 2]);
+/// fused_attention(&query, &key, &value, Some(&mask), None, &config, &mut out).unwrap();
+/// assert_eq!(out.data(), [1.0, 2.0]);
+/// ```
+pub fn fused_attention(
+    query: &Tensor,
+    key: &Tensor,
+    value: &Tensor,
+    mask: Option<&Tensor>,
+    slopes: Option<&Tensor>,
+    config: &FusedAttentionConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (query_length, key_length, head_dim) = shape::attention(
+        query.shape(),
+        key.shape(),
+        value.shape(),
+        mask.map(|mask| mask.shape()),
+        slopes.map(|slopes| slopes.shape()),
+        out.shape(),
+    )?;
+    if out.data().is_empty() {
+        return Ok(());
+    }
+    let (query, key, value) = (query.data(), key.data(), value.data());
+    let (mask, slopes) = (
+        mask.map(|mask| mask.data()),
+        slopes.map(|slopes| slopes.data()),
+    );
+    let FusedAttentionConfig { scale, causal } = *config;
+    let offset = key_length.saturating_sub(query_length);
+    let (query_size, key_size) = (query_length * head_dim, key_length * head_dim);
+    out.data_mut()
+        .par_chunks_mut(query_size)
+        .enumerate()
+        .for_each(|(head, out)| {
+            let query = &query[head * query_size..(head + 1) * query_size];
+            let key = &key[head * key_size..(head + 1) * key_size];
+            let value = &value[head * key_size..(head + 1) * key_size];
+            let slope = slopes.map_or(0.0, |slopes| slopes[head]);
+            let mut scores = [0.0; KEY_BLOCK];
+            for (block, out) in out.chunks_mut(QUERY_BLOCK * head_dim).enumerate() {
+                let mut max = [f32::NEG_INFINITY; QUERY_BLOCK];
+                let mut sum = [0.0; QUERY_BLOCK];
+                out.fill(0.0);
+                for start in (0..key_length).step_by(KEY_BLOCK) {
+                    let end = (start + KEY_BLOCK).min(key_length);
+                    for (r, row) in out.chunks_mut(head_dim).enumerate() {
+                        let i = block * QUERY_BLOCK + r;
+                        let position = offset + i;
+                        // The keys after the query are masked by the causal mask.
+                        let end = if causal { end.min(position + 1) } else { end };
+                        if end <= start {
+                            continue;
+                        }
+                        let q = &query[i * head_dim..(i + 1) * head_dim];
+                        let scores = &mut scores[..end - start];
+                        for (score, j) in scores.iter_mut().zip(start..end) {
+                            let k = &key[j * head_dim..(j + 1) * head_dim];
+                            let dot: f32 = q.iter().zip(k).map(|(q, k)| q * k).sum();
+                            *score = dot * scale - slope * position.abs_diff(j) as f32
+                                + mask.map_or(0.0, |m| m[j]);
+                        }
+                        // The online softmax rescales what was accumulated for the
+                        // previous tiles to the new maximum.
+                        let tile_max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                        let new_max = max[r].max(tile_max);
+                        if new_max == f32::NEG_INFINITY {
+                            continue;
+                        }
+                        let correction = (max[r] - new_max).exp();
+                        sum[r] *= correction;
+                        row.iter_mut().for_each(|o| *o *= correction);
+                        for (score, j) in scores.iter().zip(start..end) {
+                            let p = (score - new_max).exp();
+                            sum[r] += p;
+                            let v = &value[j * head_dim..(j + 1) * head_dim];
+                            row.iter_mut().zip(v).for_each(|(o, v)| *o += p * v);
+                        }
+                        max[r] = new_max;
+                    }
+                }
+                for (row, sum) in out.chunks_mut(head_dim).zip(sum) {
+                    row.iter_mut().for_each(|o| *o /= sum);
                 }
             }
-            for v in chunk.iter_mut() {
-                *v -= current_max;
-                *v = exp(*v);
+        });
+    Ok(())
+}
+
+/// Top-p filtering of the logits `x` on its last dimension, see [crate::traits::TensorTopP].
+/// ```
+/// use smelte_rs::cpu::f32::{top_p, Tensor};
+///
+/// let mut logits = Tensor::new(vec![2.0, 0.0, 1.0, -1.0], vec![1, 4]).unwrap();
+/// top_p(&mut logits, 0.8).unwrap();
+/// assert_eq!(logits.data(), [2.0, f32::NEG_INFINITY, 1.0, f32::NEG_INFINITY]);
+/// ```
+pub fn top_p(x: &mut Tensor, p: f32) -> Result<(), SmeltError> {
+    if !(p > 0.0 && p <= 1.0) {
+        return Err(SmeltError::InvalidProbability(p));
+    }
+    let size = match x.shape().last() {
+        Some(&size) => size,
+        None => return Err(SmeltError::InsufficientRank { minimum_rank: 1 }),
+    };
+    if x.data().is_empty() {
+        return Ok(());
+    }
+    threads::pool()?.install(|| {
+        x.data_mut().par_chunks_mut(size).for_each(|row| {
+            let mut order: Vec<usize> = (0..size).collect();
+            order.sort_by(|&a, &b| {
+                row[b]
+                    .partial_cmp(&row[a])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let max = row[order[0]];
+            let sum: f32 = row.iter().map(|&v| (v - max).exp()).sum();
+            let mut cumsum = 0.0;
+            let keep = order
+                .iter()
+                .position(|&j| {
+                    cumsum += (row[j] - max).exp() / sum;
+                    cumsum >= p
+                })
+                .map_or(size, |j| j + 1);
+            order[keep..]
+                .iter()
+                .for_each(|&j| row[j] = f32::NEG_INFINITY);
+        });
+    });
+    Ok(())
+}
+
+/// Top-k filtering of the logits `x` on its last dimension, see [crate::traits::TensorTopK].
+/// ```
+/// use smelte_rs::cpu::f32::{top_k, Tensor};
+///
+/// let mut logits = Tensor::new(vec![2.0, 0.0, 1.0, -1.0], vec![1, 4]).unwrap();
+/// top_k(&mut logits, 2).unwrap();
+/// assert_eq!(logits.data(), [2.0, f32::NEG_INFINITY, 1.0, f32::NEG_INFINITY]);
+/// ```
+pub fn top_k(x: &mut Tensor, k: usize) -> Result<(), SmeltError> {
+    if k == 0 {
+        return Err(SmeltError::InvalidConfig(
+            "top-k filtering with k = 0".into(),
+        ));
+    }
+    let size = match x.shape().last() {
+        Some(&size) => size,
+        None => return Err(SmeltError::InsufficientRank { minimum_rank: 1 }),
+    };
+    if x.data().is_empty() || k >= size {
+        return Ok(());
+    }
+    threads::pool()?.install(|| {
+        x.data_mut().par_chunks_mut(size).for_each(|row| {
+            let mut order: Vec<usize> = (0..size).collect();
+            order.sort_by(|&a, &b| {
+                row[b]
+                    .partial_cmp(&row[a])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            order[k..].iter().for_each(|&j| row[j] = f32::NEG_INFINITY);
+        });
+    });
+    Ok(())
+}
+
+/// Cumulative sum on the dimension `dim` for tensor `x`.
+/// ```
+/// use smelte_rs::cpu::f32::{cumsum, Tensor};
+///
+/// let mut x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+/// cumsum(&mut x, 1).unwrap();
+/// assert_eq!(x.data(), [1.0, 3.0, 3.0, 7.0]);
+/// ```
+pub fn cumsum(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+    let (_, size, inner) = shape::narrow(x.shape(), dim, 0, 0)?;
+    if size * inner == 0 {
+        return Ok(());
+    }
+    threads::pool()?.install(|| {
+        x.data_mut().par_chunks_mut(size * inner).for_each(|chunk| {
+            // Each slice of `inner` items adds the previous one.
+            for j in 1..size {
+                let (previous, current) = chunk.split_at_mut(j * inner);
+                current[..inner]
+                    .iter_mut()
+                    .zip(&previous[(j - 1) * inner..])
+                    .for_each(|(v, p)| *v += p);
             }
-            let mut sum = 0.0;
-            for (j, &v) in chunk.iter().enumerate() {
-                if !CAUSAL || i + past_sequence_length >= j {
-                    sum += v;
+        });
+    });
+    Ok(())
+}
+
+/// out = f(items) for every row of items of `x` along `dim`, `out` having the shape of
+/// [shape::reduce].
+fn reduce(
+    x: &Tensor,
+    dim: usize,
+    keepdim: bool,
+    out: &mut Tensor,
+    f: impl Fn(&[f32]) -> f32 + Sync,
+) -> Result<(), SmeltError> {
+    let (_, size, inner, expected) = shape::reduce(x.shape(), dim, keepdim)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if size * inner == 0 {
+        out.data_mut().iter_mut().for_each(|v| *v = f(&[]));
+        return Ok(());
+    }
+    threads::pool()?.install(|| {
+        out.data_mut()
+            .par_chunks_mut(inner)
+            .zip(x.data().par_chunks(size * inner))
+            .for_each(|(out, chunk)| {
+                let mut row = vec![0.0; size];
+                for (j, v) in out.iter_mut().enumerate() {
+                    row.iter_mut()
+                        .enumerate()
+                        .for_each(|(k, r)| *r = chunk[k * inner + j]);
+                    *v = f(&row);
                 }
+            });
+    });
+    Ok(())
+}
+
+/// Sum of `x` along `dim` into `out`, the dimension being removed or kept with a size
+/// of 1 depending on `keepdim`.
+/// ```
+/// use smelte_rs::cpu::f32::{sum, Tensor};
+///
+/// let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+/// let mut out = Tensor::zeros(vec![2, 1]);
+/// sum(&x, 1, true, &mut out).unwrap();
+/// assert_eq!(out.data(), [6.0, 15.0]);
+/// ```
+pub fn sum(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce(x, dim, keepdim, out, |row| row.iter().sum())
+}
+
+/// Mean of `x` along `dim` into `out`, see [sum]. Averaging the token embeddings
+/// (batch_size, sequence_length, hidden_dim) along 1 gives the sentence embeddings.
+pub fn mean(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce(x, dim, keepdim, out, |row| {
+        row.iter().sum::<f32>() / row.len() as f32
+    })
+}
+
+/// Maximum of `x` along `dim` into `out`, see [sum].
+pub fn max(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce(x, dim, keepdim, out, |row| {
+        row.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+    })
+}
+
+/// Population variance of `x` along `dim` into `out` (divided by the size of `dim`,
+/// like the layer norms), see [sum].
+pub fn var(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce(x, dim, keepdim, out, |row| {
+        let mean = row.iter().sum::<f32>() / row.len() as f32;
+        row.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / row.len() as f32
+    })
+}
+
+/// `x` padded with `value` into `out`, `padding` holding the `(before, after)` items
+/// added to each of the last `padding.len()` dimensions. Negative values crop `x`.
+/// ```
+/// use smelte_rs::cpu::f32::{pad, Tensor};
+///
+/// // Two more positions at the end of the sequence, and the first item cropped.
+/// let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+/// let mut out = Tensor::zeros(vec![2, 4]);
+/// pad(&x, &[(-1, 2)], 0.0, &mut out).unwrap();
+/// assert_eq!(out.data(), [2.0, 3.0, 0.0, 0.0, 5.0, 6.0, 0.0, 0.0]);
+/// ```
+pub fn pad(
+    x: &Tensor,
+    padding: &[(isize, isize)],
+    value: f32,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (expected, before) = shape::pad(x.shape(), padding)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    let shape = x.shape();
+    let rank = shape.len();
+    if rank == 0 {
+        out.data_mut().copy_from_slice(x.data());
+        return Ok(());
+    }
+    out.data_mut().iter_mut().for_each(|v| *v = value);
+    // The columns of the rows of `out` that come from `x`.
+    let (first, out_len) = (before[rank - 1], expected[rank - 1]);
+    let start = first.max(0) as usize;
+    let end = (first + shape[rank - 1] as isize).clamp(0, out_len as isize) as usize;
+    if start >= end {
+        return Ok(());
+    }
+    let skip = (start as isize - first) as usize;
+    let strides = shape::strides(shape);
+    let rows: usize = expected[..rank - 1].iter().product();
+    let data = x.data();
+    let out = out.data_mut();
+    'rows: for row in 0..rows {
+        let (mut index, mut offset) = (row, skip);
+        for d in (0..rank - 1).rev() {
+            let i = (index % expected[d]) as isize - before[d];
+            index /= expected[d];
+            if i < 0 || i >= shape[d] as isize {
+                continue 'rows;
             }
-            for (j, v) in chunk.iter_mut().enumerate() {
-                if !CAUSAL || i + past_sequence_length >= j {
-                    *v /= sum;
-                } else {
+            offset += i as usize * strides[d];
+        }
+        out[row * out_len + start..row * out_len + end]
+            .copy_from_slice(&data[offset..offset + end - start]);
+    }
+    Ok(())
+}
+
+/// Zeroes the items (i, j) of the matrices of the last two dimensions of `x` outside of
+/// the triangle, `j - i > diagonal` for the lower one and `j - i < diagonal` for the
+/// upper one.
+fn triangle(x: &mut Tensor, diagonal: isize, upper: bool) -> Result<(), SmeltError> {
+    let rank = x.shape().len();
+    if rank < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let (rows, cols) = (x.shape()[rank - 2], x.shape()[rank - 1]);
+    if rows * cols == 0 {
+        return Ok(());
+    }
+    x.data_mut().chunks_mut(rows * cols).for_each(|matrix| {
+        for (i, row) in matrix.chunks_mut(cols).enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                let offset = j as isize - i as isize;
+                if (upper && offset < diagonal) || (!upper && offset > diagonal) {
                     *v = 0.0;
                 }
             }
-        });
+        }
+    });
     Ok(())
 }
 
-/// Softmax on the last dimension for tensor `x`
-pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
-    g_softmax::<false>(x, 0)
+/// Lower triangle of the matrices of the last two dimensions of `x`, the items (i, j)
+/// with `j - i > diagonal` are zeroed.
+/// ```
+/// use smelte_rs::cpu::f32::{tril, Tensor};
+///
+/// let mut x = Tensor::new(vec![1.0; 9], vec![3, 3]).unwrap();
+/// tril(&mut x, 0).unwrap();
+/// assert_eq!(x.data(), [1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0]);
+/// ```
+pub fn tril(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+    triangle(x, diagonal, false)
 }
 
-/// Causal softmax on the last dimension for tensor `x`. The causality is determined by the
-/// shape of `x` and `past_sequence_length` which defines how big is the missing part of the
-/// square.
-pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
-    g_softmax::<true>(x, past_sequence_length)
+/// Upper triangle of the matrices of the last two dimensions of `x`, the items (i, j)
+/// with `j - i < diagonal` are zeroed.
+pub fn triu(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+    triangle(x, diagonal, true)
+}
+
+/// x = -inf for the keys hidden from the queries of the attention scores `x` (...,
+/// query_length, key_length), see [crate::traits::TensorPositionMask].
+/// ```
+/// use smelte_rs::cpu::f32::{position_mask, Tensor};
+///
+/// // The second and third queries of a causal attention, after a cached key.
+/// let mut x = Tensor::zeros(vec![2, 4]);
+/// position_mask(&mut x, 1, 3, true, None).unwrap();
+/// let inf = f32::NEG_INFINITY;
+/// assert_eq!(x.data(), [0.0, 0.0, inf, inf, 0.0, 0.0, 0.0, inf]);
+/// ```
+pub fn position_mask(
+    x: &mut Tensor,
+    offset: usize,
+    length: usize,
+    causal: bool,
+    sliding_window: Option<usize>,
+) -> Result<(), SmeltError> {
+    let rank = x.shape().len();
+    if rank < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let (rows, cols) = (x.shape()[rank - 2], x.shape()[rank - 1]);
+    if rows * cols == 0 {
+        return Ok(());
+    }
+    let window = sliding_window.unwrap_or(usize::MAX);
+    x.data_mut().chunks_mut(rows * cols).for_each(|matrix| {
+        for (i, row) in matrix.chunks_mut(cols).enumerate() {
+            let i = offset + i;
+            for (j, v) in row.iter_mut().enumerate() {
+                let after = j > i && (causal || j - i >= window);
+                if j >= length || after || (j < i && i - j >= window) {
+                    *v = f32::NEG_INFINITY;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// x = value wherever `mask` is true. The mask has the trailing dimensions of `x`
+/// and is repeated over the leading ones.
+/// ```
+/// use smelte_rs::cpu::f32::{masked_fill, Tensor};
+/// use smelte_rs::cpu::Mask;
+///
+/// let mut x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+/// let mask = Mask::from_bools(&[false, true], vec![2]).unwrap();
+/// masked_fill(&mut x, &mask, 0.0).unwrap();
+/// assert_eq!(x.data(), [1.0, 0.0, 3.0, 0.0]);
+/// ```
+pub fn masked_fill(x: &mut Tensor, mask: &Mask, value: f32) -> Result<(), SmeltError> {
+    shape::check_mask(x.shape(), mask.shape())?;
+    let mask = mask.data();
+    x.data_mut().chunks_mut(mask.len()).for_each(|chunk| {
+        chunk
+            .iter_mut()
+            .zip(mask)
+            .filter(|(_, &m)| m != 0)
+            .for_each(|(v, _)| *v = value)
+    });
+    Ok(())
+}
+
+/// Softmax on the last dimension for tensor `x`, ignoring the positions where `mask` is
+/// true, as if they were filled with `-inf`. The masked positions get a probability of 0,
+/// and so do the rows that are entirely masked. The mask is repeated like in
+/// [masked_fill], a padding mask has the shape of the rows.
+pub fn masked_softmax(x: &mut Tensor, mask: &Mask) -> Result<(), SmeltError> {
+    shape::check_mask(x.shape(), mask.shape())?;
+    let n = x.shape()[x.shape().len() - 1];
+    let mask = mask.data();
+    let rows_per_mask = mask.len() / n;
+    threads::pool()?.install(|| {
+        x.data_mut()
+            .par_chunks_mut(n)
+            .enumerate()
+            .for_each(|(i, row)| {
+                let start = (i % rows_per_mask) * n;
+                let mask = &mask[start..start + n];
+                if mask.iter().all(|&m| m != 0) {
+                    row.iter_mut().for_each(|v| *v = 0.0);
+                    return;
+                }
+                row.iter_mut()
+                    .zip(mask)
+                    .filter(|(_, &m)| m != 0)
+                    .for_each(|(v, _)| *v = f32::NEG_INFINITY);
+                simd::softmax(row);
+                // The exponential of the simd kernels is clamped, not exactly 0.
+                row.iter_mut()
+                    .zip(mask)
+                    .filter(|(_, &m)| m != 0)
+                    .for_each(|(v, _)| *v = 0.0);
+            });
+    });
+    Ok(())
+}
+
+/// Walks the items of `shape` in order, calling `f` with the index of the item and its
+/// offsets in the inputs read with `strides`, see [shape::broadcast_strides].
+fn broadcast_walk<const N: usize>(
+    shape: &[usize],
+    strides: [Vec<usize>; N],
+    mut f: impl FnMut(usize, [usize; N]),
+) {
+    let numel: usize = shape.iter().product();
+    let mut index = vec![0; shape.len()];
+    let mut offsets = [0; N];
+    for i in 0..numel {
+        f(i, offsets);
+        for d in (0..shape.len()).rev() {
+            index[d] += 1;
+            offsets
+                .iter_mut()
+                .zip(&strides)
+                .for_each(|(o, s)| *o += s[d]);
+            if index[d] < shape[d] {
+                break;
+            }
+            offsets
+                .iter_mut()
+                .zip(&strides)
+                .for_each(|(o, s)| *o -= s[d] * shape[d]);
+            index[d] = 0;
+        }
+    }
+}
+
+/// out = f(a, b), `a` and `b` being broadcasted to the shape of `out` like in NumPy.
+fn compare(
+    a: &Tensor,
+    b: &Tensor,
+    out: &mut Mask,
+    f: impl Fn(f32, f32) -> bool,
+) -> Result<(), SmeltError> {
+    let strides = [
+        shape::broadcast_strides(a.shape(), out.shape())?,
+        shape::broadcast_strides(b.shape(), out.shape())?,
+    ];
+    let shape = out.shape().to_vec();
+    let (a, b, out) = (a.data(), b.data(), out.data_mut());
+    broadcast_walk(&shape, strides, |i, [x, y]| out[i] = f(a[x], b[y]) as u8);
+    Ok(())
+}
+
+/// out = a > b, `a` and `b` being broadcasted to the shape of `out` like in NumPy.
+/// ```
+/// use smelte_rs::cpu::f32::{gt, Tensor};
+/// use smelte_rs::cpu::Mask;
+///
+/// let probs = Tensor::new(vec![0.1, 0.6, 0.3], vec![3]).unwrap();
+/// let threshold = Tensor::new(vec![0.2], vec![1]).unwrap();
+/// let mut mask = Mask::zeros(vec![3]);
+/// gt(&probs, &threshold, &mut mask).unwrap();
+/// assert_eq!(mask.data(), [0, 1, 1]);
+/// ```
+pub fn gt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare(a, b, out, |x, y| x > y)
+}
+
+/// out = a < b, see [gt].
+pub fn lt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare(a, b, out, |x, y| x < y)
+}
+
+/// out = a == b, see [gt].
+pub fn eq(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare(a, b, out, |x, y| x == y)
+}
+
+/// out = a wherever `mask` is true, b elsewhere. The mask, `a` and `b` are broadcasted
+/// to the shape of `out` like in NumPy.
+/// ```
+/// use smelte_rs::cpu::f32::{where_cond, Tensor};
+/// use smelte_rs::cpu::Mask;
+///
+/// let logits = Tensor::new(vec![1.0, 2.0, 3.0], vec![3]).unwrap();
+/// let filtered = Tensor::new(vec![f32::NEG_INFINITY], vec![1]).unwrap();
+/// let mask = Mask::from_bools(&[true, false, true], vec![3]).unwrap();
+/// let mut out = Tensor::zeros(vec![3]);
+/// where_cond(&mask, &filtered, &logits, &mut out).unwrap();
+/// assert_eq!(out.data(), [f32::NEG_INFINITY, 2.0, f32::NEG_INFINITY]);
+/// ```
+pub fn where_cond(mask: &Mask, a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let strides = [
+        shape::broadcast_strides(mask.shape(), out.shape())?,
+        shape::broadcast_strides(a.shape(), out.shape())?,
+        shape::broadcast_strides(b.shape(), out.shape())?,
+    ];
+    let shape = out.shape().to_vec();
+    let (mask, a, b, out) = (mask.data(), a.data(), b.data(), out.data_mut());
+    broadcast_walk(&shape, strides, |i, [m, x, y]| {
+        out[i] = if mask[m] != 0 { a[x] } else { b[y] }
+    });
+    Ok(())
 }
 
 /// Argmax of the last dimension of tensor `x `.
@@ -428,6 +1246,114 @@ pub fn gelu(v: f32) -> f32 {
         * (1.0 + inline_tanh((2.0f32 / std::f32::consts::PI).sqrt() * v * (1.0 + 0.044715 * v * v)))
 }
 
+/// [gelu] of every item of the tensor, vectorized when the cpu allows it.
+pub fn apply_gelu(x: &mut Tensor) {
+    simd::gelu(x.data_mut());
+}
+
+/// `activation` of every item of the tensor, see [Activation].
+pub fn apply_activation(x: &mut Tensor, activation: Activation) {
+    match activation {
+        Activation::Gelu => apply(x, |v| {
+            0.5 * v * (1.0 + erf(v * std::f32::consts::FRAC_1_SQRT_2))
+        }),
+        Activation::GeluNew => apply_gelu(x),
+        Activation::GeluFast => apply(x, faster_gelu),
+        Activation::Relu => apply(x, |v| v.max(0.0)),
+        Activation::Silu => apply(x, |v| v / (1.0 + (-v).exp())),
+        Activation::QuickGelu => apply(x, |v| v / (1.0 + (-1.702 * v).exp())),
+    }
+}
+
+/// [inline_tanh] of every item of the tensor, vectorized when the cpu allows it.
+pub fn apply_tanh(x: &mut Tensor) {
+    simd::tanh(x.data_mut());
+}
+
+/// The error function, with the approximation 7.1.26 of Abramowitz and Stegun
+/// (an absolute error below 1.5e-7).
+#[inline]
+pub fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly =
+        t * (0.2548296 + t * (-0.28449672 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+/// x = exp(x)
+pub fn apply_exp(x: &mut Tensor) {
+    apply(x, f32::exp);
+}
+
+/// x = ln(x), the natural logarithm
+pub fn apply_log(x: &mut Tensor) {
+    apply(x, f32::ln);
+}
+
+/// x = sqrt(x)
+pub fn apply_sqrt(x: &mut Tensor) {
+    apply(x, f32::sqrt);
+}
+
+/// x = 1 / sqrt(x)
+pub fn apply_rsqrt(x: &mut Tensor) {
+    apply(x, |v| 1.0 / v.sqrt());
+}
+
+/// x = 1 / (1 + exp(-x))
+/// ```
+/// use smelte_rs::cpu::f32::{apply_sigmoid, Tensor};
+///
+/// let mut x = Tensor::new(vec![0.0, f32::INFINITY, f32::NEG_INFINITY], vec![3]).unwrap();
+/// apply_sigmoid(&mut x);
+/// assert_eq!(x.data(), [0.5, 1.0, 0.0]);
+/// ```
+pub fn apply_sigmoid(x: &mut Tensor) {
+    apply(x, |v| 1.0 / (1.0 + (-v).exp()));
+}
+
+/// [erf] of every item of the tensor
+pub fn apply_erf(x: &mut Tensor) {
+    apply(x, erf);
+}
+
+/// x = -x
+pub fn apply_neg(x: &mut Tensor) {
+    apply(x, |v| -v);
+}
+
+/// x = |x|
+pub fn apply_abs(x: &mut Tensor) {
+    apply(x, f32::abs);
+}
+
+/// x = min(max(x, min), max), every item becomes `max` when `min > max` like in torch.
+/// ```
+/// use smelte_rs::cpu::f32::{clamp, Tensor};
+///
+/// let mut logits = Tensor::new(vec![-50.0, 0.5, 50.0], vec![3]).unwrap();
+/// clamp(&mut logits, -30.0, 30.0);
+/// assert_eq!(logits.data(), [-30.0, 0.5, 30.0]);
+/// ```
+pub fn clamp(x: &mut Tensor, min: f32, max: f32) {
+    apply(x, |v| v.max(min).min(max));
+}
+
+/// x = max(x, min)
+pub fn clamp_min(x: &mut Tensor, min: f32) {
+    clamp(x, min, f32::INFINITY);
+}
+
+/// x = min(x, max)
+pub fn clamp_max(x: &mut Tensor, max: f32) {
+    clamp(x, f32::NEG_INFINITY, max);
+}
+
+/// x = x * factor
+pub fn mul_scalar(x: &mut Tensor, factor: f32) {
+    apply(x, |v| v * factor);
+}
+
 /// Applies `func` to every item of the tensor
 pub fn apply<F: Fn(f32) -> f32 + Sync>(x: &mut Tensor, func: F) {
     x.data_mut().iter_mut().for_each(|v| *v = func(*v));
@@ -438,6 +1364,267 @@ mod tests {
     use super::*;
     use crate::tests::simplify;
 
+    #[test]
+    fn softmax_any_dim() {
+        let data = vec![-1.0, 3.0, 0.5, 2.0, 1.0, 1.0];
+        let mut a = Tensor::new(data.clone(), vec![3, 2]).unwrap();
+        softmax(&mut a).unwrap();
+        let mut b = Tensor::new(data.clone(), vec![3, 2]).unwrap();
+        softmax_dim(&mut b, 1).unwrap();
+        assert_eq!(simplify(a.data()), simplify(b.data()));
+
+        // Along the columns, the same as the rows of the transposed tensor.
+        let mut a = Tensor::new(data.clone(), vec![3, 2]).unwrap();
+        softmax_dim(&mut a, 0).unwrap();
+        let mut b = Tensor::new(data, vec![3, 2])
+            .unwrap()
+            .transpose(0, 1)
+            .unwrap();
+        softmax(&mut b).unwrap();
+        let b = b.transpose(0, 1).unwrap();
+        assert_eq!(simplify(a.data()), simplify(b.data()));
+
+        // A high temperature flattens the probabilities.
+        let mut a = Tensor::new(vec![0.0, 100.0], vec![2]).unwrap();
+        softmax_t(&mut a, 0, 1e6).unwrap();
+        assert_eq!(simplify(a.data()), [0.5, 0.5]);
+        assert!(matches!(
+            softmax_t(&mut a, 0, 0.0),
+            Err(SmeltError::InvalidTemperature(_))
+        ));
+        assert!(softmax_dim(&mut a, 1).is_err());
+    }
+
+    #[test]
+    fn rotary_positions() {
+        // Half turns at the position 1, applied to the positions 1 and 2.
+        let cos = Tensor::new(vec![1.0, 1.0, -1.0, -1.0, 1.0, 1.0], vec![3, 2]).unwrap();
+        let sin = Tensor::zeros(vec![3, 2]);
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let mut x = Tensor::new(data, vec![1, 2, 4]).unwrap();
+        rotary(&mut x, &cos, &sin, 1).unwrap();
+        assert_eq!(x.data(), [-0.0, -1.0, -2.0, -3.0, 4.0, 5.0, 6.0, 7.0]);
+        assert!(matches!(
+            rotary(&mut x, &cos, &sin, 2),
+            Err(SmeltError::OutOfRange { .. })
+        ));
+        let mut odd = Tensor::zeros(vec![1, 3]);
+        assert!(rotary(&mut odd, &cos, &sin, 0).is_err());
+    }
+
+    #[test]
+    fn top_k_rows() {
+        let inf = f32::NEG_INFINITY;
+        let data = vec![0.0, 0.0, 0.0, 0.0, 5.0, 1.0, 0.0, 5.0];
+        let mut x = Tensor::new(data.clone(), vec![2, 4]).unwrap();
+        top_k(&mut x, 2).unwrap();
+        // The first of the equal logits are kept.
+        assert_eq!(x.data(), [0.0, 0.0, inf, inf, 5.0, inf, inf, 5.0]);
+        let mut x = Tensor::new(data.clone(), vec![2, 4]).unwrap();
+        top_k(&mut x, 4).unwrap();
+        assert_eq!(x.data(), data);
+        assert!(top_k(&mut x, 0).is_err());
+    }
+
+    #[test]
+    fn top_p_rows() {
+        let inf = f32::NEG_INFINITY;
+        let data = vec![0.0, 0.0, 0.0, 0.0, 5.0, 1.0, 0.0, 5.0];
+        let mut x = Tensor::new(data.clone(), vec![2, 4]).unwrap();
+        top_p(&mut x, 0.5).unwrap();
+        // The first of the equal logits are kept.
+        assert_eq!(x.data(), [0.0, 0.0, inf, inf, 5.0, inf, inf, 5.0]);
+        let mut x = Tensor::new(data.clone(), vec![2, 4]).unwrap();
+        top_p(&mut x, 0.1).unwrap();
+        assert_eq!(x.data(), [0.0, inf, inf, inf, 5.0, inf, inf, inf]);
+        let mut x = Tensor::new(data.clone(), vec![2, 4]).unwrap();
+        top_p(&mut x, 1.0).unwrap();
+        assert_eq!(x.data(), data);
+        assert!(matches!(
+            top_p(&mut x, 0.0),
+            Err(SmeltError::InvalidProbability(_))
+        ));
+    }
+
+    #[test]
+    fn cumsum_any_dim() {
+        let mut a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+        cumsum(&mut a, 0).unwrap();
+        assert_eq!(a.data(), [1.0, 2.0, 3.0, 5.0, 7.0, 9.0]);
+        cumsum(&mut a, 1).unwrap();
+        assert_eq!(a.data(), [1.0, 3.0, 6.0, 5.0, 12.0, 21.0]);
+        assert!(cumsum(&mut a, 2).is_err());
+    }
+
+    #[test]
+    fn unary_ops() {
+        let data = vec![-2.0, -0.5, 0.0, 1.0];
+        let mut x = Tensor::new(data.clone(), vec![2, 2]).unwrap();
+        apply_abs(&mut x);
+        assert_eq!(x.data(), [2.0, 0.5, 0.0, 1.0]);
+        apply_sqrt(&mut x);
+        apply_log(&mut x);
+        apply_exp(&mut x);
+        apply_rsqrt(&mut x);
+        assert_eq!(simplify(x.data()), [0.8409, 1.1892, f32::INFINITY, 1.0]);
+
+        let mut x = Tensor::new(data.clone(), vec![2, 2]).unwrap();
+        apply_neg(&mut x);
+        assert_eq!(x.data(), [2.0, 0.5, -0.0, -1.0]);
+        apply_sigmoid(&mut x);
+        assert_eq!(simplify(x.data()), [0.8808, 0.6225, 0.5, 0.2689]);
+
+        // Values obtained through python
+        let mut x = Tensor::new(data.clone(), vec![2, 2]).unwrap();
+        apply_erf(&mut x);
+        assert_eq!(simplify(x.data()), [-0.9953, -0.5205, 0.0, 0.8427]);
+
+        let activation = |activation| {
+            let mut x = Tensor::new(data.clone(), vec![2, 2]).unwrap();
+            apply_activation(&mut x, activation);
+            simplify(x.data())
+        };
+        assert_eq!(
+            activation(Activation::Gelu),
+            [-0.0455, -0.1543, 0.0, 0.8413]
+        );
+        assert_eq!(
+            activation(Activation::GeluNew),
+            [-0.0454, -0.1543, 0.0, 0.8412]
+        );
+        // The faster tanh is less precise.
+        assert_eq!(
+            activation(Activation::GeluFast),
+            [-0.0452, -0.1543, 0.0, 0.8411]
+        );
+        assert_eq!(activation(Activation::Relu), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(
+            activation(Activation::Silu),
+            [-0.2384, -0.1888, 0.0, 0.7311]
+        );
+        assert_eq!(
+            activation(Activation::QuickGelu),
+            [-0.0643, -0.1496, 0.0, 0.8458]
+        );
+    }
+
+    #[test]
+    fn triangles() {
+        let mut x = Tensor::new(vec![1.0; 12], vec![2, 2, 3]).unwrap();
+        triu(&mut x, 1).unwrap();
+        assert_eq!(
+            x.data(),
+            [0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0]
+        );
+        tril(&mut x, 1).unwrap();
+        assert_eq!(x.data()[..6], [0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert!(tril(&mut Tensor::zeros(vec![3]), 0).is_err());
+
+        // The causal mask is the strict upper triangle.
+        let mut x = Tensor::new(vec![1.0; 9], vec![3, 3]).unwrap();
+        triu(&mut x, 1).unwrap();
+        let mask: Vec<u8> = x.data().iter().map(|&v| v as u8).collect();
+        assert_eq!(Mask::causal(3).data(), mask);
+    }
+
+    #[test]
+    fn padding() {
+        let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        let mut out = Tensor::zeros(vec![3, 3]);
+        pad(&x, &[(1, 0), (0, 1)], -1.0, &mut out).unwrap();
+        assert_eq!(
+            out.data(),
+            [-1.0, -1.0, -1.0, 1.0, 2.0, -1.0, 3.0, 4.0, -1.0]
+        );
+        // Cropping back to `x`.
+        let mut back = Tensor::zeros(vec![2, 2]);
+        pad(&out, &[(-1, 0), (0, -1)], 0.0, &mut back).unwrap();
+        assert_eq!(back.data(), x.data());
+
+        let mut out = Tensor::zeros(vec![1, 2]);
+        pad(&x, &[(-1, 0)], 0.0, &mut out).unwrap_err();
+        pad(&x, &[(-1, 0), (0, 0)], 0.0, &mut out).unwrap();
+        assert_eq!(out.data(), [3.0, 4.0]);
+    }
+
+    #[test]
+    fn reductions() {
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let x = Tensor::new(data, vec![2, 3, 2]).unwrap();
+        let mut out = Tensor::zeros(vec![2, 2]);
+        sum(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.data(), [6.0, 9.0, 24.0, 27.0]);
+        mean(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.data(), [2.0, 3.0, 8.0, 9.0]);
+        max(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.data(), [4.0, 5.0, 10.0, 11.0]);
+        var(&x, 1, false, &mut out).unwrap();
+        assert_eq!(simplify(out.data()), [2.6667; 4]);
+
+        let mut out = Tensor::zeros(vec![1, 3, 2]);
+        sum(&x, 0, true, &mut out).unwrap();
+        assert_eq!(out.data(), [6.0, 8.0, 10.0, 12.0, 14.0, 16.0]);
+        assert!(sum(&x, 0, false, &mut out).is_err());
+        assert!(sum(&x, 3, true, &mut out).is_err());
+    }
+
+    #[test]
+    fn clamps() {
+        let mut x = Tensor::new(vec![-2.0, -0.5, 0.5, 2.0], vec![2, 2]).unwrap();
+        clamp_min(&mut x, -1.0);
+        assert_eq!(x.data(), [-1.0, -0.5, 0.5, 2.0]);
+        clamp_max(&mut x, 1.0);
+        assert_eq!(x.data(), [-1.0, -0.5, 0.5, 1.0]);
+        clamp(&mut x, 0.0, 0.5);
+        assert_eq!(x.data(), [0.0, 0.0, 0.5, 0.5]);
+        clamp(&mut x, 1.0, 0.0);
+        assert_eq!(x.data(), [0.0; 4]);
+    }
+
+    #[test]
+    fn compare_where() {
+        let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+        let b = Tensor::new(vec![2.0, 5.0], vec![2, 1]).unwrap();
+        let mut mask = Mask::zeros(vec![2, 3]);
+        gt(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.data(), [0, 0, 1, 0, 0, 1]);
+        lt(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.data(), [1, 0, 0, 1, 0, 0]);
+        eq(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.data(), [0, 1, 0, 0, 1, 0]);
+
+        let mut out = Tensor::zeros(vec![2, 3]);
+        where_cond(&mask, &b, &a, &mut out).unwrap();
+        assert_eq!(out.data(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let zero = Tensor::zeros(vec![1]);
+        where_cond(&mask, &zero, &a, &mut out).unwrap();
+        assert_eq!(out.data(), [1.0, 0.0, 3.0, 4.0, 0.0, 6.0]);
+
+        let mut mask = Mask::zeros(vec![3]);
+        assert!(gt(&a, &b, &mut mask).is_err());
+        assert!(where_cond(&mask, &a, &b, &mut Tensor::zeros(vec![3, 2])).is_err());
+    }
+
+    #[test]
+    fn simple_masked_softmax() {
+        let mut x = Tensor::new(vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0], vec![2, 3]).unwrap();
+        let mask = Mask::from_bools(&[false, false, true, true, true, true], vec![2, 3]).unwrap();
+        masked_softmax(&mut x, &mask).unwrap();
+        assert_eq!(simplify(x.data()), [0.2689, 0.7311, 0.0, 0.0, 0.0, 0.0]);
+
+        let mut x = Tensor::zeros(vec![2, 3]);
+        let mask = Mask::from_bools(&[false, true], vec![2]).unwrap();
+        assert!(masked_softmax(&mut x, &mask).is_err());
+        assert!(masked_fill(&mut x, &mask, 0.0).is_err());
+    }
+
+    #[test]
+    fn simple_mul_scalar() {
+        let mut a = Tensor::new(vec![1.0, -2.0, 3.0], vec![3]).unwrap();
+        mul_scalar(&mut a, 0.5);
+        assert_eq!(a.data(), [0.5, -1.0, 1.5]);
+    }
+
     #[test]
     fn simple_broadcast_add() {
         let a = Tensor::new(vec![1.0, 2.0], vec![2]).unwrap();
@@ -446,6 +1633,32 @@ mod tests {
         assert_eq!(b.data(), [2.0, 3.0, 2.0, 3.0, 2.0, 3.0]);
     }
 
+    #[test]
+    fn numpy_broadcasting() {
+        // (2, 1, 2) over (2, 2, 2)
+        let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 1, 2]).unwrap();
+        let mut b = Tensor::new(vec![10.0; 8], vec![2, 2, 2]).unwrap();
+        broadcast_sub(&a, &mut b).unwrap();
+        assert_eq!(b.data(), [9.0, 8.0, 9.0, 8.0, 7.0, 6.0, 7.0, 6.0]);
+
+        // A scalar, and a column.
+        let a = Tensor::new(vec![2.0], vec![1]).unwrap();
+        broadcast_mul(&a, &mut b).unwrap();
+        let a = Tensor::new(vec![1.0, -1.0], vec![2, 1]).unwrap();
+        let mut c = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        broadcast_mul(&a, &mut c).unwrap();
+        assert_eq!(c.data(), [1.0, 2.0, -3.0, -4.0]);
+
+        let a = Tensor::zeros(vec![3]);
+        match broadcast_add(&a, &mut c) {
+            Err(SmeltError::DimensionMismatch { expected, got }) => {
+                assert_eq!(expected, [2, 2]);
+                assert_eq!(got, [3]);
+            }
+            _ => panic!("The shapes do not broadcast"),
+        }
+    }
+
     #[test]
     fn simple_matmul() {
         let data = vec![1.0, 2.0, 3.0, 4.0];
@@ -519,6 +1732,20 @@ mod tests {
         assert_eq!(c.data(), &[11., 20., 38., 74., 191., 254., 272., 362.]);
     }
 
+    #[test]
+    fn simple_gemm_provider() {
+        #[cfg(not(any(
+            feature = "rblas",
+            feature = "cblas",
+            feature = "intel-mkl",
+            feature = "openblas",
+            feature = "accelerate"
+        )))]
+        assert!(matches!(gemm_provider(), GemmProvider::Rust(_)));
+        #[cfg(feature = "intel-mkl")]
+        assert_eq!(gemm_provider(), GemmProvider::Mkl);
+    }
+
     #[test]
     fn simple_softmax() {
         let mut a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
@@ -0,0 +1,450 @@
+use crate::cpu::f32::ops::broadcast_add;
+use crate::cpu::f32::simd::{self, Int8Isa};
+use crate::cpu::f32::tensor::Tensor;
+use crate::cpu::threads;
+use crate::traits::{QuantizedWeight, RangeObserver};
+use crate::SmeltError;
+use rayon::prelude::*;
+use std::sync::Mutex;
+
+/// The int8 weight of a matmul, with one scale per row (output feature).
+/// The rows are quantized symmetrically, `weight[i][j] ~= data[i][j] * scales[i]`.
+#[derive(Clone)]
+pub struct Int8Tensor {
+    shape: Vec<usize>,
+    data: Vec<i8>,
+    scales: Vec<f32>,
+}
+
+/// Quantizes `row` into `out`, returns the scale.
+#[inline]
+fn quantize_row(row: &[f32], out: &mut [i8]) -> f32 {
+    let max = abs_max(row);
+    if max == 0.0 {
+        out.iter_mut().for_each(|q| *q = 0);
+        return 0.0;
+    }
+    let scale = max / 127.0;
+    row.iter()
+        .zip(out.iter_mut())
+        .for_each(|(v, q)| *q = (v / scale).round() as i8);
+    scale
+}
+
+/// The instruction set running the int8 products of [matmul_t_int8] and [int8_linear].
+/// ```
+/// use smelte_rs::cpu::f32::int8_isa;
+///
+/// println!("Int8 matmuls run on {:?}", int8_isa());
+/// ```
+pub fn int8_isa() -> Int8Isa {
+    simd::int8_isa()
+}
+
+impl Int8Tensor {
+    /// Quantizes the 2d `weight`.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Int8Tensor, Tensor};
+    ///
+    /// let weight = Tensor::new(vec![1.0, -0.5, 0.0, 2.0], vec![2, 2]).unwrap();
+    /// let weight = Int8Tensor::quantize(&weight).unwrap();
+    /// assert_eq!(weight.data(), [127, -64, 0, 127]);
+    /// ```
+    pub fn quantize(weight: &Tensor) -> Result<Self, SmeltError> {
+        if weight.shape().len() != 2 {
+            return Err(SmeltError::InvalidRank { expected_rank: 2 });
+        }
+        let k = weight.shape()[1];
+        let mut data = vec![0; weight.data().len()];
+        let scales = weight
+            .data()
+            .chunks(k)
+            .zip(data.chunks_mut(k))
+            .map(|(row, out)| quantize_row(row, out))
+            .collect();
+        Ok(Self {
+            shape: weight.shape().to_vec(),
+            data,
+            scales,
+        })
+    }
+
+    /// An already quantized weight, like the int8 checkpoints, with one scale per row.
+    /// ```
+    /// use smelte_rs::cpu::f32::Int8Tensor;
+    ///
+    /// let weight = Int8Tensor::new(vec![127, -64, 1, 2], vec![0.5, 0.01], vec![2, 2]).unwrap();
+    /// assert_eq!(weight.dequantize().data(), [63.5, -32.0, 0.01, 0.02]);
+    /// ```
+    pub fn new(data: Vec<i8>, scales: Vec<f32>, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        if shape.len() != 2 {
+            return Err(SmeltError::InvalidRank { expected_rank: 2 });
+        }
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        if scales.len() != shape[0] {
+            return Err(SmeltError::InvalidLength {
+                expected: shape[0],
+                got: scales.len(),
+            });
+        }
+        Ok(Self {
+            shape,
+            data,
+            scales,
+        })
+    }
+
+    /// The shape of the tensor
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The quantized values
+    pub fn data(&self) -> &[i8] {
+        &self.data
+    }
+
+    /// The scale of every row
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    /// The f32 approximation of the original weight.
+    pub fn dequantize(&self) -> Tensor {
+        let k = self.shape[1];
+        let data = self
+            .data
+            .chunks(k)
+            .zip(&self.scales)
+            .flat_map(|(row, scale)| row.iter().map(move |&q| q as f32 * scale))
+            .collect::<Vec<_>>();
+        Tensor::new(data, self.shape.clone()).expect("The buffer matches the shape")
+    }
+}
+
+/// Matrix multiplication matmul(A, B.transposed()) with int8 weights `b`. Every row of
+/// `a` is quantized on the fly, the products are accumulated in i32.
+pub fn matmul_t_int8(a: &Tensor, b: &Int8Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let n = b.shape()[0];
+    let k = b.shape()[1];
+    if a.shape()[dim - 1] != k {
+        let mut expected = a.shape().to_vec();
+        expected[dim - 1] = k;
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: a.shape().to_vec(),
+        });
+    }
+    let mut expected = a.shape().to_vec();
+    expected[dim - 1] = n;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if k == 0 || n == 0 {
+        out.data_mut().iter_mut().for_each(|v| *v = 0.0);
+        return Ok(());
+    }
+
+    let a = a.data();
+    threads::pool()?.install(|| {
+        out.data_mut()
+            .par_chunks_mut(n)
+            .zip(a.par_chunks(k))
+            .for_each_init(
+                || vec![0; k],
+                |qa, (out_row, row)| {
+                    let scale = quantize_row(row, qa);
+                    out_row
+                        .iter_mut()
+                        .zip(b.data.chunks(k).zip(&b.scales))
+                        .for_each(|(v, (qb, b_scale))| {
+                            *v = simd::dot_i8(qa, qb) as f32 * scale * b_scale;
+                        });
+                },
+            );
+    });
+    Ok(())
+}
+
+impl QuantizedWeight<Tensor> for Int8Tensor {
+    fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    fn addmm(&self, x: &Tensor, bias: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        matmul_t_int8(x, self, out)?;
+        broadcast_add(bias, out)
+    }
+}
+
+/// The scale mapping `[-range, range]` to `[-127, 127]`.
+#[inline]
+fn range_scale(range: f32) -> f32 {
+    if range > 0.0 {
+        range / 127.0
+    } else {
+        1.0
+    }
+}
+
+#[inline]
+fn saturate(v: f32) -> i8 {
+    v.round().clamp(-127.0, 127.0) as i8
+}
+
+/// A [Linear](crate::nn::layers::Linear) quantized statically: the int8 weight, the
+/// bias in i32 and the fixed scales of the inputs and of the outputs.
+#[derive(Clone)]
+pub struct Int8Linear {
+    weight: Int8Tensor,
+    bias: Vec<i32>,
+    input_scale: f32,
+    output_scale: f32,
+}
+
+impl Int8Linear {
+    /// Quantizes `weight` and `bias` for inputs within `[-input_range, input_range]` and
+    /// outputs within `[-output_range, output_range]`.
+    pub fn quantize(
+        weight: &Tensor,
+        bias: &Tensor,
+        input_range: f32,
+        output_range: f32,
+    ) -> Result<Self, SmeltError> {
+        Self::new(
+            Int8Tensor::quantize(weight)?,
+            bias,
+            input_range,
+            output_range,
+        )
+    }
+
+    /// Same as [Int8Linear::quantize] for an already quantized `weight`, the bias is
+    /// quantized with the scale of every output feature.
+    pub fn new(
+        weight: Int8Tensor,
+        bias: &Tensor,
+        input_range: f32,
+        output_range: f32,
+    ) -> Result<Self, SmeltError> {
+        if bias.shape() != [weight.shape[0]] {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![weight.shape[0]],
+                got: bias.shape().to_vec(),
+            });
+        }
+        let input_scale = range_scale(input_range);
+        let bias = bias
+            .data()
+            .iter()
+            .zip(&weight.scales)
+            .map(|(b, scale)| match scale * input_scale {
+                0.0 => 0,
+                scale => (b / scale).round() as i32,
+            })
+            .collect();
+        Ok(Self {
+            weight,
+            bias,
+            input_scale,
+            output_scale: range_scale(output_range),
+        })
+    }
+
+    /// The int8 weight
+    pub fn weight(&self) -> &Int8Tensor {
+        &self.weight
+    }
+
+    /// The bias, in units of `input_scale * weight.scales()[i]`
+    pub fn bias(&self) -> &[i32] {
+        &self.bias
+    }
+
+    /// The scale of the int8 inputs
+    pub fn input_scale(&self) -> f32 {
+        self.input_scale
+    }
+
+    /// The scale of the int8 outputs
+    pub fn output_scale(&self) -> f32 {
+        self.output_scale
+    }
+}
+
+/// out = matmul(a, W.T) + bias with the static quantization of `linear`. The inputs are
+/// rounded to int8 with `input_scale`, saturating, and the i32 accumulators are
+/// requantized to int8 with `output_scale`. The int8 outputs are stored dequantized, the
+/// following ops run in f32.
+pub fn int8_linear(a: &Tensor, linear: &Int8Linear, out: &mut Tensor) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let weight = &linear.weight;
+    let n = weight.shape[0];
+    let k = weight.shape[1];
+    let mut expected = a.shape().to_vec();
+    expected[dim - 1] = k;
+    if a.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: a.shape().to_vec(),
+        });
+    }
+    expected[dim - 1] = n;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if k == 0 || n == 0 {
+        out.data_mut().iter_mut().for_each(|v| *v = 0.0);
+        return Ok(());
+    }
+
+    let (input_scale, output_scale) = (linear.input_scale, linear.output_scale);
+    // The requantization multiplier of every output feature.
+    let multipliers: Vec<f32> = weight
+        .scales
+        .iter()
+        .map(|scale| input_scale * scale / output_scale)
+        .collect();
+    let a = a.data();
+    threads::pool()?.install(|| {
+        out.data_mut()
+            .par_chunks_mut(n)
+            .zip(a.par_chunks(k))
+            .for_each_init(
+                || vec![0; k],
+                |qa, (out_row, row)| {
+                    row.iter()
+                        .zip(qa.iter_mut())
+                        .for_each(|(v, q)| *q = saturate(v / input_scale));
+                    out_row
+                        .iter_mut()
+                        .zip(weight.data.chunks(k))
+                        .zip(linear.bias.iter().zip(&multipliers))
+                        .for_each(|((v, qb), (bias, multiplier))| {
+                            let acc = simd::dot_i8(qa, qb) + bias;
+                            *v = saturate(acc as f32 * multiplier) as f32 * output_scale;
+                        });
+                },
+            );
+    });
+    Ok(())
+}
+
+impl QuantizedWeight<Tensor> for Int8Linear {
+    fn shape(&self) -> &[usize] {
+        &self.weight.shape
+    }
+
+    fn addmm(&self, x: &Tensor, _bias: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        int8_linear(x, self, out)
+    }
+}
+
+/// Records the largest absolute values of the inputs and outputs.
+#[derive(Default)]
+pub(crate) struct AbsMaxObserver {
+    ranges: Mutex<(f32, f32)>,
+}
+
+fn abs_max(data: &[f32]) -> f32 {
+    data.iter().fold(0.0f32, |max, v| max.max(v.abs()))
+}
+
+impl RangeObserver<Tensor> for AbsMaxObserver {
+    fn observe(&self, input: &Tensor, output: &Tensor) -> Result<(), SmeltError> {
+        let (input, output) = (abs_max(input.data()), abs_max(output.data()));
+        let mut ranges = self.ranges.lock().unwrap();
+        ranges.0 = ranges.0.max(input);
+        ranges.1 = ranges.1.max(output);
+        Ok(())
+    }
+
+    fn ranges(&self) -> (f32, f32) {
+        *self.ranges.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::matmul_t;
+    use crate::tests::simplify;
+
+    #[test]
+    fn simple_quantize() {
+        let weight = Tensor::new(vec![1.0, -0.5, 0.0, 0.0], vec![2, 2]).unwrap();
+        let weight = Int8Tensor::quantize(&weight).unwrap();
+        assert_eq!(weight.data(), [127, -64, 0, 0]);
+        assert_eq!(weight.scales(), [1.0 / 127.0, 0.0]);
+        assert_eq!(weight.dequantize().data(), [1.0, -64.0 / 127.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn per_channel_scales() {
+        // A single scale for both rows would round the second one to zero.
+        let weight = Tensor::new(vec![100.0, -50.0, 0.01, 0.04], vec![2, 2]).unwrap();
+        let weight = Int8Tensor::quantize(&weight).unwrap();
+        assert_eq!(weight.data(), [127, -64, 32, 127]);
+        assert_eq!(
+            simplify(weight.dequantize().data()),
+            [100.0, -50.3937, 0.0101, 0.04]
+        );
+
+        assert!(Int8Tensor::new(vec![1, 2, 3, 4], vec![1.0], vec![2, 2]).is_err());
+        assert!(Int8Tensor::new(vec![1, 2, 3], vec![1.0, 1.0], vec![2, 2]).is_err());
+    }
+
+    #[test]
+    fn simple_matmul_t_int8() {
+        let data: Vec<_> = (0..12).map(|i| i as f32 - 5.0).collect();
+        let a = Tensor::new(data, vec![3, 4]).unwrap();
+        let data: Vec<_> = (0..8).map(|i| (i as f32 * 0.7).sin()).collect();
+        let b = Tensor::new(data, vec![2, 4]).unwrap();
+
+        let mut expected = Tensor::zeros(vec![3, 2]);
+        matmul_t(&a, &b, &mut expected).unwrap();
+        let mut out = Tensor::zeros(vec![3, 2]);
+        matmul_t_int8(&a, &Int8Tensor::quantize(&b).unwrap(), &mut out).unwrap();
+        for (v, e) in out.data().iter().zip(expected.data()) {
+            assert!((v - e).abs() < 0.05, "{v} != {e}");
+        }
+
+        let mut out = Tensor::zeros(vec![3, 3]);
+        assert!(matmul_t_int8(&a, &Int8Tensor::quantize(&b).unwrap(), &mut out).is_err());
+    }
+
+    #[test]
+    fn simple_int8_linear() {
+        let a = Tensor::new(vec![0.5, -1.0, 0.25, 1.0], vec![2, 2]).unwrap();
+        let b = Tensor::new(vec![1.0, 0.5, -0.5, 1.0], vec![2, 2]).unwrap();
+        let bias = Tensor::new(vec![0.1, -0.2], vec![2]).unwrap();
+        // [0.1, -1.45, 0.85, 0.675]
+        let linear = Int8Linear::quantize(&b, &bias, 1.0, 1.0).unwrap();
+        assert_eq!(linear.bias(), [1613, -3226]);
+        let mut out = Tensor::zeros(vec![2, 2]);
+        int8_linear(&a, &linear, &mut out).unwrap();
+        // The outputs saturate at the calibrated range.
+        let expected = [0.1, -1.0, 0.85, 0.675];
+        for (v, e) in out.data().iter().zip(expected) {
+            assert!((v - e).abs() < 0.01, "{v} != {e}");
+        }
+    }
+}
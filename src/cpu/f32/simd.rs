@@ -0,0 +1,835 @@
+use std::sync::OnceLock;
+
+/// The instruction sets with a vectorized implementation, detected once at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isa {
+    /// x86_64 with AVX-512F
+    #[cfg(target_arch = "x86_64")]
+    Avx512,
+    /// x86_64 with AVX2 and FMA
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    /// aarch64 with NEON
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+    /// Plain loops, left to the compiler
+    Scalar,
+}
+
+/// The best instruction set available on this cpu.
+pub(crate) fn isa() -> Isa {
+    static ISA: OnceLock<Isa> = OnceLock::new();
+    *ISA.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                return Isa::Avx512;
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                return Isa::Avx2;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return Isa::Neon;
+            }
+        }
+        Isa::Scalar
+    })
+}
+
+/// The instruction sets with an int8 dot product, detected once at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Int8Isa {
+    /// x86_64 with AVX-512 VNNI, `VPDPBUSD` on 64 bytes
+    #[cfg(target_arch = "x86_64")]
+    Avx512Vnni,
+    /// x86_64 with AVX-VNNI, `VPDPBUSD` on 32 bytes
+    #[cfg(target_arch = "x86_64")]
+    AvxVnni,
+    /// x86_64 with AVX2, `VPMADDUBSW` and `VPMADDWD`
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    /// aarch64 with the dot product extension, `SDOT`
+    #[cfg(target_arch = "aarch64")]
+    Dotprod,
+    /// aarch64 with NEON, `SMULL` and `SADALP`
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+    /// Plain loops, left to the compiler
+    Scalar,
+}
+
+/// The best int8 dot product available on this cpu.
+pub(crate) fn int8_isa() -> Int8Isa {
+    static ISA: OnceLock<Int8Isa> = OnceLock::new();
+    *ISA.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512vnni") && is_x86_feature_detected!("avx512bw") {
+                return Int8Isa::Avx512Vnni;
+            }
+            if is_x86_feature_detected!("avxvnni") {
+                return Int8Isa::AvxVnni;
+            }
+            if is_x86_feature_detected!("avx2") {
+                return Int8Isa::Avx2;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("dotprod") {
+                return Int8Isa::Dotprod;
+            }
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return Int8Isa::Neon;
+            }
+        }
+        Int8Isa::Scalar
+    })
+}
+
+// sqrt(2 / pi)
+const GELU_ALPHA: f32 = 0.797_884_6;
+const GELU_BETA: f32 = 0.044715;
+
+mod scalar {
+    use super::{GELU_ALPHA, GELU_BETA};
+
+    pub fn gemm(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+        for i in 0..m {
+            let crow = &mut c[i * n..(i + 1) * n];
+            for l in 0..k {
+                let av = a[i * k + l];
+                let brow = &b[l * n..(l + 1) * n];
+                crow.iter_mut().zip(brow).for_each(|(c, b)| *c += av * b);
+            }
+        }
+    }
+
+    pub fn gemm_t(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+        for i in 0..m {
+            let arow = &a[i * k..(i + 1) * k];
+            for j in 0..n {
+                let brow = &b[j * k..(j + 1) * k];
+                c[i * n + j] += arow.iter().zip(brow).map(|(a, b)| a * b).sum::<f32>();
+            }
+        }
+    }
+
+    pub fn softmax(row: &mut [f32]) {
+        let max = row.iter().fold(f32::NEG_INFINITY, |m, &v| m.max(v));
+        let mut sum = 0.0;
+        row.iter_mut().for_each(|v| {
+            *v = (*v - max).exp();
+            sum += *v;
+        });
+        row.iter_mut().for_each(|v| *v /= sum);
+    }
+
+    pub fn normalize(row: &mut [f32], epsilon: f32) {
+        let size = row.len() as f32;
+        let mean = row.iter().sum::<f32>() / size;
+        row.iter_mut().for_each(|v| *v -= mean);
+        let var = row.iter().map(|v| v * v).sum::<f32>() / size;
+        let stddev = (var + epsilon).sqrt();
+        row.iter_mut().for_each(|v| *v /= stddev);
+    }
+
+    pub fn dot_i8(a: &[i8], b: &[i8]) -> i32 {
+        a.iter().zip(b).map(|(&x, &y)| x as i32 * y as i32).sum()
+    }
+
+    #[inline]
+    pub fn tanh1(x: f32) -> f32 {
+        1.0 - (2.0 / (1.0 + (2.0 * x).exp()))
+    }
+
+    #[inline]
+    pub fn gelu1(v: f32) -> f32 {
+        0.5 * v * (1.0 + tanh1(GELU_ALPHA * v * (1.0 + GELU_BETA * v * v)))
+    }
+
+    pub fn tanh(x: &mut [f32]) {
+        x.iter_mut().for_each(|v| *v = tanh1(*v));
+    }
+
+    pub fn gelu(x: &mut [f32]) {
+        x.iter_mut().for_each(|v| *v = gelu1(*v));
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod consts {
+    // exp(x) = 2^n * exp(r) with x = n * ln(2) + r, |r| <= ln(2) / 2,
+    // exp(r) is a degree 6 polynomial.
+    pub const LOG2E: f32 = std::f32::consts::LOG2_E;
+    pub const LN2_HI: f32 = 0.693_145_75;
+    pub const LN2_LO: f32 = 1.428_606_8e-6;
+    pub const EXP_MIN: f32 = -87.3;
+    pub const EXP_MAX: f32 = 88.3;
+    pub const EXP_POLY: [f32; 7] = [
+        1.0 / 720.0,
+        1.0 / 120.0,
+        1.0 / 24.0,
+        1.0 / 6.0,
+        0.5,
+        1.0,
+        1.0,
+    ];
+}
+
+/// Generates the vectorized kernels for one instruction set, from its basic vector ops.
+/// Every kernel processes `$width` floats at a time and finishes the tails with the
+/// scalar code.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+macro_rules! kernels {
+    (
+        features: $features: literal,
+        width: $width: expr,
+        load: $load: ident,
+        store: $store: ident,
+        set1: $set1: ident,
+        add: $add: ident,
+        sub: $sub: ident,
+        mul: $mul: ident,
+        div: $div: ident,
+        max: $max: ident,
+        fmadd: $fmadd: ident,
+    ) => {
+        use super::super::scalar;
+        use super::super::{GELU_ALPHA, GELU_BETA};
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn gemm(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+            let body = n - n % $width;
+            for i in 0..m {
+                let cp = c.as_mut_ptr().add(i * n);
+                for l in 0..k {
+                    let av = a[i * k + l];
+                    let avv = $set1(av);
+                    let bp = b.as_ptr().add(l * n);
+                    for j in (0..body).step_by($width) {
+                        let cv = $fmadd(avv, $load(bp.add(j)), $load(cp.add(j)));
+                        $store(cp.add(j), cv);
+                    }
+                    for j in body..n {
+                        *cp.add(j) += av * *bp.add(j);
+                    }
+                }
+            }
+        }
+
+        #[target_feature(enable = $features)]
+        unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+            let k = a.len();
+            let body = k - k % $width;
+            let (ap, bp) = (a.as_ptr(), b.as_ptr());
+            let mut acc = $set1(0.0);
+            for l in (0..body).step_by($width) {
+                acc = $fmadd($load(ap.add(l)), $load(bp.add(l)), acc);
+            }
+            let mut sum = hsum(acc);
+            for l in body..k {
+                sum += a[l] * b[l];
+            }
+            sum
+        }
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn gemm_t(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+            for i in 0..m {
+                let arow = &a[i * k..(i + 1) * k];
+                for j in 0..n {
+                    c[i * n + j] += dot(arow, &b[j * k..(j + 1) * k]);
+                }
+            }
+        }
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn softmax(row: &mut [f32]) {
+            let size = row.len();
+            let body = size - size % $width;
+            let p = row.as_mut_ptr();
+
+            let mut maxv = $set1(f32::NEG_INFINITY);
+            for j in (0..body).step_by($width) {
+                maxv = $max(maxv, $load(p.add(j)));
+            }
+            let max = row[body..].iter().fold(hmax(maxv), |m, &v| m.max(v));
+
+            let maxv = $set1(max);
+            let mut sumv = $set1(0.0);
+            for j in (0..body).step_by($width) {
+                let v = exp($sub($load(p.add(j)), maxv));
+                sumv = $add(sumv, v);
+                $store(p.add(j), v);
+            }
+            let mut sum = hsum(sumv);
+            for v in &mut row[body..] {
+                *v = (*v - max).exp();
+                sum += *v;
+            }
+
+            let inv = $set1(1.0 / sum);
+            for j in (0..body).step_by($width) {
+                $store(p.add(j), $mul($load(p.add(j)), inv));
+            }
+            row[body..].iter_mut().for_each(|v| *v /= sum);
+        }
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn normalize(row: &mut [f32], epsilon: f32) {
+            let size = row.len();
+            let body = size - size % $width;
+            let p = row.as_mut_ptr();
+
+            let mut sumv = $set1(0.0);
+            for j in (0..body).step_by($width) {
+                sumv = $add(sumv, $load(p.add(j)));
+            }
+            let sum = hsum(sumv) + row[body..].iter().sum::<f32>();
+            let mean = sum / size as f32;
+
+            let meanv = $set1(mean);
+            let mut varv = $set1(0.0);
+            for j in (0..body).step_by($width) {
+                let v = $sub($load(p.add(j)), meanv);
+                varv = $fmadd(v, v, varv);
+                $store(p.add(j), v);
+            }
+            let mut var = hsum(varv);
+            for v in &mut row[body..] {
+                *v -= mean;
+                var += *v * *v;
+            }
+            let stddev = (var / size as f32 + epsilon).sqrt();
+
+            let stddevv = $set1(stddev);
+            for j in (0..body).step_by($width) {
+                $store(p.add(j), $div($load(p.add(j)), stddevv));
+            }
+            row[body..].iter_mut().for_each(|v| *v /= stddev);
+        }
+
+        #[target_feature(enable = $features)]
+        unsafe fn tanhv(x: Vector) -> Vector {
+            // 1 - 2 / (1 + exp(2x))
+            let one = $set1(1.0);
+            let e = exp($add(x, x));
+            $sub(one, $div($set1(2.0), $add(one, e)))
+        }
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn tanh(x: &mut [f32]) {
+            let size = x.len();
+            let body = size - size % $width;
+            let p = x.as_mut_ptr();
+            for j in (0..body).step_by($width) {
+                $store(p.add(j), tanhv($load(p.add(j))));
+            }
+            scalar::tanh(&mut x[body..]);
+        }
+
+        #[target_feature(enable = $features)]
+        pub unsafe fn gelu(x: &mut [f32]) {
+            let size = x.len();
+            let body = size - size % $width;
+            let p = x.as_mut_ptr();
+            let (half, one) = ($set1(0.5), $set1(1.0));
+            let (alpha, beta) = ($set1(GELU_ALPHA), $set1(GELU_BETA));
+            for j in (0..body).step_by($width) {
+                let v = $load(p.add(j));
+                let inner = $mul($mul(alpha, v), $fmadd($mul(beta, v), v, one));
+                let out = $mul($mul(half, v), $add(one, tanhv(inner)));
+                $store(p.add(j), out);
+            }
+            scalar::gelu(&mut x[body..]);
+        }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::consts::*;
+
+    pub mod avx2 {
+        use super::*;
+        use std::arch::x86_64::*;
+
+        type Vector = __m256;
+
+        #[target_feature(enable = "avx2,fma")]
+        unsafe fn hsum(v: __m256) -> f32 {
+            let v = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+            let v = _mm_add_ps(v, _mm_movehl_ps(v, v));
+            let v = _mm_add_ss(v, _mm_shuffle_ps(v, v, 1));
+            _mm_cvtss_f32(v)
+        }
+
+        #[target_feature(enable = "avx2,fma")]
+        unsafe fn hmax(v: __m256) -> f32 {
+            let v = _mm_max_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+            let v = _mm_max_ps(v, _mm_movehl_ps(v, v));
+            let v = _mm_max_ss(v, _mm_shuffle_ps(v, v, 1));
+            _mm_cvtss_f32(v)
+        }
+
+        #[target_feature(enable = "avx2,fma")]
+        unsafe fn exp(x: __m256) -> __m256 {
+            let x = _mm256_min_ps(
+                _mm256_max_ps(x, _mm256_set1_ps(EXP_MIN)),
+                _mm256_set1_ps(EXP_MAX),
+            );
+            let n = _mm256_round_ps(
+                _mm256_mul_ps(x, _mm256_set1_ps(LOG2E)),
+                _MM_FROUND_TO_NEAREST_INT | _MM_FROUND_NO_EXC,
+            );
+            let r = _mm256_fnmadd_ps(n, _mm256_set1_ps(LN2_HI), x);
+            let r = _mm256_fnmadd_ps(n, _mm256_set1_ps(LN2_LO), r);
+            let mut p = _mm256_set1_ps(EXP_POLY[0]);
+            for c in &EXP_POLY[1..] {
+                p = _mm256_fmadd_ps(p, r, _mm256_set1_ps(*c));
+            }
+            let pow2 = _mm256_slli_epi32(
+                _mm256_add_epi32(_mm256_cvtps_epi32(n), _mm256_set1_epi32(127)),
+                23,
+            );
+            _mm256_mul_ps(p, _mm256_castsi256_ps(pow2))
+        }
+
+        kernels!(
+            features: "avx2,fma",
+            width: 8,
+            load: _mm256_loadu_ps,
+            store: _mm256_storeu_ps,
+            set1: _mm256_set1_ps,
+            add: _mm256_add_ps,
+            sub: _mm256_sub_ps,
+            mul: _mm256_mul_ps,
+            div: _mm256_div_ps,
+            max: _mm256_max_ps,
+            fmadd: _mm256_fmadd_ps,
+        );
+    }
+
+    pub mod avx512 {
+        use super::*;
+        use std::arch::x86_64::*;
+
+        type Vector = __m512;
+
+        #[target_feature(enable = "avx512f")]
+        unsafe fn hsum(v: __m512) -> f32 {
+            _mm512_reduce_add_ps(v)
+        }
+
+        #[target_feature(enable = "avx512f")]
+        unsafe fn hmax(v: __m512) -> f32 {
+            _mm512_reduce_max_ps(v)
+        }
+
+        #[target_feature(enable = "avx512f")]
+        unsafe fn exp(x: __m512) -> __m512 {
+            let x = _mm512_min_ps(
+                _mm512_max_ps(x, _mm512_set1_ps(EXP_MIN)),
+                _mm512_set1_ps(EXP_MAX),
+            );
+            let n = _mm512_roundscale_ps(
+                _mm512_mul_ps(x, _mm512_set1_ps(LOG2E)),
+                _MM_FROUND_TO_NEAREST_INT | _MM_FROUND_NO_EXC,
+            );
+            let r = _mm512_fnmadd_ps(n, _mm512_set1_ps(LN2_HI), x);
+            let r = _mm512_fnmadd_ps(n, _mm512_set1_ps(LN2_LO), r);
+            let mut p = _mm512_set1_ps(EXP_POLY[0]);
+            for c in &EXP_POLY[1..] {
+                p = _mm512_fmadd_ps(p, r, _mm512_set1_ps(*c));
+            }
+            let pow2 = _mm512_slli_epi32(
+                _mm512_add_epi32(_mm512_cvtps_epi32(n), _mm512_set1_epi32(127)),
+                23,
+            );
+            _mm512_mul_ps(p, _mm512_castsi512_ps(pow2))
+        }
+
+        kernels!(
+            features: "avx512f",
+            width: 16,
+            load: _mm512_loadu_ps,
+            store: _mm512_storeu_ps,
+            set1: _mm512_set1_ps,
+            add: _mm512_add_ps,
+            sub: _mm512_sub_ps,
+            mul: _mm512_mul_ps,
+            div: _mm512_div_ps,
+            max: _mm512_max_ps,
+            fmadd: _mm512_fmadd_ps,
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+    use super::consts::*;
+
+    pub mod neon {
+        use super::*;
+        use std::arch::aarch64::*;
+
+        type Vector = float32x4_t;
+
+        // Same argument order as the x86 `fmadd`: `a * b + c`.
+        #[target_feature(enable = "neon")]
+        unsafe fn fmadd(a: float32x4_t, b: float32x4_t, c: float32x4_t) -> float32x4_t {
+            vfmaq_f32(c, a, b)
+        }
+
+        #[target_feature(enable = "neon")]
+        unsafe fn hsum(v: float32x4_t) -> f32 {
+            vaddvq_f32(v)
+        }
+
+        #[target_feature(enable = "neon")]
+        unsafe fn hmax(v: float32x4_t) -> f32 {
+            vmaxvq_f32(v)
+        }
+
+        #[target_feature(enable = "neon")]
+        unsafe fn exp(x: float32x4_t) -> float32x4_t {
+            let x = vminq_f32(vmaxq_f32(x, vdupq_n_f32(EXP_MIN)), vdupq_n_f32(EXP_MAX));
+            let n = vrndnq_f32(vmulq_f32(x, vdupq_n_f32(LOG2E)));
+            let r = vfmsq_f32(x, n, vdupq_n_f32(LN2_HI));
+            let r = vfmsq_f32(r, n, vdupq_n_f32(LN2_LO));
+            let mut p = vdupq_n_f32(EXP_POLY[0]);
+            for c in &EXP_POLY[1..] {
+                p = vfmaq_f32(vdupq_n_f32(*c), p, r);
+            }
+            let pow2 = vshlq_n_s32(vaddq_s32(vcvtq_s32_f32(n), vdupq_n_s32(127)), 23);
+            vmulq_f32(p, vreinterpretq_f32_s32(pow2))
+        }
+
+        kernels!(
+            features: "neon",
+            width: 4,
+            load: vld1q_f32,
+            store: vst1q_f32,
+            set1: vdupq_n_f32,
+            add: vaddq_f32,
+            sub: vsubq_f32,
+            mul: vmulq_f32,
+            div: vdivq_f32,
+            max: vmaxq_f32,
+            fmadd: fmadd,
+        );
+    }
+}
+
+/// Generates the int8 dot product for one instruction set, from its `step` accumulating
+/// `$width` products at a time in i32 lanes and its horizontal sum.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+macro_rules! dot_i8 {
+    (
+        features: $features: literal,
+        width: $width: expr,
+        zero: $zero: expr,
+        step: $step: ident,
+        hsum: $hsum: ident,
+    ) => {
+        #[target_feature(enable = $features)]
+        pub unsafe fn dot_i8(a: &[i8], b: &[i8]) -> i32 {
+            let k = a.len().min(b.len());
+            let body = k - k % $width;
+            let (ap, bp) = (a.as_ptr(), b.as_ptr());
+            let mut acc = $zero;
+            for l in (0..body).step_by($width) {
+                acc = $step(acc, ap.add(l), bp.add(l));
+            }
+            $hsum(acc) + super::super::scalar::dot_i8(&a[body..k], &b[body..k])
+        }
+    };
+}
+
+// The x86 instructions multiply unsigned bytes by signed ones: `a * b` is computed as
+// `|a| * (b * sign(a))`, exact as long as `-128` never appears, which the quantization
+// of this crate guarantees by saturating at `[-127, 127]`.
+#[cfg(target_arch = "x86_64")]
+mod x86_int8 {
+    pub mod avx2 {
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2")]
+        pub(super) unsafe fn hsum(v: __m256i) -> i32 {
+            let v = _mm_add_epi32(_mm256_castsi256_si128(v), _mm256_extracti128_si256(v, 1));
+            let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b01_00_11_10));
+            let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b10_11_00_01));
+            _mm_cvtsi128_si32(v)
+        }
+
+        #[target_feature(enable = "avx2")]
+        unsafe fn step(acc: __m256i, a: *const i8, b: *const i8) -> __m256i {
+            let (a, b) = (_mm256_loadu_si256(a.cast()), _mm256_loadu_si256(b.cast()));
+            // The pairs fit in i16: 2 * 127 * 127 < 32767
+            let pairs = _mm256_maddubs_epi16(_mm256_sign_epi8(a, a), _mm256_sign_epi8(b, a));
+            _mm256_add_epi32(acc, _mm256_madd_epi16(pairs, _mm256_set1_epi16(1)))
+        }
+
+        dot_i8!(
+            features: "avx2",
+            width: 32,
+            zero: _mm256_setzero_si256(),
+            step: step,
+            hsum: hsum,
+        );
+    }
+
+    pub mod avxvnni {
+        use super::avx2::hsum;
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2,avxvnni")]
+        unsafe fn step(acc: __m256i, a: *const i8, b: *const i8) -> __m256i {
+            let (a, b) = (_mm256_loadu_si256(a.cast()), _mm256_loadu_si256(b.cast()));
+            _mm256_dpbusd_avx_epi32(acc, _mm256_sign_epi8(a, a), _mm256_sign_epi8(b, a))
+        }
+
+        dot_i8!(
+            features: "avx2,avxvnni",
+            width: 32,
+            zero: _mm256_setzero_si256(),
+            step: step,
+            hsum: hsum,
+        );
+    }
+
+    pub mod avx512vnni {
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx512f")]
+        unsafe fn hsum(v: __m512i) -> i32 {
+            _mm512_reduce_add_epi32(v)
+        }
+
+        #[target_feature(enable = "avx512f,avx512bw,avx512vnni")]
+        unsafe fn step(acc: __m512i, a: *const i8, b: *const i8) -> __m512i {
+            let (a, b) = (_mm512_loadu_si512(a.cast()), _mm512_loadu_si512(b.cast()));
+            let negative = _mm512_movepi8_mask(a);
+            let b = _mm512_mask_sub_epi8(b, negative, _mm512_setzero_si512(), b);
+            _mm512_dpbusd_epi32(acc, _mm512_abs_epi8(a), b)
+        }
+
+        dot_i8!(
+            features: "avx512f,avx512bw,avx512vnni",
+            width: 64,
+            zero: _mm512_setzero_si512(),
+            step: step,
+            hsum: hsum,
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm_int8 {
+    pub mod neon {
+        use std::arch::aarch64::*;
+
+        #[target_feature(enable = "neon")]
+        pub(super) unsafe fn hsum(v: int32x4_t) -> i32 {
+            vaddvq_s32(v)
+        }
+
+        #[target_feature(enable = "neon")]
+        unsafe fn step(acc: int32x4_t, a: *const i8, b: *const i8) -> int32x4_t {
+            let (a, b) = (vld1q_s8(a), vld1q_s8(b));
+            let acc = vpadalq_s16(acc, vmull_s8(vget_low_s8(a), vget_low_s8(b)));
+            vpadalq_s16(acc, vmull_high_s8(a, b))
+        }
+
+        dot_i8!(
+            features: "neon",
+            width: 16,
+            zero: vdupq_n_s32(0),
+            step: step,
+            hsum: hsum,
+        );
+    }
+
+    pub mod dotprod {
+        use super::neon::hsum;
+        use std::arch::aarch64::*;
+
+        // The `vdotq_s32` intrinsic is not stable yet.
+        #[target_feature(enable = "neon,dotprod")]
+        unsafe fn step(acc: int32x4_t, a: *const i8, b: *const i8) -> int32x4_t {
+            let (a, b) = (vld1q_s8(a), vld1q_s8(b));
+            let mut acc = acc;
+            std::arch::asm!(
+                "sdot {0:v}.4s, {1:v}.16b, {2:v}.16b",
+                inout(vreg) acc,
+                in(vreg) a,
+                in(vreg) b,
+                options(pure, nomem, nostack)
+            );
+            acc
+        }
+
+        dot_i8!(
+            features: "neon,dotprod",
+            width: 16,
+            zero: vdupq_n_s32(0),
+            step: step,
+            hsum: hsum,
+        );
+    }
+}
+
+/// Calls the `$name` kernel of the best available instruction set.
+macro_rules! dispatch {
+    ($name: ident($($arg: expr),*)) => {
+        match isa() {
+            // SAFETY: The instruction set was detected on this cpu.
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx512 => unsafe { x86::avx512::$name($($arg),*) },
+            // SAFETY: The instruction set was detected on this cpu.
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::avx2::$name($($arg),*) },
+            // SAFETY: The instruction set was detected on this cpu.
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { arm::neon::$name($($arg),*) },
+            Isa::Scalar => scalar::$name($($arg),*),
+        }
+    };
+}
+
+/// `c += a x b` for row major `a` (m, k), `b` (k, n) and `c` (m, n).
+pub(crate) fn gemm(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+    dispatch!(gemm(a, b, c, m, n, k))
+}
+
+/// `c += a x b.T` for row major `a` (m, k), `b` (n, k) and `c` (m, n).
+pub(crate) fn gemm_t(a: &[f32], b: &[f32], c: &mut [f32], m: usize, n: usize, k: usize) {
+    dispatch!(gemm_t(a, b, c, m, n, k))
+}
+
+/// The dot product of the int8 `a` and `b`, accumulated in i32. The values must be in
+/// `[-127, 127]`.
+pub(crate) fn dot_i8(a: &[i8], b: &[i8]) -> i32 {
+    match int8_isa() {
+        // SAFETY: The instruction set was detected on this cpu.
+        #[cfg(target_arch = "x86_64")]
+        Int8Isa::Avx512Vnni => unsafe { x86_int8::avx512vnni::dot_i8(a, b) },
+        // SAFETY: The instruction set was detected on this cpu.
+        #[cfg(target_arch = "x86_64")]
+        Int8Isa::AvxVnni => unsafe { x86_int8::avxvnni::dot_i8(a, b) },
+        // SAFETY: The instruction set was detected on this cpu.
+        #[cfg(target_arch = "x86_64")]
+        Int8Isa::Avx2 => unsafe { x86_int8::avx2::dot_i8(a, b) },
+        // SAFETY: The instruction set was detected on this cpu.
+        #[cfg(target_arch = "aarch64")]
+        Int8Isa::Dotprod => unsafe { arm_int8::dotprod::dot_i8(a, b) },
+        // SAFETY: The instruction set was detected on this cpu.
+        #[cfg(target_arch = "aarch64")]
+        Int8Isa::Neon => unsafe { arm_int8::neon::dot_i8(a, b) },
+        Int8Isa::Scalar => scalar::dot_i8(a, b),
+    }
+}
+
+/// Softmax of `row`, in place.
+pub(crate) fn softmax(row: &mut [f32]) {
+    dispatch!(softmax(row))
+}
+
+/// `row = (row - row.mean()) / sqrt(row.var() + epsilon)`, in place.
+pub(crate) fn normalize(row: &mut [f32], epsilon: f32) {
+    dispatch!(normalize(row, epsilon))
+}
+
+/// `tanh` of every item of `x`, in place.
+pub(crate) fn tanh(x: &mut [f32]) {
+    dispatch!(tanh(x))
+}
+
+/// `gelu` of every item of `x`, in place.
+pub(crate) fn gelu(x: &mut [f32]) {
+    dispatch!(gelu(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::simplify;
+
+    fn data(n: usize) -> Vec<f32> {
+        (0..n).map(|i| ((i * 7) % 13) as f32 / 4.0 - 1.5).collect()
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        // Sizes with tails for every vector width.
+        let (m, n, k) = (3, 37, 21);
+        let a = data(m * k);
+        let b = data(k * n);
+        let mut expected = vec![0.0; m * n];
+        scalar::gemm(&a, &b, &mut expected, m, n, k);
+        let mut c = vec![0.0; m * n];
+        gemm(&a, &b, &mut c, m, n, k);
+        assert_eq!(simplify(&c), simplify(&expected));
+
+        let mut expected = vec![0.0; m * n];
+        scalar::gemm_t(&a, &b, &mut expected, m, n, k);
+        let mut c = vec![0.0; m * n];
+        gemm_t(&a, &b, &mut c, m, n, k);
+        assert_eq!(simplify(&c), simplify(&expected));
+
+        type Kernel = (fn(&mut [f32]), fn(&mut [f32]));
+        let kernels: [Kernel; 4] = [
+            (softmax, scalar::softmax),
+            (|x| normalize(x, 1e-5), |x| scalar::normalize(x, 1e-5)),
+            (tanh, scalar::tanh),
+            (gelu, scalar::gelu),
+        ];
+        for (simd, scalar) in kernels {
+            let mut x = data(37);
+            let mut expected = x.clone();
+            simd(&mut x);
+            scalar(&mut expected);
+            assert_eq!(simplify(&x), simplify(&expected));
+        }
+    }
+
+    #[test]
+    fn int8_simd_matches_scalar() {
+        // Lengths with tails for every vector width.
+        let a: Vec<i8> = (0..139).map(|i| ((i * 37) % 255 - 127) as i8).collect();
+        let b: Vec<i8> = (0..139).map(|i| (127 - (i * 11) % 255) as i8).collect();
+        let expected = scalar::dot_i8(&a, &b);
+        assert_eq!(dot_i8(&a, &b), expected);
+        assert_eq!(
+            dot_i8(&a[..17], &b[..17]),
+            scalar::dot_i8(&a[..17], &b[..17])
+        );
+
+        // Every instruction set of this cpu, not only the best one.
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                assert_eq!(unsafe { x86_int8::avx2::dot_i8(&a, &b) }, expected);
+            }
+            if is_x86_feature_detected!("avxvnni") {
+                assert_eq!(unsafe { x86_int8::avxvnni::dot_i8(&a, &b) }, expected);
+            }
+            if is_x86_feature_detected!("avx512vnni") && is_x86_feature_detected!("avx512bw") {
+                assert_eq!(unsafe { x86_int8::avx512vnni::dot_i8(&a, &b) }, expected);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                assert_eq!(unsafe { arm_int8::neon::dot_i8(&a, &b) }, expected);
+            }
+            if std::arch::is_aarch64_feature_detected!("dotprod") {
+                assert_eq!(unsafe { arm_int8::dotprod::dot_i8(&a, &b) }, expected);
+            }
+        }
+    }
+}
@@ -1,40 +1,12 @@
 use crate::SmeltError;
-use std::borrow::Cow;
 
 /// Tensor, can own, or borrow the underlying tensor
-#[derive(Clone)]
-pub struct Tensor {
-    pub(super) shape: Vec<usize>,
-    data: Cow<'static, [f32]>,
-}
+pub type Tensor = crate::cpu::Tensor<f32>;
 
 /// The CPU device
-#[derive(Copy, Clone)]
-pub struct Device {}
+pub type Device = crate::cpu::Device<f32>;
 
 impl Tensor {
-    /// The shape of the tensor
-    /// ```
-    /// use smelte_rs::cpu::f32::Tensor;
-    ///
-    /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// assert_eq!(tensor.shape(), vec![2, 2]);
-    /// ```
-    pub fn shape(&self) -> &[usize] {
-        &self.shape
-    }
-
-    /// A slice to the underlying tensor data
-    /// ```
-    /// use smelte_rs::cpu::f32::Tensor;
-    ///
-    /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// assert_eq!(tensor.data(), vec![0.0; 4]);
-    /// ```
-    pub fn data(&self) -> &[f32] {
-        self.data.as_ref()
-    }
-
     /// A slice to the underlying tensor data.
     /// Exists uniquely for symetry with gpu Tensor.
     /// ```
@@ -47,82 +19,68 @@ impl Tensor {
         Ok(self.data.as_ref())
     }
 
-    /// A mutable slice to the underlying tensor data
+    /// A tensor of uniform items in `[0, 1)`, the same for a given `seed` on every backend
+    /// (see [crate::random::next_seed] for seeds drawn from the crate-level seed).
     /// ```
     /// use smelte_rs::cpu::f32::Tensor;
     ///
-    /// let mut tensor = Tensor::zeros(vec![2, 2]);
-    /// tensor.data_mut().iter_mut().for_each(|v| *v += 1.0);
-    /// assert_eq!(tensor.data(), vec![1.0; 4]);
-    /// ```
-    pub fn data_mut(&mut self) -> &mut [f32] {
-        self.data.to_mut()
+    /// let tensor = Tensor::rand_uniform(vec![2, 3], 42);
+    /// assert!(tensor.data().iter().all(|v| (0.0..1.0).contains(v)));
+    /// assert_eq!(tensor.data(), Tensor::rand_uniform(vec![6], 42).data());
+    /// ```
+    pub fn rand_uniform(shape: Vec<usize>, seed: u64) -> Self {
+        let mut tensor = Self::zeros(shape);
+        crate::random::fill_uniform(tensor.data_mut(), seed);
+        tensor
     }
 
-    /// Creates a new nulled tensor with given shape
-    /// ```
-    /// use smelte_rs::cpu::f32::Tensor;
-    ///
-    /// let tensor = Tensor::zeros(vec![2, 2]);
-    /// ```
-    pub fn zeros(shape: Vec<usize>) -> Self {
-        let nelement: usize = shape.iter().product();
-        let data = Cow::Owned(vec![0.0; nelement]);
-        Self { shape, data }
+    /// A tensor of standard normal items, see [Tensor::rand_uniform].
+    pub fn rand_normal(shape: Vec<usize>, seed: u64) -> Self {
+        let mut tensor = Self::zeros(shape);
+        crate::random::fill_normal(tensor.data_mut(), seed);
+        tensor
     }
 
-    /// Creates a new borrowed tensor with given shape. Can fail if data doesn't match the shape
-    /// ```
-    /// use smelte_rs::cpu::f32::Tensor;
-    ///
-    /// let tensor = Tensor::borrowed(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
-    /// ```
-    pub fn borrowed(data: &'static [f32], shape: Vec<usize>) -> Result<Self, SmeltError> {
-        let cow: Cow<'static, [f32]> = data.into();
-        Self::new(cow, shape)
+    /// The half precision copy of the tensor, see [crate::cpu::f16::Tensor::from_f32].
+    #[cfg(feature = "f16")]
+    pub fn to_f16(&self) -> crate::cpu::f16::Tensor {
+        crate::cpu::f16::Tensor::from_f32(self)
     }
 
-    /// Creates a new tensor with given shape. Can fail if data doesn't match the shape
+    /// The bfloat16 copy of the tensor, see [crate::cpu::bf16::Tensor::from_f32].
+    #[cfg(feature = "bf16")]
+    pub fn to_bf16(&self) -> crate::cpu::bf16::Tensor {
+        crate::cpu::bf16::Tensor::from_f32(self)
+    }
+
+    /// The double precision copy of the tensor, which is exact.
+    pub fn to_f64(&self) -> crate::cpu::f64::Tensor {
+        crate::cpu::f64::Tensor::from_f32(self)
+    }
+
+    /// The int8 copy of the tensor, `round(v / scale)` saturating at `[-127, 127]`.
     /// ```
     /// use smelte_rs::cpu::f32::Tensor;
     ///
-    /// let data = vec![1.0, 2.0, 3.0, 4.0];
-    /// let tensor = Tensor::new(data, vec![2, 2]).unwrap();
-    /// ```
-    pub fn new<T>(data: T, shape: Vec<usize>) -> Result<Self, SmeltError>
-    where
-        T: Into<Cow<'static, [f32]>>,
-    {
-        let data = data.into();
-        if data.len() != shape.iter().product::<usize>() {
-            return Err(SmeltError::InvalidBuffer {
-                buffer_size: data.len(),
-                shape,
-            });
-        }
-        Ok(Self { shape, data })
+    /// let tensor = Tensor::new(vec![0.5, -1.0, 100.0], vec![3]).unwrap();
+    /// let tensor = tensor.to_i8(0.1);
+    /// assert_eq!(tensor.data(), [5, -10, 127]);
+    /// assert_eq!(tensor.to_f32(0.1).data(), [0.5, -1.0, 12.7]);
+    /// ```
+    pub fn to_i8(&self, scale: f32) -> crate::cpu::Tensor<i8> {
+        let data: Vec<i8> = self
+            .data
+            .iter()
+            .map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8)
+            .collect();
+        crate::cpu::Tensor::new(data, self.shape.clone()).expect("The buffer matches the shape")
     }
+}
 
-    /// Creates a new tensor with given shape. Can fail if data doesn't match the shape
-    /// Exists only for symetry with gpu tensor.
-    /// ```
-    /// use smelte_rs::cpu::f32::{Tensor, Device};
-    ///
-    /// let device = Device{};
-    /// let data = vec![1.0, 2.0, 3.0, 4.0];
-    /// let tensor = Tensor::from_cpu(data, vec![2, 2], &device).unwrap();
-    /// ```
-    pub fn from_cpu<T>(data: T, shape: Vec<usize>, _device: &Device) -> Result<Self, SmeltError>
-    where
-        T: Into<Cow<'static, [f32]>>,
-    {
-        let data = data.into();
-        if data.len() != shape.iter().product::<usize>() {
-            return Err(SmeltError::InvalidBuffer {
-                buffer_size: data.len(),
-                shape,
-            });
-        }
-        Ok(Self { shape, data })
+impl crate::cpu::Tensor<i8> {
+    /// The f32 copy of an int8 tensor, `v * scale` (see [Tensor::to_i8]).
+    pub fn to_f32(&self, scale: f32) -> Tensor {
+        let data: Vec<f32> = self.data.iter().map(|&v| v as f32 * scale).collect();
+        Tensor::new(data, self.shape.clone()).expect("The buffer matches the shape")
     }
 }
@@ -1,10 +1,24 @@
+use super::conv;
 use super::ops;
+use super::quantized::{AbsMaxObserver, Int8Linear, Int8Tensor};
 use super::tensor::{Device, Tensor};
+#[cfg(feature = "bf16")]
+use crate::cpu::bf16::Tensor as BF16Tensor;
+#[cfg(feature = "f16")]
+use crate::cpu::f16::Tensor as F16Tensor;
+use crate::cpu::Mask;
 use crate::traits::{
-    Device as DeviceTrait, Tensor as TensorTrait, TensorAdd, TensorCopy, TensorGelu, TensorMatmul,
-    TensorMatmulT, TensorMul, TensorNormalize, TensorOps, TensorSelect, TensorSoftmax, TensorTanh,
+    Activation, Conv1dConfig, Conv2dConfig, Device as DeviceTrait, FusedAttentionConfig, Precision,
+    QuantizedWeight, RangeObserver, Tensor as TensorTrait, TensorActivation, TensorAdd,
+    TensorArgmax, TensorClamp, TensorCompare, TensorConv1d, TensorConv2d, TensorCopy, TensorCumsum,
+    TensorFusedAttention, TensorGelu, TensorMask, TensorMatmul, TensorMatmulT, TensorMul,
+    TensorMulScalar, TensorNarrow, TensorNormalize, TensorOps, TensorPad, TensorPositionMask,
+    TensorPrecision, TensorQuantize, TensorReduce, TensorReshape, TensorRotary, TensorSelect,
+    TensorSoftmax, TensorSoftmaxDim, TensorSub, TensorTanh, TensorToDevice, TensorTopK, TensorTopP,
+    TensorTriangle, TensorUnary,
 };
 use crate::SmeltError;
+use std::sync::Arc;
 
 impl TensorTrait for Tensor {
     type Device = Device;
@@ -13,14 +27,18 @@ impl TensorTrait for Tensor {
     }
 
     fn device(&self) -> &Device {
-        &Device {}
+        &self.device
     }
 }
 impl DeviceTrait for Device {
     type Tensor = Tensor;
 
     fn zeros(&self, shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
-        Ok(Self::Tensor::zeros(shape))
+        Self::Tensor::zeros_on(shape, self)
+    }
+
+    fn tensor(&self, data: &[f32], shape: Vec<usize>) -> Result<Self::Tensor, SmeltError> {
+        Self::Tensor::from_cpu(data.to_vec(), shape, self)
     }
 }
 
@@ -39,6 +57,15 @@ impl TensorAdd<Tensor> for Tensor {
     }
 }
 
+impl TensorSub<Tensor> for Tensor {
+    fn sub(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::sub(x, y)
+    }
+    fn broadcast_sub(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
+        ops::broadcast_sub(x, y)
+    }
+}
+
 impl TensorMul<Tensor> for Tensor {
     fn mul(x: &Self, y: &mut Self) -> Result<(), SmeltError> {
         ops::mul(x, y)
@@ -48,6 +75,13 @@ impl TensorMul<Tensor> for Tensor {
     }
 }
 
+impl TensorMulScalar<Tensor> for Tensor {
+    fn mul_scalar(x: &mut Self, factor: f32) -> Result<(), SmeltError> {
+        ops::mul_scalar(x, factor);
+        Ok(())
+    }
+}
+
 impl TensorNormalize<Tensor> for Tensor {
     fn normalize(x: &mut Self, epsilon: f32) -> Result<(), SmeltError> {
         ops::normalize(x, epsilon)
@@ -74,14 +108,154 @@ impl TensorSelect<Tensor> for Tensor {
 
 impl TensorGelu<Tensor> for Tensor {
     fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
-        ops::apply(x, ops::gelu);
+        ops::apply_gelu(x);
+        Ok(())
+    }
+}
+
+impl TensorActivation<Tensor> for Tensor {
+    fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+        ops::apply_activation(x, activation);
         Ok(())
     }
 }
 
 impl TensorTanh<Tensor> for Tensor {
     fn tanh(x: &mut Tensor) -> Result<(), SmeltError> {
-        ops::apply(x, ops::inline_tanh);
+        ops::apply_tanh(x);
+        Ok(())
+    }
+}
+
+impl TensorPad<Tensor> for Tensor {
+    fn pad(
+        x: &Tensor,
+        padding: &[(isize, isize)],
+        value: f32,
+        out: &mut Tensor,
+    ) -> Result<(), SmeltError> {
+        ops::pad(x, padding, value, out)
+    }
+}
+
+impl TensorReshape<Tensor> for Tensor {
+    fn reshape(x: Tensor, shape: Vec<usize>) -> Result<Tensor, SmeltError> {
+        x.reshape(shape)
+    }
+}
+
+impl TensorNarrow<Tensor> for Tensor {
+    fn narrow(x: &Tensor, dim: usize, start: usize, len: usize) -> Result<Tensor, SmeltError> {
+        x.narrow(dim, start, len)
+    }
+    fn narrow_put(
+        x: &mut Tensor,
+        dim: usize,
+        start: usize,
+        values: &Tensor,
+    ) -> Result<(), SmeltError> {
+        x.narrow_put(dim, start, values)
+    }
+}
+
+impl TensorReduce<Tensor> for Tensor {
+    fn sum(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+        ops::sum(x, dim, keepdim, out)
+    }
+    fn mean(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+        ops::mean(x, dim, keepdim, out)
+    }
+    fn max(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+        ops::max(x, dim, keepdim, out)
+    }
+    fn var(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+        ops::var(x, dim, keepdim, out)
+    }
+}
+
+impl TensorRotary<Tensor> for Tensor {
+    fn rotary(x: &mut Tensor, cos: &Tensor, sin: &Tensor, offset: usize) -> Result<(), SmeltError> {
+        ops::rotary(x, cos, sin, offset)
+    }
+}
+
+impl TensorFusedAttention<Tensor> for Tensor {
+    fn fused_attention(
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        mask: Option<&Tensor>,
+        slopes: Option<&Tensor>,
+        config: &FusedAttentionConfig,
+        out: &mut Tensor,
+    ) -> Result<(), SmeltError> {
+        ops::fused_attention(query, key, value, mask, slopes, config, out)
+    }
+}
+
+impl TensorTopK<Tensor> for Tensor {
+    fn top_k(x: &mut Tensor, k: usize) -> Result<(), SmeltError> {
+        ops::top_k(x, k)
+    }
+}
+
+impl TensorArgmax<Tensor> for Tensor {
+    fn argmax(x: &Tensor, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        x.argmax(dim)
+    }
+}
+
+impl TensorTopP<Tensor> for Tensor {
+    fn top_p(x: &mut Tensor, p: f32) -> Result<(), SmeltError> {
+        ops::top_p(x, p)
+    }
+}
+
+impl TensorClamp<Tensor> for Tensor {
+    fn clamp(x: &mut Tensor, min: f32, max: f32) -> Result<(), SmeltError> {
+        ops::clamp(x, min, max);
+        Ok(())
+    }
+}
+
+impl TensorUnary<Tensor> for Tensor {
+    fn exp(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_exp(x);
+        Ok(())
+    }
+
+    fn log(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_log(x);
+        Ok(())
+    }
+
+    fn sqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_sqrt(x);
+        Ok(())
+    }
+
+    fn rsqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_rsqrt(x);
+        Ok(())
+    }
+
+    fn sigmoid(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_sigmoid(x);
+        Ok(())
+    }
+
+    fn erf(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_erf(x);
+        Ok(())
+    }
+
+    fn neg(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_neg(x);
+        Ok(())
+    }
+
+    fn abs(x: &mut Tensor) -> Result<(), SmeltError> {
+        ops::apply_abs(x);
         Ok(())
     }
 }
@@ -92,4 +266,140 @@ impl TensorSoftmax<Tensor> for Tensor {
     }
 }
 
+impl TensorSoftmaxDim<Tensor> for Tensor {
+    fn softmax_dim(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+        ops::softmax_dim(x, dim)
+    }
+    fn softmax_t(x: &mut Tensor, dim: usize, temperature: f32) -> Result<(), SmeltError> {
+        ops::softmax_t(x, dim, temperature)
+    }
+}
+
+impl TensorConv1d<Tensor> for Tensor {
+    fn conv1d(
+        x: &Tensor,
+        weight: &Tensor,
+        bias: Option<&Tensor>,
+        config: &Conv1dConfig,
+        out: &mut Tensor,
+    ) -> Result<(), SmeltError> {
+        conv::conv1d(x, weight, bias, config, out)
+    }
+}
+
+impl TensorConv2d<Tensor> for Tensor {
+    fn conv2d(
+        x: &Tensor,
+        weight: &Tensor,
+        bias: Option<&Tensor>,
+        config: &Conv2dConfig,
+        out: &mut Tensor,
+    ) -> Result<(), SmeltError> {
+        conv::conv2d(x, weight, bias, config, out)
+    }
+}
+
+impl TensorCumsum<Tensor> for Tensor {
+    fn cumsum(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+        ops::cumsum(x, dim)
+    }
+}
+
+impl TensorMask<Tensor> for Tensor {
+    type Mask = Mask;
+    fn masked_fill(x: &mut Tensor, mask: &Mask, value: f32) -> Result<(), SmeltError> {
+        ops::masked_fill(x, mask, value)
+    }
+    fn masked_softmax(x: &mut Tensor, mask: &Mask) -> Result<(), SmeltError> {
+        ops::masked_softmax(x, mask)
+    }
+    fn causal_mask(length: usize, _device: &Device) -> Result<Mask, SmeltError> {
+        Ok(Mask::causal(length))
+    }
+}
+
+impl TensorTriangle<Tensor> for Tensor {
+    fn tril(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+        ops::tril(x, diagonal)
+    }
+    fn triu(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+        ops::triu(x, diagonal)
+    }
+}
+
+impl TensorPositionMask<Tensor> for Tensor {
+    fn position_mask(
+        x: &mut Tensor,
+        offset: usize,
+        length: usize,
+        causal: bool,
+        sliding_window: Option<usize>,
+    ) -> Result<(), SmeltError> {
+        ops::position_mask(x, offset, length, causal, sliding_window)
+    }
+}
+
+impl TensorCompare<Tensor> for Tensor {
+    fn gt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+        ops::gt(a, b, out)
+    }
+    fn lt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+        ops::lt(a, b, out)
+    }
+    fn eq(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+        ops::eq(a, b, out)
+    }
+    fn where_cond(mask: &Mask, a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+        ops::where_cond(mask, a, b, out)
+    }
+}
+
+impl TensorToDevice for Tensor {
+    fn to_device(&self, device: &Device) -> Result<Self, SmeltError> {
+        Tensor::from_cpu(self.data().to_vec(), self.shape.clone(), device)
+    }
+}
+
+impl TensorQuantize for Tensor {
+    fn quantize_dynamic(weight: &Self) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        Ok(Arc::new(Int8Tensor::quantize(weight)?))
+    }
+
+    fn observer() -> Arc<dyn RangeObserver<Self>> {
+        Arc::new(AbsMaxObserver::default())
+    }
+
+    fn quantize_static(
+        weight: &Self,
+        bias: &Self,
+        (input_range, output_range): (f32, f32),
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        Ok(Arc::new(Int8Linear::quantize(
+            weight,
+            bias,
+            input_range,
+            output_range,
+        )?))
+    }
+}
+
+impl TensorPrecision for Tensor {
+    fn cast_weight(
+        weight: &Self,
+        precision: Precision,
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError> {
+        match precision {
+            #[cfg(feature = "f16")]
+            Precision::F16 => Ok(Arc::new(F16Tensor::from_f32(weight))),
+            #[cfg(feature = "bf16")]
+            Precision::BF16 => Ok(Arc::new(BF16Tensor::from_f32(weight))),
+            #[allow(unreachable_patterns)]
+            _ => Err(SmeltError::Unsupported {
+                operation: "set_precision",
+                backend: "cpu",
+            }),
+        }
+    }
+}
+
 impl TensorOps<Tensor> for Tensor {}
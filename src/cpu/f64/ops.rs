@@ -0,0 +1,401 @@
+//! The double precision ops are plain loops, without the vectorized kernels or the
+//! approximations of [crate::cpu::f32]: they are meant to be a reference, not to be fast.
+use crate::cpu::f64::tensor::Tensor;
+use crate::cpu::threads;
+use crate::traits::Activation;
+use crate::SmeltError;
+use rayon::prelude::*;
+
+/// Operation for selecting entire rows within tensor `weights`. Each `id` is the index
+/// of the row.
+pub fn select(ids: &[usize], weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let sequence_length = ids.len();
+    let vocab_size = weights.shape()[0];
+    let hidden_dim = weights.shape()[1];
+    if out.shape() != [sequence_length, hidden_dim] {
+        return Err(SmeltError::DimensionMismatch {
+            expected: vec![sequence_length, hidden_dim],
+            got: out.shape().to_vec(),
+        });
+    }
+    for (i, id) in ids.iter().enumerate() {
+        let id = *id;
+        if id >= vocab_size {
+            return Err(SmeltError::OutOfVocabulary { vocab_size, id });
+        }
+        let weight_offset = id * hidden_dim;
+        let data_offset = i * hidden_dim;
+        out.data_mut()[data_offset..data_offset + hidden_dim]
+            .copy_from_slice(&weights.data()[weight_offset..weight_offset + hidden_dim]);
+    }
+    Ok(())
+}
+
+/// Copy tensor into another tensor
+pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    if weights.shape() != out.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: out.shape().to_vec(),
+            got: weights.shape().to_vec(),
+        });
+    }
+    out.data_mut().copy_from_slice(weights.data());
+    Ok(())
+}
+
+/// Regular matrix multiplication
+pub fn matmul(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<false>(a, b, out)
+}
+
+/// Matrix multiplication matmul(A, B.transposed())
+pub fn matmul_t(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<true>(a, b, out)
+}
+
+#[inline]
+fn g_matmul<const TRANSPOSE: bool>(
+    a: &Tensor,
+    b: &Tensor,
+    c: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    if b.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+    if c.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+
+    let m = a.shape()[dim - 2];
+    let k = a.shape()[dim - 1];
+
+    let mut expected_c = a.shape().to_vec();
+    let mut expected_b = a.shape().to_vec();
+
+    let n = if TRANSPOSE {
+        let n = b.shape()[dim - 2];
+        expected_b[dim - 2] = n;
+        expected_b[dim - 1] = k;
+        n
+    } else {
+        let n = b.shape()[dim - 1];
+        expected_b[dim - 2] = k;
+        expected_b[dim - 1] = n;
+        n
+    };
+
+    expected_c[dim - 2] = m;
+    expected_c[dim - 1] = n;
+
+    if expected_b != b.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected_b,
+            got: b.shape().to_vec(),
+        });
+    }
+
+    if expected_c != c.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected_c,
+            got: c.shape().to_vec(),
+        });
+    }
+
+    let b_skip: usize = n * k;
+    let c_skip: usize = m * n;
+    if c_skip == 0 {
+        return Ok(());
+    }
+
+    let (a, b) = (a.data(), b.data());
+    threads::pool()?.install(|| {
+        // Every row of c is summed in order by a single thread, the results do not
+        // depend on the number of threads.
+        c.data_mut()
+            .par_chunks_mut(n)
+            .enumerate()
+            .for_each(|(row, cp)| {
+                // The batches are contiguous, the rows of a and c match.
+                let step = row / m;
+                let ap = &a[row * k..(row + 1) * k];
+                let bp = &b[step * b_skip..(step + 1) * b_skip];
+                cp.iter_mut().enumerate().for_each(|(j, v)| {
+                    *v = if TRANSPOSE {
+                        ap.iter()
+                            .zip(&bp[j * k..(j + 1) * k])
+                            .map(|(x, y)| x * y)
+                            .sum()
+                    } else {
+                        ap.iter().enumerate().map(|(l, x)| x * bp[l * n + j]).sum()
+                    };
+                });
+            });
+    });
+    Ok(())
+}
+
+/// tensor elementwise addition. b += a.
+pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape() != b.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    a.data()
+        .iter()
+        .zip(b.data_mut().iter_mut())
+        .for_each(|(left, right)| *right += left);
+    Ok(())
+}
+
+/// broacasted tensor elementwise addition. b += a.
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if &b.shape()[1..] != a.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    let skip: usize = a.shape().iter().product();
+    b.data_mut().chunks_mut(skip).for_each(|chunk| {
+        a.data()
+            .iter()
+            .zip(chunk)
+            .for_each(|(left, right)| *right += left)
+    });
+    Ok(())
+}
+
+/// tensor elementwise multiplication. b *= a.
+pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape() != b.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    a.data()
+        .iter()
+        .zip(b.data_mut().iter_mut())
+        .for_each(|(left, right)| *right *= left);
+    Ok(())
+}
+
+/// broacasted tensor elementwise multiplication. b *= a.
+pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if &b.shape()[1..] != a.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    let skip: usize = a.shape().iter().product();
+    b.data_mut().chunks_mut(skip).for_each(|chunk| {
+        a.data()
+            .iter()
+            .zip(chunk)
+            .for_each(|(left, right)| *right *= left)
+    });
+    Ok(())
+}
+
+/// Basic operation for the layernorm.
+/// x = (x - x.mean()) / (x.var() + epsilon)
+pub fn normalize(x: &mut Tensor, epsilon: f64) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+    let size = x.shape()[dim - 1];
+    x.data_mut().chunks_mut(size).for_each(|row| {
+        let mean = row.iter().sum::<f64>() / size as f64;
+        row.iter_mut().for_each(|v| *v -= mean);
+        let var = row.iter().map(|v| v * v).sum::<f64>() / size as f64;
+        let stddev = (var + epsilon).sqrt();
+        row.iter_mut().for_each(|v| *v /= stddev);
+    });
+    Ok(())
+}
+
+#[inline]
+fn g_softmax<const CAUSAL: bool>(
+    x: &mut Tensor,
+    past_sequence_length: usize,
+) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+
+    let m = x.shape()[dim - 2];
+    let n = x.shape()[dim - 1];
+
+    x.data_mut()
+        .chunks_mut(n)
+        .enumerate()
+        .for_each(|(i, chunk)| {
+            let i = i % m;
+            let valid = if CAUSAL {
+                n.min(i + past_sequence_length + 1)
+            } else {
+                n
+            };
+            let (row, masked) = chunk.split_at_mut(valid);
+            let max = row.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            row.iter_mut().for_each(|v| *v = (*v - max).exp());
+            let sum: f64 = row.iter().sum();
+            row.iter_mut().for_each(|v| *v /= sum);
+            masked.iter_mut().for_each(|v| *v = 0.0);
+        });
+    Ok(())
+}
+
+/// Softmax on the last dimension for tensor `x`
+pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    g_softmax::<false>(x, 0)
+}
+
+/// Causal softmax on the last dimension for tensor `x`, see
+/// [crate::cpu::f32::causal_softmax].
+pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
+    g_softmax::<true>(x, past_sequence_length)
+}
+
+/// Argmax of the last dimension of tensor `x `.
+pub fn special_argmax(x: &Tensor) -> Result<usize, SmeltError> {
+    if x.shape().len() != 2 {
+        return Err(SmeltError::InvalidRank { expected_rank: 2 });
+    }
+    let n = x.shape()[0];
+    let m = x.shape()[1];
+
+    let mut max = f64::NEG_INFINITY;
+    let mut max_id = usize::MAX;
+    for (i, &v) in x.data().iter().skip((n - 1) * m).enumerate() {
+        if v > max {
+            max = v;
+            max_id = i;
+        }
+    }
+    Ok(max_id)
+}
+
+/// `gelu` operation, with the tanh approximation of the bert models
+/// <https://en.wikipedia.org/wiki/Activation_function#Comparison_of_activation_functions>
+#[inline]
+pub fn gelu(v: f64) -> f64 {
+    0.5 * v * (1.0 + ((2.0f64 / std::f64::consts::PI).sqrt() * v * (1.0 + 0.044715 * v * v)).tanh())
+}
+
+/// [gelu] of every item of the tensor.
+pub fn apply_gelu(x: &mut Tensor) {
+    apply(x, gelu);
+}
+
+/// The error function, from the series `2 / sqrt(pi) * exp(-x^2) * sum(2^n x^(2n+1) /
+/// (1 * 3 * ... * (2n+1)))` whose terms are all positive.
+pub fn erf(x: f64) -> f64 {
+    if x.abs() > 6.0 {
+        return x.signum();
+    }
+    let (mut term, mut sum, mut n) = (x, x, 0.0);
+    while term.abs() > sum.abs() * f64::EPSILON {
+        n += 1.0;
+        term *= 2.0 * x * x / (2.0 * n + 1.0);
+        sum += term;
+    }
+    std::f64::consts::FRAC_2_SQRT_PI * (-x * x).exp() * sum
+}
+
+/// `activation` of every item of the tensor, see [Activation].
+pub fn apply_activation(x: &mut Tensor, activation: Activation) {
+    match activation {
+        Activation::Gelu => apply(x, |v| {
+            0.5 * v * (1.0 + erf(v * std::f64::consts::FRAC_1_SQRT_2))
+        }),
+        Activation::GeluNew | Activation::GeluFast => apply_gelu(x),
+        Activation::Relu => apply(x, |v| v.max(0.0)),
+        Activation::Silu => apply(x, |v| v / (1.0 + (-v).exp())),
+        Activation::QuickGelu => apply(x, |v| v / (1.0 + (-1.702 * v).exp())),
+    }
+}
+
+/// tanh of every item of the tensor.
+pub fn apply_tanh(x: &mut Tensor) {
+    apply(x, f64::tanh);
+}
+
+/// x = x * factor
+pub fn mul_scalar(x: &mut Tensor, factor: f64) {
+    apply(x, |v| v * factor);
+}
+
+/// Applies `func` to every item of the tensor
+pub fn apply<F: Fn(f64) -> f64>(x: &mut Tensor, func: F) {
+    x.data_mut().iter_mut().for_each(|v| *v = func(*v));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{self as single, Tensor as F32Tensor};
+
+    #[test]
+    fn simple_matmul() {
+        let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        let b = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2]);
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(c.data(), [7.0, 10.0, 15.0, 22.0]);
+
+        let data: Vec<_> = (0..12).map(|i| i as f64).collect();
+        let a = Tensor::new(data, vec![2, 2, 3]).unwrap();
+        let data: Vec<_> = (0..12).map(|i| (i + 2) as f64).collect();
+        let b = Tensor::new(data, vec![2, 3, 2]).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2, 2]);
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(c.data(), [16., 19., 52., 64., 214., 235., 304., 334.]);
+    }
+
+    #[test]
+    fn simple_matmul_t() {
+        let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        // A.T
+        let b = Tensor::new(vec![1.0, 3.0, 2.0, 4.0], vec![2, 2]).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2]);
+        matmul_t(&a, &b, &mut c).unwrap();
+        assert_eq!(c.data(), [7.0, 10.0, 15.0, 22.0]);
+    }
+
+    #[test]
+    fn simple_softmax() {
+        let mut a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        softmax(&mut a).unwrap();
+        let e = std::f64::consts::E;
+        let expected = [1.0 / (1.0 + e), e / (1.0 + e)];
+        a.data().chunks(2).for_each(|row| {
+            assert!((row[0] - expected[0]).abs() < 1e-15);
+            assert!((row[1] - expected[1]).abs() < 1e-15);
+        });
+    }
+
+    #[test]
+    fn reference_gelu() {
+        // The f32 kernels are an approximation of the f64 ones.
+        let data: Vec<f32> = (-40..40).map(|i| i as f32 / 8.0).collect();
+        let mut x = F32Tensor::new(data.clone(), vec![80]).unwrap();
+        let mut reference = Tensor::from_f32(&x);
+        single::apply_gelu(&mut x);
+        apply_gelu(&mut reference);
+        assert!(reference.max_error(x.data()).unwrap() < 1e-5);
+
+        // Value obtained through python
+        assert!((erf(0.5) - 0.5204998778130465).abs() < 1e-15);
+        assert_eq!(erf(0.0), 0.0);
+        let mut x = F32Tensor::new(data, vec![80]).unwrap();
+        let mut reference = Tensor::from_f32(&x);
+        single::apply_activation(&mut x, Activation::Gelu);
+        apply_activation(&mut reference, Activation::Gelu);
+        assert!(reference.max_error(x.data()).unwrap() < 1e-5);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::cpu::f32::Tensor as F32Tensor;
+use crate::SmeltError;
+
+/// Tensor, can own, or borrow the underlying tensor.
+/// The double precision tensors are a reference for the other precisions, see
+/// [Tensor::max_error].
+pub type Tensor = crate::cpu::Tensor<f64>;
+
+/// The CPU device of the double precision tensors
+pub type Device = crate::cpu::Device<f64>;
+
+impl Tensor {
+    /// A slice to the underlying tensor data.
+    /// Exists uniquely for symetry with gpu Tensor.
+    pub fn cpu_data(&self) -> Result<&[f64], SmeltError> {
+        Ok(self.data.as_ref())
+    }
+
+    /// The double precision copy of an f32 tensor, which is exact.
+    pub fn from_f32(tensor: &F32Tensor) -> Self {
+        Self {
+            shape: tensor.shape().to_vec(),
+            device: Device::new(),
+            data: tensor.data().iter().map(|&v| v as f64).collect(),
+        }
+    }
+
+    /// The f32 copy of the tensor, rounding to the nearest.
+    pub fn to_f32(&self) -> F32Tensor {
+        let data: Vec<f32> = self.data.iter().map(|&v| v as f32).collect();
+        F32Tensor::new(data, self.shape.clone()).expect("The buffer matches the shape")
+    }
+
+    /// The largest absolute difference between this tensor and `data`, the output of
+    /// the same computation in a lower precision (converted to f32 for f16 or bf16).
+    /// ```
+    /// use smelte_rs::cpu::f64::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0], vec![2]).unwrap();
+    /// assert_eq!(tensor.max_error(&[1.0, 2.5]).unwrap(), 0.5);
+    /// ```
+    pub fn max_error(&self, data: &[f32]) -> Result<f64, SmeltError> {
+        if data.len() != self.data.len() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape: self.shape.clone(),
+            });
+        }
+        Ok(self
+            .data
+            .iter()
+            .zip(data)
+            .map(|(&x, &y)| (x - y as f64).abs())
+            .fold(0.0, f64::max))
+    }
+}
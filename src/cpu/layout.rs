@@ -0,0 +1,404 @@
+use crate::cpu::{Element, Tensor};
+use crate::shape;
+use crate::SmeltError;
+use std::borrow::Cow;
+use std::ops::RangeBounds;
+
+impl<T: Element> Tensor<T> {
+    /// The items `start..start + len` of the dimension `dim`, the other dimensions are
+    /// kept whole. Borrowed tensors (like the weights of a checkpoint) are not copied
+    /// when the slice is contiguous, that is when all the dimensions before `dim` are 1.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+    /// let narrowed = tensor.narrow(1, 1, 2).unwrap();
+    /// assert_eq!(narrowed.shape(), [2, 2]);
+    /// assert_eq!(narrowed.data(), [2.0, 3.0, 5.0, 6.0]);
+    /// ```
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        let (outer, size, inner) = shape::narrow(&self.shape, dim, start, len)?;
+        let mut shape = self.shape.clone();
+        shape[dim] = len;
+        let data = match &self.data {
+            Cow::Borrowed(data) if outer == 1 => {
+                Cow::Borrowed(&data[start * inner..(start + len) * inner])
+            }
+            data => Cow::Owned(
+                data.chunks_exact(size * inner)
+                    .flat_map(|chunk| &chunk[start * inner..(start + len) * inner])
+                    .copied()
+                    .collect(),
+            ),
+        };
+        Ok(Self {
+            shape,
+            device: self.device,
+            data,
+        })
+    }
+
+    /// [Tensor::narrow] with a range, like `tensor.slice(0, ..1)` for the CLS token.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![3, 2]).unwrap();
+    /// assert_eq!(tensor.slice(0, 1..).unwrap().data(), [3.0, 4.0, 5.0, 6.0]);
+    /// ```
+    pub fn slice(&self, dim: usize, range: impl RangeBounds<usize>) -> Result<Self, SmeltError> {
+        let size = self.shape.get(dim).copied().unwrap_or(0);
+        let (start, len) = shape::range(range, size);
+        self.narrow(dim, start, len)
+    }
+
+    /// The parts of `sizes` items of the dimension `dim`, see [Tensor::narrow]. The
+    /// sizes must add up to the size of the dimension, like `[768, 768, 768]` to split
+    /// the fused query, key and value of GPT-2.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+    /// let parts = tensor.split(1, &[1, 2]).unwrap();
+    /// assert_eq!(parts[0].data(), [1.0, 4.0]);
+    /// assert_eq!(parts[1].data(), [2.0, 3.0, 5.0, 6.0]);
+    /// ```
+    pub fn split(&self, dim: usize, sizes: &[usize]) -> Result<Vec<Self>, SmeltError> {
+        shape::split(&self.shape, dim, sizes)?
+            .into_iter()
+            .map(|(start, len)| self.narrow(dim, start, len))
+            .collect()
+    }
+
+    /// [Tensor::split] in `chunks` parts of the same size, except the last one which can
+    /// be smaller.
+    pub fn chunk(&self, dim: usize, chunks: usize) -> Result<Vec<Self>, SmeltError> {
+        let size = self.shape.get(dim).copied().unwrap_or(0);
+        self.split(dim, &shape::chunk(size, chunks)?)
+    }
+
+    /// The tensor with its dimensions reordered, the dimension `i` of the result being
+    /// the dimension `dims[i]` of `self`. The data is copied.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// // (batch_size, sequence_length, num_heads, head_dim)
+    /// let data: Vec<f32> = (0..24).map(|i| i as f32).collect();
+    /// let tensor = Tensor::new(data, vec![1, 2, 3, 4]).unwrap();
+    /// // (batch_size, num_heads, sequence_length, head_dim)
+    /// let heads = tensor.permute(&[0, 2, 1, 3]).unwrap();
+    /// assert_eq!(heads.shape(), [1, 3, 2, 4]);
+    /// assert_eq!(&heads.data()[..8], [0.0, 1.0, 2.0, 3.0, 12.0, 13.0, 14.0, 15.0]);
+    /// ```
+    pub fn permute(&self, dims: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, _) = shape::permute(&self.shape, dims)?;
+        let mut data = vec![T::default(); self.data.len()];
+        permute_data(self.data(), &mut data, &self.shape, dims);
+        Ok(Self {
+            shape,
+            device: self.device,
+            data: Cow::Owned(data),
+        })
+    }
+
+    /// [Tensor::permute] swapping the dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Self, SmeltError> {
+        self.permute(&shape::transpose(self.shape.len(), dim0, dim1))
+    }
+
+    /// The same data with the shape `shape`, which must hold as many items. Nothing is
+    /// copied, the tensor is contiguous.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+    /// let tensor = tensor.reshape(vec![3, 2]).unwrap();
+    /// assert_eq!(tensor.shape(), [3, 2]);
+    /// assert!(tensor.reshape(vec![4, 2]).is_err());
+    /// ```
+    pub fn reshape(mut self, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        self.shape = shape::reshape(&self.shape, shape)?;
+        Ok(self)
+    }
+
+    /// The items `indices` of the dimension `dim`, in that order and possibly repeated,
+    /// like the rows of an embedding or the beams kept by a search.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![3, 2]).unwrap();
+    /// let rows = tensor.index_select(0, &[2, 0, 2]).unwrap();
+    /// assert_eq!(rows.shape(), [3, 2]);
+    /// assert_eq!(rows.data(), [5.0, 6.0, 1.0, 2.0, 5.0, 6.0]);
+    /// ```
+    pub fn index_select(&self, dim: usize, indices: &[usize]) -> Result<Self, SmeltError> {
+        let (_, size, inner) = shape::index_select(&self.shape, dim, indices)?;
+        let mut shape = self.shape.clone();
+        shape[dim] = indices.len();
+        let mut data = Vec::with_capacity(shape.iter().product());
+        if size * inner > 0 {
+            for chunk in self.data.chunks_exact(size * inner) {
+                for &index in indices {
+                    data.extend_from_slice(&chunk[index * inner..(index + 1) * inner]);
+                }
+            }
+        }
+        Ok(Self {
+            shape,
+            device: self.device,
+            data: Cow::Owned(data),
+        })
+    }
+
+    /// Writes the items of `values` into the items `indices` of the dimension `dim`, the
+    /// inverse of [Tensor::index_select]. `values` has the shape of `self` except for
+    /// `dim`, of size `indices.len()`. With repeated indices the last write wins.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// // A cache of 3 positions, the new key goes to the position 1.
+    /// let mut cache = Tensor::zeros(vec![3, 2]);
+    /// let key = Tensor::new(vec![1.0, 2.0], vec![1, 2]).unwrap();
+    /// cache.index_put(0, &[1], &key).unwrap();
+    /// assert_eq!(cache.data(), [0.0, 0.0, 1.0, 2.0, 0.0, 0.0]);
+    /// ```
+    pub fn index_put(
+        &mut self,
+        dim: usize,
+        indices: &[usize],
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        let (_, size, inner) = shape::index_put(&self.shape, dim, indices, &values.shape)?;
+        let len = indices.len();
+        if len * inner == 0 {
+            return Ok(());
+        }
+        self.data_mut()
+            .chunks_exact_mut(size * inner)
+            .zip(values.data().chunks_exact(len * inner))
+            .for_each(|(chunk, values)| {
+                for (&index, values) in indices.iter().zip(values.chunks_exact(inner)) {
+                    chunk[index * inner..(index + 1) * inner].copy_from_slice(values);
+                }
+            });
+        Ok(())
+    }
+
+    /// Writes the items of `values` into the items `start..start + values.shape()[dim]`
+    /// of the dimension `dim`, the inverse of [Tensor::narrow], like the new tokens of a
+    /// preallocated cache.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let mut cache = Tensor::zeros(vec![2, 3]);
+    /// let keys = Tensor::new(vec![1.0, 2.0], vec![2, 1]).unwrap();
+    /// cache.narrow_put(1, 2, &keys).unwrap();
+    /// assert_eq!(cache.data(), [0.0, 0.0, 1.0, 0.0, 0.0, 2.0]);
+    /// ```
+    pub fn narrow_put(
+        &mut self,
+        dim: usize,
+        start: usize,
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        let (_, size, inner) = shape::narrow_put(&self.shape, dim, start, &values.shape)?;
+        let len = values.shape[dim];
+        if len * inner == 0 {
+            return Ok(());
+        }
+        self.data_mut()
+            .chunks_exact_mut(size * inner)
+            .zip(values.data().chunks_exact(len * inner))
+            .for_each(|(chunk, values)| {
+                chunk[start * inner..(start + len) * inner].copy_from_slice(values)
+            });
+        Ok(())
+    }
+
+    /// The concatenation of `tensors` along `dim`, like the past and present keys and
+    /// values of a cache. They must have the same shape on the other dimensions.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let a = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let b = Tensor::new(vec![5.0, 6.0], vec![2, 1]).unwrap();
+    /// let c = Tensor::cat(&[&a, &b], 1).unwrap();
+    /// assert_eq!(c.shape(), [2, 3]);
+    /// assert_eq!(c.data(), [1.0, 2.0, 5.0, 3.0, 4.0, 6.0]);
+    /// ```
+    pub fn cat(tensors: &[&Self], dim: usize) -> Result<Self, SmeltError> {
+        let shapes: Vec<&[usize]> = tensors.iter().map(|t| t.shape()).collect();
+        let shape = shape::cat(&shapes, dim)?;
+        let outer: usize = shape[..dim].iter().product();
+        let inner: usize = shape[dim + 1..].iter().product();
+        let mut data = Vec::with_capacity(shape.iter().product());
+        for o in 0..outer {
+            for tensor in tensors {
+                let chunk = tensor.shape[dim] * inner;
+                data.extend_from_slice(&tensor.data()[o * chunk..(o + 1) * chunk]);
+            }
+        }
+        Ok(Self {
+            shape,
+            device: tensors[0].device,
+            data: Cow::Owned(data),
+        })
+    }
+}
+
+/// Writes the items of `src` of shape `shape` into `dst` in the order of the dimensions
+/// `dims`, see [Tensor::permute]. `dims` must be a permutation of the dimensions.
+pub(crate) fn permute_data<E: Copy>(src: &[E], dst: &mut [E], shape: &[usize], dims: &[usize]) {
+    let strides = shape::strides(shape);
+    let shape: Vec<usize> = dims.iter().map(|&d| shape[d]).collect();
+    let strides: Vec<usize> = dims.iter().map(|&d| strides[d]).collect();
+    copy_strided(src, dst, &shape, &strides, 0);
+}
+
+/// Writes the items of `src` read from `offset` with the shape `shape` and the strides
+/// `strides` into the contiguous `dst`.
+pub(crate) fn copy_strided<E: Copy>(
+    src: &[E],
+    dst: &mut [E],
+    shape: &[usize],
+    strides: &[usize],
+    mut offset: usize,
+) {
+    // Walks the indices of `dst`, keeping the offset in `src` up to date.
+    let mut index = vec![0; shape.len()];
+    for item in dst.iter_mut() {
+        *item = src[offset];
+        for d in (0..shape.len()).rev() {
+            index[d] += 1;
+            offset += strides[d];
+            if index[d] < shape[d] {
+                break;
+            }
+            offset -= strides[d] * shape[d];
+            index[d] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_narrow() {
+        static DATA: [f32; 12] = [0., 1., 2., 3., 4., 5., 6., 7., 8., 9., 10., 11.];
+        let tensor = Tensor::borrowed(&DATA, vec![1, 3, 4]).unwrap();
+        let narrowed = tensor.narrow(1, 1, 2).unwrap();
+        assert!(matches!(narrowed.data, Cow::Borrowed(_)));
+        assert_eq!(narrowed.data(), [4., 5., 6., 7., 8., 9., 10., 11.]);
+
+        let tensor = Tensor::borrowed(&DATA, vec![3, 4]).unwrap();
+        let narrowed = tensor.narrow(1, 3, 1).unwrap();
+        assert_eq!(narrowed.shape(), [3, 1]);
+        assert_eq!(narrowed.data(), [3., 7., 11.]);
+
+        assert!(matches!(
+            tensor.slice(0, 2..4),
+            Err(SmeltError::OutOfRange {
+                dim: 0,
+                end: 4,
+                size: 3
+            })
+        ));
+        assert!(tensor.narrow(2, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_cat() {
+        let a = Tensor::new(vec![0, 1, 2, 3], vec![2, 2, 1]).unwrap();
+        let b = Tensor::new(vec![4, 5], vec![1, 2, 1]).unwrap();
+        let c = Tensor::<i64>::cat(&[&a, &b, &a], 0).unwrap();
+        assert_eq!(c.shape(), [5, 2, 1]);
+        assert_eq!(c.data(), [0, 1, 2, 3, 4, 5, 0, 1, 2, 3]);
+        assert_eq!(c.narrow(0, 2, 1).unwrap().data(), b.data());
+
+        assert!(matches!(
+            Tensor::cat(&[&a, &b], 1),
+            Err(SmeltError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_split() {
+        let tensor = Tensor::new((0..10).collect::<Vec<i64>>(), vec![5, 2]).unwrap();
+        let parts = tensor.chunk(0, 2).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].shape(), [3, 2]);
+        assert_eq!(parts[1].data(), [6, 7, 8, 9]);
+        let parts: Vec<_> = tensor.split(1, &[1, 1]).unwrap();
+        assert_eq!(parts[1].data(), [1, 3, 5, 7, 9]);
+        assert!(tensor.split(1, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_permute() {
+        let tensor = Tensor::new((0..6).collect::<Vec<i64>>(), vec![2, 3]).unwrap();
+        let transposed = tensor.transpose(0, 1).unwrap();
+        assert_eq!(transposed.shape(), [3, 2]);
+        assert_eq!(transposed.data(), [0, 3, 1, 4, 2, 5]);
+        assert_eq!(transposed.transpose(1, 0).unwrap().data(), tensor.data());
+
+        let tensor = Tensor::new((0..24).collect::<Vec<i64>>(), vec![2, 3, 4]).unwrap();
+        let permuted = tensor.permute(&[2, 0, 1]).unwrap();
+        assert_eq!(permuted.shape(), [4, 2, 3]);
+        assert_eq!(&permuted.data()[..6], [0, 4, 8, 12, 16, 20]);
+        assert!(tensor.permute(&[0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_reshape() {
+        static DATA: [u8; 6] = [0, 1, 2, 3, 4, 5];
+        let tensor = Tensor::borrowed(&DATA, vec![2, 3]).unwrap();
+        let tensor = tensor.reshape(vec![1, 6, 1]).unwrap();
+        assert_eq!(tensor.shape(), [1, 6, 1]);
+        assert!(matches!(tensor.data, Cow::Borrowed(_)));
+        assert!(matches!(
+            tensor.reshape(vec![5]),
+            Err(SmeltError::InvalidBuffer { buffer_size: 6, .. })
+        ));
+    }
+
+    #[test]
+    fn test_index_select() {
+        let tensor = Tensor::new((0..12).collect::<Vec<i64>>(), vec![2, 3, 2]).unwrap();
+        let selected = tensor.index_select(1, &[1, 1]).unwrap();
+        assert_eq!(selected.shape(), [2, 2, 2]);
+        assert_eq!(selected.data(), [2, 3, 2, 3, 8, 9, 8, 9]);
+        let selected = tensor.index_select(2, &[1]).unwrap();
+        assert_eq!(selected.data(), [1, 3, 5, 7, 9, 11]);
+        assert_eq!(tensor.index_select(0, &[]).unwrap().shape(), [0, 3, 2]);
+        assert!(matches!(
+            tensor.index_select(1, &[3]),
+            Err(SmeltError::OutOfRange {
+                dim: 1,
+                end: 4,
+                size: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_index_put() {
+        let mut tensor = Tensor::<i64>::zeros(vec![2, 3, 2]);
+        let values = Tensor::new(vec![1, 2, 3, 4], vec![2, 1, 2]).unwrap();
+        tensor.index_put(1, &[2], &values).unwrap();
+        assert_eq!(tensor.data(), [0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 3, 4]);
+        assert_eq!(tensor.index_select(1, &[2]).unwrap().data(), values.data());
+
+        // Reorders the beams.
+        let mut beams = Tensor::new(vec![1i64, 2, 3], vec![3]).unwrap();
+        let selected = beams.index_select(0, &[2, 2, 0]).unwrap();
+        beams.index_put(0, &[0, 1, 2], &selected).unwrap();
+        assert_eq!(beams.data(), [3, 3, 1]);
+
+        assert!(matches!(
+            tensor.index_put(1, &[0, 1], &values),
+            Err(SmeltError::DimensionMismatch { .. })
+        ));
+        assert!(tensor.index_put(1, &[3], &values).is_err());
+    }
+}
@@ -1,5 +1,26 @@
+/// The bfloat16 float, the format of most recent checkpoints
+#[cfg(feature = "bf16")]
+pub mod bf16;
 /// The half precision float
-#[cfg(features = "f16")]
+#[cfg(feature = "f16")]
 pub mod f16;
 /// The regular float
 pub mod f32;
+/// The double precision float, a reference to measure the error of the other precisions
+pub mod f64;
+mod layout;
+/// NUMA placement of the tensors and of the threads
+mod numa;
+mod search;
+/// The Tensor struct, generic over the element type
+mod tensor;
+/// The thread pool of the cpu kernels
+mod threads;
+/// Strided views of the tensors
+mod view;
+
+pub(crate) use layout::permute_data;
+pub use numa::{num_nodes, NumaPolicy};
+pub use tensor::{Device, Element, Mask, Tensor};
+pub use threads::{num_threads, set_num_threads, NUM_THREADS_ENV};
+pub use view::View;
@@ -0,0 +1,181 @@
+use crate::SmeltError;
+
+/// Where the memory of the tensors created by a [crate::cpu::f32::Device] lives, on
+/// machines with several NUMA nodes (multi-socket servers).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// The operating system default, memory goes to the node of the thread which first
+    /// writes it.
+    #[default]
+    Local,
+    /// Memory is spread page by page across all the nodes, and the threads are not pinned.
+    Interleave,
+    /// Memory is allocated on this node, and the threads of the cpu kernels only run on
+    /// its cpus.
+    Bind(usize),
+}
+
+/// The number of NUMA nodes of the machine, 1 when it is not NUMA (or not Linux).
+pub fn num_nodes() -> usize {
+    std::fs::read_to_string("/sys/devices/system/node/possible")
+        .ok()
+        .and_then(|nodes| parse_cpulist(&nodes).ok())
+        .and_then(|nodes| nodes.last().map(|last| last + 1))
+        .unwrap_or(1)
+}
+
+/// Parses the lists of `/sys`, like `0-3,8,10-11`.
+fn parse_cpulist(list: &str) -> Result<Vec<usize>, SmeltError> {
+    let invalid = || SmeltError::Numa(format!("Invalid cpu list {list:?}"));
+    let mut cpus = vec![];
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let start: usize = start.parse().map_err(|_| invalid())?;
+                let end: usize = end.parse().map_err(|_| invalid())?;
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(range.parse().map_err(|_| invalid())?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// The cpus of `node`.
+pub(crate) fn node_cpus(node: usize) -> Result<Vec<usize>, SmeltError> {
+    let path = format!("/sys/devices/system/node/node{node}/cpulist");
+    let list = std::fs::read_to_string(&path)
+        .map_err(|e| SmeltError::Numa(format!("Could not read {path}: {e}")))?;
+    parse_cpulist(&list)
+}
+
+/// Restricts the current thread to `cpus`.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(cpus: &[usize]) -> Result<(), SmeltError> {
+    // SAFETY: `cpu_set_t` is a plain bitmask.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    libc::CPU_ZERO(&mut set);
+    for cpu in cpus {
+        libc::CPU_SET(*cpu, &mut set);
+    }
+    // SAFETY: `set` is a valid mask, `0` is the current thread.
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } != 0 {
+        let error = std::io::Error::last_os_error();
+        return Err(SmeltError::Numa(format!(
+            "Could not pin the thread: {error}"
+        )));
+    }
+    Ok(())
+}
+
+/// Restricts the current thread to `cpus`.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_cpus: &[usize]) -> Result<(), SmeltError> {
+    Err(SmeltError::Numa(
+        "Pinning threads requires Linux".to_string(),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+const MPOL_BIND: libc::c_long = 2;
+#[cfg(target_os = "linux")]
+const MPOL_INTERLEAVE: libc::c_long = 3;
+#[cfg(target_os = "linux")]
+const MPOL_MF_MOVE: libc::c_long = 1 << 1;
+
+/// Applies `policy` to the pages holding `data`. Pages which are not touched yet are
+/// allocated on the right node(s) when first written, the others are moved.
+#[cfg(target_os = "linux")]
+fn place<T>(data: &mut [std::mem::MaybeUninit<T>], policy: NumaPolicy) -> Result<(), SmeltError> {
+    let (mode, nodes) = match policy {
+        NumaPolicy::Local => return Ok(()),
+        NumaPolicy::Interleave => (MPOL_INTERLEAVE, (0..num_nodes()).collect()),
+        NumaPolicy::Bind(node) => (MPOL_BIND, vec![node]),
+    };
+    if data.is_empty() {
+        return Ok(());
+    }
+    let bits = 8 * std::mem::size_of::<libc::c_ulong>();
+    let mut mask: Vec<libc::c_ulong> = vec![0; nodes.iter().max().unwrap() / bits + 1];
+    for node in nodes {
+        mask[node / bits] |= 1 << (node % bits);
+    }
+
+    // SAFETY: Always safe to call.
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let start = data.as_ptr() as usize;
+    let aligned = start - start % page;
+    let len = start + std::mem::size_of_val(data) - aligned;
+    // SAFETY: The range covers memory owned by the process, `mbind` only changes where
+    // its pages live. The kernel reads one bit less than `maxnode`.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            aligned,
+            len,
+            mode,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+            MPOL_MF_MOVE,
+        )
+    };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        return Err(SmeltError::Numa(format!(
+            "Could not apply {policy:?}: {error}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn place<T>(_data: &mut [std::mem::MaybeUninit<T>], policy: NumaPolicy) -> Result<(), SmeltError> {
+    match policy {
+        NumaPolicy::Local => Ok(()),
+        _ => Err(SmeltError::Numa(
+            "NUMA placement requires Linux".to_string(),
+        )),
+    }
+}
+
+/// A buffer of `len` values, placed according to `policy` and filled by `fill`.
+/// The placement happens before the first write, so nothing needs to move.
+pub(crate) fn alloc<T>(
+    len: usize,
+    policy: NumaPolicy,
+    fill: impl FnOnce(&mut Vec<T>),
+) -> Result<Vec<T>, SmeltError> {
+    let mut data = Vec::with_capacity(len);
+    place(&mut data.spare_capacity_mut()[..len], policy)?;
+    fill(&mut data);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_parse_cpulist() {
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11\n").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpulist("0").unwrap(), [0]);
+        assert!(parse_cpulist("0-a").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn simple_alloc() {
+        for policy in [
+            NumaPolicy::Local,
+            NumaPolicy::Interleave,
+            NumaPolicy::Bind(0),
+        ] {
+            let data = alloc(10_000, policy, |data| data.resize(10_000, 1.0)).unwrap();
+            assert_eq!(data, vec![1.0; 10_000]);
+        }
+        assert!(node_cpus(0).unwrap().len() > 0);
+    }
+}
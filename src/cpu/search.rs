@@ -0,0 +1,156 @@
+use crate::cpu::{Element, Tensor};
+use crate::shape;
+use crate::SmeltError;
+use std::borrow::Cow;
+
+impl<T: Element> Tensor<T> {
+    /// The index of the largest item along the dimension `dim`, for every other index.
+    /// The indices have the shape of `self` without `dim`, the first maximum wins.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let logits = Tensor::new(vec![0.1, 2.0, -1.0, 3.0, 0.5, 0.0], vec![2, 3]).unwrap();
+    /// assert_eq!(logits.argmax(1).unwrap(), [1, 0]);
+    /// assert_eq!(logits.argmin(1).unwrap(), [2, 2]);
+    /// ```
+    pub fn argmax(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        self.arg_search(dim, |v, best| v > best)
+    }
+
+    /// The index of the smallest item along the dimension `dim`, see [Tensor::argmax].
+    pub fn argmin(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        self.arg_search(dim, |v, best| v < best)
+    }
+
+    fn arg_search(
+        &self,
+        dim: usize,
+        better: impl Fn(T, T) -> bool,
+    ) -> Result<Vec<usize>, SmeltError> {
+        let (outer, size, inner) = shape::narrow(&self.shape, dim, 0, 1)?;
+        let data = self.data();
+        let mut indices = vec![0; outer * inner];
+        for o in 0..outer {
+            for i in 0..inner {
+                let at = |j: usize| data[(o * size + j) * inner + i];
+                indices[o * inner + i] =
+                    (1..size).fold(0, |best, j| if better(at(j), at(best)) { j } else { best });
+            }
+        }
+        Ok(indices)
+    }
+
+    /// The `k` largest items along the dimension `dim` in decreasing order, and their
+    /// indices. Both have the shape of `self` with `k` items in `dim`.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let probs = Tensor::new(vec![0.1, 0.6, 0.05, 0.25], vec![1, 4]).unwrap();
+    /// let (values, indices) = probs.topk(2, 1).unwrap();
+    /// assert_eq!(values.data(), [0.6, 0.25]);
+    /// assert_eq!(indices, [1, 3]);
+    /// ```
+    pub fn topk(&self, k: usize, dim: usize) -> Result<(Self, Vec<usize>), SmeltError> {
+        self.sorted(k, dim, true)
+    }
+
+    /// The items sorted along the dimension `dim`, and their indices in `self`. The sort
+    /// is stable, equal items keep their order.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let probs = Tensor::new(vec![0.1, 0.6, 0.05, 0.25], vec![1, 4]).unwrap();
+    /// let (values, indices) = probs.sort(1, true).unwrap();
+    /// assert_eq!(values.data(), [0.6, 0.25, 0.1, 0.05]);
+    /// assert_eq!(indices, [1, 3, 0, 2]);
+    /// ```
+    pub fn sort(&self, dim: usize, descending: bool) -> Result<(Self, Vec<usize>), SmeltError> {
+        let size = self.shape.get(dim).copied().unwrap_or(0);
+        self.sorted(size, dim, descending)
+    }
+
+    /// The first `k` items of [Tensor::sort].
+    fn sorted(
+        &self,
+        k: usize,
+        dim: usize,
+        descending: bool,
+    ) -> Result<(Self, Vec<usize>), SmeltError> {
+        let (outer, size, inner) = shape::narrow(&self.shape, dim, 0, k)?;
+        let data = self.data();
+        let mut values = vec![T::default(); outer * k * inner];
+        let mut indices = vec![0; outer * k * inner];
+        let mut order: Vec<usize> = Vec::with_capacity(size);
+        for o in 0..outer {
+            for i in 0..inner {
+                let at = |j: usize| data[(o * size + j) * inner + i];
+                order.clear();
+                order.extend(0..size);
+                // Stable, the first of equal items comes first.
+                order.sort_by(|&a, &b| {
+                    let (a, b) = if descending { (b, a) } else { (a, b) };
+                    at(a)
+                        .partial_cmp(&at(b))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                for (j, &index) in order[..k].iter().enumerate() {
+                    let out = (o * k + j) * inner + i;
+                    values[out] = at(index);
+                    indices[out] = index;
+                }
+            }
+        }
+        let mut shape = self.shape.clone();
+        shape[dim] = k;
+        let values = Self {
+            shape,
+            device: self.device,
+            data: Cow::Owned(values),
+        };
+        Ok((values, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argmax() {
+        let tensor = Tensor::new(vec![1i64, 5, 3, 5, 0, 2], vec![3, 2]).unwrap();
+        assert_eq!(tensor.argmax(0).unwrap(), [1, 0]);
+        assert_eq!(tensor.argmax(1).unwrap(), [1, 1, 1]);
+        assert_eq!(tensor.argmin(0).unwrap(), [2, 2]);
+        assert!(tensor.argmax(2).is_err());
+        let empty = Tensor::<u8>::zeros(vec![2, 0]);
+        assert!(matches!(
+            empty.argmax(1),
+            Err(SmeltError::OutOfRange { size: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_topk() {
+        let tensor = Tensor::new(vec![1i64, 5, 3, 5, 0, 2], vec![3, 2]).unwrap();
+        let (values, indices) = tensor.topk(2, 0).unwrap();
+        assert_eq!(values.shape(), [2, 2]);
+        assert_eq!(values.data(), [3, 5, 1, 5]);
+        assert_eq!(indices, [1, 0, 0, 1]);
+        let (values, indices) = tensor.topk(1, 1).unwrap();
+        assert_eq!(values.data(), [5, 5, 2]);
+        assert_eq!(indices, tensor.argmax(1).unwrap());
+        assert!(tensor.topk(3, 1).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let tensor = Tensor::new(vec![1i64, 5, 3, 5, 0, 2], vec![3, 2]).unwrap();
+        let (values, indices) = tensor.sort(0, false).unwrap();
+        assert_eq!(values.data(), [0, 2, 1, 5, 3, 5]);
+        assert_eq!(indices, [2, 2, 0, 0, 1, 1]);
+        let (values, indices) = tensor.sort(0, true).unwrap();
+        assert_eq!(values.data(), [3, 5, 1, 5, 0, 2]);
+        assert_eq!(indices, [1, 0, 0, 1, 2, 2]);
+        assert!(tensor.sort(2, true).is_err());
+    }
+}
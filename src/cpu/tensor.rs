@@ -0,0 +1,265 @@
+use crate::cpu::{numa, threads, NumaPolicy};
+use crate::SmeltError;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+/// The element types of the cpu tensors. The kernels are specialized per type, in the
+/// modules of each precision ([crate::cpu::f32], [crate::cpu::f16]...).
+pub trait Element: Copy + Default + PartialOrd + std::fmt::Debug + Send + Sync + 'static {
+    /// The name of the type, like the dtypes of safetensors
+    const NAME: &'static str;
+}
+
+macro_rules! element {
+    ($($ty:ty => $name:literal),*) => {
+        $(impl Element for $ty {
+            const NAME: &'static str = $name;
+        })*
+    };
+}
+
+element!(f32 => "F32", f64 => "F64", i8 => "I8", u8 => "U8", i64 => "I64");
+
+#[cfg(feature = "f16")]
+element!(half::f16 => "F16");
+
+#[cfg(feature = "bf16")]
+element!(half::bf16 => "BF16");
+
+/// Tensor, can own, or borrow the underlying tensor
+#[derive(Clone)]
+pub struct Tensor<T: Element> {
+    pub(super) shape: Vec<usize>,
+    pub(super) device: Device<T>,
+    pub(super) data: Cow<'static, [T]>,
+}
+
+/// A boolean mask, one byte per item, `0` is false. See
+/// [masked_fill](crate::cpu::f32::masked_fill) and
+/// [masked_softmax](crate::cpu::f32::masked_softmax).
+pub type Mask = Tensor<u8>;
+
+/// The CPU device, creating tensors of `T`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Device<T: Element> {
+    numa: NumaPolicy,
+    dtype: PhantomData<T>,
+}
+
+impl<T: Element> Eq for Device<T> {}
+
+impl<T: Element> Device<T> {
+    /// The default device, the memory of its tensors follows the operating system policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A device placing the memory of its tensors according to `policy`.
+    /// With [NumaPolicy::Bind] the threads of the cpu kernels are pinned to the cpus of
+    /// the node too. They are shared by all the devices, the last one created wins.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Device, Tensor};
+    /// use smelte_rs::cpu::NumaPolicy;
+    ///
+    /// let device = Device::with_numa(NumaPolicy::Interleave).unwrap();
+    /// let tensor = Tensor::zeros_on(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn with_numa(policy: NumaPolicy) -> Result<Self, SmeltError> {
+        let node = match policy {
+            NumaPolicy::Bind(node) => {
+                let num_nodes = numa::num_nodes();
+                if node >= num_nodes {
+                    return Err(SmeltError::Numa(format!(
+                        "Node {node} does not exist, the machine has {num_nodes} node(s)"
+                    )));
+                }
+                Some(node)
+            }
+            NumaPolicy::Local | NumaPolicy::Interleave => None,
+        };
+        threads::pin_to_node(node)?;
+        Ok(Self {
+            numa: policy,
+            dtype: PhantomData,
+        })
+    }
+
+    /// The placement of the tensors of this device
+    pub fn numa(&self) -> NumaPolicy {
+        self.numa
+    }
+}
+
+impl<T: Element> Tensor<T> {
+    /// The shape of the tensor
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::zeros(vec![2, 2]);
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// A slice to the underlying tensor data
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::zeros(vec![2, 2]);
+    /// assert_eq!(tensor.data(), vec![0.0; 4]);
+    /// ```
+    pub fn data(&self) -> &[T] {
+        self.data.as_ref()
+    }
+
+    /// A mutable slice to the underlying tensor data
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let mut tensor = Tensor::zeros(vec![2, 2]);
+    /// tensor.data_mut().iter_mut().for_each(|v| *v += 1.0);
+    /// assert_eq!(tensor.data(), vec![1.0; 4]);
+    /// ```
+    pub fn data_mut(&mut self) -> &mut [T] {
+        self.data.to_mut()
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```
+    /// use smelte_rs::cpu::Tensor;
+    ///
+    /// let tensor = Tensor::<i64>::zeros(vec![2, 2]);
+    /// assert_eq!(tensor.data(), [0; 4]);
+    /// ```
+    pub fn zeros(shape: Vec<usize>) -> Self {
+        let nelement: usize = shape.iter().product();
+        let data = Cow::Owned(vec![T::default(); nelement]);
+        Self {
+            shape,
+            device: Device::default(),
+            data,
+        }
+    }
+
+    /// Creates a new nulled tensor with given shape, placed in memory according to the
+    /// [NumaPolicy] of `device`.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Tensor, Device};
+    ///
+    /// let tensor = Tensor::zeros_on(vec![2, 2], &Device::new()).unwrap();
+    /// ```
+    pub fn zeros_on(shape: Vec<usize>, device: &Device<T>) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = match device.numa {
+            NumaPolicy::Local => vec![T::default(); nelement],
+            policy => numa::alloc(nelement, policy, |data| data.resize(nelement, T::default()))?,
+        };
+        Ok(Self {
+            shape,
+            device: *device,
+            data: Cow::Owned(data),
+        })
+    }
+
+    /// Creates a new borrowed tensor with given shape. Can fail if data doesn't match the shape
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::borrowed(&[1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// ```
+    pub fn borrowed(data: &'static [T], shape: Vec<usize>) -> Result<Self, SmeltError> {
+        let cow: Cow<'static, [T]> = data.into();
+        Self::new(cow, shape)
+    }
+
+    /// Creates a new tensor with given shape. Can fail if data doesn't match the shape
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let data = vec![1.0, 2.0, 3.0, 4.0];
+    /// let tensor = Tensor::new(data, vec![2, 2]).unwrap();
+    /// ```
+    pub fn new<D>(data: D, shape: Vec<usize>) -> Result<Self, SmeltError>
+    where
+        D: Into<Cow<'static, [T]>>,
+    {
+        let data = data.into();
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        Ok(Self {
+            shape,
+            device: Device::default(),
+            data,
+        })
+    }
+
+    /// Creates a new tensor with given shape. Can fail if data doesn't match the shape
+    /// The data is copied when the [NumaPolicy] of `device` requires to move it.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new();
+    /// let data = vec![1.0, 2.0, 3.0, 4.0];
+    /// let tensor = Tensor::from_cpu(data, vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn from_cpu<D>(data: D, shape: Vec<usize>, device: &Device<T>) -> Result<Self, SmeltError>
+    where
+        D: Into<Cow<'static, [T]>>,
+    {
+        let data = data.into();
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let data = match device.numa {
+            NumaPolicy::Local => data,
+            policy => Cow::Owned(numa::alloc(data.len(), policy, |placed| {
+                placed.extend_from_slice(&data)
+            })?),
+        };
+        Ok(Self {
+            shape,
+            device: *device,
+            data,
+        })
+    }
+}
+
+impl Tensor<u8> {
+    /// Creates a new [Mask] with given shape. Can fail if data doesn't match the shape
+    /// ```
+    /// use smelte_rs::cpu::Mask;
+    ///
+    /// let mask = Mask::from_bools(&[true, false, false, true], vec![2, 2]).unwrap();
+    /// assert_eq!(mask.data(), [1, 0, 0, 1]);
+    /// ```
+    pub fn from_bools(data: &[bool], shape: Vec<usize>) -> Result<Self, SmeltError> {
+        Self::new(data.iter().map(|&b| b as u8).collect::<Vec<_>>(), shape)
+    }
+
+    /// The (length, length) [Mask] of a causal attention: the item `(i, j)` is true when
+    /// `j > i`, hiding the future positions.
+    /// ```
+    /// use smelte_rs::cpu::Mask;
+    ///
+    /// let mask = Mask::causal(3);
+    /// assert_eq!(mask.data(), [0, 1, 1, 0, 0, 1, 0, 0, 0]);
+    /// ```
+    pub fn causal(length: usize) -> Self {
+        let data: Vec<u8> = (0..length * length)
+            .map(|i| (i % length > i / length) as u8)
+            .collect();
+        Self {
+            shape: vec![length, length],
+            device: Device::default(),
+            data: Cow::Owned(data),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cpu::numa;
+use crate::SmeltError;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::env::VarError;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// The environment variable setting the initial number of threads of the cpu kernels.
+pub const NUM_THREADS_ENV: &str = "SMELT_NUM_THREADS";
+
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+// The NUMA node the threads are pinned to, if any.
+static NODE: RwLock<Option<usize>> = RwLock::new(None);
+
+fn build(num_threads: usize, node: Option<usize>) -> Result<Arc<ThreadPool>, SmeltError> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|i| format!("smelt-{i}"))
+        .build()
+        .map_err(|e| SmeltError::ThreadPool(e.to_string()))?;
+    if let Some(node) = node {
+        let cpus = numa::node_cpus(node)?;
+        if cpus.is_empty() {
+            return Err(SmeltError::Numa(format!("Node {node} has no cpus")));
+        }
+        // Every worker pins itself and reports its error, none is left unpinned.
+        pool.broadcast(|_| numa::pin_current_thread(&cpus))
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(Arc::new(pool))
+}
+
+/// Sets the number of threads used by the cpu kernels, `0` uses one thread per cpu.
+/// Defaults to [NUM_THREADS_ENV] when set, one thread per cpu otherwise.
+/// ```
+/// smelte_rs::set_num_threads(2).unwrap();
+/// assert_eq!(smelte_rs::num_threads().unwrap(), 2);
+/// ```
+pub fn set_num_threads(num_threads: usize) -> Result<(), SmeltError> {
+    let pool = build(num_threads, *NODE.read().unwrap())?;
+    *POOL.write().unwrap() = Some(pool);
+    Ok(())
+}
+
+/// The number of threads used by the cpu kernels, fails when [NUM_THREADS_ENV] is invalid
+/// and [set_num_threads] was never called.
+pub fn num_threads() -> Result<usize, SmeltError> {
+    Ok(pool()?.current_num_threads())
+}
+
+/// The number of threads in the value of [NUM_THREADS_ENV], `0` when unset, or the
+/// message of the invalid value.
+fn parse_num_threads(value: Result<String, VarError>) -> Result<usize, String> {
+    let invalid = |value: &dyn std::fmt::Debug| {
+        format!("Invalid {NUM_THREADS_ENV} {value:?}, expected a number of threads")
+    };
+    match value {
+        Ok(value) => value.trim().parse().map_err(|_| invalid(&value)),
+        Err(VarError::NotPresent) => Ok(0),
+        Err(VarError::NotUnicode(value)) => Err(invalid(&value)),
+    }
+}
+
+/// The value of [NUM_THREADS_ENV], parsed once when the first pool is created.
+fn env_num_threads() -> Result<usize, SmeltError> {
+    static NUM_THREADS: OnceLock<Result<usize, String>> = OnceLock::new();
+    NUM_THREADS
+        .get_or_init(|| parse_num_threads(std::env::var(NUM_THREADS_ENV)))
+        .clone()
+        .map_err(SmeltError::InvalidConfig)
+}
+
+/// The thread pool running the cpu kernels, created on first use.
+pub(crate) fn pool() -> Result<Arc<ThreadPool>, SmeltError> {
+    if let Some(pool) = POOL.read().unwrap().as_ref() {
+        return Ok(pool.clone());
+    }
+    let mut pool = POOL.write().unwrap();
+    if let Some(pool) = pool.as_ref() {
+        return Ok(pool.clone());
+    }
+    let new = build(env_num_threads()?, None)?;
+    *pool = Some(new.clone());
+    Ok(new)
+}
+
+/// Pins the threads to the cpus of `node` (or unpins them), keeping their number.
+pub(crate) fn pin_to_node(node: Option<usize>) -> Result<(), SmeltError> {
+    let mut current = NODE.write().unwrap();
+    if *current == node {
+        return Ok(());
+    }
+    let pool = build(num_threads()?, node)?;
+    *POOL.write().unwrap() = Some(pool);
+    *current = node;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // The tests replacing the global pool run one at a time.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn simple_set_num_threads() {
+        let _lock = LOCK.lock().unwrap();
+        set_num_threads(3).unwrap();
+        assert_eq!(num_threads().unwrap(), 3);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_pin_to_node() {
+        let _lock = LOCK.lock().unwrap();
+        pin_to_node(Some(0)).unwrap();
+        assert!(pin_to_node(Some(usize::MAX)).is_err());
+        assert_eq!(*NODE.read().unwrap(), Some(0));
+        pin_to_node(None).unwrap();
+    }
+
+    #[test]
+    fn test_parse_num_threads() {
+        assert_eq!(parse_num_threads(Ok("4".to_string())).unwrap(), 4);
+        assert_eq!(parse_num_threads(Ok(" 2\n".to_string())).unwrap(), 2);
+        assert_eq!(parse_num_threads(Err(VarError::NotPresent)).unwrap(), 0);
+        assert!(parse_num_threads(Ok("four".to_string())).is_err());
+        assert!(parse_num_threads(Ok("-1".to_string())).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::cpu::layout::copy_strided;
+use crate::cpu::{Device, Element, Tensor};
+use crate::shape;
+use crate::SmeltError;
+use std::borrow::Cow;
+use std::ops::RangeBounds;
+
+/// A strided view of the data of a [Tensor]. Narrowing and permuting a view only
+/// changes its shape, strides and offset, nothing is copied until
+/// [View::contiguous] gives back a tensor for the ops.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+///
+/// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3]).unwrap();
+/// let view = tensor.view().transpose(0, 1).unwrap().slice(0, 1..).unwrap();
+/// assert_eq!(view.shape(), [2, 2]);
+/// assert_eq!(view.strides(), [1, 3]);
+/// assert_eq!(view.contiguous().data(), [2.0, 5.0, 3.0, 6.0]);
+/// ```
+#[derive(Clone)]
+pub struct View<'a, T: Element> {
+    data: &'a [T],
+    device: Device<T>,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    offset: usize,
+}
+
+impl<T: Element> Tensor<T> {
+    /// The view of the whole tensor, see [View].
+    pub fn view(&self) -> View<'_, T> {
+        View {
+            data: self.data(),
+            device: self.device,
+            strides: shape::strides(&self.shape),
+            shape: self.shape.clone(),
+            offset: 0,
+        }
+    }
+
+    /// The tensor expanded to `shape`, see [View::expand]. The expanded items are
+    /// copied, the view itself is free.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let mask = Tensor::new(vec![1.0, 0.0], vec![1, 2]).unwrap();
+    /// let mask = mask.expand(&[3, 2]).unwrap();
+    /// assert_eq!(mask.data(), [1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+    /// ```
+    pub fn expand(&self, shape: &[usize]) -> Result<Self, SmeltError> {
+        Ok(self.view().expand(shape)?.contiguous())
+    }
+
+    /// The tensor tiled `repeats` times along every dimension, like torch. Extra repeats
+    /// add dimensions on the left.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// let tensor = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let tiled = tensor.repeat(&[1, 2]).unwrap();
+    /// assert_eq!(tiled.shape(), [2, 4]);
+    /// assert_eq!(tiled.data(), [1.0, 2.0, 1.0, 2.0, 3.0, 4.0, 3.0, 4.0]);
+    /// ```
+    pub fn repeat(&self, repeats: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, info) = shape::repeat(&self.shape, repeats)?;
+        let (view_shape, view_strides) = info.split_at(info.len() / 2);
+        let view = View {
+            data: self.data(),
+            device: self.device,
+            shape: view_shape.to_vec(),
+            strides: view_strides.to_vec(),
+            offset: 0,
+        };
+        view.contiguous().reshape(shape)
+    }
+}
+
+impl<'a, T: Element> View<'a, T> {
+    /// The shape of the view
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The number of items between two consecutive items of each dimension
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// Whether the items of the view follow each other in row major order
+    pub fn is_contiguous(&self) -> bool {
+        shape::is_contiguous(&self.shape, &self.strides)
+    }
+
+    /// The items `start..start + len` of the dimension `dim`, like [Tensor::narrow].
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        shape::narrow(&self.shape, dim, start, len)?;
+        let mut view = self.clone();
+        view.shape[dim] = len;
+        view.offset += start * self.strides[dim];
+        Ok(view)
+    }
+
+    /// [View::narrow] with a range, like [Tensor::slice].
+    pub fn slice(&self, dim: usize, range: impl RangeBounds<usize>) -> Result<Self, SmeltError> {
+        let size = self.shape.get(dim).copied().unwrap_or(0);
+        let (start, len) = shape::range(range, size);
+        self.narrow(dim, start, len)
+    }
+
+    /// The view with its dimensions reordered, like [Tensor::permute].
+    pub fn permute(&self, dims: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, _) = shape::permute(&self.shape, dims)?;
+        Ok(Self {
+            data: self.data,
+            device: self.device,
+            shape,
+            strides: dims.iter().map(|&d| self.strides[d]).collect(),
+            offset: self.offset,
+        })
+    }
+
+    /// [View::permute] swapping the dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Self, SmeltError> {
+        self.permute(&shape::transpose(self.shape.len(), dim0, dim1))
+    }
+
+    /// The view expanded to `shape`: the dimensions of 1 are repeated and new dimensions
+    /// can be added on the left, like broadcasting. Nothing is copied, the repeated
+    /// dimensions have a stride of 0.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    ///
+    /// // One key head shared by 4 query heads.
+    /// let key = Tensor::zeros(vec![1, 1, 5, 64]);
+    /// let view = key.view().expand(&[1, 4, 5, 64]).unwrap();
+    /// assert_eq!(view.strides(), [320, 0, 64, 1]);
+    /// ```
+    pub fn expand(&self, shape: &[usize]) -> Result<Self, SmeltError> {
+        let strides = shape::expand(&self.shape, &self.strides, shape)?;
+        Ok(Self {
+            data: self.data,
+            device: self.device,
+            shape: shape.to_vec(),
+            strides,
+            offset: self.offset,
+        })
+    }
+
+    /// The items of the view in a new contiguous tensor. When the view is already
+    /// contiguous this is a single copy of its items.
+    pub fn contiguous(&self) -> Tensor<T> {
+        let numel = self.shape.iter().product();
+        let data = if self.is_contiguous() {
+            self.data[self.offset..self.offset + numel].to_vec()
+        } else {
+            let mut data = vec![T::default(); numel];
+            if numel > 0 {
+                copy_strided(
+                    self.data,
+                    &mut data,
+                    &self.shape,
+                    &self.strides,
+                    self.offset,
+                );
+            }
+            data
+        };
+        Tensor {
+            shape: self.shape.clone(),
+            device: self.device,
+            data: Cow::Owned(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::f32::Tensor;
+
+    #[test]
+    fn test_view() {
+        let data: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let tensor = Tensor::new(data, vec![2, 3, 4]).unwrap();
+        let view = tensor.view();
+        assert!(view.is_contiguous());
+
+        // The same items as the copying ops, in any order.
+        let view = view.permute(&[2, 0, 1]).unwrap().narrow(2, 1, 2).unwrap();
+        assert!(!view.is_contiguous());
+        let expected = tensor.permute(&[2, 0, 1]).unwrap().narrow(2, 1, 2).unwrap();
+        assert_eq!(view.shape(), expected.shape());
+        assert_eq!(view.contiguous().data(), expected.data());
+
+        // A contiguous slice of the rows.
+        let view = tensor.view().slice(0, 1..).unwrap();
+        assert!(view.is_contiguous());
+        assert_eq!(view.contiguous().data()[0], 12.0);
+        assert!(tensor.view().narrow(1, 2, 2).is_err());
+        assert!(tensor.view().permute(&[0, 1]).is_err());
+
+        // Expanded and repeated, through a transposed view.
+        let tensor = Tensor::new(vec![1.0, 2.0], vec![2, 1]).unwrap();
+        let view = tensor
+            .view()
+            .transpose(0, 1)
+            .unwrap()
+            .expand(&[2, 2, 2])
+            .unwrap();
+        assert_eq!(view.strides(), [0, 0, 1]);
+        assert_eq!(
+            view.contiguous().data(),
+            [1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 1.0, 2.0]
+        );
+        assert!(tensor.view().expand(&[3, 3]).is_err());
+        let tiled = tensor.repeat(&[2, 1, 3]).unwrap();
+        assert_eq!(tiled.shape(), [2, 2, 3]);
+        assert_eq!(tiled.data()[..6], [1.0, 1.0, 1.0, 2.0, 2.0, 2.0]);
+        assert!(tensor.repeat(&[2]).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use crate::backend::Tensor;
+use crate::traits::{TensorMatmul, TensorMatmulT};
+use crate::SmeltError;
+
+/// An einsum equation, like `"bhqd,bhkd->bhqk"`, compiled once into [Tensor::bmm] and
+/// reused by every call. The contractions of operands already laid out for a matmul,
+/// like the attention scores above, run as [Tensor::matmul] or [Tensor::matmul_t]
+/// without copying them. The operands are contracted from left to right, the labels
+/// summed over must appear in at least two operands, and without `->` the output has
+/// the labels appearing once, in alphabetical order like numpy.
+/// ```
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use smelte_rs::backend::{Device, Tensor};
+/// use smelte_rs::einsum::Einsum;
+///
+/// let device = Device::cpu();
+/// let scores = Einsum::new("bhqd,bhkd->bhqk").unwrap();
+/// let query = Tensor::zeros(vec![1, 12, 5, 64], &device).unwrap();
+/// let key = Tensor::zeros(vec![1, 12, 7, 64], &device).unwrap();
+/// let out = scores.forward(&[&query, &key]).unwrap();
+/// assert_eq!(out.shape(), [1, 12, 5, 7]);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Einsum {
+    inputs: Vec<Vec<u8>>,
+    steps: Vec<Step>,
+    output_dims: Vec<usize>,
+}
+
+/// The contraction of the current result with the next operand, as the matmul of
+/// (batch, m, k) by (batch, k, n). `direct` is set when the operands already have these
+/// dimensions, with a single label for m, k and n, and tells whether `b` is transposed
+/// (batch, n, k).
+#[derive(Debug, Clone)]
+struct Step {
+    a_dims: Vec<usize>,
+    b_dims: Vec<usize>,
+    direct: Option<bool>,
+    batch: Vec<u8>,
+    m: Vec<u8>,
+    k: Vec<u8>,
+    n: Vec<u8>,
+}
+
+impl Einsum {
+    /// Compiles `equation`, labels are ascii letters and appear at most once per
+    /// operand.
+    pub fn new(equation: &str) -> Result<Self, SmeltError> {
+        let invalid = |reason: &str| SmeltError::InvalidEquation(format!("{equation}: {reason}"));
+        let compact: String = equation.chars().filter(|c| !c.is_whitespace()).collect();
+        let (inputs, output) = match compact.split_once("->") {
+            Some((inputs, output)) => (inputs, Some(output)),
+            None => (compact.as_str(), None),
+        };
+        let inputs: Vec<Vec<u8>> = inputs.split(',').map(|l| l.bytes().collect()).collect();
+        let count = |label: u8| inputs.iter().filter(|l| l.contains(&label)).count();
+        let output: Vec<u8> = match output {
+            Some(output) => output.bytes().collect(),
+            None => {
+                let mut output: Vec<u8> = inputs.concat();
+                output.retain(|&l| count(l) == 1);
+                output.sort_unstable();
+                output
+            }
+        };
+        for labels in inputs.iter().chain([&output]) {
+            if !labels.iter().all(u8::is_ascii_alphabetic) {
+                return Err(invalid("labels must be ascii letters"));
+            }
+            if labels
+                .iter()
+                .enumerate()
+                .any(|(i, l)| labels[..i].contains(l))
+            {
+                return Err(invalid("repeated label"));
+            }
+        }
+        if let Some(&label) = output.iter().find(|&&l| count(l) == 0) {
+            return Err(invalid(&format!("unknown output label {}", label as char)));
+        }
+        if let Some(&label) = inputs
+            .iter()
+            .flatten()
+            .find(|&&l| count(l) == 1 && !output.contains(&l))
+        {
+            return Err(invalid(&format!(
+                "{} is summed in one operand",
+                label as char
+            )));
+        }
+
+        let mut current = inputs[0].clone();
+        let mut steps = Vec::with_capacity(inputs.len() - 1);
+        for (i, b) in inputs.iter().enumerate().skip(1) {
+            let kept = |l: &u8| output.contains(l) || inputs[i + 1..].iter().any(|o| o.contains(l));
+            let (shared, m): (Vec<u8>, Vec<u8>) =
+                current.iter().copied().partition(|l| b.contains(l));
+            let (batch, k): (Vec<u8>, Vec<u8>) = shared.into_iter().partition(kept);
+            let n: Vec<u8> = b.iter().filter(|l| !current.contains(l)).copied().collect();
+            let position = |labels: &[u8], l: &u8| labels.iter().position(|x| x == l).unwrap();
+            let a_dims = [&batch, &m, &k]
+                .into_iter()
+                .flatten()
+                .map(|l| position(&current, l))
+                .collect();
+            let b_dims = [&batch, &k, &n]
+                .into_iter()
+                .flatten()
+                .map(|l| position(b, l))
+                .collect();
+            let single = m.len() == 1 && k.len() == 1 && n.len() == 1;
+            let direct = if !single || current != [&batch[..], &m, &k].concat() {
+                None
+            } else if b == &[&batch[..], &k, &n].concat() {
+                Some(false)
+            } else if b == &[&batch[..], &n, &k].concat() {
+                Some(true)
+            } else {
+                None
+            };
+            current = [&batch[..], &m, &n].concat();
+            steps.push(Step {
+                a_dims,
+                b_dims,
+                direct,
+                batch,
+                m,
+                k,
+                n,
+            });
+        }
+        let output_dims = output
+            .iter()
+            .map(|l| current.iter().position(|x| x == l).unwrap())
+            .collect();
+        Ok(Self {
+            inputs,
+            steps,
+            output_dims,
+        })
+    }
+
+    /// The number of operands of the equation
+    pub fn operands(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Runs the equation on `operands`, which must live on the same device.
+    pub fn forward(&self, operands: &[&Tensor]) -> Result<Tensor, SmeltError> {
+        if operands.len() != self.inputs.len() {
+            return Err(SmeltError::InvalidLength {
+                expected: self.inputs.len(),
+                got: operands.len(),
+            });
+        }
+        let mut label_sizes = [None; 128];
+        for (labels, operand) in self.inputs.iter().zip(operands) {
+            if labels.len() != operand.shape().len() {
+                return Err(SmeltError::InvalidRank {
+                    expected_rank: labels.len(),
+                });
+            }
+            for (&label, &size) in labels.iter().zip(operand.shape()) {
+                match label_sizes[label as usize] {
+                    Some(expected) if expected != size => {
+                        return Err(SmeltError::DimensionMismatch {
+                            expected: labels
+                                .iter()
+                                .map(|&l| label_sizes[l as usize].unwrap_or(size))
+                                .collect(),
+                            got: operand.shape().to_vec(),
+                        })
+                    }
+                    _ => label_sizes[label as usize] = Some(size),
+                }
+            }
+        }
+        let sizes = |labels: &[u8]| -> Vec<usize> {
+            labels
+                .iter()
+                .map(|&l| label_sizes[l as usize].unwrap())
+                .collect()
+        };
+        let numel = |labels: &[u8]| -> usize { sizes(labels).iter().product() };
+
+        let mut current: Option<Tensor> = None;
+        for (step, b) in self.steps.iter().zip(&operands[1..]) {
+            let a = current.as_ref().unwrap_or(operands[0]);
+            let out_shape = sizes(&[&step.batch[..], &step.m, &step.n].concat());
+            if let Some(transposed) = step.direct {
+                let mut out = Tensor::zeros(out_shape, a.device())?;
+                if transposed {
+                    Tensor::matmul_t(a, b, &mut out)?;
+                } else {
+                    Tensor::matmul(a, b, &mut out)?;
+                }
+                current = Some(out);
+                continue;
+            }
+            let (batch, m, k, n) = (
+                numel(&step.batch),
+                numel(&step.m),
+                numel(&step.k),
+                numel(&step.n),
+            );
+            let a = a.permute(&step.a_dims)?.reshape(vec![batch, m, k])?;
+            let b = b.permute(&step.b_dims)?.reshape(vec![batch, k, n])?;
+            let out = a.bmm(&b)?;
+            current = Some(out.reshape(out_shape)?);
+        }
+        let identity = self.output_dims.iter().enumerate().all(|(i, &d)| i == d);
+        match current {
+            Some(x) if identity => Ok(x),
+            x => x.as_ref().unwrap_or(operands[0]).permute(&self.output_dims),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "cpu"))]
+mod tests {
+    use super::*;
+    use crate::backend::Device;
+
+    #[test]
+    fn test_einsum() {
+        let device = Device::cpu();
+        let a = Tensor::from_cpu(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![2, 3], &device).unwrap();
+        let b = Tensor::from_cpu(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], vec![3, 2], &device).unwrap();
+
+        let matmul = Einsum::new("ij,jk->ik").unwrap();
+        let out = matmul.forward(&[&a, &b]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [4.0, 5.0, 10.0, 11.0]);
+
+        let out = Einsum::new("ij,jk->ki")
+            .unwrap()
+            .forward(&[&a, &b])
+            .unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [4.0, 10.0, 5.0, 11.0]);
+        // Implicit output, in alphabetical order.
+        let out = Einsum::new("ij, jk").unwrap().forward(&[&a, &b]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [4.0, 5.0, 10.0, 11.0]);
+        let out = Einsum::new("ij->ji").unwrap().forward(&[&a]).unwrap();
+        assert_eq!(out.shape(), [3, 2]);
+
+        // The trace of a.b, then a chain of three operands.
+        let out = Einsum::new("ij,ji->").unwrap().forward(&[&a, &b]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [15.0]);
+        let c = Tensor::from_cpu(vec![0.0, 1.0, 1.0, 0.0], vec![2, 2], &device).unwrap();
+        let chain = Einsum::new("ij,jk,kl->il").unwrap();
+        let out = chain.forward(&[&a, &b, &c]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [5.0, 4.0, 11.0, 10.0]);
+
+        // The attention scores and values, straight to the matmuls.
+        let q = Tensor::from_cpu(vec![1.0, 2.0, 3.0, 4.0], vec![1, 1, 2, 2], &device).unwrap();
+        let k = Tensor::from_cpu(
+            vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0],
+            vec![1, 1, 3, 2],
+            &device,
+        )
+        .unwrap();
+        let scores = Einsum::new("bhqd,bhkd->bhqk").unwrap();
+        assert_eq!(scores.steps[0].direct, Some(true));
+        let out = scores.forward(&[&q, &k]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [1.0, 2.0, 3.0, 3.0, 4.0, 7.0]);
+        let values = Einsum::new("bhqk,bhkd->bhqd").unwrap();
+        assert_eq!(values.steps[0].direct, Some(false));
+        let out = values.forward(&[&out, &k]).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [4.0, 5.0, 10.0, 11.0]);
+        assert_eq!(Einsum::new("ji,jk->ik").unwrap().steps[0].direct, None);
+
+        // Relative position bias: batched over b and h, with a shared q.
+        let x = Tensor::from_cpu(vec![1.0; 2 * 4 * 3], vec![1, 2, 4, 3], &device).unwrap();
+        let r = Tensor::from_cpu(vec![1.0; 4 * 5 * 3], vec![4, 5, 3], &device).unwrap();
+        let out = Einsum::new("bhqd,qkd->bhqk")
+            .unwrap()
+            .forward(&[&x, &r])
+            .unwrap();
+        assert_eq!(out.shape(), [1, 2, 4, 5]);
+        assert_eq!(out.cpu_data().unwrap(), [3.0; 40]);
+
+        assert!(Einsum::new("ii->i").is_err());
+        assert!(Einsum::new("ij,jk->il").is_err());
+        assert!(Einsum::new("ij->i").is_err());
+        assert!(matches!(
+            matmul.forward(&[&a, &a]),
+            Err(SmeltError::DimensionMismatch { .. })
+        ));
+        assert!(matmul.forward(&[&a]).is_err());
+    }
+}
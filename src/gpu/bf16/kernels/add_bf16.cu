@@ -0,0 +1,12 @@
+#include "binary_op_macros.cuh"
+#include "cuda_bf16.h"
+
+// The bf16 arithmetic operators need sm_80, the sums and products go through f32.
+#define BF16(FUNC) __float2bfloat16(FUNC(__bfloat162float(x), __bfloat162float(y)))
+#define ADD(x, y) ((x) + (y))
+#define MUL(x, y) ((x) * (y))
+
+OP(__nv_bfloat16, add_fwd_bf16, BF16(ADD))
+OP(__nv_bfloat16, mul_fwd_bf16, BF16(MUL))
+BROADCAST_OP(__nv_bfloat16, badd_fwd_bf16, BF16(ADD))
+BROADCAST_OP(__nv_bfloat16, bmul_fwd_bf16, BF16(MUL))
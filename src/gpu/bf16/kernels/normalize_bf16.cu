@@ -0,0 +1,34 @@
+#include "cuda_bf16.h"
+
+extern "C" __global__ void normalize_bf16( 
+    const size_t numel, 
+    __nv_bfloat16 *lhs, 
+    const size_t size, 
+    const float epsilon
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+
+    const size_t offset = i * size;
+
+    // Accumulate in f32, a sum over the hidden dimension loses the precision of bf16.
+    float sum = 0.0;
+    for (int i=0; i < size; i ++){
+	sum += __bfloat162float(lhs[offset + i]);
+    }
+    const float mean = sum / size;
+
+    float var = 0.0;
+    for (int i=0; i < size; i ++){
+	const float v = __bfloat162float(lhs[offset + i]) - mean;
+	var += v * v;
+    }
+    var /= size;
+    var += epsilon;
+    const float std = sqrt(var);
+    for (int i=0; i < size; i ++){
+	lhs[offset + i] = __float2bfloat16((__bfloat162float(lhs[offset + i]) - mean) / std);
+    }
+} 
@@ -0,0 +1,41 @@
+#include "cuda_bf16.h"
+
+extern "C" __global__ void softmax_bf16( 
+    const size_t numel, 
+    __nv_bfloat16 *lhs, 
+    const size_t m, 
+    const size_t size, 
+    const size_t past_sequence_length
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+
+    const size_t offset = i * size;
+    i = i % m;
+    float current_max = -1 * INFINITY;
+    for (int j = 0; j< size; j++){
+	    const float v = __bfloat162float(lhs[offset + j]);
+	    if (v > current_max && i + past_sequence_length >=j) {
+		    current_max = v;
+	    }
+    }
+
+    // The exponentials are only rounded to bf16 once normalized.
+    float sum = 0.0;
+    for (int j = 0; j< size; j++){
+	    if (i + past_sequence_length >=j){
+		    sum += exp(__bfloat162float(lhs[offset + j]) - current_max);
+	    }
+    }
+
+    for (int j = 0; j< size; j++){
+	    if (i + past_sequence_length >=j){
+		    lhs[offset + j] = __float2bfloat16(exp(__bfloat162float(lhs[offset + j]) - current_max) / sum);
+	    }else{
+		    lhs[offset + j] = __float2bfloat16(0.0);
+	    }
+    }
+
+} 
@@ -0,0 +1,85 @@
+#include "cuda_bf16.h"
+
+__device__ float gelu_fwd(float x) {
+    constexpr float fastCoeff = 0.044715;
+    float x_sq = x * x;
+    float x_cube = x_sq * x;
+    float alpha = x + fastCoeff * x_cube;
+    return 0.5 * x * (1.0 + tanhf(M_2_SQRTPI * M_SQRT1_2 * alpha));
+}
+
+extern "C" __global__ void tanh_bf16( 
+    const size_t numel, 
+    __nv_bfloat16 *x
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2bfloat16(tanhf(__bfloat162float(x[i])));
+} 
+
+extern "C" __global__ void gelu_bf16( 
+    const size_t numel, 
+    __nv_bfloat16 *x 
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2bfloat16(gelu_fwd(__bfloat162float(x[i])));
+} 
+
+#define UNARY_OP(NAME, FUNC) \
+extern "C" __global__ void NAME( \
+    const size_t numel, \
+    __nv_bfloat16 *x \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float v = __bfloat162float(x[i]); \
+    x[i] = __float2bfloat16(FUNC); \
+}
+
+UNARY_OP(gelu_erf_bf16, 0.5f * v * (1.0f + erff(v * (float)M_SQRT1_2)))
+UNARY_OP(relu_bf16, fmaxf(v, 0.0f))
+UNARY_OP(silu_bf16, v / (1.0f + expf(-v)))
+UNARY_OP(quick_gelu_bf16, v / (1.0f + expf(-1.702f * v)))
+
+extern "C" __global__ void mul_scalar_bf16( 
+    const size_t numel, 
+    __nv_bfloat16 *x ,
+    float factor
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2bfloat16(__bfloat162float(x[i]) * factor);
+} 
+
+extern "C" __global__ void f32_to_bf16( 
+    const size_t numel, 
+    const float *src,
+    __nv_bfloat16 *dst
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    dst[i] = __float2bfloat16(src[i]);
+} 
+
+extern "C" __global__ void bf16_to_f32( 
+    const size_t numel, 
+    const __nv_bfloat16 *src,
+    float *dst
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    dst[i] = __bfloat162float(src[i]);
+} 
@@ -0,0 +1,483 @@
+use crate::gpu::bf16::Tensor;
+use crate::gpu::f32::{CudaError, Tensor as F32Tensor};
+use crate::traits::Activation;
+use crate::SmeltError;
+use cudarc::cublas::sys;
+use cudarc::cublas::sys::cublasOperation_t::{CUBLAS_OP_N as NoTr, CUBLAS_OP_T as Tr};
+use cudarc::driver::{DevicePtr, DevicePtrMut, DeviceSlice, LaunchAsync, LaunchConfig};
+use std::ffi::c_void;
+
+fn check_devices(got: usize, expected: usize) -> Result<(), SmeltError> {
+    if got != expected {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got,
+            expected,
+        }));
+    }
+    Ok(())
+}
+
+fn check_shapes(got: &[usize], expected: &[usize]) -> Result<(), SmeltError> {
+    if got != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected.to_vec(),
+            got: got.to_vec(),
+        });
+    }
+    Ok(())
+}
+
+/// Operation for selecting entire rows within tensor `weights`. Each `id` is the index
+/// of the row.
+pub fn select(ids: &[usize], weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let sequence_length = ids.len();
+    let vocab_size = weights.shape()[0];
+    let hidden_dim = weights.shape()[1];
+    check_shapes(out.shape(), &[sequence_length, hidden_dim])?;
+    check_devices(out.device_id(), weights.device_id())?;
+
+    let dev = weights.cuda();
+    for (i, id) in ids.iter().enumerate() {
+        let id = *id;
+        if id >= vocab_size {
+            return Err(SmeltError::OutOfVocabulary { vocab_size, id });
+        }
+        let weight_offset = id * hidden_dim;
+        let data_offset = i * hidden_dim;
+
+        let src = weights
+            .data()
+            .slice(weight_offset..weight_offset + hidden_dim);
+        let mut dst = out
+            .data_mut()
+            .slice_mut(data_offset..data_offset + hidden_dim);
+        dev.dtod_copy(&src, &mut dst)?
+    }
+    Ok(())
+}
+
+/// Copy tensor into another tensor
+pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    check_shapes(weights.shape(), out.shape())?;
+    check_devices(out.device_id(), weights.device_id())?;
+    out.cuda().dtod_copy(weights.data(), out.data_mut())?;
+    Ok(())
+}
+
+/// Regular matrix multiplication
+pub fn matmul(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<false>(a, b, out)
+}
+
+/// Matrix multiplication matmul(A, B.transposed())
+pub fn matmul_t(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<true>(a, b, out)
+}
+
+#[inline]
+fn g_matmul<const TRANSPOSE: bool>(
+    a: &Tensor,
+    b: &Tensor,
+    c: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+    check_devices(b.device_id(), a.device_id())?;
+    check_devices(c.device_id(), a.device_id())?;
+
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    if b.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+    if c.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+
+    let m = a.shape()[dim - 2];
+    let k = a.shape()[dim - 1];
+
+    let mut expected_c = a.shape().to_vec();
+    let mut expected_b = a.shape().to_vec();
+
+    let n = if TRANSPOSE {
+        let n = b.shape()[dim - 2];
+        expected_b[dim - 2] = n;
+        expected_b[dim - 1] = k;
+        n
+    } else {
+        let n = b.shape()[dim - 1];
+        expected_b[dim - 2] = k;
+        expected_b[dim - 1] = n;
+        n
+    };
+
+    expected_c[dim - 2] = m;
+    expected_c[dim - 1] = n;
+
+    check_shapes(b.shape(), &expected_b)?;
+    check_shapes(c.shape(), &expected_c)?;
+
+    if !a.device().native_gemm() {
+        // No bf16 tensor cores before Ampere, the f32 gemm is faster than emulating them.
+        let (a, b) = (a.to_f32()?, b.to_f32()?);
+        let mut out = F32Tensor::zeros(c.shape().to_vec(), c.device().f32())?;
+        if TRANSPOSE {
+            crate::gpu::f32::matmul_t(&a, &b, &mut out)?;
+        } else {
+            crate::gpu::f32::matmul(&a, &b, &mut out)?;
+        }
+        return cast_from_f32(&out, c);
+    }
+
+    let batching: usize = a.shape()[..dim - 2].iter().product();
+
+    let a_skip: usize = m * k;
+    let b_skip: usize = n * k;
+    let c_skip: usize = m * n;
+
+    let blas = a.blas();
+
+    let (m, n, k) = (m as libc::c_int, n as libc::c_int, k as libc::c_int);
+
+    // Same swap as the f32 version, cublas is column major.
+    let (m, n, k) = (n, m, k);
+    let (a_skip, b_skip) = (b_skip, a_skip);
+    let (a, b) = (b, a);
+
+    let (ldb, ldc) = (k, m);
+    let (lda, transa) = if TRANSPOSE { (k, Tr) } else { (m, NoTr) };
+
+    // The compute type is f32, so are the scalars.
+    let (alpha, beta) = (1.0f32, 0.0f32);
+    // SAFETY: The shapes were checked above, the three buffers live on the device of
+    // `blas`. `c` is overwritten (`beta` is 0), no need to zero it out.
+    unsafe {
+        sys::cublasGemmStridedBatchedEx(
+            *blas.handle(),
+            transa,
+            NoTr,
+            m,
+            n,
+            k,
+            &alpha as *const f32 as *const c_void,
+            *a.data().device_ptr() as *const c_void,
+            sys::cudaDataType_t::CUDA_R_16BF,
+            lda,
+            a_skip as i64,
+            *b.data().device_ptr() as *const c_void,
+            sys::cudaDataType_t::CUDA_R_16BF,
+            ldb,
+            b_skip as i64,
+            &beta as *const f32 as *const c_void,
+            *c.data_mut().device_ptr_mut() as *mut c_void,
+            sys::cudaDataType_t::CUDA_R_16BF,
+            ldc,
+            c_skip as i64,
+            batching as i32,
+            sys::cublasComputeType_t::CUBLAS_COMPUTE_32F,
+            sys::cublasGemmAlgo_t::CUBLAS_GEMM_DEFAULT,
+        )
+    }
+    .result()?;
+
+    Ok(())
+}
+
+const ADD_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/add_bf16.ptx"));
+
+fn g_binary<const BROADCAST: bool>(
+    module_name: &'static str,
+    a: &Tensor,
+    b: &mut Tensor,
+) -> Result<(), SmeltError> {
+    if BROADCAST {
+        check_shapes(a.shape(), &b.shape()[1..])?;
+    } else {
+        check_shapes(a.shape(), b.shape())?;
+    }
+    check_devices(b.device_id(), a.device_id())?;
+
+    let dev = a.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(ADD_PTX.into(), module_name, &[module_name])?;
+    }
+
+    let numel = b.data().len();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    if BROADCAST {
+        let skip: usize = a.shape().iter().product();
+        let params = (numel, a.data(), b.data_mut(), skip);
+        unsafe { fwd_fn.launch(cfg, params) }?;
+    } else {
+        let params = (numel, a.data(), b.data_mut());
+        unsafe { fwd_fn.launch(cfg, params) }?;
+    }
+    Ok(())
+}
+
+/// tensor elementwise addition. b += a.
+pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    g_binary::<false>("add_fwd_bf16", a, b)
+}
+
+/// broacasted tensor elementwise addition. b += a.
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    g_binary::<true>("badd_fwd_bf16", a, b)
+}
+
+/// tensor elementwise multiplication. b *= a.
+pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    g_binary::<false>("mul_fwd_bf16", a, b)
+}
+
+/// broadcasted tensor elementwise multiplication. b *= a.
+pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    g_binary::<true>("bmul_fwd_bf16", a, b)
+}
+
+const NORMALIZE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/normalize_bf16.ptx"));
+
+/// Basic operation for the layernorm.
+/// x = (x - x.mean()) / (x.var() + epsilon)
+/// The mean and variance are accumulated in f32.
+pub fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+    let numel: usize = x.shape()[..dim - 1].iter().product();
+    let size = x.shape()[dim - 1];
+    let dev = x.cuda();
+
+    let module_name = "normalize_bf16";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(NORMALIZE_PTX.into(), module_name, &[module_name])?;
+    }
+
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), size, epsilon);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+
+    Ok(())
+}
+
+const SOFTMAX_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/softmax_bf16.ptx"));
+
+#[inline]
+fn g_softmax<const CAUSAL: bool>(
+    x: &mut Tensor,
+    past_sequence_length: usize,
+) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+
+    let m = x.shape()[dim - 2];
+    let n = x.shape()[dim - 1];
+
+    let dev = x.cuda();
+
+    let module_name = "softmax_bf16";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(SOFTMAX_PTX.into(), module_name, &[module_name])?;
+    }
+    let past_sequence_length = if CAUSAL { past_sequence_length } else { n };
+
+    let numel: usize = x.shape()[..dim - 1].iter().product();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), m, n, past_sequence_length);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+
+    Ok(())
+}
+
+/// Softmax on the last dimension for tensor `x`, computed in f32.
+pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    g_softmax::<false>(x, 0)
+}
+
+/// Causal softmax on the last dimension for tensor `x`. The causality is determined by the
+/// shape of `x` and `past_sequence_length` which defines how big is the missing part of the
+/// square.
+pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
+    g_softmax::<true>(x, past_sequence_length)
+}
+
+const UNITARY_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/unitary_bf16.ptx"));
+
+fn unitary(module_name: &'static str, x: &mut Tensor) -> Result<(), SmeltError> {
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel: usize = x.shape().iter().product();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// `tanh` operation, computed in f32.
+#[inline]
+pub fn tanh(x: &mut Tensor) -> Result<(), SmeltError> {
+    unitary("tanh_bf16", x)
+}
+
+/// `gelu` operation, computed in f32.
+/// <https://en.wikipedia.org/wiki/Activation_function#Comparison_of_activation_functions>
+#[inline]
+pub fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
+    unitary("gelu_bf16", x)
+}
+
+/// `activation` of every item of the tensor, computed in f32.
+#[inline]
+pub fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+    match activation {
+        Activation::Gelu => unitary("gelu_erf_bf16", x),
+        Activation::GeluNew | Activation::GeluFast => gelu(x),
+        Activation::Relu => unitary("relu_bf16", x),
+        Activation::Silu => unitary("silu_bf16", x),
+        Activation::QuickGelu => unitary("quick_gelu_bf16", x),
+    }
+}
+
+/// x *= factor
+#[inline]
+pub fn mul_scalar(x: &mut Tensor, factor: f32) -> Result<(), SmeltError> {
+    let dev = x.cuda();
+    let module_name = "mul_scalar_bf16";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel: usize = x.shape().iter().product();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), factor);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+
+    Ok(())
+}
+
+/// Converts `src` into `dst`, rounding to the nearest bf16.
+pub fn cast_from_f32(src: &F32Tensor, dst: &mut Tensor) -> Result<(), SmeltError> {
+    check_shapes(src.shape(), dst.shape())?;
+    check_devices(dst.device_id(), src.device_id())?;
+    let dev = dst.cuda();
+    let module_name = "f32_to_bf16";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel = dst.data().len();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, src.data(), dst.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// Converts `src` into `dst`, exactly.
+pub fn cast_to_f32(src: &Tensor, dst: &mut F32Tensor) -> Result<(), SmeltError> {
+    check_shapes(src.shape(), dst.shape())?;
+    check_devices(dst.device_id(), src.device_id())?;
+    let dev = src.cuda();
+    let module_name = "bf16_to_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel = src.data().len();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, src.data(), dst.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::bf16::Device;
+    use crate::tests::simplify;
+    use half::bf16;
+
+    fn device() -> Device {
+        Device::new(0).unwrap()
+    }
+
+    fn from_f32(data: &[f32], shape: Vec<usize>, device: &Device) -> Tensor {
+        let data: Vec<_> = data.iter().copied().map(bf16::from_f32).collect();
+        Tensor::from_cpu(&data, shape, device).unwrap()
+    }
+
+    fn to_f32(tensor: &Tensor) -> Vec<f32> {
+        tensor
+            .cpu_data()
+            .unwrap()
+            .into_iter()
+            .map(bf16::to_f32)
+            .collect()
+    }
+
+    #[test]
+    fn simple_matmul() {
+        let device = device();
+        let a = from_f32(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device);
+        let b = from_f32(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device);
+        let mut c = Tensor::zeros(vec![2, 2], &device).unwrap();
+
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(to_f32(&c), [7.0, 10.0, 15.0, 22.0]);
+
+        matmul_t(&a, &b, &mut c).unwrap();
+        assert_eq!(to_f32(&c), [5.0, 11.0, 11.0, 25.0]);
+    }
+
+    #[test]
+    fn matmul_accumulates_in_f32() {
+        let device = device();
+        // 4096 * 1.0 overflows the precision of a bf16 accumulator (256 + 1 == 256).
+        let k = 4096;
+        let a = from_f32(&vec![1.0; k], vec![1, k], &device);
+        let b = from_f32(&vec![1.0; k], vec![1, k], &device);
+        let mut c = Tensor::zeros(vec![1, 1], &device).unwrap();
+        matmul_t(&a, &b, &mut c).unwrap();
+        assert_eq!(to_f32(&c), [4096.0]);
+    }
+
+    #[test]
+    fn simple_softmax() {
+        let device = device();
+        let mut a = from_f32(&[-10.0, -10.0, -10.0, -10.0], vec![2, 2], &device);
+        softmax(&mut a).unwrap();
+        assert_eq!(to_f32(&a), [0.5, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn simple_normalize() {
+        let device = device();
+        let mut a = from_f32(&[1.0, 3.0, 5.0, 7.0], vec![2, 2], &device);
+        normalize(&mut a, 1e-5).unwrap();
+        assert_eq!(simplify(&to_f32(&a)), [-1.0, 1.0, -1.0, 1.0]);
+    }
+
+    #[test]
+    fn simple_broadcast_add() {
+        let device = device();
+        let a = from_f32(&[1.0, 2.0], vec![2], &device);
+        let mut b = from_f32(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device);
+        broadcast_add(&a, &mut b).unwrap();
+        assert_eq!(to_f32(&b), [2.0, 4.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn f32_roundtrip() {
+        let device = device();
+        let a = F32Tensor::from_cpu(&[1.0, 0.5, -2.0, 1e-3], vec![2, 2], device.f32()).unwrap();
+        let half = Tensor::from_f32(&a).unwrap();
+        assert_eq!(half.shape(), [2, 2]);
+        assert_eq!(
+            simplify(&half.to_f32().unwrap().cpu_data().unwrap()),
+            [1.0, 0.5, -2.0, 0.001]
+        );
+    }
+}
@@ -0,0 +1,6 @@
+#include "binary_op_macros.cuh"
+
+OP(__half, add_fwd_f16, x + y)
+OP(__half, mul_fwd_f16, x * y)
+BROADCAST_OP(__half, badd_fwd_f16, x + y)
+BROADCAST_OP(__half, bmul_fwd_f16, x * y)
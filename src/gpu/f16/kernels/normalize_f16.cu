@@ -0,0 +1,34 @@
+#include "cuda_fp16.h"
+
+extern "C" __global__ void normalize_f16( 
+    const size_t numel, 
+    __half *lhs, 
+    const size_t size, 
+    const float epsilon
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+
+    const size_t offset = i * size;
+
+    // Accumulate in f32, a sum over the hidden dimension overflows f16.
+    float sum = 0.0;
+    for (int i=0; i < size; i ++){
+	sum += __half2float(lhs[offset + i]);
+    }
+    const float mean = sum / size;
+
+    float var = 0.0;
+    for (int i=0; i < size; i ++){
+	const float v = __half2float(lhs[offset + i]) - mean;
+	var += v * v;
+    }
+    var /= size;
+    var += epsilon;
+    const float std = sqrt(var);
+    for (int i=0; i < size; i ++){
+	lhs[offset + i] = __float2half((__half2float(lhs[offset + i]) - mean) / std);
+    }
+} 
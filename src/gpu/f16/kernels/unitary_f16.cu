@@ -0,0 +1,85 @@
+#include "cuda_fp16.h"
+
+__device__ float gelu_fwd(float x) {
+    constexpr float fastCoeff = 0.044715;
+    float x_sq = x * x;
+    float x_cube = x_sq * x;
+    float alpha = x + fastCoeff * x_cube;
+    return 0.5 * x * (1.0 + tanhf(M_2_SQRTPI * M_SQRT1_2 * alpha));
+}
+
+extern "C" __global__ void tanh_f16( 
+    const size_t numel, 
+    __half *x
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2half(tanhf(__half2float(x[i])));
+} 
+
+extern "C" __global__ void gelu_f16( 
+    const size_t numel, 
+    __half *x 
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2half(gelu_fwd(__half2float(x[i])));
+} 
+
+#define UNARY_OP(NAME, FUNC) \
+extern "C" __global__ void NAME( \
+    const size_t numel, \
+    __half *x \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float v = __half2float(x[i]); \
+    x[i] = __float2half(FUNC); \
+}
+
+UNARY_OP(gelu_erf_f16, 0.5f * v * (1.0f + erff(v * (float)M_SQRT1_2)))
+UNARY_OP(relu_f16, fmaxf(v, 0.0f))
+UNARY_OP(silu_f16, v / (1.0f + expf(-v)))
+UNARY_OP(quick_gelu_f16, v / (1.0f + expf(-1.702f * v)))
+
+extern "C" __global__ void mul_scalar_f16( 
+    const size_t numel, 
+    __half *x ,
+    float factor
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    x[i] = __float2half(__half2float(x[i]) * factor);
+} 
+
+extern "C" __global__ void f32_to_f16( 
+    const size_t numel, 
+    const float *src,
+    __half *dst
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    dst[i] = __float2half(src[i]);
+} 
+
+extern "C" __global__ void f16_to_f32( 
+    const size_t numel, 
+    const __half *src,
+    float *dst
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    dst[i] = __half2float(src[i]);
+} 
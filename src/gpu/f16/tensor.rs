@@ -0,0 +1,152 @@
+use crate::gpu::f16::ops;
+use crate::gpu::f32::{Device as F32Device, Tensor as F32Tensor};
+use crate::SmeltError;
+use cudarc::cublas::safe::CudaBlas;
+use cudarc::driver::{CudaDevice, CudaSlice, DriverError};
+use half::f16;
+use std::sync::Arc;
+
+/// Tensor, holding half precision floats on the device
+#[derive(Clone)]
+pub struct Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: CudaSlice<f16>,
+}
+
+/// The GPU device for half precision tensors. It wraps a [crate::gpu::f32::Device], so
+/// both precisions can share the same handles.
+#[derive(Clone)]
+pub struct Device {
+    device: F32Device,
+}
+
+impl Device {
+    /// Creates the device `device_id`.
+    pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        Ok(Self {
+            device: F32Device::new(device_id)?,
+        })
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id()
+    }
+
+    /// The underlying cuda device
+    pub fn cuda(&self) -> &Arc<CudaDevice> {
+        self.device.cuda()
+    }
+
+    /// The f32 device sharing the same handles
+    pub fn f32(&self) -> &F32Device {
+        &self.device
+    }
+}
+
+impl From<F32Device> for Device {
+    fn from(device: F32Device) -> Self {
+        Self { device }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    /// ```
+    /// use smelte_rs::gpu::f16::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [CudaSlice] holding the data
+    pub fn data(&self) -> &CudaSlice<f16> {
+        &self.data
+    }
+
+    /// A mutable borrow of [CudaSlice] holding the data
+    pub fn data_mut(&mut self) -> &mut CudaSlice<f16> {
+        &mut self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The underlying cuda device
+    pub fn cuda(&self) -> Arc<CudaDevice> {
+        self.device.cuda().clone()
+    }
+
+    /// The CudaBlas handle
+    pub fn blas(&self) -> Arc<CudaBlas> {
+        self.device.device.blas().clone()
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id()
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```
+    /// use smelte_rs::gpu::f16::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, DriverError> {
+        // TODO Reuse the freed buffers like the f32 tensors.
+        let nelement: usize = shape.iter().product();
+        let data = device.cuda().alloc_zeros(nelement)?;
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a tensor from a cpu [Vec].
+    pub fn from_cpu(data: &[f16], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let data = device.cuda().htod_sync_copy(data)?;
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Converts a f32 tensor, on its own device. Weights stored in f32 can be uploaded
+    /// once and converted on the device.
+    pub fn from_f32(tensor: &F32Tensor) -> Result<Self, SmeltError> {
+        let device = Device::from(tensor.device().clone());
+        let mut out = Self::zeros(tensor.shape().to_vec(), &device)?;
+        ops::cast_from_f32(tensor, &mut out)?;
+        Ok(out)
+    }
+
+    /// Converts the tensor to f32, on the same device.
+    pub fn to_f32(&self) -> Result<F32Tensor, SmeltError> {
+        let mut out = F32Tensor::zeros(self.shape.clone(), self.device.f32())?;
+        ops::cast_to_f32(self, &mut out)?;
+        Ok(out)
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f16>, SmeltError> {
+        let cpu_data = self.device.cuda().dtoh_sync_copy(&self.data)?;
+        Ok(cpu_data)
+    }
+}
@@ -0,0 +1,186 @@
+use crate::gpu::f32::{CudaError, Tensor};
+use crate::shape;
+use crate::traits::FusedAttentionConfig;
+use crate::SmeltError;
+use cudarc::driver::{LaunchAsync, LaunchConfig};
+
+const ATTENTION_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/attention.ptx"));
+/// The queries of a block, one thread each.
+const QUERY_BLOCK: usize = 64;
+/// The keys and values loaded at once in the shared memory of a block.
+const KEY_TILE: usize = 32;
+/// The largest head dimension of the accumulators of the kernel.
+const MAX_HEAD_DIM: usize = 256;
+/// The `flags` of the kernel.
+const HAS_MASK: u32 = 1;
+const HAS_SLOPES: u32 = 2;
+const CAUSAL: u32 = 4;
+
+/// Scaled dot-product attention without the scores, see
+/// [crate::traits::TensorFusedAttention]. A block of threads runs 64 queries over the
+/// tiles of keys and values it loads in shared memory, `head_dim` is at most 256.
+/// ```
+/// use smelte_rs::gpu::f32::{fused_attention, Device, Tensor};
+/// use smelte_rs::traits::FusedAttentionConfig;
+///
+/// let device = Device::new(0).unwrap();
+/// let query = Tensor::from_cpu(&[1.0, 0.0], vec![1, 1, 2], &device).unwrap();
+/// let key = Tensor::from_cpu(&[1.0, 0.0, 0.0, 1.0], vec![1, 2, 2], &device).unwrap();
+/// let value = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![1, 2, 2], &device).unwrap();
+/// let mask = Tensor::from_cpu(&[0.0, f32::NEG_INFINITY], vec![2], &device).unwrap();
+/// let config = FusedAttentionConfig::default();
+/// let mut out = Tensor::zeros(vec![1, 1, 2], &device).unwrap();
+/// fused_attention(&query, &key, &value, Some(&mask), None, &config, &mut out).unwrap();
+/// assert_eq!(out.cpu_data().unwrap(), [1.0, 2.0]);
+/// ```
+pub fn fused_attention(
+    query: &Tensor,
+    key: &Tensor,
+    value: &Tensor,
+    mask: Option<&Tensor>,
+    slopes: Option<&Tensor>,
+    config: &FusedAttentionConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (query_length, key_length, head_dim) = shape::attention(
+        query.shape(),
+        key.shape(),
+        value.shape(),
+        mask.map(|mask| mask.shape()),
+        slopes.map(|slopes| slopes.shape()),
+        out.shape(),
+    )?;
+    if head_dim > MAX_HEAD_DIM {
+        return Err(SmeltError::InvalidConfig(format!(
+            "the fused attention supports heads of at most {MAX_HEAD_DIM}, not {head_dim}"
+        )));
+    }
+    let devices = [key.device_id(), value.device_id(), out.device_id()];
+    let optional = [mask, slopes].map(|t| t.map(|t| t.device_id()));
+    if let Some(got) = devices
+        .into_iter()
+        .chain(optional.into_iter().flatten())
+        .find(|&d| d != query.device_id())
+    {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got,
+            expected: query.device_id(),
+        }));
+    }
+    let num_heads = query.shape()[0];
+    if num_heads * query_length * head_dim == 0 {
+        return Ok(());
+    }
+    let dev = query.cuda();
+    let module_name = "fused_attention_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(ATTENTION_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig {
+        grid_dim: (
+            query_length.div_ceil(QUERY_BLOCK) as u32,
+            num_heads as u32,
+            1,
+        ),
+        block_dim: (QUERY_BLOCK as u32, 1, 1),
+        shared_mem_bytes: (2 * KEY_TILE * head_dim * std::mem::size_of::<f32>()) as u32,
+    };
+    let flags = [
+        (mask.is_some(), HAS_MASK),
+        (slopes.is_some(), HAS_SLOPES),
+        (config.causal, CAUSAL),
+    ]
+    .into_iter()
+    .filter_map(|(set, flag)| set.then_some(flag))
+    .sum::<u32>();
+    // The kernel never reads the missing mask or slopes, the query stands in for them.
+    let params = (
+        query_length,
+        key_length,
+        head_dim,
+        config.scale,
+        flags,
+        query.data(),
+        key.data(),
+        value.data(),
+        mask.unwrap_or(query).data(),
+        slopes.unwrap_or(query).data(),
+        out.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::f32::{add, matmul, matmul_t, mul_scalar, softmax, Device};
+
+    #[test]
+    fn test_fused_attention() {
+        let device = Device::new(0).unwrap();
+        // More keys than a tile, and a causal bias.
+        let (num_heads, length, head_dim) = (2, 40, 4);
+        let data: Vec<f32> = (0..num_heads * length * head_dim)
+            .map(|i| ((i * 7) % 11) as f32 / 11.0 - 0.5)
+            .collect();
+        let shape = vec![num_heads, length, head_dim];
+        let query = Tensor::from_cpu(&data, shape.clone(), &device).unwrap();
+        let shifted: Vec<f32> = data[8..].iter().chain(&data[..8]).copied().collect();
+        let key = Tensor::from_cpu(&shifted, shape.clone(), &device).unwrap();
+        let reversed: Vec<f32> = data.iter().rev().copied().collect();
+        let value = Tensor::from_cpu(&reversed, shape.clone(), &device).unwrap();
+        // The bias of the padded fifth key, the alibi slopes and the causal mask.
+        let mut padding = vec![0.0; length];
+        padding[4] = f32::NEG_INFINITY;
+        let slopes = [0.5, 0.25];
+        let bias: Vec<f32> = (0..num_heads * length * length)
+            .map(|n| {
+                let (h, i, j) = (n / (length * length), (n / length) % length, n % length);
+                if j > i {
+                    f32::NEG_INFINITY
+                } else {
+                    padding[j] - slopes[h] * (i - j) as f32
+                }
+            })
+            .collect();
+        let bias = Tensor::from_cpu(&bias, vec![num_heads, length, length], &device).unwrap();
+        let mask = Tensor::from_cpu(&padding, vec![length], &device).unwrap();
+        let slopes = Tensor::from_cpu(&slopes, vec![num_heads], &device).unwrap();
+        let config = FusedAttentionConfig {
+            scale: 0.5,
+            causal: true,
+        };
+
+        let mut out = Tensor::zeros(shape.clone(), &device).unwrap();
+        fused_attention(
+            &query,
+            &key,
+            &value,
+            Some(&mask),
+            Some(&slopes),
+            &config,
+            &mut out,
+        )
+        .unwrap();
+
+        let mut scores = Tensor::zeros(vec![num_heads, length, length], &device).unwrap();
+        matmul_t(&query, &key, &mut scores).unwrap();
+        mul_scalar(&mut scores, 0.5).unwrap();
+        add(&bias, &mut scores).unwrap();
+        softmax(&mut scores).unwrap();
+        let mut expected = Tensor::zeros(shape.clone(), &device).unwrap();
+        matmul(&scores, &value, &mut expected).unwrap();
+        let (out, expected) = (out.cpu_data().unwrap(), expected.cpu_data().unwrap());
+        assert!(out.iter().zip(&expected).all(|(o, e)| (o - e).abs() < 1e-5));
+
+        let config = FusedAttentionConfig::default();
+        let mut out = Tensor::zeros(vec![num_heads, length, 2], &device).unwrap();
+        assert!(fused_attention(&query, &key, &value, None, None, &config, &mut out).is_err());
+        // The accumulators of the kernel hold at most 256 items.
+        let large = Tensor::zeros(vec![1, 1, 512], &device).unwrap();
+        let mut out = Tensor::zeros(vec![1, 1, 512], &device).unwrap();
+        assert!(fused_attention(&large, &large, &large, None, None, &config, &mut out).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::gpu::f32::{CudaError, Tensor};
+use crate::shape;
+use crate::traits::{Conv1dConfig, Conv2dConfig};
+use crate::SmeltError;
+use cudarc::driver::{LaunchAsync, LaunchConfig};
+
+const CONV_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/conv.ptx"));
+const BLOCK_SIZE: usize = 256;
+/// The largest kernel of the depthwise kernel, held in shared memory.
+const MAX_DEPTHWISE_KERNEL: usize = 4096;
+
+/// 1d convolution of `x` (batch_size, in_channels, length) by `weight` (out_channels,
+/// in_channels / groups, kernel_size), plus the optional `bias` (out_channels).
+pub fn conv1d(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv1dConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let expected = shape::conv1d(x.shape(), weight.shape(), bias.map(|b| b.shape()), config)?;
+    let size = [1, x.shape()[2]];
+    let kernel_size = [1, weight.shape()[2]];
+    let config = Conv2dConfig::from(*config);
+    conv(x, weight, bias, &config, size, kernel_size, &expected, out)
+}
+
+/// 2d convolution of `x` (batch_size, in_channels, height, width) by `weight`
+/// (out_channels, in_channels / groups, kernel_height, kernel_width), plus the optional
+/// `bias` (out_channels).
+pub fn conv2d(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv2dConfig,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let expected = shape::conv2d(x.shape(), weight.shape(), bias.map(|b| b.shape()), config)?;
+    let size = [x.shape()[2], x.shape()[3]];
+    let kernel_size = [weight.shape()[2], weight.shape()[3]];
+    conv(x, weight, bias, config, size, kernel_size, &expected, out)
+}
+
+/// Runs the convolution kernel once the shapes of the inputs are checked, `expected`
+/// being the shape of `out`.
+#[allow(clippy::too_many_arguments)]
+fn conv(
+    x: &Tensor,
+    weight: &Tensor,
+    bias: Option<&Tensor>,
+    config: &Conv2dConfig,
+    [height, width]: [usize; 2],
+    kernel_size: [usize; 2],
+    expected: &[usize],
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected.to_vec(),
+            got: out.shape().to_vec(),
+        });
+    }
+    for tensor in [Some(weight), bias, Some(&*out)].into_iter().flatten() {
+        if tensor.device_id() != x.device_id() {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: tensor.device_id(),
+                expected: x.device_id(),
+            }));
+        }
+    }
+    let numel = expected.iter().product::<usize>();
+    if numel == 0 {
+        return Ok(());
+    }
+    let [out_height, out_width] = config.output_size([height, width], kernel_size).unwrap();
+    // Every input channel on its own, one plane of `out` per row of the grid.
+    let kernel_numel = kernel_size[0] * kernel_size[1];
+    let depthwise = config.groups == x.shape()[1] && kernel_numel <= MAX_DEPTHWISE_KERNEL;
+    let dev = x.cuda();
+    let module_name = if depthwise {
+        "depthwise_conv2d_f32"
+    } else {
+        "conv2d_f32"
+    };
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(CONV_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let plane_numel = out_height * out_width;
+    let (numel, cfg) = if depthwise {
+        let cfg = LaunchConfig {
+            grid_dim: (
+                plane_numel.div_ceil(BLOCK_SIZE) as u32,
+                (numel / plane_numel) as u32,
+                1,
+            ),
+            block_dim: (BLOCK_SIZE as u32, 1, 1),
+            shared_mem_bytes: (kernel_numel * std::mem::size_of::<f32>()) as u32,
+        };
+        (plane_numel, cfg)
+    } else {
+        (numel, LaunchConfig::for_num_elems(numel as u32))
+    };
+    let info = dev.htod_copy(vec![
+        x.shape()[1],
+        height,
+        width,
+        weight.shape()[0],
+        out_height,
+        out_width,
+        kernel_size[0],
+        kernel_size[1],
+        config.stride[0],
+        config.stride[1],
+        config.padding[0],
+        config.padding[1],
+        config.dilation[0],
+        config.dilation[1],
+        config.groups,
+    ])?;
+    let params = (
+        numel,
+        &info,
+        x.data(),
+        weight.data(),
+        bias.unwrap_or(weight).data(),
+        bias.is_some() as i32,
+        out.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::f32::Device;
+
+    #[test]
+    fn test_conv1d() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..20).map(|i| i as f32).collect();
+        let x = Tensor::from_cpu(&data, vec![1, 4, 5], &device).unwrap();
+        let weight = Tensor::from_cpu(&[1.0; 12], vec![2, 2, 3], &device).unwrap();
+        let bias = Tensor::from_cpu(&[0.5, -0.5], vec![2], &device).unwrap();
+        let config = Conv1dConfig {
+            stride: 2,
+            padding: 1,
+            dilation: 1,
+            groups: 2,
+        };
+        let mut out = Tensor::zeros(vec![1, 2, 3], &device).unwrap();
+        conv1d(&x, &weight, Some(&bias), &config, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            [12.5, 27.5, 24.5, 51.5, 86.5, 63.5]
+        );
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let x = Tensor::from_cpu(&data, vec![1, 2, 2, 3], &device).unwrap();
+        let weight = [1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0];
+        let weight = Tensor::from_cpu(&weight, vec![2, 1, 2, 2], &device).unwrap();
+        let config = Conv2dConfig {
+            stride: [1, 2],
+            padding: [0, 1],
+            dilation: [1, 1],
+            groups: 2,
+        };
+        let mut out = Tensor::zeros(vec![1, 2, 1, 2], &device).unwrap();
+        conv2d(&x, &weight, None, &config, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [3.0, 6.0, 6.0, 18.0]);
+    }
+
+    #[test]
+    fn test_depthwise_conv2d() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let x = Tensor::from_cpu(&data, vec![1, 2, 2, 3], &device).unwrap();
+        // Two kernels per input channel.
+        let weight = [
+            1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 2.0,
+        ];
+        let weight = Tensor::from_cpu(&weight, vec![4, 1, 2, 2], &device).unwrap();
+        let bias = Tensor::from_cpu(&[0.5, -0.5, 1.0, 0.0], vec![4], &device).unwrap();
+        let config = Conv2dConfig {
+            groups: 2,
+            ..Default::default()
+        };
+        let mut out = Tensor::zeros(vec![1, 4, 1, 2], &device).unwrap();
+        conv2d(&x, &weight, Some(&bias), &config, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            [4.5, 6.5, 3.5, 5.5, 33.0, 37.0, 20.0, 22.0]
+        );
+    }
+}
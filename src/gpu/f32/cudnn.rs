@@ -0,0 +1,172 @@
+use crate::gpu::f32::{CudaError, Tensor};
+use crate::SmeltError;
+use cudarc::cudnn::result::CudnnError;
+use cudarc::cudnn::sys;
+use cudarc::driver::{CudaDevice, CudaSlice, DevicePtr, DevicePtrMut};
+use std::collections::hash_map::{Entry, HashMap};
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+
+impl From<CudnnError> for SmeltError {
+    fn from(cudnn: CudnnError) -> Self {
+        Self::Cuda(CudaError::CudnnError(cudnn))
+    }
+}
+
+/// The buffers cuDNN needs for a normalization of `rows` rows of `size` floats.
+struct Scratch {
+    // The input, since the normalization cannot run in place.
+    input: CudaSlice<f32>,
+    ones: CudaSlice<f32>,
+    zeros: CudaSlice<f32>,
+}
+
+/// A cuDNN handle bound to the default stream of a device.
+pub(crate) struct Cudnn {
+    handle: sys::cudnnHandle_t,
+    device: Arc<CudaDevice>,
+    // Allocated on first use for every shape, so they exist before a cuda graph capture.
+    scratch: Mutex<HashMap<(usize, usize), Scratch>>,
+}
+
+// SAFETY: cuDNN handles can be used from any thread, as long as it is not concurrently.
+// Every call goes through the default stream of the device, like the other kernels.
+unsafe impl Send for Cudnn {}
+// SAFETY: See above.
+unsafe impl Sync for Cudnn {}
+
+impl Cudnn {
+    pub(crate) fn new(device: Arc<CudaDevice>) -> Result<Self, SmeltError> {
+        let mut handle = std::ptr::null_mut();
+        // SAFETY: `handle` is written by cuDNN.
+        unsafe { sys::cudnnCreate(&mut handle) }.result()?;
+        // SAFETY: The stream lives as long as `device`, which we keep.
+        unsafe { sys::cudnnSetStream(handle, *device.cu_stream() as *mut _) }.result()?;
+        Ok(Self {
+            handle,
+            device,
+            scratch: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl Drop for Cudnn {
+    fn drop(&mut self) {
+        // SAFETY: The handle is not used anymore.
+        unsafe { sys::cudnnDestroy(self.handle) };
+    }
+}
+
+/// A 4d float tensor descriptor, NCHW.
+struct Descriptor(sys::cudnnTensorDescriptor_t);
+
+impl Descriptor {
+    fn new(n: usize, c: usize, h: usize, w: usize) -> Result<Self, CudnnError> {
+        let mut desc = std::ptr::null_mut();
+        // SAFETY: `desc` is written by cuDNN.
+        unsafe { sys::cudnnCreateTensorDescriptor(&mut desc) }.result()?;
+        let desc = Self(desc);
+        // SAFETY: The descriptor was just created.
+        unsafe {
+            sys::cudnnSetTensor4dDescriptor(
+                desc.0,
+                sys::cudnnTensorFormat_t::CUDNN_TENSOR_NCHW,
+                sys::cudnnDataType_t::CUDNN_DATA_FLOAT,
+                n as i32,
+                c as i32,
+                h as i32,
+                w as i32,
+            )
+        }
+        .result()?;
+        Ok(desc)
+    }
+}
+
+impl Drop for Descriptor {
+    fn drop(&mut self) {
+        // SAFETY: The descriptor is not used anymore.
+        unsafe { sys::cudnnDestroyTensorDescriptor(self.0) };
+    }
+}
+
+/// Softmax on the last dimension of `x`, in place, with cuDNN.
+pub(crate) fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+    let n = x.shape()[dim - 1];
+    let rows: usize = x.shape()[..dim - 1].iter().product();
+    let cudnn = x.device().cudnn().clone();
+
+    // Every row is an instance of `n` channels.
+    let desc = Descriptor::new(rows, n, 1, 1)?;
+    let (alpha, beta) = (1.0f32, 0.0f32);
+    let ptr = *x.data_mut().device_ptr_mut() as *mut c_void;
+    // SAFETY: `x` holds `rows * n` floats, cuDNN allows the softmax in place.
+    unsafe {
+        sys::cudnnSoftmaxForward(
+            cudnn.handle,
+            sys::cudnnSoftmaxAlgorithm_t::CUDNN_SOFTMAX_ACCURATE,
+            sys::cudnnSoftmaxMode_t::CUDNN_SOFTMAX_MODE_INSTANCE,
+            &alpha as *const f32 as *const c_void,
+            desc.0,
+            ptr,
+            &beta as *const f32 as *const c_void,
+            desc.0,
+            ptr,
+        )
+    }
+    .result()?;
+    Ok(())
+}
+
+/// x = (x - x.mean()) / (x.var() + epsilon) on the last dimension, with cuDNN.
+/// cuDNN has no layer normalization, but a spatial batch normalization where every row
+/// is a channel computes the same statistics.
+pub(crate) fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+    let size = x.shape()[dim - 1];
+    let rows: usize = x.shape()[..dim - 1].iter().product();
+    let cudnn = x.device().cudnn().clone();
+    let dev = &cudnn.device;
+
+    let mut scratch = cudnn.scratch.lock().unwrap();
+    let buffers = match scratch.entry((rows, size)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(Scratch {
+            input: dev.alloc_zeros(rows * size)?,
+            ones: dev.htod_sync_copy(&vec![1.0f32; rows])?,
+            zeros: dev.alloc_zeros(rows)?,
+        }),
+    };
+    dev.dtod_copy(x.data(), &mut buffers.input)?;
+
+    let desc = Descriptor::new(1, rows, size, 1)?;
+    let bn_desc = Descriptor::new(1, rows, 1, 1)?;
+    let (alpha, beta) = (1.0f32, 0.0f32);
+    // SAFETY: All the buffers have the sizes of their descriptors. Without running
+    // statistics (null pointers), cuDNN only uses the statistics of the batch, which
+    // are the statistics of each row.
+    unsafe {
+        sys::cudnnBatchNormalizationForwardTraining(
+            cudnn.handle,
+            sys::cudnnBatchNormMode_t::CUDNN_BATCHNORM_SPATIAL,
+            &alpha as *const f32 as *const c_void,
+            &beta as *const f32 as *const c_void,
+            desc.0,
+            *buffers.input.device_ptr() as *const c_void,
+            desc.0,
+            *x.data_mut().device_ptr_mut() as *mut c_void,
+            bn_desc.0,
+            *buffers.ones.device_ptr() as *const c_void,
+            *buffers.zeros.device_ptr() as *const c_void,
+            0.0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            epsilon as f64,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    }
+    .result()?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use crate::gpu::f32::Device;
+use crate::SmeltError;
+use cudarc::driver::sys;
+
+/// A cuda graph, the kernels recorded by [Device::capture]. Replaying it launches all of
+/// them at once, on the same buffers.
+pub struct Graph {
+    graph: sys::CUgraph,
+    exec: sys::CUgraphExec,
+    device: Device,
+}
+
+// SAFETY: The graph handles are only used through the cuda driver, which is thread safe.
+unsafe impl Send for Graph {}
+// SAFETY: Same as above, launching a graph does not modify it.
+unsafe impl Sync for Graph {}
+
+impl Device {
+    /// Records all the kernels launched on the device by `f` into a [Graph], without
+    /// running them. Everything used by the kernels (loaded modules, cublas workspace...)
+    /// needs to exist beforehand, run `f` once before capturing it.
+    /// Host to device copies and allocations cannot happen within `f`.
+    pub fn capture<F>(&self, f: F) -> Result<Graph, SmeltError>
+    where
+        F: FnOnce() -> Result<(), SmeltError>,
+    {
+        let stream = *self.cuda().cu_stream();
+        // SAFETY: The stream belongs to the device, which outlives the capture.
+        unsafe {
+            sys::cuStreamBeginCapture_v2(
+                stream,
+                sys::CUstreamCaptureMode::CU_STREAM_CAPTURE_MODE_THREAD_LOCAL,
+            )
+        }
+        .result()?;
+        let recorded = f();
+        let mut graph = std::ptr::null_mut();
+        // SAFETY: Ends the capture started above, even when `f` failed.
+        let ended = unsafe { sys::cuStreamEndCapture(stream, &mut graph) }.result();
+        recorded?;
+        ended?;
+
+        let mut exec = std::ptr::null_mut();
+        // SAFETY: `graph` was just created by the capture.
+        if let Err(err) = unsafe { sys::cuGraphInstantiateWithFlags(&mut exec, graph, 0) }.result()
+        {
+            // SAFETY: Nothing else refers to the graph.
+            unsafe { sys::cuGraphDestroy(graph) };
+            return Err(err.into());
+        }
+        Ok(Graph {
+            graph,
+            exec,
+            device: self.clone(),
+        })
+    }
+}
+
+impl Graph {
+    /// Launches all the recorded kernels on the default stream of the device.
+    pub fn replay(&self) -> Result<(), SmeltError> {
+        // SAFETY: The graph is alive as long as `self`, and the device too.
+        unsafe { sys::cuGraphLaunch(self.exec, *self.device.cuda().cu_stream()) }.result()?;
+        Ok(())
+    }
+}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        // SAFETY: The handles are not used anymore, pending launches keep their own
+        // reference within the driver.
+        unsafe {
+            sys::cuGraphExecDestroy(self.exec);
+            sys::cuGraphDestroy(self.graph);
+        }
+    }
+}
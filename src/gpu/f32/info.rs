@@ -0,0 +1,100 @@
+use crate::gpu::f32::{Device, PoolStats};
+use crate::SmeltError;
+use cudarc::driver::{result, sys, CudaDevice};
+use std::sync::Arc;
+
+/// The properties of a cuda device, see [Device::enumerate].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// The id to give to [Device::new]
+    pub device_id: usize,
+    /// The name of the device, for instance `NVIDIA A100-SXM4-40GB`
+    pub name: String,
+    /// The total memory of the device (in bytes)
+    pub total_memory: usize,
+    /// The memory currently available on the device (in bytes)
+    pub free_memory: usize,
+    /// The (major, minor) compute capability, for instance `(8, 0)` for an A100
+    pub compute_capability: (i32, i32),
+    /// The number of streaming multiprocessors
+    pub multiprocessors: i32,
+}
+
+/// The memory usage of a [Device], see [Device::memory_stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The memory currently available on the device (in bytes).
+    /// Buffers cached by the pool are not part of it.
+    pub free: usize,
+    /// The total memory of the device (in bytes)
+    pub total: usize,
+    /// The buffers cached by the device
+    pub pool: PoolStats,
+}
+
+/// The number of cuda devices.
+pub(crate) fn count() -> Result<usize, SmeltError> {
+    result::init()?;
+    Ok(result::device::get_count()? as usize)
+}
+
+/// The (free, total) memory of `device` in bytes.
+fn mem_info(device: &Arc<CudaDevice>) -> Result<(usize, usize), SmeltError> {
+    device.bind_to_thread()?;
+    let (mut free, mut total) = (0, 0);
+    // SAFETY: The context of `device` is current on this thread.
+    unsafe { sys::cuMemGetInfo_v2(&mut free, &mut total) }.result()?;
+    Ok((free, total))
+}
+
+fn info(device: &Arc<CudaDevice>, device_id: usize) -> Result<DeviceInfo, SmeltError> {
+    use sys::CUdevice_attribute::*;
+    let (free_memory, total_memory) = mem_info(device)?;
+    Ok(DeviceInfo {
+        device_id,
+        name: device.name()?,
+        total_memory,
+        free_memory,
+        compute_capability: (
+            device.attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)?,
+            device.attribute(CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)?,
+        ),
+        multiprocessors: device.attribute(CU_DEVICE_ATTRIBUTE_MULTIPROCESSOR_COUNT)?,
+    })
+}
+
+impl Device {
+    /// All the cuda devices available, for instance to pick the one with the most free
+    /// memory. Having no device (or no driver) is not an error, the list is empty.
+    /// ```no_run
+    /// use smelte_rs::gpu::f32::Device;
+    ///
+    /// let devices = Device::enumerate().unwrap();
+    /// let best = devices.iter().max_by_key(|info| info.free_memory).unwrap();
+    /// let device = Device::new(best.device_id).unwrap();
+    /// ```
+    pub fn enumerate() -> Result<Vec<DeviceInfo>, SmeltError> {
+        // TODO Distinguish a missing driver from a broken one.
+        let Ok(num_devices) = count() else {
+            return Ok(vec![]);
+        };
+        (0..num_devices)
+            .map(|device_id| info(&CudaDevice::new(device_id)?, device_id))
+            .collect()
+    }
+
+    /// The properties of this device.
+    pub fn info(&self) -> Result<DeviceInfo, SmeltError> {
+        info(self.cuda(), self.device_id())
+    }
+
+    /// The current memory usage of this device.
+    pub fn memory_stats(&self) -> Result<MemoryStats, SmeltError> {
+        let (free, total) = mem_info(self.cuda())?;
+        Ok(MemoryStats {
+            free,
+            total,
+            pool: self.pool_stats(),
+        })
+    }
+}
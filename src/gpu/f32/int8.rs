@@ -0,0 +1,128 @@
+use crate::gpu::f32::{Device, Tensor};
+use crate::SmeltError;
+use cudarc::driver::{CudaDevice, CudaSlice, DeviceSlice, LaunchAsync, LaunchConfig};
+use std::sync::Arc;
+
+const UNITARY_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/unitary.ptx"));
+
+/// An int8 tensor on a cuda device, see [Tensor::to_i8].
+#[derive(Clone)]
+pub struct I8Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: CudaSlice<i8>,
+}
+
+impl I8Tensor {
+    /// Creates a new nulled tensor with given shape
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = device.cuda().alloc_zeros(nelement)?;
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// The shape of the tensor
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [CudaSlice] holding the data
+    pub fn data(&self) -> &CudaSlice<i8> {
+        &self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<i8>, SmeltError> {
+        Ok(self.device.cuda().dtoh_sync_copy(&self.data)?)
+    }
+
+    /// The f32 copy of the tensor, `v * scale`, on the same device.
+    pub fn to_f32(&self, scale: f32) -> Result<Tensor, SmeltError> {
+        let mut out = Tensor::zeros(self.shape.clone(), &self.device)?;
+        launch(
+            self.device.cuda(),
+            "i8_to_f32",
+            self.data(),
+            out.data_mut(),
+            scale,
+        )?;
+        Ok(out)
+    }
+}
+
+impl Tensor {
+    /// The int8 copy of the tensor on the same device, `round(v / scale)` saturating at
+    /// `[-127, 127]`.
+    pub fn to_i8(&self, scale: f32) -> Result<I8Tensor, SmeltError> {
+        let mut out = I8Tensor::zeros(self.shape().to_vec(), self.device())?;
+        launch(
+            self.device().cuda(),
+            "f32_to_i8",
+            self.data(),
+            &mut out.data,
+            scale,
+        )?;
+        Ok(out)
+    }
+
+    /// The half precision copy of the tensor, see [crate::gpu::f16::Tensor::from_f32].
+    #[cfg(feature = "f16")]
+    pub fn to_f16(&self) -> Result<crate::gpu::f16::Tensor, SmeltError> {
+        crate::gpu::f16::Tensor::from_f32(self)
+    }
+
+    /// The bfloat16 copy of the tensor, see [crate::gpu::bf16::Tensor::from_f32].
+    #[cfg(feature = "bf16")]
+    pub fn to_bf16(&self) -> Result<crate::gpu::bf16::Tensor, SmeltError> {
+        crate::gpu::bf16::Tensor::from_f32(self)
+    }
+}
+
+/// Runs the cast kernel `module_name` from `src` to `dst`, both on `dev`.
+fn launch<S, D>(
+    dev: &Arc<CudaDevice>,
+    module_name: &'static str,
+    src: &CudaSlice<S>,
+    dst: &mut CudaSlice<D>,
+    scale: f32,
+) -> Result<(), SmeltError> {
+    if src.len() != dst.len() {
+        return Err(SmeltError::InvalidLength {
+            expected: dst.len(),
+            got: src.len(),
+        });
+    }
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel = dst.len();
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, src, dst, scale);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_i8() {
+        let device = Device::new(0).unwrap();
+        let tensor = Tensor::from_cpu(&[0.5, -1.0, 100.0], vec![3], &device).unwrap();
+        let tensor = tensor.to_i8(0.1).unwrap();
+        assert_eq!(tensor.cpu_data().unwrap(), [5, -10, 127]);
+        let tensor = tensor.to_f32(0.1).unwrap();
+        assert_eq!(tensor.cpu_data().unwrap(), [0.5, -1.0, 12.7]);
+    }
+}
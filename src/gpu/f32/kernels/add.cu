@@ -1,8 +1,11 @@
 #include "binary_op_macros.cuh"
 
 OP(float, add_fwd_f32, x + y)
+OP(float, sub_fwd_f32, y - x)
 OP(float, mul_fwd_f32, x * y)
 BROADCAST_OP(float, badd_fwd_f32, x + y)
+BROADCAST_OP(float, bsub_fwd_f32, y - x)
 BROADCAST_OP(float, bmul_fwd_f32, x * y)
-
-
+STRIDED_OP(float, sadd_fwd_f32, x + y)
+STRIDED_OP(float, ssub_fwd_f32, y - x)
+STRIDED_OP(float, smul_fwd_f32, x * y)
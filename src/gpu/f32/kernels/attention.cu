@@ -0,0 +1,96 @@
+#include "cuda_utils.cuh"
+
+#define KEY_TILE 32
+#define MAX_HEAD_DIM 256
+
+// The `flags` of the fused attention.
+#define HAS_MASK 1
+#define HAS_SLOPES 2
+#define CAUSAL 4
+
+// Scaled dot-product attention of `query` (num_heads, query_length, head_dim) over `key`
+// and `value` (num_heads, key_length, head_dim), one thread per query and one block row
+// per head. The keys and values are loaded by tiles of KEY_TILE in shared memory and the
+// softmax is computed online, the scores are never stored. The query `i` is at the
+// position `p = key_length - query_length + i`: its score with the key `j` is biased by
+// `mask[j]` (key_length) with HAS_MASK, by `-slopes[head] * |p - j|` (num_heads) with
+// HAS_SLOPES, and the keys after `p` are skipped with CAUSAL. The launcher checks that
+// `head_dim` is at most MAX_HEAD_DIM.
+extern "C" __global__ void fused_attention_f32(
+    const size_t query_length,
+    const size_t key_length,
+    const size_t head_dim,
+    const float scale,
+    const unsigned int flags,
+    const float *query,
+    const float *key,
+    const float *value,
+    const float *mask,
+    const float *slopes,
+    float *out
+) {
+    extern __shared__ float tiles[];
+    float *key_tile = tiles;
+    float *value_tile = tiles + KEY_TILE * head_dim;
+
+    const size_t head = blockIdx.y;
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+    const bool active = i < query_length;
+    const float *q = query + (head * query_length + i) * head_dim;
+    key += head * key_length * head_dim;
+    value += head * key_length * head_dim;
+    const size_t position = (key_length > query_length ? key_length - query_length : 0) + i;
+    const float slope = flags & HAS_SLOPES ? slopes[head] : 0.0;
+
+    float acc[MAX_HEAD_DIM];
+    for (size_t d = 0; d < head_dim; d++) {
+        acc[d] = 0.0;
+    }
+    float current_max = -1 * INFINITY;
+    float sum = 0.0;
+    for (size_t start = 0; start < key_length; start += KEY_TILE) {
+        const size_t tile = key_length - start < KEY_TILE ? key_length - start : KEY_TILE;
+        // Every thread of the block loads a part of the tile, even without a query.
+        for (size_t k = threadIdx.x; k < tile * head_dim; k += blockDim.x) {
+            key_tile[k] = key[start * head_dim + k];
+            value_tile[k] = value[start * head_dim + k];
+        }
+        __syncthreads();
+        if (active) {
+            for (size_t j = 0; j < tile; j++) {
+                const size_t k = start + j;
+                if ((flags & CAUSAL) && k > position) {
+                    break;
+                }
+                float score = 0.0;
+                for (size_t d = 0; d < head_dim; d++) {
+                    score += q[d] * key_tile[j * head_dim + d];
+                }
+                score *= scale;
+                score -= slope * (float)(k > position ? k - position : position - k);
+                if (flags & HAS_MASK) {
+                    score += mask[k];
+                }
+                const float new_max = maxg(current_max, score);
+                if (new_max == -1 * INFINITY) {
+                    continue;
+                }
+                // Rescales what was accumulated for the previous keys to the new maximum.
+                const float correction = exp(current_max - new_max);
+                const float p = exp(score - new_max);
+                sum = sum * correction + p;
+                for (size_t d = 0; d < head_dim; d++) {
+                    acc[d] = acc[d] * correction + p * value_tile[j * head_dim + d];
+                }
+                current_max = new_max;
+            }
+        }
+        __syncthreads();
+    }
+    if (active) {
+        float *o = out + (head * query_length + i) * head_dim;
+        for (size_t d = 0; d < head_dim; d++) {
+            o[d] = acc[d] / sum;
+        }
+    }
+}
@@ -46,3 +46,36 @@ extern "C" __global__ void FORWARD( \
 
 #define BROADCAST_OP(TYPENAME, FORWARD, FUNC) \
     LONG_BROADCAST_OP(TYPENAME, FORWARD, fx = (FUNC);)
+
+// `lhs` is read with the strides `info[rank..2 * rank]` for the shape `info[..rank]` of
+// `rhs`, 0 on the broadcasted dimensions.
+#define LONG_STRIDED_OP(TYPENAME, FORWARD, FUNC) \
+extern "C" __global__ void FORWARD( \
+    const size_t numel, \
+    const size_t rank, \
+    const size_t *info, \
+    const TYPENAME *lhs, \
+    TYPENAME *rhs \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+\
+    size_t index = i; \
+    size_t offset = 0; \
+    for (size_t d = rank; d-- > 0;) { \
+        offset += (index % info[d]) * info[rank + d]; \
+        index /= info[d]; \
+    } \
+    TYPENAME x = lhs[offset]; \
+    TYPENAME y = rhs[i]; \
+    TYPENAME fx; \
+\
+    FUNC\
+\
+    rhs[i] = fx; \
+} \
+
+#define STRIDED_OP(TYPENAME, FORWARD, FUNC) \
+    LONG_STRIDED_OP(TYPENAME, FORWARD, fx = (FUNC);)
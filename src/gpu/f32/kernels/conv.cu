@@ -0,0 +1,111 @@
+// 2d convolution of `x` (batch_size, in_channels, height, width) by `weight`
+// (out_channels, in_channels / groups, kernel_height, kernel_width), one thread per item
+// of `out` (batch_size, out_channels, out_height, out_width). `info` holds in_channels,
+// height, width, out_channels, out_height, out_width, kernel_height, kernel_width, then
+// the strides, paddings and dilations of both axes and the groups. `bias` is only read
+// when `has_bias` is set. The 1d convolutions have a height of 1.
+extern "C" __global__ void conv2d_f32(
+    const size_t numel,
+    const size_t *info,
+    const float *x,
+    const float *weight,
+    const float *bias,
+    const int has_bias,
+    float *out
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    const size_t in_channels = info[0], height = info[1], width = info[2];
+    const size_t out_channels = info[3], out_height = info[4], out_width = info[5];
+    const size_t kernel_height = info[6], kernel_width = info[7];
+    const size_t stride_h = info[8], stride_w = info[9];
+    const size_t padding_h = info[10], padding_w = info[11];
+    const size_t dilation_h = info[12], dilation_w = info[13];
+    const size_t groups = info[14];
+
+    const size_t ow = i % out_width;
+    const size_t oh = (i / out_width) % out_height;
+    const size_t oc = (i / (out_width * out_height)) % out_channels;
+    const size_t b = i / (out_width * out_height * out_channels);
+    const size_t group_channels = in_channels / groups;
+    const size_t first_channel = (oc / (out_channels / groups)) * group_channels;
+
+    float sum = has_bias ? bias[oc] : 0.0;
+    for (size_t c = 0; c < group_channels; c++) {
+        const float *image = x + (b * in_channels + first_channel + c) * height * width;
+        const float *kernel = weight + (oc * group_channels + c) * kernel_height * kernel_width;
+        for (size_t ki = 0; ki < kernel_height; ki++) {
+            const size_t y = oh * stride_h + ki * dilation_h;
+            if (y < padding_h || y - padding_h >= height) {
+                continue;
+            }
+            for (size_t kj = 0; kj < kernel_width; kj++) {
+                const size_t z = ow * stride_w + kj * dilation_w;
+                if (z >= padding_w && z - padding_w < width) {
+                    sum += image[(y - padding_h) * width + z - padding_w]
+                        * kernel[ki * kernel_width + kj];
+                }
+            }
+        }
+    }
+    out[i] = sum;
+}
+
+// The depthwise case of `conv2d_f32`, groups == in_channels: every block of the grid y
+// computes `plane_numel` items of one plane (b, oc) of `out`, with the kernel of `oc` in
+// shared memory. `info` is the one of `conv2d_f32`.
+extern "C" __global__ void depthwise_conv2d_f32(
+    const size_t plane_numel,
+    const size_t *info,
+    const float *x,
+    const float *weight,
+    const float *bias,
+    const int has_bias,
+    float *out
+) {
+    extern __shared__ float kernel[];
+
+    const size_t in_channels = info[0], height = info[1], width = info[2];
+    const size_t out_channels = info[3], out_width = info[5];
+    const size_t kernel_height = info[6], kernel_width = info[7];
+    const size_t stride_h = info[8], stride_w = info[9];
+    const size_t padding_h = info[10], padding_w = info[11];
+    const size_t dilation_h = info[12], dilation_w = info[13];
+
+    const size_t plane = blockIdx.y;
+    const size_t oc = plane % out_channels;
+    const size_t b = plane / out_channels;
+    const size_t kernel_numel = kernel_height * kernel_width;
+    for (size_t k = threadIdx.x; k < kernel_numel; k += blockDim.x) {
+        kernel[k] = weight[oc * kernel_numel + k];
+    }
+    __syncthreads();
+
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= plane_numel) {
+        return;
+    }
+    const size_t ow = i % out_width;
+    const size_t oh = i / out_width;
+    const size_t channel = oc / (out_channels / in_channels);
+    const float *image = x + (b * in_channels + channel) * height * width;
+
+    float sum = has_bias ? bias[oc] : 0.0;
+    for (size_t ki = 0; ki < kernel_height; ki++) {
+        const size_t y = oh * stride_h + ki * dilation_h;
+        if (y < padding_h || y - padding_h >= height) {
+            continue;
+        }
+        for (size_t kj = 0; kj < kernel_width; kj++) {
+            const size_t z = ow * stride_w + kj * dilation_w;
+            if (z >= padding_w && z - padding_w < width) {
+                sum += image[(y - padding_h) * width + z - padding_w]
+                    * kernel[ki * kernel_width + kj];
+            }
+        }
+    }
+    out[plane * plane_numel + i] = sum;
+}
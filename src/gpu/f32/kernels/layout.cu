@@ -0,0 +1,125 @@
+#include "cuda_utils.cuh"
+
+// Copies the items of `src` starting at `offset`, with the strides `info[rank..2 * rank]`
+// for the shape `info[..rank]`, into the contiguous `dst`.
+extern "C" __global__ void copy_strided_f32(
+    const size_t numel,
+    const size_t rank,
+    const size_t *info,
+    const size_t offset,
+    const float *src,
+    float *dst
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    size_t index = i;
+    size_t src_index = offset;
+    for (size_t d = rank; d-- > 0;) {
+        src_index += (index % info[d]) * info[rank + d];
+        index /= info[d];
+    }
+    dst[i] = src[src_index];
+}
+
+// Copies the contiguous `src` into the items of `dst` starting at `offset`, with the
+// strides `info[rank..2 * rank]` for the shape `info[..rank]`.
+extern "C" __global__ void copy_into_strided_f32(
+    const size_t numel,
+    const size_t rank,
+    const size_t *info,
+    const size_t offset,
+    const float *src,
+    float *dst
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    size_t index = i;
+    size_t dst_index = offset;
+    for (size_t d = rank; d-- > 0;) {
+        dst_index += (index % info[d]) * info[rank + d];
+        index /= info[d];
+    }
+    dst[dst_index] = src[i];
+}
+
+// Copies the items `ids` of the middle dimension of `src` (outer, size, inner) into
+// `dst` (outer, len, inner).
+extern "C" __global__ void index_select_f32(
+    const size_t numel,
+    const size_t size,
+    const size_t inner,
+    const size_t len,
+    const size_t *ids,
+    const float *src,
+    float *dst
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    size_t j = i % inner;
+    size_t k = (i / inner) % len;
+    size_t o = i / (inner * len);
+    dst[i] = src[(o * size + ids[k]) * inner + j];
+}
+
+// Copies `src` (outer, len, inner) into the items `ids` of the middle dimension of `dst`
+// (outer, size, inner).
+extern "C" __global__ void index_put_f32(
+    const size_t numel,
+    const size_t size,
+    const size_t inner,
+    const size_t len,
+    const size_t *ids,
+    const float *src,
+    float *dst
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    size_t j = i % inner;
+    size_t k = (i / inner) % len;
+    size_t o = i / (inner * len);
+    dst[(o * size + ids[k]) * inner + j] = src[i];
+}
+
+// `src` padded with `value` into `dst`, `info` holding the shape of `dst` then the one
+// of `src`, and `before` the items added before each dimension (negative when cropped).
+extern "C" __global__ void pad_f32(
+    const size_t numel,
+    const size_t rank,
+    const size_t *info,
+    const long long *before,
+    const float value,
+    const float *src,
+    float *dst
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    size_t index = i;
+    size_t src_index = 0;
+    size_t stride = 1;
+    for (size_t d = rank; d-- > 0;) {
+        const long long j = (long long) (index % info[d]) - before[d];
+        index /= info[d];
+        if (j < 0 || j >= (long long) info[rank + d]) {
+            dst[i] = value;
+            return;
+        }
+        src_index += j * stride;
+        stride *= info[rank + d];
+    }
+    dst[i] = src[src_index];
+}
@@ -0,0 +1,134 @@
+#include "cuda_utils.cuh"
+
+// One thread per row of `n` items of `x`, the `mask_numel` items of `mask` cover
+// `mask_numel / n` rows and are repeated over the others.
+extern "C" __global__ void masked_fill_f32(
+    const size_t rows,
+    const size_t n,
+    const size_t mask_numel,
+    const unsigned char *mask,
+    const float value,
+    float *x
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= rows) {
+        return;
+    }
+
+    float *row = x + i * n;
+    const unsigned char *row_mask = mask + (i * n) % mask_numel;
+    for (size_t j = 0; j < n; j++) {
+        if (row_mask[j]) {
+            row[j] = value;
+        }
+    }
+}
+
+extern "C" __global__ void masked_softmax_f32(
+    const size_t rows,
+    const size_t n,
+    const size_t mask_numel,
+    const unsigned char *mask,
+    const float value,
+    float *x
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= rows) {
+        return;
+    }
+
+    float *row = x + i * n;
+    const unsigned char *row_mask = mask + (i * n) % mask_numel;
+    float current_max = -1 * INFINITY;
+    for (size_t j = 0; j < n; j++) {
+        if (!row_mask[j]) {
+            current_max = fmaxf(current_max, row[j]);
+        }
+    }
+
+    float sum = 0.0;
+    for (size_t j = 0; j < n; j++) {
+        const float v = row_mask[j] ? 0.0 : exp(row[j] - current_max);
+        row[j] = v;
+        sum += v;
+    }
+
+    if (sum == 0.0) {
+        return;
+    }
+    for (size_t j = 0; j < n; j++) {
+        row[j] /= sum;
+    }
+}
+
+// The offset in an input read with `strides` of the item `i` of `shape`.
+__device__ size_t broadcast_offset(
+    size_t i,
+    const size_t rank,
+    const size_t *shape,
+    const size_t *strides
+) {
+    size_t offset = 0;
+    for (size_t d = rank; d-- > 0;) {
+        offset += (i % shape[d]) * strides[d];
+        i /= shape[d];
+    }
+    return offset;
+}
+
+// `info` holds the shape of `out`, then the strides of `lhs` and `rhs` broadcasted to it.
+#define COMPARE_OP(FORWARD, OP) \
+extern "C" __global__ void FORWARD( \
+    const size_t numel, \
+    const size_t rank, \
+    const size_t *info, \
+    const float *lhs, \
+    const float *rhs, \
+    unsigned char *out \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+    float x = lhs[broadcast_offset(i, rank, info, info + rank)]; \
+    float y = rhs[broadcast_offset(i, rank, info, info + 2 * rank)]; \
+    out[i] = x OP y; \
+}
+
+COMPARE_OP(gt_f32, >)
+COMPARE_OP(lt_f32, <)
+COMPARE_OP(eq_f32, ==)
+
+// `info` holds the shape of `out`, then the strides of `mask`, `a` and `b`.
+extern "C" __global__ void where_cond_f32(
+    const size_t numel,
+    const size_t rank,
+    const size_t *info,
+    const unsigned char *mask,
+    const float *a,
+    const float *b,
+    float *out
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    if (mask[broadcast_offset(i, rank, info, info + rank)]) {
+        out[i] = a[broadcast_offset(i, rank, info, info + 2 * rank)];
+    } else {
+        out[i] = b[broadcast_offset(i, rank, info, info + 3 * rank)];
+    }
+}
+
+// The (length, length) causal mask, true above the diagonal.
+extern "C" __global__ void causal_mask_u8(
+    const size_t numel,
+    const size_t length,
+    unsigned char *out
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    out[i] = i % length > i / length;
+}
@@ -0,0 +1,43 @@
+#include "cuda_utils.cuh"
+
+#define GOLDEN_GAMMA 0x9e3779b97f4a7c15ull
+
+// The same streams as `crate::random`: every item is the splitmix64 hash of its index.
+__device__ unsigned long long splitmix64(unsigned long long state) {
+    unsigned long long z = state + GOLDEN_GAMMA;
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ull;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebull;
+    return z ^ (z >> 31);
+}
+
+__device__ float uniform(unsigned long long seed, unsigned long long index) {
+    unsigned long long bits = splitmix64(seed + index * GOLDEN_GAMMA);
+    return (float)(bits >> 40) / 16777216.0f;
+}
+
+extern "C" __global__ void uniform_f32(
+    const size_t numel,
+    const unsigned long long seed,
+    float *out
+) {
+    size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    out[i] = uniform(seed, i);
+}
+
+// Box-Muller on the uniform items 2 * i and 2 * i + 1.
+extern "C" __global__ void normal_f32(
+    const size_t numel,
+    const unsigned long long seed,
+    float *out
+) {
+    size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    float u1 = 1.0f - uniform(seed, 2 * i);
+    float u2 = uniform(seed, 2 * i + 1);
+    out[i] = sqrtf(-2.0f * logf(u1)) * cosf(2.0f * 3.14159265358979f * u2);
+}
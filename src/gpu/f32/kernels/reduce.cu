@@ -0,0 +1,52 @@
+// Reductions of `x` (outer, size, inner) along its middle dimension into `out` (outer,
+// inner), one thread per item of `out` reading `size` items `inner` apart.
+#define REDUCE_OP(FORWARD, INIT, ACCUMULATE, FINISH) \
+extern "C" __global__ void FORWARD( \
+    const size_t numel, \
+    const size_t size, \
+    const size_t inner, \
+    const float *x, \
+    float *out \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float *row = x + (i / inner) * size * inner + i % inner; \
+    float acc = INIT; \
+    for (size_t j = 0; j < size; j++) { \
+        const float v = row[j * inner]; \
+        ACCUMULATE; \
+    } \
+    out[i] = FINISH; \
+}
+
+REDUCE_OP(sum_f32, 0.0f, acc += v, acc)
+REDUCE_OP(mean_f32, 0.0f, acc += v, acc / size)
+REDUCE_OP(max_f32, -INFINITY, acc = fmaxf(acc, v), acc)
+
+// The population variance, from the mean of a first pass.
+extern "C" __global__ void var_f32(
+    const size_t numel,
+    const size_t size,
+    const size_t inner,
+    const float *x,
+    float *out
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    const float *row = x + (i / inner) * size * inner + i % inner;
+    float mean = 0.0f;
+    for (size_t j = 0; j < size; j++) {
+        mean += row[j * inner];
+    }
+    mean /= size;
+    float var = 0.0f;
+    for (size_t j = 0; j < size; j++) {
+        const float d = row[j * inner] - mean;
+        var += d * d;
+    }
+    out[i] = var / size;
+}
@@ -0,0 +1,20 @@
+// Cumulative sum of `x` (outer, size, inner) along its middle dimension, one thread per
+// row of `size` items `inner` apart.
+extern "C" __global__ void cumsum_f32(
+    const size_t numel,
+    float *x,
+    const size_t size,
+    const size_t inner
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    float *row = x + (i / inner) * size * inner + i % inner;
+    float sum = 0.0;
+    for (size_t j = 0; j < size; j++) {
+        sum += row[j * inner];
+        row[j * inner] = sum;
+    }
+}
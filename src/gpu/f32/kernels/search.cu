@@ -0,0 +1,199 @@
+#include "cuda_utils.cuh"
+
+// One thread per row of the middle dimension of `x` (outer, size, inner), writes the
+// index of its best item, the first one on ties.
+#define ARG_SEARCH(FORWARD, BETTER) \
+extern "C" __global__ void FORWARD( \
+    const size_t numel, \
+    const size_t size, \
+    const size_t inner, \
+    const float *x, \
+    size_t *indices \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+\
+    const float *row = x + (i / inner) * size * inner + i % inner; \
+    size_t best = 0; \
+    for (size_t j = 1; j < size; j++) { \
+        if (row[j * inner] BETTER row[best * inner]) { \
+            best = j; \
+        } \
+    } \
+    indices[i] = best; \
+} \
+
+ARG_SEARCH(argmax_f32, >)
+ARG_SEARCH(argmin_f32, <)
+
+// One thread per row of the middle dimension of `x` (outer, size, inner), keeps its `k`
+// largest items sorted by insertion into `values` and `indices` (outer, k, inner).
+extern "C" __global__ void topk_f32(
+    const size_t numel,
+    const size_t size,
+    const size_t inner,
+    const size_t k,
+    const float *x,
+    float *values,
+    size_t *indices
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    const size_t offset = (i / inner) * size * inner + i % inner;
+    const size_t out_offset = (i / inner) * k * inner + i % inner;
+    size_t count = 0;
+    for (size_t j = 0; j < size; j++) {
+        const float v = x[offset + j * inner];
+        if (count == k && !(v > values[out_offset + (k - 1) * inner])) {
+            continue;
+        }
+        size_t slot = count < k ? count++ : k - 1;
+        while (slot > 0 && v > values[out_offset + (slot - 1) * inner]) {
+            values[out_offset + slot * inner] = values[out_offset + (slot - 1) * inner];
+            indices[out_offset + slot * inner] = indices[out_offset + (slot - 1) * inner];
+            slot--;
+        }
+        values[out_offset + slot * inner] = v;
+        indices[out_offset + slot * inner] = j;
+    }
+}
+
+// Whether the item (va, ia) comes before (vb, ib), ties broken by the index so that
+// the sort is stable.
+__device__ bool sorted_before(float va, size_t ia, float vb, size_t ib, int descending) {
+    if (va == vb) {
+        return ia < ib;
+    }
+    return descending ? va > vb : va < vb;
+}
+
+// The bitonic sort of the `padded` (a power of 2) items of `k` and `o` by the threads
+// of a block.
+__device__ void bitonic_sort(float *k, size_t *o, const size_t padded, const int descending) {
+    for (size_t width = 2; width <= padded; width <<= 1) {
+        for (size_t stride = width >> 1; stride > 0; stride >>= 1) {
+            for (size_t t = threadIdx.x; t < padded / 2; t += blockDim.x) {
+                const size_t a = (t / stride) * stride * 2 + t % stride;
+                const size_t b = a + stride;
+                const bool up = (a & width) == 0;
+                const bool swap = up
+                    ? sorted_before(k[b], o[b], k[a], o[a], descending)
+                    : sorted_before(k[a], o[a], k[b], o[b], descending);
+                if (swap) {
+                    const float v = k[a];
+                    k[a] = k[b];
+                    k[b] = v;
+                    const size_t index = o[a];
+                    o[a] = o[b];
+                    o[b] = index;
+                }
+            }
+            __syncthreads();
+        }
+    }
+}
+
+// One block per row of the middle dimension of `x` (outer, size, inner): the row padded
+// to `padded` items is sorted in `keys` and `order` (rows, padded), then written to
+// `values` and `indices` with the layout of `x`.
+extern "C" __global__ void sort_f32(
+    const size_t size,
+    const size_t inner,
+    const size_t padded,
+    const int descending,
+    const float *x,
+    float *keys,
+    size_t *order,
+    float *values,
+    size_t *indices
+) {
+    const size_t row = blockIdx.x;
+    const size_t offset = (row / inner) * size * inner + row % inner;
+    float *k = keys + row * padded;
+    size_t *o = order + row * padded;
+    const float pad = descending ? -INFINITY : INFINITY;
+    for (size_t j = threadIdx.x; j < padded; j += blockDim.x) {
+        k[j] = j < size ? x[offset + j * inner] : pad;
+        o[j] = j;
+    }
+    __syncthreads();
+    bitonic_sort(k, o, padded, descending);
+
+    for (size_t j = threadIdx.x; j < size; j += blockDim.x) {
+        values[offset + j * inner] = k[j];
+        indices[offset + j * inner] = o[j];
+    }
+}
+
+// One block per row of `x` (rows, size): the row is sorted in decreasing order, then
+// the logits after the first `k` are set to -inf.
+extern "C" __global__ void top_k_f32(
+    const size_t size,
+    const size_t padded,
+    const size_t keep,
+    float *x,
+    float *keys,
+    size_t *order
+) {
+    float *row = x + blockIdx.x * size;
+    float *k = keys + blockIdx.x * padded;
+    size_t *o = order + blockIdx.x * padded;
+    for (size_t j = threadIdx.x; j < padded; j += blockDim.x) {
+        k[j] = j < size ? row[j] : -INFINITY;
+        o[j] = j;
+    }
+    __syncthreads();
+    bitonic_sort(k, o, padded, 1);
+    for (size_t j = keep + threadIdx.x; j < size; j += blockDim.x) {
+        row[o[j]] = -INFINITY;
+    }
+}
+
+// One block per row of `x` (rows, size): the row is sorted in decreasing order, the
+// first thread accumulates the softmax until it reaches `p`, then the logits after
+// that are set to -inf.
+extern "C" __global__ void top_p_f32(
+    const size_t size,
+    const size_t padded,
+    const float p,
+    float *x,
+    float *keys,
+    size_t *order
+) {
+    __shared__ size_t keep;
+    float *row = x + blockIdx.x * size;
+    float *k = keys + blockIdx.x * padded;
+    size_t *o = order + blockIdx.x * padded;
+    for (size_t j = threadIdx.x; j < padded; j += blockDim.x) {
+        k[j] = j < size ? row[j] : -INFINITY;
+        o[j] = j;
+    }
+    __syncthreads();
+    bitonic_sort(k, o, padded, 1);
+
+    if (threadIdx.x == 0) {
+        const float max = k[0];
+        float sum = 0.0f;
+        for (size_t j = 0; j < size; j++) {
+            sum += expf(k[j] - max);
+        }
+        float cumsum = 0.0f;
+        keep = size;
+        for (size_t j = 0; j < size; j++) {
+            cumsum += expf(k[j] - max) / sum;
+            if (cumsum >= p) {
+                keep = j + 1;
+                break;
+            }
+        }
+    }
+    __syncthreads();
+    for (size_t j = keep + threadIdx.x; j < size; j += blockDim.x) {
+        row[o[j]] = -INFINITY;
+    }
+}
@@ -43,3 +43,35 @@ extern "C" __global__ void softmax_f32(
 
 } 
 
+
+// Softmax of `x * scale` (outer, size, inner) along its middle dimension, one thread per
+// row of `size` items `inner` apart.
+extern "C" __global__ void softmax_dim_f32(
+    const size_t numel,
+    float *x,
+    const size_t size,
+    const size_t inner,
+    const float scale
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+
+    float *row = x + (i / inner) * size * inner + i % inner;
+    float current_max = -1 * INFINITY;
+    for (size_t j = 0; j < size; j++) {
+        current_max = fmaxf(current_max, row[j * inner] * scale);
+    }
+
+    float sum = 0.0;
+    for (size_t j = 0; j < size; j++) {
+        const float v = exp(row[j * inner] * scale - current_max);
+        row[j * inner] = v;
+        sum += v;
+    }
+
+    for (size_t j = 0; j < size; j++) {
+        row[j * inner] /= sum;
+    }
+}
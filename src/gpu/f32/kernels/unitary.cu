@@ -34,6 +34,91 @@ extern "C" __global__ void gelu_f32(
     x[i] = gelu_fwd(x[i]);
 } 
 
+#define UNARY_OP(NAME, FUNC) \
+extern "C" __global__ void NAME( \
+    const size_t numel, \
+    float *x \
+) { \
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float v = x[i]; \
+    x[i] = FUNC; \
+}
+
+UNARY_OP(exp_f32, expf(v))
+UNARY_OP(log_f32, logf(v))
+UNARY_OP(sqrt_f32, sqrtf(v))
+UNARY_OP(rsqrt_f32, rsqrtf(v))
+UNARY_OP(sigmoid_f32, 1.0f / (1.0f + expf(-v)))
+UNARY_OP(erf_f32, erff(v))
+UNARY_OP(neg_f32, -v)
+UNARY_OP(abs_f32, fabsf(v))
+UNARY_OP(gelu_erf_f32, 0.5f * v * (1.0f + erff(v * (float)M_SQRT1_2)))
+UNARY_OP(relu_f32, fmaxf(v, 0.0f))
+UNARY_OP(silu_f32, v / (1.0f + expf(-v)))
+UNARY_OP(quick_gelu_f32, v / (1.0f + expf(-1.702f * v)))
+
+extern "C" __global__ void clamp_f32(
+    const size_t numel,
+    float *x,
+    const float min,
+    const float max
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    x[i] = fminf(fmaxf(x[i], min), max);
+}
+
+// Zeroes the items (i, j) of the (rows, cols) matrices of `x` with `j - i < diagonal`
+// when `upper` is set, `j - i > diagonal` otherwise.
+extern "C" __global__ void triangle_f32(
+    const size_t numel,
+    const size_t rows,
+    const size_t cols,
+    const long long diagonal,
+    const int upper,
+    float *x
+) {
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    const long long offset = (long long) (i % cols) - (long long) ((i / cols) % rows);
+    if (upper ? offset < diagonal : offset > diagonal) {
+        x[i] = 0.0;
+    }
+}
+
+// Sets -inf the keys j of the (rows, cols) matrices of `x` hidden from the query i at
+// the position `offset + i`: from `length` on, after it when `causal`, and at `window`
+// positions or more from it.
+extern "C" __global__ void position_mask_f32(
+    const size_t numel,
+    const size_t rows,
+    const size_t cols,
+    const size_t offset,
+    const size_t length,
+    const int causal,
+    const size_t window,
+    float *x
+) {
+    unsigned int index = blockIdx.x * blockDim.x + threadIdx.x;
+    if (index >= numel) {
+        return;
+    }
+    const size_t i = offset + (index / cols) % rows;
+    const size_t j = index % cols;
+    const bool after = j > i && (causal || j - i >= window);
+    const bool before = j < i && i - j >= window;
+    if (j >= length || after || before) {
+        x[index] = -1 * INFINITY;
+    }
+}
+
 extern "C" __global__ void mul_scalar_f32( 
     const size_t numel, 
     float *x ,
@@ -45,3 +130,56 @@ extern "C" __global__ void mul_scalar_f32(
     } 
     x[i] *= factor;
 } 
+
+extern "C" __global__ void f32_to_i8( 
+    const size_t numel, 
+    const float *x,
+    signed char *out,
+    float scale
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    out[i] = (signed char) fmaxf(-127.0, fminf(127.0, rintf(x[i] / scale)));
+} 
+
+extern "C" __global__ void i8_to_f32( 
+    const size_t numel, 
+    const signed char *x,
+    float *out,
+    float scale
+) { 
+    unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; 
+    if (i >= numel) { 
+        return; 
+    } 
+    out[i] = (float) x[i] * scale;
+} 
+
+// One thread per pair of items (j, j + half) of the rows of `x` (rows, 2 * half), the
+// row `r` is at the position `offset + r % length` of the tables (positions, half).
+extern "C" __global__ void rotary_f32(
+    const size_t numel,
+    const size_t length,
+    const size_t half,
+    const size_t offset,
+    const float *cos,
+    const float *sin,
+    float *x
+) {
+    size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= numel) {
+        return;
+    }
+    const size_t row = i / half;
+    const size_t j = i % half;
+    const size_t position = offset + row % length;
+    const float c = cos[position * half + j];
+    const float s = sin[position * half + j];
+    float *r = x + row * 2 * half;
+    const float a = r[j];
+    const float b = r[j + half];
+    r[j] = a * c - b * s;
+    r[j + half] = b * c + a * s;
+}
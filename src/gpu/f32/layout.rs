@@ -0,0 +1,364 @@
+use crate::gpu::f32::{CudaError, Tensor};
+use crate::shape;
+use crate::SmeltError;
+use cudarc::driver::{DeviceSlice, LaunchAsync, LaunchConfig};
+use std::ops::RangeBounds;
+
+pub(super) const LAYOUT_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/layout.ptx"));
+
+impl Tensor {
+    /// The items `start..start + len` of the dimension `dim` copied in a new tensor, the
+    /// other dimensions are kept whole.
+    /// ```
+    /// use smelte_rs::gpu::f32::{Device, Tensor};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    /// let tensor = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+    /// let narrowed = tensor.narrow(1, 1, 2).unwrap();
+    /// assert_eq!(narrowed.cpu_data().unwrap(), [2.0, 3.0, 5.0, 6.0]);
+    /// ```
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        let (outer, size, inner) = shape::narrow(self.shape(), dim, start, len)?;
+        let mut shape = self.shape().to_vec();
+        shape[dim] = len;
+        let mut out = Tensor::zeros(shape, self.device())?;
+        let numel = outer * len * inner;
+        if numel == 0 {
+            return Ok(out);
+        }
+        let dev = self.cuda();
+        if outer == 1 {
+            let data = self.data().slice(start * inner..(start + len) * inner);
+            dev.dtod_copy(&data, out.data_mut())?;
+            return Ok(out);
+        }
+        copy_strided(
+            self,
+            &mut out,
+            &[outer, len, inner, size * inner, inner, 1],
+            start * inner,
+        )?;
+        Ok(out)
+    }
+
+    /// [Tensor::narrow] with a range, like `tensor.slice(0, ..1)` for the CLS token.
+    pub fn slice(&self, dim: usize, range: impl RangeBounds<usize>) -> Result<Self, SmeltError> {
+        let size = self.shape().get(dim).copied().unwrap_or(0);
+        let (start, len) = shape::range(range, size);
+        self.narrow(dim, start, len)
+    }
+
+    /// The parts of `sizes` items of the dimension `dim`, see [Tensor::narrow]. The
+    /// sizes must add up to the size of the dimension.
+    pub fn split(&self, dim: usize, sizes: &[usize]) -> Result<Vec<Self>, SmeltError> {
+        shape::split(self.shape(), dim, sizes)?
+            .into_iter()
+            .map(|(start, len)| self.narrow(dim, start, len))
+            .collect()
+    }
+
+    /// [Tensor::split] in `chunks` parts of the same size, except the last one which can
+    /// be smaller.
+    pub fn chunk(&self, dim: usize, chunks: usize) -> Result<Vec<Self>, SmeltError> {
+        let size = self.shape().get(dim).copied().unwrap_or(0);
+        self.split(dim, &shape::chunk(size, chunks)?)
+    }
+
+    /// The tensor with its dimensions reordered, the dimension `i` of the result being
+    /// the dimension `dims[i]` of `self`. The data is copied.
+    pub fn permute(&self, dims: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, strides) = shape::permute(self.shape(), dims)?;
+        let info: Vec<usize> = shape.iter().copied().chain(strides).collect();
+        let mut out = Tensor::zeros(shape, self.device())?;
+        copy_strided(self, &mut out, &info, 0)?;
+        Ok(out)
+    }
+
+    /// [Tensor::permute] swapping the dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Self, SmeltError> {
+        self.permute(&shape::transpose(self.shape().len(), dim0, dim1))
+    }
+
+    /// The items `indices` of the dimension `dim`, in that order and possibly repeated,
+    /// like the rows of an embedding or the beams kept by a search.
+    pub fn index_select(&self, dim: usize, indices: &[usize]) -> Result<Self, SmeltError> {
+        let (_, size, inner) = shape::index_select(self.shape(), dim, indices)?;
+        let mut shape = self.shape().to_vec();
+        shape[dim] = indices.len();
+        let mut out = Tensor::zeros(shape, self.device())?;
+        let numel = out.data().len();
+        if numel == 0 {
+            return Ok(out);
+        }
+        let module_name = "index_select_f32";
+        let dev = self.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(LAYOUT_PTX.into(), module_name, &[module_name])?;
+        }
+        let ids = dev.htod_copy(indices.to_vec())?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            size,
+            inner,
+            indices.len(),
+            &ids,
+            self.data(),
+            out.data_mut(),
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok(out)
+    }
+
+    /// Writes the items of `values` into the items `indices` of the dimension `dim`, the
+    /// inverse of [Tensor::index_select]. `values` has the shape of `self` except for
+    /// `dim`, of size `indices.len()`. With repeated indices any of the writes can win.
+    pub fn index_put(
+        &mut self,
+        dim: usize,
+        indices: &[usize],
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        let (_, size, inner) = shape::index_put(self.shape(), dim, indices, values.shape())?;
+        if values.device_id() != self.device_id() {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: values.device_id(),
+                expected: self.device_id(),
+            }));
+        }
+        let numel = values.data().len();
+        if numel == 0 {
+            return Ok(());
+        }
+        let module_name = "index_put_f32";
+        let dev = self.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(LAYOUT_PTX.into(), module_name, &[module_name])?;
+        }
+        let ids = dev.htod_copy(indices.to_vec())?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            size,
+            inner,
+            indices.len(),
+            &ids,
+            values.data(),
+            self.data_mut(),
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok(())
+    }
+
+    /// Writes the items of `values` into the items `start..start + values.shape()[dim]`
+    /// of the dimension `dim`, the inverse of [Tensor::narrow], like the new tokens of a
+    /// preallocated cache.
+    pub fn narrow_put(
+        &mut self,
+        dim: usize,
+        start: usize,
+        values: &Self,
+    ) -> Result<(), SmeltError> {
+        let (outer, size, inner) = shape::narrow_put(self.shape(), dim, start, values.shape())?;
+        if values.device_id() != self.device_id() {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: values.device_id(),
+                expected: self.device_id(),
+            }));
+        }
+        let len = values.shape()[dim];
+        copy_into_strided(
+            values,
+            self,
+            &[outer, len * inner, size * inner, 1],
+            start * inner,
+        )
+    }
+
+    /// The concatenation of `tensors` along `dim`, like the past and present keys and
+    /// values of a cache. They must have the same shape on the other dimensions and live
+    /// on the same device.
+    pub fn cat(tensors: &[&Self], dim: usize) -> Result<Self, SmeltError> {
+        let shapes: Vec<&[usize]> = tensors.iter().map(|t| t.shape()).collect();
+        let shape = shape::cat(&shapes, dim)?;
+        let device = tensors[0].device();
+        if let Some(tensor) = tensors.iter().find(|t| t.device_id() != device.device_id()) {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: tensor.device_id(),
+                expected: device.device_id(),
+            }));
+        }
+        let outer: usize = shape[..dim].iter().product();
+        let inner: usize = shape[dim + 1..].iter().product();
+        let size = shape[dim];
+        let mut out = Tensor::zeros(shape, device)?;
+        let mut start = 0;
+        for tensor in tensors {
+            let len = tensor.shape()[dim];
+            copy_into_strided(
+                tensor,
+                &mut out,
+                &[outer, len * inner, size * inner, 1],
+                start * inner,
+            )?;
+            start += len;
+        }
+        Ok(out)
+    }
+}
+
+/// Copies `src` starting at `offset` into `dst`, reading it with the shape and the
+/// strides of `info` (`[shape..., strides...]`).
+pub(super) fn copy_strided(
+    src: &Tensor,
+    dst: &mut Tensor,
+    info: &[usize],
+    offset: usize,
+) -> Result<(), SmeltError> {
+    let numel = dst.data().len();
+    launch("copy_strided_f32", numel, src, dst, info, offset)
+}
+
+/// Copies `src` into `dst` starting at `offset`, writing it with the shape and the
+/// strides of `info` (`[shape..., strides...]`).
+fn copy_into_strided(
+    src: &Tensor,
+    dst: &mut Tensor,
+    info: &[usize],
+    offset: usize,
+) -> Result<(), SmeltError> {
+    let numel = src.data().len();
+    launch("copy_into_strided_f32", numel, src, dst, info, offset)
+}
+
+/// Runs the layout kernel `module_name` over `numel` items.
+fn launch(
+    module_name: &'static str,
+    numel: usize,
+    src: &Tensor,
+    dst: &mut Tensor,
+    info: &[usize],
+    offset: usize,
+) -> Result<(), SmeltError> {
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = src.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(LAYOUT_PTX.into(), module_name, &[module_name])?;
+    }
+    let rank = info.len() / 2;
+    let info = dev.htod_copy(info.to_vec())?;
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, rank, &info, offset, src.data(), dst.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::f32::Device;
+
+    #[test]
+    fn test_narrow() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![3, 4], &device).unwrap();
+        let narrowed = tensor.narrow(0, 1, 2).unwrap();
+        assert_eq!(narrowed.shape(), [2, 4]);
+        assert_eq!(
+            narrowed.cpu_data().unwrap(),
+            [4., 5., 6., 7., 8., 9., 10., 11.]
+        );
+        let narrowed = tensor.slice(1, 3..).unwrap();
+        assert_eq!(narrowed.shape(), [3, 1]);
+        assert_eq!(narrowed.cpu_data().unwrap(), [3., 7., 11.]);
+        assert!(tensor.narrow(1, 2, 3).is_err());
+    }
+
+    #[test]
+    fn test_cat() {
+        let device = Device::new(0).unwrap();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let b = Tensor::from_cpu(&[5.0, 6.0], vec![2, 1], &device).unwrap();
+        let c = Tensor::cat(&[&a, &b], 1).unwrap();
+        assert_eq!(c.shape(), [2, 3]);
+        assert_eq!(c.cpu_data().unwrap(), [1.0, 2.0, 5.0, 3.0, 4.0, 6.0]);
+        let c = Tensor::cat(&[&a, &a], 0).unwrap();
+        assert_eq!(
+            c.cpu_data().unwrap(),
+            [1.0, 2.0, 3.0, 4.0, 1.0, 2.0, 3.0, 4.0]
+        );
+        assert!(Tensor::cat(&[&a, &b], 0).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let device = Device::new(0).unwrap();
+        let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let tensor = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+        let parts = tensor.split(1, &[1, 2]).unwrap();
+        assert_eq!(parts[0].cpu_data().unwrap(), [1.0, 4.0]);
+        assert_eq!(parts[1].cpu_data().unwrap(), [2.0, 3.0, 5.0, 6.0]);
+        let parts = tensor.chunk(0, 2).unwrap();
+        assert_eq!(parts[1].cpu_data().unwrap(), [4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn test_permute() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![2, 3, 4], &device).unwrap();
+        let permuted = tensor.permute(&[2, 0, 1]).unwrap();
+        assert_eq!(permuted.shape(), [4, 2, 3]);
+        assert_eq!(
+            permuted.cpu_data().unwrap()[..6],
+            [0.0, 4.0, 8.0, 12.0, 16.0, 20.0]
+        );
+        let transposed = tensor.transpose(0, 1).unwrap();
+        assert_eq!(transposed.shape(), [3, 2, 4]);
+        assert_eq!(
+            transposed.cpu_data().unwrap()[4..8],
+            [12.0, 13.0, 14.0, 15.0]
+        );
+    }
+
+    #[test]
+    fn test_reshape() {
+        let device = Device::new(0).unwrap();
+        let tensor = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let tensor = tensor.reshape(vec![4, 1]).unwrap();
+        assert_eq!(tensor.shape(), [4, 1]);
+        assert_eq!(tensor.cpu_data().unwrap(), [1.0, 2.0, 3.0, 4.0]);
+        assert!(tensor.reshape(vec![3]).is_err());
+    }
+
+    #[test]
+    fn test_index_select() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![2, 3, 2], &device).unwrap();
+        let selected = tensor.index_select(1, &[1, 1]).unwrap();
+        assert_eq!(selected.shape(), [2, 2, 2]);
+        assert_eq!(
+            selected.cpu_data().unwrap(),
+            [2.0, 3.0, 2.0, 3.0, 8.0, 9.0, 8.0, 9.0]
+        );
+        assert!(tensor.index_select(1, &[3]).is_err());
+    }
+
+    #[test]
+    fn test_index_put() {
+        let device = Device::new(0).unwrap();
+        let mut tensor = Tensor::zeros(vec![2, 3, 1], &device).unwrap();
+        let values = Tensor::from_cpu(&[1.0, 2.0], vec![2, 1, 1], &device).unwrap();
+        tensor.index_put(1, &[2], &values).unwrap();
+        assert_eq!(tensor.cpu_data().unwrap(), [0.0, 0.0, 1.0, 0.0, 0.0, 2.0]);
+        assert!(tensor.index_put(0, &[2], &values).is_err());
+    }
+}
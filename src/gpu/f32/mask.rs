@@ -0,0 +1,316 @@
+use crate::gpu::f32::{CudaError, Device, Tensor};
+use crate::shape;
+use crate::SmeltError;
+use cudarc::driver::{CudaSlice, LaunchAsync, LaunchConfig};
+use std::sync::Arc;
+
+const MASK_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/mask.ptx"));
+
+/// A boolean mask on a cuda device, one byte per item, `0` is false. See
+/// [masked_fill] and [masked_softmax].
+#[derive(Clone)]
+pub struct Mask {
+    shape: Vec<usize>,
+    device: Device,
+    // Shared with the clones and the cache of [Mask::causal], copied when written to.
+    data: Arc<CudaSlice<u8>>,
+}
+
+impl Mask {
+    /// Creates a new mask with given shape. Can fail if data doesn't match the shape
+    pub fn from_bools(
+        data: &[bool],
+        shape: Vec<usize>,
+        device: &Device,
+    ) -> Result<Self, SmeltError> {
+        let data: Vec<u8> = data.iter().map(|&b| b as u8).collect();
+        Self::from_cpu(&data, shape, device)
+    }
+
+    /// Creates a new mask with given shape from bytes, `0` is false. Can fail if data
+    /// doesn't match the shape
+    pub fn from_cpu(data: &[u8], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let data = Arc::new(device.cuda().htod_sync_copy(data)?);
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a mask of `shape` with every item false
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let data = Arc::new(device.cuda().alloc_zeros(shape.iter().product())?);
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// The (length, length) mask of a causal attention: the item `(i, j)` is true when
+    /// `j > i`, hiding the future positions. Built on the device once per length, then
+    /// shared by the next calls.
+    pub fn causal(length: usize, device: &Device) -> Result<Self, SmeltError> {
+        let mut masks = device.causal_masks().lock().unwrap();
+        let data = match masks.get(&length) {
+            Some(data) => data.clone(),
+            None => {
+                let numel = length * length;
+                let dev = device.cuda();
+                let mut data = dev.alloc_zeros(numel)?;
+                if numel > 0 {
+                    let module_name = "causal_mask_u8";
+                    if !dev.has_func(module_name, module_name) {
+                        dev.load_ptx(MASK_PTX.into(), module_name, &[module_name])?;
+                    }
+                    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+                    let cfg = LaunchConfig::for_num_elems(numel as u32);
+                    unsafe { fwd_fn.launch(cfg, (numel, length, &mut data)) }?;
+                }
+                let data = Arc::new(data);
+                masks.insert(length, data.clone());
+                data
+            }
+        };
+        Ok(Self {
+            shape: vec![length, length],
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// The shape of the mask
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The device of the mask
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<u8>, SmeltError> {
+        Ok(self.device.cuda().dtoh_sync_copy(&*self.data)?)
+    }
+}
+
+/// Runs the mask kernel `module_name` on the rows of `x`, after checking that `mask`
+/// is the trailing dimensions of `x`.
+fn launch(
+    module_name: &'static str,
+    x: &mut Tensor,
+    mask: &Mask,
+    value: f32,
+) -> Result<(), SmeltError> {
+    shape::check_mask(x.shape(), mask.shape())?;
+    if x.device_id() != mask.device.device_id() {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got: mask.device.device_id(),
+            expected: x.device_id(),
+        }));
+    }
+    let n = x.shape()[x.shape().len() - 1];
+    let mask_numel: usize = mask.shape.iter().product();
+    let rows = x.shape().iter().product::<usize>() / n.max(1);
+    if rows * n == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(MASK_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(rows as u32);
+    let params = (rows, n, mask_numel, &*mask.data, value, x.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// x = value wherever `mask` is true. The mask has the trailing dimensions of `x`
+/// and is repeated over the leading ones.
+pub fn masked_fill(x: &mut Tensor, mask: &Mask, value: f32) -> Result<(), SmeltError> {
+    launch("masked_fill_f32", x, mask, value)
+}
+
+/// Softmax on the last dimension of `x`, the positions where `mask` is true get a
+/// probability of 0. Rows without any position left are filled with 0.
+pub fn masked_softmax(x: &mut Tensor, mask: &Mask) -> Result<(), SmeltError> {
+    launch("masked_softmax_f32", x, mask, 0.0)
+}
+
+/// Checks that `tensors` live on the device of `out`, and copies the shape of `out`
+/// then the strides of `shapes` broadcasted to it.
+fn broadcast_info(
+    out: (&[usize], usize),
+    shapes: &[&[usize]],
+    tensors: &[usize],
+) -> Result<Vec<usize>, SmeltError> {
+    let (out_shape, device_id) = out;
+    if let Some(&got) = tensors.iter().find(|&&d| d != device_id) {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got,
+            expected: device_id,
+        }));
+    }
+    let mut info = out_shape.to_vec();
+    for shape in shapes {
+        info.extend(shape::broadcast_strides(shape, out_shape)?);
+    }
+    Ok(info)
+}
+
+/// Runs the comparison kernel `module_name` into `out`.
+fn compare(
+    module_name: &'static str,
+    a: &Tensor,
+    b: &Tensor,
+    out: &mut Mask,
+) -> Result<(), SmeltError> {
+    let info = broadcast_info(
+        (&out.shape, out.device.device_id()),
+        &[a.shape(), b.shape()],
+        &[a.device_id(), b.device_id()],
+    )?;
+    let numel: usize = out.shape.iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = a.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(MASK_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let info = dev.htod_copy(info)?;
+    let params = (
+        numel,
+        out.shape.len(),
+        &info,
+        a.data(),
+        b.data(),
+        Arc::make_mut(&mut out.data),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// out = a > b, `a` and `b` being broadcasted to the shape of `out` like in NumPy.
+pub fn gt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare("gt_f32", a, b, out)
+}
+
+/// out = a < b, see [gt].
+pub fn lt(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare("lt_f32", a, b, out)
+}
+
+/// out = a == b, see [gt].
+pub fn eq(a: &Tensor, b: &Tensor, out: &mut Mask) -> Result<(), SmeltError> {
+    compare("eq_f32", a, b, out)
+}
+
+/// out = a wherever `mask` is true, b elsewhere. The mask, `a` and `b` are broadcasted
+/// to the shape of `out` like in NumPy.
+pub fn where_cond(mask: &Mask, a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let info = broadcast_info(
+        (out.shape(), out.device_id()),
+        &[&mask.shape, a.shape(), b.shape()],
+        &[mask.device.device_id(), a.device_id(), b.device_id()],
+    )?;
+    let numel: usize = out.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = a.cuda();
+    let module_name = "where_cond_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(MASK_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let rank = out.shape().len();
+    let info = dev.htod_copy(info)?;
+    let params = (
+        numel,
+        rank,
+        &info,
+        &*mask.data,
+        a.data(),
+        b.data(),
+        out.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::simplify;
+
+    #[test]
+    fn test_masked_softmax() {
+        let device = Device::new(0).unwrap();
+        let data = [1.0, 2.0, 3.0, 1.0, 2.0, 3.0];
+        let mut x = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+        let bools = [false, false, true, true, true, true];
+        let mask = Mask::from_bools(&bools, vec![2, 3], &device).unwrap();
+        masked_softmax(&mut x, &mask).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [0.2689, 0.7311, 0.0, 0.0, 0.0, 0.0]
+        );
+
+        let mut x = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+        let mask = Mask::from_bools(&[false, true, false], vec![3], &device).unwrap();
+        masked_fill(&mut x, &mask, -1.0).unwrap();
+        assert_eq!(x.cpu_data().unwrap(), [1.0, -1.0, 3.0, 1.0, -1.0, 3.0]);
+        let mask = Mask::from_bools(&[false, true], vec![2], &device).unwrap();
+        assert!(masked_fill(&mut x, &mask, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_compare_where() {
+        let device = Device::new(0).unwrap();
+        let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let a = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+        let b = Tensor::from_cpu(&[2.0, 5.0], vec![2, 1], &device).unwrap();
+        let mut mask = Mask::zeros(vec![2, 3], &device).unwrap();
+        gt(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.cpu_data().unwrap(), [0, 0, 1, 0, 0, 1]);
+        lt(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.cpu_data().unwrap(), [1, 0, 0, 1, 0, 0]);
+        eq(&a, &b, &mut mask).unwrap();
+        assert_eq!(mask.cpu_data().unwrap(), [0, 1, 0, 0, 1, 0]);
+
+        let zero = Tensor::zeros(vec![1], &device).unwrap();
+        let mut out = Tensor::zeros(vec![2, 3], &device).unwrap();
+        where_cond(&mask, &zero, &a, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [1.0, 0.0, 3.0, 4.0, 0.0, 6.0]);
+        let mut mask = Mask::zeros(vec![3], &device).unwrap();
+        assert!(gt(&a, &b, &mut mask).is_err());
+    }
+
+    #[test]
+    fn test_causal() {
+        let device = Device::new(0).unwrap();
+        let mask = Mask::causal(3, &device).unwrap();
+        assert_eq!(mask.shape(), [3, 3]);
+        assert_eq!(mask.cpu_data().unwrap(), [0, 1, 1, 0, 0, 1, 0, 0, 0]);
+        // The cached mask is not changed by the comparisons written into its clones.
+        let mut copy = Mask::causal(3, &device).unwrap();
+        let x = Tensor::zeros(vec![3, 3], &device).unwrap();
+        eq(&x, &x, &mut copy).unwrap();
+        assert_eq!(copy.cpu_data().unwrap(), [1; 9]);
+        let mask = Mask::causal(3, &device).unwrap();
+        assert_eq!(mask.cpu_data().unwrap(), [0, 1, 1, 0, 0, 1, 0, 0, 0]);
+    }
+}
@@ -1,10 +1,51 @@
+/// The fused attention
+mod attention;
+/// The convolutions
+mod conv;
+/// cuDNN implementations of the softmax and the layer normalization
+#[cfg(feature = "cudnn")]
+mod cudnn;
+/// Cuda graphs, to replay recorded kernels
+mod graph;
+/// Properties and memory usage of the devices
+mod info;
+/// The int8 tensors and the casts from and to f32
+mod int8;
+mod layout;
+mod mask;
 /// The various ops
 mod ops;
+/// Page-locked host memory
+mod pinned;
+/// The caching allocator of the devices
+mod pool;
+/// Seeded random tensors
+mod random;
+mod search;
 /// The Tensor struct
 mod tensor;
 
 /// The Tensor trait implementations
 mod traits;
+/// Strided views of the tensors
+mod view;
 
+/// Tensor parallelism, splits layers across several devices.
+/// Weights are split once with [parallel::shard], then every device runs its own shard,
+/// the linears by columns or rows and the attentions by heads. The models aren't
+/// partitioned automatically, see [crate::nn::models::bert::BertClassifier::split_layers]
+/// to run their layers on several devices instead.
+pub mod parallel;
+
+pub use attention::fused_attention;
+pub use conv::{conv1d, conv2d};
+pub use graph::Graph;
+pub use info::{DeviceInfo, MemoryStats};
+pub use int8::I8Tensor;
+pub use mask::{eq, gt, lt, masked_fill, masked_softmax, where_cond, Mask};
 pub use ops::*;
-pub use tensor::{Device, Tensor};
+pub use pinned::PinnedBuffer;
+pub use pool::PoolStats;
+pub use search::{top_k, top_p};
+pub use tensor::{Device, HostCopy, Stream, Tensor};
+pub use view::View;
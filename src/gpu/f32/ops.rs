@@ -1,9 +1,19 @@
+use crate::gpu::f32::layout::LAYOUT_PTX;
 use crate::gpu::f32::Tensor;
+use crate::shape;
+use crate::traits::Activation;
 use crate::SmeltError;
 use cudarc::cublas::result::CublasError;
+#[cfg(not(feature = "cublaslt"))]
 use cudarc::cublas::safe::{GemmConfig, StridedBatchedConfig};
+#[cfg(not(feature = "cublaslt"))]
 use cudarc::cublas::sys::cublasOperation_t::{CUBLAS_OP_N as NoTr, CUBLAS_OP_T as Tr};
+#[cfg(not(feature = "cublaslt"))]
 use cudarc::cublas::Gemm;
+#[cfg(feature = "cublaslt")]
+use cudarc::cublaslt::{result::CublasError as CublasLtError, Matmul, MatmulConfig};
+#[cfg(feature = "cudnn")]
+use cudarc::cudnn::result::CudnnError;
 use cudarc::driver::DeviceSlice;
 use cudarc::driver::DriverError;
 use cudarc::driver::LaunchAsync;
@@ -21,8 +31,28 @@ pub enum CudaError {
     },
     /// Error with cublas library
     CublasError(CublasError),
+    /// Error with cublasLt library
+    #[cfg(feature = "cublaslt")]
+    CublasLtError(CublasLtError),
+    /// Error with cuDNN library
+    #[cfg(feature = "cudnn")]
+    CudnnError(CudnnError),
     /// Error with cuda driver.
     DriverError(DriverError),
+    /// The dimension cannot be evenly split across devices.
+    InvalidSharding {
+        /// The size of the dimension to split
+        size: usize,
+        /// The number of shards requested
+        num_shards: usize,
+    },
+    /// There is no cuda device with this id.
+    DeviceNotFound {
+        /// The requested id
+        device_id: usize,
+        /// The number of cuda devices available
+        num_devices: usize,
+    },
 }
 
 impl From<CublasError> for SmeltError {
@@ -31,6 +61,13 @@ impl From<CublasError> for SmeltError {
     }
 }
 
+#[cfg(feature = "cublaslt")]
+impl From<CublasLtError> for SmeltError {
+    fn from(cublaslt: CublasLtError) -> Self {
+        Self::Cuda(CudaError::CublasLtError(cublaslt))
+    }
+}
+
 impl From<DriverError> for SmeltError {
     fn from(cublas: DriverError) -> Self {
         Self::Cuda(CudaError::DriverError(cublas))
@@ -79,7 +116,19 @@ pub fn select(ids: &[usize], weights: &Tensor, out: &mut Tensor) -> Result<(), S
 
 /// Copy tensor into another tensor
 pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
-    out.device().dtod_copy(weights.data(), out.data_mut())?;
+    if weights.shape() != out.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: out.shape().to_vec(),
+            got: weights.shape().to_vec(),
+        });
+    }
+    if weights.device_id() != out.device_id() {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got: out.device_id(),
+            expected: weights.device_id(),
+        }));
+    }
+    out.cuda().dtod_copy(weights.data(), out.data_mut())?;
     Ok(())
 }
 
@@ -93,6 +142,15 @@ pub fn matmul_t(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltErr
     g_matmul::<true>(a, b, out)
 }
 
+/// Batched matrix multiplication of `a` (batch, m, k) by `b` (batch, k, n) into `out`
+/// (batch, m, n), as a single strided batched gemm.
+pub fn bmm(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape().len() != 3 {
+        return Err(SmeltError::InvalidRank { expected_rank: 3 });
+    }
+    g_matmul::<false>(a, b, out)
+}
+
 #[inline]
 fn g_matmul<'a, const TRANSPOSE: bool>(
     a: &Tensor,
@@ -159,11 +217,23 @@ fn g_matmul<'a, const TRANSPOSE: bool>(
         });
     }
 
+    let batching: usize = a.shape()[..dim - 2].iter().product();
+    gemm::<TRANSPOSE>(a, b, c, (m, n, k), batching)
+}
+
+#[cfg(not(feature = "cublaslt"))]
+#[inline]
+fn gemm<const TRANSPOSE: bool>(
+    a: &Tensor,
+    b: &Tensor,
+    c: &mut Tensor,
+    (m, n, k): (usize, usize, usize),
+    batching: usize,
+) -> Result<(), SmeltError> {
     // TODO Maybe Zero out c
     // c.data_mut().iter_mut().for_each(|v| *v = 0.0);
     c.cuda().memset_zeros(c.data_mut())?;
 
-    let batching: usize = a.shape()[..dim - 2].iter().product();
     let a_skip: usize = m * k;
     let b_skip: usize = n * k;
     let c_skip: usize = m * n;
@@ -215,6 +285,55 @@ fn g_matmul<'a, const TRANSPOSE: bool>(
     Ok(())
 }
 
+/// Same as the cublas gemm, but through cuBLASLt which picks the algorithm with its
+/// heuristics. The compute type stays the default full f32 one, not TF32.
+#[cfg(feature = "cublaslt")]
+#[inline]
+fn gemm<const TRANSPOSE: bool>(
+    a: &Tensor,
+    b: &Tensor,
+    c: &mut Tensor,
+    (m, n, k): (usize, usize, usize),
+    batching: usize,
+) -> Result<(), SmeltError> {
+    let a_skip = m * k;
+    let b_skip = n * k;
+    let c_skip = m * n;
+
+    let blaslt = a.blaslt();
+
+    // Same swap as the cublas version, cuBLASLt is column major too.
+    let (m, n) = (n, m);
+    let (a_skip, b_skip) = (b_skip, a_skip);
+    let (a, b) = (b, a);
+
+    let lda = if TRANSPOSE { k } else { m };
+    let cfg = MatmulConfig {
+        transa: TRANSPOSE,
+        transb: false,
+        m: m as u64,
+        n: n as u64,
+        k: k as u64,
+        alpha: 1.0,
+        lda: lda as i64,
+        ldb: k as i64,
+        // `c` is overwritten, no need to zero it out.
+        beta: 0.0,
+        ldc: m as i64,
+        stride_a: Some(a_skip as i64),
+        stride_b: Some(b_skip as i64),
+        stride_c: Some(c_skip as i64),
+        stride_bias: None,
+        batch_size: Some(batching as libc::c_int),
+    };
+    // SAFETY: The shapes of `a`, `b` and `c` were checked against `(m, n, k)` and
+    // `batching` by the caller, so the leading dimensions and strides stay in bounds.
+    unsafe {
+        blaslt.matmul(cfg, a.data(), b.data(), c.data_mut(), None, None)?;
+    }
+    Ok(())
+}
+
 const ADD_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/add.ptx"));
 
 /// tensor elementwise addition. b += a.
@@ -248,41 +367,58 @@ pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
-/// broacasted tensor elementwise addition. b += a.
-pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
-    if &b.shape()[1..] != a.shape() {
-        return Err(SmeltError::DimensionMismatch {
-            expected: b.shape().to_vec(),
-            got: a.shape().to_vec(),
-        });
-    }
-    let skip: usize = a.shape().iter().product();
+/// Runs the `repeated` kernel when `a` is repeated as a whole over `b`, like a bias, and
+/// the `strided` kernel for the other NumPy broadcasts of `a` to the shape of `b`.
+fn broadcast_op(
+    a: &Tensor,
+    b: &mut Tensor,
+    repeated: &'static str,
+    strided: &'static str,
+) -> Result<(), SmeltError> {
+    let strides = shape::broadcast_strides(a.shape(), b.shape())?;
     if a.device_id() != b.device_id() {
         return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
             got: b.device_id(),
             expected: a.device_id(),
         }));
     }
-
-    let dev = a.cuda();
-
-    let module_name = "badd_fwd_f32";
-    if !dev.has_func(module_name, module_name) {
-        dev.load_ptx(ADD_PTX.into(), module_name, &[module_name])?;
-    }
-
     let numel = b.data().len();
+    if numel == 0 {
+        return Ok(());
+    }
 
-    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let dev = a.cuda();
     let cfg = LaunchConfig::for_num_elems(numel as u32);
-    let params = (numel, a.data(), b.data_mut(), skip);
-    unsafe { fwd_fn.launch(cfg, params) }?;
-
+    if shape::is_repeated(a.shape(), b.shape()) {
+        if !dev.has_func(repeated, repeated) {
+            dev.load_ptx(ADD_PTX.into(), repeated, &[repeated])?;
+        }
+        let skip = a.data().len();
+        let fwd_fn = dev.get_func(repeated, repeated).unwrap();
+        let params = (numel, a.data(), b.data_mut(), skip);
+        unsafe { fwd_fn.launch(cfg, params) }?;
+    } else {
+        if !dev.has_func(strided, strided) {
+            dev.load_ptx(ADD_PTX.into(), strided, &[strided])?;
+        }
+        let rank = b.shape().len();
+        let info = dev.htod_copy(b.shape().iter().copied().chain(strides).collect())?;
+        let fwd_fn = dev.get_func(strided, strided).unwrap();
+        let params = (numel, rank, &info, a.data(), b.data_mut());
+        unsafe { fwd_fn.launch(cfg, params) }?;
+    }
     Ok(())
 }
 
-/// tensor elementwise multiplication. b *= a.
-pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+/// broacasted tensor elementwise addition. b += a.
+/// `a` is broadcasted to the shape of `b` like in NumPy: the shapes are aligned on the
+/// right and the dimensions of size 1 are repeated.
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op(a, b, "badd_fwd_f32", "sadd_fwd_f32")
+}
+
+/// tensor elementwise subtraction. b -= a.
+pub fn sub(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     if a.shape() != b.shape() {
         return Err(SmeltError::DimensionMismatch {
             expected: b.shape().to_vec(),
@@ -298,7 +434,7 @@ pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
 
     let dev = a.cuda();
 
-    let module_name = "mul_fwd_f32";
+    let module_name = "sub_fwd_f32";
     if !dev.has_func(module_name, module_name) {
         dev.load_ptx(ADD_PTX.into(), module_name, &[module_name])?;
     }
@@ -313,15 +449,19 @@ pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
-/// broadcasted tensor elementwise multiplication. b *= a.
-pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
-    if &b.shape()[1..] != a.shape() {
+/// broacasted tensor elementwise subtraction. b -= a, see [broadcast_add].
+pub fn broadcast_sub(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op(a, b, "bsub_fwd_f32", "ssub_fwd_f32")
+}
+
+/// tensor elementwise multiplication. b *= a.
+pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape() != b.shape() {
         return Err(SmeltError::DimensionMismatch {
             expected: b.shape().to_vec(),
             got: a.shape().to_vec(),
         });
     }
-    let skip: usize = a.shape().iter().product();
     if a.device_id() != b.device_id() {
         return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
             got: b.device_id(),
@@ -331,21 +471,26 @@ pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
 
     let dev = a.cuda();
 
-    let module_name = "bmul_fwd_f32";
+    let module_name = "mul_fwd_f32";
     if !dev.has_func(module_name, module_name) {
         dev.load_ptx(ADD_PTX.into(), module_name, &[module_name])?;
     }
 
-    let numel = b.data().len();
+    let numel = a.data().len();
 
     let fwd_fn = dev.get_func(module_name, module_name).unwrap();
     let cfg = LaunchConfig::for_num_elems(numel as u32);
-    let params = (numel, a.data(), b.data_mut(), skip);
+    let params = (numel, a.data(), b.data_mut());
     unsafe { fwd_fn.launch(cfg, params) }?;
 
     Ok(())
 }
 
+/// broadcasted tensor elementwise multiplication. b *= a, see [broadcast_add].
+pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op(a, b, "bmul_fwd_f32", "smul_fwd_f32")
+}
+
 const NORMALIZE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/normalize.ptx"));
 
 /// Basic operation for the layernorm.
@@ -353,6 +498,18 @@ const NORMALIZE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/normalize.pt
 /// `mean` and `var` do not have to be initialized, they are simply passed to
 /// avoid allocation.
 pub fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
+    #[cfg(feature = "cudnn")]
+    {
+        super::cudnn::normalize(x, epsilon)
+    }
+    #[cfg(not(feature = "cudnn"))]
+    {
+        normalize_kernel(x, epsilon)
+    }
+}
+
+#[cfg_attr(feature = "cudnn", allow(dead_code))]
+fn normalize_kernel(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
     let dim = x.shape().len();
     let numel: usize = x.shape()[..dim - 1].iter().product();
     let size = x.shape()[dim - 1];
@@ -378,6 +535,11 @@ fn g_softmax<const CAUSAL: bool>(
     x: &mut Tensor,
     past_sequence_length: usize,
 ) -> Result<(), SmeltError> {
+    // TODO Causal softmax through cuDNN, it has no masking.
+    #[cfg(feature = "cudnn")]
+    if !CAUSAL {
+        return super::cudnn::softmax(x);
+    }
     let dim = x.shape().len();
 
     let m = x.shape()[dim - 2];
@@ -412,6 +574,152 @@ pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(),
     g_softmax::<true>(x, past_sequence_length)
 }
 
+/// Softmax on the dimension `dim` for tensor `x`.
+pub fn softmax_dim(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+    softmax_t(x, dim, 1.0)
+}
+
+/// Softmax of `x / temperature` on the dimension `dim` for tensor `x`.
+pub fn softmax_t(x: &mut Tensor, dim: usize, temperature: f32) -> Result<(), SmeltError> {
+    if temperature.is_nan() || temperature <= 0.0 {
+        return Err(SmeltError::InvalidTemperature(temperature));
+    }
+    let (outer, size, inner) = shape::narrow(x.shape(), dim, 0, 0)?;
+    let numel = outer * inner;
+    if numel * size == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "softmax_dim_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(SOFTMAX_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), size, inner, 1.0 / temperature);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+const SCAN_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/scan.ptx"));
+/// Cumulative sum on the dimension `dim` for tensor `x`.
+pub fn cumsum(x: &mut Tensor, dim: usize) -> Result<(), SmeltError> {
+    let (outer, size, inner) = shape::narrow(x.shape(), dim, 0, 0)?;
+    let numel = outer * inner;
+    if numel * size == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "cumsum_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(SCAN_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), size, inner);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// `x` padded with `value` into `out`, `padding` holding the `(before, after)` items
+/// added to each of the last `padding.len()` dimensions. Negative values crop `x`.
+pub fn pad(
+    x: &Tensor,
+    padding: &[(isize, isize)],
+    value: f32,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (expected, before) = shape::pad(x.shape(), padding)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if x.device_id() != out.device_id() {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got: out.device_id(),
+            expected: x.device_id(),
+        }));
+    }
+    let numel: usize = expected.iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "pad_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(LAYOUT_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let rank = expected.len();
+    let info = dev.htod_copy([expected, x.shape().to_vec()].concat())?;
+    let before = dev.htod_copy(before.into_iter().map(|b| b as i64).collect())?;
+    let params = (numel, rank, &info, &before, value, x.data(), out.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+const REDUCE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/reduce.ptx"));
+
+/// Runs the reduction kernel `module_name` of `x` along `dim` into `out`.
+fn reduce(
+    module_name: &'static str,
+    x: &Tensor,
+    dim: usize,
+    keepdim: bool,
+    out: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let (_, size, inner, expected) = shape::reduce(x.shape(), dim, keepdim)?;
+    if out.shape() != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: out.shape().to_vec(),
+        });
+    }
+    if x.device_id() != out.device_id() {
+        return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+            got: out.device_id(),
+            expected: x.device_id(),
+        }));
+    }
+    let numel: usize = expected.iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(REDUCE_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, size, inner, x.data(), out.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// Sum of `x` along `dim` into `out`, the dimension being removed or kept with a size
+/// of 1 depending on `keepdim`.
+pub fn sum(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce("sum_f32", x, dim, keepdim, out)
+}
+
+/// Mean of `x` along `dim` into `out`, see [sum].
+pub fn mean(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce("mean_f32", x, dim, keepdim, out)
+}
+
+/// Maximum of `x` along `dim` into `out`, see [sum].
+pub fn max(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce("max_f32", x, dim, keepdim, out)
+}
+
+/// Population variance of `x` along `dim` into `out`, see [sum].
+pub fn var(x: &Tensor, dim: usize, keepdim: bool, out: &mut Tensor) -> Result<(), SmeltError> {
+    reduce("var_f32", x, dim, keepdim, out)
+}
+
 const UNITARY_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/unitary.ptx"));
 /// utility function to use a faster but less precise tanh
 #[inline]
@@ -448,6 +756,212 @@ pub fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
     Ok(())
 }
 
+/// Runs the elementwise kernel `module_name` of the unitary ops on every item of `x`.
+fn unary(x: &mut Tensor, module_name: &'static str) -> Result<(), SmeltError> {
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut());
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// `activation` of every item of the tensor, see [Activation].
+pub fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+    match activation {
+        Activation::Gelu => unary(x, "gelu_erf_f32"),
+        Activation::GeluNew | Activation::GeluFast => gelu(x),
+        Activation::Relu => unary(x, "relu_f32"),
+        Activation::Silu => unary(x, "silu_f32"),
+        Activation::QuickGelu => unary(x, "quick_gelu_f32"),
+    }
+}
+
+/// x = exp(x)
+pub fn exp(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "exp_f32")
+}
+
+/// x = ln(x), the natural logarithm
+pub fn log(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "log_f32")
+}
+
+/// x = sqrt(x)
+pub fn sqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "sqrt_f32")
+}
+
+/// x = 1 / sqrt(x)
+pub fn rsqrt(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "rsqrt_f32")
+}
+
+/// x = 1 / (1 + exp(-x))
+pub fn sigmoid(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "sigmoid_f32")
+}
+
+/// x = erf(x), the error function
+pub fn erf(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "erf_f32")
+}
+
+/// x = -x
+pub fn neg(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "neg_f32")
+}
+
+/// x = |x|
+pub fn abs(x: &mut Tensor) -> Result<(), SmeltError> {
+    unary(x, "abs_f32")
+}
+
+/// x = min(max(x, min), max), every item becomes `max` when `min > max` like in torch.
+pub fn clamp(x: &mut Tensor, min: f32, max: f32) -> Result<(), SmeltError> {
+    let dev = x.cuda();
+    let module_name = "clamp_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (numel, x.data_mut(), min, max);
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// x = max(x, min)
+pub fn clamp_min(x: &mut Tensor, min: f32) -> Result<(), SmeltError> {
+    clamp(x, min, f32::INFINITY)
+}
+
+/// x = min(x, max)
+pub fn clamp_max(x: &mut Tensor, max: f32) -> Result<(), SmeltError> {
+    clamp(x, f32::NEG_INFINITY, max)
+}
+
+/// Zeroes the items (i, j) of the matrices of the last two dimensions of `x` outside of
+/// the triangle, see [tril] and [triu].
+fn triangle(x: &mut Tensor, diagonal: isize, upper: bool) -> Result<(), SmeltError> {
+    let rank = x.shape().len();
+    if rank < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let (rows, cols) = (x.shape()[rank - 2], x.shape()[rank - 1]);
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "triangle_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (
+        numel,
+        rows,
+        cols,
+        diagonal as i64,
+        upper as i32,
+        x.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// Rotary position embedding of `x` in place, see [crate::traits::TensorRotary].
+pub fn rotary(x: &mut Tensor, cos: &Tensor, sin: &Tensor, offset: usize) -> Result<(), SmeltError> {
+    let (length, half) = shape::rotary(x.shape(), cos.shape(), sin.shape(), offset)?;
+    let numel = x.shape().iter().product::<usize>() / 2;
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "rotary_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (
+        numel,
+        length,
+        half,
+        offset,
+        cos.data(),
+        sin.data(),
+        x.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
+/// Lower triangle of the matrices of the last two dimensions of `x`, the items (i, j)
+/// with `j - i > diagonal` are zeroed.
+pub fn tril(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+    triangle(x, diagonal, false)
+}
+
+/// Upper triangle of the matrices of the last two dimensions of `x`, the items (i, j)
+/// with `j - i < diagonal` are zeroed.
+pub fn triu(x: &mut Tensor, diagonal: isize) -> Result<(), SmeltError> {
+    triangle(x, diagonal, true)
+}
+
+/// x = -inf for the keys hidden from the queries of the attention scores `x` (...,
+/// query_length, key_length), see [crate::traits::TensorPositionMask].
+pub fn position_mask(
+    x: &mut Tensor,
+    offset: usize,
+    length: usize,
+    causal: bool,
+    sliding_window: Option<usize>,
+) -> Result<(), SmeltError> {
+    let rank = x.shape().len();
+    if rank < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let (rows, cols) = (x.shape()[rank - 2], x.shape()[rank - 1]);
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let dev = x.cuda();
+    let module_name = "position_mask_f32";
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(UNITARY_PTX.into(), module_name, &[module_name])?;
+    }
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let cfg = LaunchConfig::for_num_elems(numel as u32);
+    let params = (
+        numel,
+        rows,
+        cols,
+        offset,
+        length,
+        causal as i32,
+        // No window is a window larger than any distance.
+        sliding_window.unwrap_or(usize::MAX),
+        x.data_mut(),
+    );
+    unsafe { fwd_fn.launch(cfg, params) }?;
+    Ok(())
+}
+
 /// TODO
 #[inline]
 pub fn mul_scalar(x: &mut Tensor, factor: f32) -> Result<(), SmeltError> {
@@ -468,7 +982,7 @@ pub fn mul_scalar(x: &mut Tensor, factor: f32) -> Result<(), SmeltError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::gpu::f32::Device;
+    use crate::gpu::f32::{Device, PinnedBuffer};
     use crate::tests::simplify;
 
     fn device() -> Device {
@@ -521,6 +1035,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn simple_bmm() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 1, 2], &device).unwrap();
+        let b = Tensor::from_cpu(&[1.0, 1.0, 2.0, 0.0], vec![2, 2, 1], &device).unwrap();
+        let mut c = Tensor::zeros(vec![2, 1, 1], &device).unwrap();
+        bmm(&a, &b, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), [3.0, 6.0]);
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2], &device).unwrap();
+        assert!(bmm(&a, &a, &mut c).is_err());
+    }
+
     #[test]
     fn simple_matmul_t() {
         let device = device();
@@ -591,6 +1118,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn numpy_broadcasting() {
+        let device = device();
+        // (2, 1, 2) over (2, 2, 2)
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 1, 2], &device).unwrap();
+        let mut b = Tensor::from_cpu(&[10.0; 8], vec![2, 2, 2], &device).unwrap();
+        broadcast_sub(&a, &mut b).unwrap();
+        assert_eq!(
+            b.cpu_data().unwrap(),
+            [9.0, 8.0, 9.0, 8.0, 7.0, 6.0, 7.0, 6.0]
+        );
+
+        let a = Tensor::from_cpu(&[1.0, -1.0], vec![2, 1], &device).unwrap();
+        let mut c = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        broadcast_mul(&a, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), [1.0, 2.0, -3.0, -4.0]);
+
+        let a = Tensor::zeros(vec![3], &device).unwrap();
+        assert!(broadcast_add(&a, &mut c).is_err());
+    }
+
     #[test]
     fn simple_mul() {
         let device = device();
@@ -616,6 +1164,159 @@ mod tests {
         );
     }
 
+    #[test]
+    fn softmax_any_dim() {
+        let device = device();
+        let mut a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        softmax_dim(&mut a, 0).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            [0.1192, 0.1192, 0.8808, 0.8808]
+        );
+        let mut a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        softmax_t(&mut a, 1, 0.5).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            [0.1192, 0.8808, 0.1192, 0.8808]
+        );
+        assert!(softmax_t(&mut a, 1, -1.0).is_err());
+    }
+
+    #[test]
+    fn triangles() {
+        let device = device();
+        let mut x = Tensor::from_cpu(&[1.0; 12], vec![2, 2, 3], &device).unwrap();
+        triu(&mut x, 1).unwrap();
+        assert_eq!(
+            x.cpu_data().unwrap(),
+            [0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0]
+        );
+        tril(&mut x, 1).unwrap();
+        assert_eq!(x.cpu_data().unwrap()[..6], [0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn rotary_positions() {
+        let device = device();
+        let cos = Tensor::from_cpu(&[1.0, 1.0, -1.0, -1.0, 1.0, 1.0], vec![3, 2], &device).unwrap();
+        let sin = Tensor::zeros(vec![3, 2], &device).unwrap();
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let mut x = Tensor::from_cpu(&data, vec![1, 2, 4], &device).unwrap();
+        rotary(&mut x, &cos, &sin, 1).unwrap();
+        assert_eq!(
+            x.cpu_data().unwrap(),
+            [-0.0, -1.0, -2.0, -3.0, 4.0, 5.0, 6.0, 7.0]
+        );
+    }
+
+    #[test]
+    fn padding() {
+        let device = device();
+        let x = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let mut out = Tensor::zeros(vec![3, 3], &device).unwrap();
+        pad(&x, &[(1, 0), (0, 1)], -1.0, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            [-1.0, -1.0, -1.0, 1.0, 2.0, -1.0, 3.0, 4.0, -1.0]
+        );
+        let mut back = Tensor::zeros(vec![2, 2], &device).unwrap();
+        pad(&out, &[(-1, 0), (0, -1)], 0.0, &mut back).unwrap();
+        assert_eq!(back.cpu_data().unwrap(), [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn reductions() {
+        let device = device();
+        let data: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        let x = Tensor::from_cpu(&data, vec![2, 3, 2], &device).unwrap();
+        let mut out = Tensor::zeros(vec![2, 2], &device).unwrap();
+        sum(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [6.0, 9.0, 24.0, 27.0]);
+        mean(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [2.0, 3.0, 8.0, 9.0]);
+        max(&x, 1, false, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [4.0, 5.0, 10.0, 11.0]);
+        var(&x, 1, false, &mut out).unwrap();
+        assert_eq!(simplify(&out.cpu_data().unwrap()), [2.6667; 4]);
+        let mut out = Tensor::zeros(vec![2, 3, 1], &device).unwrap();
+        sum(&x, 2, true, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), [1.0, 5.0, 9.0, 13.0, 17.0, 21.0]);
+    }
+
+    #[test]
+    fn clamps() {
+        let device = device();
+        let data = [-2.0, -0.5, 0.5, 2.0];
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        clamp_min(&mut x, -1.0).unwrap();
+        clamp_max(&mut x, 1.0).unwrap();
+        assert_eq!(x.cpu_data().unwrap(), [-1.0, -0.5, 0.5, 1.0]);
+        clamp(&mut x, 1.0, 0.0).unwrap();
+        assert_eq!(x.cpu_data().unwrap(), [0.0; 4]);
+    }
+
+    #[test]
+    fn unary_ops() {
+        let device = device();
+        let data = [-2.0, -0.5, 0.0, 1.0];
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        abs(&mut x).unwrap();
+        assert_eq!(x.cpu_data().unwrap(), [2.0, 0.5, 0.0, 1.0]);
+        sqrt(&mut x).unwrap();
+        log(&mut x).unwrap();
+        exp(&mut x).unwrap();
+        rsqrt(&mut x).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [0.8409, 1.1892, f32::INFINITY, 1.0]
+        );
+
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        neg(&mut x).unwrap();
+        sigmoid(&mut x).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [0.8808, 0.6225, 0.5, 0.2689]
+        );
+
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        erf(&mut x).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [-0.9953, -0.5205, 0.0, 0.8427]
+        );
+
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        activation(&mut x, Activation::Gelu).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [-0.0455, -0.1543, 0.0, 0.8413]
+        );
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        activation(&mut x, Activation::Silu).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [-0.2384, -0.1888, 0.0, 0.7311]
+        );
+        let mut x = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        activation(&mut x, Activation::QuickGelu).unwrap();
+        assert_eq!(
+            simplify(&x.cpu_data().unwrap()),
+            [-0.0643, -0.1496, 0.0, 0.8458]
+        );
+    }
+
+    #[test]
+    fn cumsum_any_dim() {
+        let device = device();
+        let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let mut a = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+        cumsum(&mut a, 0).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), [1.0, 2.0, 3.0, 5.0, 7.0, 9.0]);
+        cumsum(&mut a, 1).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), [1.0, 3.0, 6.0, 5.0, 12.0, 21.0]);
+    }
+
     #[test]
     fn simple_causal_softmax() {
         let device = device();
@@ -696,4 +1397,136 @@ mod tests {
         //     [ 1.1031, -0.2559, -0.8472, -0.6359, -0.5167,  1.1526]
         // );
     }
+
+    #[test]
+    #[cfg(feature = "cudnn")]
+    fn cudnn_matches_kernels() {
+        let device = device();
+        let data: Vec<_> = (0..24).map(|i| ((i * 7) % 11) as f32 - 5.0).collect();
+
+        let mut a = Tensor::from_cpu(&data, vec![2, 3, 4], &device).unwrap();
+        let mut b = a.clone();
+        softmax(&mut a).unwrap();
+        // A causal softmax which sees the whole row, through the custom kernel.
+        causal_softmax(&mut b, 4).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            simplify(&b.cpu_data().unwrap())
+        );
+
+        let mut a = Tensor::from_cpu(&data, vec![6, 4], &device).unwrap();
+        let mut b = a.clone();
+        normalize(&mut a, 1e-5).unwrap();
+        normalize_kernel(&mut b, 1e-5).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            simplify(&b.cpu_data().unwrap())
+        );
+    }
+
+    #[test]
+    fn async_copies() {
+        let device = device();
+        let stream = device.fork_stream().unwrap();
+        let a =
+            Tensor::from_cpu_async(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device, &stream).unwrap();
+        let mut b = Tensor::from_cpu(&[1.0, 1.0, 1.0, 1.0], vec![2, 2], &device).unwrap();
+        device.wait_for(&stream).unwrap();
+        add(&a, &mut b).unwrap();
+        let copy = b.to_cpu_async().unwrap();
+        assert_eq!(copy.wait().unwrap(), [2.0, 3.0, 4.0, 5.0]);
+        device.synchronize().unwrap();
+    }
+
+    #[test]
+    fn pinned_copies() {
+        let device = device();
+        let mut pinned = PinnedBuffer::new(4, &device).unwrap();
+        pinned.copy_from_slice(&[1.0, 2.0, 3.0, 4.0]);
+        let a = Tensor::from_pinned(&pinned, vec![2, 2], &device).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), [1.0, 2.0, 3.0, 4.0]);
+
+        // Large enough to be staged in several chunks.
+        let data: Vec<_> = (0..10_000_000).map(|i| (i % 7) as f32).collect();
+        let a = Tensor::from_cpu(&data, vec![data.len()], &device).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), data);
+        device.release_staging();
+    }
+
+    #[test]
+    fn pool_reuse() {
+        // A fresh device, the others are shared between tests.
+        let device = Device::new(0).unwrap();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0], vec![3], &device).unwrap();
+        drop(a);
+        assert_eq!(device.pool_stats().cached_buffers, 1);
+        assert_eq!(device.pool_stats().cached_bytes, 12);
+
+        let b = Tensor::zeros(vec![3, 1], &device).unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [0.0, 0.0, 0.0]);
+        let stats = device.pool_stats();
+        assert_eq!((stats.hits, stats.misses, stats.cached_buffers), (1, 1, 0));
+
+        drop(b);
+        device.clear_pool();
+        assert_eq!(device.pool_stats().cached_buffers, 0);
+    }
+
+    #[test]
+    fn device_info() {
+        let devices = Device::enumerate().unwrap();
+        assert!(!devices.is_empty());
+        let info = device().info().unwrap();
+        // Free memory changes as other tests run.
+        assert_eq!(info.name, devices[0].name);
+        assert_eq!(info.total_memory, devices[0].total_memory);
+        assert!(info.compute_capability.0 > 0);
+
+        let stats = device().memory_stats().unwrap();
+        assert!(stats.free <= stats.total);
+        assert_eq!(stats.total, info.total_memory);
+
+        let num_devices = devices.len();
+        match Device::new(num_devices) {
+            Err(SmeltError::Cuda(CudaError::DeviceNotFound { device_id, .. })) => {
+                assert_eq!(device_id, num_devices)
+            }
+            _ => panic!("Expected DeviceNotFound"),
+        }
+    }
+
+    #[test]
+    fn device_to_device() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let b = a.to_device(&device).unwrap();
+        assert_eq!(b.shape(), [2, 2]);
+        assert_eq!(b.cpu_data().unwrap(), [1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn copy_to_device() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let mut b = Tensor::zeros(vec![2, 2], &device).unwrap();
+        a.copy_to_device(&mut b).unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [1.0, 2.0, 3.0, 4.0]);
+
+        let mut c = Tensor::zeros(vec![4], &device).unwrap();
+        assert!(a.copy_to_device(&mut c).is_err());
+    }
+
+    #[test]
+    fn graph_replay() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0], vec![2], &device).unwrap();
+        let mut b = Tensor::zeros(vec![2], &device).unwrap();
+        // Loads the kernel before the capture.
+        add(&a, &mut b).unwrap();
+        let graph = device.capture(|| add(&a, &mut b)).unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [1.0, 2.0]);
+        graph.replay().unwrap();
+        graph.replay().unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [3.0, 6.0]);
+    }
 }
@@ -0,0 +1,406 @@
+use crate::gpu::f32::{add, broadcast_add, matmul_t, pad, CudaError, Device, Tensor};
+use crate::nn::layers::{
+    AttentionConfig, AttentionContext, Linear, MultiHeadAttention, QkvProjection,
+};
+use crate::SmeltError;
+
+fn check_sharding(size: usize, num_shards: usize) -> Result<usize, SmeltError> {
+    if num_shards == 0 || !size.is_multiple_of(num_shards) {
+        return Err(SmeltError::Cuda(CudaError::InvalidSharding {
+            size,
+            num_shards,
+        }));
+    }
+    Ok(size / num_shards)
+}
+
+/// Splits `tensor` in `devices.len()` equal parts along dimension `dim`, the `i`-th part
+/// being sent to `devices[i]`. The parts are cropped on the device of `tensor`, then
+/// copied peer to peer (see [Tensor::to_device]).
+pub fn shard(tensor: &Tensor, dim: usize, devices: &[Device]) -> Result<Vec<Tensor>, SmeltError> {
+    let shape = tensor.shape();
+    if dim >= shape.len() {
+        return Err(SmeltError::InsufficientRank {
+            minimum_rank: dim + 1,
+        });
+    }
+    let chunk = check_sharding(shape[dim], devices.len())?;
+
+    let mut shard_shape = shape.to_vec();
+    shard_shape[dim] = chunk;
+    devices
+        .iter()
+        .enumerate()
+        .map(|(i, device)| {
+            // Negative padding crops `dim`, the following dimensions are kept whole.
+            let mut padding = vec![(0, 0); shape.len() - dim];
+            padding[0] = (
+                -((i * chunk) as isize),
+                -(((devices.len() - i - 1) * chunk) as isize),
+            );
+            let mut part = Tensor::zeros(shard_shape.clone(), tensor.device())?;
+            pad(tensor, &padding, 0.0, &mut part)?;
+            part.to_device(device)
+        })
+        .collect()
+}
+
+/// Copies `tensor` on every device of `devices`.
+pub fn replicate(tensor: &Tensor, devices: &[Device]) -> Result<Vec<Tensor>, SmeltError> {
+    devices
+        .iter()
+        .map(|device| tensor.to_device(device))
+        .collect()
+}
+
+/// Sums all the `shards` elementwise, every shard contains the sum afterwards.
+/// The shards are reduced onto the device of the first one, and the sum is copied back
+/// peer to peer.
+pub fn all_reduce(shards: &mut [Tensor]) -> Result<(), SmeltError> {
+    let Some((root, others)) = shards.split_first_mut() else {
+        return Ok(());
+    };
+    for shard in others.iter() {
+        if shard.shape() != root.shape() {
+            return Err(SmeltError::DimensionMismatch {
+                expected: root.shape().to_vec(),
+                got: shard.shape().to_vec(),
+            });
+        }
+        add(&shard.to_device(root.device())?, root)?;
+    }
+    for shard in others {
+        root.copy_to_device(shard)?;
+    }
+    Ok(())
+}
+
+/// Concatenates `shards` along their last dimension into `out`, the shards being copied
+/// peer to peer onto the device of `out`.
+pub fn all_gather(shards: &[Tensor], out: &mut Tensor) -> Result<(), SmeltError> {
+    let dim = out.shape().len();
+    if dim == 0 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 1 });
+    }
+    let chunk = check_sharding(out.shape()[dim - 1], shards.len())?;
+    let mut expected = out.shape().to_vec();
+    expected[dim - 1] = chunk;
+
+    let mut padded = Tensor::zeros(out.shape().to_vec(), out.device())?;
+    for (i, shard) in shards.iter().enumerate() {
+        if shard.shape() != expected {
+            return Err(SmeltError::DimensionMismatch {
+                expected,
+                got: shard.shape().to_vec(),
+            });
+        }
+        let shard = shard.to_device(out.device())?;
+        // Every shard is padded with zeros to its columns of `out`, then summed.
+        let padding = [(
+            (i * chunk) as isize,
+            ((shards.len() - i - 1) * chunk) as isize,
+        )];
+        if i == 0 {
+            pad(&shard, &padding, 0.0, out)?;
+        } else {
+            pad(&shard, &padding, 0.0, &mut padded)?;
+            add(&padded, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn dense_weight(linear: &Linear<Tensor>) -> Result<&Tensor, SmeltError> {
+    linear.weight().ok_or(SmeltError::Unsupported {
+        operation: "shard",
+        backend: "quantized",
+    })
+}
+
+/// A [Linear] layer whose output features are split across devices.
+/// Every device receives the full input and computes its own slice of the output,
+/// use [all_gather] to recover the full output.
+#[derive(Clone)]
+pub struct ColumnParallelLinear {
+    shards: Vec<Linear<Tensor>>,
+}
+
+impl ColumnParallelLinear {
+    /// Splits `linear` across `devices`.
+    pub fn new(linear: &Linear<Tensor>, devices: &[Device]) -> Result<Self, SmeltError> {
+        let weights = shard(dense_weight(linear)?, 0, devices)?;
+        let biases = shard(linear.bias(), 0, devices)?;
+        let shards = weights
+            .into_iter()
+            .zip(biases)
+            .map(|(weight, bias)| Linear::new(weight, bias))
+            .collect();
+        Ok(Self { shards })
+    }
+
+    /// The per device layers
+    pub fn shards(&self) -> &[Linear<Tensor>] {
+        &self.shards
+    }
+
+    /// Forward pass, `inputs[i]` and `outputs[i]` live on the `i`-th device.
+    pub fn forward(&self, inputs: &[Tensor], outputs: &mut [Tensor]) -> Result<(), SmeltError> {
+        if inputs.len() != self.shards.len() || outputs.len() != self.shards.len() {
+            return Err(SmeltError::InvalidLength {
+                expected: self.shards.len(),
+                got: inputs.len().min(outputs.len()),
+            });
+        }
+        for ((linear, input), output) in self.shards.iter().zip(inputs).zip(outputs) {
+            linear.forward(input, output)?;
+        }
+        Ok(())
+    }
+}
+
+/// A [Linear] layer whose input features are split across devices.
+/// Every device receives its slice of the input (like the output of
+/// [ColumnParallelLinear]), and the partial outputs are summed with [all_reduce].
+#[derive(Clone)]
+pub struct RowParallelLinear {
+    weights: Vec<Tensor>,
+    bias: Tensor,
+}
+
+impl RowParallelLinear {
+    /// Splits `linear` across `devices`.
+    pub fn new(linear: &Linear<Tensor>, devices: &[Device]) -> Result<Self, SmeltError> {
+        let weights = shard(dense_weight(linear)?, 1, devices)?;
+        // The bias is added only once, before the reduction.
+        let bias = replicate(linear.bias(), &devices[..1])?.remove(0);
+        Ok(Self { weights, bias })
+    }
+
+    /// Forward pass, `inputs[i]` and `outputs[i]` live on the `i`-th device.
+    /// Every output contains the full result afterwards.
+    pub fn forward(&self, inputs: &[Tensor], outputs: &mut [Tensor]) -> Result<(), SmeltError> {
+        if inputs.len() != self.weights.len() || outputs.len() != self.weights.len() {
+            return Err(SmeltError::InvalidLength {
+                expected: self.weights.len(),
+                got: inputs.len().min(outputs.len()),
+            });
+        }
+        for ((weight, input), output) in self.weights.iter().zip(inputs).zip(outputs.iter_mut()) {
+            matmul_t(input, weight, output)?;
+        }
+        broadcast_add(&self.bias, &mut outputs[0])?;
+        all_reduce(outputs)
+    }
+}
+
+/// The buffers of a [HeadParallelAttention] for a sequence length, one per device, see
+/// [HeadParallelAttention::context].
+pub struct HeadParallelContext {
+    attention: Vec<AttentionContext<Tensor>>,
+    // The merged heads of every device, the input of the output projection.
+    heads: Vec<Tensor>,
+}
+
+/// A [MultiHeadAttention] whose heads are split across devices.
+/// The query, key and value projections are split like [ColumnParallelLinear], every
+/// device attending with its own heads, and the output projection is a
+/// [RowParallelLinear] so the heads are summed with [all_reduce].
+#[derive(Clone)]
+pub struct HeadParallelAttention {
+    heads: Vec<MultiHeadAttention<Tensor>>,
+    output: RowParallelLinear,
+}
+
+impl HeadParallelAttention {
+    /// Splits the heads of `config` across `devices`, the number of heads (and of key
+    /// and value heads) must be a multiple of `devices.len()`.
+    pub fn new(
+        query: &Linear<Tensor>,
+        key: &Linear<Tensor>,
+        value: &Linear<Tensor>,
+        output: &Linear<Tensor>,
+        config: AttentionConfig,
+        devices: &[Device],
+    ) -> Result<Self, SmeltError> {
+        let config = AttentionConfig {
+            num_heads: check_sharding(config.num_heads, devices.len())?,
+            num_kv_heads: check_sharding(config.num_kv_heads, devices.len())?,
+            ..config
+        };
+        let query = ColumnParallelLinear::new(query, devices)?;
+        let key = ColumnParallelLinear::new(key, devices)?;
+        let value = ColumnParallelLinear::new(value, devices)?;
+        let heads = query
+            .shards
+            .into_iter()
+            .zip(key.shards)
+            .zip(value.shards)
+            .map(|((query, key), value)| {
+                let qkv = QkvProjection::Separate { query, key, value };
+                MultiHeadAttention::new(qkv, None, config.clone())
+            })
+            .collect::<Result<_, _>>()?;
+        let output = RowParallelLinear::new(output, devices)?;
+        Ok(Self { heads, output })
+    }
+
+    /// The per device attentions, without their output projection
+    pub fn shards(&self) -> &[MultiHeadAttention<Tensor>] {
+        &self.heads
+    }
+
+    /// The buffers of the attention of `sequence_length` tokens on every device.
+    pub fn context(&self, sequence_length: usize) -> Result<HeadParallelContext, SmeltError> {
+        let mut attention = Vec::with_capacity(self.heads.len());
+        let mut heads = Vec::with_capacity(self.heads.len());
+        for (shard, weight) in self.heads.iter().zip(&self.output.weights) {
+            let device = weight.device();
+            let width = weight.shape()[1];
+            attention.push(shard.context(sequence_length, device)?);
+            heads.push(Tensor::zeros(vec![sequence_length, width], device)?);
+        }
+        Ok(HeadParallelContext { attention, heads })
+    }
+
+    /// Forward pass, `inputs[i]`, `masks[i]` and `outputs[i]` live on the `i`-th device,
+    /// see [MultiHeadAttention::forward]. Every output contains the full result afterwards.
+    pub fn forward(
+        &self,
+        inputs: &[Tensor],
+        masks: Option<&[Tensor]>,
+        ctx: &mut HeadParallelContext,
+        outputs: &mut [Tensor],
+    ) -> Result<(), SmeltError> {
+        let lengths = [
+            inputs.len(),
+            masks.map_or(self.heads.len(), |masks| masks.len()),
+            ctx.attention.len(),
+        ];
+        if let Some(got) = lengths.into_iter().find(|len| *len != self.heads.len()) {
+            return Err(SmeltError::InvalidLength {
+                expected: self.heads.len(),
+                got,
+            });
+        }
+        for (i, (shard, input)) in self.heads.iter().zip(inputs).enumerate() {
+            let mask = masks.map(|masks| &masks[i]);
+            shard.forward(input, mask, &mut ctx.attention[i], &mut ctx.heads[i])?;
+        }
+        self.output.forward(&ctx.heads, outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<Device> {
+        // Two handles on the same gpu are enough to check the splits.
+        vec![Device::new(0).unwrap(), Device::new(0).unwrap()]
+    }
+
+    #[test]
+    fn test_shard() {
+        let devices = devices();
+        let data: Vec<_> = (0..8).map(|i| i as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![2, 4], &devices[0]).unwrap();
+
+        let shards = shard(&tensor, 0, &devices).unwrap();
+        assert_eq!(shards[0].cpu_data().unwrap(), [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(shards[1].cpu_data().unwrap(), [4.0, 5.0, 6.0, 7.0]);
+
+        let shards = shard(&tensor, 1, &devices).unwrap();
+        assert_eq!(shards[0].shape(), [2, 2]);
+        assert_eq!(shards[0].cpu_data().unwrap(), [0.0, 1.0, 4.0, 5.0]);
+        assert_eq!(shards[1].cpu_data().unwrap(), [2.0, 3.0, 6.0, 7.0]);
+
+        assert!(shard(&tensor, 0, &devices[..1]).is_ok());
+        let tensor = Tensor::from_cpu(&data[..6], vec![3, 2], &devices[0]).unwrap();
+        assert!(shard(&tensor, 0, &devices).is_err());
+    }
+
+    #[test]
+    fn test_parallel_linear() {
+        let devices = devices();
+        let weight: Vec<_> = (0..8).map(|i| i as f32).collect();
+        let weight = Tensor::from_cpu(&weight, vec![4, 2], &devices[0]).unwrap();
+        let bias = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![4], &devices[0]).unwrap();
+        let linear = Linear::new(weight, bias);
+        let input = Tensor::from_cpu(&[1.0, 1.0], vec![1, 2], &devices[0]).unwrap();
+
+        let mut expected = Tensor::zeros(vec![1, 4], &devices[0]).unwrap();
+        linear.forward(&input, &mut expected).unwrap();
+
+        let column = ColumnParallelLinear::new(&linear, &devices).unwrap();
+        let inputs = replicate(&input, &devices).unwrap();
+        let mut outputs: Vec<_> = devices
+            .iter()
+            .map(|d| Tensor::zeros(vec![1, 2], d).unwrap())
+            .collect();
+        column.forward(&inputs, &mut outputs).unwrap();
+        let mut out = Tensor::zeros(vec![1, 4], &devices[0]).unwrap();
+        all_gather(&outputs, &mut out).unwrap();
+        assert_eq!(out.cpu_data().unwrap(), expected.cpu_data().unwrap());
+
+        let row = RowParallelLinear::new(&linear, &devices).unwrap();
+        let inputs = shard(&input, 1, &devices).unwrap();
+        let mut outputs: Vec<_> = devices
+            .iter()
+            .map(|d| Tensor::zeros(vec![1, 4], d).unwrap())
+            .collect();
+        row.forward(&inputs, &mut outputs).unwrap();
+        assert_eq!(outputs[1].cpu_data().unwrap(), expected.cpu_data().unwrap());
+    }
+
+    #[test]
+    fn test_head_parallel_attention() {
+        let devices = devices();
+        let linear = |offset: usize| {
+            let weight: Vec<_> = (0..16).map(|i| ((i + offset) as f32 * 0.1).sin()).collect();
+            let weight = Tensor::from_cpu(&weight, vec![4, 4], &devices[0]).unwrap();
+            let bias = Tensor::from_cpu(&[0.1, 0.2, 0.3, 0.4], vec![4], &devices[0]).unwrap();
+            Linear::new(weight, bias)
+        };
+        let (query, key, value, output) = (linear(0), linear(16), linear(32), linear(48));
+        let config = AttentionConfig::new(2, 2);
+        let input: Vec<_> = (0..12).map(|i| (i as f32 * 0.3).cos()).collect();
+        let input = Tensor::from_cpu(&input, vec![3, 4], &devices[0]).unwrap();
+
+        let qkv = QkvProjection::Separate {
+            query: query.clone(),
+            key: key.clone(),
+            value: value.clone(),
+        };
+        let attention = MultiHeadAttention::new(qkv, Some(output.clone()), config.clone()).unwrap();
+        let mut ctx = attention.context(3, &devices[0]).unwrap();
+        let mut expected = Tensor::zeros(vec![3, 4], &devices[0]).unwrap();
+        attention
+            .forward(&input, None, &mut ctx, &mut expected)
+            .unwrap();
+
+        let parallel =
+            HeadParallelAttention::new(&query, &key, &value, &output, config, &devices).unwrap();
+        assert_eq!(parallel.shards()[0].config().num_heads, 1);
+        let inputs = replicate(&input, &devices).unwrap();
+        let mut ctx = parallel.context(3).unwrap();
+        let mut outputs: Vec<_> = devices
+            .iter()
+            .map(|d| Tensor::zeros(vec![3, 4], d).unwrap())
+            .collect();
+        parallel
+            .forward(&inputs, None, &mut ctx, &mut outputs)
+            .unwrap();
+        let expected = expected.cpu_data().unwrap();
+        for output in &outputs {
+            let output = output.cpu_data().unwrap();
+            for (o, e) in output.iter().zip(&expected) {
+                assert!((o - e).abs() < 1e-5, "{output:?} != {expected:?}");
+            }
+        }
+
+        let config = AttentionConfig::new(3, 2);
+        assert!(
+            HeadParallelAttention::new(&query, &key, &value, &output, config, &devices).is_err()
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::gpu::f32::Device;
+use crate::SmeltError;
+use cudarc::driver::sys;
+use std::ops::{Deref, DerefMut};
+
+/// A buffer of page-locked (pinned) host memory.
+/// The device reads it directly, which makes host to device copies several times faster
+/// than from regular (pageable) memory. Pinned memory is a scarce resource, keep the
+/// buffers few and reuse them.
+pub struct PinnedBuffer {
+    ptr: *mut f32,
+    len: usize,
+}
+
+// SAFETY: The buffer is plain host memory, uniquely owned.
+unsafe impl Send for PinnedBuffer {}
+// SAFETY: The buffer is plain host memory, only mutated through `&mut`.
+unsafe impl Sync for PinnedBuffer {}
+
+impl PinnedBuffer {
+    /// Allocates a zeroed pinned buffer of `len` floats, within the context of `device`.
+    pub fn new(len: usize, device: &Device) -> Result<Self, SmeltError> {
+        device.cuda().bind_to_thread()?;
+        let mut ptr = std::ptr::null_mut();
+        // Zero sized allocations are invalid.
+        let size = len.max(1) * std::mem::size_of::<f32>();
+        // SAFETY: The pointer is written by the allocation.
+        unsafe { sys::cuMemAllocHost_v2(&mut ptr, size) }.result()?;
+        let ptr = ptr as *mut f32;
+        // SAFETY: The allocation holds at least `len` floats.
+        unsafe { std::ptr::write_bytes(ptr, 0, len) };
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Deref for PinnedBuffer {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        // SAFETY: `ptr` holds `len` initialized floats.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for PinnedBuffer {
+    fn deref_mut(&mut self) -> &mut [f32] {
+        // SAFETY: `ptr` holds `len` initialized floats.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for PinnedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` was allocated by `cuMemAllocHost` and is not used anymore.
+        unsafe { sys::cuMemFreeHost(self.ptr as *mut _) }
+            .result()
+            .ok();
+    }
+}
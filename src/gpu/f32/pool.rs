@@ -0,0 +1,59 @@
+use cudarc::driver::{CudaSlice, DeviceSlice};
+use std::collections::HashMap;
+
+/// Statistics of the caching allocator of a [crate::gpu::f32::Device].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The number of freed buffers kept for reuse
+    pub cached_buffers: usize,
+    /// The total size (in bytes) of the cached buffers
+    pub cached_bytes: usize,
+    /// The number of allocations served by a cached buffer
+    pub hits: usize,
+    /// The number of allocations which went to the driver
+    pub misses: usize,
+}
+
+/// Freed device buffers, by number of floats. Buffers are only reused for the exact
+/// same size, which is enough for the intermediate tensors of a model that are
+/// recreated with the same shapes at every layer and every run.
+/// TODO Split larger buffers instead of going back to the driver.
+#[derive(Default)]
+pub(crate) struct Pool {
+    buffers: HashMap<usize, Vec<CudaSlice<f32>>>,
+    stats: PoolStats,
+}
+
+impl Pool {
+    /// A cached buffer of exactly `len` floats, if any.
+    pub(crate) fn take(&mut self, len: usize) -> Option<CudaSlice<f32>> {
+        let buffer = self.buffers.get_mut(&len).and_then(|buffers| buffers.pop());
+        if buffer.is_some() {
+            self.stats.hits += 1;
+            self.stats.cached_buffers -= 1;
+            self.stats.cached_bytes -= len * std::mem::size_of::<f32>();
+        } else {
+            self.stats.misses += 1;
+        }
+        buffer
+    }
+
+    /// Keeps `buffer` for a later allocation of the same size.
+    pub(crate) fn put(&mut self, buffer: CudaSlice<f32>) {
+        let len = buffer.len();
+        self.stats.cached_buffers += 1;
+        self.stats.cached_bytes += len * std::mem::size_of::<f32>();
+        self.buffers.entry(len).or_default().push(buffer);
+    }
+
+    /// Gives all the cached buffers back to the driver.
+    pub(crate) fn clear(&mut self) {
+        self.buffers.clear();
+        self.stats.cached_buffers = 0;
+        self.stats.cached_bytes = 0;
+    }
+
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
@@ -0,0 +1,74 @@
+use crate::gpu::f32::{Device, Tensor};
+use crate::SmeltError;
+use cudarc::driver::{LaunchAsync, LaunchConfig};
+
+const RANDOM_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/random.ptx"));
+
+impl Tensor {
+    /// A tensor of uniform items in `[0, 1)`, generated on the device. The items are the
+    /// same as [crate::cpu::f32::Tensor::rand_uniform] for a given `seed`.
+    /// ```
+    /// use smelte_rs::gpu::f32::{Device, Tensor};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::rand_uniform(vec![2, 3], 42, &device).unwrap();
+    /// let data = tensor.cpu_data().unwrap();
+    /// assert!(data.iter().all(|v| (0.0..1.0).contains(v)));
+    /// ```
+    pub fn rand_uniform(shape: Vec<usize>, seed: u64, device: &Device) -> Result<Self, SmeltError> {
+        Self::rand(shape, seed, device, "uniform_f32")
+    }
+
+    /// A tensor of standard normal items, see [Tensor::rand_uniform]. The items match the
+    /// cpu ones up to the rounding of `logf` and `cosf`.
+    pub fn rand_normal(shape: Vec<usize>, seed: u64, device: &Device) -> Result<Self, SmeltError> {
+        Self::rand(shape, seed, device, "normal_f32")
+    }
+
+    fn rand(
+        shape: Vec<usize>,
+        seed: u64,
+        device: &Device,
+        module_name: &'static str,
+    ) -> Result<Self, SmeltError> {
+        let mut out = Tensor::zeros(shape, device)?;
+        let numel: usize = out.shape().iter().product();
+        if numel == 0 {
+            return Ok(out);
+        }
+        let dev = out.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(RANDOM_PTX.into(), module_name, &[module_name])?;
+        }
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (numel, seed, out.data_mut());
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rand() {
+        let device = Device::new(0).unwrap();
+        let a = Tensor::rand_uniform(vec![4, 8], 3, &device).unwrap();
+        let b = Tensor::rand_uniform(vec![32], 3, &device).unwrap();
+        assert_eq!(a.cpu_data().unwrap(), b.cpu_data().unwrap());
+        let c = Tensor::rand_uniform(vec![32], 4, &device).unwrap();
+        assert_ne!(a.cpu_data().unwrap(), c.cpu_data().unwrap());
+        #[cfg(feature = "cpu")]
+        assert_eq!(
+            a.cpu_data().unwrap(),
+            crate::cpu::f32::Tensor::rand_uniform(vec![32], 3).data()
+        );
+
+        let x = Tensor::rand_normal(vec![1024], 3, &device).unwrap();
+        let data = x.cpu_data().unwrap();
+        let mean = data.iter().sum::<f32>() / data.len() as f32;
+        assert!(mean.abs() < 0.1);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::gpu::f32::Tensor;
+use crate::shape;
+use crate::SmeltError;
+use cudarc::driver::{LaunchAsync, LaunchConfig};
+
+const SEARCH_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/search.ptx"));
+
+impl Tensor {
+    /// The index of the largest item along the dimension `dim`, for every other index.
+    /// The indices have the shape of `self` without `dim`, the first maximum wins.
+    /// Only the indices are copied back to the host.
+    /// ```
+    /// use smelte_rs::gpu::f32::{Device, Tensor};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let data = [0.1, 2.0, -1.0, 3.0, 0.5, 0.0];
+    /// let logits = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+    /// assert_eq!(logits.argmax(1).unwrap(), [1, 0]);
+    /// ```
+    pub fn argmax(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        self.arg_search(dim, "argmax_f32")
+    }
+
+    /// The index of the smallest item along the dimension `dim`, see [Tensor::argmax].
+    pub fn argmin(&self, dim: usize) -> Result<Vec<usize>, SmeltError> {
+        self.arg_search(dim, "argmin_f32")
+    }
+
+    fn arg_search(&self, dim: usize, module_name: &'static str) -> Result<Vec<usize>, SmeltError> {
+        let (outer, size, inner) = shape::narrow(self.shape(), dim, 0, 1)?;
+        let numel = outer * inner;
+        if numel == 0 {
+            return Ok(vec![]);
+        }
+        let dev = self.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(SEARCH_PTX.into(), module_name, &[module_name])?;
+        }
+        let mut indices = dev.alloc_zeros::<usize>(numel)?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (numel, size, inner, self.data(), &mut indices);
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok(dev.dtoh_sync_copy(&indices)?)
+    }
+
+    /// The `k` largest items along the dimension `dim` in decreasing order, and their
+    /// indices. Both have the shape of `self` with `k` items in `dim`, the values stay
+    /// on the device.
+    pub fn topk(&self, k: usize, dim: usize) -> Result<(Self, Vec<usize>), SmeltError> {
+        let (outer, size, inner) = shape::narrow(self.shape(), dim, 0, k)?;
+        let mut shape = self.shape().to_vec();
+        shape[dim] = k;
+        let mut values = Tensor::zeros(shape, self.device())?;
+        let numel = outer * inner;
+        if numel * k == 0 {
+            return Ok((values, vec![]));
+        }
+        let module_name = "topk_f32";
+        let dev = self.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(SEARCH_PTX.into(), module_name, &[module_name])?;
+        }
+        let mut indices = dev.alloc_zeros::<usize>(numel * k)?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            size,
+            inner,
+            k,
+            self.data(),
+            values.data_mut(),
+            &mut indices,
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok((values, dev.dtoh_sync_copy(&indices)?))
+    }
+
+    /// The items sorted along the dimension `dim` on the device, and their indices in
+    /// `self`. The sort is stable, equal items keep their order.
+    /// ```
+    /// use smelte_rs::gpu::f32::{Device, Tensor};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let probs = Tensor::from_cpu(&[0.1, 0.6, 0.05, 0.25], vec![1, 4], &device).unwrap();
+    /// let (values, indices) = probs.sort(1, true).unwrap();
+    /// assert_eq!(values.cpu_data().unwrap(), [0.6, 0.25, 0.1, 0.05]);
+    /// assert_eq!(indices, [1, 3, 0, 2]);
+    /// ```
+    pub fn sort(&self, dim: usize, descending: bool) -> Result<(Self, Vec<usize>), SmeltError> {
+        let (outer, size, inner) = shape::narrow(self.shape(), dim, 0, 0)?;
+        let mut values = Tensor::zeros(self.shape().to_vec(), self.device())?;
+        let rows = outer * inner;
+        if rows * size == 0 {
+            return Ok((values, vec![]));
+        }
+        let module_name = "sort_f32";
+        let dev = self.cuda();
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(SEARCH_PTX.into(), module_name, &[module_name])?;
+        }
+        let padded = size.next_power_of_two();
+        // SAFETY: The rows are filled by the kernel before being sorted.
+        let mut keys = unsafe { dev.alloc::<f32>(rows * padded)? };
+        let mut order = unsafe { dev.alloc::<usize>(rows * padded)? };
+        let mut indices = dev.alloc_zeros::<usize>(rows * size)?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = sort_config(rows, padded);
+        let params = (
+            size,
+            inner,
+            padded,
+            descending as i32,
+            self.data(),
+            &mut keys,
+            &mut order,
+            values.data_mut(),
+            &mut indices,
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
+        Ok((values, dev.dtoh_sync_copy(&indices)?))
+    }
+}
+
+/// Top-p filtering of the logits `x` on its last dimension, see
+/// [crate::traits::TensorTopP]. The rows are sorted on the device.
+pub fn top_p(x: &mut Tensor, p: f32) -> Result<(), SmeltError> {
+    if !(p > 0.0 && p <= 1.0) {
+        return Err(SmeltError::InvalidProbability(p));
+    }
+    let size = match x.shape().last() {
+        Some(&size) => size,
+        None => return Err(SmeltError::InsufficientRank { minimum_rank: 1 }),
+    };
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 {
+        return Ok(());
+    }
+    let rows = numel / size;
+    let module_name = "top_p_f32";
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(SEARCH_PTX.into(), module_name, &[module_name])?;
+    }
+    let padded = size.next_power_of_two();
+    // SAFETY: The rows are filled by the kernel before being sorted.
+    let mut keys = unsafe { dev.alloc::<f32>(rows * padded)? };
+    let mut order = unsafe { dev.alloc::<usize>(rows * padded)? };
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let params = (size, padded, p, x.data_mut(), &mut keys, &mut order);
+    unsafe { fwd_fn.launch(sort_config(rows, padded), params) }?;
+    Ok(())
+}
+
+/// Top-k filtering of the logits `x` on its last dimension, see
+/// [crate::traits::TensorTopK]. The rows are sorted on the device.
+pub fn top_k(x: &mut Tensor, k: usize) -> Result<(), SmeltError> {
+    if k == 0 {
+        return Err(SmeltError::InvalidConfig(
+            "top-k filtering with k = 0".into(),
+        ));
+    }
+    let size = match x.shape().last() {
+        Some(&size) => size,
+        None => return Err(SmeltError::InsufficientRank { minimum_rank: 1 }),
+    };
+    let numel: usize = x.shape().iter().product();
+    if numel == 0 || k >= size {
+        return Ok(());
+    }
+    let rows = numel / size;
+    let module_name = "top_k_f32";
+    let dev = x.cuda();
+    if !dev.has_func(module_name, module_name) {
+        dev.load_ptx(SEARCH_PTX.into(), module_name, &[module_name])?;
+    }
+    let padded = size.next_power_of_two();
+    // SAFETY: The rows are filled by the kernel before being sorted.
+    let mut keys = unsafe { dev.alloc::<f32>(rows * padded)? };
+    let mut order = unsafe { dev.alloc::<usize>(rows * padded)? };
+    let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+    let params = (size, padded, k, x.data_mut(), &mut keys, &mut order);
+    unsafe { fwd_fn.launch(sort_config(rows, padded), params) }?;
+    Ok(())
+}
+
+/// One block per row, with a thread per pair of items of the bitonic sort.
+fn sort_config(rows: usize, padded: usize) -> LaunchConfig {
+    LaunchConfig {
+        grid_dim: (rows as u32, 1, 1),
+        block_dim: ((padded / 2).clamp(1, 1024) as u32, 1, 1),
+        shared_mem_bytes: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::f32::Device;
+
+    #[test]
+    fn test_topk() {
+        let device = Device::new(0).unwrap();
+        let data = [1.0, 5.0, 3.0, 5.0, 0.0, 2.0];
+        let tensor = Tensor::from_cpu(&data, vec![3, 2], &device).unwrap();
+        assert_eq!(tensor.argmax(0).unwrap(), [1, 0]);
+        assert_eq!(tensor.argmin(0).unwrap(), [2, 2]);
+        let (values, indices) = tensor.topk(2, 0).unwrap();
+        assert_eq!(values.shape(), [2, 2]);
+        assert_eq!(values.cpu_data().unwrap(), [3.0, 5.0, 1.0, 5.0]);
+        assert_eq!(indices, [1, 0, 0, 1]);
+        assert!(tensor.topk(3, 1).is_err());
+    }
+
+    #[test]
+    fn test_sort() {
+        let device = Device::new(0).unwrap();
+        let data = [1.0, 5.0, 3.0, 5.0, 0.0, 2.0];
+        let tensor = Tensor::from_cpu(&data, vec![3, 2], &device).unwrap();
+        let (values, indices) = tensor.sort(0, false).unwrap();
+        assert_eq!(values.cpu_data().unwrap(), [0.0, 2.0, 1.0, 5.0, 3.0, 5.0]);
+        assert_eq!(indices, [2, 2, 0, 0, 1, 1]);
+        let (values, indices) = tensor.sort(0, true).unwrap();
+        assert_eq!(values.cpu_data().unwrap(), [3.0, 5.0, 1.0, 5.0, 0.0, 2.0]);
+        assert_eq!(indices, [1, 0, 0, 1, 2, 2]);
+
+        // Longer than a block, and not a power of 2.
+        let data: Vec<f32> = (0..3000).map(|i| ((i * 7919) % 3000) as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![3000], &device).unwrap();
+        let (values, _) = tensor.sort(0, false).unwrap();
+        let expected: Vec<f32> = (0..3000).map(|i| i as f32).collect();
+        assert_eq!(values.cpu_data().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_top_p() {
+        let device = Device::new(0).unwrap();
+        let inf = f32::NEG_INFINITY;
+        let data = [0.0, 0.0, 0.0, 0.0, 5.0, 1.0, 0.0, 5.0];
+        let mut x = Tensor::from_cpu(&data, vec![2, 4], &device).unwrap();
+        top_p(&mut x, 0.5).unwrap();
+        assert_eq!(
+            x.cpu_data().unwrap(),
+            [0.0, 0.0, inf, inf, 5.0, inf, inf, 5.0]
+        );
+        assert!(top_p(&mut x, 1.5).is_err());
+    }
+
+    #[test]
+    fn test_top_k() {
+        let device = Device::new(0).unwrap();
+        let inf = f32::NEG_INFINITY;
+        let data = [0.0, 0.0, 0.0, 0.0, 5.0, 1.0, 0.0, 5.0];
+        let mut x = Tensor::from_cpu(&data, vec![2, 4], &device).unwrap();
+        top_k(&mut x, 2).unwrap();
+        assert_eq!(
+            x.cpu_data().unwrap(),
+            [0.0, 0.0, inf, inf, 5.0, inf, inf, 5.0]
+        );
+        assert!(top_k(&mut x, 0).is_err());
+    }
+}
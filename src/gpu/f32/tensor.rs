@@ -1,38 +1,242 @@
+#[cfg(feature = "cudnn")]
+use crate::gpu::f32::cudnn::Cudnn;
+use crate::gpu::f32::pool::{Pool, PoolStats};
+use crate::gpu::f32::{info, CudaError, PinnedBuffer};
 use crate::SmeltError;
 use cudarc::cublas::safe::CudaBlas;
-use cudarc::driver::{CudaDevice, CudaSlice, DriverError};
-use std::sync::Arc;
+#[cfg(feature = "cublaslt")]
+use cudarc::cublaslt::CudaBlasLT;
+use cudarc::driver::{sys, CudaDevice, CudaSlice, CudaStream, DevicePtr, DeviceSlice, DriverError};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, Mutex};
+
+/// The size (in floats) of the pinned buffer used to stage [Tensor::from_cpu] copies.
+const STAGING_SIZE: usize = 4 * 1024 * 1024;
 
 /// Tensor, can own, or borrow the underlying tensor
-#[derive(Clone)]
 pub struct Tensor {
     shape: Vec<usize>,
     device: Device,
-    data: CudaSlice<f32>,
+    // Given back to the pool of `device` on drop.
+    data: ManuallyDrop<CudaSlice<f32>>,
 }
 
-/// The GPU device, contains its id, a cuda handle and a cublas handle
+/// The GPU device, contains its id, a cuda handle, a cublas handle (and a cuDNN one with
+/// the `cudnn` feature), the pinned buffer used to upload tensors, the pool of freed buffers
+/// and the causal masks already built.
 #[derive(Clone)]
 pub struct Device {
     device: Arc<CudaDevice>,
     device_id: usize,
     blas: Arc<CudaBlas>,
+    #[cfg(feature = "cublaslt")]
+    blaslt: Arc<CudaBlasLT>,
+    #[cfg(feature = "cudnn")]
+    cudnn: Arc<Cudnn>,
+    staging: Arc<Mutex<Option<PinnedBuffer>>>,
+    pool: Arc<Mutex<Pool>>,
+    causal_masks: Arc<Mutex<HashMap<usize, Arc<CudaSlice<u8>>>>>,
+}
+
+/// A cuda stream forked from the default stream of a [Device]. Work queued on it
+/// (for instance [Tensor::from_cpu_async]) overlaps with the kernels launched on the
+/// default stream, use [Device::wait_for] before using its results.
+#[derive(Clone)]
+pub struct Stream {
+    stream: Arc<CudaStream>,
+}
+
+/// A pending device to host copy, created by [Tensor::to_cpu_async].
+/// Dropping it waits for the copy to finish.
+pub struct HostCopy<'a> {
+    data: Vec<f32>,
+    stream: sys::CUstream,
+    // Keeps the context (and its default stream) alive.
+    _device: Arc<CudaDevice>,
+    // The source tensor cannot be modified while the copy is in flight.
+    _tensor: PhantomData<&'a Tensor>,
 }
 
 impl Device {
-    /// TODO
+    /// Creates the device `device_id`, see [Device::enumerate] for the available ones.
     pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        let num_devices = info::count()?;
+        if device_id >= num_devices {
+            return Err(SmeltError::Cuda(CudaError::DeviceNotFound {
+                device_id,
+                num_devices,
+            }));
+        }
         let device = CudaDevice::new(device_id)?;
         let blas = Arc::new(CudaBlas::new(device.clone())?);
+        #[cfg(feature = "cublaslt")]
+        let blaslt = Arc::new(CudaBlasLT::new(device.clone())?);
+        #[cfg(feature = "cudnn")]
+        let cudnn = Arc::new(Cudnn::new(device.clone())?);
         Ok(Self {
             device,
             device_id,
             blas,
+            #[cfg(feature = "cublaslt")]
+            blaslt,
+            #[cfg(feature = "cudnn")]
+            cudnn,
+            staging: Arc::new(Mutex::new(None)),
+            pool: Arc::new(Mutex::new(Pool::default())),
+            causal_masks: Arc::new(Mutex::new(HashMap::new())),
         })
     }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device_id
+    }
+
+    /// The underlying cuda device
+    pub fn cuda(&self) -> &Arc<CudaDevice> {
+        &self.device
+    }
+
+    /// The cublas handle of the device
+    #[cfg(any(feature = "f16", feature = "bf16"))]
+    pub(crate) fn blas(&self) -> &Arc<CudaBlas> {
+        &self.blas
+    }
+
+    /// The causal masks of every length built on the device, see [super::Mask::causal]
+    pub(super) fn causal_masks(&self) -> &Mutex<HashMap<usize, Arc<CudaSlice<u8>>>> {
+        &self.causal_masks
+    }
+
+    /// The cuDNN handle of the device
+    #[cfg(feature = "cudnn")]
+    pub(crate) fn cudnn(&self) -> &Arc<Cudnn> {
+        &self.cudnn
+    }
+
+    /// Frees the pinned buffer used by [Tensor::from_cpu], for instance once all the
+    /// weights are loaded. It is allocated again when needed.
+    pub fn release_staging(&self) {
+        *self.staging.lock().unwrap() = None;
+    }
+
+    /// The statistics of the caching allocator. Every buffer freed by a [Tensor] is kept
+    /// by the device, and reused by the next tensor of the same size.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.lock().unwrap().stats()
+    }
+
+    /// Gives all the buffers cached by the device back to the driver.
+    pub fn clear_pool(&self) {
+        self.pool.lock().unwrap().clear();
+    }
+
+    /// A buffer of `len` floats, from the pool when possible.
+    /// # Safety
+    /// The buffer is not initialized.
+    unsafe fn alloc(&self, len: usize) -> Result<CudaSlice<f32>, DriverError> {
+        let buffer = self.pool.lock().unwrap().take(len);
+        match buffer {
+            Some(buffer) => Ok(buffer),
+            None => self.device.alloc(len),
+        }
+    }
+
+    /// A nulled buffer of `len` floats, from the pool when possible.
+    fn alloc_zeros(&self, len: usize) -> Result<CudaSlice<f32>, DriverError> {
+        let buffer = self.pool.lock().unwrap().take(len);
+        match buffer {
+            Some(mut buffer) => {
+                self.device.memset_zeros(&mut buffer)?;
+                Ok(buffer)
+            }
+            None => self.device.alloc_zeros(len),
+        }
+    }
+
+    /// Creates a new [Stream], which will run concurrently with the default stream.
+    pub fn fork_stream(&self) -> Result<Stream, SmeltError> {
+        let stream = self.device.fork_default_stream()?;
+        Ok(Stream {
+            stream: Arc::new(stream),
+        })
+    }
+
+    /// Makes the default stream wait for all the work currently queued on `stream`.
+    /// This does not block the host.
+    pub fn wait_for(&self, stream: &Stream) -> Result<(), SmeltError> {
+        self.device.wait_for(&stream.stream)?;
+        Ok(())
+    }
+
+    /// Blocks the host until all the work queued on this device is done.
+    pub fn synchronize(&self) -> Result<(), SmeltError> {
+        self.device.synchronize()?;
+        Ok(())
+    }
+}
+
+impl Stream {
+    /// Blocks the host until all the work queued on this stream is done.
+    pub fn synchronize(&self) -> Result<(), SmeltError> {
+        // SAFETY: The stream is alive as long as `self`.
+        unsafe { sys::cuStreamSynchronize(self.stream.stream) }.result()?;
+        Ok(())
+    }
+}
+
+impl<'a> HostCopy<'a> {
+    fn synchronize(&self) -> Result<(), DriverError> {
+        // SAFETY: The stream belongs to `_device` which is still alive.
+        unsafe { sys::cuStreamSynchronize(self.stream) }.result()
+    }
+
+    /// Waits for the copy to finish and returns the data.
+    pub fn wait(mut self) -> Result<Vec<f32>, SmeltError> {
+        self.synchronize()?;
+        Ok(std::mem::take(&mut self.data))
+    }
+}
+
+impl<'a> Drop for HostCopy<'a> {
+    fn drop(&mut self) {
+        // The driver might still be writing into `data`.
+        self.synchronize().ok();
+    }
+}
+
+impl Clone for Tensor {
+    fn clone(&self) -> Self {
+        // SAFETY: The whole buffer is overwritten right away.
+        let mut data = unsafe { self.device.alloc(self.data.len()) }
+            .expect("Could not allocate the tensor copy");
+        self.device
+            .device
+            .dtod_copy(&*self.data, &mut data)
+            .expect("Could not copy the tensor");
+        Self::new(data, self.shape.clone(), &self.device)
+    }
+}
+
+impl Drop for Tensor {
+    fn drop(&mut self) {
+        // SAFETY: `data` is never used again.
+        let data = unsafe { ManuallyDrop::take(&mut self.data) };
+        self.device.pool.lock().unwrap().put(data);
+    }
 }
 
 impl Tensor {
+    fn new(data: CudaSlice<f32>, shape: Vec<usize>, device: &Device) -> Self {
+        Self {
+            shape,
+            device: device.clone(),
+            data: ManuallyDrop::new(data),
+        }
+    }
+
     /// The shape of the tensor
     /// ```
     /// use smelte_rs::gpu::f32::{Tensor, Device};
@@ -70,6 +274,12 @@ impl Tensor {
         self.device.blas.clone()
     }
 
+    /// The CudaBlasLT handle
+    #[cfg(feature = "cublaslt")]
+    pub fn blaslt(&self) -> Arc<CudaBlasLT> {
+        self.device.blaslt.clone()
+    }
+
     /// The device id
     pub fn device_id(&self) -> usize {
         self.device.device_id
@@ -84,15 +294,13 @@ impl Tensor {
     /// ```
     pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, DriverError> {
         let nelement: usize = shape.iter().product();
-        let data: CudaSlice<f32> = device.device.alloc_zeros(nelement)?;
-        Ok(Self {
-            shape,
-            data,
-            device: device.clone(),
-        })
+        let data = device.alloc_zeros(nelement)?;
+        Ok(Self::new(data, shape, device))
     }
 
     /// Creates a tensor from a cpu [Vec].
+    /// Large pageable buffers are staged through a pinned buffer of the device, which is
+    /// much faster than copying them directly.
     pub fn from_cpu(data: &[f32], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
         if data.len() != shape.iter().product::<usize>() {
             return Err(SmeltError::InvalidBuffer {
@@ -100,17 +308,165 @@ impl Tensor {
                 shape,
             });
         }
-        let data = device.device.htod_sync_copy(data).unwrap();
-        Ok(Self {
-            device: device.clone(),
-            data,
-            shape,
-        })
+        if data.len() < STAGING_SIZE / 16 {
+            // SAFETY: The whole buffer is written right away.
+            let mut buffer = unsafe { device.alloc(data.len())? };
+            device.device.htod_sync_copy_into(data, &mut buffer)?;
+            return Ok(Self::new(buffer, shape, device));
+        }
+        // SAFETY: The whole buffer is written chunk by chunk below, before any use.
+        let mut buffer = unsafe { device.alloc(data.len())? };
+        let mut staging = device.staging.lock().unwrap();
+        if staging.is_none() {
+            *staging = Some(PinnedBuffer::new(STAGING_SIZE, device)?);
+        }
+        let staging = staging.as_mut().unwrap();
+        for (i, chunk) in data.chunks(STAGING_SIZE).enumerate() {
+            let start = i * STAGING_SIZE;
+            staging[..chunk.len()].copy_from_slice(chunk);
+            device.device.htod_sync_copy_into(
+                &staging[..chunk.len()],
+                &mut buffer.slice_mut(start..start + chunk.len()),
+            )?;
+        }
+        Ok(Self::new(buffer, shape, device))
+    }
+
+    /// Creates a tensor from a [PinnedBuffer], the fastest way to upload data.
+    pub fn from_pinned(
+        data: &PinnedBuffer,
+        shape: Vec<usize>,
+        device: &Device,
+    ) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        // SAFETY: The whole buffer is written right away.
+        let mut buffer = unsafe { device.alloc(data.len())? };
+        device.device.htod_sync_copy_into(data, &mut buffer)?;
+        Ok(Self::new(buffer, shape, device))
+    }
+
+    /// Creates a tensor from a cpu [Vec], the copy is queued on `stream` and the
+    /// function returns without waiting for the device.
+    /// Call [Device::wait_for] before using the tensor on the default stream.
+    pub fn from_cpu_async(
+        data: &[f32],
+        shape: Vec<usize>,
+        device: &Device,
+        stream: &Stream,
+    ) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        // SAFETY: The whole buffer is written by the copy below, before any use.
+        let buffer = unsafe { device.alloc(data.len())? };
+        if !data.is_empty() {
+            // SAFETY: `buffer` holds `data.len()` floats. `data` is pageable, so the
+            // driver stages it before returning, it can be freed right after.
+            unsafe {
+                sys::cuMemcpyHtoDAsync_v2(
+                    *buffer.device_ptr(),
+                    data.as_ptr() as *const _,
+                    std::mem::size_of_val(data),
+                    stream.stream.stream,
+                )
+            }
+            .result()?;
+        }
+        Ok(Self::new(buffer, shape, device))
+    }
+
+    /// Copies the tensor onto `device`. Between two gpus the copy goes peer to peer
+    /// when the hardware allows it, and is staged through the host by the driver otherwise.
+    pub fn to_device(&self, device: &Device) -> Result<Self, SmeltError> {
+        if device.device_id == self.device.device_id {
+            return Ok(self.clone());
+        }
+        // SAFETY: The whole buffer is written by the copy below, before any use.
+        let buffer = unsafe { device.alloc(self.data.len())? };
+        let mut out = Self::new(buffer, self.shape.clone(), device);
+        self.copy_to_device(&mut out)?;
+        Ok(out)
+    }
+
+    /// Copies the tensor into `dst`, which can live on another gpu (see [Tensor::to_device]).
+    pub fn copy_to_device(&self, dst: &mut Tensor) -> Result<(), SmeltError> {
+        if self.shape != dst.shape {
+            return Err(SmeltError::DimensionMismatch {
+                expected: dst.shape.clone(),
+                got: self.shape.clone(),
+            });
+        }
+        if dst.device.device_id == self.device.device_id {
+            self.device.device.dtod_copy(&*self.data, &mut *dst.data)?;
+            return Ok(());
+        }
+        let numel = self.data.len();
+        if numel > 0 {
+            // The kernels writing the source run on its own default stream.
+            self.device.device.synchronize()?;
+            // SAFETY: Both buffers hold `numel` floats, and live in their own contexts.
+            // The copy is queued on the destination default stream, like the kernels
+            // that will read it.
+            unsafe {
+                sys::cuMemcpyPeerAsync(
+                    *dst.data.device_ptr(),
+                    *dst.device.device.cu_primary_ctx(),
+                    *self.data.device_ptr(),
+                    *self.device.device.cu_primary_ctx(),
+                    numel * std::mem::size_of::<f32>(),
+                    *dst.device.device.cu_stream(),
+                )
+            }
+            .result()?;
+        }
+        Ok(())
     }
 
     /// Returns a cpu vec containing copied data from the device.
     pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
-        let cpu_data = self.device.device.dtoh_sync_copy(&self.data)?;
+        let cpu_data = self.device.device.dtoh_sync_copy(&*self.data)?;
         Ok(cpu_data)
     }
+
+    /// Queues a copy of the data to the cpu on the default stream, after all the
+    /// kernels already launched on it. The host is free to launch more work
+    /// until [HostCopy::wait] is called.
+    pub fn to_cpu_async(&self) -> Result<HostCopy<'_>, SmeltError> {
+        let numel = self.shape.iter().product();
+        let mut data = vec![0.0; numel];
+        let stream = *self.device.device.cu_stream();
+        if numel > 0 {
+            // SAFETY: `data` holds as many floats as the device buffer, and outlives
+            // the copy since [HostCopy] waits for it.
+            unsafe {
+                sys::cuMemcpyDtoHAsync_v2(
+                    data.as_mut_ptr() as *mut _,
+                    *self.data.device_ptr(),
+                    numel * std::mem::size_of::<f32>(),
+                    stream,
+                )
+            }
+            .result()?;
+        }
+        Ok(HostCopy {
+            data,
+            stream,
+            _device: self.device.device.clone(),
+            _tensor: PhantomData,
+        })
+    }
+    /// The same data with the shape `shape`, which must hold as many items. Nothing is
+    /// copied, the tensor is contiguous.
+    pub fn reshape(mut self, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        self.shape = crate::shape::reshape(&self.shape, shape)?;
+        Ok(self)
+    }
 }
@@ -0,0 +1,188 @@
+use crate::gpu::f32::layout::copy_strided;
+use crate::gpu::f32::Tensor;
+use crate::shape;
+use crate::SmeltError;
+use cudarc::driver::DeviceSlice;
+use std::ops::RangeBounds;
+
+/// A strided view of the data of a [Tensor]. Narrowing and permuting a view only
+/// changes its shape, strides and offset, nothing is copied until
+/// [View::contiguous] runs a single kernel to give back a tensor for the ops.
+/// ```
+/// use smelte_rs::gpu::f32::{Device, Tensor};
+///
+/// let device = Device::new(0).unwrap();
+/// let data = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+/// let tensor = Tensor::from_cpu(&data, vec![2, 3], &device).unwrap();
+/// let view = tensor.view().transpose(0, 1).unwrap().slice(0, 1..).unwrap();
+/// assert_eq!(view.strides(), [1, 3]);
+/// let tensor = view.contiguous().unwrap();
+/// assert_eq!(tensor.cpu_data().unwrap(), [2.0, 5.0, 3.0, 6.0]);
+/// ```
+#[derive(Clone)]
+pub struct View<'a> {
+    tensor: &'a Tensor,
+    shape: Vec<usize>,
+    strides: Vec<usize>,
+    offset: usize,
+}
+
+impl Tensor {
+    /// The view of the whole tensor, see [View].
+    pub fn view(&self) -> View<'_> {
+        View {
+            tensor: self,
+            shape: self.shape().to_vec(),
+            strides: shape::strides(self.shape()),
+            offset: 0,
+        }
+    }
+
+    /// The tensor expanded to `shape` in a single kernel, see [View::expand].
+    pub fn expand(&self, shape: &[usize]) -> Result<Self, SmeltError> {
+        self.view().expand(shape)?.contiguous()
+    }
+
+    /// The tensor tiled `repeats` times along every dimension, like torch. Extra repeats
+    /// add dimensions on the left.
+    /// ```
+    /// use smelte_rs::gpu::f32::{Device, Tensor};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+    /// let tiled = tensor.repeat(&[1, 2]).unwrap();
+    /// assert_eq!(tiled.shape(), [2, 4]);
+    /// assert_eq!(tiled.cpu_data().unwrap(), [1.0, 2.0, 1.0, 2.0, 3.0, 4.0, 3.0, 4.0]);
+    /// ```
+    pub fn repeat(&self, repeats: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, info) = shape::repeat(self.shape(), repeats)?;
+        let (view_shape, view_strides) = info.split_at(info.len() / 2);
+        let view = View {
+            tensor: self,
+            shape: view_shape.to_vec(),
+            strides: view_strides.to_vec(),
+            offset: 0,
+        };
+        view.contiguous()?.reshape(shape)
+    }
+}
+
+impl<'a> View<'a> {
+    /// The shape of the view
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The number of items between two consecutive items of each dimension
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// Whether the items of the view follow each other in row major order
+    pub fn is_contiguous(&self) -> bool {
+        shape::is_contiguous(&self.shape, &self.strides)
+    }
+
+    /// The items `start..start + len` of the dimension `dim`, like [Tensor::narrow].
+    pub fn narrow(&self, dim: usize, start: usize, len: usize) -> Result<Self, SmeltError> {
+        shape::narrow(&self.shape, dim, start, len)?;
+        let mut view = self.clone();
+        view.shape[dim] = len;
+        view.offset += start * self.strides[dim];
+        Ok(view)
+    }
+
+    /// [View::narrow] with a range, like [Tensor::slice].
+    pub fn slice(&self, dim: usize, range: impl RangeBounds<usize>) -> Result<Self, SmeltError> {
+        let size = self.shape.get(dim).copied().unwrap_or(0);
+        let (start, len) = shape::range(range, size);
+        self.narrow(dim, start, len)
+    }
+
+    /// The view with its dimensions reordered, like [Tensor::permute].
+    pub fn permute(&self, dims: &[usize]) -> Result<Self, SmeltError> {
+        let (shape, _) = shape::permute(&self.shape, dims)?;
+        Ok(Self {
+            tensor: self.tensor,
+            shape,
+            strides: dims.iter().map(|&d| self.strides[d]).collect(),
+            offset: self.offset,
+        })
+    }
+
+    /// [View::permute] swapping the dimensions `dim0` and `dim1`.
+    pub fn transpose(&self, dim0: usize, dim1: usize) -> Result<Self, SmeltError> {
+        self.permute(&shape::transpose(self.shape.len(), dim0, dim1))
+    }
+
+    /// The view expanded to `shape`: the dimensions of 1 are repeated and new dimensions
+    /// can be added on the left, like broadcasting. Nothing is copied, the repeated
+    /// dimensions have a stride of 0.
+    pub fn expand(&self, shape: &[usize]) -> Result<Self, SmeltError> {
+        let strides = shape::expand(&self.shape, &self.strides, shape)?;
+        Ok(Self {
+            tensor: self.tensor,
+            shape: shape.to_vec(),
+            strides,
+            offset: self.offset,
+        })
+    }
+
+    /// The items of the view in a new contiguous tensor. When the view is already
+    /// contiguous this is a single device to device copy.
+    pub fn contiguous(&self) -> Result<Tensor, SmeltError> {
+        let mut out = Tensor::zeros(self.shape.clone(), self.tensor.device())?;
+        let numel = out.data().len();
+        if numel == 0 {
+            return Ok(out);
+        }
+        if self.is_contiguous() {
+            let data = self.tensor.data().slice(self.offset..self.offset + numel);
+            self.tensor.cuda().dtod_copy(&data, out.data_mut())?;
+        } else {
+            let info: Vec<usize> = self.shape.iter().chain(&self.strides).copied().collect();
+            copy_strided(self.tensor, &mut out, &info, self.offset)?;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu::f32::{Device, Tensor};
+
+    #[test]
+    fn test_view() {
+        let device = Device::new(0).unwrap();
+        let data: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let tensor = Tensor::from_cpu(&data, vec![2, 3, 4], &device).unwrap();
+
+        let view = tensor
+            .view()
+            .permute(&[2, 0, 1])
+            .unwrap()
+            .narrow(2, 1, 2)
+            .unwrap();
+        assert!(!view.is_contiguous());
+        let expected = tensor.permute(&[2, 0, 1]).unwrap().narrow(2, 1, 2).unwrap();
+        let out = view.contiguous().unwrap();
+        assert_eq!(out.shape(), expected.shape());
+        assert_eq!(out.cpu_data().unwrap(), expected.cpu_data().unwrap());
+
+        let view = tensor.view().slice(0, 1..).unwrap();
+        assert!(view.is_contiguous());
+        assert_eq!(view.contiguous().unwrap().cpu_data().unwrap()[0], 12.0);
+
+        let tensor = Tensor::from_cpu(&[1.0, 2.0], vec![2, 1], &device).unwrap();
+        let view = tensor.view().expand(&[3, 2, 2]).unwrap();
+        assert_eq!(view.strides(), [0, 1, 0]);
+        let out = tensor.expand(&[3, 2, 2]).unwrap();
+        assert_eq!(out.cpu_data().unwrap()[..4], [1.0, 1.0, 2.0, 2.0]);
+        let tiled = tensor.repeat(&[2, 1, 3]).unwrap();
+        assert_eq!(tiled.shape(), [2, 2, 3]);
+        assert_eq!(
+            tiled.cpu_data().unwrap()[..6],
+            [1.0, 1.0, 1.0, 2.0, 2.0, 2.0]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+#include <metal_stdlib>
+using namespace metal;
+
+#define OP(FORWARD, FUNC) \
+kernel void FORWARD( \
+    device const float *lhs [[buffer(0)]], \
+    device float *rhs [[buffer(1)]], \
+    constant uint &numel [[buffer(2)]], \
+    uint i [[thread_position_in_grid]] \
+) { \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float x = lhs[i]; \
+    const float y = rhs[i]; \
+    rhs[i] = (FUNC); \
+}
+
+#define BROADCAST_OP(FORWARD, FUNC) \
+kernel void FORWARD( \
+    device const float *lhs [[buffer(0)]], \
+    device float *rhs [[buffer(1)]], \
+    constant uint &numel [[buffer(2)]], \
+    constant uint &skip [[buffer(3)]], \
+    uint i [[thread_position_in_grid]] \
+) { \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float x = lhs[i % skip]; \
+    const float y = rhs[i]; \
+    rhs[i] = (FUNC); \
+}
+
+OP(add_fwd_f32, x + y)
+OP(mul_fwd_f32, x * y)
+BROADCAST_OP(badd_fwd_f32, x + y)
+BROADCAST_OP(bmul_fwd_f32, x * y)
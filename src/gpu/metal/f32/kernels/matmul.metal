@@ -0,0 +1,38 @@
+#include <metal_stdlib>
+using namespace metal;
+
+// One thread per output element, `b` is read as (k, n) or as (n, k) when
+// `b_transposed` is set.
+kernel void matmul_f32(
+    device const float *a [[buffer(0)]],
+    device const float *b [[buffer(1)]],
+    device float *c [[buffer(2)]],
+    constant uint &numel [[buffer(3)]],
+    constant uint &m [[buffer(4)]],
+    constant uint &n [[buffer(5)]],
+    constant uint &k [[buffer(6)]],
+    constant uint &b_transposed [[buffer(7)]],
+    uint idx [[thread_position_in_grid]]
+) {
+    if (idx >= numel) {
+        return;
+    }
+    const uint batch = idx / (m * n);
+    const uint row = (idx / n) % m;
+    const uint col = idx % n;
+
+    device const float *ap = a + batch * m * k + row * k;
+    device const float *bp = b + batch * n * k;
+
+    float sum = 0.0;
+    if (b_transposed) {
+        for (uint l = 0; l < k; l++) {
+            sum += ap[l] * bp[col * k + l];
+        }
+    } else {
+        for (uint l = 0; l < k; l++) {
+            sum += ap[l] * bp[l * n + col];
+        }
+    }
+    c[idx] = sum;
+}
@@ -0,0 +1,34 @@
+#include <metal_stdlib>
+using namespace metal;
+
+kernel void normalize_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    constant uint &size [[buffer(2)]],
+    constant float &epsilon [[buffer(3)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    device float *row = x + i * size;
+
+    float sum = 0.0;
+    for (uint j = 0; j < size; j++) {
+        sum += row[j];
+    }
+    const float mean = sum / size;
+    for (uint j = 0; j < size; j++) {
+        row[j] -= mean;
+    }
+
+    float var = 0.0;
+    for (uint j = 0; j < size; j++) {
+        var += row[j] * row[j];
+    }
+    var /= size;
+    const float stddev = sqrt(var + epsilon);
+    for (uint j = 0; j < size; j++) {
+        row[j] /= stddev;
+    }
+}
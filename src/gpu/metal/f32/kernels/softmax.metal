@@ -0,0 +1,41 @@
+#include <metal_stdlib>
+using namespace metal;
+
+kernel void softmax_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    constant uint &m [[buffer(2)]],
+    constant uint &size [[buffer(3)]],
+    constant uint &past_sequence_length [[buffer(4)]],
+    uint idx [[thread_position_in_grid]]
+) {
+    if (idx >= numel) {
+        return;
+    }
+    device float *row = x + idx * size;
+    const uint i = idx % m;
+
+    float current_max = -INFINITY;
+    for (uint j = 0; j < size; j++) {
+        if (row[j] > current_max && i + past_sequence_length >= j) {
+            current_max = row[j];
+        }
+    }
+    for (uint j = 0; j < size; j++) {
+        row[j] = exp(row[j] - current_max);
+    }
+
+    float sum = 0.0;
+    for (uint j = 0; j < size; j++) {
+        if (i + past_sequence_length >= j) {
+            sum += row[j];
+        }
+    }
+    for (uint j = 0; j < size; j++) {
+        if (i + past_sequence_length >= j) {
+            row[j] /= sum;
+        } else {
+            row[j] = 0.0;
+        }
+    }
+}
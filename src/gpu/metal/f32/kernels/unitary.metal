@@ -0,0 +1,95 @@
+#include <metal_stdlib>
+using namespace metal;
+
+kernel void tanh_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    x[i] = precise::tanh(x[i]);
+}
+
+kernel void gelu_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    // sqrt(2 / pi)
+    const float alpha = 0.7978845608 * (v + 0.044715 * v * v * v);
+    x[i] = 0.5 * v * (1.0 + precise::tanh(alpha));
+}
+
+// The error function, with the approximation 7.1.26 of Abramowitz and Stegun.
+float erf_approx(float v) {
+    const float t = 1.0 / (1.0 + 0.3275911 * fabs(v));
+    const float poly = t * (0.2548296 + t * (-0.28449672 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    return copysign(1.0 - poly * exp(-v * v), v);
+}
+
+kernel void gelu_erf_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    // 1 / sqrt(2)
+    x[i] = 0.5 * v * (1.0 + erf_approx(0.7071067812 * v));
+}
+
+kernel void relu_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = max(v, 0.0);
+}
+
+kernel void silu_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = v / (1.0 + exp(-v));
+}
+
+kernel void quick_gelu_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = v / (1.0 + exp(-1.702 * v));
+}
+
+kernel void mul_scalar_f32(
+    device float *x [[buffer(0)]],
+    constant uint &numel [[buffer(1)]],
+    constant float &factor [[buffer(2)]],
+    uint i [[thread_position_in_grid]]
+) {
+    if (i >= numel) {
+        return;
+    }
+    x[i] *= factor;
+}
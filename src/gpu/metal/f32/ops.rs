@@ -0,0 +1,460 @@
+use crate::gpu::metal::f32::{Arg, Tensor};
+use crate::traits::Activation;
+use crate::SmeltError;
+
+/// All potential errors linked specifically to metal.
+#[derive(Debug, Clone)]
+pub enum MetalError {
+    /// Tried an operation with tensors on different devices.
+    TensorOnDifferentDevice {
+        /// The device id of the culprit tensor
+        got: usize,
+        /// The device id of the reference tensor
+        expected: usize,
+    },
+    /// There is no metal device with this id on the machine.
+    DeviceNotFound(usize),
+    /// A kernel failed to compile.
+    Compilation(String),
+}
+
+fn same_device(a: &Tensor, b: &Tensor) -> Result<(), SmeltError> {
+    if a.device_id() != b.device_id() {
+        return Err(SmeltError::Metal(MetalError::TensorOnDifferentDevice {
+            got: b.device_id(),
+            expected: a.device_id(),
+        }));
+    }
+    Ok(())
+}
+
+/// Operation for selecting entire rows within tensor `weights`. Each `id` is the index
+/// of the row.
+pub fn select(ids: &[usize], weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    let sequence_length = ids.len();
+    let vocab_size = weights.shape()[0];
+    let hidden_dim = weights.shape()[1];
+    if out.shape() != [sequence_length, hidden_dim] {
+        return Err(SmeltError::DimensionMismatch {
+            expected: vec![sequence_length, hidden_dim],
+            got: out.shape().to_vec(),
+        });
+    }
+    same_device(weights, out)?;
+
+    let dev = weights.device().clone();
+    for (i, id) in ids.iter().enumerate() {
+        let id = *id;
+        if id >= vocab_size {
+            return Err(SmeltError::OutOfVocabulary { vocab_size, id });
+        }
+        let weight_offset = id * hidden_dim;
+        let data_offset = i * hidden_dim;
+        dev.copy(
+            weights.data(),
+            weight_offset,
+            out.data_mut(),
+            data_offset,
+            hidden_dim,
+        );
+    }
+    Ok(())
+}
+
+/// Copy tensor into another tensor
+pub fn copy(weights: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    if weights.shape() != out.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: out.shape().to_vec(),
+            got: weights.shape().to_vec(),
+        });
+    }
+    same_device(weights, out)?;
+    let numel = weights.shape().iter().product();
+    let dev = out.device().clone();
+    dev.copy(weights.data(), 0, out.data_mut(), 0, numel);
+    Ok(())
+}
+
+/// Regular matrix multiplication
+pub fn matmul(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<false>(a, b, out)
+}
+
+/// Matrix multiplication matmul(A, B.transposed())
+pub fn matmul_t(a: &Tensor, b: &Tensor, out: &mut Tensor) -> Result<(), SmeltError> {
+    g_matmul::<true>(a, b, out)
+}
+
+const MATMUL_METAL: &str = include_str!("kernels/matmul.metal");
+
+#[inline]
+fn g_matmul<const TRANSPOSE: bool>(
+    a: &Tensor,
+    b: &Tensor,
+    c: &mut Tensor,
+) -> Result<(), SmeltError> {
+    let dim = a.shape().len();
+
+    same_device(a, b)?;
+    same_device(a, c)?;
+
+    if dim < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    if b.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+    if c.shape().len() != dim {
+        return Err(SmeltError::InvalidRank { expected_rank: dim });
+    }
+
+    let m = a.shape()[dim - 2];
+    let k = a.shape()[dim - 1];
+
+    let mut expected_c = a.shape().to_vec();
+    let mut expected_b = a.shape().to_vec();
+
+    let (expected_b, n) = if TRANSPOSE {
+        let n = b.shape()[dim - 2];
+        expected_b[dim - 2] = n;
+        expected_b[dim - 1] = k;
+        (expected_b, n)
+    } else {
+        let n = b.shape()[dim - 1];
+        expected_b[dim - 2] = k;
+        expected_b[dim - 1] = n;
+        (expected_b, n)
+    };
+
+    expected_c[dim - 2] = m;
+    expected_c[dim - 1] = n;
+
+    if expected_b != b.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected_b,
+            got: b.shape().to_vec(),
+        });
+    }
+
+    if expected_c != c.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: expected_c,
+            got: c.shape().to_vec(),
+        });
+    }
+
+    let numel: usize = c.shape().iter().product();
+    let dev = a.device();
+    dev.launch(
+        MATMUL_METAL,
+        "matmul_f32",
+        &[
+            Arg::Buffer(a.data()),
+            Arg::Buffer(b.data()),
+            Arg::Buffer(c.data_mut()),
+            Arg::U32(numel as u32),
+            Arg::U32(m as u32),
+            Arg::U32(n as u32),
+            Arg::U32(k as u32),
+            Arg::U32(TRANSPOSE as u32),
+        ],
+        numel,
+    )
+}
+
+const ADD_METAL: &str = include_str!("kernels/add.metal");
+
+#[inline]
+fn binary_op(name: &'static str, a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if a.shape() != b.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    same_device(a, b)?;
+
+    let numel: usize = a.shape().iter().product();
+    let dev = a.device();
+    dev.launch(
+        ADD_METAL,
+        name,
+        &[
+            Arg::Buffer(a.data()),
+            Arg::Buffer(b.data_mut()),
+            Arg::U32(numel as u32),
+        ],
+        numel,
+    )
+}
+
+#[inline]
+fn broadcast_op(name: &'static str, a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    if &b.shape()[1..] != a.shape() {
+        return Err(SmeltError::DimensionMismatch {
+            expected: b.shape().to_vec(),
+            got: a.shape().to_vec(),
+        });
+    }
+    same_device(a, b)?;
+
+    let skip: usize = a.shape().iter().product();
+    let numel: usize = b.shape().iter().product();
+    let dev = a.device();
+    dev.launch(
+        ADD_METAL,
+        name,
+        &[
+            Arg::Buffer(a.data()),
+            Arg::Buffer(b.data_mut()),
+            Arg::U32(numel as u32),
+            Arg::U32(skip as u32),
+        ],
+        numel,
+    )
+}
+
+/// tensor elementwise addition. b += a.
+pub fn add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    binary_op("add_fwd_f32", a, b)
+}
+
+/// broacasted tensor elementwise addition. b += a.
+pub fn broadcast_add(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op("badd_fwd_f32", a, b)
+}
+
+/// tensor elementwise multiplication. b *= a.
+pub fn mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    binary_op("mul_fwd_f32", a, b)
+}
+
+/// broadcasted tensor elementwise multiplication. b *= a.
+pub fn broadcast_mul(a: &Tensor, b: &mut Tensor) -> Result<(), SmeltError> {
+    broadcast_op("bmul_fwd_f32", a, b)
+}
+
+const NORMALIZE_METAL: &str = include_str!("kernels/normalize.metal");
+
+/// Basic operation for the layernorm.
+/// x = (x - x.mean()) / (x.var() + epsilon)
+pub fn normalize(x: &mut Tensor, epsilon: f32) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+    let numel: usize = x.shape()[..dim - 1].iter().product();
+    let size = x.shape()[dim - 1];
+    let dev = x.device().clone();
+    dev.launch(
+        NORMALIZE_METAL,
+        "normalize_f32",
+        &[
+            Arg::Buffer(x.data_mut()),
+            Arg::U32(numel as u32),
+            Arg::U32(size as u32),
+            Arg::F32(epsilon),
+        ],
+        numel,
+    )
+}
+
+const SOFTMAX_METAL: &str = include_str!("kernels/softmax.metal");
+
+#[inline]
+fn g_softmax<const CAUSAL: bool>(
+    x: &mut Tensor,
+    past_sequence_length: usize,
+) -> Result<(), SmeltError> {
+    let dim = x.shape().len();
+
+    let m = x.shape()[dim - 2];
+    let n = x.shape()[dim - 1];
+    let past_sequence_length = if CAUSAL { past_sequence_length } else { n };
+
+    let numel: usize = x.shape()[..dim - 1].iter().product();
+    let dev = x.device().clone();
+    dev.launch(
+        SOFTMAX_METAL,
+        "softmax_f32",
+        &[
+            Arg::Buffer(x.data_mut()),
+            Arg::U32(numel as u32),
+            Arg::U32(m as u32),
+            Arg::U32(n as u32),
+            Arg::U32(past_sequence_length as u32),
+        ],
+        numel,
+    )
+}
+
+/// Softmax on the last dimension for tensor `x`
+pub fn softmax(x: &mut Tensor) -> Result<(), SmeltError> {
+    g_softmax::<false>(x, 0)
+}
+
+/// Causal softmax on the last dimension for tensor `x`. The causality is determined by the
+/// shape of `x` and `past_sequence_length` which defines how big is the missing part of the
+/// square.
+pub fn causal_softmax(x: &mut Tensor, past_sequence_length: usize) -> Result<(), SmeltError> {
+    g_softmax::<true>(x, past_sequence_length)
+}
+
+const UNITARY_METAL: &str = include_str!("kernels/unitary.metal");
+
+/// `tanh` operation
+pub fn tanh(x: &mut Tensor) -> Result<(), SmeltError> {
+    let numel: usize = x.shape().iter().product();
+    let dev = x.device().clone();
+    dev.launch(
+        UNITARY_METAL,
+        "tanh_f32",
+        &[Arg::Buffer(x.data_mut()), Arg::U32(numel as u32)],
+        numel,
+    )
+}
+
+/// `gelu` operation
+/// <https://en.wikipedia.org/wiki/Activation_function#Comparison_of_activation_functions>
+pub fn gelu(x: &mut Tensor) -> Result<(), SmeltError> {
+    let numel: usize = x.shape().iter().product();
+    let dev = x.device().clone();
+    dev.launch(
+        UNITARY_METAL,
+        "gelu_f32",
+        &[Arg::Buffer(x.data_mut()), Arg::U32(numel as u32)],
+        numel,
+    )
+}
+
+/// `activation` of every item of the tensor, see [Activation].
+pub fn activation(x: &mut Tensor, activation: Activation) -> Result<(), SmeltError> {
+    let name = match activation {
+        Activation::Gelu => "gelu_erf_f32",
+        Activation::GeluNew | Activation::GeluFast => "gelu_f32",
+        Activation::Relu => "relu_f32",
+        Activation::Silu => "silu_f32",
+        Activation::QuickGelu => "quick_gelu_f32",
+    };
+    let numel: usize = x.shape().iter().product();
+    let dev = x.device().clone();
+    dev.launch(
+        UNITARY_METAL,
+        name,
+        &[Arg::Buffer(x.data_mut()), Arg::U32(numel as u32)],
+        numel,
+    )
+}
+
+/// Multiplies every item of the tensor by `factor`
+pub fn mul_scalar(x: &mut Tensor, factor: f32) -> Result<(), SmeltError> {
+    let numel: usize = x.shape().iter().product();
+    let dev = x.device().clone();
+    dev.launch(
+        UNITARY_METAL,
+        "mul_scalar_f32",
+        &[
+            Arg::Buffer(x.data_mut()),
+            Arg::U32(numel as u32),
+            Arg::F32(factor),
+        ],
+        numel,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::metal::f32::Device;
+    use crate::tests::simplify;
+
+    fn device() -> Device {
+        Device::new(0).unwrap()
+    }
+
+    #[test]
+    fn simple_matmul() {
+        let device = device();
+        let data = vec![1.0, 2.0, 3.0, 4.0];
+        let a = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        let b = Tensor::from_cpu(&data, vec![2, 2], &device).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2], &device).unwrap();
+
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), &[7.0, 10.0, 15.0, 22.0]);
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(c.cpu_data().unwrap(), &[7.0, 10.0, 15.0, 22.0]);
+
+        let data: Vec<_> = (0..12).map(|i| i as f32).collect();
+        let a = Tensor::from_cpu(&data, vec![2, 2, 3], &device).unwrap();
+        let data: Vec<_> = (0..24).map(|i| (i + 2) as f32).collect();
+        let b = Tensor::from_cpu(&data, vec![2, 3, 4], &device).unwrap();
+        let mut c: Tensor = Tensor::zeros(vec![2, 2, 4], &device).unwrap();
+        matmul(&a, &b, &mut c).unwrap();
+        assert_eq!(
+            c.cpu_data().unwrap(),
+            &[
+                26., 29., 32., 35., 80., 92., 104., 116., 386., 407., 428., 449., 548., 578., 608.,
+                638.
+            ]
+        );
+    }
+
+    #[test]
+    fn simple_matmul_t() {
+        let device = device();
+        let data: Vec<_> = (0..12).map(|i| i as f32).collect();
+        let a = Tensor::from_cpu(&data, vec![2, 2, 3], &device).unwrap();
+        let data: Vec<_> = (0..24).map(|i| (i + 2) as f32).collect();
+        let b = Tensor::from_cpu(&data, vec![2, 4, 3], &device).unwrap();
+        let mut c = Tensor::zeros(vec![2, 2, 4], &device).unwrap();
+        matmul_t(&a, &b, &mut c).unwrap();
+        assert_eq!(
+            c.cpu_data().unwrap(),
+            &[
+                11., 20., 29., 38., 38., 74., 110., 146., 317., 380., 443., 506., 452., 542., 632.,
+                722.
+            ]
+        );
+    }
+
+    #[test]
+    fn simple_broadcast_add() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0], vec![2], &device).unwrap();
+        let mut b = Tensor::from_cpu(&[1.0; 6], vec![3, 2], &device).unwrap();
+        broadcast_add(&a, &mut b).unwrap();
+        assert_eq!(b.cpu_data().unwrap(), [2.0, 3.0, 2.0, 3.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn simple_causal_softmax() {
+        let device = device();
+        let data: Vec<_> = (0..12).map(|i| (i + 1) as f32).collect();
+        let mut a = Tensor::from_cpu(&data, vec![2, 2, 3], &device).unwrap();
+        causal_softmax(&mut a, 1).unwrap();
+        assert_eq!(
+            simplify(&a.cpu_data().unwrap()),
+            // Values obtained through python
+            [
+                0.2689, 0.7311, 0.0, 0.09, 0.2447, 0.6652, 0.2689, 0.7311, 0.0, 0.09, 0.2447,
+                0.6652
+            ]
+        );
+    }
+
+    #[test]
+    fn simple_select() {
+        let device = device();
+        let a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        let mut tensor = Tensor::zeros(vec![3, 2], &device).unwrap();
+        select(&[1, 0, 0], &a, &mut tensor).unwrap();
+        assert_eq!(tensor.cpu_data().unwrap(), [3.0, 4.0, 1.0, 2.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn simple_normalize() {
+        let device = device();
+        let mut a = Tensor::from_cpu(&[1.0, 2.0, 3.0, 4.0], vec![2, 2], &device).unwrap();
+        normalize(&mut a, 1e-5).unwrap();
+        assert_eq!(simplify(&a.cpu_data().unwrap()), [-1.0, 1.0, -1.0, 1.0]);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::gpu::metal::f32::MetalError;
+use crate::SmeltError;
+use metal::{
+    Buffer, BufferRef, CommandQueue, CompileOptions, ComputePipelineState, MTLResourceOptions,
+    MTLSize,
+};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+
+/// Tensor, owns a shared (cpu visible) metal buffer.
+pub struct Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: Buffer,
+}
+
+/// The Metal device, contains its id, the metal handle, a command queue
+/// and the cache of already compiled kernels.
+#[derive(Clone)]
+pub struct Device {
+    device: metal::Device,
+    device_id: usize,
+    queue: CommandQueue,
+    pipelines: Arc<Mutex<HashMap<&'static str, ComputePipelineState>>>,
+}
+
+/// An argument sent to a metal kernel, in the order of the `[[buffer(i)]]` indices.
+pub enum Arg<'a> {
+    /// A device buffer
+    Buffer(&'a BufferRef),
+    /// An unsigned integer, read as `constant uint&`
+    U32(u32),
+    /// A float, read as `constant float&`
+    F32(f32),
+}
+
+fn buffer(device: &metal::Device, nelement: usize) -> Buffer {
+    // Metal refuses to allocate empty buffers, but empty tensors are legit
+    // (for instance an empty past for gpt2).
+    let size = (nelement.max(1) * std::mem::size_of::<f32>()) as u64;
+    device.new_buffer(size, MTLResourceOptions::StorageModeShared)
+}
+
+impl Device {
+    /// Creates a new device, `device_id` is the index within all the metal devices of this
+    /// machine.
+    pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        let device = metal::Device::all()
+            .into_iter()
+            .nth(device_id)
+            .ok_or(SmeltError::Metal(MetalError::DeviceNotFound(device_id)))?;
+        let queue = device.new_command_queue();
+        Ok(Self {
+            device,
+            device_id,
+            queue,
+            pipelines: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device_id
+    }
+
+    /// The underlying metal device
+    pub fn metal(&self) -> &metal::Device {
+        &self.device
+    }
+
+    fn pipeline(
+        &self,
+        source: &str,
+        name: &'static str,
+    ) -> Result<ComputePipelineState, SmeltError> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(name) {
+            return Ok(pipeline.clone());
+        }
+        let compile = |e: String| SmeltError::Metal(MetalError::Compilation(e));
+        let library = self
+            .device
+            .new_library_with_source(source, &CompileOptions::new())
+            .map_err(compile)?;
+        let function = library.get_function(name, None).map_err(compile)?;
+        let pipeline = self
+            .device
+            .new_compute_pipeline_state_with_function(&function)
+            .map_err(compile)?;
+        pipelines.insert(name, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Runs the kernel `name` found in the metal `source` over `numel` threads, and waits
+    /// for its completion. The kernel is compiled once and cached on the device.
+    pub fn launch(
+        &self,
+        source: &str,
+        name: &'static str,
+        args: &[Arg],
+        numel: usize,
+    ) -> Result<(), SmeltError> {
+        if numel == 0 {
+            return Ok(());
+        }
+        let pipeline = self.pipeline(source, name)?;
+        let command_buffer = self.queue.new_command_buffer();
+        let encoder = command_buffer.new_compute_command_encoder();
+        encoder.set_compute_pipeline_state(&pipeline);
+        for (i, arg) in args.iter().enumerate() {
+            let i = i as u64;
+            match arg {
+                Arg::Buffer(buffer) => encoder.set_buffer(i, Some(*buffer), 0),
+                Arg::U32(v) => encoder.set_bytes(
+                    i,
+                    std::mem::size_of::<u32>() as u64,
+                    v as *const u32 as *const c_void,
+                ),
+                Arg::F32(v) => encoder.set_bytes(
+                    i,
+                    std::mem::size_of::<f32>() as u64,
+                    v as *const f32 as *const c_void,
+                ),
+            }
+        }
+        let width = pipeline
+            .max_total_threads_per_threadgroup()
+            .min(numel as u64);
+        encoder.dispatch_threads(MTLSize::new(numel as u64, 1, 1), MTLSize::new(width, 1, 1));
+        encoder.end_encoding();
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
+        Ok(())
+    }
+
+    /// Copies `size` floats from `src[src_offset..]` into `dst[dst_offset..]`, offsets
+    /// are in number of floats.
+    pub fn copy(
+        &self,
+        src: &BufferRef,
+        src_offset: usize,
+        dst: &BufferRef,
+        dst_offset: usize,
+        size: usize,
+    ) {
+        if size == 0 {
+            return;
+        }
+        let f = std::mem::size_of::<f32>() as u64;
+        let command_buffer = self.queue.new_command_buffer();
+        let encoder = command_buffer.new_blit_command_encoder();
+        encoder.copy_from_buffer(
+            src,
+            src_offset as u64 * f,
+            dst,
+            dst_offset as u64 * f,
+            size as u64 * f,
+        );
+        encoder.end_encoding();
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
+    }
+}
+
+impl Clone for Tensor {
+    fn clone(&self) -> Self {
+        let numel = self.shape.iter().product();
+        let data = buffer(&self.device.device, numel);
+        self.device.copy(&self.data, 0, &data, 0, numel);
+        Self {
+            shape: self.shape.clone(),
+            device: self.device.clone(),
+            data,
+        }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    /// ```no_run
+    /// use smelte_rs::gpu::metal::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [Buffer] holding the data
+    pub fn data(&self) -> &Buffer {
+        &self.data
+    }
+
+    /// A mutable borrow of the [Buffer] holding the data
+    pub fn data_mut(&mut self) -> &mut Buffer {
+        &mut self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```no_run
+    /// use smelte_rs::gpu::metal::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = buffer(&device.device, nelement);
+        // SAFETY: The buffer is cpu visible (shared storage) and holds at least
+        // `nelement` floats.
+        unsafe {
+            std::ptr::write_bytes(data.contents() as *mut f32, 0, nelement);
+        }
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a tensor from a cpu [Vec].
+    pub fn from_cpu(data: &[f32], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let buffer = buffer(&device.device, data.len());
+        // SAFETY: The buffer is cpu visible (shared storage) and holds at least
+        // `data.len()` floats.
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), buffer.contents() as *mut f32, data.len());
+        }
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data: buffer,
+        })
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        let numel: usize = self.shape.iter().product();
+        // SAFETY: The buffer is cpu visible (shared storage), every command
+        // is waited upon, so no kernel is writing into it.
+        let data = unsafe { std::slice::from_raw_parts(self.data.contents() as *const f32, numel) };
+        Ok(data.to_vec())
+    }
+}
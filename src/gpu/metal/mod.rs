@@ -0,0 +1,2 @@
+/// F32 tensor precision.
+pub mod f32;
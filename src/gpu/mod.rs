@@ -1,2 +1,27 @@
 /// F32 tensor precision.
+#[cfg(feature = "cuda")]
 pub mod f32;
+
+/// F16 tensor precision, the matmuls run on tensor cores and accumulate in f32.
+#[cfg(all(feature = "cuda", feature = "f16"))]
+pub mod f16;
+
+/// BF16 tensor precision, the matmuls run natively on the tensor cores of Ampere and newer.
+#[cfg(all(feature = "cuda", feature = "bf16"))]
+pub mod bf16;
+
+/// The Metal backend (Apple Silicon GPUs).
+#[cfg(feature = "metal")]
+pub mod metal;
+
+/// The wgpu backend (WebGPU, Vulkan, Metal, DX12).
+#[cfg(feature = "wgpu")]
+pub mod wgpu;
+
+/// The OpenCL backend (Intel iGPUs, older AMD cards...).
+#[cfg(feature = "opencl")]
+pub mod opencl;
+
+/// The Vulkan backend (Linux and Windows GPUs, Android).
+#[cfg(feature = "vulkan")]
+pub mod vulkan;
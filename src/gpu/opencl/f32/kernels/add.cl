@@ -0,0 +1,35 @@
+#define OP(FORWARD, FUNC) \
+__kernel void FORWARD( \
+    __global const float *lhs, \
+    __global float *rhs, \
+    const uint numel \
+) { \
+    const uint i = get_global_id(0); \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float x = lhs[i]; \
+    const float y = rhs[i]; \
+    rhs[i] = (FUNC); \
+}
+
+#define BROADCAST_OP(FORWARD, FUNC) \
+__kernel void FORWARD( \
+    __global const float *lhs, \
+    __global float *rhs, \
+    const uint numel, \
+    const uint skip \
+) { \
+    const uint i = get_global_id(0); \
+    if (i >= numel) { \
+        return; \
+    } \
+    const float x = lhs[i % skip]; \
+    const float y = rhs[i]; \
+    rhs[i] = (FUNC); \
+}
+
+OP(add_fwd_f32, x + y)
+OP(mul_fwd_f32, x * y)
+BROADCAST_OP(badd_fwd_f32, x + y)
+BROADCAST_OP(bmul_fwd_f32, x * y)
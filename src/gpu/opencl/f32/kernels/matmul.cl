@@ -0,0 +1,35 @@
+// One work item per output element, `b` is read as (k, n) or as (n, k) when
+// `b_transposed` is set.
+__kernel void matmul_f32(
+    __global const float *a,
+    __global const float *b,
+    __global float *c,
+    const uint numel,
+    const uint m,
+    const uint n,
+    const uint k,
+    const uint b_transposed
+) {
+    const uint idx = get_global_id(0);
+    if (idx >= numel) {
+        return;
+    }
+    const uint batch = idx / (m * n);
+    const uint row = (idx / n) % m;
+    const uint col = idx % n;
+
+    __global const float *ap = a + batch * m * k + row * k;
+    __global const float *bp = b + batch * n * k;
+
+    float sum = 0.0f;
+    if (b_transposed) {
+        for (uint l = 0; l < k; l++) {
+            sum += ap[l] * bp[col * k + l];
+        }
+    } else {
+        for (uint l = 0; l < k; l++) {
+            sum += ap[l] * bp[l * n + col];
+        }
+    }
+    c[idx] = sum;
+}
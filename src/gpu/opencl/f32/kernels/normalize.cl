@@ -0,0 +1,31 @@
+__kernel void normalize_f32(
+    __global float *x,
+    const uint numel,
+    const uint size,
+    const float epsilon
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    __global float *row = x + i * size;
+
+    float sum = 0.0f;
+    for (uint j = 0; j < size; j++) {
+        sum += row[j];
+    }
+    const float mean = sum / size;
+    for (uint j = 0; j < size; j++) {
+        row[j] -= mean;
+    }
+
+    float var = 0.0f;
+    for (uint j = 0; j < size; j++) {
+        var += row[j] * row[j];
+    }
+    var /= size;
+    const float stddev = sqrt(var + epsilon);
+    for (uint j = 0; j < size; j++) {
+        row[j] /= stddev;
+    }
+}
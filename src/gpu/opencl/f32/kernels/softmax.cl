@@ -0,0 +1,38 @@
+__kernel void softmax_f32(
+    __global float *x,
+    const uint numel,
+    const uint m,
+    const uint size,
+    const uint past_sequence_length
+) {
+    const uint idx = get_global_id(0);
+    if (idx >= numel) {
+        return;
+    }
+    __global float *row = x + idx * size;
+    const uint i = idx % m;
+
+    float current_max = -INFINITY;
+    for (uint j = 0; j < size; j++) {
+        if (row[j] > current_max && i + past_sequence_length >= j) {
+            current_max = row[j];
+        }
+    }
+    for (uint j = 0; j < size; j++) {
+        row[j] = exp(row[j] - current_max);
+    }
+
+    float sum = 0.0f;
+    for (uint j = 0; j < size; j++) {
+        if (i + past_sequence_length >= j) {
+            sum += row[j];
+        }
+    }
+    for (uint j = 0; j < size; j++) {
+        if (i + past_sequence_length >= j) {
+            row[j] /= sum;
+        } else {
+            row[j] = 0.0f;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+__kernel void tanh_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    x[i] = tanh(x[i]);
+}
+
+__kernel void gelu_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    // sqrt(2 / pi)
+    const float alpha = 0.7978845608f * (v + 0.044715f * v * v * v);
+    x[i] = 0.5f * v * (1.0f + tanh(alpha));
+}
+
+__kernel void gelu_erf_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = 0.5f * v * (1.0f + erf(v * M_SQRT1_2_F));
+}
+
+__kernel void relu_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = fmax(v, 0.0f);
+}
+
+__kernel void silu_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = v / (1.0f + exp(-v));
+}
+
+__kernel void quick_gelu_f32(
+    __global float *x,
+    const uint numel
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    const float v = x[i];
+    x[i] = v / (1.0f + exp(-1.702f * v));
+}
+
+__kernel void mul_scalar_f32(
+    __global float *x,
+    const uint numel,
+    const float factor
+) {
+    const uint i = get_global_id(0);
+    if (i >= numel) {
+        return;
+    }
+    x[i] *= factor;
+}
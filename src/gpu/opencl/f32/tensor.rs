@@ -0,0 +1,248 @@
+use crate::gpu::opencl::f32::OpenClError;
+use crate::SmeltError;
+use ocl::{Buffer, Context, Kernel, Platform, Program, Queue};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Tensor, owns a buffer on the OpenCL device.
+pub struct Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: Buffer<f32>,
+}
+
+/// The OpenCL device, contains its id, the context and queue handles
+/// and the cache of already compiled programs.
+#[derive(Clone)]
+pub struct Device {
+    device: ocl::Device,
+    device_id: usize,
+    context: Context,
+    queue: Queue,
+    programs: Arc<Mutex<HashMap<&'static str, Program>>>,
+}
+
+/// An argument sent to an OpenCL kernel, in the order of the kernel parameters.
+pub enum Arg<'a> {
+    /// A device buffer
+    Buffer(&'a Buffer<f32>),
+    /// An unsigned integer, read as `const uint`
+    U32(u32),
+    /// A float, read as `const float`
+    F32(f32),
+}
+
+fn buffer(queue: &Queue, nelement: usize) -> Result<Buffer<f32>, SmeltError> {
+    // Empty buffers are invalid, but empty tensors are legit
+    // (for instance an empty past for gpt2).
+    let buffer = Buffer::builder()
+        .queue(queue.clone())
+        .len(nelement.max(1))
+        .fill_val(0.0)
+        .build()?;
+    Ok(buffer)
+}
+
+impl Device {
+    /// Creates a new device, `device_id` is the index within all the OpenCL devices
+    /// (of every platform) available on this machine.
+    pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        let (platform, device) = Platform::list()
+            .into_iter()
+            .flat_map(|platform| {
+                ocl::Device::list_all(platform)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(move |device| (platform, device))
+            })
+            .nth(device_id)
+            .ok_or(SmeltError::OpenCl(OpenClError::DeviceNotFound(device_id)))?;
+        let context = Context::builder()
+            .platform(platform)
+            .devices(device)
+            .build()?;
+        let queue = Queue::new(&context, device, None)?;
+        Ok(Self {
+            device,
+            device_id,
+            context,
+            queue,
+            programs: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device_id
+    }
+
+    /// The underlying OpenCL queue
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    fn program(&self, source: &'static str) -> Result<Program, SmeltError> {
+        let mut programs = self.programs.lock().unwrap();
+        if let Some(program) = programs.get(source) {
+            return Ok(program.clone());
+        }
+        let program = Program::builder()
+            .src(source)
+            .devices(self.device)
+            .build(&self.context)
+            .map_err(|e| SmeltError::OpenCl(OpenClError::Compilation(e.to_string())))?;
+        programs.insert(source, program.clone());
+        Ok(program)
+    }
+
+    /// Runs the kernel `name` found in the OpenCL `source` over `numel` work items, and
+    /// waits for its completion. The program is compiled once and cached on the device.
+    pub fn launch(
+        &self,
+        source: &'static str,
+        name: &'static str,
+        args: &[Arg],
+        numel: usize,
+    ) -> Result<(), SmeltError> {
+        if numel == 0 {
+            return Ok(());
+        }
+        let program = self.program(source)?;
+        let mut builder = Kernel::builder();
+        builder
+            .program(&program)
+            .name(name)
+            .queue(self.queue.clone())
+            .global_work_size(numel);
+        for arg in args {
+            match arg {
+                Arg::Buffer(buffer) => builder.arg(*buffer),
+                Arg::U32(v) => builder.arg(*v),
+                Arg::F32(v) => builder.arg(*v),
+            };
+        }
+        let kernel = builder.build()?;
+        // SAFETY: Every kernel checks its bounds against `numel`.
+        unsafe {
+            kernel.enq()?;
+        }
+        self.queue.finish()?;
+        Ok(())
+    }
+
+    /// Copies `size` floats from `src[src_offset..]` into `dst[dst_offset..]`, offsets
+    /// are in number of floats.
+    pub fn copy(
+        &self,
+        src: &Buffer<f32>,
+        src_offset: usize,
+        dst: &Buffer<f32>,
+        dst_offset: usize,
+        size: usize,
+    ) -> Result<(), SmeltError> {
+        if size == 0 {
+            return Ok(());
+        }
+        src.cmd()
+            .queue(&self.queue)
+            .offset(src_offset)
+            .copy(dst, Some(dst_offset), Some(size))
+            .enq()?;
+        self.queue.finish()?;
+        Ok(())
+    }
+}
+
+impl Clone for Tensor {
+    fn clone(&self) -> Self {
+        let numel = self.shape.iter().product();
+        // Cloning cannot fail, the device is expected to be valid.
+        let data = buffer(&self.device.queue, numel).unwrap();
+        self.device.copy(&self.data, 0, &data, 0, numel).unwrap();
+        Self {
+            shape: self.shape.clone(),
+            device: self.device.clone(),
+            data,
+        }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    /// ```no_run
+    /// use smelte_rs::gpu::opencl::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [Buffer] holding the data
+    pub fn data(&self) -> &Buffer<f32> {
+        &self.data
+    }
+
+    /// A mutable borrow of the [Buffer] holding the data
+    pub fn data_mut(&mut self) -> &mut Buffer<f32> {
+        &mut self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```no_run
+    /// use smelte_rs::gpu::opencl::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = buffer(&device.queue, nelement)?;
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a tensor from a cpu [Vec].
+    pub fn from_cpu(data: &[f32], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let buffer = buffer(&device.queue, data.len())?;
+        if !data.is_empty() {
+            buffer.write(data).enq()?;
+        }
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data: buffer,
+        })
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        let numel: usize = self.shape.iter().product();
+        let mut data = vec![0.0; numel];
+        if numel > 0 {
+            self.data.read(&mut data).enq()?;
+        }
+        Ok(data)
+    }
+}
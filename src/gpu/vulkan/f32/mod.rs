@@ -0,0 +1,10 @@
+/// The various ops
+mod ops;
+/// The Tensor struct
+mod tensor;
+
+/// The Tensor trait implementations
+mod traits;
+
+pub use ops::*;
+pub use tensor::{Arg, Buffer, Device, Tensor};
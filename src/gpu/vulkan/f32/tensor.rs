@@ -0,0 +1,679 @@
+use crate::gpu::vulkan::f32::VulkanError;
+use crate::SmeltError;
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The size in bytes of the push constants, every kernel parameter takes 4 bytes.
+/// 128 is the minimum every Vulkan implementation supports.
+const PUSH_CONSTANTS_SIZE: usize = 128;
+/// Must match `local_size_x` of the shaders.
+const WORKGROUP_SIZE: usize = 64;
+/// The minimum `maxComputeWorkGroupCount` every Vulkan implementation supports.
+const MAX_WORKGROUPS: usize = 65535;
+
+/// Tensor, owns a buffer on the Vulkan device.
+pub struct Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: Buffer,
+}
+
+/// A compute pipeline built from a SPIR-V module.
+struct Pipeline {
+    module: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+/// Everything created from the instance, destroyed when the last [Device] clone or
+/// [Buffer] goes away.
+struct Context {
+    // Keeps the Vulkan library loaded.
+    _entry: ash::Entry,
+    instance: ash::Instance,
+    device: ash::Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue: vk::Queue,
+    // Submitting to the queue and recording from the pool both need external
+    // synchronization, so the pool lock guards both.
+    commands: Mutex<vk::CommandPool>,
+    pipelines: Mutex<HashMap<&'static str, Pipeline>>,
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // SAFETY: Nothing uses the device anymore once the last reference is gone, and
+        // every object is destroyed before its parent.
+        unsafe {
+            self.device.device_wait_idle().ok();
+            for (_, pipeline) in self.pipelines.get_mut().unwrap().drain() {
+                self.device.destroy_pipeline(pipeline.pipeline, None);
+                self.device.destroy_pipeline_layout(pipeline.layout, None);
+                self.device
+                    .destroy_descriptor_set_layout(pipeline.set_layout, None);
+                self.device.destroy_shader_module(pipeline.module, None);
+            }
+            self.device
+                .destroy_command_pool(*self.commands.get_mut().unwrap(), None);
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// The Vulkan device, contains its id, the compute queue and the cache of already built
+/// pipelines.
+#[derive(Clone)]
+pub struct Device {
+    device_id: usize,
+    context: Arc<Context>,
+}
+
+/// A buffer of floats in the device memory.
+pub struct Buffer {
+    context: Arc<Context>,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // SAFETY: Every submission waits for its completion, so the buffer is not in use.
+        unsafe {
+            self.context.device.destroy_buffer(self.buffer, None);
+            self.context.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// An argument sent to a Vulkan kernel. Buffers are bound in order (`binding = 0`,
+/// `binding = 1`...), the other arguments fill the push constants in order.
+pub enum Arg<'a> {
+    /// A device buffer
+    Buffer(&'a Buffer),
+    /// An unsigned integer, read as `uint`
+    U32(u32),
+    /// A float, read as `float`
+    F32(f32),
+}
+
+impl Context {
+    fn memory_type(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<u32, SmeltError> {
+        let properties = &self.memory_properties;
+        (0..properties.memory_type_count)
+            .find(|&i| {
+                requirements.memory_type_bits & (1 << i) != 0
+                    && properties.memory_types[i as usize]
+                        .property_flags
+                        .contains(flags)
+            })
+            .ok_or(SmeltError::Vulkan(VulkanError::NoMemoryType))
+    }
+
+    fn buffer(
+        self: &Arc<Self>,
+        nelement: usize,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Result<Buffer, SmeltError> {
+        // Empty buffers are invalid, but empty tensors are legit
+        // (for instance an empty past for gpt2).
+        let size = (nelement.max(1) * std::mem::size_of::<f32>()) as vk::DeviceSize;
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(
+                vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_SRC
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        // SAFETY: The create infos are valid, the memory is bound right away.
+        unsafe {
+            let buffer = self.device.create_buffer(&info, None)?;
+            let requirements = self.device.get_buffer_memory_requirements(buffer);
+            let memory_type = match self.memory_type(&requirements, flags) {
+                Ok(memory_type) => memory_type,
+                Err(err) => {
+                    self.device.destroy_buffer(buffer, None);
+                    return Err(err);
+                }
+            };
+            let alloc = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type);
+            let memory = match self.device.allocate_memory(&alloc, None) {
+                Ok(memory) => memory,
+                Err(err) => {
+                    self.device.destroy_buffer(buffer, None);
+                    return Err(err.into());
+                }
+            };
+            let buffer = Buffer {
+                context: self.clone(),
+                buffer,
+                memory,
+            };
+            self.device
+                .bind_buffer_memory(buffer.buffer, buffer.memory, 0)?;
+            Ok(buffer)
+        }
+    }
+
+    /// Makes all the previous writes visible to the `dst_access` of the `dst_stage`.
+    fn barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let barriers = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(dst_access)
+            .build()];
+        // SAFETY: The command buffer is recording.
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &barriers,
+                &[],
+                &[],
+            )
+        };
+    }
+
+    /// Records commands with `record` and runs them, waiting for their completion.
+    fn submit(
+        &self,
+        record: impl FnOnce(&ash::Device, vk::CommandBuffer),
+    ) -> Result<(), SmeltError> {
+        let pool = self.commands.lock().unwrap();
+        let alloc = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(*pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        // SAFETY: The pool and the queue are locked, the command buffer and the fence are
+        // only freed once the queue is done with them.
+        unsafe {
+            let command_buffer = self.device.allocate_command_buffers(&alloc)?[0];
+            let begin = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            let result = (|| {
+                self.device.begin_command_buffer(command_buffer, &begin)?;
+                // Every submission sees the writes of the previous ones, and the host
+                // sees its writes once the fence is signaled.
+                self.barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                );
+                record(&self.device, command_buffer);
+                self.barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::HOST,
+                    vk::AccessFlags::HOST_READ,
+                );
+                self.device.end_command_buffer(command_buffer)?;
+
+                let fence = self
+                    .device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)?;
+                let command_buffers = [command_buffer];
+                let submit = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+                let result = self
+                    .device
+                    .queue_submit(self.queue, &[submit.build()], fence)
+                    .and_then(|_| self.device.wait_for_fences(&[fence], true, u64::MAX));
+                self.device.destroy_fence(fence, None);
+                result
+            })();
+            self.device.free_command_buffers(*pool, &[command_buffer]);
+            result?;
+        }
+        Ok(())
+    }
+
+    fn pipeline(
+        &self,
+        spirv: &'static [u8],
+        name: &'static str,
+        num_buffers: usize,
+    ) -> Result<(vk::PipelineLayout, vk::Pipeline, vk::DescriptorSetLayout), SmeltError> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(p) = pipelines.get(name) {
+            return Ok((p.layout, p.pipeline, p.set_layout));
+        }
+        let code = ash::util::read_spv(&mut std::io::Cursor::new(spirv))
+            .map_err(|e| SmeltError::Vulkan(VulkanError::InvalidShader(e.to_string())))?;
+        let bindings: Vec<_> = (0..num_buffers as u32)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect();
+        let push_constants = [vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(PUSH_CONSTANTS_SIZE as u32)
+            .build()];
+        // Every shader has a single `main` entry point.
+        let entry_point = c"main";
+
+        // SAFETY: The create infos are valid, the objects are kept in the cache and
+        // destroyed with the context.
+        unsafe {
+            let module_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+            let module = self.device.create_shader_module(&module_info, None)?;
+            let set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            let set_layout = self
+                .device
+                .create_descriptor_set_layout(&set_layout_info, None)?;
+            let set_layouts = [set_layout];
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constants);
+            let layout = self.device.create_pipeline_layout(&layout_info, None)?;
+            let stage = vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(module)
+                .name(entry_point);
+            let info = vk::ComputePipelineCreateInfo::builder()
+                .stage(stage.build())
+                .layout(layout);
+            let pipeline = self
+                .device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[info.build()], None)
+                .map_err(|(_, err)| err)?[0];
+            pipelines.insert(
+                name,
+                Pipeline {
+                    module,
+                    set_layout,
+                    layout,
+                    pipeline,
+                },
+            );
+            Ok((layout, pipeline, set_layout))
+        }
+    }
+}
+
+impl Device {
+    /// Creates a new device, `device_id` is the index within the physical devices
+    /// reported by the Vulkan loader.
+    pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        // SAFETY: Loading the Vulkan library runs its initialization code, which we trust.
+        let entry = unsafe { ash::Entry::load() }
+            .map_err(|e| SmeltError::Vulkan(VulkanError::Loading(e.to_string())))?;
+        let name = c"smelte-rs";
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(name)
+            .engine_name(name)
+            .api_version(vk::API_VERSION_1_1);
+        let instance_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
+        // SAFETY: The create infos are valid, every object created here is either owned
+        // by the context or destroyed on failure.
+        unsafe {
+            let instance = entry.create_instance(&instance_info, None)?;
+            let found = (|| {
+                let physical_device = *instance
+                    .enumerate_physical_devices()?
+                    .get(device_id)
+                    .ok_or(SmeltError::Vulkan(VulkanError::DeviceNotFound(device_id)))?;
+                let queue_family = instance
+                    .get_physical_device_queue_family_properties(physical_device)
+                    .iter()
+                    .position(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE))
+                    .ok_or(SmeltError::Vulkan(VulkanError::NoComputeQueue(device_id)))?
+                    as u32;
+                Ok::<_, SmeltError>((physical_device, queue_family))
+            })();
+            let (physical_device, queue_family) = match found {
+                Ok(found) => found,
+                Err(err) => {
+                    instance.destroy_instance(None);
+                    return Err(err);
+                }
+            };
+
+            let priorities = [1.0];
+            let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(queue_family)
+                .queue_priorities(&priorities)
+                .build()];
+            let device_info = vk::DeviceCreateInfo::builder().queue_create_infos(&queue_infos);
+            let device = match instance.create_device(physical_device, &device_info, None) {
+                Ok(device) => device,
+                Err(err) => {
+                    instance.destroy_instance(None);
+                    return Err(err.into());
+                }
+            };
+            let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+            let queue = device.get_device_queue(queue_family, 0);
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family)
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+            let commands = match device.create_command_pool(&pool_info, None) {
+                Ok(commands) => commands,
+                Err(err) => {
+                    device.destroy_device(None);
+                    instance.destroy_instance(None);
+                    return Err(err.into());
+                }
+            };
+            Ok(Self {
+                device_id,
+                context: Arc::new(Context {
+                    _entry: entry,
+                    instance,
+                    device,
+                    memory_properties,
+                    queue,
+                    commands: Mutex::new(commands),
+                    pipelines: Mutex::new(HashMap::new()),
+                }),
+            })
+        }
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device_id
+    }
+
+    /// Runs the `main` entry point of the SPIR-V module `spirv` over `numel` invocations,
+    /// and waits for its completion. The pipeline is built once and cached on the device
+    /// under `name`.
+    pub fn launch(
+        &self,
+        spirv: &'static [u8],
+        name: &'static str,
+        args: &[Arg],
+        numel: usize,
+    ) -> Result<(), SmeltError> {
+        if numel == 0 {
+            return Ok(());
+        }
+        let mut buffers = vec![];
+        let mut push_constants = vec![];
+        for arg in args {
+            match arg {
+                Arg::Buffer(buffer) => buffers.push(vk::DescriptorBufferInfo {
+                    buffer: buffer.buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                }),
+                Arg::U32(v) => push_constants.extend(v.to_ne_bytes()),
+                Arg::F32(v) => push_constants.extend(v.to_ne_bytes()),
+            }
+        }
+        if push_constants.len() > PUSH_CONSTANTS_SIZE {
+            return Err(SmeltError::Vulkan(VulkanError::TooManyArguments(name)));
+        }
+        push_constants.resize(PUSH_CONSTANTS_SIZE, 0);
+
+        let context = &self.context;
+        let (layout, pipeline, set_layout) = context.pipeline(spirv, name, buffers.len())?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: buffers.len() as u32,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        // A 2d grid, since a single dimension is only guaranteed to hold 65535 workgroups.
+        let workgroups = numel.div_ceil(WORKGROUP_SIZE);
+        let x = workgroups.min(MAX_WORKGROUPS);
+        let y = workgroups.div_ceil(x);
+
+        // TODO Keep the descriptor sets around instead of rebuilding them every launch.
+        // SAFETY: The descriptor set matches the layout of the pipeline, and lives until
+        // the submission is done. Every shader checks its bounds against `numel`.
+        unsafe {
+            let descriptor_pool = context.device.create_descriptor_pool(&pool_info, None)?;
+            let result = (|| {
+                let set_layouts = [set_layout];
+                let alloc = vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&set_layouts);
+                let set = context.device.allocate_descriptor_sets(&alloc)?[0];
+                let writes: Vec<_> = buffers
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, info)| {
+                        vk::WriteDescriptorSet::builder()
+                            .dst_set(set)
+                            .dst_binding(binding as u32)
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .buffer_info(std::slice::from_ref(info))
+                            .build()
+                    })
+                    .collect();
+                context.device.update_descriptor_sets(&writes, &[]);
+
+                context.submit(|device, command_buffer| {
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        pipeline,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        layout,
+                        0,
+                        &[set],
+                        &[],
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        &push_constants,
+                    );
+                    device.cmd_dispatch(command_buffer, x as u32, y as u32, 1);
+                })
+            })();
+            context
+                .device
+                .destroy_descriptor_pool(descriptor_pool, None);
+            result
+        }
+    }
+
+    /// Copies `size` floats from `src[src_offset..]` into `dst[dst_offset..]`, offsets
+    /// are in number of floats.
+    pub fn copy(
+        &self,
+        src: &Buffer,
+        src_offset: usize,
+        dst: &Buffer,
+        dst_offset: usize,
+        size: usize,
+    ) -> Result<(), SmeltError> {
+        self.copy_regions(src, dst, &[(src_offset, dst_offset, size)])
+    }
+
+    /// Copies every `(src_offset, dst_offset, size)` region of `src` into `dst` in a
+    /// single submission, offsets and sizes are in number of floats.
+    pub fn copy_regions(
+        &self,
+        src: &Buffer,
+        dst: &Buffer,
+        regions: &[(usize, usize, usize)],
+    ) -> Result<(), SmeltError> {
+        let float = std::mem::size_of::<f32>() as vk::DeviceSize;
+        let regions: Vec<_> = regions
+            .iter()
+            .filter(|(_, _, size)| *size > 0)
+            .map(|&(src_offset, dst_offset, size)| vk::BufferCopy {
+                src_offset: src_offset as vk::DeviceSize * float,
+                dst_offset: dst_offset as vk::DeviceSize * float,
+                size: size as vk::DeviceSize * float,
+            })
+            .collect();
+        if regions.is_empty() {
+            return Ok(());
+        }
+        self.context.submit(|device, command_buffer| {
+            // SAFETY: The command buffer is recording, the regions are in bounds.
+            unsafe { device.cmd_copy_buffer(command_buffer, src.buffer, dst.buffer, &regions) };
+        })
+    }
+
+    fn buffer(&self, nelement: usize) -> Result<Buffer, SmeltError> {
+        let buffer = self
+            .context
+            .buffer(nelement, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        // Vulkan memory is not zeroed.
+        self.context.submit(|device, command_buffer| {
+            // SAFETY: The command buffer is recording, the buffer is alive.
+            unsafe { device.cmd_fill_buffer(command_buffer, buffer.buffer, 0, vk::WHOLE_SIZE, 0) };
+        })?;
+        Ok(buffer)
+    }
+
+    /// A host visible buffer, to move data in and out of the device.
+    fn staging(&self, nelement: usize) -> Result<Buffer, SmeltError> {
+        self.context.buffer(
+            nelement,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
+
+    /// Runs `f` on the host mapping of the `nelement` floats of the staging `buffer`.
+    fn map<T>(
+        &self,
+        buffer: &Buffer,
+        nelement: usize,
+        f: impl FnOnce(&mut [f32]) -> T,
+    ) -> Result<T, SmeltError> {
+        let device = &self.context.device;
+        // SAFETY: The buffer is host visible and coherent, holds at least `nelement`
+        // floats and is not in use by the device.
+        unsafe {
+            let ptr = device.map_memory(
+                buffer.memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            let data = std::slice::from_raw_parts_mut(ptr as *mut f32, nelement);
+            let result = f(data);
+            device.unmap_memory(buffer.memory);
+            Ok(result)
+        }
+    }
+}
+
+impl Clone for Tensor {
+    fn clone(&self) -> Self {
+        let numel = self.shape.iter().product();
+        // Cloning cannot fail, the device is expected to be valid.
+        let data = self.device.buffer(numel).unwrap();
+        self.device.copy(&self.data, 0, &data, 0, numel).unwrap();
+        Self {
+            shape: self.shape.clone(),
+            device: self.device.clone(),
+            data,
+        }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    /// ```no_run
+    /// use smelte_rs::gpu::vulkan::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [Buffer] holding the data
+    pub fn data(&self) -> &Buffer {
+        &self.data
+    }
+
+    /// A mutable borrow of the [Buffer] holding the data
+    pub fn data_mut(&mut self) -> &mut Buffer {
+        &mut self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```no_run
+    /// use smelte_rs::gpu::vulkan::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = device.buffer(nelement)?;
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a tensor from a cpu [Vec].
+    pub fn from_cpu(data: &[f32], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let buffer = device.buffer(data.len())?;
+        if !data.is_empty() {
+            // TODO Skip the staging buffer on unified memory (integrated and mobile GPUs).
+            let staging = device.staging(data.len())?;
+            device.map(&staging, data.len(), |mapped| mapped.copy_from_slice(data))?;
+            device.copy(&staging, 0, &buffer, 0, data.len())?;
+        }
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data: buffer,
+        })
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        let numel: usize = self.shape.iter().product();
+        if numel == 0 {
+            return Ok(vec![]);
+        }
+        let staging = self.device.staging(numel)?;
+        self.device.copy(&self.data, 0, &staging, 0, numel)?;
+        self.device.map(&staging, numel, |mapped| mapped.to_vec())
+    }
+}
@@ -0,0 +1,26 @@
+struct Params {
+    numel: u32,
+    skip: u32,
+}
+
+@group(0) @binding(0) var<storage, read> lhs: array<f32>;
+@group(0) @binding(1) var<storage, read_write> rhs: array<f32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn badd_fwd_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    rhs[i] = lhs[i % params.skip] + rhs[i];
+}
+
+@compute @workgroup_size(64)
+fn bmul_fwd_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    rhs[i] = lhs[i % params.skip] * rhs[i];
+}
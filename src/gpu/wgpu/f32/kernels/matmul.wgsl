@@ -0,0 +1,43 @@
+struct Params {
+    numel: u32,
+    m: u32,
+    n: u32,
+    k: u32,
+    b_transposed: u32,
+}
+
+@group(0) @binding(0) var<storage, read> a: array<f32>;
+@group(0) @binding(1) var<storage, read> b: array<f32>;
+@group(0) @binding(2) var<storage, read_write> c: array<f32>;
+@group(0) @binding(3) var<uniform> params: Params;
+
+// One invocation per output element, `b` is read as (k, n) or as (n, k) when
+// `b_transposed` is set.
+@compute @workgroup_size(64)
+fn matmul_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let idx = gid.x + gid.y * nwg.x * 64u;
+    if (idx >= params.numel) {
+        return;
+    }
+    let m = params.m;
+    let n = params.n;
+    let k = params.k;
+    let batch = idx / (m * n);
+    let row = (idx / n) % m;
+    let col = idx % n;
+
+    let ap = batch * m * k + row * k;
+    let bp = batch * n * k;
+
+    var sum = 0.0;
+    if (params.b_transposed != 0u) {
+        for (var l = 0u; l < k; l++) {
+            sum += a[ap + l] * b[bp + col * k + l];
+        }
+    } else {
+        for (var l = 0u; l < k; l++) {
+            sum += a[ap + l] * b[bp + l * n + col];
+        }
+    }
+    c[idx] = sum;
+}
@@ -0,0 +1,37 @@
+struct Params {
+    numel: u32,
+    size: u32,
+    epsilon: f32,
+}
+
+@group(0) @binding(0) var<storage, read_write> x: array<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn normalize_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let size = params.size;
+    let row = i * size;
+
+    var sum = 0.0;
+    for (var j = 0u; j < size; j++) {
+        sum += x[row + j];
+    }
+    let mean = sum / f32(size);
+    for (var j = 0u; j < size; j++) {
+        x[row + j] -= mean;
+    }
+
+    var variance = 0.0;
+    for (var j = 0u; j < size; j++) {
+        variance += x[row + j] * x[row + j];
+    }
+    variance /= f32(size);
+    let stddev = sqrt(variance + params.epsilon);
+    for (var j = 0u; j < size; j++) {
+        x[row + j] /= stddev;
+    }
+}
@@ -0,0 +1,46 @@
+struct Params {
+    numel: u32,
+    m: u32,
+    size: u32,
+    past_sequence_length: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> x: array<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn softmax_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let idx = gid.x + gid.y * nwg.x * 64u;
+    if (idx >= params.numel) {
+        return;
+    }
+    let size = params.size;
+    let row = idx * size;
+    let i = idx % params.m;
+    let past = params.past_sequence_length;
+
+    // WGSL has no infinity literal, this is -f32::MAX.
+    var current_max = -3.40282347e+38;
+    for (var j = 0u; j < size; j++) {
+        if (x[row + j] > current_max && i + past >= j) {
+            current_max = x[row + j];
+        }
+    }
+    for (var j = 0u; j < size; j++) {
+        x[row + j] = exp(x[row + j] - current_max);
+    }
+
+    var sum = 0.0;
+    for (var j = 0u; j < size; j++) {
+        if (i + past >= j) {
+            sum += x[row + j];
+        }
+    }
+    for (var j = 0u; j < size; j++) {
+        if (i + past >= j) {
+            x[row + j] /= sum;
+        } else {
+            x[row + j] = 0.0;
+        }
+    }
+}
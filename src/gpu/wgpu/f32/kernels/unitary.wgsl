@@ -0,0 +1,75 @@
+struct Params {
+    numel: u32,
+}
+
+@group(0) @binding(0) var<storage, read_write> x: array<f32>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn tanh_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    x[i] = tanh(x[i]);
+}
+
+@compute @workgroup_size(64)
+fn gelu_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let v = x[i];
+    // sqrt(2 / pi)
+    let alpha = 0.7978845608 * (v + 0.044715 * v * v * v);
+    x[i] = 0.5 * v * (1.0 + tanh(alpha));
+}
+
+// The error function, with the approximation 7.1.26 of Abramowitz and Stegun.
+fn erf_approx(v: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * abs(v));
+    let poly = t * (0.2548296 + t * (-0.28449672 + t * (1.4214138 + t * (-1.4531521 + t * 1.0614054))));
+    return sign(v) * (1.0 - poly * exp(-v * v));
+}
+
+@compute @workgroup_size(64)
+fn gelu_erf_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let v = x[i];
+    // 1 / sqrt(2)
+    x[i] = 0.5 * v * (1.0 + erf_approx(0.7071067812 * v));
+}
+
+@compute @workgroup_size(64)
+fn relu_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let v = x[i];
+    x[i] = max(v, 0.0);
+}
+
+@compute @workgroup_size(64)
+fn silu_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let v = x[i];
+    x[i] = v / (1.0 + exp(-v));
+}
+
+@compute @workgroup_size(64)
+fn quick_gelu_f32(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = gid.x + gid.y * nwg.x * 64u;
+    if (i >= params.numel) {
+        return;
+    }
+    let v = x[i];
+    x[i] = v / (1.0 + exp(-1.702 * v));
+}
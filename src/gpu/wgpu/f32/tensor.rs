@@ -0,0 +1,350 @@
+use crate::gpu::wgpu::f32::WgpuError;
+use crate::SmeltError;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, BufferUsages, ComputePipeline};
+
+/// The workgroup size used by every kernel of this module (`@workgroup_size(64)`).
+const WORKGROUP_SIZE: usize = 64;
+/// The maximum number of workgroups within a single dispatch dimension.
+const MAX_WORKGROUPS: usize = 65535;
+
+/// Tensor, owns a storage buffer on the wgpu device.
+pub struct Tensor {
+    shape: Vec<usize>,
+    device: Device,
+    data: Buffer,
+}
+
+/// The wgpu device, contains its id, the device and queue handles
+/// and the cache of already compiled kernels.
+#[derive(Clone)]
+pub struct Device {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    device_id: usize,
+    pipelines: Arc<Mutex<HashMap<&'static str, Arc<ComputePipeline>>>>,
+}
+
+/// An argument sent to a wgpu kernel. Buffers are bound in order starting at
+/// `@binding(0)`, scalars are packed in order within a uniform struct bound right after
+/// the last buffer.
+pub enum Arg<'a> {
+    /// A device buffer
+    Buffer(&'a Buffer),
+    /// An unsigned integer, read as `u32`
+    U32(u32),
+    /// A float, read as `f32`
+    F32(f32),
+}
+
+fn buffer(device: &wgpu::Device, nelement: usize) -> Buffer {
+    // Empty bindings are invalid, but empty tensors are legit
+    // (for instance an empty past for gpt2).
+    let size = (nelement.max(1) * std::mem::size_of::<f32>()) as u64;
+    // Buffers are zero initialized by wgpu.
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl Device {
+    /// Creates a new device, `device_id` is the index within all the adapters (Vulkan,
+    /// Metal, DX12...) available on this machine.
+    pub fn new(device_id: usize) -> Result<Self, SmeltError> {
+        Self::with_backends(device_id, wgpu::Backends::all())
+    }
+
+    /// Creates a new device, only looking at adapters from `backends`.
+    pub fn with_backends(device_id: usize, backends: wgpu::Backends) -> Result<Self, SmeltError> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        });
+        let adapter = instance
+            .enumerate_adapters(backends)
+            .nth(device_id)
+            .ok_or(SmeltError::Wgpu(WgpuError::DeviceNotFound(device_id)))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: adapter.limits(),
+            },
+            None,
+        ))
+        .map_err(|e| SmeltError::Wgpu(WgpuError::RequestDevice(e.to_string())))?;
+        Ok(Self {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            device_id,
+            pipelines: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device_id
+    }
+
+    /// The underlying wgpu device
+    pub fn wgpu(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    fn pipeline(&self, source: &str, name: &'static str) -> Arc<ComputePipeline> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(name) {
+            return pipeline.clone();
+        }
+        let module = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+            });
+        let pipeline = Arc::new(self.device.create_compute_pipeline(
+            &wgpu::ComputePipelineDescriptor {
+                label: Some(name),
+                layout: None,
+                module: &module,
+                entry_point: name,
+            },
+        ));
+        pipelines.insert(name, pipeline.clone());
+        pipeline
+    }
+
+    /// Runs the kernel `name` found in the wgsl `source` over `numel` invocations, and
+    /// waits for its completion. The kernel is compiled once and cached on the device.
+    pub fn launch(
+        &self,
+        source: &str,
+        name: &'static str,
+        args: &[Arg],
+        numel: usize,
+    ) -> Result<(), SmeltError> {
+        if numel == 0 {
+            return Ok(());
+        }
+        let pipeline = self.pipeline(source, name);
+
+        let mut buffers = vec![];
+        let mut params: Vec<u32> = vec![];
+        for arg in args {
+            match arg {
+                Arg::Buffer(buffer) => buffers.push(*buffer),
+                Arg::U32(v) => params.push(*v),
+                Arg::F32(v) => params.push(v.to_bits()),
+            }
+        }
+        // Uniform buffers sizes need to be multiples of 16 bytes.
+        params.resize(params.len().div_ceil(4) * 4, 0);
+        let params: Vec<u8> = params.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let uniform = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &params,
+                usage: BufferUsages::UNIFORM,
+            });
+
+        let mut entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        entries.push(wgpu::BindGroupEntry {
+            binding: buffers.len() as u32,
+            resource: uniform.as_entire_binding(),
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let workgroups = numel.div_ceil(WORKGROUP_SIZE);
+        let x = workgroups.min(MAX_WORKGROUPS);
+        let y = workgroups.div_ceil(x);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(x as u32, y as u32, 1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+        Ok(())
+    }
+
+    /// Copies `size` floats from `src[src_offset..]` into `dst[dst_offset..]`, offsets
+    /// are in number of floats.
+    pub fn copy(
+        &self,
+        src: &Buffer,
+        src_offset: usize,
+        dst: &Buffer,
+        dst_offset: usize,
+        size: usize,
+    ) {
+        self.copy_many(src, dst, &[(src_offset, dst_offset, size)]);
+    }
+
+    /// Same as [Device::copy] but for several `(src_offset, dst_offset, size)` regions,
+    /// all sent within a single submission.
+    pub fn copy_many(&self, src: &Buffer, dst: &Buffer, regions: &[(usize, usize, usize)]) {
+        let f = std::mem::size_of::<f32>() as u64;
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for (src_offset, dst_offset, size) in regions {
+            if *size == 0 {
+                continue;
+            }
+            encoder.copy_buffer_to_buffer(
+                src,
+                *src_offset as u64 * f,
+                dst,
+                *dst_offset as u64 * f,
+                *size as u64 * f,
+            );
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+}
+
+impl Clone for Tensor {
+    fn clone(&self) -> Self {
+        let numel = self.shape.iter().product();
+        let data = buffer(&self.device.device, numel);
+        self.device.copy(&self.data, 0, &data, 0, numel);
+        Self {
+            shape: self.shape.clone(),
+            device: self.device.clone(),
+            data,
+        }
+    }
+}
+
+impl Tensor {
+    /// The shape of the tensor
+    /// ```no_run
+    /// use smelte_rs::gpu::wgpu::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// assert_eq!(tensor.shape(), vec![2, 2]);
+    /// ```
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// The [Buffer] holding the data
+    pub fn data(&self) -> &Buffer {
+        &self.data
+    }
+
+    /// A mutable borrow of the [Buffer] holding the data
+    pub fn data_mut(&mut self) -> &mut Buffer {
+        &mut self.data
+    }
+
+    /// The device of the tensor
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The device id
+    pub fn device_id(&self) -> usize {
+        self.device.device_id
+    }
+
+    /// Creates a new nulled tensor with given shape
+    /// ```no_run
+    /// use smelte_rs::gpu::wgpu::f32::{Tensor, Device};
+    ///
+    /// let device = Device::new(0).unwrap();
+    /// let tensor = Tensor::zeros(vec![2, 2], &device).unwrap();
+    /// ```
+    pub fn zeros(shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        let nelement: usize = shape.iter().product();
+        let data = buffer(&device.device, nelement);
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data,
+        })
+    }
+
+    /// Creates a tensor from a cpu [Vec].
+    pub fn from_cpu(data: &[f32], shape: Vec<usize>, device: &Device) -> Result<Self, SmeltError> {
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        let buffer = buffer(&device.device, data.len());
+        if !data.is_empty() {
+            device
+                .queue
+                .write_buffer(&buffer, 0, bytemuck::cast_slice(data));
+        }
+        Ok(Self {
+            shape,
+            device: device.clone(),
+            data: buffer,
+        })
+    }
+
+    /// Returns a cpu vec containing copied data from the device.
+    pub fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+        let numel: usize = self.shape.iter().product();
+        if numel == 0 {
+            return Ok(vec![]);
+        }
+        let device = &self.device;
+        let size = (numel * std::mem::size_of::<f32>()) as u64;
+        let staging = device.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.data, 0, &staging, 0, size);
+        device.queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            // The receiver is waiting right below, it cannot be dropped.
+            sender.send(result).unwrap();
+        });
+        device.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(|e| SmeltError::Wgpu(WgpuError::BufferMap(e.to_string())))?
+            .map_err(|e| SmeltError::Wgpu(WgpuError::BufferMap(e.to_string())))?;
+
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+        Ok(data)
+    }
+}
@@ -20,8 +20,47 @@
 //! # Linux
 //! cargo run --example bert --release --features intel-mkl -- -p "This is a test" -n 3
 //!
+//! # Linux (OpenBLAS)
+//! cargo run --example bert --release --features openblas -- -p "This is a test" -n 3
+//!
 //! # M1
 //! cargo run --example bert --release -- -p "This is a test" -n 3
+//!
+//! # M1 (Accelerate)
+//! cargo run --example bert --release --features accelerate -- -p "This is a test" -n 3
+//!
+//! # M1 (GPU)
+//! cargo run --example bert --release --features metal -- -p "This is a test" -n 3
+//!
+//! # Any GPU (Vulkan, Metal, DX12)
+//! cargo run --example bert --release --features wgpu -- -p "This is a test" -n 3
+//!
+//! # Intel iGPUs, older AMD cards (OpenCL)
+//! cargo run --example bert --release --features opencl -- -p "This is a test" -n 3
+//!
+//! # Linux and Android GPUs (Vulkan, requires glslc from the Vulkan SDK)
+//! cargo run --example bert --release --features vulkan -- -p "This is a test" -n 3
+//!
+//! # Several backends at once, picking the device at runtime
+//! cargo run --example bert --release --features cpu,cuda -- -p "This is a test" -d cuda:0
+//!
+//! # Many sequences at once, one worker thread per cpu
+//! cargo run --example bert --release --features cpu -- -p "This is a test" -n 64 --parallel
+//!
+//! # Int8 weights for the linear layers, quantized at load time
+//! cargo run --example bert --release --features cpu -- -p "This is a test" -n 3 --quantize
+//!
+//! # Int8 weights and activations, the activation ranges calibrated on the prompt
+//! cargo run --example bert --release --features cpu -- -p "This is a test" -n 3 --calibrate
+//!
+//! # Cuda, replaying a recorded cuda graph for every run
+//! cargo run --example bert --release --features cuda -- -p "This is a test" -n 3 --capture
+//!
+//! # Cuda, with the softmax and layer norm from cuDNN
+//! cargo run --example bert --release --features cudnn -- -p "This is a test" -n 3
+//!
+//! # Cuda, with the matmuls of the linear layers in f16
+//! cargo run --example bert --release --features cuda,f16 -- -p "This is a test" -n 3 --precision f16
 //! ```
 //!
 //! # Why not use library X ?
@@ -51,6 +90,8 @@
 //! look closely like torch implementations.
 //! - [traits] Contains the glue that allows [nn] to be written independantly of [cpu]
 //!   which should hopefully making using different precisions (or backends) quite easy.
+//! - [checkpoint] loads the safetensors checkpoints, converting their F16, BF16, F64 or I64
+//!   tensors to f32 on the fly.
 //!
 //!
 //! # How does the model look like:
@@ -86,7 +127,8 @@
 //! in full f32 precision.
 //! For comparison, on the same hardware `torch` gives ~47ms/token and ggml ~37ms.
 //!
-//! Current implementations does *not* use threading, nor precomputed gelu/exp
+//! Current implementations only split matmuls and softmaxes across threads (see
+//! `set_num_threads` or `SMELT_NUM_THREADS`), and use neither precomputed gelu/exp
 //! nor f16 shortcuts that ggml can use (like for the softmax).
 //!
 //! So there is still lots of room for improvement, and most of the current performance
@@ -96,16 +138,59 @@
 /// The various CPU implementations
 #[cfg(feature = "cpu")]
 pub mod cpu;
+#[cfg(feature = "cpu")]
+pub use cpu::{num_threads, set_num_threads};
 
 /// The various GPU implementations
-#[cfg(feature = "cuda")]
+#[cfg(any(
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
 pub mod gpu;
 #[cfg(feature = "cuda")]
 use gpu::f32::CudaError;
+#[cfg(feature = "metal")]
+use gpu::metal::f32::MetalError;
+#[cfg(feature = "opencl")]
+use gpu::opencl::f32::OpenClError;
+#[cfg(feature = "vulkan")]
+use gpu::vulkan::f32::VulkanError;
+#[cfg(feature = "wgpu")]
+use gpu::wgpu::f32::WgpuError;
+
+/// A [backend::Device] and [backend::Tensor] chosen at runtime, dispatching to all
+/// the backends compiled in.
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+pub mod backend;
+
+/// Loading of the safetensors checkpoints, whatever their dtype
+#[cfg(feature = "safetensors")]
+pub mod checkpoint;
+
+/// Einsum equations over the [backend::Tensor]
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+pub mod einsum;
+
+/// Shape computations shared by the backends
+#[cfg(any(feature = "cpu", feature = "cuda"))]
+mod shape;
 
 /// The neural networks
 pub mod nn;
 
+/// Seeded random numbers, the same on every backend
+pub mod random;
+
 /// The traits for generic implementations
 pub mod traits;
 
@@ -151,6 +236,31 @@ pub enum SmeltError {
         id: usize,
     },
 
+    /// A token, position or type id is negative
+    NegativeId(i64),
+
+    /// A slice of a tensor goes past the end of its dimension
+    OutOfRange {
+        /// The dimension sliced
+        dim: usize,
+        /// The end of the slice
+        end: usize,
+        /// The size of the dimension
+        size: usize,
+    },
+
+    /// The einsum equation is invalid or not supported
+    InvalidEquation(String),
+
+    /// The parameters of an operation or a layer are invalid, like a stride of 0
+    InvalidConfig(String),
+
+    /// The temperature of a softmax is not strictly positive
+    InvalidTemperature(f32),
+
+    /// The probability threshold of a top-p filtering is not in `(0, 1]`
+    InvalidProbability(f32),
+
     /// Some slices do not have the expected lengths
     InvalidLength {
         /// The size we expected
@@ -159,19 +269,167 @@ pub enum SmeltError {
         got: usize,
     },
 
+    /// The operation mixed tensors from different backends
+    BackendMismatch {
+        /// The backend of the reference tensor
+        expected: &'static str,
+        /// The backend of the culprit tensor
+        got: &'static str,
+    },
+
+    /// The device name could not be parsed, or its backend is not compiled in
+    InvalidDevice(String),
+
+    /// The backend does not implement this operation
+    Unsupported {
+        /// The operation attempted
+        operation: &'static str,
+        /// The backend of the device
+        backend: &'static str,
+    },
+
+    /// A layer was quantized statically without any calibration run, see
+    /// [crate::nn::quantize::Quantizer::observe]
+    NotCalibrated,
+
+    /// The checkpoint contains a tensor of a dtype that cannot be converted
+    #[cfg(feature = "safetensors")]
+    UnsupportedDtype(String),
+
+    /// The checkpoint does not contain a tensor of this name
+    #[cfg(feature = "safetensors")]
+    MissingTensor(String),
+
+    /// The cpu thread pool could not be created
+    #[cfg(feature = "cpu")]
+    ThreadPool(String),
+
+    /// The NUMA placement of the memory or the threads failed
+    #[cfg(feature = "cpu")]
+    Numa(String),
+
     /// All errors of cuda handling
     #[cfg(feature = "cuda")]
     Cuda(CudaError),
+
+    /// All errors of metal handling
+    #[cfg(feature = "metal")]
+    Metal(MetalError),
+
+    /// All errors of wgpu handling
+    #[cfg(feature = "wgpu")]
+    Wgpu(WgpuError),
+
+    /// All errors of OpenCL handling
+    #[cfg(feature = "opencl")]
+    OpenCl(OpenClError),
+
+    /// All errors of Vulkan handling
+    #[cfg(feature = "vulkan")]
+    Vulkan(VulkanError),
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "cpu")]
+    use crate::cpu::f32::Tensor;
+    #[cfg(feature = "cpu")]
+    use crate::nn::layers::{
+        AttentionConfig, LayerNorm, Linear, MultiHeadAttention, QkvProjection, RmsNorm,
+    };
+
     pub(crate) fn simplify(data: &[f32]) -> Vec<f32> {
         let precision = 3;
         let m = 10.0 * 10.0f32.powf(precision as f32);
         data.iter().map(|x| (x * m).round() / m).collect()
     }
 
+    /// A [Linear] (out, dim) of random weights and biases, the same for a given `seed`.
+    #[cfg(feature = "cpu")]
+    pub(crate) fn linear(out: usize, dim: usize, seed: u64) -> Linear<Tensor> {
+        let weight = Tensor::rand_normal(vec![out, dim], seed);
+        Linear::new(weight, Tensor::rand_normal(vec![out], seed + 1000))
+    }
+
+    /// A [LayerNorm] of `dim` items which only normalizes.
+    #[cfg(feature = "cpu")]
+    pub(crate) fn layer_norm(dim: usize, epsilon: f32) -> LayerNorm<Tensor> {
+        let weight = Tensor::new(vec![1.0; dim], vec![dim]).unwrap();
+        LayerNorm::new(weight, Tensor::zeros(vec![dim]), epsilon)
+    }
+
+    /// A [RmsNorm] of `dim` items which only normalizes.
+    #[cfg(feature = "cpu")]
+    pub(crate) fn rms_norm(dim: usize) -> RmsNorm<Tensor> {
+        let weight = Tensor::new(vec![1.0; dim], vec![dim]).unwrap();
+        RmsNorm::new(weight, 1e-6).unwrap()
+    }
+
+    /// A [MultiHeadAttention] of `config` over `num_heads * head_dim` items, its query,
+    /// key, value and output projections being the [linear] of `seed` to `seed + 3`.
+    #[cfg(feature = "cpu")]
+    pub(crate) fn attention(config: AttentionConfig, seed: u64) -> MultiHeadAttention<Tensor> {
+        let hidden_dim = config.num_heads * config.head_dim;
+        let kv_dim = config.num_kv_heads * config.head_dim;
+        let qkv = QkvProjection::Separate {
+            query: linear(hidden_dim, hidden_dim, seed),
+            key: linear(kv_dim, hidden_dim, seed + 1),
+            value: linear(kv_dim, hidden_dim, seed + 2),
+        };
+        let output = linear(hidden_dim, hidden_dim, seed + 3);
+        MultiHeadAttention::new(qkv, Some(output), config).unwrap()
+    }
+
+    /// The buffer of a safetensors checkpoint of the f32 `tensors` (name, shape), filled
+    /// with small values, to load the models without the files of the hub.
+    #[cfg(feature = "safetensors")]
+    pub(crate) fn checkpoint(tensors: &[(String, Vec<usize>)]) -> Vec<u8> {
+        let mut header = vec![];
+        let mut data = vec![];
+        for (name, shape) in tensors {
+            let start = data.len();
+            let numel: usize = shape.iter().product();
+            for i in 0..numel {
+                data.extend_from_slice(&((i % 7) as f32 * 0.1 - 0.3).to_le_bytes());
+            }
+            let end = data.len();
+            header.push(format!(
+                r#""{name}":{{"dtype":"F32","shape":{shape:?},"data_offsets":[{start},{end}]}}"#
+            ));
+        }
+        let mut header = format!("{{{}}}", header.join(","));
+        // The data starts 8 bytes aligned, like the files of the safetensors library.
+        while header.len() % 8 != 0 {
+            header.push(' ');
+        }
+        let mut buffer = (header.len() as u64).to_le_bytes().to_vec();
+        buffer.extend_from_slice(header.as_bytes());
+        buffer.extend(data);
+        buffer
+    }
+
+    /// The tensors of the weight (out, dim) and bias (out) of the linear `prefix`.
+    #[cfg(feature = "safetensors")]
+    pub(crate) fn linear_tensors(
+        prefix: &str,
+        out: usize,
+        dim: usize,
+    ) -> [(String, Vec<usize>); 2] {
+        [
+            (format!("{prefix}.weight"), vec![out, dim]),
+            (format!("{prefix}.bias"), vec![out]),
+        ]
+    }
+
+    /// The tensors of the weight and bias (dim) of the layer norm `prefix`.
+    #[cfg(feature = "safetensors")]
+    pub(crate) fn layer_norm_tensors(prefix: &str, dim: usize) -> [(String, Vec<usize>); 2] {
+        [
+            (format!("{prefix}.weight"), vec![dim]),
+            (format!("{prefix}.bias"), vec![dim]),
+        ]
+    }
+
     // fn assert_float_eq(left: &[f32], right: &[f32]) {
     //     assert_eq!(left.len(), right.len());
 
@@ -0,0 +1,206 @@
+use crate::nn::layers::KvCache;
+use crate::traits::{Tensor, TensorArgmax};
+use crate::SmeltError;
+
+/// An encoder-decoder model generating tokens from the states of its encoder, like T5
+/// or BART, see [generate].
+pub trait Seq2Seq<T: Tensor> {
+    /// The encoder states (sequence_length, d_model) of `input_ids`.
+    fn encode(&self, input_ids: &[usize]) -> Result<T, SmeltError>;
+
+    /// An empty cache of the decoder.
+    fn new_cache(&self) -> KvCache<T>;
+
+    /// The logits (sequence_length, vocab_size) of the next tokens of the decoder inputs
+    /// `decoder_input_ids`, following the ones of `cache`, attending to the
+    /// `encoder_states`.
+    fn decode(
+        &self,
+        decoder_input_ids: &[usize],
+        encoder_states: &T,
+        cache: &mut KvCache<T>,
+    ) -> Result<T, SmeltError>;
+}
+
+/// A decoder-only model generating the tokens following a prompt, like LLaMA, see
+/// [generate_causal].
+pub trait CausalLm<T: Tensor> {
+    /// An empty cache of the decoder.
+    fn new_cache(&self) -> KvCache<T>;
+
+    /// The logits (1, vocab_size) of the token following `input_ids`, which follow the
+    /// tokens of `cache`.
+    fn next_logits(&self, input_ids: &[usize], cache: &mut KvCache<T>) -> Result<T, SmeltError>;
+}
+
+/// The special tokens and the length of a generation, the `generation_config.json` of
+/// transformers.
+/// ```
+/// use smelte_rs::nn::generation::GenerationConfig;
+///
+/// // The summarizers of BART start with the eos token, then the bos token.
+/// let config = GenerationConfig {
+///     forced_bos_token_id: Some(0),
+///     ..GenerationConfig::new(142, 2, 2)
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerationConfig {
+    /// The maximum number of generated tokens
+    pub max_new_tokens: usize,
+    /// The first input of the decoder
+    pub decoder_start_token_id: usize,
+    /// The token ending the generation, it is not returned
+    pub eos_token_id: usize,
+    /// The first generated token whatever the logits, like the bos token of the BART
+    /// summarizers or the target language of mBART-50
+    pub forced_bos_token_id: Option<usize>,
+}
+
+impl GenerationConfig {
+    /// Up to `max_new_tokens` tokens after `decoder_start_token_id`, until
+    /// `eos_token_id`.
+    pub fn new(max_new_tokens: usize, decoder_start_token_id: usize, eos_token_id: usize) -> Self {
+        Self {
+            max_new_tokens,
+            decoder_start_token_id,
+            eos_token_id,
+            forced_bos_token_id: None,
+        }
+    }
+}
+
+/// The greedy decoding of `input_ids` by `model`: the decoder is given a token at a time,
+/// the largest logit being the next one, and its keys and values are cached in buffers
+/// allocated for the whole generation.
+pub fn generate<T, M>(
+    model: &M,
+    input_ids: &[usize],
+    config: &GenerationConfig,
+) -> Result<Vec<usize>, SmeltError>
+where
+    T: Tensor + TensorArgmax<T>,
+    M: Seq2Seq<T> + ?Sized,
+{
+    let encoder_states = model.encode(input_ids)?;
+    let mut cache = model.new_cache();
+    cache.reserve(config.max_new_tokens);
+    let mut output_ids = Vec::with_capacity(config.max_new_tokens);
+    let mut next_id = config.decoder_start_token_id;
+    for step in 0..config.max_new_tokens {
+        let logits = model.decode(&[next_id], &encoder_states, &mut cache)?;
+        next_id = match config.forced_bos_token_id {
+            Some(bos_token_id) if step == 0 => bos_token_id,
+            _ => T::argmax(&logits, 1)?[0],
+        };
+        if next_id == config.eos_token_id {
+            break;
+        }
+        output_ids.push(next_id);
+    }
+    Ok(output_ids)
+}
+
+/// The greedy decoding of the tokens following `input_ids` by `model`, up to
+/// `max_new_tokens` tokens or one of `eos_token_ids`, which is not returned. The prompt
+/// runs in a single pass, then a token at a time with the keys and values cached.
+pub fn generate_causal<T, M>(
+    model: &M,
+    input_ids: &[usize],
+    max_new_tokens: usize,
+    eos_token_ids: &[usize],
+) -> Result<Vec<usize>, SmeltError>
+where
+    T: Tensor + TensorArgmax<T>,
+    M: CausalLm<T> + ?Sized,
+{
+    if input_ids.is_empty() {
+        return Err(SmeltError::InvalidConfig(
+            "the generation of an empty prompt".to_string(),
+        ));
+    }
+    let mut cache = model.new_cache();
+    cache.reserve(input_ids.len() + max_new_tokens);
+    let mut output_ids = Vec::with_capacity(max_new_tokens);
+    let mut logits = model.next_logits(input_ids, &mut cache)?;
+    for _ in 0..max_new_tokens {
+        let next_id = T::argmax(&logits, 1)?[0];
+        if eos_token_ids.contains(&next_id) {
+            break;
+        }
+        output_ids.push(next_id);
+        if output_ids.len() < max_new_tokens {
+            logits = model.next_logits(&[next_id], &mut cache)?;
+        }
+    }
+    Ok(output_ids)
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+
+    /// The next token of `id` is `id + 1`, the encoder states being ignored.
+    struct Counter;
+
+    impl Seq2Seq<Tensor> for Counter {
+        fn encode(&self, input_ids: &[usize]) -> Result<Tensor, SmeltError> {
+            Ok(Tensor::zeros(vec![input_ids.len(), 1]))
+        }
+
+        fn new_cache(&self) -> KvCache<Tensor> {
+            KvCache::new(1)
+        }
+
+        fn decode(
+            &self,
+            decoder_input_ids: &[usize],
+            _encoder_states: &Tensor,
+            _cache: &mut KvCache<Tensor>,
+        ) -> Result<Tensor, SmeltError> {
+            let mut logits = vec![0.0; 8];
+            logits[(decoder_input_ids[0] + 1) % 8] = 1.0;
+            Tensor::new(logits, vec![1, 8])
+        }
+    }
+
+    impl CausalLm<Tensor> for Counter {
+        fn new_cache(&self) -> KvCache<Tensor> {
+            KvCache::new(1)
+        }
+
+        fn next_logits(
+            &self,
+            input_ids: &[usize],
+            cache: &mut KvCache<Tensor>,
+        ) -> Result<Tensor, SmeltError> {
+            let last = &input_ids[input_ids.len() - 1..];
+            self.decode(last, &Tensor::zeros(vec![1]), cache)
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let config = GenerationConfig::new(10, 2, 6);
+        assert_eq!(generate(&Counter, &[0], &config).unwrap(), [3, 4, 5]);
+        let config = GenerationConfig::new(2, 2, 6);
+        assert_eq!(generate(&Counter, &[0], &config).unwrap(), [3, 4]);
+        let config = GenerationConfig {
+            forced_bos_token_id: Some(0),
+            ..GenerationConfig::new(10, 2, 2)
+        };
+        assert_eq!(generate(&Counter, &[0], &config).unwrap(), [0, 1]);
+    }
+
+    #[test]
+    fn test_generate_causal() {
+        assert_eq!(
+            generate_causal(&Counter, &[5, 1], 10, &[5]).unwrap(),
+            [2, 3, 4]
+        );
+        assert_eq!(generate_causal(&Counter, &[1], 2, &[5]).unwrap(), [2, 3]);
+        assert!(generate_causal(&Counter, &[], 2, &[5]).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::SmeltError;
+
+/// The integer tensor of token ids, position ids or type ids, for one sequence
+/// (sequence_length) or a batch of them (batch_size, sequence_length).
+/// The ids are converted once from the integers of the tokenizers or of the checkpoints,
+/// then reused by every run. They stay on the host, the backends only read them to
+/// gather the embeddings.
+/// ```
+/// use smelte_rs::nn::ids::Ids;
+///
+/// let input_ids = Ids::from_u32(&[101, 2023, 102, 101, 2003, 102], vec![2, 3]).unwrap();
+/// let position_ids = Ids::positions(vec![2, 3]);
+/// assert_eq!(input_ids.row(1), [101, 2003, 102]);
+/// assert_eq!(position_ids.row(1), [0, 1, 2]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ids {
+    shape: Vec<usize>,
+    data: Vec<usize>,
+}
+
+impl Ids {
+    /// Creates new ids with given shape. Can fail if data doesn't match the shape
+    pub fn new(data: Vec<usize>, shape: Vec<usize>) -> Result<Self, SmeltError> {
+        if shape.is_empty() || shape.len() > 2 {
+            return Err(SmeltError::InvalidRank { expected_rank: 2 });
+        }
+        if data.len() != shape.iter().product::<usize>() {
+            return Err(SmeltError::InvalidBuffer {
+                buffer_size: data.len(),
+                shape,
+            });
+        }
+        Ok(Self { shape, data })
+    }
+
+    /// The ids of the tokenizers.
+    pub fn from_u32(data: &[u32], shape: Vec<usize>) -> Result<Self, SmeltError> {
+        Self::new(data.iter().map(|&id| id as usize).collect(), shape)
+    }
+
+    /// The ids of the checkpoints and of torch, fails on negative ids.
+    pub fn from_i64(data: &[i64], shape: Vec<usize>) -> Result<Self, SmeltError> {
+        let data = data
+            .iter()
+            .map(|&id| usize::try_from(id).map_err(|_| SmeltError::NegativeId(id)))
+            .collect::<Result<_, _>>()?;
+        Self::new(data, shape)
+    }
+
+    /// The ids `0..sequence_length` of every sequence.
+    pub fn positions(shape: Vec<usize>) -> Self {
+        let sequence_length = shape.last().copied().unwrap_or(0);
+        let batch_size: usize = shape.iter().product::<usize>() / sequence_length.max(1);
+        let data = (0..batch_size).flat_map(|_| 0..sequence_length).collect();
+        Self { shape, data }
+    }
+
+    /// The position ids of RoBERTa for `input_ids`, like `create_position_ids_from_input_ids`
+    /// of transformers: the tokens are numbered from `padding_idx + 1`, the pad tokens
+    /// (`padding_idx`, usually 1) keeping the position `padding_idx`.
+    /// ```
+    /// use smelte_rs::nn::ids::Ids;
+    ///
+    /// let input_ids = Ids::new(vec![0, 713, 2, 1], vec![4]).unwrap();
+    /// assert_eq!(Ids::padded_positions(&input_ids, 1).data(), [2, 3, 4, 1]);
+    /// ```
+    pub fn padded_positions(input_ids: &Ids, padding_idx: usize) -> Self {
+        let data = input_ids
+            .rows()
+            .flat_map(|row| {
+                row.iter().scan(padding_idx, move |position, &id| {
+                    if id == padding_idx {
+                        return Some(padding_idx);
+                    }
+                    *position += 1;
+                    Some(*position)
+                })
+            })
+            .collect();
+        Self {
+            shape: input_ids.shape.clone(),
+            data,
+        }
+    }
+
+    /// Ids of 0 only, like the type ids of single sentences.
+    pub fn zeros(shape: Vec<usize>) -> Self {
+        let data = vec![0; shape.iter().product()];
+        Self { shape, data }
+    }
+
+    /// The shape of the ids
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// All the ids, sequence after sequence
+    pub fn data(&self) -> &[usize] {
+        &self.data
+    }
+
+    /// The number of sequences, 1 without a batch dimension
+    pub fn batch_size(&self) -> usize {
+        if self.shape.len() == 2 {
+            self.shape[0]
+        } else {
+            1
+        }
+    }
+
+    /// The ids of the sequence `i` of the batch
+    pub fn row(&self, i: usize) -> &[usize] {
+        let sequence_length = self.shape[self.shape.len() - 1];
+        &self.data[i * sequence_length..(i + 1) * sequence_length]
+    }
+
+    /// The sequences of the batch
+    pub fn rows(&self) -> impl Iterator<Item = &[usize]> {
+        (0..self.batch_size()).map(|i| self.row(i))
+    }
+}
+
+impl From<Vec<usize>> for Ids {
+    fn from(data: Vec<usize>) -> Self {
+        let shape = vec![data.len()];
+        Self { shape, data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        let ids = Ids::from_i64(&[3, 1, 4, 1, 5, 9], vec![3, 2]).unwrap();
+        assert_eq!(ids.batch_size(), 3);
+        assert_eq!(ids.rows().collect::<Vec<_>>(), [[3, 1], [4, 1], [5, 9]]);
+        assert!(Ids::from_i64(&[-1], vec![1]).is_err());
+        assert!(Ids::from_u32(&[1, 2, 3], vec![2, 2]).is_err());
+
+        let ids = Ids::from(vec![7, 8]);
+        assert_eq!(ids.shape(), [2]);
+        assert_eq!(ids.rows().collect::<Vec<_>>(), [[7, 8]]);
+        assert_eq!(Ids::positions(vec![3]).data(), [0, 1, 2]);
+        assert_eq!(Ids::zeros(vec![2, 2]).data(), [0; 4]);
+
+        // The positions restart on every row, the pad tokens 1 keep the position 1.
+        let ids = Ids::new(vec![0, 5, 2, 1, 0, 1, 6, 2], vec![2, 4]).unwrap();
+        let positions = Ids::padded_positions(&ids, 1);
+        assert_eq!(positions.shape(), [2, 4]);
+        assert_eq!(positions.data(), [2, 3, 4, 1, 2, 1, 3, 4]);
+    }
+}
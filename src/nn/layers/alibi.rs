@@ -0,0 +1,126 @@
+use crate::traits::Device;
+use crate::SmeltError;
+
+/// Attention with linear biases (ALiBi), like BLOOM and MPT: instead of position
+/// embeddings, every head penalizes its scores by its slope times the distance between
+/// the query and the key. The layer has no weights, the bias is built on the device of
+/// the attention with [Alibi::bias].
+/// ```
+/// use smelte_rs::cpu::f32::Device;
+/// use smelte_rs::nn::layers::Alibi;
+///
+/// let alibi = Alibi::new(8);
+/// assert_eq!(alibi.slopes()[..2], [0.5, 0.25]);
+/// // The bias of 3 new queries after 2 cached keys.
+/// let bias = alibi.bias(3, 5, &Device::new()).unwrap();
+/// assert_eq!(bias.shape(), [8, 3, 5]);
+/// assert_eq!(bias.data()[..5], [-1.0, -0.5, 0.0, -0.5, -1.0]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alibi {
+    slopes: Vec<f32>,
+}
+
+impl Alibi {
+    /// The slopes of BLOOM for `num_heads` heads, `2 ^ (-8 * i / num_heads)`.
+    pub fn new(num_heads: usize) -> Self {
+        Self::with_max_bias(num_heads, 8.0)
+    }
+
+    /// The slopes `2 ^ (-max_bias * i / num_heads)` (MPT's `alibi_bias_max`). When
+    /// `num_heads` isn't a power of 2, the slopes of the closest smaller power of 2 are
+    /// followed by every other slope of twice as many heads, like BLOOM.
+    pub fn with_max_bias(num_heads: usize, max_bias: f32) -> Self {
+        let slopes = |n: usize, step: usize| {
+            (1..)
+                .step_by(step)
+                .map(move |i| 2f64.powf(-(max_bias as f64) * i as f64 / n as f64) as f32)
+        };
+        let closest = match num_heads {
+            0 => 0,
+            n => 1 << n.ilog2(),
+        };
+        let slopes = slopes(closest, 1)
+            .take(closest)
+            .chain(slopes(2 * closest, 2).take(num_heads - closest))
+            .collect();
+        Self { slopes }
+    }
+
+    /// The slope of every head
+    pub fn slopes(&self) -> &[f32] {
+        &self.slopes
+    }
+
+    /// The bias (num_heads, query_length, key_length) added to the attention scores,
+    /// `-slope * |i - j|` where the queries are the last `query_length` keys. Under the
+    /// causal mask it gives the same probabilities as the `slope * j` of BLOOM.
+    pub fn bias<D: Device>(
+        &self,
+        query_length: usize,
+        key_length: usize,
+        device: &D,
+    ) -> Result<D::Tensor, SmeltError> {
+        if query_length > key_length {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{query_length} queries attend to {key_length} keys"
+            )));
+        }
+        self.bias_at(key_length - query_length, query_length, key_length, device)
+    }
+
+    /// [Alibi::bias] with the query `i` at the position `offset + i` of the keys, like
+    /// the new tokens of a [crate::nn::layers::KvCache] followed by free room.
+    pub fn bias_at<D: Device>(
+        &self,
+        offset: usize,
+        query_length: usize,
+        key_length: usize,
+        device: &D,
+    ) -> Result<D::Tensor, SmeltError> {
+        let mut data = Vec::with_capacity(self.slopes.len() * query_length * key_length);
+        for slope in &self.slopes {
+            for i in offset..offset + query_length {
+                data.extend((0..key_length).map(|j| -slope * i.abs_diff(j) as f32));
+            }
+        }
+        device.tensor(&data, vec![self.slopes.len(), query_length, key_length])
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Device;
+
+    #[test]
+    fn test_alibi() {
+        let slopes: Vec<f32> = (1..=8).map(|i| 0.5f32.powi(i)).collect();
+        assert_eq!(Alibi::new(8).slopes(), slopes);
+
+        // BLOOM-7b1 has 32 heads, BLOOM-176b 112 heads.
+        let alibi = Alibi::new(12);
+        assert_eq!(alibi.slopes()[..8], slopes);
+        let extra = [
+            0.5f32.powf(0.5),
+            0.5f32.powf(1.5),
+            0.5f32.powf(2.5),
+            0.5f32.powf(3.5),
+        ];
+        for (slope, expected) in alibi.slopes()[8..].iter().zip(extra) {
+            assert!((slope - expected).abs() < 1e-6);
+        }
+        assert_eq!(Alibi::new(112).slopes().len(), 112);
+        assert_eq!(
+            Alibi::with_max_bias(4, 4.0).slopes(),
+            [0.5, 0.25, 0.125, 0.0625]
+        );
+
+        let bias = Alibi::with_max_bias(2, 2.0)
+            .bias(2, 2, &Device::new())
+            .unwrap();
+        assert_eq!(bias.data(), [0.0, -0.5, -0.5, 0.0, 0.0, -0.25, -0.25, 0.0]);
+        assert!(Alibi::new(2).bias(3, 2, &Device::new()).is_err());
+    }
+}
@@ -0,0 +1,1558 @@
+use crate::nn::layers::{Alibi, Dropout, Linear, RelativePositionBias, RotaryEmbedding};
+use crate::traits::{
+    Device, Tensor, TensorHeads, TensorNarrow, TensorOps, TensorPositionMask, TensorQuantize,
+    TensorReshape, TensorRotary, TensorToDevice,
+};
+use crate::SmeltError;
+
+/// The projections of the hidden states to the queries, keys and values of a
+/// [MultiHeadAttention].
+#[derive(Clone)]
+pub enum QkvProjection<T: Tensor> {
+    /// A layer each, like BERT and LLaMA
+    Separate {
+        /// The queries (num_heads * head_dim, hidden_dim)
+        query: Linear<T>,
+        /// The keys (num_kv_heads * head_dim, hidden_dim)
+        key: Linear<T>,
+        /// The values (num_kv_heads * head_dim, hidden_dim)
+        value: Linear<T>,
+    },
+    /// A single layer to the queries, keys and values concatenated on the features
+    /// ((num_heads + 2 * num_kv_heads) * head_dim, hidden_dim), like Falcon.
+    Fused(Linear<T>),
+}
+
+impl<T: TensorToDevice> QkvProjection<T> {
+    /// A copy of the projections with their weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(match self {
+            Self::Separate { query, key, value } => Self::Separate {
+                query: query.to_device(device)?,
+                key: key.to_device(device)?,
+                value: value.to_device(device)?,
+            },
+            Self::Fused(qkv) => Self::Fused(qkv.to_device(device)?),
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> QkvProjection<T> {
+    /// Quantizes the projections, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        match self {
+            Self::Separate { query, key, value } => {
+                query.quantize_dynamic()?;
+                key.quantize_dynamic()?;
+                value.quantize_dynamic()
+            }
+            Self::Fused(qkv) => qkv.quantize_dynamic(),
+        }
+    }
+}
+
+/// The heads of a [MultiHeadAttention]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttentionConfig {
+    /// The number of heads of the queries
+    pub num_heads: usize,
+    /// The number of heads of the keys and values, shared by groups of `num_heads /
+    /// num_kv_heads` queries when smaller than `num_heads` (grouped-query attention, or
+    /// multi-query attention with a single head).
+    pub num_kv_heads: usize,
+    /// The size of every head
+    pub head_dim: usize,
+    /// The factor of the scores, `1 / sqrt(head_dim)` when unset
+    pub scale: Option<f32>,
+    /// Whether the queries only attend to the keys before them, like the decoders of
+    /// autoregressive models
+    pub causal: bool,
+    /// The number of keys up to a query (itself included) it attends to, like Mistral,
+    /// and as many after it unless `causal`. The cache of
+    /// [MultiHeadAttention::forward_cached] keeps that many tokens.
+    pub sliding_window: Option<usize>,
+}
+
+impl AttentionConfig {
+    /// `num_heads` heads of `head_dim` items for the queries, keys and values.
+    pub fn new(num_heads: usize, head_dim: usize) -> Self {
+        Self {
+            num_heads,
+            num_kv_heads: num_heads,
+            head_dim,
+            scale: None,
+            causal: false,
+            sliding_window: None,
+        }
+    }
+
+    fn scale(&self) -> f32 {
+        self.scale
+            .unwrap_or_else(|| 1.0 / (self.head_dim as f32).sqrt())
+    }
+}
+
+/// The buffers of a [MultiHeadAttention] for a sequence length, see
+/// [MultiHeadAttention::context].
+pub struct AttentionContext<T: Tensor> {
+    // The output of the fused projection, or of the query projection.
+    projected: T,
+    // The output of the key and value projections, when they have fewer heads.
+    kv_projected: Option<T>,
+    query: T,
+    key: T,
+    value: T,
+    // The scores, then the probabilities (num_heads, sequence_length, sequence_length)
+    scores: T,
+    heads: T,
+    merged: T,
+    position_bias: Option<T>,
+    // The -inf of the keys after the queries when the attention is causal, and outside
+    // of the sliding window
+    position_mask: Option<T>,
+}
+
+impl<T: Tensor> AttentionContext<T> {
+    /// The attention probabilities (num_heads, query_length, key_length) of the last
+    /// forward pass.
+    pub fn probs(&self) -> &T {
+        &self.scores
+    }
+}
+
+/// The keys and values (num_kv_heads, length, head_dim) of the tokens already seen by
+/// every layer of a decoder, extended by [MultiHeadAttention::forward_cached] so that
+/// every generation step only projects its new tokens. The tokens are written in place in
+/// buffers allocated for more of them, see [KvCache::reserve]. With a sliding window,
+/// the cache drops the tokens out of the window and remembers the position of the next
+/// one.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::{AttentionConfig, KvCache, Linear, MultiHeadAttention, QkvProjection};
+/// use smelte_rs::traits::Tensor as _;
+///
+/// let linear = |out, dim| Linear::new(Tensor::zeros(vec![out, dim]), Tensor::zeros(vec![out]));
+/// let config = AttentionConfig {
+///     causal: true,
+///     ..AttentionConfig::new(4, 8)
+/// };
+/// let qkv = QkvProjection::Fused(linear(3 * 32, 32));
+/// let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+///
+/// let mut cache = KvCache::new(1);
+/// let mut out = Tensor::zeros(vec![5, 32]);
+/// // The prompt, then a token at a time.
+/// let prompt = Tensor::zeros(vec![5, 32]);
+/// attention.forward_cached(&prompt, None, &mut cache, 0, &mut out).unwrap();
+/// let token = Tensor::zeros(vec![1, 32]);
+/// let mut out = Tensor::zeros(vec![1, 32]);
+/// attention.forward_cached(&token, None, &mut cache, 0, &mut out).unwrap();
+/// assert_eq!(cache.len(), 6);
+/// ```
+pub struct KvCache<T: Tensor> {
+    layers: Vec<Option<CachedLayer<T>>>,
+    // The keys and values of the states attended to by the cross-attention of the layers,
+    // projected once per sequence.
+    cross: Vec<Option<CachedLayer<T>>>,
+    // The number of tokens the buffers are allocated for, at least.
+    reserved: usize,
+}
+
+/// The tokens of a layer of a [KvCache]
+struct CachedLayer<T: Tensor> {
+    // The buffers (num_kv_heads, capacity, head_dim), holding `length` tokens.
+    key: T,
+    value: T,
+    length: usize,
+    // The number of tokens dropped out of the sliding window.
+    dropped: usize,
+    sliding_window: Option<usize>,
+    // The buffers of the last forward pass, reused by the next ones of the same length.
+    ctx: Option<AttentionContext<T>>,
+    // The position bias of every relative position of the buffers, sliced by the forward
+    // passes.
+    position_bias: Option<T>,
+}
+
+impl<T: Tensor> KvCache<T> {
+    /// An empty cache for `num_layers` layers
+    pub fn new(num_layers: usize) -> Self {
+        Self {
+            layers: (0..num_layers).map(|_| None).collect(),
+            cross: (0..num_layers).map(|_| None).collect(),
+            reserved: 0,
+        }
+    }
+
+    /// Allocates the buffers of the layers for at least `capacity` tokens, like the
+    /// prompt and the generated tokens, instead of doubling them as the tokens come. The
+    /// scores of the attention cover the whole buffers, the tokens not cached yet being
+    /// masked.
+    pub fn reserve(&mut self, capacity: usize) {
+        self.reserved = self.reserved.max(capacity);
+    }
+
+    /// The number of layers of the cache
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// The number of tokens cached by the first layer, up to its sliding window
+    pub fn len(&self) -> usize {
+        match self.layers.first() {
+            Some(Some(layer)) => layer
+                .length
+                .min(layer.sliding_window.unwrap_or(layer.length)),
+            _ => 0,
+        }
+    }
+
+    /// Whether no token is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The position of the next token of the first layer, the number of tokens seen
+    /// since the cache was cleared, cached or dropped out of a sliding window.
+    pub fn position(&self) -> usize {
+        match self.layers.first() {
+            Some(Some(layer)) => layer.length + layer.dropped,
+            _ => 0,
+        }
+    }
+
+    /// The buffers of the keys and values (num_kv_heads, capacity, head_dim) of the layer
+    /// `index`, if any, holding its tokens from the start.
+    pub fn layer(&self, index: usize) -> Option<(&T, &T)> {
+        match self.layers.get(index) {
+            Some(Some(layer)) => Some((&layer.key, &layer.value)),
+            _ => None,
+        }
+    }
+
+    /// Forgets every cached token, for a new sequence. The buffers are kept, but not the
+    /// keys and values of the states attended to by [MultiHeadAttention::forward_cross_cached].
+    pub fn clear(&mut self) {
+        for layer in self.layers.iter_mut().flatten() {
+            layer.length = 0;
+            layer.dropped = 0;
+        }
+        self.cross.iter_mut().for_each(|cross| *cross = None);
+    }
+}
+
+impl<T: Tensor + TensorNarrow<T>> CachedLayer<T> {
+    /// The empty buffers of `num_kv_heads` heads of `head_dim` items for `query_length`
+    /// tokens, see [capacity].
+    fn new(
+        num_kv_heads: usize,
+        head_dim: usize,
+        query_length: usize,
+        reserved: usize,
+        sliding_window: Option<usize>,
+        device: &T::Device,
+    ) -> Result<Self, SmeltError> {
+        let capacity = capacity(0, query_length, reserved, sliding_window);
+        let shape = vec![num_kv_heads, capacity, head_dim];
+        Ok(Self {
+            key: device.zeros(shape.clone())?,
+            value: device.zeros(shape)?,
+            length: 0,
+            dropped: 0,
+            sliding_window,
+            ctx: None,
+            position_bias: None,
+        })
+    }
+
+    /// Makes room for `query_length` new tokens: the tokens out of the sliding window are
+    /// dropped, then the buffers grow if they are still too small.
+    fn make_room(&mut self, query_length: usize, reserved: usize) -> Result<(), SmeltError> {
+        let current = self.key.shape()[1];
+        if self.length + query_length <= current {
+            return Ok(());
+        }
+        if let Some(window) = self.sliding_window {
+            // The new tokens attend to the last `window - 1` cached ones at most.
+            let dropped = self.length.saturating_sub(window - 1);
+            if dropped > 0 {
+                let kept = self.length - dropped;
+                let key = T::narrow(&self.key, 1, dropped, kept)?;
+                T::narrow_put(&mut self.key, 1, 0, &key)?;
+                let value = T::narrow(&self.value, 1, dropped, kept)?;
+                T::narrow_put(&mut self.value, 1, 0, &value)?;
+                self.length = kept;
+                self.dropped += dropped;
+            }
+        }
+        let needed = self.length + query_length;
+        if needed > current {
+            let mut shape = self.key.shape().to_vec();
+            shape[1] = capacity(current, needed, reserved, self.sliding_window);
+            let device = self.key.device();
+            let mut key = device.zeros(shape.clone())?;
+            T::narrow_put(&mut key, 1, 0, &self.key)?;
+            let mut value = device.zeros(shape)?;
+            T::narrow_put(&mut value, 1, 0, &self.value)?;
+            (self.key, self.value) = (key, value);
+        }
+        Ok(())
+    }
+}
+
+/// The capacity of buffers of `current` tokens grown for `needed` tokens: doubled so that
+/// every token is copied a bounded number of times, at least `reserved`, and up to twice
+/// the `sliding_window` which drops the older tokens.
+fn capacity(
+    current: usize,
+    needed: usize,
+    reserved: usize,
+    sliding_window: Option<usize>,
+) -> usize {
+    let capacity = (2 * current).max(reserved);
+    let capacity = match sliding_window {
+        Some(window) => capacity.min(2 * window),
+        None => capacity,
+    };
+    capacity.max(needed)
+}
+
+/// Multi-head scaled dot-product attention, built from the common tensor operations so
+/// models can compose it on every backend: the projections, the heads sharing the keys
+/// and values or not, the optional output projection and the masking are configurable.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::{AttentionConfig, Linear, MultiHeadAttention, QkvProjection};
+/// use smelte_rs::traits::Tensor as _;
+///
+/// let linear = |out, dim| Linear::new(Tensor::zeros(vec![out, dim]), Tensor::zeros(vec![out]));
+/// // 4 heads of 8 items, the keys and values having 2 heads.
+/// let config = AttentionConfig {
+///     num_kv_heads: 2,
+///     ..AttentionConfig::new(4, 8)
+/// };
+/// let qkv = QkvProjection::Fused(linear((4 + 2 * 2) * 8, 32));
+/// let attention = MultiHeadAttention::new(qkv, Some(linear(32, 32)), config).unwrap();
+///
+/// let hidden_states = Tensor::zeros(vec![5, 32]);
+/// let mut context = attention.context(5, &Device::new()).unwrap();
+/// let mut out = Tensor::zeros(vec![5, 32]);
+/// attention
+///     .forward(&hidden_states, None, &mut context, &mut out)
+///     .unwrap();
+/// assert_eq!(context.probs().shape(), [4, 5, 5]);
+/// ```
+#[derive(Clone)]
+pub struct MultiHeadAttention<T: Tensor> {
+    qkv: QkvProjection<T>,
+    output: Option<Linear<T>>,
+    config: AttentionConfig,
+    position_bias: Option<PositionBias>,
+    // The dropout of the attention probabilities
+    dropout: Dropout,
+}
+
+/// The bias of the positions added to the scores of a [MultiHeadAttention]
+#[derive(Debug, Clone, PartialEq)]
+enum PositionBias {
+    Alibi(Alibi),
+    Relative(RelativePositionBias),
+}
+
+impl<T: Tensor + TensorOps<T> + TensorHeads<T>> MultiHeadAttention<T> {
+    /// The attention of the projections `qkv` and `output`, checked against `config`.
+    pub fn new(
+        qkv: QkvProjection<T>,
+        output: Option<Linear<T>>,
+        config: AttentionConfig,
+    ) -> Result<Self, SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            ..
+        } = config;
+        if num_kv_heads == 0 || num_heads % num_kv_heads != 0 {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{num_heads} heads can't share {num_kv_heads} heads"
+            )));
+        }
+        if config.sliding_window == Some(0) {
+            return Err(SmeltError::InvalidConfig(
+                "a sliding window can't be empty".to_string(),
+            ));
+        }
+        let check = |linear: &Linear<T>, dim: usize| match linear.shape() {
+            [out, _] if *out == dim => Ok(()),
+            shape => Err(SmeltError::DimensionMismatch {
+                expected: vec![dim, shape.get(1).copied().unwrap_or(0)],
+                got: shape.to_vec(),
+            }),
+        };
+        match &qkv {
+            QkvProjection::Separate { query, key, value } => {
+                check(query, num_heads * head_dim)?;
+                check(key, num_kv_heads * head_dim)?;
+                check(value, num_kv_heads * head_dim)?;
+            }
+            QkvProjection::Fused(qkv) => check(qkv, (num_heads + 2 * num_kv_heads) * head_dim)?,
+        }
+        if let Some(output) = &output {
+            if output.shape().get(1) != Some(&(num_heads * head_dim)) {
+                return Err(SmeltError::DimensionMismatch {
+                    expected: vec![output.shape()[0], num_heads * head_dim],
+                    got: output.shape().to_vec(),
+                });
+            }
+        }
+        Ok(Self {
+            qkv,
+            output,
+            config,
+            position_bias: None,
+            dropout: Dropout::default(),
+        })
+    }
+
+    /// Biases the scores with `alibi`, which must have a slope per head.
+    pub fn with_alibi(mut self, alibi: Alibi) -> Self {
+        self.position_bias = Some(PositionBias::Alibi(alibi));
+        self
+    }
+
+    /// Biases the scores with the learned `bias` of T5, which must have a bias per head.
+    /// T5 doesn't scale the scores either, its [AttentionConfig] has a `scale` of 1.
+    pub fn with_relative_position_bias(mut self, bias: RelativePositionBias) -> Self {
+        self.position_bias = Some(PositionBias::Relative(bias));
+        self
+    }
+
+    /// Drops the attention probabilities with `dropout` in train mode, like the
+    /// `attention_dropout` of the configurations.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout of the probabilities to train or eval mode, see
+    /// [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// The heads of the attention
+    pub fn config(&self) -> &AttentionConfig {
+        &self.config
+    }
+
+    /// The buffers of the attention of `sequence_length` tokens on `device`, reused by
+    /// every forward pass of that length.
+    pub fn context(
+        &self,
+        sequence_length: usize,
+        device: &T::Device,
+    ) -> Result<AttentionContext<T>, SmeltError> {
+        self.cross_context(sequence_length, sequence_length, device)
+    }
+
+    /// The buffers of the attention of `query_length` tokens to the `key_length` tokens
+    /// of another sequence on `device`, see [MultiHeadAttention::forward_cross].
+    pub fn cross_context(
+        &self,
+        query_length: usize,
+        key_length: usize,
+        device: &T::Device,
+    ) -> Result<AttentionContext<T>, SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            ..
+        } = self.config;
+        let projected_dim = match &self.qkv {
+            QkvProjection::Separate { .. } => num_heads * head_dim,
+            QkvProjection::Fused(_) => (num_heads + 2 * num_kv_heads) * head_dim,
+        };
+        let kv_projected = match &self.qkv {
+            QkvProjection::Separate { .. }
+                if num_kv_heads != num_heads || key_length != query_length =>
+            {
+                Some(device.zeros(vec![key_length, num_kv_heads * head_dim])?)
+            }
+            _ => None,
+        };
+        let query_shape = vec![num_heads, query_length, head_dim];
+        let key_shape = vec![num_heads, key_length, head_dim];
+        let offset = key_length.saturating_sub(query_length);
+        let position_bias = self.position_bias(offset, query_length, key_length, device)?;
+        let AttentionConfig {
+            causal,
+            sliding_window,
+            ..
+        } = self.config;
+        let position_mask = (causal || sliding_window.is_some())
+            .then(|| {
+                position_mask(
+                    num_heads,
+                    query_length,
+                    key_length,
+                    causal,
+                    sliding_window,
+                    device,
+                )
+            })
+            .transpose()?;
+        Ok(AttentionContext {
+            projected: device.zeros(vec![query_length, projected_dim])?,
+            kv_projected,
+            query: device.zeros(query_shape.clone())?,
+            key: device.zeros(key_shape.clone())?,
+            value: device.zeros(key_shape)?,
+            scores: device.zeros(vec![num_heads, query_length, key_length])?,
+            heads: device.zeros(query_shape)?,
+            merged: device.zeros(vec![query_length, num_heads * head_dim])?,
+            position_bias,
+            position_mask,
+        })
+    }
+
+    /// The bias of the positions (num_heads, query_length, key_length), the query `i`
+    /// being at the position `offset + i` of the keys, checked to have a bias per head.
+    fn position_bias(
+        &self,
+        offset: usize,
+        query_length: usize,
+        key_length: usize,
+        device: &T::Device,
+    ) -> Result<Option<T>, SmeltError> {
+        let num_heads = self.config.num_heads;
+        let bias_heads = match &self.position_bias {
+            Some(PositionBias::Alibi(alibi)) => alibi.slopes().len(),
+            Some(PositionBias::Relative(bias)) => bias.num_heads(),
+            None => num_heads,
+        };
+        if bias_heads != num_heads {
+            return Err(SmeltError::InvalidLength {
+                expected: num_heads,
+                got: bias_heads,
+            });
+        }
+        Ok(match &self.position_bias {
+            Some(PositionBias::Alibi(alibi)) => {
+                Some(alibi.bias_at(offset, query_length, key_length, device)?)
+            }
+            Some(PositionBias::Relative(bias)) => {
+                Some(bias.bias_at(offset, query_length, key_length, device)?)
+            }
+            None => None,
+        })
+    }
+
+    /// The attention of `hidden_states` (sequence_length, hidden_dim) into `out`
+    /// (sequence_length, output_dim), or (sequence_length, num_heads * head_dim) without
+    /// an output projection. The optional `mask` is added to the scores before the
+    /// softmax (`-inf` to mask a position), broadcasted to (num_heads, sequence_length,
+    /// sequence_length) like [crate::traits::TensorAdd::broadcast_add].
+    pub fn forward(
+        &self,
+        hidden_states: &T,
+        mask: Option<&T>,
+        ctx: &mut AttentionContext<T>,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            ..
+        } = self.config;
+        match &self.qkv {
+            QkvProjection::Separate { .. } => {
+                self.forward_cross(hidden_states, hidden_states, mask, ctx, out)
+            }
+            QkvProjection::Fused(qkv) => {
+                qkv.forward(hidden_states, &mut ctx.projected)?;
+                let (key_offset, value_offset) =
+                    (num_heads * head_dim, (num_heads + num_kv_heads) * head_dim);
+                T::split_heads_at(&ctx.projected, 0, num_heads, &mut ctx.query)?;
+                T::split_heads_at(&ctx.projected, key_offset, num_kv_heads, &mut ctx.key)?;
+                T::split_heads_at(&ctx.projected, value_offset, num_kv_heads, &mut ctx.value)?;
+                self.attend(mask, ctx, out)
+            }
+        }
+    }
+
+    /// The attention of `hidden_states` (query_length, hidden_dim) to the keys and values
+    /// of `key_value_states` (key_length, kv_hidden_dim), like the encoder states of the
+    /// decoders of T5, BART or Whisper, with a context from
+    /// [MultiHeadAttention::cross_context]. The `mask` is broadcasted to (num_heads,
+    /// query_length, key_length). A fused projection can't attend to another sequence.
+    pub fn forward_cross(
+        &self,
+        hidden_states: &T,
+        key_value_states: &T,
+        mask: Option<&T>,
+        ctx: &mut AttentionContext<T>,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        let QkvProjection::Separate { query, key, value } = &self.qkv else {
+            return Err(SmeltError::InvalidConfig(
+                "a fused projection can't attend to another sequence".to_string(),
+            ));
+        };
+        query.forward(hidden_states, &mut ctx.projected)?;
+        T::split_heads(&ctx.projected, &mut ctx.query)?;
+        let kv_projected = ctx.kv_projected.as_mut().unwrap_or(&mut ctx.projected);
+        key.forward(key_value_states, kv_projected)?;
+        T::split_heads(kv_projected, &mut ctx.key)?;
+        value.forward(key_value_states, kv_projected)?;
+        T::split_heads(kv_projected, &mut ctx.value)?;
+        self.attend(mask, ctx, out)
+    }
+
+    /// The scaled dot-product of the heads of `ctx`, merged into `out`.
+    fn attend(
+        &self,
+        mask: Option<&T>,
+        ctx: &mut AttentionContext<T>,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        T::matmul_t(&ctx.query, &ctx.key, &mut ctx.scores)?;
+        T::mul_scalar(&mut ctx.scores, self.config.scale())?;
+        if let Some(bias) = &ctx.position_bias {
+            T::add(bias, &mut ctx.scores)?;
+        }
+        if let Some(mask) = &ctx.position_mask {
+            T::add(mask, &mut ctx.scores)?;
+        }
+        if let Some(mask) = mask {
+            T::broadcast_add(mask, &mut ctx.scores)?;
+        }
+        T::softmax(&mut ctx.scores)?;
+        self.dropout.forward(&mut ctx.scores)?;
+        T::matmul(&ctx.scores, &ctx.value, &mut ctx.heads)?;
+        self.merge(ctx, out)
+    }
+
+    /// Merges the heads of `ctx` into `out`, through the output projection if any.
+    fn merge(&self, ctx: &mut AttentionContext<T>, out: &mut T) -> Result<(), SmeltError> {
+        match &self.output {
+            Some(output) => {
+                T::unsplit_heads(&ctx.heads, &mut ctx.merged)?;
+                output.forward(&ctx.merged, out)
+            }
+            None => T::unsplit_heads(&ctx.heads, out),
+        }
+    }
+}
+
+impl<T> MultiHeadAttention<T>
+where
+    T: Tensor
+        + TensorOps<T>
+        + TensorHeads<T>
+        + TensorNarrow<T>
+        + TensorReshape<T>
+        + TensorPositionMask<T>,
+{
+    /// The attention of the new tokens `hidden_states` (query_length, hidden_dim) to
+    /// themselves and to the tokens cached for the layer `layer` of `cache`, which then
+    /// holds the keys and values of every token, or of the last `sliding_window` ones
+    /// so that long generations use a bounded memory. The queries being the last tokens, a
+    /// causal attention masks the new tokens after them. The new keys and values are
+    /// written in the buffers of the cache, whose scores are masked on the device. The
+    /// `mask` is broadcasted to (num_heads, query_length, capacity), the capacity of the
+    /// buffers of [KvCache::layer].
+    pub fn forward_cached(
+        &self,
+        hidden_states: &T,
+        mask: Option<&T>,
+        cache: &mut KvCache<T>,
+        layer: usize,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        self.cached(hidden_states, mask, cache, layer, out, |_, _, _| Ok(()))
+    }
+
+    /// The buffers of [MultiHeadAttention::forward_cached] for `query_length` new tokens
+    /// attending to the `capacity` tokens of the buffers of a [KvCache], the keys and
+    /// values being the ones of the new tokens.
+    fn cached_context(
+        &self,
+        query_length: usize,
+        capacity: usize,
+        device: &T::Device,
+    ) -> Result<AttentionContext<T>, SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            ..
+        } = self.config;
+        let projected_dim = match &self.qkv {
+            QkvProjection::Separate { .. } => num_heads * head_dim,
+            QkvProjection::Fused(_) => (num_heads + 2 * num_kv_heads) * head_dim,
+        };
+        let kv_projected = match &self.qkv {
+            QkvProjection::Separate { .. } if num_kv_heads != num_heads => {
+                Some(device.zeros(vec![query_length, num_kv_heads * head_dim])?)
+            }
+            _ => None,
+        };
+        let query_shape = vec![num_heads, query_length, head_dim];
+        let kv_shape = vec![num_kv_heads, query_length, head_dim];
+        Ok(AttentionContext {
+            projected: device.zeros(vec![query_length, projected_dim])?,
+            kv_projected,
+            query: device.zeros(query_shape.clone())?,
+            key: device.zeros(kv_shape.clone())?,
+            value: device.zeros(kv_shape)?,
+            scores: device.zeros(vec![num_heads, query_length, capacity])?,
+            heads: device.zeros(query_shape)?,
+            merged: device.zeros(vec![query_length, num_heads * head_dim])?,
+            position_bias: self
+                .position_bias
+                .as_ref()
+                .map(|_| device.zeros(vec![num_heads, query_length, capacity]))
+                .transpose()?,
+            position_mask: None,
+        })
+    }
+
+    /// [MultiHeadAttention::forward_cached], `rotate` being applied to the new queries
+    /// (num_heads, query_length, head_dim) and keys (num_kv_heads, query_length,
+    /// head_dim) at the position of the first new token (see [KvCache::position]),
+    /// before they are cached.
+    fn cached(
+        &self,
+        hidden_states: &T,
+        mask: Option<&T>,
+        cache: &mut KvCache<T>,
+        layer: usize,
+        out: &mut T,
+        rotate: impl FnOnce(&mut T, &mut T, usize) -> Result<(), SmeltError>,
+    ) -> Result<(), SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            sliding_window,
+            ..
+        } = self.config;
+        let (num_layers, reserved) = (cache.num_layers(), cache.reserved);
+        let Some(cached) = cache.layers.get_mut(layer) else {
+            return Err(SmeltError::InvalidConfig(format!(
+                "no layer {layer} in a cache of {num_layers} layers"
+            )));
+        };
+        let query_length = hidden_states.shape()[0];
+        let device = hidden_states.device();
+        let cached = match cached {
+            Some(cached) => cached,
+            None => cached.insert(CachedLayer::new(
+                num_kv_heads,
+                head_dim,
+                query_length,
+                reserved,
+                sliding_window,
+                device,
+            )?),
+        };
+        cached.make_room(query_length, reserved)?;
+        let capacity = cached.key.shape()[1];
+        let mut ctx = match cached.ctx.take() {
+            Some(ctx) if ctx.scores.shape() == [num_heads, query_length, capacity] => ctx,
+            _ => self.cached_context(query_length, capacity, device)?,
+        };
+
+        match &self.qkv {
+            QkvProjection::Separate { query, key, value } => {
+                query.forward(hidden_states, &mut ctx.projected)?;
+                T::split_heads(&ctx.projected, &mut ctx.query)?;
+                let kv_projected = ctx.kv_projected.as_mut().unwrap_or(&mut ctx.projected);
+                key.forward(hidden_states, kv_projected)?;
+                T::split_heads(kv_projected, &mut ctx.key)?;
+                value.forward(hidden_states, kv_projected)?;
+                T::split_heads(kv_projected, &mut ctx.value)?;
+            }
+            QkvProjection::Fused(qkv) => {
+                qkv.forward(hidden_states, &mut ctx.projected)?;
+                let (key_offset, value_offset) =
+                    (num_heads * head_dim, (num_heads + num_kv_heads) * head_dim);
+                T::split_heads_at(&ctx.projected, 0, num_heads, &mut ctx.query)?;
+                T::split_heads_at(&ctx.projected, key_offset, num_kv_heads, &mut ctx.key)?;
+                T::split_heads_at(&ctx.projected, value_offset, num_kv_heads, &mut ctx.value)?;
+            }
+        }
+        let offset = cached.length;
+        rotate(&mut ctx.query, &mut ctx.key, cached.dropped + offset)?;
+        T::narrow_put(&mut cached.key, 1, offset, &ctx.key)?;
+        T::narrow_put(&mut cached.value, 1, offset, &ctx.value)?;
+        cached.length += query_length;
+
+        let length = cached.length;
+        let bias = &mut cached.position_bias;
+        let mut ctx =
+            self.attend_shared(&cached.key, &cached.value, bias, mask, offset, length, ctx)?;
+        let result = self.merge(&mut ctx, out);
+        cached.ctx = Some(ctx);
+        result
+    }
+
+    /// [MultiHeadAttention::forward_cross] of the new tokens `hidden_states` (query_length,
+    /// hidden_dim) to `key_value_states` (key_length, kv_hidden_dim), like the encoder
+    /// states of the decoders of T5 or BART. The keys and values of `key_value_states` are
+    /// projected by the first call for the layer `layer` of `cache`, then reused by the
+    /// next ones until [KvCache::clear], which must attend to the same states. The `mask`
+    /// is broadcasted to (num_heads, query_length, key_length).
+    pub fn forward_cross_cached(
+        &self,
+        hidden_states: &T,
+        key_value_states: &T,
+        mask: Option<&T>,
+        cache: &mut KvCache<T>,
+        layer: usize,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            ..
+        } = self.config;
+        let QkvProjection::Separate { query, key, value } = &self.qkv else {
+            return Err(SmeltError::InvalidConfig(
+                "a fused projection can't attend to another sequence".to_string(),
+            ));
+        };
+        let num_layers = cache.num_layers();
+        let Some(cross) = cache.cross.get_mut(layer) else {
+            return Err(SmeltError::InvalidConfig(format!(
+                "no layer {layer} in a cache of {num_layers} layers"
+            )));
+        };
+        let device = hidden_states.device();
+        let cross = match cross {
+            Some(cross) => cross,
+            None => {
+                let key_length = key_value_states.shape()[0];
+                let mut projected = device.zeros(vec![key_length, num_kv_heads * head_dim])?;
+                let shape = vec![num_kv_heads, key_length, head_dim];
+                let (mut cross_key, mut cross_value) =
+                    (device.zeros(shape.clone())?, device.zeros(shape)?);
+                key.forward(key_value_states, &mut projected)?;
+                T::split_heads(&projected, &mut cross_key)?;
+                value.forward(key_value_states, &mut projected)?;
+                T::split_heads(&projected, &mut cross_value)?;
+                cross.insert(CachedLayer {
+                    key: cross_key,
+                    value: cross_value,
+                    length: key_length,
+                    dropped: 0,
+                    sliding_window: None,
+                    ctx: None,
+                    position_bias: None,
+                })
+            }
+        };
+        let (query_length, key_length) = (hidden_states.shape()[0], cross.length);
+        let mut ctx = match cross.ctx.take() {
+            Some(ctx) if ctx.scores.shape() == [num_heads, query_length, key_length] => ctx,
+            _ => self.cached_context(query_length, key_length, device)?,
+        };
+        query.forward(hidden_states, &mut ctx.projected)?;
+        T::split_heads(&ctx.projected, &mut ctx.query)?;
+        let offset = key_length.saturating_sub(query_length);
+        let (key, value, bias) = (&cross.key, &cross.value, &mut cross.position_bias);
+        let mut ctx = self.attend_shared(key, value, bias, mask, offset, key_length, ctx)?;
+        let result = self.merge(&mut ctx, out);
+        cross.ctx = Some(ctx);
+        result
+    }
+
+    /// The heads of the scaled dot-product of the queries of `ctx` with the `key` and
+    /// `value` heads (num_kv_heads, key_length, head_dim) shared by groups of queries. The
+    /// query `i` is at the position `offset + i` of the keys, which are masked from
+    /// `length` on. The position bias is sliced out of `table`, see
+    /// [MultiHeadAttention::slice_position_bias].
+    #[allow(clippy::too_many_arguments)]
+    fn attend_shared(
+        &self,
+        key: &T,
+        value: &T,
+        table: &mut Option<T>,
+        mask: Option<&T>,
+        offset: usize,
+        length: usize,
+        mut ctx: AttentionContext<T>,
+    ) -> Result<AttentionContext<T>, SmeltError> {
+        let AttentionConfig {
+            num_heads,
+            num_kv_heads,
+            head_dim,
+            causal,
+            sliding_window,
+            ..
+        } = self.config;
+        let (query_length, key_length) = (ctx.query.shape()[1], key.shape()[1]);
+        // The heads sharing a key and value head are consecutive, the queries (num_heads,
+        // query_length, head_dim) are (num_kv_heads, group * query_length, head_dim).
+        let group = num_heads / num_kv_heads;
+        let grouped = |dim| vec![num_kv_heads, group * query_length, dim];
+        let heads = |dim| vec![num_heads, query_length, dim];
+        let query = T::reshape(ctx.query, grouped(head_dim))?;
+        let mut scores = T::reshape(ctx.scores, grouped(key_length))?;
+        T::matmul_t(&query, key, &mut scores)?;
+        ctx.query = T::reshape(query, heads(head_dim))?;
+        ctx.scores = T::reshape(scores, heads(key_length))?;
+
+        T::mul_scalar(&mut ctx.scores, self.config.scale())?;
+        if let Some(bias) = &mut ctx.position_bias {
+            self.slice_position_bias(table, offset, bias)?;
+            T::add(bias, &mut ctx.scores)?;
+        }
+        if length < key_length || causal || sliding_window.is_some() {
+            T::position_mask(&mut ctx.scores, offset, length, causal, sliding_window)?;
+        }
+        if let Some(mask) = mask {
+            T::broadcast_add(mask, &mut ctx.scores)?;
+        }
+        T::softmax(&mut ctx.scores)?;
+        self.dropout.forward(&mut ctx.scores)?;
+
+        let scores = T::reshape(ctx.scores, grouped(key_length))?;
+        let mut merged = T::reshape(ctx.heads, grouped(head_dim))?;
+        T::matmul(&scores, value, &mut merged)?;
+        ctx.scores = T::reshape(scores, heads(key_length))?;
+        ctx.heads = T::reshape(merged, heads(head_dim))?;
+        Ok(ctx)
+    }
+
+    /// Writes into `bias` (num_heads, query_length, key_length) the position bias of the
+    /// query `i` at the position `offset + i` of the keys. Its rows are sliced on the
+    /// device out of `table`, the bias (num_heads, 1, 2 * n - 1) of the relative
+    /// positions `1 - n..n`, which is only built again when it gets too small for the
+    /// positions, so that the tokens generated one at a time don't upload their bias.
+    fn slice_position_bias(
+        &self,
+        table: &mut Option<T>,
+        offset: usize,
+        bias: &mut T,
+    ) -> Result<(), SmeltError> {
+        let (query_length, key_length) = (bias.shape()[1], bias.shape()[2]);
+        let needed = key_length.max(offset + query_length);
+        let table = match table {
+            Some(table) if table.shape()[2] >= 2 * needed - 1 => table,
+            _ => {
+                // The query at the position `needed - 1` sees the relative positions
+                // `1 - needed..needed`.
+                let built = self.position_bias(needed - 1, 1, 2 * needed - 1, bias.device())?;
+                let Some(built) = built else {
+                    return Ok(());
+                };
+                table.insert(built)
+            }
+        };
+        let n = table.shape()[2].div_ceil(2);
+        for i in 0..query_length {
+            let row = T::narrow(table, 2, n - 1 - (offset + i), key_length)?;
+            T::narrow_put(bias, 1, i, &row)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> MultiHeadAttention<T>
+where
+    T: Tensor
+        + TensorOps<T>
+        + TensorHeads<T>
+        + TensorNarrow<T>
+        + TensorReshape<T>
+        + TensorPositionMask<T>
+        + TensorRotary<T>,
+{
+    /// [MultiHeadAttention::forward_cached] with the queries and keys of the new tokens
+    /// rotated by `rotary` at their positions, like LLaMA. The cached keys are already
+    /// rotated.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Device, Tensor};
+    /// use smelte_rs::nn::layers::{
+    ///     AttentionConfig, KvCache, Linear, MultiHeadAttention, QkvProjection, RotaryEmbedding,
+    /// };
+    /// use smelte_rs::traits::Tensor as _;
+    ///
+    /// let linear = |out, dim| Linear::new(Tensor::zeros(vec![out, dim]), Tensor::zeros(vec![out]));
+    /// let config = AttentionConfig {
+    ///     causal: true,
+    ///     ..AttentionConfig::new(4, 8)
+    /// };
+    /// let qkv = QkvProjection::Fused(linear(3 * 32, 32));
+    /// let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+    /// let rotary = RotaryEmbedding::new(8, 64, Default::default(), &Device::new()).unwrap();
+    ///
+    /// let mut cache = KvCache::new(1);
+    /// let hidden_states = Tensor::zeros(vec![5, 32]);
+    /// let mut out = Tensor::zeros(vec![5, 32]);
+    /// attention
+    ///     .forward_rotary(&hidden_states, None, &rotary, &mut cache, 0, &mut out)
+    ///     .unwrap();
+    /// assert_eq!(cache.len(), 5);
+    /// ```
+    pub fn forward_rotary(
+        &self,
+        hidden_states: &T,
+        mask: Option<&T>,
+        rotary: &RotaryEmbedding<T>,
+        cache: &mut KvCache<T>,
+        layer: usize,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        self.cached(
+            hidden_states,
+            mask,
+            cache,
+            layer,
+            out,
+            |query, key, offset| {
+                rotary.forward(query, offset)?;
+                rotary.forward(key, offset)
+            },
+        )
+    }
+}
+
+/// The mask (num_heads, query_length, key_length) of the keys the queries don't attend
+/// to, `-inf` for the keys after the queries when `causal` and for the keys outside of
+/// the `sliding_window`. The queries are the last `query_length` keys.
+fn position_mask<D: Device>(
+    num_heads: usize,
+    query_length: usize,
+    key_length: usize,
+    causal: bool,
+    sliding_window: Option<usize>,
+    device: &D,
+) -> Result<D::Tensor, SmeltError> {
+    if query_length > key_length {
+        return Err(SmeltError::InvalidConfig(format!(
+            "{query_length} queries attend to {key_length} keys"
+        )));
+    }
+    let offset = key_length - query_length;
+    let mask: Vec<f32> = (offset..key_length)
+        .flat_map(|i| {
+            (0..key_length).map(move |j| {
+                let after = j > i && (causal || sliding_window.is_some_and(|w| j >= i + w));
+                let before = sliding_window.is_some_and(|w| i >= j + w);
+                if after || before {
+                    f32::NEG_INFINITY
+                } else {
+                    0.0
+                }
+            })
+        })
+        .collect();
+    device.tensor(
+        &mask.repeat(num_heads),
+        vec![num_heads, query_length, key_length],
+    )
+}
+
+impl<T: TensorToDevice> MultiHeadAttention<T> {
+    /// A copy of the attention with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            qkv: self.qkv.to_device(device)?,
+            output: self
+                .output
+                .as_ref()
+                .map(|output| output.to_device(device))
+                .transpose()?,
+            config: self.config.clone(),
+            position_bias: self.position_bias.clone(),
+            dropout: self.dropout,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> MultiHeadAttention<T> {
+    /// Quantizes the projections and the output projection, see
+    /// [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.qkv.quantize_dynamic()?;
+        match &mut self.output {
+            Some(output) => output.quantize_dynamic(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+
+    fn linear(weight: &Tensor, bias: &Tensor) -> Linear<Tensor> {
+        Linear::new(weight.clone(), bias.clone())
+    }
+
+    fn cat(tensors: &[&Tensor]) -> Tensor {
+        let data: Vec<f32> = tensors.iter().flat_map(|t| t.data().to_vec()).collect();
+        let mut shape = tensors[0].shape().to_vec();
+        shape[0] = tensors.iter().map(|t| t.shape()[0]).sum();
+        Tensor::new(data, shape).unwrap()
+    }
+
+    fn run(attention: &MultiHeadAttention<Tensor>, x: &Tensor, mask: Option<&Tensor>) -> Tensor {
+        let mut context = attention.context(x.shape()[0], &Device::new()).unwrap();
+        let mut out = Tensor::zeros(vec![x.shape()[0], 4]);
+        attention.forward(x, mask, &mut context, &mut out).unwrap();
+        out
+    }
+
+    fn assert_close(a: &Tensor, b: &Tensor) {
+        for (a, b) in a.data().iter().zip(b.data()) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_attention() {
+        let x = Tensor::rand_normal(vec![3, 4], 0);
+        let weight = |seed| Tensor::rand_normal(vec![4, 4], seed);
+        let (wq, wk, wv) = (weight(1), weight(2), weight(3));
+        let bias = Tensor::rand_normal(vec![4], 4);
+        let separate = QkvProjection::Separate {
+            query: linear(&wq, &bias),
+            key: linear(&wk, &bias),
+            value: linear(&wv, &bias),
+        };
+        let config = AttentionConfig::new(2, 2);
+        let attention = MultiHeadAttention::new(separate, None, config.clone()).unwrap();
+        let out = run(&attention, &x, None);
+
+        let fused = QkvProjection::Fused(linear(&cat(&[&wq, &wk, &wv]), &cat(&[&bias; 3])));
+        let fused = MultiHeadAttention::new(fused, None, config.clone()).unwrap();
+        assert_close(&run(&fused, &x, None), &out);
+
+        // A single key and value head is the same as repeating its weights to 2 heads.
+        let half = |w: &Tensor| Tensor::new(w.data()[..8].to_vec(), vec![2, 4]).unwrap();
+        let (wk, wv) = (half(&wk), half(&wv));
+        let half_bias = Tensor::new(bias.data()[..2].to_vec(), vec![2]).unwrap();
+        let mqa_config = AttentionConfig {
+            num_kv_heads: 1,
+            ..config.clone()
+        };
+        let mqa = QkvProjection::Separate {
+            query: linear(&wq, &bias),
+            key: linear(&wk, &half_bias),
+            value: linear(&wv, &half_bias),
+        };
+        let mqa = MultiHeadAttention::new(mqa, None, mqa_config.clone()).unwrap();
+        let repeated = QkvProjection::Separate {
+            query: linear(&wq, &bias),
+            key: linear(&cat(&[&wk, &wk]), &cat(&[&half_bias, &half_bias])),
+            value: linear(&cat(&[&wv, &wv]), &cat(&[&half_bias, &half_bias])),
+        };
+        let repeated = MultiHeadAttention::new(repeated, None, config.clone()).unwrap();
+        let out = run(&mqa, &x, None);
+        assert_close(&out, &run(&repeated, &x, None));
+        let fused = QkvProjection::Fused(linear(
+            &cat(&[&wq, &wk, &wv]),
+            &cat(&[&bias, &half_bias, &half_bias]),
+        ));
+        let fused = MultiHeadAttention::new(fused, None, mqa_config).unwrap();
+        assert_close(&run(&fused, &x, None), &out);
+
+        // Masking every key but the first one gives its value to every query.
+        let mask = Tensor::new(vec![0.0, f32::NEG_INFINITY, f32::NEG_INFINITY], vec![3]).unwrap();
+        let out = run(&repeated, &x, Some(&mask));
+        let mut values = Tensor::zeros(vec![3, 4]);
+        linear(&cat(&[&wv, &wv]), &cat(&[&half_bias, &half_bias]))
+            .forward(&x, &mut values)
+            .unwrap();
+        for row in out.data().chunks(4) {
+            assert_close(
+                &Tensor::new(row.to_vec(), vec![4]).unwrap(),
+                &values.clone(),
+            );
+        }
+
+        let invalid = QkvProjection::Fused(linear(&wq, &bias));
+        assert!(MultiHeadAttention::new(invalid, None, config).is_err());
+    }
+
+    #[test]
+    fn test_position_bias() {
+        let x = Tensor::rand_normal(vec![3, 4], 0);
+        let weight = |seed| Tensor::rand_normal(vec![4, 4], seed);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1), &Tensor::zeros(vec![4])),
+            key: linear(&weight(2), &Tensor::zeros(vec![4])),
+            value: linear(&weight(3), &Tensor::zeros(vec![4])),
+        };
+        let config = AttentionConfig {
+            scale: Some(1.0),
+            ..AttentionConfig::new(2, 2)
+        };
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+        let table = Tensor::rand_normal(vec![8, 2], 4).data().to_vec();
+        let bias = RelativePositionBias::new(table, 2, true, 16).unwrap();
+
+        // The bias is the same as an additive mask.
+        let mask = bias.bias(3, 3, &Device::new()).unwrap();
+        let expected = run(&attention, &x, Some(&mask));
+        let biased = attention.clone().with_relative_position_bias(bias);
+        assert_close(&run(&biased, &x, None), &expected);
+
+        let bias = RelativePositionBias::new(vec![0.0; 8], 1, true, 16).unwrap();
+        let invalid = attention.with_relative_position_bias(bias);
+        assert!(invalid.context(3, &Device::new()).is_err());
+    }
+
+    #[test]
+    fn test_causal_attention() {
+        let x = Tensor::rand_normal(vec![3, 4], 0);
+        let weight = |seed| Tensor::rand_normal(vec![4, 4], seed);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1), &Tensor::zeros(vec![4])),
+            key: linear(&weight(2), &Tensor::zeros(vec![4])),
+            value: linear(&weight(3), &Tensor::zeros(vec![4])),
+        };
+        let config = AttentionConfig::new(2, 2);
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+
+        // The same as the additive mask of the keys after the queries.
+        let inf = f32::NEG_INFINITY;
+        let mask = [0.0, inf, inf, 0.0, 0.0, inf, 0.0, 0.0, 0.0].repeat(2);
+        let mask = Tensor::new(mask, vec![2, 3, 3]).unwrap();
+        let expected = run(&attention, &x, Some(&mask));
+        let mut causal = attention.clone();
+        causal.config.causal = true;
+        assert_close(&run(&causal, &x, None), &expected);
+
+        // The first query only attends to itself.
+        let mut context = causal.context(3, &Device::new()).unwrap();
+        let mut out = Tensor::zeros(vec![3, 4]);
+        causal.forward(&x, None, &mut context, &mut out).unwrap();
+        assert_eq!(context.probs().data()[..3], [1.0, 0.0, 0.0]);
+        assert!(causal.cross_context(3, 2, &Device::new()).is_err());
+    }
+
+    #[test]
+    fn test_cached_attention() {
+        let x = Tensor::rand_normal(vec![4, 4], 0);
+        let weight = |seed, out| Tensor::rand_normal(vec![out, 4], seed);
+        let bias = |out| Tensor::zeros(vec![out]);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1, 4), &bias(4)),
+            key: linear(&weight(2, 2), &bias(2)),
+            value: linear(&weight(3, 2), &bias(2)),
+        };
+        let config = AttentionConfig {
+            num_kv_heads: 1,
+            causal: true,
+            ..AttentionConfig::new(2, 2)
+        };
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+        let expected = run(&attention, &x, None);
+
+        // The prompt of 3 tokens, then the last token, in buffers of 4 tokens.
+        let mut cache = KvCache::new(1);
+        cache.reserve(4);
+        let prompt = Tensor::new(x.data()[..12].to_vec(), vec![3, 4]).unwrap();
+        let mut out = Tensor::zeros(vec![3, 4]);
+        attention
+            .forward_cached(&prompt, None, &mut cache, 0, &mut out)
+            .unwrap();
+        assert_close(
+            &out,
+            &Tensor::new(expected.data()[..12].to_vec(), vec![3, 4]).unwrap(),
+        );
+        let token = Tensor::new(x.data()[12..].to_vec(), vec![1, 4]).unwrap();
+        let mut out = Tensor::zeros(vec![1, 4]);
+        attention
+            .forward_cached(&token, None, &mut cache, 0, &mut out)
+            .unwrap();
+        assert_close(
+            &out,
+            &Tensor::new(expected.data()[12..].to_vec(), vec![1, 4]).unwrap(),
+        );
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.layer(0).unwrap().0.shape(), [1, 4, 2]);
+
+        // A token at a time, the buffers growing from a single token.
+        let mut grown = KvCache::new(1);
+        for (i, row) in x.data().chunks(4).enumerate() {
+            let token = Tensor::new(row.to_vec(), vec![1, 4]).unwrap();
+            attention
+                .forward_cached(&token, None, &mut grown, 0, &mut out)
+                .unwrap();
+            let expected = &expected.data()[i * 4..(i + 1) * 4];
+            assert_close(&out, &Tensor::new(expected.to_vec(), vec![1, 4]).unwrap());
+        }
+        assert_eq!(grown.layer(0).unwrap().0.shape(), [1, 4, 2]);
+
+        assert!(attention
+            .forward_cached(&token, None, &mut cache, 1, &mut out)
+            .is_err());
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_cached_position_bias() {
+        let x = Tensor::rand_normal(vec![5, 4], 0);
+        let weight = |seed| Tensor::rand_normal(vec![4, 4], seed);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1), &Tensor::zeros(vec![4])),
+            key: linear(&weight(2), &Tensor::zeros(vec![4])),
+            value: linear(&weight(3), &Tensor::zeros(vec![4])),
+        };
+        let config = AttentionConfig {
+            causal: true,
+            ..AttentionConfig::new(2, 2)
+        };
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+        let table = Tensor::rand_normal(vec![8, 2], 4).data().to_vec();
+        let relative = RelativePositionBias::new(table, 2, false, 16).unwrap();
+        for biased in [
+            attention.clone().with_alibi(Alibi::new(2)),
+            attention.with_relative_position_bias(relative),
+        ] {
+            let expected = run(&biased, &x, None);
+            // The prompt of 2 tokens, then a token at a time, the buffers growing.
+            let mut cache = KvCache::new(1);
+            let prompt = Tensor::new(x.data()[..8].to_vec(), vec![2, 4]).unwrap();
+            let mut out = Tensor::zeros(vec![2, 4]);
+            biased
+                .forward_cached(&prompt, None, &mut cache, 0, &mut out)
+                .unwrap();
+            assert_close(
+                &out,
+                &Tensor::new(expected.data()[..8].to_vec(), vec![2, 4]).unwrap(),
+            );
+            let mut out = Tensor::zeros(vec![1, 4]);
+            for (i, row) in x.data().chunks(4).enumerate().skip(2) {
+                let token = Tensor::new(row.to_vec(), vec![1, 4]).unwrap();
+                biased
+                    .forward_cached(&token, None, &mut cache, 0, &mut out)
+                    .unwrap();
+                let expected = &expected.data()[i * 4..(i + 1) * 4];
+                assert_close(&out, &Tensor::new(expected.to_vec(), vec![1, 4]).unwrap());
+            }
+            assert_eq!(cache.layer(0).unwrap().0.shape(), [2, 8, 2]);
+        }
+    }
+
+    #[test]
+    fn test_rotary_attention() {
+        let x = Tensor::rand_normal(vec![4, 4], 0);
+        let weight = |seed, out| Tensor::rand_normal(vec![out, 4], seed);
+        let bias = |out| Tensor::zeros(vec![out]);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1, 4), &bias(4)),
+            key: linear(&weight(2, 2), &bias(2)),
+            value: linear(&weight(3, 2), &bias(2)),
+        };
+        let config = AttentionConfig {
+            num_kv_heads: 1,
+            causal: true,
+            ..AttentionConfig::new(2, 2)
+        };
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+        let rotary = RotaryEmbedding::new(2, 8, Default::default(), &Device::new()).unwrap();
+        let mut cache = KvCache::new(1);
+        let mut expected = Tensor::zeros(vec![4, 4]);
+        attention
+            .forward_rotary(&x, None, &rotary, &mut cache, 0, &mut expected)
+            .unwrap();
+        // The positions change the attention of the last token.
+        let last = Tensor::new(expected.data()[12..].to_vec(), vec![1, 4]).unwrap();
+        let unrotated = run(&attention, &x, None);
+        assert!(last
+            .data()
+            .iter()
+            .zip(&unrotated.data()[12..])
+            .any(|(a, b)| (a - b).abs() > 1e-3));
+
+        // The prompt of 3 tokens, then the last token at the position 3.
+        cache.clear();
+        let prompt = Tensor::new(x.data()[..12].to_vec(), vec![3, 4]).unwrap();
+        let mut out = Tensor::zeros(vec![3, 4]);
+        attention
+            .forward_rotary(&prompt, None, &rotary, &mut cache, 0, &mut out)
+            .unwrap();
+        let token = Tensor::new(x.data()[12..].to_vec(), vec![1, 4]).unwrap();
+        let mut out = Tensor::zeros(vec![1, 4]);
+        attention
+            .forward_rotary(&token, None, &rotary, &mut cache, 0, &mut out)
+            .unwrap();
+        assert_close(&out, &last);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let x = Tensor::rand_normal(vec![4, 4], 0);
+        let weight = |seed| Tensor::rand_normal(vec![4, 4], seed);
+        let qkv = QkvProjection::Separate {
+            query: linear(&weight(1), &Tensor::zeros(vec![4])),
+            key: linear(&weight(2), &Tensor::zeros(vec![4])),
+            value: linear(&weight(3), &Tensor::zeros(vec![4])),
+        };
+        let config = AttentionConfig {
+            causal: true,
+            sliding_window: Some(2),
+            ..AttentionConfig::new(2, 2)
+        };
+        let attention = MultiHeadAttention::new(qkv.clone(), None, config).unwrap();
+
+        // Every query attends to itself and to the key before it.
+        let inf = f32::NEG_INFINITY;
+        let mask = [
+            [0.0, inf, inf, inf],
+            [0.0, 0.0, inf, inf],
+            [inf, 0.0, 0.0, inf],
+            [inf, inf, 0.0, 0.0],
+        ]
+        .concat()
+        .repeat(2);
+        let mask = Tensor::new(mask, vec![2, 4, 4]).unwrap();
+        let full = MultiHeadAttention::new(qkv, None, AttentionConfig::new(2, 2)).unwrap();
+        let expected = run(&full, &x, Some(&mask));
+        assert_close(&run(&attention, &x, None), &expected);
+
+        // The cache rolls over the last 2 tokens.
+        let mut cache = KvCache::new(1);
+        for (i, row) in x.data().chunks(4).enumerate() {
+            let token = Tensor::new(row.to_vec(), vec![1, 4]).unwrap();
+            let mut out = Tensor::zeros(vec![1, 4]);
+            attention
+                .forward_cached(&token, None, &mut cache, 0, &mut out)
+                .unwrap();
+            let expected = &expected.data()[i * 4..(i + 1) * 4];
+            assert_close(&out, &Tensor::new(expected.to_vec(), vec![1, 4]).unwrap());
+            assert_eq!(cache.len(), (i + 1).min(2));
+            assert_eq!(cache.position(), i + 1);
+        }
+
+        // The rolled out tokens keep the positions of the rotary embedding.
+        let rotary = RotaryEmbedding::new(2, 8, Default::default(), &Device::new()).unwrap();
+        let mut expected = Tensor::zeros(vec![4, 4]);
+        attention
+            .forward_rotary(&x, None, &rotary, &mut KvCache::new(1), 0, &mut expected)
+            .unwrap();
+        cache.clear();
+        for (i, row) in x.data().chunks(4).enumerate() {
+            let token = Tensor::new(row.to_vec(), vec![1, 4]).unwrap();
+            let mut out = Tensor::zeros(vec![1, 4]);
+            attention
+                .forward_rotary(&token, None, &rotary, &mut cache, 0, &mut out)
+                .unwrap();
+            let expected = &expected.data()[i * 4..(i + 1) * 4];
+            assert_close(&out, &Tensor::new(expected.to_vec(), vec![1, 4]).unwrap());
+        }
+        assert_eq!(cache.position(), 4);
+    }
+
+    #[test]
+    fn test_cross_attention() {
+        // 2 queries attending to 5 encoder states of 6 items.
+        let x = Tensor::rand_normal(vec![2, 4], 0);
+        let encoder_states = Tensor::rand_normal(vec![5, 6], 1);
+        let bias = Tensor::rand_normal(vec![4], 2);
+        let wv = Tensor::rand_normal(vec![4, 6], 3);
+        let qkv = QkvProjection::Separate {
+            query: linear(&Tensor::rand_normal(vec![4, 4], 4), &bias),
+            key: linear(&Tensor::rand_normal(vec![4, 6], 5), &bias),
+            value: linear(&wv, &bias),
+        };
+        let attention = MultiHeadAttention::new(qkv, None, AttentionConfig::new(2, 2)).unwrap();
+        let mut context = attention.cross_context(2, 5, &Device::new()).unwrap();
+        let mut out = Tensor::zeros(vec![2, 4]);
+
+        // Masking every key but the fourth one gives its value to every query.
+        let mut mask = vec![f32::NEG_INFINITY; 5];
+        mask[3] = 0.0;
+        let mask = Tensor::new(mask, vec![5]).unwrap();
+        attention
+            .forward_cross(&x, &encoder_states, Some(&mask), &mut context, &mut out)
+            .unwrap();
+        assert_eq!(context.probs().shape(), [2, 2, 5]);
+        let mut values = Tensor::zeros(vec![5, 4]);
+        linear(&wv, &bias)
+            .forward(&encoder_states, &mut values)
+            .unwrap();
+        let expected = Tensor::new(values.data()[12..16].repeat(2), vec![2, 4]).unwrap();
+        assert_close(&out, &expected);
+
+        let fused = QkvProjection::Fused(linear(
+            &Tensor::zeros(vec![12, 4]),
+            &Tensor::zeros(vec![12]),
+        ));
+        let fused = MultiHeadAttention::new(fused, None, AttentionConfig::new(2, 2)).unwrap();
+        let mut context = fused.cross_context(2, 2, &Device::new()).unwrap();
+        assert!(fused
+            .forward_cross(&x, &x, None, &mut context, &mut out)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cross_attention_cached() {
+        let x = Tensor::rand_normal(vec![2, 4], 0);
+        let encoder_states = Tensor::rand_normal(vec![5, 6], 1);
+        let bias = Tensor::rand_normal(vec![4], 2);
+        let qkv = QkvProjection::Separate {
+            query: linear(&Tensor::rand_normal(vec![4, 4], 4), &bias),
+            key: linear(&Tensor::rand_normal(vec![4, 6], 5), &bias),
+            value: linear(&Tensor::rand_normal(vec![4, 6], 3), &bias),
+        };
+        let attention = MultiHeadAttention::new(qkv, None, AttentionConfig::new(2, 2)).unwrap();
+        let mut context = attention.cross_context(2, 5, &Device::new()).unwrap();
+        let mut expected = Tensor::zeros(vec![2, 4]);
+        attention
+            .forward_cross(&x, &encoder_states, None, &mut context, &mut expected)
+            .unwrap();
+
+        // The queries at once, then a token at a time from the projected states.
+        let mut cache = KvCache::new(1);
+        let mut out = Tensor::zeros(vec![2, 4]);
+        attention
+            .forward_cross_cached(&x, &encoder_states, None, &mut cache, 0, &mut out)
+            .unwrap();
+        assert_close(&out, &expected);
+        for i in 0..2 {
+            let token = Tensor::new(x.data()[i * 4..(i + 1) * 4].to_vec(), vec![1, 4]).unwrap();
+            let mut out = Tensor::zeros(vec![1, 4]);
+            attention
+                .forward_cross_cached(&token, &encoder_states, None, &mut cache, 0, &mut out)
+                .unwrap();
+            let expected = &expected.data()[i * 4..(i + 1) * 4];
+            assert_close(&out, &Tensor::new(expected.to_vec(), vec![1, 4]).unwrap());
+        }
+        // The cached states are the ones attended to, until the cache is cleared.
+        let other = Tensor::rand_normal(vec![5, 6], 6);
+        attention
+            .forward_cross_cached(&x, &other, None, &mut cache, 0, &mut out)
+            .unwrap();
+        assert_close(&out, &expected);
+        cache.clear();
+        attention
+            .forward_cross_cached(&x, &other, None, &mut cache, 0, &mut out)
+            .unwrap();
+        assert!(out.data() != expected.data());
+
+        assert!(attention
+            .forward_cross_cached(&x, &encoder_states, None, &mut cache, 1, &mut out)
+            .is_err());
+        let fused = QkvProjection::Fused(linear(
+            &Tensor::zeros(vec![12, 4]),
+            &Tensor::zeros(vec![12]),
+        ));
+        let fused = MultiHeadAttention::new(fused, None, AttentionConfig::new(2, 2)).unwrap();
+        assert!(fused
+            .forward_cross_cached(&x, &x, None, &mut cache, 0, &mut out)
+            .is_err());
+    }
+}
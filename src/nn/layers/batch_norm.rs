@@ -0,0 +1,197 @@
+use crate::traits::{Device, Tensor, TensorOps, TensorToDevice};
+use crate::SmeltError;
+
+/// The running statistics and affine parameters of a batch normalization, like the
+/// `running_mean`, `running_var`, `weight` and `bias` of a torch checkpoint. Without a
+/// weight or a bias, they are ones and zeros.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchNormStats {
+    /// The mean of every channel
+    pub running_mean: Vec<f32>,
+    /// The variance of every channel
+    pub running_var: Vec<f32>,
+    /// The scale of every channel, if any
+    pub weight: Option<Vec<f32>>,
+    /// The shift of every channel, if any
+    pub bias: Option<Vec<f32>>,
+    /// Added to the variance, 1e-5 in torch
+    pub eps: f32,
+}
+
+impl BatchNormStats {
+    /// The statistics of the channels, without affine parameters and with the `eps` of
+    /// torch.
+    pub fn new(running_mean: Vec<f32>, running_var: Vec<f32>) -> Self {
+        Self {
+            running_mean,
+            running_var,
+            weight: None,
+            bias: None,
+            eps: 1e-5,
+        }
+    }
+
+    /// The number of channels
+    pub fn num_features(&self) -> usize {
+        self.running_mean.len()
+    }
+
+    /// The normalization folded into a `(scale, shift)` per channel, `x * scale + shift`.
+    pub fn fold(&self) -> Result<(Vec<f32>, Vec<f32>), SmeltError> {
+        let num_features = self.num_features();
+        let lengths = [
+            Some(self.running_var.len()),
+            self.weight.as_ref().map(Vec::len),
+            self.bias.as_ref().map(Vec::len),
+        ];
+        if let Some(got) = lengths.into_iter().flatten().find(|&l| l != num_features) {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![num_features],
+                got: vec![got],
+            });
+        }
+        let scale: Vec<f32> = (0..num_features)
+            .map(|c| {
+                let weight = self.weight.as_ref().map_or(1.0, |w| w[c]);
+                weight / (self.running_var[c] + self.eps).sqrt()
+            })
+            .collect();
+        let shift = (0..num_features)
+            .map(|c| {
+                let bias = self.bias.as_ref().map_or(0.0, |b| b[c]);
+                bias - self.running_mean[c] * scale[c]
+            })
+            .collect();
+        Ok((scale, shift))
+    }
+}
+
+/// The scale and shift of the channels of a batch normalization, with trailing
+/// dimensions of 1 to broadcast them over the spatial dimensions of the inputs.
+#[derive(Clone)]
+struct ChannelAffine<T: Tensor> {
+    scale: T,
+    shift: T,
+}
+
+impl<T: Tensor + TensorOps<T>> ChannelAffine<T> {
+    fn new(
+        stats: &BatchNormStats,
+        spatial_dims: usize,
+        device: &T::Device,
+    ) -> Result<Self, SmeltError> {
+        let (scale, shift) = stats.fold()?;
+        let mut shape = vec![stats.num_features()];
+        shape.extend(std::iter::repeat_n(1, spatial_dims));
+        Ok(Self {
+            scale: device.tensor(&scale, shape.clone())?,
+            shift: device.tensor(&shift, shape)?,
+        })
+    }
+
+    fn forward(&self, x: &mut T, expected_rank: usize) -> Result<(), SmeltError> {
+        if x.shape().len() != expected_rank {
+            return Err(SmeltError::InvalidRank { expected_rank });
+        }
+        T::broadcast_mul(&self.scale, x)?;
+        T::broadcast_add(&self.shift, x)
+    }
+}
+
+impl<T: TensorToDevice> ChannelAffine<T> {
+    fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            scale: self.scale.to_device(device)?,
+            shift: self.shift.to_device(device)?,
+        })
+    }
+}
+
+/// Batch normalization of `(batch_size, num_features, length)` inputs in inference mode,
+/// with the running statistics of the checkpoint, like the audio front-ends.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::{BatchNorm1d, BatchNormStats};
+///
+/// let stats = BatchNormStats::new(vec![1.0, -1.0], vec![4.0, 1.0]);
+/// let norm = BatchNorm1d::<Tensor>::new(&stats, &Device::new()).unwrap();
+/// let mut x = Tensor::new(vec![3.0, 5.0, 0.0, 1.0], vec![1, 2, 2]).unwrap();
+/// norm.forward(&mut x).unwrap();
+/// let rounded: Vec<_> = x.data().iter().map(|v| (v * 1e3).round() / 1e3).collect();
+/// assert_eq!(rounded, [1.0, 2.0, 1.0, 2.0]);
+/// ```
+#[derive(Clone)]
+pub struct BatchNorm1d<T: Tensor>(ChannelAffine<T>);
+
+impl<T: Tensor + TensorOps<T>> BatchNorm1d<T> {
+    /// The normalization of the channels of `stats` on `device`.
+    pub fn new(stats: &BatchNormStats, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self(ChannelAffine::new(stats, 1, device)?))
+    }
+
+    /// Normalizes `x` (batch_size, num_features, length) in place.
+    pub fn forward(&self, x: &mut T) -> Result<(), SmeltError> {
+        self.0.forward(x, 3)
+    }
+}
+
+impl<T: TensorToDevice> BatchNorm1d<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self(self.0.to_device(device)?))
+    }
+}
+
+/// Batch normalization of `(batch_size, num_features, height, width)` inputs in
+/// inference mode, with the running statistics of the checkpoint, like the CNN backbones.
+/// It usually follows a [crate::nn::layers::Conv2d].
+#[derive(Clone)]
+pub struct BatchNorm2d<T: Tensor>(ChannelAffine<T>);
+
+impl<T: Tensor + TensorOps<T>> BatchNorm2d<T> {
+    /// The normalization of the channels of `stats` on `device`.
+    pub fn new(stats: &BatchNormStats, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self(ChannelAffine::new(stats, 2, device)?))
+    }
+
+    /// Normalizes `x` (batch_size, num_features, height, width) in place.
+    pub fn forward(&self, x: &mut T) -> Result<(), SmeltError> {
+        self.0.forward(x, 4)
+    }
+}
+
+impl<T: TensorToDevice> BatchNorm2d<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self(self.0.to_device(device)?))
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+
+    #[test]
+    fn test_batch_norm() {
+        let device = Device::new();
+        let mut stats = BatchNormStats::new(vec![1.0, 0.0], vec![3.0, 0.0]);
+        stats.weight = Some(vec![2.0, 1.0]);
+        stats.bias = Some(vec![0.5, -1.0]);
+        stats.eps = 1.0;
+        // scale = [1, 1], shift = [-0.5, -1]
+        assert_eq!(stats.fold().unwrap(), (vec![1.0, 1.0], vec![-0.5, -1.0]));
+
+        let norm = BatchNorm2d::<Tensor>::new(&stats, &device).unwrap();
+        let data: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let mut x = Tensor::new(data, vec![1, 2, 2, 2]).unwrap();
+        norm.forward(&mut x).unwrap();
+        assert_eq!(x.data(), [-0.5, 0.5, 1.5, 2.5, 3.0, 4.0, 5.0, 6.0]);
+        let mut x = Tensor::zeros(vec![1, 2, 2]);
+        assert!(norm.forward(&mut x).is_err());
+
+        stats.bias = Some(vec![0.0]);
+        assert!(BatchNorm1d::<Tensor>::new(&stats, &device).is_err());
+    }
+}
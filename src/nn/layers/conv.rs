@@ -0,0 +1,350 @@
+use crate::traits::{
+    Conv1dConfig, Conv2dConfig, Device, Tensor, TensorConv1d, TensorConv2d, TensorToDevice,
+};
+use crate::SmeltError;
+
+/// 1d convolution layer, like the feature extractors of wav2vec2 and Whisper.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::Conv1d;
+/// use smelte_rs::traits::Conv1dConfig;
+///
+/// // 80 mel bins to 384 channels, halving the length like Whisper.
+/// let weight = Tensor::zeros(vec![384, 80, 3]);
+/// let config = Conv1dConfig { stride: 2, padding: 1, ..Default::default() };
+/// let conv = Conv1d::new(weight, Some(Tensor::zeros(vec![384])), config);
+/// let mel = Tensor::zeros(vec![1, 80, 3000]);
+/// let shape = conv.output_shape(mel.shape()).unwrap();
+/// assert_eq!(shape, [1, 384, 1500]);
+/// let mut features = Tensor::zeros(shape);
+/// conv.forward(&mel, &mut features).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Conv1d<T: Tensor> {
+    weight: T,
+    bias: Option<T>,
+    config: Conv1dConfig,
+}
+
+impl<T: Tensor + TensorConv1d<T>> Conv1d<T> {
+    /// Conv1d layer creation, `weight` being (out_channels, in_channels / groups,
+    /// kernel_size).
+    pub fn new(weight: T, bias: Option<T>, config: Conv1dConfig) -> Self {
+        Self {
+            weight,
+            bias,
+            config,
+        }
+    }
+
+    /// Forward pass of `x` (batch_size, in_channels, length) into `out` (batch_size,
+    /// out_channels, output_length), see [Conv1d::output_shape].
+    pub fn forward(&self, x: &T, out: &mut T) -> Result<(), SmeltError> {
+        T::conv1d(x, &self.weight, self.bias.as_ref(), &self.config, out)
+    }
+
+    /// The shape of the output for an input of `shape`
+    pub fn output_shape(&self, shape: &[usize]) -> Result<Vec<usize>, SmeltError> {
+        let kernel_size = self.weight.shape()[2];
+        match shape {
+            &[batch_size, _, length] => {
+                let length = self
+                    .config
+                    .output_length(length, kernel_size)
+                    .ok_or_else(|| {
+                        SmeltError::InvalidConfig(format!("kernel longer than {shape:?}"))
+                    })?;
+                Ok(vec![batch_size, self.weight.shape()[0], length])
+            }
+            _ => Err(SmeltError::InvalidRank { expected_rank: 3 }),
+        }
+    }
+
+    /// The weight (out_channels, in_channels / groups, kernel_size)
+    pub fn weight(&self) -> &T {
+        &self.weight
+    }
+
+    /// The bias (out_channels)
+    pub fn bias(&self) -> Option<&T> {
+        self.bias.as_ref()
+    }
+
+    /// The stride, padding, dilation and groups of the layer
+    pub fn config(&self) -> &Conv1dConfig {
+        &self.config
+    }
+}
+
+impl<T: TensorToDevice> Conv1d<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            weight: self.weight.to_device(device)?,
+            bias: self
+                .bias
+                .as_ref()
+                .map(|b| b.to_device(device))
+                .transpose()?,
+            config: self.config,
+        })
+    }
+}
+
+/// 2d convolution layer, like the patch embeddings of ViT.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::Conv2d;
+/// use smelte_rs::traits::Conv2dConfig;
+///
+/// // 16x16 patches of a 224x224 image into 768 channels.
+/// let weight = Tensor::zeros(vec![768, 3, 16, 16]);
+/// let config = Conv2dConfig { stride: [16, 16], ..Default::default() };
+/// let conv = Conv2d::new(weight, Some(Tensor::zeros(vec![768])), config);
+/// let image = Tensor::zeros(vec![1, 3, 224, 224]);
+/// let shape = conv.output_shape(image.shape()).unwrap();
+/// assert_eq!(shape, [1, 768, 14, 14]);
+/// let mut patches = Tensor::zeros(shape);
+/// conv.forward(&image, &mut patches).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Conv2d<T: Tensor> {
+    weight: T,
+    bias: Option<T>,
+    config: Conv2dConfig,
+}
+
+impl<T: Tensor + TensorConv2d<T>> Conv2d<T> {
+    /// Conv2d layer creation, `weight` being (out_channels, in_channels / groups,
+    /// kernel_height, kernel_width).
+    pub fn new(weight: T, bias: Option<T>, config: Conv2dConfig) -> Self {
+        Self {
+            weight,
+            bias,
+            config,
+        }
+    }
+
+    /// Depthwise Conv2d layer creation, every channel convolved by its own kernel,
+    /// `weight` being (channels, 1, kernel_height, kernel_width). The groups of `config`
+    /// are set to the number of channels.
+    pub fn depthwise(weight: T, bias: Option<T>, config: Conv2dConfig) -> Self {
+        let groups = weight.shape()[0];
+        Self::new(weight, bias, Conv2dConfig { groups, ..config })
+    }
+
+    /// Forward pass of `x` (batch_size, in_channels, height, width) into `out`
+    /// (batch_size, out_channels, output_height, output_width), see
+    /// [Conv2d::output_shape].
+    pub fn forward(&self, x: &T, out: &mut T) -> Result<(), SmeltError> {
+        T::conv2d(x, &self.weight, self.bias.as_ref(), &self.config, out)
+    }
+
+    /// The shape of the output for an input of `shape`
+    pub fn output_shape(&self, shape: &[usize]) -> Result<Vec<usize>, SmeltError> {
+        let kernel_size = [self.weight.shape()[2], self.weight.shape()[3]];
+        match shape {
+            &[batch_size, _, height, width] => {
+                let [height, width] = self
+                    .config
+                    .output_size([height, width], kernel_size)
+                    .ok_or_else(|| {
+                        SmeltError::InvalidConfig(format!("kernel larger than {shape:?}"))
+                    })?;
+                Ok(vec![batch_size, self.weight.shape()[0], height, width])
+            }
+            _ => Err(SmeltError::InvalidRank { expected_rank: 4 }),
+        }
+    }
+
+    /// The weight (out_channels, in_channels / groups, kernel_height, kernel_width)
+    pub fn weight(&self) -> &T {
+        &self.weight
+    }
+
+    /// The bias (out_channels)
+    pub fn bias(&self) -> Option<&T> {
+        self.bias.as_ref()
+    }
+
+    /// The stride, padding, dilation and groups of the layer
+    pub fn config(&self) -> &Conv2dConfig {
+        &self.config
+    }
+}
+
+impl<T: TensorToDevice> Conv2d<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            weight: self.weight.to_device(device)?,
+            bias: self
+                .bias
+                .as_ref()
+                .map(|b| b.to_device(device))
+                .transpose()?,
+            config: self.config,
+        })
+    }
+}
+
+/// Depthwise separable 2d convolution, a depthwise [Conv2d] (see [Conv2d::depthwise])
+/// followed by a 1x1 [Conv2d] mixing the channels, like MobileNet and the lightweight
+/// vision backbones.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::{Conv2d, SeparableConv2d};
+/// use smelte_rs::traits::Conv2dConfig;
+///
+/// // 3x3 kernels over 32 channels, then 32 to 64 channels.
+/// let config = Conv2dConfig { padding: [1, 1], ..Default::default() };
+/// let depthwise = Conv2d::depthwise(Tensor::zeros(vec![32, 1, 3, 3]), None, config);
+/// let pointwise = Conv2d::new(Tensor::zeros(vec![64, 32, 1, 1]), None, Default::default());
+/// let conv = SeparableConv2d::new(depthwise, pointwise).unwrap();
+/// let x = Tensor::zeros(vec![1, 32, 56, 56]);
+/// let shape = conv.output_shape(x.shape()).unwrap();
+/// assert_eq!(shape, [1, 64, 56, 56]);
+/// let mut out = Tensor::zeros(shape);
+/// conv.forward(&x, &mut out).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SeparableConv2d<T: Tensor> {
+    depthwise: Conv2d<T>,
+    pointwise: Conv2d<T>,
+}
+
+impl<T: Tensor + TensorConv2d<T>> SeparableConv2d<T> {
+    /// SeparableConv2d layer creation, `depthwise` having one input channel per group
+    /// and `pointwise` 1x1 kernels over the channels of `depthwise`.
+    pub fn new(depthwise: Conv2d<T>, pointwise: Conv2d<T>) -> Result<Self, SmeltError> {
+        let weight = depthwise.weight().shape();
+        if weight[1] != 1 || depthwise.config().groups == 0 {
+            return Err(SmeltError::InvalidConfig(format!(
+                "depthwise kernels of shape {weight:?} in {} groups",
+                depthwise.config().groups
+            )));
+        }
+        let channels = weight[0];
+        let weight = pointwise.weight().shape();
+        if weight[2..] != [1, 1] || weight[1] * pointwise.config().groups != channels {
+            return Err(SmeltError::InvalidConfig(format!(
+                "pointwise kernels of shape {weight:?} after {channels} channels"
+            )));
+        }
+        Ok(Self {
+            depthwise,
+            pointwise,
+        })
+    }
+
+    /// Forward pass of `x` (batch_size, in_channels, height, width) into `out`
+    /// (batch_size, out_channels, output_height, output_width), see
+    /// [SeparableConv2d::output_shape].
+    pub fn forward(&self, x: &T, out: &mut T) -> Result<(), SmeltError> {
+        let shape = self.depthwise.output_shape(x.shape())?;
+        let mut hidden = x.device().zeros(shape)?;
+        self.depthwise.forward(x, &mut hidden)?;
+        self.pointwise.forward(&hidden, out)
+    }
+
+    /// The shape of the output for an input of `shape`
+    pub fn output_shape(&self, shape: &[usize]) -> Result<Vec<usize>, SmeltError> {
+        self.pointwise
+            .output_shape(&self.depthwise.output_shape(shape)?)
+    }
+
+    /// The depthwise convolution
+    pub fn depthwise(&self) -> &Conv2d<T> {
+        &self.depthwise
+    }
+
+    /// The 1x1 convolution
+    pub fn pointwise(&self) -> &Conv2d<T> {
+        &self.pointwise
+    }
+}
+
+impl<T: TensorToDevice> SeparableConv2d<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            depthwise: self.depthwise.to_device(device)?,
+            pointwise: self.pointwise.to_device(device)?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+
+    #[test]
+    fn test_conv1d() {
+        // A moving sum over 2 items.
+        let conv = Conv1d::new(
+            Tensor::new(vec![1.0, 1.0], vec![1, 1, 2]).unwrap(),
+            None,
+            Conv1dConfig::default(),
+        );
+        let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![1, 1, 4]).unwrap();
+        let mut out = Tensor::zeros(conv.output_shape(x.shape()).unwrap());
+        conv.forward(&x, &mut out).unwrap();
+        assert_eq!(out.data(), [3.0, 5.0, 7.0]);
+        assert!(conv.output_shape(&[1, 4]).is_err());
+        assert!(conv.output_shape(&[1, 1, 1]).is_err());
+    }
+    #[test]
+    fn test_conv2d() {
+        // The sums of the 2x2 patches, with one of padding on the right and bottom.
+        let conv = Conv2d::new(
+            Tensor::new(vec![1.0; 4], vec![1, 1, 2, 2]).unwrap(),
+            Some(Tensor::new(vec![1.0], vec![1]).unwrap()),
+            Conv2dConfig {
+                stride: [2, 2],
+                padding: [0, 1],
+                ..Default::default()
+            },
+        );
+        let x = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![1, 1, 2, 3]).unwrap();
+        let shape = conv.output_shape(x.shape()).unwrap();
+        assert_eq!(shape, [1, 1, 1, 2]);
+        let mut out = Tensor::zeros(shape);
+        conv.forward(&x, &mut out).unwrap();
+        assert_eq!(out.data(), [6.0, 17.0]);
+        assert!(conv.output_shape(&[1, 1, 4]).is_err());
+        assert!(conv.output_shape(&[1, 1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_separable_conv2d() {
+        // The moving sums of 2 items along the width of both channels, then their
+        // difference.
+        let depthwise = Conv2d::depthwise(
+            Tensor::new(vec![1.0; 4], vec![2, 1, 1, 2]).unwrap(),
+            Some(Tensor::new(vec![0.0, 1.0], vec![2]).unwrap()),
+            Conv2dConfig::default(),
+        );
+        assert_eq!(depthwise.config().groups, 2);
+        let pointwise = Conv2d::new(
+            Tensor::new(vec![1.0, -1.0], vec![1, 2, 1, 1]).unwrap(),
+            None,
+            Conv2dConfig::default(),
+        );
+        let conv = SeparableConv2d::new(depthwise, pointwise).unwrap();
+        let x = Tensor::new(vec![1.0, 2.0, 3.0, 0.0, 4.0, 8.0], vec![1, 2, 1, 3]).unwrap();
+        let shape = conv.output_shape(x.shape()).unwrap();
+        assert_eq!(shape, [1, 1, 1, 2]);
+        let mut out = Tensor::zeros(shape);
+        conv.forward(&x, &mut out).unwrap();
+        assert_eq!(out.data(), [-2.0, -8.0]);
+
+        let pointwise = |shape| Conv2d::new(Tensor::zeros(shape), None, Default::default());
+        let depthwise = conv.depthwise().clone();
+        assert!(SeparableConv2d::new(depthwise.clone(), pointwise(vec![1, 3, 1, 1])).is_err());
+        assert!(SeparableConv2d::new(depthwise, pointwise(vec![1, 2, 2, 1])).is_err());
+        let dense = Conv2d::new(Tensor::zeros(vec![2, 2, 1, 2]), None, Default::default());
+        assert!(SeparableConv2d::new(dense, pointwise(vec![1, 2, 1, 1])).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::traits::{Device, Tensor, TensorOps};
+use crate::SmeltError;
+
+/// Dropout of the items with a probability `p`, the kept ones being scaled by
+/// `1 / (1 - p)` like torch. The layer starts in eval mode, where it is the identity,
+/// like the models, which switch all of their dropouts with their `set_training`. In
+/// train mode every call draws a new mask from the crate seed, see
+/// [crate::random::manual_seed].
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::Dropout;
+///
+/// let mut dropout = Dropout::new(0.1).unwrap();
+/// let mut x = Tensor::new(vec![1.0; 4], vec![2, 2]).unwrap();
+/// dropout.forward(&mut x).unwrap();
+/// assert_eq!(x.data(), [1.0; 4]);
+///
+/// dropout.set_training(true);
+/// dropout.forward(&mut x).unwrap();
+/// assert!(x.data().iter().all(|&v| v == 0.0 || v == 1.0 / 0.9));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dropout {
+    p: f32,
+    training: bool,
+}
+
+impl Default for Dropout {
+    /// The dropout of a probability 0, the identity in both modes, for the layers of
+    /// the models without dropout.
+    fn default() -> Self {
+        Self {
+            p: 0.0,
+            training: false,
+        }
+    }
+}
+
+impl Dropout {
+    /// The dropout of a probability `p` in `[0, 1]`, in eval mode.
+    pub fn new(p: f32) -> Result<Self, SmeltError> {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(SmeltError::InvalidConfig(format!(
+                "a dropout probability of {p}"
+            )));
+        }
+        Ok(Self { p, training: false })
+    }
+
+    /// The probability of dropping an item
+    pub fn p(&self) -> f32 {
+        self.p
+    }
+
+    /// Switches between the train mode, `true`, and the eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    /// Whether the layer is in train mode
+    pub fn is_training(&self) -> bool {
+        self.training
+    }
+
+    /// Whether the layer drops items, in train mode with a positive probability
+    pub fn is_active(&self) -> bool {
+        self.training && self.p > 0.0
+    }
+
+    /// Drops the items of `x` in place in train mode, the mask being built on the host.
+    pub fn forward<T: Tensor + TensorOps<T>>(&self, x: &mut T) -> Result<(), SmeltError> {
+        if !self.is_active() {
+            return Ok(());
+        }
+        let mut mask = vec![0.0; x.shape().iter().product()];
+        crate::random::fill_uniform(&mut mask, crate::random::next_seed());
+        let scale = if self.p < 1.0 {
+            1.0 / (1.0 - self.p)
+        } else {
+            0.0
+        };
+        mask.iter_mut()
+            .for_each(|v| *v = if *v < self.p { 0.0 } else { scale });
+        let mask = x.device().tensor(&mask, x.shape().to_vec())?;
+        T::mul(&mask, x)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+
+    #[test]
+    fn test_dropout() {
+        let mut dropout = Dropout::new(0.25).unwrap();
+        dropout.set_training(true);
+        let mut x = Tensor::new(vec![1.0; 10_000], vec![100, 100]).unwrap();
+        dropout.forward(&mut x).unwrap();
+        let dropped = x.data().iter().filter(|&&v| v == 0.0).count();
+        assert!((dropped as f32 / 10_000.0 - 0.25).abs() < 0.02);
+        let mean = x.data().iter().sum::<f32>() / 10_000.0;
+        assert!((mean - 1.0).abs() < 0.05);
+
+        let mut x = Tensor::new(vec![1.0; 4], vec![4]).unwrap();
+        Dropout::new(1.0).unwrap().forward(&mut x).unwrap();
+        assert_eq!(x.data(), [1.0; 4]);
+        let mut all = Dropout::new(1.0).unwrap();
+        all.set_training(true);
+        all.forward(&mut x).unwrap();
+        assert_eq!(x.data(), [0.0; 4]);
+        assert!(Dropout::new(1.5).is_err());
+        assert!(Dropout::new(f32::NAN).is_err());
+    }
+}
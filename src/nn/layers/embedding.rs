@@ -1,27 +1,82 @@
-use crate::traits::{Tensor, TensorOps};
+use crate::traits::{Device, Tensor, TensorOps};
 use crate::SmeltError;
+use std::sync::Arc;
 
 /// TODO
 #[derive(Clone)]
 pub struct Embedding<T: Tensor> {
-    weight: T,
+    weight: Arc<T>,
+    padding_idx: Option<usize>,
 }
 
 impl<T: Tensor + TensorOps<T>> Embedding<T> {
     /// TODO
     pub fn new(weight: T) -> Self {
-        Self { weight }
+        Self {
+            weight: Arc::new(weight),
+            padding_idx: None,
+        }
     }
 
-    /// TODO
+    /// The embedding of the id `padding_idx` is zeros, whatever its row of the weight,
+    /// like the `padding_idx` of torch.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    /// use smelte_rs::nn::layers::Embedding;
+    ///
+    /// let weight = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let embedding = Embedding::new(weight).with_padding_idx(0);
+    /// let mut out = Tensor::zeros(vec![3, 2]);
+    /// embedding.forward(&[1, 0, 1], &mut out).unwrap();
+    /// assert_eq!(out.data(), [3.0, 4.0, 0.0, 0.0, 3.0, 4.0]);
+    /// ```
+    pub fn with_padding_idx(mut self, padding_idx: usize) -> Self {
+        self.padding_idx = Some(padding_idx);
+        self
+    }
+
+    /// The id of the padding, if any
+    pub fn padding_idx(&self) -> Option<usize> {
+        self.padding_idx
+    }
+
+    /// Gathers the rows `ids` of the weight into `out` (ids.len(), hidden_dim). Fails
+    /// with [SmeltError::OutOfVocabulary] before writing anything when an id is not a
+    /// row of the weight.
     pub fn forward(&self, ids: &[usize], out: &mut T) -> Result<(), SmeltError> {
-        T::select(ids, &self.weight, out)
+        let vocab_size = self.weight.shape()[0];
+        if let Some(&id) = ids.iter().find(|&&id| id >= vocab_size) {
+            return Err(SmeltError::OutOfVocabulary { vocab_size, id });
+        }
+        T::gather(ids, &self.weight, out)?;
+        let Some(padding_idx) = self.padding_idx else {
+            return Ok(());
+        };
+        if !ids.contains(&padding_idx) {
+            return Ok(());
+        }
+        let hidden_dim = self.weight.shape()[1];
+        let mask: Vec<f32> = ids
+            .iter()
+            .flat_map(|&id| {
+                std::iter::repeat_n(if id == padding_idx { 0.0 } else { 1.0 }, hidden_dim)
+            })
+            .collect();
+        let mask = self.weight.device().tensor(&mask, out.shape().to_vec())?;
+        T::mul(&mask, out)
     }
 
     /// TODO
     pub fn weight(&self) -> &T {
         &self.weight
     }
+
+    /// The weight, shared with the layers tied to the embedding like [Linear::tied].
+    ///
+    /// [Linear::tied]: crate::nn::layers::Linear::tied
+    pub fn shared_weight(&self) -> &Arc<T> {
+        &self.weight
+    }
 }
 
 #[cfg(test)]
@@ -44,5 +99,20 @@ mod tests {
         let embedding = Embedding::new(weights);
         let mut out = Tensor::zeros(vec![2, 2]);
         assert!(embedding.forward(&[3], &mut out).is_err());
+
+        let weights = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], vec![3, 2]).unwrap();
+        let embedding = Embedding::new(weights).with_padding_idx(2);
+        let mut out = Tensor::zeros(vec![2, 2]);
+        assert!(matches!(
+            embedding.forward(&[0, 7], &mut out),
+            Err(SmeltError::OutOfVocabulary {
+                vocab_size: 3,
+                id: 7
+            })
+        ));
+        // Nothing was written.
+        assert_eq!(out.data(), [0.0; 4]);
+        embedding.forward(&[2, 1], &mut out).unwrap();
+        assert_eq!(out.data(), [0.0, 0.0, 3.0, 4.0]);
     }
 }
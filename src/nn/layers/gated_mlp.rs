@@ -0,0 +1,170 @@
+use crate::nn::layers::{Dropout, Linear};
+use crate::traits::{Activation, Device, Tensor, TensorOps, TensorQuantize, TensorToDevice};
+use crate::SmeltError;
+
+/// The buffers (sequence_length, intermediate_dim) of a [GatedMlp], see
+/// [GatedMlp::context].
+pub struct GatedMlpContext<T: Tensor> {
+    gate: T,
+    up: T,
+}
+
+/// Gated feed-forward layer, `down(activation(gate(x)) * up(x))`: SwiGLU with
+/// [Activation::Silu] (LLaMA), GEGLU with [Activation::GeluNew] (T5 v1.1, Gemma). The
+/// projections of these models have no bias, they are given zero biases.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::{GatedMlp, Linear};
+/// use smelte_rs::traits::{Activation, Tensor as _};
+///
+/// let linear = |out, dim| Linear::new(Tensor::zeros(vec![out, dim]), Tensor::zeros(vec![out]));
+/// let mlp = GatedMlp::new(linear(64, 16), linear(64, 16), linear(16, 64), Activation::Silu)
+///     .unwrap();
+///
+/// let x = Tensor::zeros(vec![3, 16]);
+/// let mut context = mlp.context(3, &Device::new()).unwrap();
+/// let mut out = Tensor::zeros(vec![3, 16]);
+/// mlp.forward(&x, &mut context, &mut out).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct GatedMlp<T: Tensor> {
+    gate: Linear<T>,
+    up: Linear<T>,
+    down: Linear<T>,
+    activation: Activation,
+    // The dropout of the gated states, before `down`
+    dropout: Dropout,
+}
+
+impl<T: Tensor + TensorOps<T>> GatedMlp<T> {
+    /// The layer of the projections `gate` and `up` (intermediate_dim, hidden_dim) and
+    /// `down` (hidden_dim, intermediate_dim).
+    pub fn new(
+        gate: Linear<T>,
+        up: Linear<T>,
+        down: Linear<T>,
+        activation: Activation,
+    ) -> Result<Self, SmeltError> {
+        if up.shape() != gate.shape() {
+            return Err(SmeltError::DimensionMismatch {
+                expected: gate.shape().to_vec(),
+                got: up.shape().to_vec(),
+            });
+        }
+        let expected = vec![gate.shape()[1], gate.shape()[0]];
+        if down.shape() != expected {
+            return Err(SmeltError::DimensionMismatch {
+                expected,
+                got: down.shape().to_vec(),
+            });
+        }
+        Ok(Self {
+            gate,
+            up,
+            down,
+            activation,
+            dropout: Dropout::default(),
+        })
+    }
+
+    /// Drops the gated states with `dropout` in train mode, before the down projection
+    /// like T5.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// The activation of the gate
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+
+    /// The buffers of the layer for `sequence_length` tokens on `device`.
+    pub fn context(
+        &self,
+        sequence_length: usize,
+        device: &T::Device,
+    ) -> Result<GatedMlpContext<T>, SmeltError> {
+        let shape = vec![sequence_length, self.gate.shape()[0]];
+        Ok(GatedMlpContext {
+            gate: device.zeros(shape.clone())?,
+            up: device.zeros(shape)?,
+        })
+    }
+
+    /// The layer of `x` (sequence_length, hidden_dim) into `out` (sequence_length,
+    /// hidden_dim).
+    pub fn forward(
+        &self,
+        x: &T,
+        ctx: &mut GatedMlpContext<T>,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        self.gate.forward(x, &mut ctx.gate)?;
+        T::activation(&mut ctx.gate, self.activation)?;
+        self.up.forward(x, &mut ctx.up)?;
+        T::mul(&ctx.up, &mut ctx.gate)?;
+        self.dropout.forward(&mut ctx.gate)?;
+        self.down.forward(&ctx.gate, out)
+    }
+}
+
+impl<T: TensorToDevice> GatedMlp<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            gate: self.gate.to_device(device)?,
+            up: self.up.to_device(device)?,
+            down: self.down.to_device(device)?,
+            activation: self.activation,
+            dropout: self.dropout,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> GatedMlp<T> {
+    /// Quantizes the 3 projections, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.gate.quantize_dynamic()?;
+        self.up.quantize_dynamic()?;
+        self.down.quantize_dynamic()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+
+    fn linear(data: Vec<f32>, shape: Vec<usize>) -> Linear<Tensor> {
+        let bias = Tensor::zeros(vec![shape[0]]);
+        Linear::new(Tensor::new(data, shape).unwrap(), bias)
+    }
+
+    #[test]
+    fn test_gated_mlp() {
+        let x = Tensor::new(vec![1.0, -2.0], vec![1, 2]).unwrap();
+        // gate(x) = [1, -2, -1], up(x) = [2, 4, 1]
+        let gate = linear(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], vec![3, 2]);
+        let up = linear(vec![2.0, 0.0, 0.0, -2.0, -1.0, -1.0], vec![3, 2]);
+        let down = linear(vec![1.0, 1.0, 1.0, 0.0, 0.0, 1.0], vec![2, 3]);
+        let mlp = GatedMlp::new(gate, up, down, Activation::Relu).unwrap();
+
+        let mut context = mlp.context(1, &Device::new()).unwrap();
+        let mut out = Tensor::zeros(vec![1, 2]);
+        mlp.forward(&x, &mut context, &mut out).unwrap();
+        // relu(gate(x)) * up(x) = [2, 0, 0]
+        assert_eq!(out.data(), [2.0, 0.0]);
+
+        let gate = linear(vec![0.0; 6], vec![3, 2]);
+        let up = linear(vec![0.0; 4], vec![2, 2]);
+        let down = linear(vec![0.0; 6], vec![2, 3]);
+        assert!(GatedMlp::new(gate, up, down, Activation::Silu).is_err());
+    }
+}
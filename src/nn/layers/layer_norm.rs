@@ -1,4 +1,4 @@
-use crate::traits::{Tensor, TensorOps};
+use crate::traits::{Tensor, TensorOps, TensorToDevice};
 use crate::SmeltError;
 
 /// TODO
@@ -21,10 +21,18 @@ impl<T: Tensor + TensorOps<T>> LayerNorm<T> {
 
     /// TODO
     pub fn forward(&self, tensor: &mut T) -> Result<(), SmeltError> {
-        T::normalize(tensor, self.epsilon)?;
-        T::broadcast_mul(&self.weight, tensor)?;
-        T::broadcast_add(&self.bias, tensor)?;
-        Ok(())
+        T::layer_norm(tensor, &self.weight, &self.bias, self.epsilon)
+    }
+}
+
+impl<T: TensorToDevice> LayerNorm<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            weight: self.weight.to_device(device)?,
+            bias: self.bias.to_device(device)?,
+            epsilon: self.epsilon,
+        })
     }
 }
 
@@ -1,35 +1,291 @@
-use crate::traits::{Tensor, TensorOps};
+use crate::nn::layers::{Embedding, LoraLinear};
+use crate::traits::{
+    Precision, QuantizedWeight, RangeObserver, Tensor, TensorOps, TensorPrecision, TensorQuantize,
+    TensorToDevice,
+};
 use crate::SmeltError;
+use std::sync::Arc;
 
-/// Linear layer, applies matmul(x, W.T) + b
+/// The weight of a [Linear], replaced by its quantized version in
+/// [Linear::quantize_dynamic] or [Linear::quantize_static], or quantized from the start
+/// with [Linear::quantized].
+/// The dense weights are shared, with an [Embedding] for tied weights.
+#[derive(Clone)]
+enum Weight<T: Tensor> {
+    Dense(Arc<T>),
+    /// A dense weight recording the ranges of the activations, for the calibration.
+    Observed {
+        weight: Arc<T>,
+        observer: Arc<dyn RangeObserver<T>>,
+    },
+    Quantized(Arc<dyn QuantizedWeight<T>>),
+    /// A dense weight with its copy in a reduced precision, see [Linear::set_precision].
+    Cast {
+        weight: Arc<T>,
+        cast: Arc<dyn QuantizedWeight<T>>,
+    },
+}
+
+/// Linear layer, applies matmul(x, W.T) + b, plus the update of its LoRA adapter if
+/// one is attached with [Linear::set_lora].
 #[derive(Clone)]
 pub struct Linear<T: Tensor> {
-    weight: T,
+    weight: Weight<T>,
     bias: T,
+    lora: Option<Arc<LoraLinear<T>>>,
 }
 
 impl<T: Tensor + TensorOps<T>> Linear<T> {
     /// Linear layer creation
     pub fn new(weight: T, bias: T) -> Self {
-        Self { weight, bias }
+        Self {
+            weight: Weight::Dense(Arc::new(weight)),
+            bias,
+            lora: None,
+        }
+    }
+
+    /// A layer sharing the weight (vocab_size, hidden_dim) of `embedding`, like the
+    /// language modeling heads of the checkpoints storing the tied weight once.
+    /// Quantizing the layer replaces its weight, the embedding keeps the dense one.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    /// use smelte_rs::nn::layers::{Embedding, Linear};
+    ///
+    /// let embedding = Embedding::new(Tensor::zeros(vec![10, 4]));
+    /// let lm_head = Linear::tied(&embedding, Tensor::zeros(vec![10]));
+    /// assert!(std::ptr::eq(lm_head.weight().unwrap(), embedding.weight()));
+    /// ```
+    pub fn tied(embedding: &Embedding<T>, bias: T) -> Self {
+        Self {
+            weight: Weight::Dense(embedding.shared_weight().clone()),
+            bias,
+            lora: None,
+        }
+    }
+
+    /// A layer with an already quantized weight, like the 4-bit weights of GPTQ or AWQ
+    /// checkpoints.
+    /// ```
+    /// use smelte_rs::cpu::f32::{Int4Tensor, Tensor};
+    /// use smelte_rs::nn::layers::Linear;
+    /// use std::sync::Arc;
+    ///
+    /// let weight = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let weight = Int4Tensor::quantize(&weight, 2).unwrap();
+    /// let linear = Linear::quantized(Arc::new(weight), Tensor::zeros(vec![2]));
+    /// assert_eq!(linear.shape(), [2, 2]);
+    /// ```
+    pub fn quantized(weight: Arc<dyn QuantizedWeight<T>>, bias: T) -> Self {
+        Self {
+            weight: Weight::Quantized(weight),
+            bias,
+            lora: None,
+        }
     }
 
     /// Forward pass
     pub fn forward(&self, tensor: &T, out: &mut T) -> Result<(), SmeltError> {
-        T::matmul_t(tensor, &self.weight, out)?;
-        T::broadcast_add(&self.bias, out)?;
+        match &self.weight {
+            Weight::Dense(weight) => T::addmm(tensor, weight, &self.bias, out)?,
+            Weight::Observed { weight, observer } => {
+                T::addmm(tensor, weight, &self.bias, out)?;
+                observer.observe(tensor, out)?
+            }
+            Weight::Quantized(weight) | Weight::Cast { cast: weight, .. } => {
+                weight.addmm(tensor, &self.bias, out)?
+            }
+        }
+        if let Some(lora) = &self.lora {
+            lora.forward(tensor, out)?;
+        }
         Ok(())
     }
 
-    /// TODO
-    pub fn weight(&self) -> &T {
-        &self.weight
+    /// The weight, `None` once the layer is quantized.
+    pub fn weight(&self) -> Option<&T> {
+        match &self.weight {
+            Weight::Dense(weight)
+            | Weight::Observed { weight, .. }
+            | Weight::Cast { weight, .. } => Some(weight.as_ref()),
+            Weight::Quantized(_) => None,
+        }
+    }
+
+    /// The shape of the weight (out_features, in_features), even once quantized.
+    pub fn shape(&self) -> &[usize] {
+        match &self.weight {
+            Weight::Dense(weight)
+            | Weight::Observed { weight, .. }
+            | Weight::Cast { weight, .. } => weight.shape(),
+            Weight::Quantized(weight) => weight.shape(),
+        }
     }
 
     /// TODO
     pub fn bias(&self) -> &T {
         &self.bias
     }
+
+    /// Attaches the LoRA adapter `lora`, replacing the previous one. The weight is left
+    /// untouched, so the adapter also applies on top of a quantized weight.
+    pub fn set_lora(&mut self, lora: LoraLinear<T>) -> Result<(), SmeltError> {
+        if lora.shape() != self.shape() {
+            return Err(SmeltError::DimensionMismatch {
+                expected: self.shape().to_vec(),
+                got: lora.shape().to_vec(),
+            });
+        }
+        self.lora = Some(Arc::new(lora));
+        Ok(())
+    }
+
+    /// The attached LoRA adapter, if any
+    pub fn lora(&self) -> Option<&LoraLinear<T>> {
+        self.lora.as_deref()
+    }
+
+    /// Folds the attached LoRA adapter into a new dense weight, so that [Linear::forward]
+    /// costs the same as without the adapter. A tied weight is left untouched, and the
+    /// matmul goes back to the precision of `T`. Fails with a quantized weight.
+    pub fn merge_lora(&mut self) -> Result<(), SmeltError> {
+        let Some(lora) = &self.lora else {
+            return Ok(());
+        };
+        let weight = match &self.weight {
+            Weight::Dense(weight)
+            | Weight::Observed { weight, .. }
+            | Weight::Cast { weight, .. } => weight,
+            Weight::Quantized(_) => {
+                return Err(SmeltError::Unsupported {
+                    operation: "merge_lora",
+                    backend: "quantized",
+                })
+            }
+        };
+        let mut merged = lora.delta()?;
+        T::add(weight, &mut merged)?;
+        self.weight = Weight::Dense(Arc::new(merged));
+        self.lora = None;
+        Ok(())
+    }
+}
+
+impl<T: TensorQuantize> Linear<T> {
+    /// Replaces the weight with int8 values and one scale per row, see
+    /// [TensorQuantize::quantize_dynamic]. The bias stays in full precision.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    /// use smelte_rs::nn::layers::Linear;
+    ///
+    /// let weight = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let mut linear = Linear::new(weight, Tensor::zeros(vec![2]));
+    /// linear.quantize_dynamic().unwrap();
+    /// assert!(linear.weight().is_none());
+    /// ```
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        if let Weight::Dense(weight)
+        | Weight::Observed { weight, .. }
+        | Weight::Cast { weight, .. } = &self.weight
+        {
+            self.weight = Weight::Quantized(T::quantize_dynamic(weight)?);
+        }
+        Ok(())
+    }
+
+    /// Starts recording the ranges of the inputs and outputs of [Linear::forward], for
+    /// [Linear::quantize_static]. Restarts the recording if already observed.
+    pub fn observe(&mut self) {
+        if let Weight::Dense(weight)
+        | Weight::Observed { weight, .. }
+        | Weight::Cast { weight, .. } = &self.weight
+        {
+            self.weight = Weight::Observed {
+                weight: weight.clone(),
+                observer: T::observer(),
+            };
+        }
+    }
+
+    /// Replaces the weight and the bias with int8 and i32 values quantized for the
+    /// observed ranges, see [TensorQuantize::quantize_static]. Fails with
+    /// [SmeltError::NotCalibrated] unless [Linear::observe] was called first.
+    /// ```
+    /// use smelte_rs::cpu::f32::Tensor;
+    /// use smelte_rs::nn::layers::Linear;
+    ///
+    /// let weight = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+    /// let mut linear = Linear::new(weight, Tensor::zeros(vec![2]));
+    /// assert!(linear.quantize_static().is_err());
+    /// linear.observe();
+    /// let input = Tensor::new(vec![1.0, -1.0], vec![1, 2]).unwrap();
+    /// let mut out = Tensor::zeros(vec![1, 2]);
+    /// linear.forward(&input, &mut out).unwrap();
+    /// linear.quantize_static().unwrap();
+    /// ```
+    pub fn quantize_static(&mut self) -> Result<(), SmeltError> {
+        match &self.weight {
+            Weight::Observed { weight, observer } => {
+                self.weight =
+                    Weight::Quantized(T::quantize_static(weight, &self.bias, observer.ranges())?);
+                Ok(())
+            }
+            Weight::Dense(_) | Weight::Cast { .. } => Err(SmeltError::NotCalibrated),
+            Weight::Quantized(_) => Ok(()),
+        }
+    }
+}
+
+impl<T: TensorPrecision> Linear<T> {
+    /// Runs the matmul in `precision`, keeping the weight in the precision of `T` too so
+    /// that [Precision::F32] goes back to it. See [TensorPrecision].
+    pub fn set_precision(&mut self, precision: Precision) -> Result<(), SmeltError> {
+        let weight = match &self.weight {
+            Weight::Dense(weight)
+            | Weight::Observed { weight, .. }
+            | Weight::Cast { weight, .. } => weight,
+            Weight::Quantized(_) => {
+                return Err(SmeltError::Unsupported {
+                    operation: "set_precision",
+                    backend: "quantized",
+                })
+            }
+        };
+        self.weight = match precision {
+            Precision::F32 => Weight::Dense(weight.clone()),
+            precision => Weight::Cast {
+                weight: weight.clone(),
+                cast: T::cast_weight(weight, precision)?,
+            },
+        };
+        Ok(())
+    }
+}
+
+impl<T: TensorToDevice> Linear<T> {
+    /// A copy of the layer with its weights on `device`, in the precision of `T`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        let weight = match &self.weight {
+            Weight::Dense(weight)
+            | Weight::Observed { weight, .. }
+            | Weight::Cast { weight, .. } => Weight::Dense(Arc::new(weight.to_device(device)?)),
+            Weight::Quantized(_) => {
+                return Err(SmeltError::Unsupported {
+                    operation: "to_device",
+                    backend: "quantized",
+                })
+            }
+        };
+        let lora = match &self.lora {
+            Some(lora) => Some(Arc::new(lora.to_device(device)?)),
+            None => None,
+        };
+        Ok(Self {
+            weight,
+            bias: self.bias.to_device(device)?,
+            lora,
+        })
+    }
 }
 
 /// Linear layer, applies matmul(x, W) + b (also named conv1d sometimes)
@@ -66,13 +322,27 @@ impl<T: Tensor + TensorOps<T>> LinearT<T> {
 /// UnbiasedLinear layer, applies matmul(x, W.T)
 #[derive(Clone)]
 pub struct UnbiasedLinear<T: Tensor> {
-    weight: T,
+    weight: Arc<T>,
 }
 
 impl<T: Tensor + TensorOps<T>> UnbiasedLinear<T> {
     /// UnbiasedLinear layer creation
     pub fn new(weight: T) -> Self {
-        Self { weight }
+        Self {
+            weight: Arc::new(weight),
+        }
+    }
+
+    /// A layer sharing the weight of `embedding`, see [Linear::tied].
+    pub fn tied(embedding: &Embedding<T>) -> Self {
+        Self {
+            weight: embedding.shared_weight().clone(),
+        }
+    }
+
+    /// The weight (out_features, in_features)
+    pub fn weight(&self) -> &T {
+        &self.weight
     }
 
     /// Forward pass
@@ -100,4 +370,94 @@ mod tests {
 
         linear.forward(&zeros, &mut out).unwrap();
     }
+
+    #[test]
+    fn test_tied_linear() {
+        let data: Vec<_> = (0..12).map(|i| i as f32).collect();
+        let embedding = Embedding::new(Tensor::new(data, vec![4, 3]).unwrap());
+        let mut lm_head = Linear::tied(&embedding, Tensor::zeros(vec![4]));
+        let head = UnbiasedLinear::tied(&embedding);
+        assert!(std::ptr::eq(head.weight(), embedding.weight()));
+
+        let input = Tensor::new(vec![1.0, 0.0, 0.0], vec![1, 3]).unwrap();
+        let mut out = Tensor::zeros(vec![1, 4]);
+        lm_head.forward(&input, &mut out).unwrap();
+        assert_eq!(out.data(), [0.0, 3.0, 6.0, 9.0]);
+
+        // Quantizing the head unties it.
+        lm_head.quantize_dynamic().unwrap();
+        assert_eq!(embedding.weight().data()[..3], [0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_quantized_linear() {
+        let data: Vec<_> = (0..6).map(|i| i as f32 - 2.0).collect();
+        let input = Tensor::new(data, vec![2, 3]).unwrap();
+        let data: Vec<_> = (0..12).map(|i| (i as f32).cos()).collect();
+        let weights = Tensor::new(data, vec![4, 3]).unwrap();
+        let bias = Tensor::new(vec![1.0, 0.0, -1.0, 0.5], vec![4]).unwrap();
+
+        let mut linear = Linear::new(weights, bias);
+        let mut expected = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut expected).unwrap();
+
+        linear.quantize_dynamic().unwrap();
+        assert_eq!(linear.shape(), [4, 3]);
+        let mut out = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut out).unwrap();
+        for (v, e) in out.data().iter().zip(expected.data()) {
+            assert!((v - e).abs() < 0.05, "{v} != {e}");
+        }
+    }
+
+    #[test]
+    fn test_static_quantized_linear() {
+        let data: Vec<_> = (0..6).map(|i| i as f32 - 2.0).collect();
+        let input = Tensor::new(data, vec![2, 3]).unwrap();
+        let data: Vec<_> = (0..12).map(|i| (i as f32).cos()).collect();
+        let weights = Tensor::new(data, vec![4, 3]).unwrap();
+        let bias = Tensor::new(vec![1.0, 0.0, -1.0, 0.5], vec![4]).unwrap();
+
+        let mut linear = Linear::new(weights, bias);
+        assert!(matches!(
+            linear.quantize_static(),
+            Err(SmeltError::NotCalibrated)
+        ));
+        linear.observe();
+        let mut expected = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut expected).unwrap();
+
+        linear.quantize_static().unwrap();
+        assert!(linear.weight().is_none());
+        let mut out = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut out).unwrap();
+        for (v, e) in out.data().iter().zip(expected.data()) {
+            assert!((v - e).abs() < 0.05, "{v} != {e}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "f16")]
+    fn test_mixed_precision_linear() {
+        let data: Vec<_> = (0..6).map(|i| i as f32 - 2.0).collect();
+        let input = Tensor::new(data, vec![2, 3]).unwrap();
+        let data: Vec<_> = (0..12).map(|i| (i as f32).cos()).collect();
+        let weights = Tensor::new(data, vec![4, 3]).unwrap();
+        let bias = Tensor::new(vec![1.0, 0.0, -1.0, 0.5], vec![4]).unwrap();
+
+        let mut linear = Linear::new(weights, bias);
+        let mut expected = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut expected).unwrap();
+
+        linear.set_precision(Precision::F16).unwrap();
+        let mut out = Tensor::zeros(vec![2, 4]);
+        linear.forward(&input, &mut out).unwrap();
+        for (v, e) in out.data().iter().zip(expected.data()) {
+            assert!((v - e).abs() < 0.01, "{v} != {e}");
+        }
+
+        linear.set_precision(Precision::F32).unwrap();
+        linear.forward(&input, &mut out).unwrap();
+        assert_eq!(out.data(), expected.data());
+    }
 }
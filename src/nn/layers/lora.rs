@@ -0,0 +1,151 @@
+use crate::traits::{Device, Tensor, TensorOps, TensorToDevice};
+use crate::SmeltError;
+
+/// The low-rank update `scaling * x A.T B.T` of a LoRA adapter, with `A` (rank,
+/// in_features), `B` (out_features, rank) and `scaling = alpha / rank` like peft.
+/// It is attached to the [crate::nn::layers::Linear] it adapts with
+/// [crate::nn::layers::Linear::set_lora], and folded into its weight with
+/// [crate::nn::layers::Linear::merge_lora].
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::{Linear, LoraLinear};
+///
+/// let a = Tensor::zeros(vec![8, 16]);
+/// let b = Tensor::zeros(vec![32, 8]);
+/// let lora = LoraLinear::new(a, b, 16.0).unwrap();
+/// assert_eq!(lora.rank(), 8);
+/// assert_eq!(lora.scaling(), 2.0);
+///
+/// let mut linear = Linear::new(Tensor::zeros(vec![32, 16]), Tensor::zeros(vec![32]));
+/// linear.set_lora(lora).unwrap();
+/// linear.merge_lora().unwrap();
+/// assert!(linear.lora().is_none());
+/// ```
+#[derive(Clone)]
+pub struct LoraLinear<T: Tensor> {
+    a: T,
+    b: T,
+    scaling: f32,
+}
+
+impl<T: Tensor> LoraLinear<T> {
+    /// The adapter of the matrices `a` (rank, in_features) and `b` (out_features, rank),
+    /// the `lora_A` and `lora_B` of peft, and its `lora_alpha`.
+    pub fn new(a: T, b: T, alpha: f32) -> Result<Self, SmeltError> {
+        if a.shape().len() != 2 || a.shape()[0] == 0 {
+            return Err(SmeltError::InvalidConfig(format!(
+                "lora_A has the shape {:?}",
+                a.shape()
+            )));
+        }
+        let rank = a.shape()[0];
+        if b.shape().len() != 2 || b.shape()[1] != rank {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![b.shape()[0], rank],
+                got: b.shape().to_vec(),
+            });
+        }
+        Ok(Self {
+            a,
+            b,
+            scaling: alpha / rank as f32,
+        })
+    }
+
+    /// The rank of the update
+    pub fn rank(&self) -> usize {
+        self.a.shape()[0]
+    }
+
+    /// The factor of the update, `alpha / rank`
+    pub fn scaling(&self) -> f32 {
+        self.scaling
+    }
+
+    /// The shape (out_features, in_features) of the adapted weight
+    pub fn shape(&self) -> [usize; 2] {
+        [self.b.shape()[0], self.a.shape()[1]]
+    }
+}
+
+impl<T: Tensor + TensorOps<T>> LoraLinear<T> {
+    /// Adds the update of `x` (.., in_features) to `out` (.., out_features). The
+    /// intermediate buffers are allocated on every call, merging the adapter avoids them.
+    pub fn forward(&self, x: &T, out: &mut T) -> Result<(), SmeltError> {
+        let mut shape = x.shape().to_vec();
+        if let Some(last) = shape.last_mut() {
+            *last = self.rank();
+        }
+        let device = x.device();
+        let mut low = device.zeros(shape)?;
+        T::matmul_t(x, &self.a, &mut low)?;
+        T::mul_scalar(&mut low, self.scaling)?;
+        let mut update = device.zeros(out.shape().to_vec())?;
+        T::matmul_t(&low, &self.b, &mut update)?;
+        T::add(&update, out)
+    }
+
+    /// The update `scaling * B A` (out_features, in_features) of the weight.
+    pub fn delta(&self) -> Result<T, SmeltError> {
+        let mut delta = self.a.device().zeros(self.shape().to_vec())?;
+        T::matmul(&self.b, &self.a, &mut delta)?;
+        T::mul_scalar(&mut delta, self.scaling)?;
+        Ok(delta)
+    }
+}
+
+impl<T: TensorToDevice> LoraLinear<T> {
+    /// A copy of the adapter on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            a: self.a.to_device(device)?,
+            b: self.b.to_device(device)?,
+            scaling: self.scaling,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+    use crate::nn::layers::Linear;
+
+    #[test]
+    fn test_lora_linear() {
+        let weight = Tensor::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], vec![3, 2]).unwrap();
+        let bias = Tensor::new(vec![0.0, 1.0, 0.0], vec![3]).unwrap();
+        let mut linear = Linear::new(weight, bias);
+        // B A = [[1, -1], [0, 0], [2, -2]]
+        let a = Tensor::new(vec![1.0, -1.0], vec![1, 2]).unwrap();
+        let b = Tensor::new(vec![1.0, 0.0, 2.0], vec![3, 1]).unwrap();
+        linear
+            .set_lora(LoraLinear::new(a, b, 0.5).unwrap())
+            .unwrap();
+
+        let x = Tensor::new(vec![2.0, 1.0], vec![1, 2]).unwrap();
+        let mut out = Tensor::zeros(vec![1, 3]);
+        linear.forward(&x, &mut out).unwrap();
+        // [2, 2, 3] + 0.5 * [1, 0, 2]
+        assert_eq!(out.data(), [2.5, 2.0, 4.0]);
+
+        linear.merge_lora().unwrap();
+        assert!(linear.lora().is_none());
+        assert_eq!(
+            linear.weight().unwrap().data(),
+            [1.5, -0.5, 0.0, 1.0, 2.0, 0.0]
+        );
+        let mut merged = Tensor::zeros(vec![1, 3]);
+        linear.forward(&x, &mut merged).unwrap();
+        assert_eq!(merged.data(), out.data());
+
+        let a = Tensor::zeros(vec![1, 3]);
+        let b = Tensor::zeros(vec![3, 1]);
+        assert!(linear
+            .set_lora(LoraLinear::new(a, b, 1.0).unwrap())
+            .is_err());
+        let a = Tensor::zeros(vec![2, 2]);
+        assert!(LoraLinear::new(a, Tensor::zeros(vec![3, 1]), 1.0).is_err());
+    }
+}
@@ -4,9 +4,63 @@ pub mod linear;
 /// Layer norm
 pub mod layer_norm;
 
+/// Root mean square layer norm
+pub mod rms_norm;
+
+/// Batch normalization
+pub mod batch_norm;
+
+/// Dropout
+pub mod dropout;
+
 /// Embedding
 pub mod embedding;
 
+/// Convolutions
+pub mod conv;
+
+/// Rotary position embeddings
+pub mod rotary;
+
+/// Linear attention biases
+pub mod alibi;
+
+/// Sinusoidal position embeddings
+pub mod sinusoidal;
+
+/// Sentence embedding pooling
+pub mod pooling;
+
+/// Multi-head attention
+pub mod attention;
+
+/// Gated feed-forward layers
+pub mod gated_mlp;
+
+/// Mixtures of experts
+pub mod moe;
+
+/// Relative position biases
+pub mod relative_position;
+
+/// Low-rank adapters
+pub mod lora;
+
+pub use alibi::Alibi;
+pub use attention::{
+    AttentionConfig, AttentionContext, KvCache, MultiHeadAttention, QkvProjection,
+};
+pub use batch_norm::{BatchNorm1d, BatchNorm2d, BatchNormStats};
+pub use conv::{Conv1d, Conv2d, SeparableConv2d};
+pub use dropout::Dropout;
 pub use embedding::Embedding;
+pub use gated_mlp::{GatedMlp, GatedMlpContext};
 pub use layer_norm::LayerNorm;
 pub use linear::{Linear, LinearT, UnbiasedLinear};
+pub use lora::LoraLinear;
+pub use moe::{MixtureOfExperts, MoeContext};
+pub use pooling::Pooling;
+pub use relative_position::{LogBucketPositions, RelativePositionBias};
+pub use rms_norm::RmsNorm;
+pub use rotary::{RopeScaling, RotaryConfig, RotaryEmbedding};
+pub use sinusoidal::{SinusoidalEmbedding, SinusoidalLayout};
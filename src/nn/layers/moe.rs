@@ -0,0 +1,208 @@
+use crate::nn::layers::{GatedMlp, GatedMlpContext, Linear};
+use crate::traits::{Device, Tensor, TensorOps, TensorToDevice, TensorTopK};
+use crate::SmeltError;
+
+/// The buffers of a [MixtureOfExperts] for a number of tokens, see
+/// [MixtureOfExperts::context].
+pub struct MoeContext<T: Tensor> {
+    routing: T,
+    weight: T,
+    expert_out: T,
+    mlp: GatedMlpContext<T>,
+}
+
+impl<T: Tensor> MoeContext<T> {
+    /// The routing weights (sequence_length, num_experts) of the last forward pass, zero
+    /// for the experts a token was not routed to.
+    pub fn routing(&self) -> &T {
+        &self.routing
+    }
+}
+
+/// Sparse mixture of experts feed-forward layer, like Mixtral: a router picks the
+/// `top_k` experts of every token, and their outputs are summed with the softmax of
+/// their router logits. Every expert runs on every token for now (dense compute), the
+/// tokens not routed to it getting a weight of zero.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::{GatedMlp, Linear, MixtureOfExperts};
+/// use smelte_rs::traits::Activation;
+///
+/// let linear = |out, dim| Linear::new(Tensor::zeros(vec![out, dim]), Tensor::zeros(vec![out]));
+/// let expert = || GatedMlp::new(linear(64, 16), linear(64, 16), linear(16, 64), Activation::Silu);
+/// let experts = (0..8).map(|_| expert()).collect::<Result<_, _>>().unwrap();
+/// // 8 experts, 2 per token.
+/// let moe = MixtureOfExperts::new(linear(8, 16), experts, 2, &Device::new()).unwrap();
+///
+/// let x = Tensor::zeros(vec![3, 16]);
+/// let mut context = moe.context(3, &Device::new()).unwrap();
+/// let mut out = Tensor::zeros(vec![3, 16]);
+/// moe.forward(&x, &mut context, &mut out).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct MixtureOfExperts<T: Tensor> {
+    router: Linear<T>,
+    experts: Vec<GatedMlp<T>>,
+    top_k: usize,
+    // The one-hot rows (1, num_experts) picking the routing weight of every expert.
+    selectors: Vec<T>,
+}
+
+impl<T: Tensor + TensorOps<T> + TensorTopK<T>> MixtureOfExperts<T> {
+    /// The layer of the `router` (num_experts, hidden_dim) and its `experts`, routing
+    /// every token to `top_k` of them.
+    pub fn new(
+        router: Linear<T>,
+        experts: Vec<GatedMlp<T>>,
+        top_k: usize,
+        device: &T::Device,
+    ) -> Result<Self, SmeltError> {
+        let num_experts = experts.len();
+        if router.shape()[0] != num_experts {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![num_experts, router.shape()[1]],
+                got: router.shape().to_vec(),
+            });
+        }
+        if top_k == 0 || top_k > num_experts {
+            return Err(SmeltError::InvalidConfig(format!(
+                "routing to {top_k} of {num_experts} experts"
+            )));
+        }
+        let selectors = (0..num_experts)
+            .map(|e| {
+                let mut row = vec![0.0; num_experts];
+                row[e] = 1.0;
+                device.tensor(&row, vec![1, num_experts])
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            router,
+            experts,
+            top_k,
+            selectors,
+        })
+    }
+
+    /// The number of experts
+    pub fn num_experts(&self) -> usize {
+        self.experts.len()
+    }
+
+    /// The number of experts of every token
+    pub fn top_k(&self) -> usize {
+        self.top_k
+    }
+
+    /// The experts of the layer
+    pub fn experts(&self) -> &[GatedMlp<T>] {
+        &self.experts
+    }
+
+    /// The buffers of the layer for `sequence_length` tokens on `device`.
+    pub fn context(
+        &self,
+        sequence_length: usize,
+        device: &T::Device,
+    ) -> Result<MoeContext<T>, SmeltError> {
+        let hidden_dim = self.router.shape()[1];
+        Ok(MoeContext {
+            routing: device.zeros(vec![sequence_length, self.num_experts()])?,
+            weight: device.zeros(vec![sequence_length, 1])?,
+            expert_out: device.zeros(vec![sequence_length, hidden_dim])?,
+            mlp: self.experts[0].context(sequence_length, device)?,
+        })
+    }
+
+    /// The layer of `x` (sequence_length, hidden_dim) into `out` (sequence_length,
+    /// hidden_dim).
+    pub fn forward(&self, x: &T, ctx: &mut MoeContext<T>, out: &mut T) -> Result<(), SmeltError> {
+        // The softmax of the top-k logits, the weights renormalized over the chosen
+        // experts like Mixtral.
+        self.router.forward(x, &mut ctx.routing)?;
+        T::top_k(&mut ctx.routing, self.top_k)?;
+        T::softmax(&mut ctx.routing)?;
+        for (e, (expert, selector)) in self.experts.iter().zip(&self.selectors).enumerate() {
+            expert.forward(x, &mut ctx.mlp, &mut ctx.expert_out)?;
+            T::matmul_t(&ctx.routing, selector, &mut ctx.weight)?;
+            T::broadcast_mul(&ctx.weight, &mut ctx.expert_out)?;
+            if e == 0 {
+                T::copy(&ctx.expert_out, out)?;
+            } else {
+                T::add(&ctx.expert_out, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: TensorToDevice> MixtureOfExperts<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            router: self.router.to_device(device)?,
+            experts: self
+                .experts
+                .iter()
+                .map(|expert| expert.to_device(device))
+                .collect::<Result<_, _>>()?,
+            top_k: self.top_k,
+            selectors: self
+                .selectors
+                .iter()
+                .map(|selector| selector.to_device(device))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+    use crate::traits::Activation;
+
+    fn linear(data: Vec<f32>, shape: Vec<usize>) -> Linear<Tensor> {
+        let bias = Tensor::zeros(vec![shape[0]]);
+        Linear::new(Tensor::new(data, shape).unwrap(), bias)
+    }
+
+    /// relu(x) * x of the 1 item of the token, scaled by `factor`.
+    fn expert(factor: f32) -> GatedMlp<Tensor> {
+        let mlp = |data| linear(data, vec![1, 1]);
+        GatedMlp::new(
+            mlp(vec![1.0]),
+            mlp(vec![1.0]),
+            mlp(vec![factor]),
+            Activation::Relu,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mixture_of_experts() {
+        let device = Device::new();
+        // The logits of the experts are [x, 0, -x].
+        let router = linear(vec![1.0, 0.0, -1.0], vec![3, 1]);
+        let experts = vec![expert(1.0), expert(10.0), expert(100.0)];
+        let moe = MixtureOfExperts::new(router, experts, 2, &device).unwrap();
+
+        let x = Tensor::new(vec![2.0, -2.0], vec![2, 1]).unwrap();
+        let mut context = moe.context(2, &device).unwrap();
+        let mut out = Tensor::zeros(vec![2, 1]);
+        moe.forward(&x, &mut context, &mut out).unwrap();
+        // The first token goes to the experts 0 and 1, with the softmax of [2, 0].
+        let p = 1.0 / (1.0 + (-2f32).exp());
+        let routing = context.routing().data();
+        assert!((routing[0] - p).abs() < 1e-6 && routing[2] == 0.0);
+        assert!((out.data()[0] - 4.0 * (p + 10.0 * (1.0 - p))).abs() < 1e-4);
+        // relu(-2) = 0 for every expert of the second token.
+        assert_eq!(out.data()[1], 0.0);
+
+        let router = linear(vec![0.0; 2], vec![2, 1]);
+        assert!(MixtureOfExperts::new(router, vec![expert(1.0)], 1, &device).is_err());
+        let router = linear(vec![0.0], vec![1, 1]);
+        assert!(MixtureOfExperts::new(router, vec![expert(1.0)], 2, &device).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::traits::{Device, Tensor, TensorOps, TensorReduce};
+use crate::SmeltError;
+
+/// The pooling of the hidden states of the tokens into a sentence embedding, like the
+/// `Pooling` module of sentence-transformers. Unlike [crate::nn::models::bert::BertPooler],
+/// there is no dense layer nor tanh.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::Pooling;
+///
+/// // The `pooling_mode_*` of the `1_Pooling/config.json` of a checkpoint.
+/// let pooling = Pooling::from_modes(false, true, false).unwrap();
+/// assert_eq!(pooling, "mean".parse().unwrap());
+///
+/// let hidden_states = Tensor::new(vec![1.0, 2.0, 3.0, 4.0, 9.0, 9.0], vec![3, 2]).unwrap();
+/// let mut embedding = Tensor::zeros(vec![1, 2]);
+/// // The last token is a pad token.
+/// pooling.forward(&hidden_states, Some(&[1, 1, 0]), &mut embedding).unwrap();
+/// assert_eq!(embedding.data(), [2.0, 3.0]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pooling {
+    /// The hidden states of the first token, "cls"
+    #[default]
+    Cls,
+    /// The mean of the hidden states of the tokens which are not padding, "mean"
+    Mean,
+    /// The max of the hidden states of the tokens which are not padding on every
+    /// dimension, "max"
+    Max,
+}
+
+impl Pooling {
+    /// The pooling of the `pooling_mode_cls_token`, `pooling_mode_mean_tokens` and
+    /// `pooling_mode_max_tokens` of a sentence-transformers configuration, exactly one of
+    /// them is set.
+    pub fn from_modes(
+        cls_token: bool,
+        mean_tokens: bool,
+        max_tokens: bool,
+    ) -> Result<Self, SmeltError> {
+        match (cls_token, mean_tokens, max_tokens) {
+            (true, false, false) => Ok(Self::Cls),
+            (false, true, false) => Ok(Self::Mean),
+            (false, false, true) => Ok(Self::Max),
+            modes => Err(SmeltError::InvalidConfig(format!(
+                "unsupported pooling modes (cls, mean, max) {modes:?}"
+            ))),
+        }
+    }
+
+    /// Pools `hidden_states` (sequence_length, hidden_dim) into `out` (1, hidden_dim),
+    /// ignoring the pad tokens where `attention_mask` is 0 in the mean and the max.
+    pub fn forward<T: Tensor + TensorOps<T> + TensorReduce<T>>(
+        &self,
+        hidden_states: &T,
+        attention_mask: Option<&[usize]>,
+        out: &mut T,
+    ) -> Result<(), SmeltError> {
+        let &[sequence_length, hidden_dim] = hidden_states.shape() else {
+            return Err(SmeltError::InvalidRank { expected_rank: 2 });
+        };
+        let tokens: Vec<usize> = match attention_mask {
+            Some(mask) if mask.len() != sequence_length => {
+                return Err(SmeltError::InvalidLength {
+                    expected: sequence_length,
+                    got: mask.len(),
+                })
+            }
+            Some(mask) => (0..sequence_length).filter(|&i| mask[i] != 0).collect(),
+            None => (0..sequence_length).collect(),
+        };
+        if tokens.is_empty() {
+            return Err(SmeltError::InvalidConfig("no token to pool".to_string()));
+        }
+        match self {
+            Self::Cls => T::select(&[0], hidden_states, out),
+            Self::Mean => {
+                let mut weights = vec![0.0; sequence_length];
+                for &i in &tokens {
+                    weights[i] = 1.0 / tokens.len() as f32;
+                }
+                let device = hidden_states.device();
+                let weights = device.tensor(&weights, vec![1, sequence_length])?;
+                T::matmul(&weights, hidden_states, out)
+            }
+            Self::Max if tokens.len() == sequence_length => T::max(hidden_states, 0, true, out),
+            Self::Max => {
+                let device = hidden_states.device();
+                let mut states = device.zeros(vec![tokens.len(), hidden_dim])?;
+                T::select(&tokens, hidden_states, &mut states)?;
+                T::max(&states, 0, true, out)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Pooling {
+    type Err = SmeltError;
+
+    fn from_str(pooling_mode: &str) -> Result<Self, Self::Err> {
+        match pooling_mode {
+            "cls" => Ok(Self::Cls),
+            "mean" => Ok(Self::Mean),
+            "max" => Ok(Self::Max),
+            _ => Err(SmeltError::InvalidConfig(format!(
+                "unsupported pooling mode {pooling_mode}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+
+    #[test]
+    fn test_pooling() {
+        let data = vec![1.0, 5.0, 3.0, -1.0, 8.0, 0.0, 2.0, 2.0];
+        let hidden_states = Tensor::new(data, vec![4, 2]).unwrap();
+        let mask = [1, 1, 0, 1];
+        let mut out = Tensor::zeros(vec![1, 2]);
+        let pool = |pooling: Pooling, mask: Option<&[usize]>, out: &mut Tensor| {
+            pooling.forward(&hidden_states, mask, out).unwrap();
+            out.data().to_vec()
+        };
+        assert_eq!(pool(Pooling::Cls, Some(&mask), &mut out), [1.0, 5.0]);
+        assert_eq!(pool(Pooling::Mean, Some(&mask), &mut out), [2.0, 2.0]);
+        assert_eq!(pool(Pooling::Mean, None, &mut out), [3.5, 1.5]);
+        assert_eq!(pool(Pooling::Max, Some(&mask), &mut out), [3.0, 5.0]);
+        assert_eq!(pool(Pooling::Max, None, &mut out), [8.0, 5.0]);
+
+        assert!(Pooling::Mean
+            .forward(&hidden_states, Some(&[1, 1]), &mut out)
+            .is_err());
+        assert!(Pooling::Max
+            .forward(&hidden_states, Some(&[0; 4]), &mut out)
+            .is_err());
+        assert!(Pooling::from_modes(true, true, false).is_err());
+        assert!(Pooling::from_modes(false, false, false).is_err());
+        assert_eq!("max".parse::<Pooling>().unwrap(), Pooling::Max);
+        assert!("weightedmean".parse::<Pooling>().is_err());
+    }
+}
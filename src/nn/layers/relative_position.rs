@@ -0,0 +1,268 @@
+use crate::traits::Device;
+use crate::SmeltError;
+
+/// The learned relative position bias of T5: the distances between the queries and the
+/// keys are bucketized, exactly for the short ones and logarithmically up to
+/// `max_distance` for the others, and every bucket has a learned bias per head. Like
+/// [crate::nn::layers::Alibi], the bias is built on the device of the attention with
+/// [RelativePositionBias::bias].
+/// ```
+/// use smelte_rs::cpu::f32::Device;
+/// use smelte_rs::nn::layers::RelativePositionBias;
+///
+/// // The `relative_attention_bias` (num_buckets, num_heads) of an encoder with 8 heads.
+/// let table = vec![0.0; 32 * 8];
+/// let bias = RelativePositionBias::new(table, 8, true, 128).unwrap();
+/// assert_eq!(bias.num_buckets(), 32);
+/// assert_eq!(bias.bucket(-1), 1);
+/// assert_eq!(bias.bucket(1), 17);
+/// let bias = bias.bias(5, 5, &Device::new()).unwrap();
+/// assert_eq!(bias.shape(), [8, 5, 5]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelativePositionBias {
+    table: Vec<f32>,
+    num_heads: usize,
+    bidirectional: bool,
+    max_distance: usize,
+}
+
+impl RelativePositionBias {
+    /// The bias of the `table` (num_buckets, num_heads). The encoders are
+    /// `bidirectional`, the buckets of the keys before and after the query are distinct.
+    /// T5 has 32 buckets and a `max_distance` of 128.
+    pub fn new(
+        table: Vec<f32>,
+        num_heads: usize,
+        bidirectional: bool,
+        max_distance: usize,
+    ) -> Result<Self, SmeltError> {
+        if num_heads == 0 || table.is_empty() || !table.len().is_multiple_of(num_heads) {
+            return Err(SmeltError::InvalidConfig(format!(
+                "a table of {} items can't have {num_heads} heads",
+                table.len()
+            )));
+        }
+        // The exact and logarithmic halves need a bucket each, for both directions.
+        let min_buckets = if bidirectional { 4 } else { 2 };
+        if table.len() / num_heads < min_buckets {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{} buckets are too few",
+                table.len() / num_heads
+            )));
+        }
+        Ok(Self {
+            table,
+            num_heads,
+            bidirectional,
+            max_distance,
+        })
+    }
+
+    /// The number of buckets of the table
+    pub fn num_buckets(&self) -> usize {
+        self.table.len() / self.num_heads
+    }
+
+    /// The number of heads of the table
+    pub fn num_heads(&self) -> usize {
+        self.num_heads
+    }
+
+    /// The bucket of the key at `relative_position` (key position - query position),
+    /// like `_relative_position_bucket` of transformers.
+    pub fn bucket(&self, relative_position: isize) -> usize {
+        let mut num_buckets = self.num_buckets();
+        let mut bucket = 0;
+        let distance = if self.bidirectional {
+            num_buckets /= 2;
+            if relative_position > 0 {
+                bucket += num_buckets;
+            }
+            relative_position.unsigned_abs()
+        } else {
+            // The keys after the query all share the bucket 0.
+            (-relative_position).max(0) as usize
+        };
+        let max_exact = num_buckets / 2;
+        if distance < max_exact {
+            return bucket + distance;
+        }
+        // Computed in f32 like transformers, for the same rounding.
+        let ratio = (distance as f32 / max_exact as f32).ln()
+            / (self.max_distance as f32 / max_exact as f32).ln();
+        let large = max_exact + (ratio * (num_buckets - max_exact) as f32) as usize;
+        bucket + large.min(num_buckets - 1)
+    }
+
+    /// The bias (num_heads, query_length, key_length) added to the attention scores,
+    /// where the queries are the last `query_length` keys.
+    pub fn bias<D: Device>(
+        &self,
+        query_length: usize,
+        key_length: usize,
+        device: &D,
+    ) -> Result<D::Tensor, SmeltError> {
+        if query_length > key_length {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{query_length} queries attend to {key_length} keys"
+            )));
+        }
+        self.bias_at(key_length - query_length, query_length, key_length, device)
+    }
+
+    /// [RelativePositionBias::bias] with the query `i` at the position `offset + i` of the
+    /// keys, like the new tokens of a [crate::nn::layers::KvCache] followed by free room.
+    pub fn bias_at<D: Device>(
+        &self,
+        offset: usize,
+        query_length: usize,
+        key_length: usize,
+        device: &D,
+    ) -> Result<D::Tensor, SmeltError> {
+        let buckets: Vec<usize> = (offset..offset + query_length)
+            .flat_map(|i| (0..key_length).map(move |j| j as isize - i as isize))
+            .map(|relative_position| self.bucket(relative_position))
+            .collect();
+        let mut data = Vec::with_capacity(self.num_heads * buckets.len());
+        for head in 0..self.num_heads {
+            data.extend(
+                buckets
+                    .iter()
+                    .map(|bucket| self.table[bucket * self.num_heads + head]),
+            );
+        }
+        device.tensor(&data, vec![self.num_heads, query_length, key_length])
+    }
+}
+
+/// The relative positions of DeBERTa, bucketized like [RelativePositionBias]: exactly up
+/// to `bucket_size / 2`, logarithmically up to `max_position` for the others. They index
+/// the `2 * bucket_size` relative embeddings shared by the layers, which every attention
+/// projects with its queries and keys (disentangled attention).
+/// ```
+/// use smelte_rs::nn::layers::LogBucketPositions;
+///
+/// // The `position_buckets` and `max_relative_positions` of DeBERTa-v3.
+/// let positions = LogBucketPositions::new(256, 512).unwrap();
+/// assert_eq!(positions.num_positions(), 512);
+/// assert_eq!(positions.bucket(-3), -3);
+/// assert_eq!(positions.bucket(200), 169);
+/// // The relative embeddings of the queries and keys of 2 tokens.
+/// assert_eq!(positions.indices(2, 2), [256, 255, 257, 256]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogBucketPositions {
+    bucket_size: usize,
+    max_position: usize,
+}
+
+impl LogBucketPositions {
+    /// The positions of the `position_buckets` (`bucket_size`) and the
+    /// `max_relative_positions` (`max_position`, the `max_position_embeddings` when it is
+    /// unset) of a DeBERTa configuration.
+    pub fn new(bucket_size: usize, max_position: usize) -> Result<Self, SmeltError> {
+        // The logarithmic buckets start after the exact half.
+        if bucket_size < 2 || max_position <= bucket_size / 2 + 1 {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{bucket_size} buckets up to the position {max_position}"
+            )));
+        }
+        Ok(Self {
+            bucket_size,
+            max_position,
+        })
+    }
+
+    /// The number of relative embeddings, `2 * bucket_size`
+    pub fn num_positions(&self) -> usize {
+        2 * self.bucket_size
+    }
+
+    /// The bucket of `relative_position` (query position - key position), negative
+    /// before the query, like `make_log_bucket_position` of transformers.
+    pub fn bucket(&self, relative_position: isize) -> isize {
+        let mid = (self.bucket_size / 2) as isize;
+        let distance = relative_position.abs();
+        if distance <= mid {
+            return relative_position;
+        }
+        // Computed in f32 like transformers, for the same rounding.
+        let ratio = (distance as f32 / mid as f32).ln()
+            / ((self.max_position - 1) as f32 / mid as f32).ln();
+        let bucket = (ratio * (mid - 1) as f32).ceil() as isize + mid;
+        bucket * relative_position.signum()
+    }
+
+    /// The relative embedding of every query and key, `query_length * key_length`
+    /// indices in `0..num_positions`, where the queries are the last `query_length` keys.
+    pub fn indices(&self, query_length: usize, key_length: usize) -> Vec<usize> {
+        let offset = key_length.saturating_sub(query_length);
+        let last = self.num_positions() as isize - 1;
+        (offset..offset + query_length)
+            .flat_map(|i| (0..key_length).map(move |j| i as isize - j as isize))
+            .map(|relative_position| {
+                let bucket = self.bucket(relative_position) + self.bucket_size as isize;
+                bucket.clamp(0, last) as usize
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Device;
+
+    #[test]
+    fn test_relative_position_bias() {
+        // Values obtained through python
+        let table: Vec<f32> = (0..64).map(|i| i as f32).collect();
+        let bias = RelativePositionBias::new(table.clone(), 2, true, 128).unwrap();
+        let buckets: Vec<_> = [0, -1, 1, -7, -8, -20, -200, 200]
+            .iter()
+            .map(|&p| bias.bucket(p))
+            .collect();
+        assert_eq!(buckets, [0, 1, 17, 7, 8, 10, 15, 31]);
+
+        let causal = RelativePositionBias::new(table, 2, false, 128).unwrap();
+        let buckets: Vec<_> = [1, 0, -1, -20, -1000]
+            .iter()
+            .map(|&p| causal.bucket(p))
+            .collect();
+        assert_eq!(buckets, [0, 0, 1, 17, 31]);
+
+        // A query after a cached key, the buckets are [1, 0] for the table of the heads
+        // [[0, 1], [2, 3], ...].
+        let bias = causal.bias(1, 2, &Device::new()).unwrap();
+        assert_eq!(bias.data(), [2.0, 0.0, 3.0, 1.0]);
+        assert!(causal.bias(2, 1, &Device::new()).is_err());
+        assert!(RelativePositionBias::new(vec![0.0; 5], 2, true, 128).is_err());
+        assert!(RelativePositionBias::new(vec![0.0; 6], 2, true, 128).is_err());
+    }
+
+    #[test]
+    fn test_log_bucket_positions() {
+        // Values obtained through python
+        let positions = LogBucketPositions::new(8, 16).unwrap();
+        let buckets: Vec<_> = [0, 1, -4, 5, 8, -10, 100]
+            .iter()
+            .map(|&p| positions.bucket(p))
+            .collect();
+        assert_eq!(buckets, [0, 1, -4, 5, 6, -7, 12]);
+        let positions = LogBucketPositions::new(256, 512).unwrap();
+        let buckets: Vec<_> = [129, -200, 511, 1000]
+            .iter()
+            .map(|&p| positions.bucket(p))
+            .collect();
+        assert_eq!(buckets, [129, -169, 255, 317]);
+
+        // The farthest keys are clamped to the first and last embeddings.
+        let positions = LogBucketPositions::new(2, 4).unwrap();
+        assert_eq!(positions.indices(1, 3), [3, 3, 2]);
+        assert_eq!(positions.indices(3, 3)[6], 3);
+        assert!(LogBucketPositions::new(1, 512).is_err());
+        assert!(LogBucketPositions::new(8, 5).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::traits::{Device, Tensor, TensorOps, TensorReduce, TensorToDevice, TensorUnary};
+use crate::SmeltError;
+
+/// Root mean square layer norm, `x / sqrt(mean(x^2) + epsilon) * weight` on the last
+/// dimension: unlike [crate::nn::layers::LayerNorm], the mean isn't subtracted and
+/// there is no bias, like the `T5LayerNorm` of T5 and the `LlamaRMSNorm` of LLaMA.
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::RmsNorm;
+///
+/// let norm = RmsNorm::new(Tensor::new(vec![1.0, 2.0], vec![2]).unwrap(), 0.0).unwrap();
+/// let mut x = Tensor::new(vec![3.0, 4.0, 0.0, -2.0], vec![2, 2]).unwrap();
+/// norm.forward(&mut x).unwrap();
+/// let rounded: Vec<_> = x.data().iter().map(|v| (v * 1e4).round() / 1e4).collect();
+/// // The root mean squares of the rows are 12.5.sqrt() and 2.sqrt().
+/// assert_eq!(rounded, [0.8485, 2.2627, 0.0, -2.8284]);
+/// ```
+#[derive(Clone)]
+pub struct RmsNorm<T: Tensor> {
+    weight: T,
+    epsilon: f32,
+    // The epsilon (1,) on the device of the weight, added to the mean squares.
+    epsilon_tensor: T,
+}
+
+impl<T: Tensor + TensorOps<T> + TensorReduce<T> + TensorUnary<T>> RmsNorm<T> {
+    /// The norm of the `weight` (hidden_dim,), 1e-6 is the `epsilon` of T5 and LLaMA.
+    pub fn new(weight: T, epsilon: f32) -> Result<Self, SmeltError> {
+        if weight.shape().len() != 1 {
+            return Err(SmeltError::InvalidRank { expected_rank: 1 });
+        }
+        let epsilon_tensor = weight.device().tensor(&[epsilon], vec![1])?;
+        Ok(Self {
+            weight,
+            epsilon,
+            epsilon_tensor,
+        })
+    }
+
+    /// The weight (hidden_dim,)
+    pub fn weight(&self) -> &T {
+        &self.weight
+    }
+
+    /// The epsilon added to the mean squares
+    pub fn epsilon(&self) -> f32 {
+        self.epsilon
+    }
+
+    /// Normalizes `x` (..., hidden_dim) in place.
+    pub fn forward(&self, x: &mut T) -> Result<(), SmeltError> {
+        let shape = x.shape().to_vec();
+        let Some(dim) = shape.len().checked_sub(1) else {
+            return Err(SmeltError::InvalidRank { expected_rank: 1 });
+        };
+        let device = x.device();
+        let mut squares = device.zeros(shape.clone())?;
+        T::copy(x, &mut squares)?;
+        T::mul(x, &mut squares)?;
+        let mut mean_shape = shape;
+        mean_shape[dim] = 1;
+        let mut scale = device.zeros(mean_shape)?;
+        T::mean(&squares, dim, true, &mut scale)?;
+        T::broadcast_add(&self.epsilon_tensor, &mut scale)?;
+        T::rsqrt(&mut scale)?;
+        T::broadcast_mul(&scale, x)?;
+        T::broadcast_mul(&self.weight, x)
+    }
+}
+
+impl<T: TensorToDevice> RmsNorm<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            weight: self.weight.to_device(device)?,
+            epsilon: self.epsilon,
+            epsilon_tensor: self.epsilon_tensor.to_device(device)?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+
+    #[test]
+    fn test_rms_norm() {
+        let weight = Tensor::new(vec![2.0, 1.0, 1.0], vec![3]).unwrap();
+        let norm = RmsNorm::new(weight, 1.0).unwrap();
+        // mean(x^2) + 1 = 4 for the first row, 1 for the second one.
+        let mut x = Tensor::new(vec![3.0, 0.0, 0.0, 0.0, 0.0, 0.0], vec![2, 3]).unwrap();
+        norm.forward(&mut x).unwrap();
+        assert_eq!(x.data(), [3.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        assert!(RmsNorm::new(Tensor::zeros(vec![1, 3]), 1e-6).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::traits::{Device, Tensor, TensorRotary, TensorToDevice};
+use crate::SmeltError;
+
+/// The scaling of the positions of a [RotaryEmbedding], to run past the length the model
+/// was trained on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RopeScaling {
+    /// The positions are divided by the factor (linear interpolation).
+    Linear(f32),
+    /// The base of the frequencies is multiplied by `factor ^ (head_dim / (head_dim - 2))`
+    /// (NTK-aware), the high frequencies are barely changed.
+    Ntk(f32),
+}
+
+/// The base and scaling of the frequencies of a [RotaryEmbedding]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RotaryConfig {
+    /// The base of the frequencies, 10000 for LLaMA
+    pub theta: f32,
+    /// The scaling of the positions, if any
+    pub scaling: Option<RopeScaling>,
+}
+
+impl Default for RotaryConfig {
+    fn default() -> Self {
+        Self {
+            theta: 10000.0,
+            scaling: None,
+        }
+    }
+}
+
+impl RotaryConfig {
+    /// The tables `(cos, sin)` (max_positions, head_dim / 2) of the angles
+    /// `position / theta ^ (2 * i / head_dim)`.
+    pub fn tables(&self, head_dim: usize, max_positions: usize) -> (Vec<f32>, Vec<f32>) {
+        let (mut theta, mut scale) = (self.theta as f64, 1.0);
+        match self.scaling {
+            Some(RopeScaling::Linear(factor)) => scale = 1.0 / factor as f64,
+            Some(RopeScaling::Ntk(factor)) if head_dim > 2 => {
+                theta *= (factor as f64).powf(head_dim as f64 / (head_dim as f64 - 2.0))
+            }
+            _ => (),
+        }
+        let half = head_dim / 2;
+        let mut cos = Vec::with_capacity(max_positions * half);
+        let mut sin = Vec::with_capacity(max_positions * half);
+        for position in 0..max_positions {
+            for i in 0..half {
+                let frequency = theta.powf(-2.0 * i as f64 / head_dim as f64);
+                let angle = position as f64 * scale * frequency;
+                cos.push(angle.cos() as f32);
+                sin.push(angle.sin() as f32);
+            }
+        }
+        (cos, sin)
+    }
+}
+
+/// Rotary position embeddings (RoPE) of the queries and keys of an attention, like
+/// LLaMA. The cos and sin tables are computed once on the device of the layer.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::RotaryEmbedding;
+/// use smelte_rs::traits::Tensor as _;
+///
+/// let rope = RotaryEmbedding::<Tensor>::new(64, 2048, Default::default(), &Device::new())
+///     .unwrap();
+/// // The queries of 32 heads for 5 new tokens, after 7 cached ones.
+/// let mut query = Tensor::zeros(vec![32, 5, 64]);
+/// rope.forward(&mut query, 7).unwrap();
+/// assert_eq!(rope.max_positions(), 2048);
+/// ```
+#[derive(Clone)]
+pub struct RotaryEmbedding<T: Tensor> {
+    cos: T,
+    sin: T,
+    config: RotaryConfig,
+}
+
+impl<T: Tensor + TensorRotary<T>> RotaryEmbedding<T> {
+    /// The rotary embedding of heads of `head_dim` items, for up to `max_positions`
+    /// positions.
+    pub fn new(
+        head_dim: usize,
+        max_positions: usize,
+        config: RotaryConfig,
+        device: &T::Device,
+    ) -> Result<Self, SmeltError> {
+        let (cos, sin) = config.tables(head_dim, max_positions);
+        let shape = vec![max_positions, head_dim / 2];
+        Ok(Self {
+            cos: device.tensor(&cos, shape.clone())?,
+            sin: device.tensor(&sin, shape)?,
+            config,
+        })
+    }
+
+    /// Rotates `x` (..., sequence_length, head_dim) in place, its first item being at
+    /// the position `offset` (the length of the cache before it).
+    pub fn forward(&self, x: &mut T, offset: usize) -> Result<(), SmeltError> {
+        T::rotary(x, &self.cos, &self.sin, offset)
+    }
+
+    /// The number of positions of the tables
+    pub fn max_positions(&self) -> usize {
+        self.cos.shape()[0]
+    }
+
+    /// The size of the rotated heads
+    pub fn head_dim(&self) -> usize {
+        2 * self.cos.shape()[1]
+    }
+
+    /// The base and scaling of the frequencies
+    pub fn config(&self) -> &RotaryConfig {
+        &self.config
+    }
+}
+
+impl<T: TensorToDevice> RotaryEmbedding<T> {
+    /// A copy of the layer with its tables on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            cos: self.cos.to_device(device)?,
+            sin: self.sin.to_device(device)?,
+            config: self.config,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+
+    fn dot(a: &Tensor, b: &Tensor) -> f32 {
+        a.data().iter().zip(b.data()).map(|(a, b)| a * b).sum()
+    }
+
+    #[test]
+    fn test_rotary() {
+        let device = Device::new();
+        let rope = RotaryEmbedding::<Tensor>::new(4, 16, RotaryConfig::default(), &device).unwrap();
+        let data = vec![0.5, -1.0, 2.0, 0.25];
+        let mut x = Tensor::new(data.clone(), vec![1, 4]).unwrap();
+        rope.forward(&mut x, 0).unwrap();
+        assert_eq!(x.data(), data);
+
+        // The scores only depend on the distance between the positions.
+        let scores = |query_position, key_position| {
+            let mut q = Tensor::new(data.clone(), vec![1, 4]).unwrap();
+            let mut k = Tensor::new(vec![1.0, 0.5, -0.5, 2.0], vec![1, 4]).unwrap();
+            rope.forward(&mut q, query_position).unwrap();
+            rope.forward(&mut k, key_position).unwrap();
+            dot(&q, &k)
+        };
+        assert!((scores(5, 2) - scores(12, 9)).abs() < 1e-5);
+        assert!((scores(5, 2) - scores(5, 3)).abs() > 1e-3);
+        assert!(rope.forward(&mut x, 16).is_err());
+
+        // Linear scaling halves the angles.
+        let config = RotaryConfig {
+            scaling: Some(RopeScaling::Linear(2.0)),
+            ..Default::default()
+        };
+        let (cos, _) = config.tables(4, 3);
+        let (expected, _) = RotaryConfig::default().tables(4, 2);
+        assert_eq!(cos[4..6], expected[2..4]);
+        assert!((cos[2] - 0.5f32.cos()).abs() < 1e-6);
+    }
+}
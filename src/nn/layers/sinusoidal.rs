@@ -0,0 +1,169 @@
+use crate::traits::{Device, Tensor, TensorOps, TensorToDevice};
+use crate::SmeltError;
+
+/// The order of the sines and cosines of a [SinusoidalEmbedding].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SinusoidalLayout {
+    /// The sine and cosine of the frequency `10000 ^ (-2i / dim)` are the items `2i` and
+    /// `2i + 1`, like the vanilla Transformer.
+    #[default]
+    Interleaved,
+    /// The `dim / 2` sines then the `dim / 2` cosines of the frequencies
+    /// `10000 ^ (-i / (dim / 2 - 1))`, like the encoder of Whisper.
+    Concatenated,
+}
+
+impl SinusoidalLayout {
+    /// The table (max_positions, dim) of the embeddings, `dim` is even.
+    pub fn table(&self, dim: usize, max_positions: usize) -> Vec<f32> {
+        let half = dim / 2;
+        let frequency = |i: usize| match self {
+            Self::Interleaved => 10000f64.powf(-2.0 * i as f64 / dim as f64),
+            Self::Concatenated => (-(10000f64.ln()) * i as f64 / (half as f64 - 1.0)).exp(),
+        };
+        let frequencies: Vec<f64> = (0..half).map(frequency).collect();
+        let mut table = Vec::with_capacity(max_positions * dim);
+        for position in 0..max_positions {
+            let angles = frequencies.iter().map(|f| position as f64 * f);
+            match self {
+                Self::Interleaved => {
+                    table.extend(angles.flat_map(|a| [a.sin() as f32, a.cos() as f32]))
+                }
+                Self::Concatenated => {
+                    table.extend(angles.clone().map(|a| a.sin() as f32));
+                    table.extend(angles.map(|a| a.cos() as f32));
+                }
+            }
+        }
+        table
+    }
+}
+
+/// The fixed sinusoidal position embeddings of the vanilla Transformer and Whisper, for
+/// the models without a learned position table. The table is computed once on the device
+/// of the layer, for up to `max_positions` positions.
+/// ```
+/// use smelte_rs::cpu::f32::{Device, Tensor};
+/// use smelte_rs::nn::layers::{SinusoidalEmbedding, SinusoidalLayout};
+///
+/// let layout = SinusoidalLayout::Interleaved;
+/// let embedding = SinusoidalEmbedding::<Tensor>::new(8, 512, layout, &Device::new()).unwrap();
+/// // The hidden states of 3 new tokens, after 4 cached ones.
+/// let mut hidden_states = Tensor::zeros(vec![3, 8]);
+/// embedding.forward(&mut hidden_states, 4).unwrap();
+/// assert_eq!(embedding.max_positions(), 512);
+/// ```
+#[derive(Clone)]
+pub struct SinusoidalEmbedding<T: Tensor> {
+    table: T,
+    layout: SinusoidalLayout,
+}
+
+impl<T: Tensor + TensorOps<T>> SinusoidalEmbedding<T> {
+    /// The embeddings of `dim` items for up to `max_positions` positions. `dim` is even,
+    /// and at least 4 for [SinusoidalLayout::Concatenated].
+    pub fn new(
+        dim: usize,
+        max_positions: usize,
+        layout: SinusoidalLayout,
+        device: &T::Device,
+    ) -> Result<Self, SmeltError> {
+        let min_dim = match layout {
+            SinusoidalLayout::Interleaved => 2,
+            SinusoidalLayout::Concatenated => 4,
+        };
+        if !dim.is_multiple_of(2) || dim < min_dim {
+            return Err(SmeltError::InvalidConfig(format!(
+                "sinusoidal embeddings of {dim} items"
+            )));
+        }
+        let table = layout.table(dim, max_positions);
+        Ok(Self {
+            table: device.tensor(&table, vec![max_positions, dim])?,
+            layout,
+        })
+    }
+
+    /// Adds the embeddings to `x` (sequence_length, dim), its first item being at the
+    /// position `offset` (the length of the cache before it).
+    pub fn forward(&self, x: &mut T, offset: usize) -> Result<(), SmeltError> {
+        let sequence_length = x.shape().first().copied().unwrap_or(0);
+        let size = self.max_positions();
+        if offset + sequence_length > size {
+            return Err(SmeltError::OutOfRange {
+                dim: 0,
+                end: offset + sequence_length,
+                size,
+            });
+        }
+        let positions: Vec<usize> = (offset..offset + sequence_length).collect();
+        let mut embeddings = x.device().zeros(x.shape().to_vec())?;
+        T::gather(&positions, &self.table, &mut embeddings)?;
+        T::add(&embeddings, x)
+    }
+
+    /// The table (max_positions, dim) of the embeddings
+    pub fn table(&self) -> &T {
+        &self.table
+    }
+
+    /// The number of positions of the table
+    pub fn max_positions(&self) -> usize {
+        self.table.shape()[0]
+    }
+
+    /// The order of the sines and cosines
+    pub fn layout(&self) -> SinusoidalLayout {
+        self.layout
+    }
+}
+
+impl<T: TensorToDevice> SinusoidalEmbedding<T> {
+    /// A copy of the layer with its table on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            table: self.table.to_device(device)?,
+            layout: self.layout,
+        })
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+
+    #[test]
+    fn test_sinusoidal_embedding() {
+        let device = Device::new();
+        let layout = SinusoidalLayout::Interleaved;
+        let embedding = SinusoidalEmbedding::<Tensor>::new(4, 8, layout, &device).unwrap();
+        let table = embedding.table().data();
+        assert_eq!(table[..4], [0.0, 1.0, 0.0, 1.0]);
+        // The frequencies of the position 1 are 1 and 0.01.
+        let expected = [1f32.sin(), 1f32.cos(), 0.01f32.sin(), 0.01f32.cos()];
+        assert!(table[4..8]
+            .iter()
+            .zip(expected)
+            .all(|(t, e)| (t - e).abs() < 1e-6));
+
+        let mut x = Tensor::new(vec![1.0; 8], vec![2, 4]).unwrap();
+        embedding.forward(&mut x, 1).unwrap();
+        let expected: Vec<f32> = table[4..8].iter().map(|t| t + 1.0).collect();
+        assert_eq!(x.data()[..4], expected);
+        assert!(embedding.forward(&mut x, 7).is_err());
+
+        // Whisper: the sines then the cosines of the frequencies 1 and 1e-4.
+        let table = SinusoidalLayout::Concatenated.table(4, 2);
+        let at_1 = [1f32.sin(), 1e-4f32.sin(), 1f32.cos(), 1e-4f32.cos()];
+        let expected = [0.0, 0.0, 1.0, 1.0].into_iter().chain(at_1);
+        assert!(table
+            .iter()
+            .zip(expected)
+            .all(|(t, e)| (t - e).abs() < 1e-6));
+        assert!(SinusoidalEmbedding::<Tensor>::new(3, 8, layout, &device).is_err());
+        let layout = SinusoidalLayout::Concatenated;
+        assert!(SinusoidalEmbedding::<Tensor>::new(2, 8, layout, &device).is_err());
+    }
+}
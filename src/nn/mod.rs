@@ -1,5 +1,14 @@
 /// All complete models
 pub mod models;
 
+/// The integer tensors of the ids given to the models.
+pub mod ids;
+
 /// Various basic layers.
 pub mod layers;
+
+/// Text generation of the encoder-decoders and of the decoder-only models.
+pub mod generation;
+
+/// Static quantization of the models, calibrated on sample inputs.
+pub mod quantize;
@@ -0,0 +1,254 @@
+use crate::nn::models::bert::BertClassifier;
+
+/// The ALBERT sequence classifier. Its layers are the ones of BERT, shared across the
+/// depth of the encoder (see [crate::nn::models::bert::BertEncoder::shared]), and its
+/// embeddings are factorized (see
+/// [crate::nn::models::bert::BertEmbeddings::with_projection]), see [classifier_from].
+pub type AlbertClassifier<T> = BertClassifier<T>;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::classifier_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_layer_norm, load_linear, load_tensor};
+    use crate::nn::layers::{Dropout, Embedding};
+    use crate::nn::models::bert::{
+        Bert, BertAttention, BertEmbeddings, BertEncoder, BertLayer, BertPooler, Mlp,
+    };
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of ALBERT, the `layer_norm_eps` of its configurations.
+    const LAYER_NORM_EPS: f32 = 1e-12;
+
+    /// The dropout before the classifier, the `classifier_dropout_prob` of the
+    /// configurations: the layers of ALBERT-v2 have no dropout.
+    const CLASSIFIER_DROPOUT: f32 = 0.1;
+
+    /// Creates an [AlbertClassifier] of `num_heads` heads and `num_hidden_layers` layers
+    /// (the `num_attention_heads` and `num_hidden_layers` of the configuration, the
+    /// checkpoint stores the shared layers once) on `device`, from the tensors of an
+    /// `AlbertForSequenceClassification` checkpoint: `albert.embeddings.*`,
+    /// `albert.encoder.embedding_hidden_mapping_in`,
+    /// `albert.encoder.albert_layer_groups.N.albert_layers.M.*`, `albert.pooler` and
+    /// `classifier`. The groups of layers are spread evenly over the depth, like
+    /// transformers.
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::Device;
+    /// use smelte_rs::nn::models::albert::classifier_from;
+    ///
+    /// # let buffer = vec![];
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// let model = classifier_from(&tensors, 12, 12, &Device::cpu()).unwrap();
+    /// # }
+    /// ```
+    pub fn classifier_from(
+        tensors: &SafeTensors<'_>,
+        num_heads: usize,
+        num_hidden_layers: usize,
+        device: &Device,
+    ) -> Result<AlbertClassifier<Tensor>, SmeltError> {
+        let prefix = "albert.embeddings";
+        let embeddings = BertEmbeddings::new(
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.word_embeddings.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.position_embeddings.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.token_type_embeddings.weight"),
+                device,
+            )?),
+            load_layer_norm(
+                tensors,
+                &format!("{prefix}.LayerNorm"),
+                LAYER_NORM_EPS,
+                device,
+            )?,
+        )
+        .with_projection(load_linear(
+            tensors,
+            "albert.encoder.embedding_hidden_mapping_in",
+            device,
+        )?);
+
+        let layer_prefix = |group: usize, index: usize| {
+            format!("albert.encoder.albert_layer_groups.{group}.albert_layers.{index}")
+        };
+        let has_layer = |group: usize, index: usize| {
+            let name = format!("{}.attention.query.weight", layer_prefix(group, index));
+            tensors.tensor(&name).is_ok()
+        };
+        let num_groups = (0..).take_while(|&group| has_layer(group, 0)).count();
+        let inner_group_num = (0..).take_while(|&index| has_layer(0, index)).count();
+        if num_groups == 0 || !num_hidden_layers.is_multiple_of(num_groups) {
+            return Err(SmeltError::InvalidConfig(format!(
+                "{num_hidden_layers} layers in {num_groups} groups"
+            )));
+        }
+        let mut layers = vec![];
+        for group in 0..num_groups {
+            for index in 0..inner_group_num {
+                let prefix = layer_prefix(group, index);
+                let attention = BertAttention::new(
+                    load_linear(tensors, &format!("{prefix}.attention.query"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.key"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.value"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.dense"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.attention.LayerNorm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                );
+                let mlp = Mlp::new(
+                    load_linear(tensors, &format!("{prefix}.ffn"), device)?,
+                    load_linear(tensors, &format!("{prefix}.ffn_output"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.full_layer_layer_norm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                );
+                layers.push(BertLayer::new(attention, mlp));
+            }
+        }
+        // The layers of the group `depth / (num_hidden_layers / num_groups)` at every
+        // depth.
+        let layers_per_group = num_hidden_layers / num_groups;
+        let order = (0..num_hidden_layers)
+            .flat_map(|depth| {
+                let group = depth / layers_per_group;
+                (0..inner_group_num).map(move |index| group * inner_group_num + index)
+            })
+            .collect();
+        let encoder = BertEncoder::shared(layers, order)?;
+
+        let bert = Bert::new(embeddings, encoder);
+        let pooler = BertPooler::new(load_linear(tensors, "albert.pooler", device)?);
+        let mut model =
+            AlbertClassifier::new(bert, pooler, load_linear(tensors, "classifier", device)?)
+                .with_dropout(Dropout::new(CLASSIFIER_DROPOUT)?);
+        model.set_num_heads(num_heads);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "cpu", feature = "safetensors"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::tests::{checkpoint, layer_norm_tensors, linear_tensors};
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The tensors of an ALBERT of `num_groups` groups of one layer of width 4, whose
+    /// embeddings of width 2 are projected.
+    fn albert(num_groups: usize) -> Vec<(String, Vec<usize>)> {
+        let prefix = "albert.embeddings";
+        let mut tensors = vec![
+            (format!("{prefix}.word_embeddings.weight"), vec![10, 2]),
+            (format!("{prefix}.position_embeddings.weight"), vec![16, 2]),
+            (format!("{prefix}.token_type_embeddings.weight"), vec![2, 2]),
+        ];
+        tensors.extend(layer_norm_tensors(&format!("{prefix}.LayerNorm"), 2));
+        tensors.extend(linear_tensors(
+            "albert.encoder.embedding_hidden_mapping_in",
+            4,
+            2,
+        ));
+        for group in 0..num_groups {
+            let prefix = format!("albert.encoder.albert_layer_groups.{group}.albert_layers.0");
+            for name in ["query", "key", "value", "dense"] {
+                tensors.extend(linear_tensors(&format!("{prefix}.attention.{name}"), 4, 4));
+            }
+            tensors.extend(layer_norm_tensors(
+                &format!("{prefix}.attention.LayerNorm"),
+                4,
+            ));
+            tensors.extend(linear_tensors(&format!("{prefix}.ffn"), 8, 4));
+            tensors.extend(linear_tensors(&format!("{prefix}.ffn_output"), 4, 8));
+            tensors.extend(layer_norm_tensors(
+                &format!("{prefix}.full_layer_layer_norm"),
+                4,
+            ));
+        }
+        tensors.extend(linear_tensors("albert.pooler", 4, 4));
+        tensors.extend(linear_tensors("classifier", 2, 4));
+        tensors
+    }
+
+    fn load(
+        tensors: &[(String, Vec<usize>)],
+        num_hidden_layers: usize,
+    ) -> Result<AlbertClassifier<Tensor>, SmeltError> {
+        let buffer = checkpoint(tensors);
+        let tensors = SafeTensors::deserialize(&buffer).unwrap();
+        classifier_from(&tensors, 2, num_hidden_layers, &Device::cpu())
+    }
+
+    fn hidden_states(model: &AlbertClassifier<Tensor>) -> Vec<f32> {
+        let mut ctx = model
+            .new_context(vec![1, 2, 3], vec![0, 1, 2], vec![0; 3], 2)
+            .unwrap();
+        model.forward(&mut ctx).unwrap();
+        assert_eq!(ctx.hidden_states().shape(), [3, 4]);
+        assert_eq!(ctx.probs().shape(), [1, 2]);
+        ctx.hidden_states().cpu_data().unwrap()
+    }
+
+    #[test]
+    fn test_classifier_from() {
+        // The single layer stored in the checkpoint runs at every depth.
+        let shared = hidden_states(&load(&albert(1), 2).unwrap());
+        assert_ne!(shared, hidden_states(&load(&albert(1), 1).unwrap()));
+        // The same as two groups of the same weights, one per depth.
+        assert_eq!(shared, hidden_states(&load(&albert(2), 2).unwrap()));
+
+        assert!(matches!(
+            load(&albert(2), 3),
+            Err(SmeltError::InvalidConfig(_))
+        ));
+        let mut tensors = albert(1);
+        tensors.retain(|(name, _)| !name.starts_with("albert.encoder.embedding_hidden"));
+        assert!(matches!(
+            load(&tensors, 2),
+            Err(SmeltError::MissingTensor(_))
+        ));
+    }
+}
@@ -0,0 +1,864 @@
+use crate::nn::generation::{generate, GenerationConfig, Seq2Seq};
+use crate::nn::layers::{Dropout, Embedding, KvCache, LayerNorm, Linear, MultiHeadAttention};
+use crate::traits::{
+    Activation, Device, Tensor, TensorArgmax, TensorHeads, TensorNarrow, TensorOps,
+    TensorPositionMask, TensorReshape,
+};
+use crate::SmeltError;
+
+/// The operations of BART, on top of the common ones: the heads of the attention, the
+/// writes and the masks of the [KvCache] and the greedy decoding of [generate]. The cpu, cuda and
+/// backend f32 tensors implement them.
+pub trait BartOps<T: Tensor>:
+    TensorOps<T>
+    + TensorHeads<T>
+    + TensorNarrow<T>
+    + TensorReshape<T>
+    + TensorPositionMask<T>
+    + TensorArgmax<T>
+{
+}
+
+impl<T> BartOps<T> for T where
+    T: Tensor
+        + TensorOps<T>
+        + TensorHeads<T>
+        + TensorNarrow<T>
+        + TensorReshape<T>
+        + TensorPositionMask<T>
+        + TensorArgmax<T>
+{
+}
+
+/// The learned positions of BART start at this row of their table, the first ones were
+/// reserved for the padding.
+const POSITION_OFFSET: usize = 2;
+
+/// The configuration of a BART or mBART checkpoint, the `config.json` of transformers.
+/// ```
+/// use smelte_rs::nn::models::bart::BartConfig;
+///
+/// // mBART-50 translating to French.
+/// let config = BartConfig {
+///     forced_bos_token_id: Some(250008),
+///     ..BartConfig::mbart(16)
+/// };
+/// assert!(config.pre_norm && config.scale_embedding);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BartConfig {
+    /// The number of heads of the encoder, `encoder_attention_heads`
+    pub encoder_attention_heads: usize,
+    /// The number of heads of the decoder, `decoder_attention_heads`
+    pub decoder_attention_heads: usize,
+    /// The activation of the feed-forward layers, `activation_function`
+    pub activation: Activation,
+    /// Whether the token embeddings are scaled by `sqrt(d_model)`, `scale_embedding`
+    pub scale_embedding: bool,
+    /// Whether the layer norms are applied to the inputs of the attentions and the
+    /// feed-forward layers, the encoder and the decoder ending with one more, like
+    /// mBART, or to their outputs like BART
+    pub pre_norm: bool,
+    /// The first token of the decoder, the eos token of BART
+    pub decoder_start_token_id: usize,
+    /// The token ending the generation
+    pub eos_token_id: usize,
+    /// The first generated token, see [GenerationConfig::forced_bos_token_id]
+    pub forced_bos_token_id: Option<usize>,
+    /// The probability of the dropouts of the embeddings and of the output of every
+    /// layer, `dropout`
+    pub dropout: f32,
+    /// The probability of the dropout of the attention probabilities,
+    /// `attention_dropout`
+    pub attention_dropout: f32,
+    /// The probability of the dropout after the activation of the feed-forward layers,
+    /// `activation_dropout`
+    pub activation_dropout: f32,
+}
+
+impl BartConfig {
+    /// The configuration of BART with `num_heads` heads, like its summarizers.
+    pub fn new(num_heads: usize) -> Self {
+        Self {
+            encoder_attention_heads: num_heads,
+            decoder_attention_heads: num_heads,
+            activation: Activation::Gelu,
+            scale_embedding: false,
+            pre_norm: false,
+            decoder_start_token_id: 2,
+            eos_token_id: 2,
+            forced_bos_token_id: Some(0),
+            dropout: 0.1,
+            attention_dropout: 0.0,
+            activation_dropout: 0.0,
+        }
+    }
+
+    /// The configuration of mBART with `num_heads` heads, the `forced_bos_token_id`
+    /// being the target language of mBART-50.
+    pub fn mbart(num_heads: usize) -> Self {
+        Self {
+            scale_embedding: true,
+            pre_norm: true,
+            forced_bos_token_id: None,
+            ..Self::new(num_heads)
+        }
+    }
+}
+
+/// The embeddings of the encoder or the decoder of BART: the shared token embeddings,
+/// the learned positions of the stack and a layer norm.
+#[derive(Clone)]
+pub struct BartEmbeddings<T: Tensor> {
+    tokens: Embedding<T>,
+    positions: Embedding<T>,
+    scale: Option<f32>,
+    layer_norm: LayerNorm<T>,
+    dropout: Dropout,
+}
+
+impl<T: Tensor + BartOps<T>> BartEmbeddings<T> {
+    /// The embeddings of the `tokens` (vocab_size, d_model) and the `positions`
+    /// (max_position_embeddings + 2, d_model), the first of them being at the row 2.
+    pub fn new(tokens: Embedding<T>, positions: Embedding<T>, layer_norm: LayerNorm<T>) -> Self {
+        Self {
+            tokens,
+            positions,
+            scale: None,
+            layer_norm,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the normalized embeddings with `dropout` in train mode.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// Scales the token embeddings by `scale`, the `sqrt(d_model)` of `scale_embedding`.
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    /// The token embeddings
+    pub fn tokens(&self) -> &Embedding<T> {
+        &self.tokens
+    }
+
+    /// The embeddings (sequence_length, d_model) of `input_ids`, following
+    /// `past_length` tokens. Fails with [SmeltError::OutOfVocabulary] past the last
+    /// position of the table.
+    pub fn forward(&self, input_ids: &[usize], past_length: usize) -> Result<T, SmeltError> {
+        let weight = self.tokens.weight();
+        let shape = vec![input_ids.len(), weight.shape()[1]];
+        let device = weight.device();
+        let mut hidden_states = device.zeros(shape.clone())?;
+        let mut positions = device.zeros(shape)?;
+        let start = past_length + POSITION_OFFSET;
+        let position_ids: Vec<usize> = (start..start + input_ids.len()).collect();
+        self.positions.forward(&position_ids, &mut positions)?;
+        self.tokens.forward(input_ids, &mut hidden_states)?;
+        if let Some(scale) = self.scale {
+            T::mul_scalar(&mut hidden_states, scale)?;
+        }
+        T::add(&positions, &mut hidden_states)?;
+        self.layer_norm.forward(&mut hidden_states)?;
+        self.dropout.forward(&mut hidden_states)?;
+        Ok(hidden_states)
+    }
+}
+
+/// A layer of the encoder or the decoder of BART: the self-attention, the attention to
+/// the encoder states (decoders only) and the feed-forward layer, each added to its
+/// input and followed by a layer norm, or applied to the layer norm of its input with
+/// [BartLayer::with_pre_norm].
+#[derive(Clone)]
+pub struct BartLayer<T: Tensor> {
+    self_attention: MultiHeadAttention<T>,
+    self_attention_norm: LayerNorm<T>,
+    cross_attention: Option<(MultiHeadAttention<T>, LayerNorm<T>)>,
+    fc1: Linear<T>,
+    fc2: Linear<T>,
+    activation: Activation,
+    final_layer_norm: LayerNorm<T>,
+    pre_norm: bool,
+    // The dropout of the output of every sublayer, then of the activation
+    dropout: Dropout,
+    activation_dropout: Dropout,
+}
+
+impl<T: Tensor + BartOps<T>> BartLayer<T> {
+    /// The layer of an encoder, its feed-forward layer being `fc2(gelu(fc1(x)))`.
+    pub fn new(
+        self_attention: MultiHeadAttention<T>,
+        self_attention_norm: LayerNorm<T>,
+        fc1: Linear<T>,
+        fc2: Linear<T>,
+        final_layer_norm: LayerNorm<T>,
+    ) -> Self {
+        Self {
+            self_attention,
+            self_attention_norm,
+            cross_attention: None,
+            fc1,
+            fc2,
+            activation: Activation::Gelu,
+            final_layer_norm,
+            pre_norm: false,
+            dropout: Dropout::default(),
+            activation_dropout: Dropout::default(),
+        }
+    }
+
+    /// The layer of a decoder, attending to the encoder states with `attention` after
+    /// its causal self-attention.
+    pub fn with_cross_attention(
+        mut self,
+        attention: MultiHeadAttention<T>,
+        norm: LayerNorm<T>,
+    ) -> Self {
+        self.cross_attention = Some((attention, norm));
+        self
+    }
+
+    /// Replaces the activation of the feed-forward layer.
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    /// Applies the layer norms to the inputs of the sublayers, like mBART.
+    pub fn with_pre_norm(mut self) -> Self {
+        self.pre_norm = true;
+        self
+    }
+
+    /// Drops the output of every sublayer with `dropout`, before it is added to its
+    /// input, and the activation of the feed-forward layer with `activation_dropout` in
+    /// train mode.
+    pub fn with_dropout(mut self, dropout: Dropout, activation_dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self.activation_dropout = activation_dropout;
+        self
+    }
+
+    /// Switches the dropouts of the layer and its attentions to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.self_attention.set_training(training);
+        if let Some((attention, _)) = &mut self.cross_attention {
+            attention.set_training(training);
+        }
+        self.dropout.set_training(training);
+        self.activation_dropout.set_training(training);
+    }
+
+    /// Whether the layer attends to the encoder states
+    pub fn is_decoder(&self) -> bool {
+        self.cross_attention.is_some()
+    }
+
+    /// The layer of `hidden_states` (sequence_length, d_model) in place. A decoder layer
+    /// attends to the `encoder_states` (encoder_length, d_model), and caches its keys and
+    /// values in the layer `layer` of `cache`.
+    fn forward(
+        &self,
+        hidden_states: &mut T,
+        encoder_states: Option<&T>,
+        mut cache: Option<(&mut KvCache<T>, usize)>,
+    ) -> Result<(), SmeltError> {
+        let shape = hidden_states.shape().to_vec();
+        let sequence_length = shape[0];
+        let device = hidden_states.device();
+        let mut buffers = (device.zeros(shape.clone())?, device.zeros(shape)?);
+        let mut intermediate = device.zeros(vec![sequence_length, self.fc1.shape()[0]])?;
+
+        let self_attention = &self.self_attention;
+        self.sublayer(
+            hidden_states,
+            &self.self_attention_norm,
+            &mut buffers,
+            |x, out| match &mut cache {
+                Some((cache, layer)) => self_attention.forward_cached(x, None, cache, *layer, out),
+                None => {
+                    let mut ctx = self_attention.context(sequence_length, x.device())?;
+                    self_attention.forward(x, None, &mut ctx, out)
+                }
+            },
+        )?;
+
+        if let Some((attention, norm)) = &self.cross_attention {
+            let Some(encoder_states) = encoder_states else {
+                return Err(SmeltError::InvalidConfig(
+                    "a decoder layer attends to the encoder states".to_string(),
+                ));
+            };
+            let encoder_length = encoder_states.shape()[0];
+            self.sublayer(
+                hidden_states,
+                norm,
+                &mut buffers,
+                |x, out| match &mut cache {
+                    Some((cache, layer)) => {
+                        attention.forward_cross_cached(x, encoder_states, None, cache, *layer, out)
+                    }
+                    None => {
+                        let mut ctx =
+                            attention.cross_context(sequence_length, encoder_length, x.device())?;
+                        attention.forward_cross(x, encoder_states, None, &mut ctx, out)
+                    }
+                },
+            )?;
+        }
+
+        self.sublayer(
+            hidden_states,
+            &self.final_layer_norm,
+            &mut buffers,
+            |x, out| {
+                self.fc1.forward(x, &mut intermediate)?;
+                T::activation(&mut intermediate, self.activation)?;
+                self.activation_dropout.forward(&mut intermediate)?;
+                self.fc2.forward(&intermediate, out)
+            },
+        )
+    }
+
+    /// Adds `f(x)` to `hidden_states`, `x` being their layer norm or the layer norm
+    /// following the sum, with the `(normed, out)` buffers.
+    fn sublayer(
+        &self,
+        hidden_states: &mut T,
+        norm: &LayerNorm<T>,
+        (normed, out): &mut (T, T),
+        f: impl FnOnce(&T, &mut T) -> Result<(), SmeltError>,
+    ) -> Result<(), SmeltError> {
+        if self.pre_norm {
+            T::copy(hidden_states, normed)?;
+            norm.forward(normed)?;
+            f(normed, out)?;
+            self.dropout.forward(out)?;
+            T::add(out, hidden_states)
+        } else {
+            f(hidden_states, out)?;
+            self.dropout.forward(out)?;
+            T::add(out, hidden_states)?;
+            norm.forward(hidden_states)
+        }
+    }
+}
+
+/// The encoder or the decoder of BART: its embeddings and layers, followed by a layer
+/// norm for mBART.
+#[derive(Clone)]
+pub struct BartStack<T: Tensor> {
+    embeddings: BartEmbeddings<T>,
+    layers: Vec<BartLayer<T>>,
+    layer_norm: Option<LayerNorm<T>>,
+}
+
+impl<T: Tensor + BartOps<T>> BartStack<T> {
+    /// The stack of `layers`, all of them encoder layers or decoder layers.
+    pub fn new(
+        embeddings: BartEmbeddings<T>,
+        layers: Vec<BartLayer<T>>,
+    ) -> Result<Self, SmeltError> {
+        let Some(first) = layers.first() else {
+            return Err(SmeltError::InvalidConfig(
+                "a stack without layers".to_string(),
+            ));
+        };
+        if layers
+            .iter()
+            .any(|layer| layer.is_decoder() != first.is_decoder())
+        {
+            return Err(SmeltError::InvalidConfig(
+                "a stack mixing encoder and decoder layers".to_string(),
+            ));
+        }
+        Ok(Self {
+            embeddings,
+            layers,
+            layer_norm: None,
+        })
+    }
+
+    /// Ends the stack with `layer_norm`, like mBART.
+    pub fn with_layer_norm(mut self, layer_norm: LayerNorm<T>) -> Self {
+        self.layer_norm = Some(layer_norm);
+        self
+    }
+
+    /// The embeddings
+    pub fn embeddings(&self) -> &BartEmbeddings<T> {
+        &self.embeddings
+    }
+
+    /// The number of layers
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Switches the dropouts of the embeddings and of every layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.embeddings.set_training(training);
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
+    /// Whether the layers attend to the encoder states
+    pub fn is_decoder(&self) -> bool {
+        self.layers[0].is_decoder()
+    }
+
+    /// The hidden states (sequence_length, d_model) of `input_ids`. A decoder attends
+    /// to the `encoder_states`, its `input_ids` following the tokens of `cache`, which
+    /// it extends.
+    pub fn forward(
+        &self,
+        input_ids: &[usize],
+        encoder_states: Option<&T>,
+        mut cache: Option<&mut KvCache<T>>,
+    ) -> Result<T, SmeltError> {
+        let past_length = cache.as_deref().map_or(0, KvCache::len);
+        let mut hidden_states = self.embeddings.forward(input_ids, past_length)?;
+        for (index, layer) in self.layers.iter().enumerate() {
+            let cache = cache.as_deref_mut().map(|cache| (cache, index));
+            layer.forward(&mut hidden_states, encoder_states, cache)?;
+        }
+        if let Some(layer_norm) = &self.layer_norm {
+            layer_norm.forward(&mut hidden_states)?;
+        }
+        Ok(hidden_states)
+    }
+}
+
+/// The BART or mBART encoder-decoder with its language modeling head, like
+/// `BartForConditionalGeneration`, for summarization (DistilBART) or translation, see
+/// [Seq2Seq].
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::nn::models::bart::{seq2seq_from, BartConfig};
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// // sshleifer/distilbart-cnn-12-6
+/// let model = seq2seq_from(&tensors, &BartConfig::new(16), &Device::cpu()).unwrap();
+/// let input_ids = [0, 133, 812, 9, 1470, 16, 2201, 4, 2];
+/// let summary_ids = model.generate(&input_ids, 142).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct BartForConditionalGeneration<T: Tensor> {
+    encoder: BartStack<T>,
+    decoder: BartStack<T>,
+    lm_head: Linear<T>,
+    decoder_start_token_id: usize,
+    eos_token_id: usize,
+    forced_bos_token_id: Option<usize>,
+}
+
+impl<T: Tensor + BartOps<T>> BartForConditionalGeneration<T> {
+    /// The model of the `encoder`, the `decoder` and the `lm_head` (vocab_size,
+    /// d_model), usually tied to the token embeddings with the `final_logits_bias` as
+    /// its bias. The special tokens are the ones of `config`.
+    pub fn new(
+        encoder: BartStack<T>,
+        decoder: BartStack<T>,
+        lm_head: Linear<T>,
+        config: &BartConfig,
+    ) -> Result<Self, SmeltError> {
+        if encoder.is_decoder() || !decoder.is_decoder() {
+            return Err(SmeltError::InvalidConfig(
+                "an encoder of encoder layers and a decoder of decoder layers".to_string(),
+            ));
+        }
+        Ok(Self {
+            encoder,
+            decoder,
+            lm_head,
+            decoder_start_token_id: config.decoder_start_token_id,
+            eos_token_id: config.eos_token_id,
+            forced_bos_token_id: config.forced_bos_token_id,
+        })
+    }
+
+    /// The encoder
+    pub fn encoder(&self) -> &BartStack<T> {
+        &self.encoder
+    }
+
+    /// Switches the dropouts of the encoder and the decoder to train or eval mode, the
+    /// models being built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.encoder.set_training(training);
+        self.decoder.set_training(training);
+    }
+
+    /// The greedy decoding of `input_ids`, up to `max_new_tokens` tokens or the eos
+    /// token, see [generate]. The summarizers of the hub are usually run with a beam
+    /// search, their greedy summaries are close but not always the same.
+    pub fn generate(
+        &self,
+        input_ids: &[usize],
+        max_new_tokens: usize,
+    ) -> Result<Vec<usize>, SmeltError> {
+        let config = GenerationConfig {
+            forced_bos_token_id: self.forced_bos_token_id,
+            ..GenerationConfig::new(
+                max_new_tokens,
+                self.decoder_start_token_id,
+                self.eos_token_id,
+            )
+        };
+        generate(self, input_ids, &config)
+    }
+}
+
+impl<T: Tensor + BartOps<T>> Seq2Seq<T> for BartForConditionalGeneration<T> {
+    fn encode(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        self.encoder.forward(input_ids, None, None)
+    }
+
+    fn new_cache(&self) -> KvCache<T> {
+        KvCache::new(self.decoder.num_layers())
+    }
+
+    /// The keys and values of the encoder states are projected by the first call after
+    /// [KvCache::clear], then reused from `cache`.
+    fn decode(
+        &self,
+        decoder_input_ids: &[usize],
+        encoder_states: &T,
+        cache: &mut KvCache<T>,
+    ) -> Result<T, SmeltError> {
+        let hidden_states =
+            self.decoder
+                .forward(decoder_input_ids, Some(encoder_states), Some(cache))?;
+        let vocab_size = self.lm_head.shape()[0];
+        let device = hidden_states.device();
+        let mut logits = device.zeros(vec![decoder_input_ids.len(), vocab_size])?;
+        self.lm_head.forward(&hidden_states, &mut logits)?;
+        Ok(logits)
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::seq2seq_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_layer_norm, load_linear, load_tensor, to_f32};
+    use crate::nn::layers::{AttentionConfig, QkvProjection};
+    use safetensors::SafeTensors;
+
+    /// The layer norms of BART, like the `nn.LayerNorm` of transformers.
+    const LAYER_NORM_EPS: f32 = 1e-5;
+
+    /// Creates a [BartForConditionalGeneration] of the `config` on `device`, from the
+    /// tensors of a `BartForConditionalGeneration` or `MBartForConditionalGeneration`
+    /// checkpoint: `model.shared`, `model.encoder.*`, `model.decoder.*` and
+    /// `final_logits_bias`, the language modeling head being tied to the shared
+    /// embeddings. The number of layers is the one of the checkpoint.
+    pub fn seq2seq_from(
+        tensors: &SafeTensors<'_>,
+        config: &BartConfig,
+        device: &Device,
+    ) -> Result<BartForConditionalGeneration<Tensor>, SmeltError> {
+        let has_tensor = |name: &str| tensors.tensor(name).is_ok();
+        let attention = |prefix: String,
+                         num_heads: usize,
+                         causal: bool|
+         -> Result<MultiHeadAttention<Tensor>, SmeltError> {
+            let dropout = Dropout::new(config.attention_dropout)?;
+            let query = load_linear(tensors, &format!("{prefix}.q_proj"), device)?;
+            let config = AttentionConfig {
+                causal,
+                ..AttentionConfig::new(num_heads, query.shape()[0] / num_heads)
+            };
+            let qkv = QkvProjection::Separate {
+                query,
+                key: load_linear(tensors, &format!("{prefix}.k_proj"), device)?,
+                value: load_linear(tensors, &format!("{prefix}.v_proj"), device)?,
+            };
+            let output = load_linear(tensors, &format!("{prefix}.out_proj"), device)?;
+            Ok(MultiHeadAttention::new(qkv, Some(output), config)?.with_dropout(dropout))
+        };
+
+        // The shared embeddings are also stored as the `embed_tokens` of the stacks.
+        let shared = if has_tensor("model.shared.weight") {
+            "model.shared.weight"
+        } else {
+            "model.encoder.embed_tokens.weight"
+        };
+        let shared = Embedding::new(load_tensor(tensors, shared, device)?);
+        let d_model = shared.weight().shape()[1];
+
+        let stack = |name: &str, num_heads: usize| -> Result<BartStack<Tensor>, SmeltError> {
+            let is_decoder = name == "decoder";
+            let prefix = format!("model.{name}");
+            let mut embeddings = BartEmbeddings::new(
+                shared.clone(),
+                Embedding::new(load_tensor(
+                    tensors,
+                    &format!("{prefix}.embed_positions.weight"),
+                    device,
+                )?),
+                load_layer_norm(
+                    tensors,
+                    &format!("{prefix}.layernorm_embedding"),
+                    LAYER_NORM_EPS,
+                    device,
+                )?,
+            )
+            .with_dropout(Dropout::new(config.dropout)?);
+            if config.scale_embedding {
+                embeddings = embeddings.with_scale((d_model as f32).sqrt());
+            }
+
+            let has_layer = |index: usize| {
+                has_tensor(&format!("{prefix}.layers.{index}.self_attn.q_proj.weight"))
+            };
+            let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+            if num_layers == 0 {
+                return Err(SmeltError::MissingTensor(format!(
+                    "{prefix}.layers.0.self_attn.q_proj.weight"
+                )));
+            }
+            let layers = (0..num_layers)
+                .map(|index| {
+                    let prefix = format!("{prefix}.layers.{index}");
+                    let mut layer = BartLayer::new(
+                        attention(format!("{prefix}.self_attn"), num_heads, is_decoder)?,
+                        load_layer_norm(
+                            tensors,
+                            &format!("{prefix}.self_attn_layer_norm"),
+                            LAYER_NORM_EPS,
+                            device,
+                        )?,
+                        load_linear(tensors, &format!("{prefix}.fc1"), device)?,
+                        load_linear(tensors, &format!("{prefix}.fc2"), device)?,
+                        load_layer_norm(
+                            tensors,
+                            &format!("{prefix}.final_layer_norm"),
+                            LAYER_NORM_EPS,
+                            device,
+                        )?,
+                    )
+                    .with_activation(config.activation)
+                    .with_dropout(
+                        Dropout::new(config.dropout)?,
+                        Dropout::new(config.activation_dropout)?,
+                    );
+                    if is_decoder {
+                        layer = layer.with_cross_attention(
+                            attention(format!("{prefix}.encoder_attn"), num_heads, false)?,
+                            load_layer_norm(
+                                tensors,
+                                &format!("{prefix}.encoder_attn_layer_norm"),
+                                LAYER_NORM_EPS,
+                                device,
+                            )?,
+                        );
+                    }
+                    if config.pre_norm {
+                        layer = layer.with_pre_norm();
+                    }
+                    Ok(layer)
+                })
+                .collect::<Result<_, SmeltError>>()?;
+            let stack = BartStack::new(embeddings, layers)?;
+            if !config.pre_norm {
+                return Ok(stack);
+            }
+            Ok(stack.with_layer_norm(load_layer_norm(
+                tensors,
+                &format!("{prefix}.layer_norm"),
+                LAYER_NORM_EPS,
+                device,
+            )?))
+        };
+        let encoder = stack("encoder", config.encoder_attention_heads)?;
+        let decoder = stack("decoder", config.decoder_attention_heads)?;
+
+        // The `final_logits_bias` (1, vocab_size), zeros for most checkpoints.
+        let vocab_size = shared.weight().shape()[0];
+        let bias = match tensors.tensor("final_logits_bias") {
+            Ok(view) => Tensor::from_cpu(to_f32(&view)?.into_owned(), vec![vocab_size], device)?,
+            Err(_) => Tensor::zeros(vec![vocab_size], device)?,
+        };
+        let lm_head = Linear::tied(&shared, bias);
+        BartForConditionalGeneration::new(encoder, decoder, lm_head, config)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+    use crate::nn::layers::AttentionConfig;
+    use crate::tests::{layer_norm, linear};
+
+    const D_MODEL: usize = 4;
+    const VOCAB_SIZE: usize = 6;
+
+    fn attention(seed: u64, causal: bool) -> MultiHeadAttention<Tensor> {
+        let config = AttentionConfig {
+            causal,
+            ..AttentionConfig::new(2, 2)
+        };
+        crate::tests::attention(config, seed)
+    }
+
+    fn embeddings(seed: u64) -> BartEmbeddings<Tensor> {
+        let tokens = Embedding::new(Tensor::rand_normal(vec![VOCAB_SIZE, D_MODEL], 0));
+        let positions = Embedding::new(Tensor::rand_normal(vec![8, D_MODEL], seed));
+        BartEmbeddings::new(tokens, positions, layer_norm(D_MODEL, 1e-5))
+    }
+
+    fn model(pre_norm: bool) -> BartForConditionalGeneration<Tensor> {
+        let layer = |seed: u64, is_decoder: bool| {
+            let mut layer = BartLayer::new(
+                attention(seed, is_decoder),
+                layer_norm(D_MODEL, 1e-5),
+                linear(8, D_MODEL, seed + 4),
+                linear(D_MODEL, 8, seed + 5),
+                layer_norm(D_MODEL, 1e-5),
+            );
+            if is_decoder {
+                layer = layer
+                    .with_cross_attention(attention(seed + 6, false), layer_norm(D_MODEL, 1e-5));
+            }
+            if pre_norm {
+                layer = layer.with_pre_norm();
+            }
+            layer
+        };
+        let stack = |seed: u64, is_decoder: bool| {
+            let layers = vec![layer(seed, is_decoder), layer(seed + 10, is_decoder)];
+            let stack = BartStack::new(embeddings(seed), layers).unwrap();
+            if !pre_norm {
+                return stack;
+            }
+            stack.with_layer_norm(layer_norm(D_MODEL, 1e-5))
+        };
+        let (encoder, decoder) = (stack(10, false), stack(100, true));
+        let lm_head = Linear::tied(
+            encoder.embeddings().tokens(),
+            Tensor::zeros(vec![VOCAB_SIZE]),
+        );
+        BartForConditionalGeneration::new(encoder, decoder, lm_head, &BartConfig::new(2)).unwrap()
+    }
+
+    #[test]
+    fn test_bart_embeddings() {
+        let tokens = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+        let positions: Vec<f32> = (0..8).map(|i| 10.0 * i as f32).collect();
+        let positions = Tensor::new(positions, vec![4, 2]).unwrap();
+        let weight = Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+        let embeddings = BartEmbeddings::new(
+            Embedding::new(tokens),
+            Embedding::new(positions),
+            LayerNorm::new(weight, Tensor::new(vec![0.0, 5.0], vec![2]).unwrap(), 1e-5),
+        )
+        .with_scale(2.0);
+        // [2, 4] + [60, 70] after a token, normalized to [-1, 1], plus the bias.
+        let hidden_states = embeddings.forward(&[0], 1).unwrap();
+        let rounded: Vec<_> = hidden_states.data().iter().map(|v| v.round()).collect();
+        assert_eq!(rounded, [-1.0, 6.0]);
+        // The table has 2 positions.
+        assert!(embeddings.forward(&[0, 1], 1).is_err());
+    }
+
+    #[test]
+    fn test_bart_decode() {
+        for pre_norm in [false, true] {
+            let model = model(pre_norm);
+            let encoder_states = model.encode(&[0, 3, 4, 2]).unwrap();
+            assert_eq!(encoder_states.shape(), [4, D_MODEL]);
+
+            // The cached decoding of a token at a time is the decoding of the whole
+            // sequence.
+            let decoder_input_ids = [2, 0, 5, 3];
+            let mut cache = model.new_cache();
+            let logits = model
+                .decode(&decoder_input_ids, &encoder_states, &mut cache)
+                .unwrap();
+            let mut cache = model.new_cache();
+            for (i, &id) in decoder_input_ids.iter().enumerate() {
+                let step = model.decode(&[id], &encoder_states, &mut cache).unwrap();
+                let expected = &logits.data()[i * VOCAB_SIZE..(i + 1) * VOCAB_SIZE];
+                for (a, b) in step.data().iter().zip(expected) {
+                    assert!((a - b).abs() < 1e-4, "{a} != {b}");
+                }
+            }
+
+            // The bos token is forced first.
+            let output_ids = model.generate(&[0, 3, 4, 2], 3).unwrap();
+            assert_eq!(output_ids.first(), Some(&0));
+            assert!(output_ids.len() <= 3 && !output_ids.contains(&2));
+            // 8 positions minus the offset of 2.
+            assert!(model.encode(&[0; 7]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_bart_stack_errors() {
+        let encoder_layer = BartLayer::new(
+            attention(0, false),
+            layer_norm(D_MODEL, 1e-5),
+            linear(8, D_MODEL, 4),
+            linear(D_MODEL, 8, 5),
+            layer_norm(D_MODEL, 1e-5),
+        );
+        let decoder_layer = encoder_layer
+            .clone()
+            .with_cross_attention(attention(6, false), layer_norm(D_MODEL, 1e-5));
+        assert!(BartStack::new(embeddings(0), vec![]).is_err());
+        let layers = vec![encoder_layer.clone(), decoder_layer.clone()];
+        assert!(BartStack::new(embeddings(0), layers).is_err());
+
+        let encoder = BartStack::new(embeddings(0), vec![encoder_layer]).unwrap();
+        let decoder = BartStack::new(embeddings(1), vec![decoder_layer]).unwrap();
+        assert!(decoder.forward(&[0], None, None).is_err());
+        let lm_head = Linear::tied(
+            encoder.embeddings().tokens(),
+            Tensor::zeros(vec![VOCAB_SIZE]),
+        );
+        let config = BartConfig::new(2);
+        assert!(BartForConditionalGeneration::new(decoder, encoder, lm_head, &config).is_err());
+    }
+}
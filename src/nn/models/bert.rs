@@ -1,5 +1,14 @@
 #[cfg(feature = "cpu")]
-use crate::cpu::f32::{matmul, matmul_t, softmax, Tensor as F32Tensor};
+use crate::cpu::f32::Tensor as F32Tensor;
+
+#[cfg(feature = "cpu")]
+use crate::cpu::f64::Tensor as F64Tensor;
+
+#[cfg(all(feature = "cpu", feature = "f16"))]
+use crate::cpu::f16::Tensor as F16Tensor;
+
+#[cfg(all(feature = "cpu", feature = "bf16"))]
+use crate::cpu::bf16::Tensor as BF16Tensor;
 
 #[cfg(feature = "cuda")]
 use crate::gpu::f32 as cuda_f32;
@@ -7,9 +16,52 @@ use crate::gpu::f32 as cuda_f32;
 #[cfg(feature = "cuda")]
 use crate::gpu::f32::Tensor as F32CudaTensor;
 
-use crate::nn::layers::{Embedding, LayerNorm, Linear};
-use crate::traits::{Device, Tensor, TensorOps};
+#[cfg(all(feature = "cuda", feature = "f16"))]
+use crate::gpu::f16 as cuda_f16;
+
+#[cfg(all(feature = "cuda", feature = "f16"))]
+use crate::gpu::f16::Tensor as F16CudaTensor;
+
+#[cfg(all(feature = "cuda", feature = "bf16"))]
+use crate::gpu::bf16::Tensor as BF16CudaTensor;
+
+#[cfg(feature = "metal")]
+use crate::gpu::metal::f32 as metal_f32;
+
+#[cfg(feature = "metal")]
+use crate::gpu::metal::f32::Tensor as F32MetalTensor;
+
+#[cfg(feature = "wgpu")]
+use crate::gpu::wgpu::f32 as wgpu_f32;
+
+#[cfg(feature = "wgpu")]
+use crate::gpu::wgpu::f32::Tensor as F32WgpuTensor;
+
+#[cfg(feature = "opencl")]
+use crate::gpu::opencl::f32 as opencl_f32;
+
+#[cfg(feature = "opencl")]
+use crate::gpu::opencl::f32::Tensor as F32OpenClTensor;
+
+#[cfg(feature = "vulkan")]
+use crate::gpu::vulkan::f32 as vulkan_f32;
+
+#[cfg(feature = "vulkan")]
+use crate::gpu::vulkan::f32::Tensor as F32VulkanTensor;
+
+use crate::nn::ids::Ids;
+use crate::nn::layers::{
+    Alibi, Dropout, Embedding, LayerNorm, Linear, LogBucketPositions, LoraLinear,
+};
+use crate::nn::quantize::Quantize;
+pub use crate::traits::TensorHeads;
+use crate::traits::{
+    Activation, Device, DeviceCapture, FusedAttentionConfig, Precision, Replay, Tensor,
+    TensorFusedAttention, TensorOps, TensorPrecision, TensorQuantize, TensorToDevice,
+};
 use crate::SmeltError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 macro_rules! debug {
     // `()` indicates that the macro takes no argument.
@@ -38,31 +90,97 @@ pub struct BertContext<T: Tensor> {
     type_ids: Vec<usize>,
     position_ids: Vec<usize>,
     hidden_states: T,
+    // The embeddings (sequence_length, embedding_dim) and their scratch buffer, before
+    // their projection to the hidden states, when the two sizes differ like in ALBERT
+    embedding_states: Option<[T; 2]>,
     // Required to compute position_ids before adding into hidden_states
     // - Used in the MLP to prevent cloning the skip connection
     // - Used in the attention for the output Linear layer
     hidden_states_copy: T,
     // Store the hidden_states after the attention (prevents a clone in the skip connection)
     hidden_states_attn_output: T,
+    // The keys and values (sequence_length, num_kv_heads * head_dim) before splitting
+    // their heads, when they have fewer heads than the queries
+    kv_states: Option<T>,
     q_cache: T,
     // Store the k splitted_heads
     k_cache: T,
     // Store the k splitted_heads
     v_cache: T,
-    // Store the qk result
-    qk: T,
+    // The scores (num_heads, sequence_length, sequence_length), not allocated for a fused
+    // attention, see [TensorAttention::SCORES]
+    qk: Option<T>,
+    // The ALiBi bias (num_heads, sequence_length, sequence_length) added to qk, shared by
+    // the layers
+    alibi: Option<T>,
+    // The slopes (num_heads) of the ALiBi bias of a fused attention
+    alibi_slopes: Option<T>,
+    // The padding mask (sequence_length) of the keys, -inf for the pad tokens, see
+    // [BertContext::set_attention_mask]
+    attention_mask: Option<T>,
+    // The buffers of the disentangled attention of DeBERTa, see [RelativeEmbeddings]
+    relative: Option<RelativeContext<T>>,
     qkv: T,
     // Intermediate states (H, 4H)
     intermediate_states: T,
     pool: T,
     pool_output: T,
     probs: T,
+    // The buffers on the device of the layers after [BertClassifier::split_layers].
+    next_stage: Option<Box<BertContext<T>>>,
 }
 
 impl<T: Tensor> BertContext<T> {
     /// TODO
     pub fn probs(&self) -> &T {
-        &self.probs
+        match &self.next_stage {
+            Some(stage) => stage.probs(),
+            None => &self.probs,
+        }
+    }
+
+    /// The hidden states (sequence_length, hidden_dim) of the last layer after a forward
+    /// pass, to pool into a sentence embedding with [crate::nn::layers::Pooling].
+    pub fn hidden_states(&self) -> &T {
+        match &self.next_stage {
+            Some(stage) => stage.hidden_states(),
+            None => &self.hidden_states,
+        }
+    }
+
+    /// Masks the pad tokens, the tokens where `attention_mask` is 0 like in
+    /// transformers: no token attends to them, in every layer. `None` removes the mask.
+    pub fn set_attention_mask(
+        &mut self,
+        attention_mask: Option<&[usize]>,
+    ) -> Result<(), SmeltError> {
+        if let Some(stage) = &mut self.next_stage {
+            stage.set_attention_mask(attention_mask)?;
+        }
+        let Some(attention_mask) = attention_mask else {
+            self.attention_mask = None;
+            return Ok(());
+        };
+        let sequence_length = self.hidden_states.shape()[0];
+        if attention_mask.len() != sequence_length {
+            return Err(SmeltError::InvalidLength {
+                expected: sequence_length,
+                got: attention_mask.len(),
+            });
+        }
+        // The softmax of a row of -inf is NaN.
+        if attention_mask.iter().all(|&m| m == 0) {
+            return Err(SmeltError::InvalidConfig(
+                "the attention mask masks every token".to_string(),
+            ));
+        }
+        let data: Vec<f32> = attention_mask
+            .iter()
+            .map(|&m| if m == 0 { f32::NEG_INFINITY } else { 0.0 })
+            .collect();
+        let device = self.hidden_states.device();
+        self.attention_mask = Some(device.tensor(&data, vec![sequence_length])?);
+        Ok(())
     }
 }
 
@@ -70,93 +188,159 @@ impl<T: Tensor> BertContext<T> {
 mod cpu {
     use super::*;
 
-    pub(super) fn split_heads(q: &F32Tensor, out_q: &mut F32Tensor) -> Result<(), SmeltError> {
-        let num_heads = out_q.shape()[0];
-        let sequence_length = out_q.shape()[1];
-        let head_dim = out_q.shape()[2];
-        let hidden_dim = head_dim * num_heads;
-
-        (0..num_heads).for_each(|i| {
-            (0..sequence_length).for_each(|j| {
-                (0..head_dim).for_each(|k| {
-                    let index = j * hidden_dim + i * head_dim + k;
-                    let out_index = i * sequence_length * head_dim + j * head_dim + k;
-                    out_q.data_mut()[out_index] = q.data()[index];
-                });
-            });
-        });
-        Ok(())
+    /// Splits the columns `offset..` of the rows of `src` (sequence_length, width) into
+    /// the heads of `dst` (num_heads, sequence_length, head_dim), for any element type,
+    /// see [TensorHeads::split_heads_at].
+    pub(super) fn split_heads_data<E: Copy>(
+        src: &[E],
+        dst: &mut [E],
+        heads_shape: &[usize],
+        offset: usize,
+        num_src_heads: usize,
+    ) {
+        let (num_heads, sequence_length, head_dim) =
+            (heads_shape[0], heads_shape[1], heads_shape[2]);
+        let width = src.len() / sequence_length.max(1);
+        if offset == 0 && num_src_heads == num_heads && width == num_heads * head_dim {
+            let shape = [sequence_length, num_heads, head_dim];
+            crate::cpu::permute_data(src, dst, &shape, &[1, 0, 2]);
+            return;
+        }
+        let group = num_heads / num_src_heads;
+        for (i, head) in dst.chunks_mut(sequence_length * head_dim).enumerate() {
+            for (j, row) in head.chunks_mut(head_dim).enumerate() {
+                let start = j * width + offset + i / group * head_dim;
+                row.copy_from_slice(&src[start..start + head_dim]);
+            }
+        }
     }
 
-    #[inline]
-    pub(super) fn unsplit_heads(src: &F32Tensor, dst: &mut F32Tensor) -> Result<(), SmeltError> {
-        let num_heads = src.shape()[0];
-        let sequence_length = src.shape()[1];
-        let head_dim = src.shape()[2];
-        let hidden_dim = head_dim * num_heads;
-        (0..num_heads).for_each(|i| {
-            (0..sequence_length).for_each(|j| {
-                (0..head_dim).for_each(|k| {
-                    let in_index = i * sequence_length * head_dim + j * head_dim + k;
-                    let out_index = j * hidden_dim + i * head_dim + k;
-                    dst.data_mut()[out_index] = src.data()[in_index];
-                });
-            });
-        });
-        Ok(())
+    /// The inverse of [split_heads_data], `heads_shape` being the shape of `src`.
+    pub(super) fn unsplit_heads_data<E: Copy>(
+        src: &[E],
+        dst: &mut [E],
+        heads_shape: &[usize],
+        offset: usize,
+    ) {
+        let (num_heads, sequence_length, head_dim) =
+            (heads_shape[0], heads_shape[1], heads_shape[2]);
+        let width = dst.len() / sequence_length.max(1);
+        if offset == 0 && width == num_heads * head_dim {
+            crate::cpu::permute_data(src, dst, heads_shape, &[1, 0, 2]);
+            return;
+        }
+        for (i, head) in src.chunks(sequence_length * head_dim).enumerate() {
+            for (j, row) in head.chunks(head_dim).enumerate() {
+                let start = j * width + offset + i * head_dim;
+                dst[start..start + head_dim].copy_from_slice(row);
+            }
+        }
     }
 
-    fn attention(
-        q_weights: &Linear<F32Tensor>,
-        k_weights: &Linear<F32Tensor>,
-        v_weights: &Linear<F32Tensor>,
-        ctx: &mut BertContext<F32Tensor>,
+    pub(super) fn split_heads(
+        src: &F32Tensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F32Tensor,
     ) -> Result<(), SmeltError> {
-        q_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        split_heads(&ctx.hidden_states_copy, &mut ctx.q_cache)?;
-
-        debug!("Q head splitted", ctx.q_cache);
-
-        k_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        split_heads(&ctx.hidden_states_copy, &mut ctx.k_cache)?;
-
-        debug!("K head splitted", ctx.k_cache);
-
-        v_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        split_heads(&ctx.hidden_states_copy, &mut ctx.v_cache)?;
-
-        debug!("V head splitted", ctx.v_cache);
-
-        matmul_t(&ctx.q_cache, &ctx.k_cache, &mut ctx.qk).unwrap();
+        let heads_shape = dst.shape().to_vec();
+        crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        split_heads_data(
+            src.data(),
+            dst.data_mut(),
+            &heads_shape,
+            offset,
+            num_src_heads,
+        );
+        Ok(())
+    }
 
-        // let num_heads = ctx.q_cache.shape()[0];
-        // let sequence_length = ctx.q_cache.shape()[1];
-        let head_dim = ctx.q_cache.shape()[2];
-        // let hidden_dim = head_dim * num_heads;
-        let scale = (head_dim as f32).sqrt();
-        ctx.qk.data_mut().iter_mut().for_each(|v| *v /= scale);
+    /// Adds `c2p[h, i, indices[i, j]] + p2c[h, indices[i, j], j]` to the `scores`
+    /// (num_heads, query_length, key_length), for any element type, see
+    /// [TensorAttention::add_relative_scores].
+    pub(super) fn add_relative_scores_data<E: Copy + std::ops::AddAssign>(
+        c2p: &[E],
+        p2c: &[E],
+        indices: &[usize],
+        scores: &mut [E],
+        [query_length, key_length, num_positions]: [usize; 3],
+    ) {
+        let heads = scores
+            .chunks_mut((query_length * key_length).max(1))
+            .zip(c2p.chunks((query_length * num_positions).max(1)))
+            .zip(p2c.chunks((num_positions * key_length).max(1)));
+        for ((scores, c2p), p2c) in heads {
+            for (n, (score, &index)) in scores.iter_mut().zip(indices).enumerate() {
+                let (i, j) = (n / key_length, n % key_length);
+                *score += c2p[i * num_positions + index];
+                *score += p2c[index * key_length + j];
+            }
+        }
+    }
 
-        softmax(&mut ctx.qk).unwrap();
-        debug!("attention_probs", ctx.qk);
-        matmul(&ctx.qk, &ctx.v_cache, &mut ctx.qkv)?;
-        debug!("qkv", ctx.qkv);
+    pub(super) fn add_relative_scores(
+        c2p: &F32Tensor,
+        p2c: &F32Tensor,
+        indices: &[usize],
+        scores: &mut F32Tensor,
+    ) -> Result<(), SmeltError> {
+        let (query_length, key_length, num_positions) =
+            crate::shape::relative_scores(c2p.shape(), p2c.shape(), indices, scores.shape())?;
+        let lengths = [query_length, key_length, num_positions];
+        add_relative_scores_data(c2p.data(), p2c.data(), indices, scores.data_mut(), lengths);
+        Ok(())
+    }
 
-        unsplit_heads(&ctx.qkv, &mut ctx.hidden_states_attn_output)?;
+    #[inline]
+    pub(super) fn unsplit_heads(
+        src: &F32Tensor,
+        offset: usize,
+        dst: &mut F32Tensor,
+    ) -> Result<(), SmeltError> {
+        crate::shape::unsplit_heads(src.shape(), offset, dst.shape())?;
+        unsplit_heads_data(src.data(), dst.data_mut(), src.shape(), offset);
+        Ok(())
+    }
 
-        debug!("qkv (reshaed)", ctx.hidden_states_attn_output);
+    impl TensorHeads<F32Tensor> for F32Tensor {
+        fn split_heads_at(
+            src: &F32Tensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32Tensor,
+        ) -> Result<(), SmeltError> {
+            split_heads(src, offset, num_src_heads, dst)
+        }
 
-        Ok(())
+        fn unsplit_heads_at(
+            src: &F32Tensor,
+            offset: usize,
+            dst: &mut F32Tensor,
+        ) -> Result<(), SmeltError> {
+            unsplit_heads(src, offset, dst)
+        }
     }
 
     impl TensorAttention<F32Tensor> for F32Tensor {
+        const SCORES: bool = false;
+
         fn attention(
             query: &Linear<F32Tensor>,
             key: &Linear<F32Tensor>,
             value: &Linear<F32Tensor>,
+            dropout: &Dropout,
             ctx: &mut BertContext<F32Tensor>,
         ) -> Result<(), SmeltError> {
-            attention(query, key, value, ctx)?;
-            Ok(())
+            fused_attention(query, key, value, dropout, ctx)
+        }
+
+        fn add_relative_scores(
+            c2p: &F32Tensor,
+            p2c: &F32Tensor,
+            indices: &[usize],
+            scores: &mut F32Tensor,
+        ) -> Result<(), SmeltError> {
+            add_relative_scores(c2p, p2c, indices, scores)
         }
     }
 
@@ -169,6 +353,142 @@ mod cpu {
     impl BertOps<F32Tensor> for F32Tensor {}
 }
 
+/// The half precision cpu tensors, the heads are reshaped without conversion.
+/// The double precision reference of the cpu attention.
+#[cfg(feature = "cpu")]
+mod cpu_double {
+    use super::*;
+
+    impl TensorHeads<F64Tensor> for F64Tensor {
+        fn split_heads_at(
+            src: &F64Tensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F64Tensor,
+        ) -> Result<(), SmeltError> {
+            let heads_shape = dst.shape().to_vec();
+            crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+            let (src, dst) = (src.data(), dst.data_mut());
+            cpu::split_heads_data(src, dst, &heads_shape, offset, num_src_heads);
+            Ok(())
+        }
+
+        fn unsplit_heads_at(
+            src: &F64Tensor,
+            offset: usize,
+            dst: &mut F64Tensor,
+        ) -> Result<(), SmeltError> {
+            crate::shape::unsplit_heads(src.shape(), offset, dst.shape())?;
+            cpu::unsplit_heads_data(src.data(), dst.data_mut(), src.shape(), offset);
+            Ok(())
+        }
+    }
+
+    impl TensorAttention<F64Tensor> for F64Tensor {
+        fn add_relative_scores(
+            c2p: &F64Tensor,
+            p2c: &F64Tensor,
+            indices: &[usize],
+            scores: &mut F64Tensor,
+        ) -> Result<(), SmeltError> {
+            let (query_length, key_length, num_positions) =
+                crate::shape::relative_scores(c2p.shape(), p2c.shape(), indices, scores.shape())?;
+            let lengths = [query_length, key_length, num_positions];
+            let (c2p, p2c) = (c2p.data(), p2c.data());
+            cpu::add_relative_scores_data(c2p, p2c, indices, scores.data_mut(), lengths);
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<F64Tensor> for F64Tensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            Ok(self.data().iter().map(|&v| v as f32).collect())
+        }
+    }
+
+    impl BertOps<F64Tensor> for F64Tensor {}
+}
+
+#[cfg(all(feature = "cpu", feature = "f16"))]
+mod cpu_half {
+    use super::*;
+
+    impl TensorHeads<F16Tensor> for F16Tensor {
+        fn split_heads_at(
+            src: &F16Tensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F16Tensor,
+        ) -> Result<(), SmeltError> {
+            let heads_shape = dst.shape().to_vec();
+            crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+            let (src, dst) = (src.data(), dst.data_mut());
+            cpu::split_heads_data(src, dst, &heads_shape, offset, num_src_heads);
+            Ok(())
+        }
+
+        fn unsplit_heads_at(
+            src: &F16Tensor,
+            offset: usize,
+            dst: &mut F16Tensor,
+        ) -> Result<(), SmeltError> {
+            crate::shape::unsplit_heads(src.shape(), offset, dst.shape())?;
+            cpu::unsplit_heads_data(src.data(), dst.data_mut(), src.shape(), offset);
+            Ok(())
+        }
+    }
+
+    impl TensorAttention<F16Tensor> for F16Tensor {}
+
+    impl TensorDebug<F16Tensor> for F16Tensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<F16Tensor> for F16Tensor {}
+}
+
+#[cfg(all(feature = "cpu", feature = "bf16"))]
+mod cpu_bf16 {
+    use super::*;
+
+    impl TensorHeads<BF16Tensor> for BF16Tensor {
+        fn split_heads_at(
+            src: &BF16Tensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut BF16Tensor,
+        ) -> Result<(), SmeltError> {
+            let heads_shape = dst.shape().to_vec();
+            crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+            let (src, dst) = (src.data(), dst.data_mut());
+            cpu::split_heads_data(src, dst, &heads_shape, offset, num_src_heads);
+            Ok(())
+        }
+
+        fn unsplit_heads_at(
+            src: &BF16Tensor,
+            offset: usize,
+            dst: &mut BF16Tensor,
+        ) -> Result<(), SmeltError> {
+            crate::shape::unsplit_heads(src.shape(), offset, dst.shape())?;
+            cpu::unsplit_heads_data(src.data(), dst.data_mut(), src.shape(), offset);
+            Ok(())
+        }
+    }
+
+    impl TensorAttention<BF16Tensor> for BF16Tensor {}
+
+    impl TensorDebug<BF16Tensor> for BF16Tensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<BF16Tensor> for BF16Tensor {}
+}
+
 #[cfg(feature = "cuda")]
 mod cuda {
     use super::*;
@@ -179,6 +499,8 @@ mod cuda {
 
     pub(super) fn cuda_split_heads(
         src: &F32CudaTensor,
+        offset: usize,
+        num_src_heads: usize,
         dst: &mut F32CudaTensor,
     ) -> Result<(), SmeltError> {
         let dev = src.cuda();
@@ -188,6 +510,7 @@ mod cuda {
                 expected: dst.device_id(),
             }));
         }
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, dst.shape())?;
         let module_name = "split_heads";
         if !dev.has_func(module_name, module_name) {
             dev.load_ptx(RESHAPE_PTX.into(), module_name, &[module_name])?;
@@ -207,6 +530,9 @@ mod cuda {
             num_heads,
             sequence_length,
             head_dim,
+            num_src_heads,
+            offset,
+            width,
         );
         unsafe { fwd_fn.launch(cfg, params) }?;
 
@@ -215,6 +541,7 @@ mod cuda {
 
     pub(super) fn cuda_unsplit_heads(
         src: &F32CudaTensor,
+        offset: usize,
         dst: &mut F32CudaTensor,
     ) -> Result<(), SmeltError> {
         let dev = src.cuda();
@@ -224,6 +551,7 @@ mod cuda {
                 expected: dst.device_id(),
             }));
         }
+        let width = crate::shape::unsplit_heads(src.shape(), offset, dst.shape())?;
         let module_name = "unsplit_heads";
         if !dev.has_func(module_name, module_name) {
             dev.load_ptx(RESHAPE_PTX.into(), module_name, &[module_name])?;
@@ -243,56 +571,99 @@ mod cuda {
             num_heads,
             sequence_length,
             head_dim,
+            num_heads,
+            offset,
+            width,
         );
         unsafe { fwd_fn.launch(cfg, params) }?;
 
         Ok(())
     }
-    fn cuda_attention(
-        q_weights: &Linear<F32CudaTensor>,
-        k_weights: &Linear<F32CudaTensor>,
-        v_weights: &Linear<F32CudaTensor>,
-        ctx: &mut BertContext<F32CudaTensor>,
-    ) -> Result<(), SmeltError> {
-        q_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        cuda_split_heads(&ctx.hidden_states_copy, &mut ctx.q_cache)?;
-
-        debug!("Q head splitted", ctx.q_cache);
-
-        k_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        cuda_split_heads(&ctx.hidden_states_copy, &mut ctx.k_cache)?;
 
-        debug!("K head splitted", ctx.k_cache);
-
-        v_weights.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
-        cuda_split_heads(&ctx.hidden_states_copy, &mut ctx.v_cache)?;
-
-        debug!("V head splitted", ctx.v_cache);
-
-        cuda_f32::matmul_t(&ctx.q_cache, &ctx.k_cache, &mut ctx.qk)?;
+    pub(super) fn cuda_add_relative_scores(
+        c2p: &F32CudaTensor,
+        p2c: &F32CudaTensor,
+        indices: &[usize],
+        scores: &mut F32CudaTensor,
+    ) -> Result<(), SmeltError> {
+        let dev = scores.cuda();
+        if let Some(tensor) = [c2p, p2c]
+            .into_iter()
+            .find(|tensor| tensor.device_id() != scores.device_id())
+        {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: tensor.device_id(),
+                expected: scores.device_id(),
+            }));
+        }
+        let (query_length, key_length, num_positions) =
+            crate::shape::relative_scores(c2p.shape(), p2c.shape(), indices, scores.shape())?;
+        let numel = scores.data().len();
+        if numel == 0 {
+            return Ok(());
+        }
+        let module_name = "add_relative_scores";
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(RESHAPE_PTX.into(), module_name, &[module_name])?;
+        }
 
-        let head_dim = ctx.q_cache.shape()[2];
-        let scale = (head_dim as f32).sqrt();
-        cuda_f32::mul_scalar(&mut ctx.qk, 1.0 / scale)?;
+        let indices = dev.htod_copy(indices.to_vec())?;
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            c2p.data(),
+            p2c.data(),
+            &indices,
+            scores.data_mut(),
+            query_length,
+            key_length,
+            num_positions,
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
 
-        cuda_f32::softmax(&mut ctx.qk)?;
-        debug!("attention_probs", ctx.qk);
-        cuda_f32::matmul(&ctx.qk, &ctx.v_cache, &mut ctx.qkv)?;
-        debug!("qkv", ctx.qkv);
+        Ok(())
+    }
 
-        cuda_unsplit_heads(&ctx.qkv, &mut ctx.hidden_states_attn_output)?;
+    impl TensorHeads<F32CudaTensor> for F32CudaTensor {
+        fn split_heads_at(
+            src: &F32CudaTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_split_heads(src, offset, num_src_heads, dst)
+        }
 
-        Ok(())
+        fn unsplit_heads_at(
+            src: &F32CudaTensor,
+            offset: usize,
+            dst: &mut F32CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_unsplit_heads(src, offset, dst)
+        }
     }
+
     impl TensorAttention<F32CudaTensor> for F32CudaTensor {
+        const SCORES: bool = false;
+
         fn attention(
             query: &Linear<F32CudaTensor>,
             key: &Linear<F32CudaTensor>,
             value: &Linear<F32CudaTensor>,
+            dropout: &Dropout,
             ctx: &mut BertContext<F32CudaTensor>,
         ) -> Result<(), SmeltError> {
-            cuda_attention(query, key, value, ctx)?;
-            Ok(())
+            fused_attention(query, key, value, dropout, ctx)
+        }
+
+        fn add_relative_scores(
+            c2p: &F32CudaTensor,
+            p2c: &F32CudaTensor,
+            indices: &[usize],
+            scores: &mut F32CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_add_relative_scores(c2p, p2c, indices, scores)
         }
     }
 
@@ -305,84 +676,1283 @@ mod cuda {
     impl BertOps<F32CudaTensor> for F32CudaTensor {}
 }
 
-/// TODO
-pub trait TensorAttention<T: Tensor> {
-    /// TODO
-    fn attention(
-        query: &Linear<T>,
-        key: &Linear<T>,
-        value: &Linear<T>,
-        ctx: &mut BertContext<T>,
-    ) -> Result<(), SmeltError>;
-}
-
-/// TODO
-pub trait TensorDebug<T: Tensor> {
-    /// TODO
-    fn cpu_data(&self) -> Result<Vec<f32>, SmeltError>;
-}
-
-/// TODO
-pub trait BertOps<T: Tensor>: TensorOps<T> + TensorAttention<T> + TensorDebug<T> {}
+/// The f16 version of the cuda attention, the weights and activations stay in half
+/// precision on the device.
+#[cfg(all(feature = "cuda", feature = "f16"))]
+mod cuda_half {
+    use super::*;
+    use crate::gpu::f32::CudaError;
+    use cudarc::driver::{DeviceSlice, LaunchAsync, LaunchConfig};
 
-/// TODO
-#[derive(Clone)]
-pub struct BertAttention<T: Tensor> {
-    query: Linear<T>,
-    key: Linear<T>,
-    value: Linear<T>,
-    output: Linear<T>,
-    output_ln: LayerNorm<T>,
-}
+    const RESHAPE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/bert_reshape.ptx"));
 
-impl<T: Tensor + BertOps<T>> BertAttention<T> {
-    /// TODO
-    pub fn new(
-        query: Linear<T>,
-        key: Linear<T>,
-        value: Linear<T>,
-        output: Linear<T>,
-        output_ln: LayerNorm<T>,
-    ) -> Self {
-        Self {
-            query,
-            key,
-            value,
-            output,
-            output_ln,
+    fn reshape_heads(
+        module_name: &'static str,
+        src: &F16CudaTensor,
+        dst: &mut F16CudaTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        let dev = src.cuda();
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(RESHAPE_PTX.into(), module_name, &[module_name])?;
         }
-    }
 
-    /// TODO
-    pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
-        T::attention(&self.query, &self.key, &self.value, ctx)?;
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            src.data(),
+            dst.data_mut(),
+            num_heads,
+            sequence_length,
+            head_dim,
+            num_src_heads,
+            offset,
+            width,
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
 
-        self.output
-            .forward(&ctx.hidden_states_attn_output, &mut ctx.hidden_states_copy)?;
-        T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
-        self.output_ln.forward(&mut ctx.hidden_states)?;
         Ok(())
     }
-}
 
-/// TODO
-#[derive(Clone)]
-pub struct Mlp<T: Tensor> {
-    intermediate: Linear<T>,
-    output: Linear<T>,
-    output_ln: LayerNorm<T>,
-}
+    pub(super) fn cuda_split_heads(
+        src: &F16CudaTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F16CudaTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(
+            "split_heads_f16",
+            src,
+            dst,
+            &heads_shape,
+            num_src_heads,
+            offset,
+            width,
+        )
+    }
+
+    pub(super) fn cuda_unsplit_heads(
+        src: &F16CudaTensor,
+        offset: usize,
+        dst: &mut F16CudaTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(
+            "unsplit_heads_f16",
+            src,
+            dst,
+            &heads_shape,
+            heads_shape[0],
+            offset,
+            width,
+        )
+    }
+
+    impl TensorHeads<F16CudaTensor> for F16CudaTensor {
+        fn split_heads_at(
+            src: &F16CudaTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F16CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &F16CudaTensor,
+            offset: usize,
+            dst: &mut F16CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<F16CudaTensor> for F16CudaTensor {}
+
+    impl TensorDebug<F16CudaTensor> for F16CudaTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.to_f32()?.cpu_data()
+        }
+    }
+
+    impl BertOps<F16CudaTensor> for F16CudaTensor {}
+}
+
+/// The bf16 version of the cuda attention, like the f16 one.
+#[cfg(all(feature = "cuda", feature = "bf16"))]
+mod cuda_bf16 {
+    use super::*;
+    use crate::gpu::f32::CudaError;
+    use cudarc::driver::{DeviceSlice, LaunchAsync, LaunchConfig};
+
+    const RESHAPE_PTX: &str = include_str!(concat!(env!("OUT_DIR"), "/bert_reshape.ptx"));
+
+    fn reshape_heads(
+        module_name: &'static str,
+        src: &BF16CudaTensor,
+        dst: &mut BF16CudaTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        let dev = src.cuda();
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::Cuda(CudaError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        if !dev.has_func(module_name, module_name) {
+            dev.load_ptx(RESHAPE_PTX.into(), module_name, &[module_name])?;
+        }
+
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let fwd_fn = dev.get_func(module_name, module_name).unwrap();
+        let cfg = LaunchConfig::for_num_elems(numel as u32);
+        let params = (
+            numel,
+            src.data(),
+            dst.data_mut(),
+            num_heads,
+            sequence_length,
+            head_dim,
+            num_src_heads,
+            offset,
+            width,
+        );
+        unsafe { fwd_fn.launch(cfg, params) }?;
+
+        Ok(())
+    }
+
+    pub(super) fn cuda_split_heads(
+        src: &BF16CudaTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut BF16CudaTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(
+            "split_heads_bf16",
+            src,
+            dst,
+            &heads_shape,
+            num_src_heads,
+            offset,
+            width,
+        )
+    }
+
+    pub(super) fn cuda_unsplit_heads(
+        src: &BF16CudaTensor,
+        offset: usize,
+        dst: &mut BF16CudaTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(
+            "unsplit_heads_bf16",
+            src,
+            dst,
+            &heads_shape,
+            heads_shape[0],
+            offset,
+            width,
+        )
+    }
+
+    impl TensorHeads<BF16CudaTensor> for BF16CudaTensor {
+        fn split_heads_at(
+            src: &BF16CudaTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut BF16CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &BF16CudaTensor,
+            offset: usize,
+            dst: &mut BF16CudaTensor,
+        ) -> Result<(), SmeltError> {
+            cuda_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<BF16CudaTensor> for BF16CudaTensor {}
+
+    impl TensorDebug<BF16CudaTensor> for BF16CudaTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.to_f32()?.cpu_data()
+        }
+    }
+
+    impl BertOps<BF16CudaTensor> for BF16CudaTensor {}
+}
+
+#[cfg(feature = "metal")]
+mod metal {
+    use super::*;
+    use crate::gpu::metal::f32::{Arg, MetalError};
+
+    const RESHAPE_METAL: &str = include_str!("bert_reshape.metal");
+
+    fn reshape_heads(
+        name: &'static str,
+        src: &F32MetalTensor,
+        dst: &mut F32MetalTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::Metal(MetalError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let dev = src.device().clone();
+        dev.launch(
+            RESHAPE_METAL,
+            name,
+            &[
+                Arg::Buffer(src.data()),
+                Arg::Buffer(dst.data_mut()),
+                Arg::U32(numel as u32),
+                Arg::U32(num_heads as u32),
+                Arg::U32(sequence_length as u32),
+                Arg::U32(head_dim as u32),
+                Arg::U32(num_src_heads as u32),
+                Arg::U32(offset as u32),
+                Arg::U32(width as u32),
+            ],
+            numel,
+        )
+    }
+
+    pub(super) fn metal_split_heads(
+        src: &F32MetalTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F32MetalTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(
+            "split_heads",
+            src,
+            dst,
+            &heads_shape,
+            num_src_heads,
+            offset,
+            width,
+        )
+    }
+
+    pub(super) fn metal_unsplit_heads(
+        src: &F32MetalTensor,
+        offset: usize,
+        dst: &mut F32MetalTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(
+            "unsplit_heads",
+            src,
+            dst,
+            &heads_shape,
+            heads_shape[0],
+            offset,
+            width,
+        )
+    }
+
+    impl TensorHeads<F32MetalTensor> for F32MetalTensor {
+        fn split_heads_at(
+            src: &F32MetalTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32MetalTensor,
+        ) -> Result<(), SmeltError> {
+            metal_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &F32MetalTensor,
+            offset: usize,
+            dst: &mut F32MetalTensor,
+        ) -> Result<(), SmeltError> {
+            metal_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<F32MetalTensor> for F32MetalTensor {}
+
+    impl TensorDebug<F32MetalTensor> for F32MetalTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<F32MetalTensor> for F32MetalTensor {}
+}
+
+#[cfg(feature = "wgpu")]
+mod wgpu {
+    use super::*;
+    use crate::gpu::wgpu::f32::{Arg, WgpuError};
+
+    const RESHAPE_WGSL: &str = include_str!("bert_reshape.wgsl");
+
+    fn reshape_heads(
+        name: &'static str,
+        src: &F32WgpuTensor,
+        dst: &mut F32WgpuTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::Wgpu(WgpuError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let dev = src.device().clone();
+        dev.launch(
+            RESHAPE_WGSL,
+            name,
+            &[
+                Arg::Buffer(src.data()),
+                Arg::Buffer(dst.data_mut()),
+                Arg::U32(numel as u32),
+                Arg::U32(num_heads as u32),
+                Arg::U32(sequence_length as u32),
+                Arg::U32(head_dim as u32),
+                Arg::U32(num_src_heads as u32),
+                Arg::U32(offset as u32),
+                Arg::U32(width as u32),
+            ],
+            numel,
+        )
+    }
+
+    pub(super) fn wgpu_split_heads(
+        src: &F32WgpuTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F32WgpuTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(
+            "split_heads",
+            src,
+            dst,
+            &heads_shape,
+            num_src_heads,
+            offset,
+            width,
+        )
+    }
+
+    pub(super) fn wgpu_unsplit_heads(
+        src: &F32WgpuTensor,
+        offset: usize,
+        dst: &mut F32WgpuTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(
+            "unsplit_heads",
+            src,
+            dst,
+            &heads_shape,
+            heads_shape[0],
+            offset,
+            width,
+        )
+    }
+
+    impl TensorHeads<F32WgpuTensor> for F32WgpuTensor {
+        fn split_heads_at(
+            src: &F32WgpuTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32WgpuTensor,
+        ) -> Result<(), SmeltError> {
+            wgpu_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &F32WgpuTensor,
+            offset: usize,
+            dst: &mut F32WgpuTensor,
+        ) -> Result<(), SmeltError> {
+            wgpu_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<F32WgpuTensor> for F32WgpuTensor {}
+
+    impl TensorDebug<F32WgpuTensor> for F32WgpuTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<F32WgpuTensor> for F32WgpuTensor {}
+}
+
+#[cfg(feature = "opencl")]
+mod opencl {
+    use super::*;
+    use crate::gpu::opencl::f32::{Arg, OpenClError};
+
+    const RESHAPE_CL: &str = include_str!("bert_reshape.cl");
+
+    fn reshape_heads(
+        name: &'static str,
+        src: &F32OpenClTensor,
+        dst: &mut F32OpenClTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::OpenCl(OpenClError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let dev = src.device().clone();
+        dev.launch(
+            RESHAPE_CL,
+            name,
+            &[
+                Arg::Buffer(src.data()),
+                Arg::Buffer(dst.data_mut()),
+                Arg::U32(numel as u32),
+                Arg::U32(num_heads as u32),
+                Arg::U32(sequence_length as u32),
+                Arg::U32(head_dim as u32),
+                Arg::U32(num_src_heads as u32),
+                Arg::U32(offset as u32),
+                Arg::U32(width as u32),
+            ],
+            numel,
+        )
+    }
+
+    pub(super) fn opencl_split_heads(
+        src: &F32OpenClTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F32OpenClTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(
+            "split_heads",
+            src,
+            dst,
+            &heads_shape,
+            num_src_heads,
+            offset,
+            width,
+        )
+    }
+
+    pub(super) fn opencl_unsplit_heads(
+        src: &F32OpenClTensor,
+        offset: usize,
+        dst: &mut F32OpenClTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(
+            "unsplit_heads",
+            src,
+            dst,
+            &heads_shape,
+            heads_shape[0],
+            offset,
+            width,
+        )
+    }
+
+    impl TensorHeads<F32OpenClTensor> for F32OpenClTensor {
+        fn split_heads_at(
+            src: &F32OpenClTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32OpenClTensor,
+        ) -> Result<(), SmeltError> {
+            opencl_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &F32OpenClTensor,
+            offset: usize,
+            dst: &mut F32OpenClTensor,
+        ) -> Result<(), SmeltError> {
+            opencl_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<F32OpenClTensor> for F32OpenClTensor {}
+
+    impl TensorDebug<F32OpenClTensor> for F32OpenClTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<F32OpenClTensor> for F32OpenClTensor {}
+}
+
+#[cfg(feature = "vulkan")]
+mod vulkan {
+    use super::*;
+    use crate::gpu::vulkan::f32::{Arg, VulkanError};
+
+    const RESHAPE_SPV: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bert_reshape.spv"));
+
+    fn reshape_heads(
+        unsplit: bool,
+        src: &F32VulkanTensor,
+        dst: &mut F32VulkanTensor,
+        heads_shape: &[usize],
+        num_src_heads: usize,
+        offset: usize,
+        width: usize,
+    ) -> Result<(), SmeltError> {
+        if src.device_id() != dst.device_id() {
+            return Err(SmeltError::Vulkan(VulkanError::TensorOnDifferentDevice {
+                got: src.device_id(),
+                expected: dst.device_id(),
+            }));
+        }
+        let numel: usize = heads_shape.iter().product();
+        let num_heads = heads_shape[0];
+        let sequence_length = heads_shape[1];
+        let head_dim = heads_shape[2];
+
+        let dev = src.device().clone();
+        dev.launch(
+            RESHAPE_SPV,
+            "bert_reshape",
+            &[
+                Arg::Buffer(src.data()),
+                Arg::Buffer(dst.data_mut()),
+                Arg::U32(numel as u32),
+                Arg::U32(num_heads as u32),
+                Arg::U32(sequence_length as u32),
+                Arg::U32(head_dim as u32),
+                Arg::U32(num_src_heads as u32),
+                Arg::U32(offset as u32),
+                Arg::U32(width as u32),
+                Arg::U32(unsplit as u32),
+            ],
+            numel,
+        )
+    }
+
+    pub(super) fn vulkan_split_heads(
+        src: &F32VulkanTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut F32VulkanTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = dst.shape().to_vec();
+        let width = crate::shape::heads(src.shape(), offset, num_src_heads, &heads_shape)?;
+        reshape_heads(false, src, dst, &heads_shape, num_src_heads, offset, width)
+    }
+
+    pub(super) fn vulkan_unsplit_heads(
+        src: &F32VulkanTensor,
+        offset: usize,
+        dst: &mut F32VulkanTensor,
+    ) -> Result<(), SmeltError> {
+        let heads_shape = src.shape().to_vec();
+        let width = crate::shape::unsplit_heads(&heads_shape, offset, dst.shape())?;
+        reshape_heads(true, src, dst, &heads_shape, heads_shape[0], offset, width)
+    }
+
+    impl TensorHeads<F32VulkanTensor> for F32VulkanTensor {
+        fn split_heads_at(
+            src: &F32VulkanTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut F32VulkanTensor,
+        ) -> Result<(), SmeltError> {
+            vulkan_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &F32VulkanTensor,
+            offset: usize,
+            dst: &mut F32VulkanTensor,
+        ) -> Result<(), SmeltError> {
+            vulkan_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<F32VulkanTensor> for F32VulkanTensor {}
+
+    impl TensorDebug<F32VulkanTensor> for F32VulkanTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<F32VulkanTensor> for F32VulkanTensor {}
+}
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+mod backend {
+    use super::*;
+    use crate::backend::{Storage, Tensor as BackendTensor};
+
+    fn backend_split_heads(
+        src: &BackendTensor,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut BackendTensor,
+    ) -> Result<(), SmeltError> {
+        let n = num_src_heads;
+        match (src.storage(), dst.storage_mut()) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(src), Storage::Cpu(dst)) => cpu::split_heads(src, offset, n, dst),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(src), Storage::Cuda(dst)) => cuda::cuda_split_heads(src, offset, n, dst),
+            #[cfg(feature = "metal")]
+            (Storage::Metal(src), Storage::Metal(dst)) => {
+                metal::metal_split_heads(src, offset, n, dst)
+            }
+            #[cfg(feature = "wgpu")]
+            (Storage::Wgpu(src), Storage::Wgpu(dst)) => wgpu::wgpu_split_heads(src, offset, n, dst),
+            #[cfg(feature = "opencl")]
+            (Storage::OpenCl(src), Storage::OpenCl(dst)) => {
+                opencl::opencl_split_heads(src, offset, n, dst)
+            }
+            #[cfg(feature = "vulkan")]
+            (Storage::Vulkan(src), Storage::Vulkan(dst)) => {
+                vulkan::vulkan_split_heads(src, offset, n, dst)
+            }
+            #[allow(unreachable_patterns)]
+            (src, dst) => Err(SmeltError::BackendMismatch {
+                expected: src.name(),
+                got: dst.name(),
+            }),
+        }
+    }
+
+    fn backend_unsplit_heads(
+        src: &BackendTensor,
+        offset: usize,
+        dst: &mut BackendTensor,
+    ) -> Result<(), SmeltError> {
+        match (src.storage(), dst.storage_mut()) {
+            #[cfg(feature = "cpu")]
+            (Storage::Cpu(src), Storage::Cpu(dst)) => cpu::unsplit_heads(src, offset, dst),
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(src), Storage::Cuda(dst)) => cuda::cuda_unsplit_heads(src, offset, dst),
+            #[cfg(feature = "metal")]
+            (Storage::Metal(src), Storage::Metal(dst)) => {
+                metal::metal_unsplit_heads(src, offset, dst)
+            }
+            #[cfg(feature = "wgpu")]
+            (Storage::Wgpu(src), Storage::Wgpu(dst)) => wgpu::wgpu_unsplit_heads(src, offset, dst),
+            #[cfg(feature = "opencl")]
+            (Storage::OpenCl(src), Storage::OpenCl(dst)) => {
+                opencl::opencl_unsplit_heads(src, offset, dst)
+            }
+            #[cfg(feature = "vulkan")]
+            (Storage::Vulkan(src), Storage::Vulkan(dst)) => {
+                vulkan::vulkan_unsplit_heads(src, offset, dst)
+            }
+            #[allow(unreachable_patterns)]
+            (src, dst) => Err(SmeltError::BackendMismatch {
+                expected: src.name(),
+                got: dst.name(),
+            }),
+        }
+    }
+
+    impl TensorHeads<BackendTensor> for BackendTensor {
+        fn split_heads_at(
+            src: &BackendTensor,
+            offset: usize,
+            num_src_heads: usize,
+            dst: &mut BackendTensor,
+        ) -> Result<(), SmeltError> {
+            backend_split_heads(src, offset, num_src_heads, dst)
+        }
+
+        fn unsplit_heads_at(
+            src: &BackendTensor,
+            offset: usize,
+            dst: &mut BackendTensor,
+        ) -> Result<(), SmeltError> {
+            backend_unsplit_heads(src, offset, dst)
+        }
+    }
+
+    impl TensorAttention<BackendTensor> for BackendTensor {
+        const SCORES: bool = false;
+
+        fn attention(
+            query: &Linear<BackendTensor>,
+            key: &Linear<BackendTensor>,
+            value: &Linear<BackendTensor>,
+            dropout: &Dropout,
+            ctx: &mut BertContext<BackendTensor>,
+        ) -> Result<(), SmeltError> {
+            fused_attention(query, key, value, dropout, ctx)
+        }
+
+        fn add_relative_scores(
+            c2p: &BackendTensor,
+            p2c: &BackendTensor,
+            indices: &[usize],
+            scores: &mut BackendTensor,
+        ) -> Result<(), SmeltError> {
+            match (c2p.storage(), p2c.storage(), scores.storage_mut()) {
+                #[cfg(feature = "cpu")]
+                (Storage::Cpu(c2p), Storage::Cpu(p2c), Storage::Cpu(scores)) => {
+                    cpu::add_relative_scores(c2p, p2c, indices, scores)
+                }
+                #[cfg(feature = "cuda")]
+                (Storage::Cuda(c2p), Storage::Cuda(p2c), Storage::Cuda(scores)) => {
+                    cuda::cuda_add_relative_scores(c2p, p2c, indices, scores)
+                }
+                #[allow(unreachable_patterns)]
+                (c2p, _, _) => Err(SmeltError::Unsupported {
+                    operation: "add_relative_scores",
+                    backend: c2p.name(),
+                }),
+            }
+        }
+    }
+
+    impl TensorDebug<BackendTensor> for BackendTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl BertOps<BackendTensor> for BackendTensor {}
+}
+
+/// TODO
+pub trait TensorAttention<T: Tensor>: TensorOps<T> + TensorHeads<T> {
+    /// Whether [TensorAttention::attention] stores the scores (num_heads,
+    /// sequence_length, sequence_length) of the heads, the [BertContext] only allocates
+    /// them and the full ALiBi bias then.
+    const SCORES: bool = true;
+
+    /// The self attention of bert, written with [TensorOps] for every backend. The
+    /// attention probabilities go through `dropout`.
+    fn attention(
+        query: &Linear<T>,
+        key: &Linear<T>,
+        value: &Linear<T>,
+        dropout: &Dropout,
+        ctx: &mut BertContext<T>,
+    ) -> Result<(), SmeltError>
+    where
+        T: TensorOps<T>,
+    {
+        scores_attention::<T, Self>(query, key, value, dropout, ctx)
+    }
+
+    /// Adds `c2p[h, i, indices[i, j]] + p2c[h, indices[i, j], j]` to the `scores`
+    /// (num_heads, query_length, key_length) of the disentangled attention of DeBERTa,
+    /// from the content to position scores `c2p` (num_heads, query_length,
+    /// num_positions), the position to content scores `p2c` (num_heads, num_positions,
+    /// key_length) and the relative embedding of every query and key, see
+    /// [LogBucketPositions::indices]. Implemented on the cpu and cuda for now.
+    fn add_relative_scores(
+        c2p: &T,
+        p2c: &T,
+        indices: &[usize],
+        scores: &mut T,
+    ) -> Result<(), SmeltError> {
+        let _ = (c2p, p2c, indices, scores);
+        Err(SmeltError::Unsupported {
+            operation: "add_relative_scores",
+            backend: std::any::type_name::<T>(),
+        })
+    }
+}
+
+/// The disentangled attention of DeBERTa: the scores of bert plus the content to position
+/// and position to content scores of the `relative` embeddings, projected by the queries
+/// and keys of the layer, all scaled by `1 / sqrt(3 * head_dim)`.
+fn disentangled_attention<T: Tensor + BertOps<T>>(
+    query: &Linear<T>,
+    key: &Linear<T>,
+    value: &Linear<T>,
+    relative: &RelativeEmbeddings<T>,
+    dropout: &Dropout,
+    ctx: &mut BertContext<T>,
+) -> Result<(), SmeltError> {
+    project::<T, T>(query, key, value, ctx)?;
+    let Some(buffers) = &mut ctx.relative else {
+        return Err(SmeltError::InvalidConfig(
+            "no buffers for the relative positions".to_string(),
+        ));
+    };
+    query.forward(&relative.embeddings, &mut buffers.positions)?;
+    T::split_heads(&buffers.positions, &mut buffers.position_queries)?;
+    key.forward(&relative.embeddings, &mut buffers.positions)?;
+    T::split_heads(&buffers.positions, &mut buffers.position_keys)?;
+    T::matmul_t(&ctx.q_cache, &buffers.position_keys, &mut buffers.c2p)?;
+    T::matmul_t(&buffers.position_queries, &ctx.k_cache, &mut buffers.p2c)?;
+
+    let qk = scores(&mut ctx.qk)?;
+    T::matmul_t(&ctx.q_cache, &ctx.k_cache, qk)?;
+    T::add_relative_scores(&buffers.c2p, &buffers.p2c, &buffers.indices, qk)?;
+    let head_dim = ctx.q_cache.shape()[2];
+    T::mul_scalar(qk, 1.0 / (3.0 * head_dim as f32).sqrt())?;
+    if let Some(bias) = &ctx.alibi {
+        T::add(bias, qk)?;
+    }
+    if let Some(mask) = &ctx.attention_mask {
+        T::broadcast_add(mask, qk)?;
+    }
+
+    T::softmax(qk)?;
+    dropout.forward(qk)?;
+    T::matmul(qk, &ctx.v_cache, &mut ctx.qkv)?;
+    T::unsplit_heads(&ctx.qkv, &mut ctx.hidden_states_attn_output)
+}
+
+/// The projections of the hidden states of `ctx`, split by `H` into the heads of its
+/// caches.
+fn project<T: Tensor + TensorOps<T>, H: TensorHeads<T> + ?Sized>(
+    query: &Linear<T>,
+    key: &Linear<T>,
+    value: &Linear<T>,
+    ctx: &mut BertContext<T>,
+) -> Result<(), SmeltError> {
+    query.forward(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
+    H::split_heads(&ctx.hidden_states_copy, &mut ctx.q_cache)?;
+
+    debug!("Q head splitted", ctx.q_cache);
+
+    // With fewer key and value heads, their heads are repeated to the query heads.
+    let kv_states = ctx
+        .kv_states
+        .as_mut()
+        .unwrap_or(&mut ctx.hidden_states_copy);
+    key.forward(&ctx.hidden_states, kv_states)?;
+    H::split_heads(kv_states, &mut ctx.k_cache)?;
+
+    debug!("K head splitted", ctx.k_cache);
+
+    value.forward(&ctx.hidden_states, kv_states)?;
+    H::split_heads(kv_states, &mut ctx.v_cache)?;
+
+    debug!("V head splitted", ctx.v_cache);
+    Ok(())
+}
+
+/// The self attention of bert through the scores of the heads, see
+/// [TensorAttention::attention].
+fn scores_attention<T: Tensor + TensorOps<T>, H: TensorHeads<T> + ?Sized>(
+    query: &Linear<T>,
+    key: &Linear<T>,
+    value: &Linear<T>,
+    dropout: &Dropout,
+    ctx: &mut BertContext<T>,
+) -> Result<(), SmeltError> {
+    project::<T, H>(query, key, value, ctx)?;
+    let qk = scores(&mut ctx.qk)?;
+    T::matmul_t(&ctx.q_cache, &ctx.k_cache, qk)?;
+
+    let head_dim = ctx.q_cache.shape()[2];
+    let scale = (head_dim as f32).sqrt();
+    T::mul_scalar(qk, 1.0 / scale)?;
+    if let Some(bias) = &ctx.alibi {
+        T::add(bias, qk)?;
+    }
+    if let Some(mask) = &ctx.attention_mask {
+        T::broadcast_add(mask, qk)?;
+    }
+
+    T::softmax(qk)?;
+    debug!("attention_probs", qk);
+    dropout.forward(qk)?;
+    T::matmul(qk, &ctx.v_cache, &mut ctx.qkv)?;
+    debug!("qkv", ctx.qkv);
+
+    H::unsplit_heads(&ctx.qkv, &mut ctx.hidden_states_attn_output)?;
+
+    debug!("qkv (reshaed)", ctx.hidden_states_attn_output);
+
+    Ok(())
+}
+
+/// The scores buffer of a [BertContext], allocated for the attentions storing them.
+fn scores<T>(qk: &mut Option<T>) -> Result<&mut T, SmeltError> {
+    qk.as_mut().ok_or_else(|| {
+        SmeltError::InvalidConfig("the context has no buffer for the scores".to_string())
+    })
+}
+
+/// The self attention of bert on the backends with a [TensorFusedAttention], the scores
+/// of the heads are never stored. The kernels compute the ALiBi bias from its slopes and
+/// mask the keys of the pad tokens. A `dropout` in train mode needs the probabilities,
+/// see [scores_attention].
+fn fused_attention<T: Tensor + TensorOps<T> + TensorHeads<T> + TensorFusedAttention<T>>(
+    query: &Linear<T>,
+    key: &Linear<T>,
+    value: &Linear<T>,
+    dropout: &Dropout,
+    ctx: &mut BertContext<T>,
+) -> Result<(), SmeltError> {
+    if dropout.is_active() {
+        return scores_attention::<T, T>(query, key, value, dropout, ctx);
+    }
+    project::<T, T>(query, key, value, ctx)?;
+    let head_dim = ctx.q_cache.shape()[2];
+    let config = FusedAttentionConfig {
+        scale: 1.0 / (head_dim as f32).sqrt(),
+        causal: false,
+    };
+    T::fused_attention(
+        &ctx.q_cache,
+        &ctx.k_cache,
+        &ctx.v_cache,
+        ctx.attention_mask.as_ref(),
+        ctx.alibi_slopes.as_ref(),
+        &config,
+        &mut ctx.qkv,
+    )?;
+    debug!("qkv", ctx.qkv);
+    T::unsplit_heads(&ctx.qkv, &mut ctx.hidden_states_attn_output)
+}
+
+/// TODO
+pub trait TensorDebug<T: Tensor> {
+    /// TODO
+    fn cpu_data(&self) -> Result<Vec<f32>, SmeltError>;
+}
+
+/// TODO
+pub trait BertOps<T: Tensor>: TensorOps<T> + TensorAttention<T> + TensorDebug<T> {}
+
+/// The relative position embeddings of DeBERTa (num_positions, hidden_dim), shared by
+/// the layers, see [BertAttention::with_relative_embeddings]. Cloning them shares their
+/// weights.
+#[derive(Clone)]
+pub struct RelativeEmbeddings<T: Tensor> {
+    embeddings: Arc<T>,
+    positions: LogBucketPositions,
+}
+
+impl<T: Tensor> RelativeEmbeddings<T> {
+    /// The `embeddings` of the relative `positions`, already normalized: the
+    /// `encoder.rel_embeddings` of the checkpoints after the `encoder.LayerNorm`.
+    pub fn new(embeddings: T, positions: LogBucketPositions) -> Result<Self, SmeltError> {
+        let num_positions = positions.num_positions();
+        match embeddings.shape() {
+            &[rows, _] if rows == num_positions => Ok(Self {
+                embeddings: Arc::new(embeddings),
+                positions,
+            }),
+            shape => Err(SmeltError::DimensionMismatch {
+                expected: vec![num_positions, shape.last().copied().unwrap_or(0)],
+                got: shape.to_vec(),
+            }),
+        }
+    }
+
+    /// The relative positions of the embeddings
+    pub fn positions(&self) -> LogBucketPositions {
+        self.positions
+    }
+
+    /// The buffers of the attention of `num_heads` heads for `sequence_length` tokens.
+    fn context(
+        &self,
+        sequence_length: usize,
+        num_heads: usize,
+        device: &T::Device,
+    ) -> Result<RelativeContext<T>, SmeltError> {
+        let (num_positions, hidden_dim) = (self.embeddings.shape()[0], self.embeddings.shape()[1]);
+        let heads_shape = vec![num_heads, num_positions, hidden_dim / num_heads];
+        Ok(RelativeContext {
+            indices: self.positions.indices(sequence_length, sequence_length),
+            positions: device.zeros(vec![num_positions, hidden_dim])?,
+            position_queries: device.zeros(heads_shape.clone())?,
+            position_keys: device.zeros(heads_shape)?,
+            c2p: device.zeros(vec![num_heads, sequence_length, num_positions])?,
+            p2c: device.zeros(vec![num_heads, num_positions, sequence_length])?,
+        })
+    }
+}
+
+impl<T: TensorToDevice> RelativeEmbeddings<T> {
+    /// A copy of the embeddings on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            embeddings: Arc::new(self.embeddings.to_device(device)?),
+            positions: self.positions,
+        })
+    }
+}
+
+/// The buffers of the disentangled attention for a sequence length.
+struct RelativeContext<T: Tensor> {
+    // The relative embedding of every query and key
+    indices: Vec<usize>,
+    // The relative embeddings projected by the queries or the keys (num_positions,
+    // hidden_dim), then split into heads (num_heads, num_positions, head_dim)
+    positions: T,
+    position_queries: T,
+    position_keys: T,
+    // The content to position (num_heads, sequence_length, num_positions) and position
+    // to content (num_heads, num_positions, sequence_length) scores
+    c2p: T,
+    p2c: T,
+}
+
+/// TODO
+#[derive(Clone)]
+pub struct BertAttention<T: Tensor> {
+    query: Linear<T>,
+    key: Linear<T>,
+    value: Linear<T>,
+    output: Linear<T>,
+    output_ln: LayerNorm<T>,
+    alibi: Option<Alibi>,
+    relative: Option<RelativeEmbeddings<T>>,
+    // The dropout of the attention probabilities, then of the output before the residual
+    attention_dropout: Dropout,
+    dropout: Dropout,
+}
+
+impl<T: Tensor + BertOps<T>> BertAttention<T> {
+    /// TODO
+    pub fn new(
+        query: Linear<T>,
+        key: Linear<T>,
+        value: Linear<T>,
+        output: Linear<T>,
+        output_ln: LayerNorm<T>,
+    ) -> Self {
+        Self {
+            query,
+            key,
+            value,
+            output,
+            output_ln,
+            alibi: None,
+            relative: None,
+            attention_dropout: Dropout::default(),
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the attention probabilities with `attention_dropout` and the output with
+    /// `dropout` in train mode, the `attention_probs_dropout_prob` and
+    /// `hidden_dropout_prob` of the configurations.
+    pub fn with_dropout(mut self, attention_dropout: Dropout, dropout: Dropout) -> Self {
+        self.attention_dropout = attention_dropout;
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.attention_dropout.set_training(training);
+        self.dropout.set_training(training);
+    }
+
+    /// Whether the attention needs the scores (num_heads, sequence_length,
+    /// sequence_length) of the [BertContext] even on the backends with a
+    /// [TensorFusedAttention]: the disentangled attention, and the dropout of the
+    /// probabilities in train mode.
+    fn needs_scores(&self) -> bool {
+        self.relative.is_some() || self.attention_dropout.is_active()
+    }
+
+    /// Biases the scores with `alibi` (MosaicBERT). The [BertContext] builds the bias
+    /// of the first layer once per sequence length, the layers are expected to share
+    /// the same slopes.
+    pub fn with_alibi(mut self, alibi: Alibi) -> Self {
+        self.alibi = Some(alibi);
+        self
+    }
+
+    /// The linear biases of the scores, if any
+    pub fn alibi(&self) -> Option<&Alibi> {
+        self.alibi.as_ref()
+    }
+
+    /// The disentangled attention of DeBERTa: the queries and keys also project the
+    /// `relative` embeddings, whose content to position and position to content scores
+    /// are added to the scores. Like the alibi, the [BertContext] builds the relative
+    /// positions of the first layer.
+    pub fn with_relative_embeddings(mut self, relative: RelativeEmbeddings<T>) -> Self {
+        self.relative = Some(relative);
+        self
+    }
+
+    /// The relative position embeddings, if any
+    pub fn relative_embeddings(&self) -> Option<&RelativeEmbeddings<T>> {
+        self.relative.as_ref()
+    }
+
+    /// TODO
+    pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
+        let (query, key, value) = (&self.query, &self.key, &self.value);
+        let dropout = &self.attention_dropout;
+        match &self.relative {
+            Some(relative) => disentangled_attention(query, key, value, relative, dropout, ctx)?,
+            None => T::attention(query, key, value, dropout, ctx)?,
+        }
+
+        self.output
+            .forward(&ctx.hidden_states_attn_output, &mut ctx.hidden_states_copy)?;
+        self.dropout.forward(&mut ctx.hidden_states_copy)?;
+        T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
+        self.output_ln.forward(&mut ctx.hidden_states)?;
+        Ok(())
+    }
+}
+
+impl<T: TensorToDevice> BertAttention<T> {
+    /// A copy of the attention with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            query: self.query.to_device(device)?,
+            key: self.key.to_device(device)?,
+            value: self.value.to_device(device)?,
+            output: self.output.to_device(device)?,
+            output_ln: self.output_ln.to_device(device)?,
+            alibi: self.alibi.clone(),
+            relative: self
+                .relative
+                .as_ref()
+                .map(|relative| relative.to_device(device))
+                .transpose()?,
+            attention_dropout: self.attention_dropout,
+            dropout: self.dropout,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> BertAttention<T> {
+    /// Quantizes the 4 projections, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.query.quantize_dynamic()?;
+        self.key.quantize_dynamic()?;
+        self.value.quantize_dynamic()?;
+        self.output.quantize_dynamic()
+    }
+}
+
+/// TODO
+#[derive(Clone)]
+pub struct Mlp<T: Tensor> {
+    intermediate: Linear<T>,
+    output: Linear<T>,
+    output_ln: LayerNorm<T>,
+    activation: Activation,
+    // The dropout of the output before the residual
+    dropout: Dropout,
+}
 
 impl<T: Tensor + BertOps<T>> Mlp<T> {
-    /// TODO
+    /// The mlp with the tanh approximation of gelu, see [Mlp::with_activation].
     pub fn new(intermediate: Linear<T>, output: Linear<T>, output_ln: LayerNorm<T>) -> Self {
         Self {
             intermediate,
             output,
             output_ln,
+            activation: Activation::GeluNew,
+            dropout: Dropout::default(),
         }
     }
 
+    /// Drops the output with `dropout` in train mode, the `hidden_dropout_prob` of the
+    /// configurations.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// Replaces the activation of the intermediate states, like the `hidden_act` of the
+    /// configuration.
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    /// The activation of the intermediate states
+    pub fn activation(&self) -> Activation {
+        self.activation
+    }
+
     /// TODO
     pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
         // println!("=====");
@@ -390,11 +1960,12 @@ impl<T: Tensor + BertOps<T>> Mlp<T> {
         self.intermediate
             .forward(&ctx.hidden_states, &mut ctx.intermediate_states)?;
         debug!("Intermediate ", ctx.intermediate_states);
-        T::gelu(&mut ctx.intermediate_states)?;
-        debug!("Intermediate (gelu)", ctx.intermediate_states);
+        T::activation(&mut ctx.intermediate_states, self.activation)?;
+        debug!("Intermediate (activation)", ctx.intermediate_states);
         self.output
             .forward(&ctx.intermediate_states, &mut ctx.hidden_states_copy)?;
         debug!("output", ctx.hidden_states_copy);
+        self.dropout.forward(&mut ctx.hidden_states_copy)?;
         T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
         debug!("output (skip)", ctx.hidden_states);
         self.output_ln.forward(&mut ctx.hidden_states)?;
@@ -403,6 +1974,27 @@ impl<T: Tensor + BertOps<T>> Mlp<T> {
     }
 }
 
+impl<T: TensorToDevice> Mlp<T> {
+    /// A copy of the mlp with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            intermediate: self.intermediate.to_device(device)?,
+            output: self.output.to_device(device)?,
+            output_ln: self.output_ln.to_device(device)?,
+            activation: self.activation,
+            dropout: self.dropout,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> Mlp<T> {
+    /// Quantizes the 2 projections, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.intermediate.quantize_dynamic()?;
+        self.output.quantize_dynamic()
+    }
+}
+
 /// TODO
 #[derive(Clone)]
 pub struct BertLayer<T: Tensor> {
@@ -416,6 +2008,12 @@ impl<T: Tensor + BertOps<T>> BertLayer<T> {
         Self { attention, mlp }
     }
 
+    /// Switches the dropouts of the attention and the mlp to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.attention.set_training(training);
+        self.mlp.set_training(training);
+    }
+
     /// TODO
     pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
         debug!("Before attention", ctx.hidden_states);
@@ -428,22 +2026,124 @@ impl<T: Tensor + BertOps<T>> BertLayer<T> {
     }
 }
 
+impl<T: TensorToDevice> BertLayer<T> {
+    /// A copy of the layer with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            attention: self.attention.to_device(device)?,
+            mlp: self.mlp.to_device(device)?,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> BertLayer<T> {
+    /// Quantizes the attention and the mlp, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.attention.quantize_dynamic()?;
+        self.mlp.quantize_dynamic()
+    }
+}
+
 /// TODO
 #[derive(Clone)]
 pub struct BertEncoder<T: Tensor> {
     layers: Vec<BertLayer<T>>,
+    // The index in `layers` of the layer run at every depth, `0..layers.len()` unless
+    // the layers are shared
+    order: Vec<usize>,
 }
 
 impl<T: Tensor + BertOps<T>> BertEncoder<T> {
     /// TODO
     pub fn new(layers: Vec<BertLayer<T>>) -> Self {
-        Self { layers }
+        let order = (0..layers.len()).collect();
+        Self { layers, order }
+    }
+
+    /// Cross-layer parameter sharing like ALBERT: the encoder runs `layers[order[0]]`,
+    /// then `layers[order[1]]`... The shared layers hold their weights once, so they are
+    /// quantized or moved once too.
+    /// ```no_run
+    /// # use smelte_rs::cpu::f32::Tensor;
+    /// # use smelte_rs::nn::models::bert::{BertEncoder, BertLayer};
+    /// # fn load() -> BertLayer<Tensor> { unimplemented!() }
+    /// // One layer for the 12 layers of ALBERT.
+    /// let encoder = BertEncoder::shared(vec![load()], vec![0; 12]).unwrap();
+    /// assert_eq!(encoder.num_layers(), 12);
+    /// ```
+    pub fn shared(layers: Vec<BertLayer<T>>, order: Vec<usize>) -> Result<Self, SmeltError> {
+        if let Some(&got) = order.iter().find(|&&i| i >= layers.len()) {
+            return Err(SmeltError::InvalidLength {
+                expected: layers.len(),
+                got,
+            });
+        }
+        if layers.is_empty() {
+            return Err(SmeltError::InvalidConfig(
+                "an encoder without layers".to_string(),
+            ));
+        }
+        Ok(Self { layers, order })
+    }
+
+    /// The number of layers run by the encoder, counting the shared layers once per run.
+    pub fn num_layers(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Switches the dropouts of every layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
+    /// Every [Linear] layer of the encoder, named like in transformers
+    /// (`encoder.layer.0.attention.self.query`).
+    fn named_linears_mut(&mut self) -> Vec<(String, &mut Linear<T>)> {
+        let mut linears = vec![];
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            let prefix = format!("encoder.layer.{index}");
+            let attention = &mut layer.attention;
+            linears.extend([
+                (
+                    format!("{prefix}.attention.self.query"),
+                    &mut attention.query,
+                ),
+                (format!("{prefix}.attention.self.key"), &mut attention.key),
+                (
+                    format!("{prefix}.attention.self.value"),
+                    &mut attention.value,
+                ),
+                (
+                    format!("{prefix}.attention.output.dense"),
+                    &mut attention.output,
+                ),
+            ]);
+            let mlp = &mut layer.mlp;
+            linears.extend([
+                (
+                    format!("{prefix}.intermediate.dense"),
+                    &mut mlp.intermediate,
+                ),
+                (format!("{prefix}.output.dense"), &mut mlp.output),
+            ]);
+        }
+        linears
+    }
+
+    /// Every [Linear] layer of the encoder.
+    fn linears_mut(&mut self) -> Vec<&mut Linear<T>> {
+        self.named_linears_mut()
+            .into_iter()
+            .map(|(_, linear)| linear)
+            .collect()
     }
 
     /// TODO
     pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
-        for layer in &self.layers {
-            layer.forward(ctx)?;
+        for &index in &self.order {
+            self.layers[index].forward(ctx)?;
         }
         Ok(())
     }
@@ -453,9 +2153,12 @@ impl<T: Tensor + BertOps<T>> BertEncoder<T> {
 #[derive(Clone)]
 pub struct BertEmbeddings<T: Tensor> {
     input_embeddings: Embedding<T>,
-    position_embeddings: Embedding<T>,
-    type_embeddings: Embedding<T>,
+    position_embeddings: Option<Embedding<T>>,
+    type_embeddings: Option<Embedding<T>>,
     layer_norm: LayerNorm<T>,
+    // The projection of the factorized embeddings of ALBERT to the hidden states
+    projection: Option<Linear<T>>,
+    dropout: Dropout,
 }
 
 impl<T: Tensor + BertOps<T>> BertEmbeddings<T> {
@@ -468,9 +2171,79 @@ impl<T: Tensor + BertOps<T>> BertEmbeddings<T> {
     ) -> Self {
         Self {
             input_embeddings,
-            position_embeddings,
+            position_embeddings: Some(position_embeddings),
+            type_embeddings: Some(type_embeddings),
+            layer_norm,
+            projection: None,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// The embeddings of the checkpoints without token type embeddings, like some
+    /// RoBERTa variants: the type ids are ignored.
+    pub fn without_type_embeddings(
+        input_embeddings: Embedding<T>,
+        position_embeddings: Embedding<T>,
+        layer_norm: LayerNorm<T>,
+    ) -> Self {
+        Self {
+            input_embeddings,
+            position_embeddings: Some(position_embeddings),
+            type_embeddings: None,
+            layer_norm,
+            projection: None,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// The embeddings of the models with relative positions only, like DeBERTa-v3: the
+    /// position ids are ignored, and so are the type ids without `type_embeddings`.
+    pub fn without_position_embeddings(
+        input_embeddings: Embedding<T>,
+        type_embeddings: Option<Embedding<T>>,
+        layer_norm: LayerNorm<T>,
+    ) -> Self {
+        Self {
+            input_embeddings,
+            position_embeddings: None,
             type_embeddings,
             layer_norm,
+            projection: None,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Factorized embeddings like ALBERT: the embeddings (sequence_length, embedding_dim)
+    /// are normalized then projected to the hidden states by `projection` (hidden_dim,
+    /// embedding_dim), the `embedding_hidden_mapping_in` of the checkpoints.
+    pub fn with_projection(mut self, projection: Linear<T>) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Drops the normalized embeddings with `dropout` in train mode, before the
+    /// projection of the factorized embeddings.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// The size of the embeddings, the hidden size unless they are factorized (see
+    /// [BertEmbeddings::with_projection]).
+    pub fn embedding_dim(&self) -> usize {
+        self.input_embeddings.weight().shape()[1]
+    }
+
+    /// The size of the hidden states
+    pub fn hidden_dim(&self) -> usize {
+        match &self.projection {
+            Some(projection) => projection.shape()[0],
+            None => self.embedding_dim(),
         }
     }
 
@@ -480,39 +2253,62 @@ impl<T: Tensor + BertOps<T>> BertEmbeddings<T> {
         let position_ids = &ctx.position_ids;
         let type_ids = &ctx.type_ids;
 
-        if input_ids.len() != position_ids.len() {
+        if self.position_embeddings.is_some() && input_ids.len() != position_ids.len() {
             return Err(SmeltError::InvalidLength {
                 expected: input_ids.len(),
                 got: position_ids.len(),
             });
         }
-        if input_ids.len() != type_ids.len() {
+        if self.type_embeddings.is_some() && input_ids.len() != type_ids.len() {
             return Err(SmeltError::InvalidLength {
                 expected: input_ids.len(),
                 got: type_ids.len(),
             });
         }
 
-        self.input_embeddings
-            .forward(input_ids, &mut ctx.hidden_states)?;
+        let ids = [&input_ids[..], position_ids, type_ids];
+        match (&self.projection, &mut ctx.embedding_states) {
+            (None, _) => self.embed(ids, &mut ctx.hidden_states, &mut ctx.hidden_states_copy),
+            (Some(projection), Some([states, scratch])) => {
+                self.embed(ids, states, scratch)?;
+                projection.forward(states, &mut ctx.hidden_states)
+            }
+            (Some(_), None) => Err(SmeltError::InvalidConfig(
+                "no buffers for the factorized embeddings".to_string(),
+            )),
+        }
+    }
+
+    /// The normalized sum of the embeddings of the ids into `states`, `scratch` holding
+    /// every embedding before the sum.
+    fn embed(
+        &self,
+        [input_ids, position_ids, type_ids]: [&[usize]; 3],
+        states: &mut T,
+        scratch: &mut T,
+    ) -> Result<(), SmeltError> {
+        self.input_embeddings.forward(input_ids, states)?;
 
-        debug!("input embeddings", ctx.hidden_states);
+        debug!("input embeddings", states);
 
-        self.type_embeddings
-            .forward(type_ids, &mut ctx.hidden_states_copy)?;
-        debug!("type embeddings", ctx.hidden_states_copy);
-        T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
-        debug!("After add type embeddings", ctx.hidden_states);
+        if let Some(type_embeddings) = &self.type_embeddings {
+            type_embeddings.forward(type_ids, scratch)?;
+            debug!("type embeddings", scratch);
+            T::add(scratch, states)?;
+            debug!("After add type embeddings", states);
+        }
 
-        self.position_embeddings
-            .forward(position_ids, &mut ctx.hidden_states_copy)?;
-        debug!("position embeddings", ctx.hidden_states_copy);
-        T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
-        debug!("After add position embeddings", ctx.hidden_states);
+        if let Some(position_embeddings) = &self.position_embeddings {
+            position_embeddings.forward(position_ids, scratch)?;
+            debug!("position embeddings", scratch);
+            T::add(scratch, states)?;
+            debug!("After add position embeddings", states);
+        }
 
-        self.layer_norm.forward(&mut ctx.hidden_states)?;
+        self.layer_norm.forward(states)?;
+        self.dropout.forward(states)?;
 
-        debug!("After embeddings", ctx.hidden_states);
+        debug!("After embeddings", states);
         Ok(())
     }
 }
@@ -537,29 +2333,216 @@ impl<T: Tensor + BertOps<T>> Bert<T> {
         self.embeddings.forward(ctx)?;
         self.encoder.forward(ctx)
     }
+
+    /// Switches the dropouts of the embeddings and of every layer to train or eval mode,
+    /// the models being built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.embeddings.set_training(training);
+        self.encoder.set_training(training);
+    }
+
+    /// Attaches the LoRA `adapters` to the linear layers of the encoder, named like in
+    /// transformers with or without the `bert.` (or `roberta.`, `electra.`) prefix, as
+    /// returned by `checkpoint::lora_adapters`. Nothing is attached if a name matches no
+    /// layer.
+    pub fn set_lora(&mut self, adapters: Vec<(String, LoraLinear<T>)>) -> Result<(), SmeltError> {
+        let mut linears: HashMap<_, _> = self.encoder.named_linears_mut().into_iter().collect();
+        let key = |name: &str| {
+            let stripped = ["bert.", "roberta.", "electra."]
+                .iter()
+                .find_map(|prefix| name.strip_prefix(prefix));
+            stripped.unwrap_or(name).to_string()
+        };
+        if let Some((name, _)) = adapters
+            .iter()
+            .find(|(name, _)| !linears.contains_key(&key(name)))
+        {
+            return Err(SmeltError::InvalidConfig(format!("no linear layer {name}")));
+        }
+        for (name, lora) in adapters {
+            if let Some(linear) = linears.get_mut(&key(&name)) {
+                linear.set_lora(lora)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Folds the attached adapters into the weights, see [Linear::merge_lora].
+    pub fn merge_lora(&mut self) -> Result<(), SmeltError> {
+        for linear in self.encoder.linears_mut() {
+            linear.merge_lora()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Tensor + BertOps<T> + TensorPrecision> Bert<T> {
+    /// Mixed precision: runs the matmuls of the [Linear] layers in `precision`, while
+    /// the embeddings, the layer norms, the softmax and the attention stay in the precision
+    /// of `T` (see [Linear::set_precision]). [Precision::F32] goes back to full precision.
+    pub fn set_precision(&mut self, precision: Precision) -> Result<(), SmeltError> {
+        for linear in self.encoder.linears_mut() {
+            linear.set_precision(precision)?;
+        }
+        Ok(())
+    }
 }
 
 /// TODO
 #[derive(Clone)]
 pub struct BertPooler<T: Tensor> {
     pooler: Linear<T>,
+    // The tanh of BERT when `None`
+    activation: Option<Activation>,
 }
 
 impl<T: Tensor + BertOps<T>> BertPooler<T> {
     /// TODO
     pub fn new(pooler: Linear<T>) -> Self {
-        Self { pooler }
+        Self {
+            pooler,
+            activation: None,
+        }
+    }
+
+    /// Replaces the tanh after the dense layer, like the relu after the `pre_classifier`
+    /// of DistilBERT.
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = Some(activation);
+        self
     }
 
     /// TODO
     pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
         T::select(&[0], &ctx.hidden_states, &mut ctx.pool)?;
         self.pooler.forward(&ctx.pool, &mut ctx.pool_output)?;
-        T::tanh(&mut ctx.pool_output)?;
+        match self.activation {
+            Some(activation) => T::activation(&mut ctx.pool_output, activation)?,
+            None => T::tanh(&mut ctx.pool_output)?,
+        }
         Ok(())
     }
 }
 
+impl<T: TensorToDevice> BertPooler<T> {
+    /// A copy of the pooler with its weights on `device`.
+    pub fn to_device(&self, device: &T::Device) -> Result<Self, SmeltError> {
+        Ok(Self {
+            pooler: self.pooler.to_device(device)?,
+            activation: self.activation,
+        })
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> BertPooler<T> {
+    /// Quantizes the pooler, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.pooler.quantize_dynamic()
+    }
+}
+
+/// The forward pass recorded by [BertClassifier::capture], with the context it runs on.
+struct Captured<T: Tensor> {
+    context: BertContext<T>,
+    replay: Replay,
+}
+
+/// The sizes of the buffers of a [BertContext].
+struct BufferShapes {
+    sequence_length: usize,
+    hidden_dim: usize,
+    kv_dim: usize,
+    intermediate_dim: usize,
+    num_heads: usize,
+    head_dim: usize,
+    num_classes: usize,
+}
+
+impl BufferShapes {
+    fn alloc<T: Tensor>(
+        &self,
+        device: &T::Device,
+        input_ids: Vec<usize>,
+        position_ids: Vec<usize>,
+        type_ids: Vec<usize>,
+        alibi: Option<&Alibi>,
+        scores: bool,
+    ) -> Result<BertContext<T>, SmeltError> {
+        let Self {
+            sequence_length,
+            hidden_dim,
+            kv_dim,
+            intermediate_dim,
+            num_heads,
+            head_dim,
+            num_classes,
+        } = *self;
+        let hidden_states = device.zeros(vec![sequence_length, hidden_dim])?;
+        let hidden_states_copy = device.zeros(vec![sequence_length, hidden_dim])?;
+        let hidden_states_attn_output = device.zeros(vec![sequence_length, hidden_dim])?;
+        let intermediate_states = device.zeros(vec![sequence_length, intermediate_dim])?;
+        let kv_states = (kv_dim != hidden_dim)
+            .then(|| device.zeros(vec![sequence_length, kv_dim]))
+            .transpose()?;
+        let q_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
+        let k_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
+        let v_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
+        let (qk, alibi, alibi_slopes) = match scores {
+            true => (
+                Some(device.zeros(vec![num_heads, sequence_length, sequence_length])?),
+                alibi
+                    .map(|alibi| alibi.bias(sequence_length, sequence_length, device))
+                    .transpose()?,
+                None,
+            ),
+            false => (
+                None,
+                None,
+                alibi
+                    .map(|alibi| device.tensor(alibi.slopes(), vec![num_heads]))
+                    .transpose()?,
+            ),
+        };
+        let qkv = device.zeros(vec![num_heads, sequence_length, head_dim])?;
+        let pool = device.zeros(vec![1, hidden_dim])?;
+        let pool_output = device.zeros(vec![1, hidden_dim])?;
+        let probs = device.zeros(vec![1, num_classes])?;
+        Ok(BertContext {
+            input_ids,
+            position_ids,
+            type_ids,
+            hidden_states,
+            embedding_states: None,
+            hidden_states_copy,
+            hidden_states_attn_output,
+            intermediate_states,
+            kv_states,
+            q_cache,
+            k_cache,
+            v_cache,
+            qk,
+            alibi,
+            alibi_slopes,
+            attention_mask: None,
+            relative: None,
+            qkv,
+            pool,
+            pool_output,
+            probs,
+            next_stage: None,
+        })
+    }
+}
+
+/// Where [BertClassifier::split_layers] cut the encoder.
+#[derive(Clone)]
+struct Stage<T: Tensor> {
+    // The first layer on the second device.
+    split: usize,
+    // Moves the hidden states between the devices.
+    transfer: fn(&T, &mut T) -> Result<(), SmeltError>,
+}
+
 /// TODO
 #[derive(Clone)]
 pub struct BertClassifier<T: Tensor + BertOps<T>> {
@@ -568,6 +2551,10 @@ pub struct BertClassifier<T: Tensor + BertOps<T>> {
     /// NO
     pub classifier: Linear<T>,
     num_heads: usize,
+    // The dropout of the pooled output, before the classifier
+    dropout: Dropout,
+    captured: Option<Arc<Mutex<Captured<T>>>>,
+    pipeline: Option<Stage<T>>,
 }
 
 impl<T: Tensor + BertOps<T> + TensorAttention<T>> BertClassifier<T> {
@@ -578,83 +2565,480 @@ impl<T: Tensor + BertOps<T> + TensorAttention<T>> BertClassifier<T> {
             pooler,
             classifier,
             num_heads: 0,
+            dropout: Dropout::default(),
+            captured: None,
+            pipeline: None,
+        }
+    }
+
+    /// Drops the pooled output with `dropout` in train mode, the `classifier_dropout` (or
+    /// `hidden_dropout_prob`) of the configurations.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches every dropout of the model to train or eval mode, see
+    /// [Bert::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.bert.set_training(training);
+        self.dropout.set_training(training);
+        // Recorded with the previous dropouts.
+        self.captured = None;
+    }
+
+    /// TODO
+    pub fn set_num_heads(&mut self, num_heads: usize) {
+        self.num_heads = num_heads
+    }
+
+    /// Sets the activation of every [Mlp], see [Mlp::with_activation].
+    pub fn set_activation(&mut self, activation: Activation) {
+        for layer in &mut self.bert.encoder.layers {
+            layer.mlp.activation = activation;
+        }
+    }
+
+    /// Attaches LoRA adapters to the encoder, see [Bert::set_lora].
+    /// ```no_run
+    /// # #[cfg(feature = "safetensors")]
+    /// # {
+    /// # use smelte_rs::backend::{Device, Tensor};
+    /// # use smelte_rs::nn::models::bert::BertClassifier;
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::checkpoint::lora_adapters;
+    ///
+    /// # fn load() -> BertClassifier<Tensor> { unimplemented!() }
+    /// let mut bert = load();
+    /// let buffer = std::fs::read("adapter_model.safetensors").unwrap();
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// // The `lora_alpha` of the `adapter_config.json`
+    /// let adapters = lora_adapters(&tensors, 16.0, &Device::cpu()).unwrap();
+    /// bert.set_lora(adapters).unwrap();
+    /// // Optional, for the speed of the model without adapters.
+    /// bert.merge_lora().unwrap();
+    /// # }
+    /// ```
+    pub fn set_lora(&mut self, adapters: Vec<(String, LoraLinear<T>)>) -> Result<(), SmeltError> {
+        self.bert.set_lora(adapters)?;
+        // Recorded with the previous weights.
+        self.captured = None;
+        Ok(())
+    }
+
+    /// Folds the attached adapters into the weights, see [Linear::merge_lora].
+    pub fn merge_lora(&mut self) -> Result<(), SmeltError> {
+        self.bert.merge_lora()?;
+        self.captured = None;
+        Ok(())
+    }
+
+    /// TODO
+    pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
+        self.bert.embeddings.forward(ctx)?;
+        self.forward_encoder(ctx)
+    }
+
+    /// Everything after the embeddings, which only depends on the device buffers (the
+    /// embeddings read the ids from the host).
+    fn forward_encoder(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
+        let BertEncoder { layers, order } = &self.bert.encoder;
+        let split = self
+            .pipeline
+            .as_ref()
+            .map_or(order.len(), |stage| stage.split);
+        for &index in &order[..split] {
+            layers[index].forward(ctx)?;
+        }
+        let ctx = match (&self.pipeline, ctx.next_stage.as_deref_mut()) {
+            (Some(stage), Some(next)) => {
+                (stage.transfer)(&ctx.hidden_states, &mut next.hidden_states)?;
+                next
+            }
+            _ => ctx,
+        };
+        for &index in &order[split..] {
+            layers[index].forward(ctx)?;
+        }
+        self.pooler.forward(ctx)?;
+        self.dropout.forward(&mut ctx.pool_output)?;
+        self.classifier.forward(&ctx.pool_output, &mut ctx.probs)?;
+        T::softmax(&mut ctx.probs)?;
+        Ok(())
+    }
+
+    /// TODO
+    pub fn new_context(
+        &self,
+        input_ids: Vec<usize>,
+        position_ids: Vec<usize>,
+        type_ids: Vec<usize>,
+        num_heads: usize,
+    ) -> Result<BertContext<T>, SmeltError> {
+        let hidden_dim = self.bert.embeddings.hidden_dim();
+        let kv_dim = self.bert.encoder.layers[0].attention.key.shape()[0];
+        let intermediate_dim = self.bert.encoder.layers[0].mlp.intermediate.shape()[0];
+        let num_classes = self.classifier.shape()[0];
+
+        let shapes = BufferShapes {
+            sequence_length: input_ids.len(),
+            hidden_dim,
+            kv_dim,
+            intermediate_dim,
+            num_heads,
+            head_dim: hidden_dim / num_heads,
+            num_classes,
+        };
+
+        let alibi = self.bert.encoder.layers[0].attention.alibi();
+        let relative = self.bert.encoder.layers[0].attention.relative_embeddings();
+        let device = self.bert.embeddings.input_embeddings.weight().device();
+        let layers = &self.bert.encoder.layers;
+        let scores = T::SCORES || layers.iter().any(|layer| layer.attention.needs_scores());
+        let mut context = shapes.alloc(device, input_ids, position_ids, type_ids, alibi, scores)?;
+        context.relative = relative
+            .map(|relative| relative.context(shapes.sequence_length, num_heads, device))
+            .transpose()?;
+        if self.bert.embeddings.projection.is_some() {
+            let shape = vec![shapes.sequence_length, self.bert.embeddings.embedding_dim()];
+            context.embedding_states = Some([device.zeros(shape.clone())?, device.zeros(shape)?]);
+        }
+        if self.pipeline.is_some() {
+            let device = self.classifier.bias().device();
+            let mut stage = shapes.alloc(device, vec![], vec![], vec![], alibi, scores)?;
+            stage.relative = relative
+                .map(|relative| relative.context(shapes.sequence_length, num_heads, device))
+                .transpose()?;
+            context.next_stage = Some(Box::new(stage));
+        }
+        Ok(context)
+    }
+
+    /// TODO
+    pub fn run(
+        &self,
+        input_ids: Vec<usize>,
+        position_ids: Vec<usize>,
+        type_ids: Vec<usize>,
+    ) -> Result<T, SmeltError> {
+        if let Some(captured) = &self.captured {
+            let mut captured = captured.lock().unwrap();
+            if captured.context.input_ids.len() == input_ids.len() {
+                let Captured { context, replay } = &mut *captured;
+                context.input_ids = input_ids;
+                context.position_ids = position_ids;
+                context.type_ids = type_ids;
+                self.bert.embeddings.forward(context)?;
+                replay()?;
+                return Ok(context.probs().clone());
+            }
+        }
+        let mut context = self.new_context(input_ids, position_ids, type_ids, self.num_heads)?;
+        self.forward(&mut context)?;
+        Ok(context.probs().clone())
+    }
+
+    /// [BertClassifier::run] ignoring the pad tokens, where `attention_mask` is 0, see
+    /// [BertContext::set_attention_mask]. Runs without the captured graph, if any.
+    pub fn run_masked(
+        &self,
+        input_ids: Vec<usize>,
+        position_ids: Vec<usize>,
+        type_ids: Vec<usize>,
+        attention_mask: &[usize],
+    ) -> Result<T, SmeltError> {
+        let mut context = self.new_context(input_ids, position_ids, type_ids, self.num_heads)?;
+        context.set_attention_mask(Some(attention_mask))?;
+        self.forward(&mut context)?;
+        Ok(context.probs().clone())
+    }
+
+    /// [BertClassifier::run] on every sequence of a batch of [Ids], returns their
+    /// probabilities in order. The sequences run one after the other, reusing the same
+    /// [BertContext]. The padded sequences need their `attention_mask`, see
+    /// [BertClassifier::run_masked].
+    /// ```no_run
+    /// # use smelte_rs::cpu::f32::Tensor;
+    /// # use smelte_rs::nn::models::bert::BertClassifier;
+    /// use smelte_rs::nn::ids::Ids;
+    ///
+    /// # fn load() -> BertClassifier<Tensor> { unimplemented!() }
+    /// let bert = load();
+    /// // The second sequence is padded with the pad token 0.
+    /// let input_ids = Ids::from_u32(&[101, 2023, 102, 101, 102, 0], vec![2, 3]).unwrap();
+    /// let position_ids = Ids::positions(vec![2, 3]);
+    /// let type_ids = Ids::zeros(vec![2, 3]);
+    /// let attention_mask = Ids::new(vec![1, 1, 1, 1, 1, 0], vec![2, 3]).unwrap();
+    /// let probs = bert
+    ///     .run_ids(&input_ids, &position_ids, &type_ids, Some(&attention_mask))
+    ///     .unwrap();
+    /// assert_eq!(probs.len(), 2);
+    /// ```
+    pub fn run_ids(
+        &self,
+        input_ids: &Ids,
+        position_ids: &Ids,
+        type_ids: &Ids,
+        attention_mask: Option<&Ids>,
+    ) -> Result<Vec<T>, SmeltError> {
+        for ids in [Some(position_ids), Some(type_ids), attention_mask]
+            .into_iter()
+            .flatten()
+        {
+            if ids.shape() != input_ids.shape() {
+                return Err(SmeltError::DimensionMismatch {
+                    expected: input_ids.shape().to_vec(),
+                    got: ids.shape().to_vec(),
+                });
+            }
+        }
+        let masks = (0..input_ids.batch_size()).map(|i| attention_mask.map(|mask| mask.row(i)));
+        let rows = input_ids
+            .rows()
+            .zip(position_ids.rows())
+            .zip(type_ids.rows())
+            .zip(masks);
+        if self.captured.is_some() {
+            return rows
+                .map(|(((input_ids, position_ids), type_ids), mask)| {
+                    let (input_ids, position_ids, type_ids) =
+                        (input_ids.to_vec(), position_ids.to_vec(), type_ids.to_vec());
+                    match mask {
+                        Some(mask) => self.run_masked(input_ids, position_ids, type_ids, mask),
+                        None => self.run(input_ids, position_ids, type_ids),
+                    }
+                })
+                .collect();
+        }
+        let mut context: Option<BertContext<T>> = None;
+        let mut probs = Vec::with_capacity(input_ids.batch_size());
+        for (((input_ids, position_ids), type_ids), mask) in rows {
+            let ctx = match context.take() {
+                Some(mut ctx) => {
+                    ctx.input_ids.copy_from_slice(input_ids);
+                    ctx.position_ids.copy_from_slice(position_ids);
+                    ctx.type_ids.copy_from_slice(type_ids);
+                    ctx
+                }
+                None => self.new_context(
+                    input_ids.to_vec(),
+                    position_ids.to_vec(),
+                    type_ids.to_vec(),
+                    self.num_heads,
+                )?,
+            };
+            let ctx = context.insert(ctx);
+            ctx.set_attention_mask(mask)?;
+            self.forward(ctx)?;
+            probs.push(ctx.probs().clone());
+        }
+        Ok(probs)
+    }
+}
+
+impl<T: Tensor + BertOps<T> + TensorToDevice> BertClassifier<T> {
+    /// Pipeline parallelism: keeps the embeddings and the first `split` layers where they
+    /// are, and moves the other layers, the pooler and the classifier to `device`, for
+    /// models too large for a single gpu. The hidden states are copied to `device`
+    /// after the layer `split - 1`, peer to peer between cuda gpus. A layer shared by
+    /// both sides of the split cannot move (see [BertEncoder::shared]).
+    /// Each sequence goes through the devices one after the other, so
+    /// [BertClassifier::run_batch] with several workers keeps both devices busy, one
+    /// worker running the first layers while another runs the last ones.
+    pub fn split_layers(&mut self, split: usize, device: &T::Device) -> Result<(), SmeltError> {
+        let BertEncoder { layers, order } = &mut self.bert.encoder;
+        if split > order.len() {
+            return Err(SmeltError::InvalidLength {
+                expected: order.len(),
+                got: split,
+            });
+        }
+        let (first, second) = order.split_at(split);
+        if let Some(index) = second.iter().find(|index| first.contains(index)) {
+            return Err(SmeltError::InvalidConfig(format!(
+                "the layer {index} runs on both devices"
+            )));
+        }
+        let mut moved: Vec<usize> = second.to_vec();
+        moved.sort_unstable();
+        moved.dedup();
+        for index in moved {
+            layers[index] = layers[index].to_device(device)?;
+        }
+        self.pooler = self.pooler.to_device(device)?;
+        self.classifier = self.classifier.to_device(device)?;
+        self.pipeline = Some(Stage {
+            split,
+            transfer: T::copy_to_device,
+        });
+        // Recorded on the previous devices.
+        self.captured = None;
+        Ok(())
+    }
+}
+
+impl<T: Tensor + BertOps<T>> BertClassifier<T> {
+    /// Every [Linear] layer of the model.
+    fn linears_mut(&mut self) -> Vec<&mut Linear<T>> {
+        let mut linears = self.bert.encoder.linears_mut();
+        linears.extend([&mut self.pooler.pooler, &mut self.classifier]);
+        linears
+    }
+}
+
+impl<T: Tensor + BertOps<T> + TensorPrecision> BertClassifier<T> {
+    /// Mixed precision for the whole model, see [Bert::set_precision].
+    /// ```no_run
+    /// # use smelte_rs::cpu::f32::Tensor;
+    /// # use smelte_rs::nn::models::bert::BertClassifier;
+    /// use smelte_rs::traits::Precision;
+    ///
+    /// # fn load() -> BertClassifier<Tensor> { unimplemented!() }
+    /// let mut bert = load();
+    /// bert.set_precision(Precision::F16).unwrap();
+    /// ```
+    pub fn set_precision(&mut self, precision: Precision) -> Result<(), SmeltError> {
+        for linear in self.linears_mut() {
+            linear.set_precision(precision)?;
+        }
+        // Recorded with the previous weights.
+        self.captured = None;
+        Ok(())
+    }
+}
+
+impl<T: Tensor + BertOps<T> + TensorQuantize> BertClassifier<T> {
+    /// Dynamic int8 quantization of every [Linear] layer (see
+    /// [Linear::quantize_dynamic]), the embeddings and the layer norms stay in full
+    /// precision.
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        for layer in &mut self.bert.encoder.layers {
+            layer.quantize_dynamic()?;
         }
+        self.pooler.quantize_dynamic()?;
+        self.classifier.quantize_dynamic()?;
+        // Recorded with the previous weights.
+        self.captured = None;
+        Ok(())
     }
+}
 
-    /// TODO
-    pub fn set_num_heads(&mut self, num_heads: usize) {
-        self.num_heads = num_heads
+/// The inputs of one sequence for [BertClassifier::run]: the input ids, the position ids
+/// and the type ids.
+pub type BertInput = (Vec<usize>, Vec<usize>, Vec<usize>);
+
+impl<T: Tensor + BertOps<T> + TensorAttention<T>> Quantize<T> for BertClassifier<T> {
+    type Input = BertInput;
+
+    fn linears(&mut self) -> Vec<&mut Linear<T>> {
+        // Recorded with the previous weights.
+        self.captured = None;
+        self.linears_mut()
     }
 
-    /// TODO
-    pub fn forward(&self, ctx: &mut BertContext<T>) -> Result<(), SmeltError> {
-        self.bert.forward(ctx)?;
-        self.pooler.forward(ctx)?;
-        self.classifier.forward(&ctx.pool_output, &mut ctx.probs)?;
-        T::softmax(&mut ctx.probs)?;
+    fn calibrate(
+        &mut self,
+        (input_ids, position_ids, type_ids): &BertInput,
+    ) -> Result<(), SmeltError> {
+        self.run(input_ids.clone(), position_ids.clone(), type_ids.clone())?;
         Ok(())
     }
+}
 
-    /// TODO
-    pub fn new_context(
+impl<T: Tensor + BertOps<T> + Send + Sync> BertClassifier<T> {
+    /// Runs the independent sequences of `batch` on `num_workers` threads (`0` uses one
+    /// per cpu) and returns their probabilities, in order.
+    /// Every worker reads the same weights and keeps its own [BertContext], reused while
+    /// the sequence lengths match, so sorting `batch` by length avoids reallocations.
+    /// The kernels still split over the cpu thread pool, for many short sequences
+    /// [crate::set_num_threads]`(1)` leaves the cpus to the workers.
+    pub fn run_batch(
         &self,
-        input_ids: Vec<usize>,
-        position_ids: Vec<usize>,
-        type_ids: Vec<usize>,
-        num_heads: usize,
-    ) -> Result<BertContext<T>, SmeltError> {
-        let hidden_dim = self.bert.embeddings.input_embeddings.weight().shape()[1];
-        let intermediate_dim = self.bert.encoder.layers[0]
-            .mlp
-            .intermediate
-            .weight()
-            .shape()[0];
-        let num_classes = self.classifier.weight().shape()[0];
-        let head_dim = hidden_dim / num_heads;
-        let sequence_length = input_ids.len();
-
-        let device = self.classifier.weight().device();
-        let hidden_states = device.zeros(vec![sequence_length, hidden_dim])?;
-        let hidden_states_copy = device.zeros(vec![sequence_length, hidden_dim])?;
-        let hidden_states_attn_output = device.zeros(vec![sequence_length, hidden_dim])?;
-        let intermediate_states = device.zeros(vec![sequence_length, intermediate_dim])?;
-        let q_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
-        let k_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
-        let v_cache = device.zeros(vec![num_heads, sequence_length, head_dim])?;
-        let qk = device.zeros(vec![num_heads, sequence_length, sequence_length])?;
-        let qkv = device.zeros(vec![num_heads, sequence_length, head_dim])?;
-        let pool = device.zeros(vec![1, hidden_dim])?;
-        let pool_output = device.zeros(vec![1, hidden_dim])?;
-        let probs = device.zeros(vec![1, num_classes])?;
-        Ok(BertContext {
-            input_ids,
-            position_ids,
-            type_ids,
-            hidden_states,
-            hidden_states_copy,
-            hidden_states_attn_output,
-            intermediate_states,
-            q_cache,
-            k_cache,
-            v_cache,
-            qk,
-            qkv,
-            pool,
-            pool_output,
-            probs,
-        })
+        batch: Vec<BertInput>,
+        num_workers: usize,
+    ) -> Result<Vec<T>, SmeltError> {
+        let num_workers = match num_workers {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(batch.len());
+        let queue = Mutex::new(batch.into_iter().enumerate());
+        let outputs: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..num_workers)
+                .map(|_| scope.spawn(|| self.batch_worker(&queue)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Bert worker panicked"))
+                .collect()
+        });
+        let mut probs = vec![];
+        for output in outputs {
+            probs.extend(output?);
+        }
+        probs.sort_by_key(|(i, _)| *i);
+        Ok(probs.into_iter().map(|(_, probs)| probs).collect())
     }
 
-    /// TODO
-    pub fn run(
+    fn batch_worker(
         &self,
-        input_ids: Vec<usize>,
-        position_ids: Vec<usize>,
-        type_ids: Vec<usize>,
-    ) -> Result<T, SmeltError> {
-        let mut context = self.new_context(input_ids, position_ids, type_ids, self.num_heads)?;
+        queue: &Mutex<impl Iterator<Item = (usize, BertInput)>>,
+    ) -> Result<Vec<(usize, T)>, SmeltError> {
+        let mut context: Option<BertContext<T>> = None;
+        let mut probs = vec![];
+        loop {
+            let next = queue.lock().unwrap().next();
+            let Some((i, (input_ids, position_ids, type_ids))) = next else {
+                break;
+            };
+            let ctx = match context.take() {
+                Some(mut ctx) if ctx.input_ids.len() == input_ids.len() => {
+                    ctx.input_ids = input_ids;
+                    ctx.position_ids = position_ids;
+                    ctx.type_ids = type_ids;
+                    ctx
+                }
+                _ => self.new_context(input_ids, position_ids, type_ids, self.num_heads)?,
+            };
+            let ctx = context.insert(ctx);
+            self.forward(ctx)?;
+            probs.push((i, ctx.probs().clone()));
+        }
+        Ok(probs)
+    }
+}
+
+impl<T: Tensor + BertOps<T>> BertClassifier<T>
+where
+    T::Device: DeviceCapture,
+{
+    /// Records the forward pass for inputs of `sequence_length` tokens (a cuda graph),
+    /// [BertClassifier::run] replays it for all the inputs of that length afterwards,
+    /// which removes the launch overhead of every kernel.
+    pub fn capture(&mut self, sequence_length: usize) -> Result<(), SmeltError> {
+        if self.pipeline.is_some() {
+            // A graph records the kernels of a single device.
+            return Err(SmeltError::Unsupported {
+                operation: "capture",
+                backend: "pipeline",
+            });
+        }
+        if self.dropout.is_training() {
+            // The graph would replay the masks of the dropouts.
+            return Err(SmeltError::Unsupported {
+                operation: "capture",
+                backend: "train mode",
+            });
+        }
+        let ids = vec![0; sequence_length];
+        let position_ids = (0..sequence_length).collect();
+        let mut context = self.new_context(ids.clone(), position_ids, ids, self.num_heads)?;
+        // Loads all the kernels, which cannot happen during the capture.
         self.forward(&mut context)?;
-        Ok(context.probs)
+        let device = self.classifier.bias().device();
+        let replay = device.capture(&mut || self.forward_encoder(&mut context))?;
+        self.captured = Some(Arc::new(Mutex::new(Captured { context, replay })));
+        Ok(())
     }
 }
 
@@ -669,6 +3053,466 @@ mod tests {
         Device::new(0).unwrap()
     }
 
+    #[cfg(feature = "metal")]
+    fn metal_device() -> crate::gpu::metal::f32::Device {
+        crate::gpu::metal::f32::Device::new(0).unwrap()
+    }
+
+    #[cfg(feature = "wgpu")]
+    fn wgpu_device() -> crate::gpu::wgpu::f32::Device {
+        crate::gpu::wgpu::f32::Device::new(0).unwrap()
+    }
+
+    #[cfg(feature = "opencl")]
+    fn opencl_device() -> crate::gpu::opencl::f32::Device {
+        crate::gpu::opencl::f32::Device::new(0).unwrap()
+    }
+
+    #[cfg(feature = "vulkan")]
+    fn vulkan_device() -> crate::gpu::vulkan::f32::Device {
+        crate::gpu::vulkan::f32::Device::new(0).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_attention_mask() {
+        let shapes = BufferShapes {
+            sequence_length: 3,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let mut ctx: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![0; 3], vec![0; 3], vec![0; 3], None, false)
+            .unwrap();
+        ctx.hidden_states = F32Tensor::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], vec![3, 2]).unwrap();
+        let identity = || {
+            let weight = F32Tensor::new(vec![1.0, 0.0, 0.0, 1.0], vec![2, 2]).unwrap();
+            Linear::new(weight, F32Tensor::zeros(vec![2]))
+        };
+        let (query, key, value) = (identity(), identity(), identity());
+
+        assert!(ctx.set_attention_mask(Some(&[1, 1])).is_err());
+        assert!(ctx.set_attention_mask(Some(&[0, 0, 0])).is_err());
+        ctx.set_attention_mask(Some(&[1, 1, 0])).unwrap();
+        F32Tensor::attention(&query, &key, &value, &Dropout::default(), &mut ctx).unwrap();
+        // Without the last value [1, 1], the outputs mix [1, 0] and [0, 1].
+        for row in ctx.hidden_states_attn_output.data().chunks(2) {
+            assert!((row[0] + row[1] - 1.0).abs() < 1e-6);
+        }
+
+        ctx.set_attention_mask(None).unwrap();
+        F32Tensor::attention(&query, &key, &value, &Dropout::default(), &mut ctx).unwrap();
+        let row = &ctx.hidden_states_attn_output.data()[..2];
+        assert!(row[0] + row[1] > 1.0);
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_pooler_activation() {
+        let shapes = BufferShapes {
+            sequence_length: 2,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let mut ctx: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![0; 2], vec![0; 2], vec![0; 2], None, false)
+            .unwrap();
+        ctx.hidden_states = F32Tensor::new(vec![2.0, -1.0, 5.0, 5.0], vec![2, 2]).unwrap();
+        let identity = || {
+            let weight = F32Tensor::new(vec![1.0, 0.0, 0.0, 1.0], vec![2, 2]).unwrap();
+            Linear::new(weight, F32Tensor::zeros(vec![2]))
+        };
+        // The first token, through a tanh like BERT or a relu like DistilBERT.
+        BertPooler::new(identity()).forward(&mut ctx).unwrap();
+        let expected = [2f32.tanh(), (-1f32).tanh()];
+        let output = ctx.pool_output.data();
+        assert!(output
+            .iter()
+            .zip(expected)
+            .all(|(o, e)| (o - e).abs() < 1e-6));
+        let pooler = BertPooler::new(identity()).with_activation(Activation::Relu);
+        pooler.forward(&mut ctx).unwrap();
+        assert_eq!(ctx.pool_output.data(), [2.0, 0.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_embeddings_without_type_embeddings() {
+        let shapes = BufferShapes {
+            sequence_length: 2,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let embedding = |data: Vec<f32>| {
+            let shape = vec![data.len() / 2, 2];
+            Embedding::new(F32Tensor::new(data, shape).unwrap())
+        };
+        let layer_norm = || {
+            let weight = F32Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+            LayerNorm::new(weight, F32Tensor::zeros(vec![2]), 1e-5)
+        };
+        let (words, positions) = (vec![1.0, 0.0, 0.0, 3.0], vec![0.0, 1.0, 2.0, 0.0]);
+        let embeddings = BertEmbeddings::without_type_embeddings(
+            embedding(words.clone()),
+            embedding(positions.clone()),
+            layer_norm(),
+        );
+        // Without type ids, like a zero type embedding.
+        let mut ctx: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![1, 0], vec![0, 1], vec![], None, false)
+            .unwrap();
+        embeddings.forward(&mut ctx).unwrap();
+        let embeddings = BertEmbeddings::new(
+            embedding(words),
+            embedding(positions),
+            embedding(vec![0.0; 2]),
+            layer_norm(),
+        );
+        let mut expected: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![1, 0], vec![0, 1], vec![0; 2], None, false)
+            .unwrap();
+        embeddings.forward(&mut expected).unwrap();
+        assert_eq!(ctx.hidden_states.data(), expected.hidden_states.data());
+        assert!(embeddings.forward(&mut ctx).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_add_relative_scores() {
+        let c2p = F32Tensor::new(vec![0.0, 1.0, 2.0, 10.0, 11.0, 12.0], vec![1, 2, 3]).unwrap();
+        let data = vec![100.0, 200.0, 300.0, 400.0, 500.0, 600.0];
+        let p2c = F32Tensor::new(data, vec![1, 3, 2]).unwrap();
+        let mut scores = F32Tensor::zeros(vec![1, 2, 2]);
+        F32Tensor::add_relative_scores(&c2p, &p2c, &[1, 0, 2, 1], &mut scores).unwrap();
+        assert_eq!(scores.data(), [301.0, 200.0, 512.0, 411.0]);
+
+        assert!(F32Tensor::add_relative_scores(&c2p, &p2c, &[1, 0, 2], &mut scores).is_err());
+        assert!(matches!(
+            F32Tensor::add_relative_scores(&c2p, &p2c, &[1, 0, 3, 1], &mut scores),
+            Err(SmeltError::OutOfVocabulary { .. })
+        ));
+        let mut scores = F32Tensor::zeros(vec![1, 2, 3]);
+        assert!(F32Tensor::add_relative_scores(&c2p, &p2c, &[0; 6], &mut scores).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_disentangled_attention() {
+        let shapes = BufferShapes {
+            sequence_length: 3,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let linear = |data: Vec<f32>| {
+            let weight = F32Tensor::new(data, vec![2, 2]).unwrap();
+            Linear::new(weight, F32Tensor::zeros(vec![2]))
+        };
+        let layer_norm = || {
+            let weight = F32Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+            LayerNorm::new(weight, F32Tensor::zeros(vec![2]), 1e-5)
+        };
+        let attention = |query: Vec<f32>| {
+            BertAttention::new(
+                linear(query),
+                linear(vec![0.5, 1.0, -1.0, 2.0]),
+                linear(vec![1.0, 0.0, 1.0, 1.0]),
+                linear(vec![1.0, 0.0, 0.0, 1.0]),
+                layer_norm(),
+            )
+        };
+        let run = |attention: &BertAttention<F32Tensor>, ctx: &mut BertContext<F32Tensor>| {
+            ctx.hidden_states =
+                F32Tensor::new(vec![1.0, 0.0, 0.0, 2.0, 1.0, -1.0], vec![3, 2]).unwrap();
+            attention.forward(ctx).unwrap();
+            ctx.hidden_states.data().to_vec()
+        };
+        let mut ctx: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![0; 3], vec![0; 3], vec![0; 3], None, true)
+            .unwrap();
+        // Without relative scores, the queries are scaled by 1 / sqrt(3).
+        let scale = 1.0 / 3f32.sqrt();
+        let expected = run(&attention(vec![scale, 0.0, -scale, scale]), &mut ctx);
+
+        let positions = LogBucketPositions::new(2, 4).unwrap();
+        let zeros = F32Tensor::zeros(vec![4, 2]);
+        let relative = RelativeEmbeddings::new(zeros, positions).unwrap();
+        let attention = attention(vec![1.0, 0.0, -1.0, 1.0]).with_relative_embeddings(relative);
+        // The context has no buffers for the relative positions.
+        assert!(attention.forward(&mut ctx).is_err());
+        let relative = attention.relative_embeddings().unwrap();
+        ctx.relative = Some(relative.context(3, 1, &device).unwrap());
+        let output = run(&attention, &mut ctx);
+        assert!(output
+            .iter()
+            .zip(expected)
+            .all(|(o, e)| (o - e).abs() < 1e-5));
+
+        let embeddings = F32Tensor::zeros(vec![3, 2]);
+        assert!(RelativeEmbeddings::new(embeddings, positions).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_shared_encoder() {
+        let shapes = BufferShapes {
+            sequence_length: 2,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let linear = |data: Vec<f32>| {
+            let bias = F32Tensor::new(vec![0.5, -0.5], vec![2]).unwrap();
+            Linear::new(F32Tensor::new(data, vec![2, 2]).unwrap(), bias)
+        };
+        let layer_norm = || {
+            let weight = F32Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+            LayerNorm::new(weight, F32Tensor::zeros(vec![2]), 1e-5)
+        };
+        let layer = || {
+            let attention = BertAttention::new(
+                linear(vec![1.0, 0.5, 0.0, 1.0]),
+                linear(vec![0.5, 0.0, 1.0, 1.0]),
+                linear(vec![1.0, 0.0, -1.0, 2.0]),
+                linear(vec![0.0, 1.0, 1.0, 0.0]),
+                layer_norm(),
+            );
+            let mlp = Mlp::new(
+                linear(vec![2.0, 1.0, 0.0, -1.0]),
+                linear(vec![1.0, 1.0, 0.5, 0.0]),
+                layer_norm(),
+            );
+            BertLayer::new(attention, mlp)
+        };
+        let run = |encoder: &BertEncoder<F32Tensor>| {
+            let mut ctx: BertContext<F32Tensor> = shapes
+                .alloc(&device, vec![0; 2], vec![0; 2], vec![0; 2], None, false)
+                .unwrap();
+            ctx.hidden_states = F32Tensor::new(vec![1.0, -2.0, 0.5, 3.0], vec![2, 2]).unwrap();
+            encoder.forward(&mut ctx).unwrap();
+            ctx.hidden_states.data().to_vec()
+        };
+        // One layer run 3 times, like 3 copies of it.
+        let shared = BertEncoder::shared(vec![layer()], vec![0; 3]).unwrap();
+        assert_eq!(shared.num_layers(), 3);
+        assert_eq!(run(&shared), run(&BertEncoder::new(vec![layer(); 3])));
+
+        assert!(matches!(
+            BertEncoder::shared(vec![layer()], vec![0, 1]),
+            Err(SmeltError::InvalidLength { .. })
+        ));
+        assert!(BertEncoder::<F32Tensor>::shared(vec![], vec![]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_layer_dropout() {
+        let shapes = BufferShapes {
+            sequence_length: 2,
+            hidden_dim: 2,
+            kv_dim: 2,
+            intermediate_dim: 2,
+            num_heads: 1,
+            head_dim: 2,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let linear = |data: Vec<f32>| {
+            let bias = F32Tensor::new(vec![0.5, -0.5], vec![2]).unwrap();
+            Linear::new(F32Tensor::new(data, vec![2, 2]).unwrap(), bias)
+        };
+        let layer_norm = || {
+            let weight = F32Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+            LayerNorm::new(weight, F32Tensor::zeros(vec![2]), 1e-5)
+        };
+        let all = || Dropout::new(1.0).unwrap();
+        let attention = BertAttention::new(
+            linear(vec![1.0, 0.5, 0.0, 1.0]),
+            linear(vec![0.5, 0.0, 1.0, 1.0]),
+            linear(vec![1.0, 0.0, -1.0, 2.0]),
+            linear(vec![0.0, 1.0, 1.0, 0.0]),
+            layer_norm(),
+        );
+        let mlp = Mlp::new(
+            linear(vec![2.0, 1.0, 0.0, -1.0]),
+            linear(vec![1.0, 1.0, 0.5, 0.0]),
+            layer_norm(),
+        );
+        let plain = BertLayer::new(attention.clone(), mlp.clone());
+        let mut layer = BertLayer::new(
+            attention.with_dropout(all(), all()),
+            mlp.with_dropout(all()),
+        );
+        let run = |layer: &BertLayer<F32Tensor>| {
+            let scores = layer.attention.needs_scores();
+            let mut ctx: BertContext<F32Tensor> = shapes
+                .alloc(&device, vec![0; 2], vec![0; 2], vec![0; 2], None, scores)
+                .unwrap();
+            ctx.hidden_states = F32Tensor::new(vec![1.0, -2.0, 0.5, 3.0], vec![2, 2]).unwrap();
+            layer.forward(&mut ctx).unwrap();
+            ctx.hidden_states.data().to_vec()
+        };
+        // The identity in eval mode.
+        assert!(!layer.attention.needs_scores());
+        assert_eq!(run(&layer), run(&plain));
+
+        // Both outputs dropped, only the layer norms of the residuals remain.
+        layer.set_training(true);
+        assert!(layer.attention.needs_scores());
+        let mut expected = F32Tensor::new(vec![1.0, -2.0, 0.5, 3.0], vec![2, 2]).unwrap();
+        layer_norm().forward(&mut expected).unwrap();
+        layer_norm().forward(&mut expected).unwrap();
+        for (a, b) in run(&layer).iter().zip(expected.data()) {
+            assert!((a - b).abs() < 1e-4, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_embeddings_projection() {
+        let shapes = BufferShapes {
+            sequence_length: 2,
+            hidden_dim: 3,
+            kv_dim: 3,
+            intermediate_dim: 3,
+            num_heads: 1,
+            head_dim: 3,
+            num_classes: 1,
+        };
+        let device = crate::cpu::f32::Device::new();
+        let embedding = |data: Vec<f32>| {
+            let shape = vec![data.len() / 2, 2];
+            Embedding::new(F32Tensor::new(data, shape).unwrap())
+        };
+        let layer_norm = F32Tensor::new(vec![1.0; 2], vec![2]).unwrap();
+        let layer_norm = LayerNorm::new(layer_norm, F32Tensor::zeros(vec![2]), 1e-5);
+        let projection = F32Tensor::new(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0], vec![3, 2]).unwrap();
+        let projection = Linear::new(projection, F32Tensor::zeros(vec![3]));
+        // Embeddings of 2 projected on the hidden states of 3, like ALBERT.
+        let embeddings = BertEmbeddings::without_type_embeddings(
+            embedding(vec![1.0, 0.0, 0.0, 3.0]),
+            embedding(vec![0.0; 4]),
+            layer_norm,
+        )
+        .with_projection(projection);
+        assert_eq!(
+            (embeddings.embedding_dim(), embeddings.hidden_dim()),
+            (2, 3)
+        );
+
+        let mut ctx: BertContext<F32Tensor> = shapes
+            .alloc(&device, vec![0, 1], vec![0, 1], vec![], None, false)
+            .unwrap();
+        assert!(embeddings.forward(&mut ctx).is_err());
+        let states = || F32Tensor::zeros(vec![2, 2]);
+        ctx.embedding_states = Some([states(), states()]);
+        embeddings.forward(&mut ctx).unwrap();
+        // The layer norms of [1, 0] and [0, 3], then their sum.
+        let output: Vec<_> = ctx.hidden_states.data().iter().map(|v| v.round()).collect();
+        assert_eq!(output, [1.0, -1.0, 0.0, -1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_fused_attention() {
+        use crate::cpu::f32 as cpu_f32;
+        // More queries than a block and more keys than a tile.
+        let (num_heads, length, head_dim) = (2, 70, 4);
+        let data: Vec<f32> = (0..num_heads * length * head_dim)
+            .map(|i| ((i * 7) % 11) as f32 / 11.0 - 0.5)
+            .collect();
+        let shape = vec![num_heads, length, head_dim];
+        let query = F32Tensor::new(data.clone(), shape.clone()).unwrap();
+        let shifted: Vec<f32> = data[8..].iter().chain(&data[..8]).copied().collect();
+        let key = F32Tensor::new(shifted, shape.clone()).unwrap();
+        let reversed: Vec<f32> = data.iter().rev().copied().collect();
+        let value = F32Tensor::new(reversed, shape.clone()).unwrap();
+        // The bias of the padded fifth key, the alibi slopes and the causal mask.
+        let mut padding = vec![0.0; length];
+        padding[4] = f32::NEG_INFINITY;
+        let slopes = [0.5, 0.25];
+        let bias: Vec<f32> = (0..num_heads * length * length)
+            .map(|n| {
+                let (h, i, j) = (n / (length * length), (n / length) % length, n % length);
+                if j > i {
+                    f32::NEG_INFINITY
+                } else {
+                    padding[j] - slopes[h] * (i - j) as f32
+                }
+            })
+            .collect();
+        let bias = F32Tensor::new(bias, vec![num_heads, length, length]).unwrap();
+        let mask = F32Tensor::new(padding, vec![length]).unwrap();
+        let slopes = F32Tensor::new(slopes.to_vec(), vec![num_heads]).unwrap();
+        let config = FusedAttentionConfig {
+            scale: 0.5,
+            causal: true,
+        };
+
+        let mut out = F32Tensor::zeros(shape.clone());
+        F32Tensor::fused_attention(
+            &query,
+            &key,
+            &value,
+            Some(&mask),
+            Some(&slopes),
+            &config,
+            &mut out,
+        )
+        .unwrap();
+
+        let mut scores = F32Tensor::zeros(vec![num_heads, length, length]);
+        cpu_f32::matmul_t(&query, &key, &mut scores).unwrap();
+        cpu_f32::mul_scalar(&mut scores, 0.5);
+        cpu_f32::add(&bias, &mut scores).unwrap();
+        cpu_f32::softmax(&mut scores).unwrap();
+        let mut expected = F32Tensor::zeros(shape.clone());
+        cpu_f32::matmul(&scores, &value, &mut expected).unwrap();
+        for (o, e) in out.data().iter().zip(expected.data()) {
+            assert!((o - e).abs() < 1e-5);
+        }
+
+        let config = FusedAttentionConfig::default();
+        let mut out = F32Tensor::zeros(vec![num_heads, length, 2]);
+        assert!(
+            F32Tensor::fused_attention(&query, &key, &value, None, None, &config, &mut out)
+                .is_err()
+        );
+        assert!(F32Tensor::fused_attention(
+            &query,
+            &key,
+            &value,
+            Some(&F32Tensor::zeros(vec![num_heads, length, length])),
+            None,
+            &config,
+            &mut F32Tensor::zeros(shape),
+        )
+        .is_err());
+    }
+
     #[test]
     #[cfg(feature = "cpu")]
     fn test_split_heads() {
@@ -676,10 +3520,39 @@ mod tests {
             F32Tensor::new(vec![1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0], vec![2, 4]).unwrap();
         let mut out = F32Tensor::zeros(vec![2, 2, 2]);
 
-        cpu::split_heads(&tensor, &mut out).unwrap();
+        F32Tensor::split_heads(&tensor, &mut out).unwrap();
         assert_eq!(out.data(), [1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]);
     }
 
+    #[test]
+    #[cfg(feature = "cpu")]
+    fn test_split_kv_heads() {
+        // 2 key heads shared by 4 query heads.
+        let tensor =
+            F32Tensor::new((0..8).map(|v| v as f32).collect::<Vec<_>>(), vec![2, 4]).unwrap();
+        let mut out = F32Tensor::zeros(vec![4, 2, 2]);
+
+        F32Tensor::split_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.data(),
+            [0.0, 1.0, 4.0, 5.0, 0.0, 1.0, 4.0, 5.0, 2.0, 3.0, 6.0, 7.0, 2.0, 3.0, 6.0, 7.0]
+        );
+        let mut out = F32Tensor::zeros(vec![3, 2, 2]);
+        assert!(matches!(
+            F32Tensor::split_heads(&tensor, &mut out),
+            Err(SmeltError::InvalidConfig(_))
+        ));
+
+        // A single column shared by the 2 heads, like in a fused projection.
+        let mut out = F32Tensor::zeros(vec![2, 2, 1]);
+        F32Tensor::split_heads_at(&tensor, 1, 1, &mut out).unwrap();
+        assert_eq!(out.data(), [1.0, 5.0, 1.0, 5.0]);
+        let mut merged = F32Tensor::zeros(vec![2, 4]);
+        F32Tensor::unsplit_heads_at(&out, 2, &mut merged).unwrap();
+        assert_eq!(merged.data(), [0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 5.0, 5.0]);
+        assert!(F32Tensor::split_heads_at(&tensor, 4, 1, &mut out).is_err());
+    }
+
     #[test]
     #[cfg(feature = "cpu")]
     fn test_unsplit_heads() {
@@ -687,7 +3560,7 @@ mod tests {
             F32Tensor::new(vec![1.0, 3.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0], vec![2, 2, 2]).unwrap();
         let mut out = F32Tensor::zeros(vec![2, 4]);
 
-        cpu::unsplit_heads(&tensor, &mut out).unwrap();
+        F32Tensor::unsplit_heads(&tensor, &mut out).unwrap();
         assert_eq!(out.data(), [1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]);
     }
 
@@ -703,13 +3576,27 @@ mod tests {
         .unwrap();
         let mut out = F32CudaTensor::zeros(vec![2, 2, 2], &device).unwrap();
 
-        cuda::cuda_split_heads(&tensor, &mut out).unwrap();
+        F32CudaTensor::split_heads(&tensor, &mut out).unwrap();
         assert_eq!(
             out.cpu_data().unwrap(),
             vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
         );
     }
 
+    #[cfg(feature = "cuda")]
+    #[test]
+    fn test_cuda_add_relative_scores() {
+        let device = device();
+        let data = [0.0, 1.0, 2.0, 10.0, 11.0, 12.0];
+        let c2p = F32CudaTensor::from_cpu(&data, vec![1, 2, 3], &device).unwrap();
+        let data = [100.0, 200.0, 300.0, 400.0, 500.0, 600.0];
+        let p2c = F32CudaTensor::from_cpu(&data, vec![1, 3, 2], &device).unwrap();
+        let mut scores = F32CudaTensor::zeros(vec![1, 2, 2], &device).unwrap();
+
+        F32CudaTensor::add_relative_scores(&c2p, &p2c, &[1, 0, 2, 1], &mut scores).unwrap();
+        assert_eq!(scores.cpu_data().unwrap(), vec![301.0, 200.0, 512.0, 411.0]);
+    }
+
     #[cfg(feature = "cuda")]
     #[test]
     fn test_cuda_unsplit_heads() {
@@ -722,7 +3609,181 @@ mod tests {
         .unwrap();
         let mut out = F32CudaTensor::zeros(vec![2, 4], &device).unwrap();
 
-        cuda::cuda_unsplit_heads(&tensor, &mut out).unwrap();
+        F32CudaTensor::unsplit_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(all(feature = "cuda", feature = "f16"))]
+    #[test]
+    fn test_cuda_f16_split_heads() {
+        use half::f16;
+        let device = crate::gpu::f16::Device::new(0).unwrap();
+        let data: Vec<_> = [1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+            .into_iter()
+            .map(f16::from_f32)
+            .collect();
+        let tensor = F16CudaTensor::from_cpu(&data, vec![2, 4], &device).unwrap();
+        let mut out = F16CudaTensor::zeros(vec![2, 2, 2], &device).unwrap();
+        F16CudaTensor::split_heads(&tensor, &mut out).unwrap();
+
+        let mut back = F16CudaTensor::zeros(vec![2, 4], &device).unwrap();
+        F16CudaTensor::unsplit_heads(&out, &mut back).unwrap();
+        assert_eq!(
+            out.to_f32().unwrap().cpu_data().unwrap(),
+            vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
+        );
+        assert_eq!(back.cpu_data().unwrap(), data);
+    }
+
+    #[cfg(feature = "metal")]
+    #[test]
+    fn test_metal_split_heads() {
+        let device = metal_device();
+        let tensor = F32MetalTensor::from_cpu(
+            &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0],
+            vec![2, 4],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32MetalTensor::zeros(vec![2, 2, 2], &device).unwrap();
+
+        F32MetalTensor::split_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "metal")]
+    #[test]
+    fn test_metal_unsplit_heads() {
+        let device = metal_device();
+        let tensor = F32MetalTensor::from_cpu(
+            &[1.0, 3.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0],
+            vec![2, 2, 2],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32MetalTensor::zeros(vec![2, 4], &device).unwrap();
+
+        F32MetalTensor::unsplit_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "wgpu")]
+    #[test]
+    fn test_wgpu_split_heads() {
+        let device = wgpu_device();
+        let tensor = F32WgpuTensor::from_cpu(
+            &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0],
+            vec![2, 4],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32WgpuTensor::zeros(vec![2, 2, 2], &device).unwrap();
+
+        F32WgpuTensor::split_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "wgpu")]
+    #[test]
+    fn test_wgpu_unsplit_heads() {
+        let device = wgpu_device();
+        let tensor = F32WgpuTensor::from_cpu(
+            &[1.0, 3.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0],
+            vec![2, 2, 2],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32WgpuTensor::zeros(vec![2, 4], &device).unwrap();
+
+        F32WgpuTensor::unsplit_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "opencl")]
+    #[test]
+    fn test_opencl_split_heads() {
+        let device = opencl_device();
+        let tensor = F32OpenClTensor::from_cpu(
+            &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0],
+            vec![2, 4],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32OpenClTensor::zeros(vec![2, 2, 2], &device).unwrap();
+
+        F32OpenClTensor::split_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "opencl")]
+    #[test]
+    fn test_opencl_unsplit_heads() {
+        let device = opencl_device();
+        let tensor = F32OpenClTensor::from_cpu(
+            &[1.0, 3.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0],
+            vec![2, 2, 2],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32OpenClTensor::zeros(vec![2, 4], &device).unwrap();
+
+        F32OpenClTensor::unsplit_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "vulkan")]
+    #[test]
+    fn test_vulkan_split_heads() {
+        let device = vulkan_device();
+        let tensor = F32VulkanTensor::from_cpu(
+            &[1.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0],
+            vec![2, 4],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32VulkanTensor::zeros(vec![2, 2, 2], &device).unwrap();
+
+        F32VulkanTensor::split_heads(&tensor, &mut out).unwrap();
+        assert_eq!(
+            out.cpu_data().unwrap(),
+            vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]
+        );
+    }
+
+    #[cfg(feature = "vulkan")]
+    #[test]
+    fn test_vulkan_unsplit_heads() {
+        let device = vulkan_device();
+        let tensor = F32VulkanTensor::from_cpu(
+            &[1.0, 3.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0],
+            vec![2, 2, 2],
+            &device,
+        )
+        .unwrap();
+        let mut out = F32VulkanTensor::zeros(vec![2, 4], &device).unwrap();
+
+        F32VulkanTensor::unsplit_heads(&tensor, &mut out).unwrap();
         assert_eq!(
             out.cpu_data().unwrap(),
             vec![1.0, 3.0, 1.0, 1.0, 2.0, 2.0, 2.0, 2.0]
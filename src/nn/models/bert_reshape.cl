@@ -0,0 +1,51 @@
+__kernel void split_heads(
+    __global const float *q,
+    __global float *q_split,
+    const uint numel,
+    const uint num_heads,
+    const uint sequence_length,
+    const uint head_dim,
+    const uint num_kv_heads,
+    const uint offset,
+    const uint width
+) {
+    const uint n = get_global_id(0);
+    if (n >= numel) {
+        return;
+    }
+
+    const uint k = n % head_dim;
+    const uint j = (n / head_dim) % sequence_length;
+    const uint i = n / head_dim / sequence_length;
+
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of q, the heads
+    // being repeated for grouped-query attention.
+    const uint index = j * width + offset + (i / (num_heads / num_kv_heads)) * head_dim + k;
+    const uint out_index = i * sequence_length * head_dim + j * head_dim + k;
+    q_split[out_index] = q[index];
+}
+
+__kernel void unsplit_heads(
+    __global const float *q_split,
+    __global float *q,
+    const uint numel,
+    const uint num_heads,
+    const uint sequence_length,
+    const uint head_dim,
+    const uint num_kv_heads,
+    const uint offset,
+    const uint width
+) {
+    const uint n = get_global_id(0);
+    if (n >= numel) {
+        return;
+    }
+
+    const uint k = n % head_dim;
+    const uint j = (n / head_dim) % sequence_length;
+    const uint i = n / head_dim / sequence_length;
+
+    const uint in_index = i * sequence_length * head_dim + j * head_dim + k;
+    const uint out_index = j * width + offset + i * head_dim + k;
+    q[out_index] = q_split[in_index];
+}
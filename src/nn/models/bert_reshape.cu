@@ -1,10 +1,16 @@
+#include "cuda_fp16.h"
+#include "cuda_bf16.h"
+
 extern "C" __global__ void split_heads(
     const size_t numel,
     const float *q,
     float *q_split,
     const size_t num_heads,
     const size_t sequence_length,
-    const size_t head_dim
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
 ) {
     size_t n = blockIdx.x * blockDim.x + threadIdx.x;
     if (n >= numel) {
@@ -15,8 +21,9 @@ extern "C" __global__ void split_heads(
     const size_t j = (n / head_dim) % sequence_length;
     const size_t i = n / head_dim / sequence_length;
 
-    const size_t hidden_dim = num_heads * head_dim;
-    const size_t index = j * hidden_dim + i * head_dim + k;
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of q, the heads
+    // being repeated for grouped-query attention.
+    const size_t index = j * width + offset + (i / (num_heads / num_kv_heads)) * head_dim + k;
     const size_t out_index = i * sequence_length * head_dim + j * head_dim + k;
     q_split[out_index] = q[index];
 }
@@ -27,7 +34,10 @@ extern "C" __global__ void unsplit_heads(
     float *q,
     const size_t num_heads,
     const size_t sequence_length,
-    const size_t head_dim
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
 ) {
     size_t n = blockIdx.x * blockDim.x + threadIdx.x;
     if (n >= numel) {
@@ -38,9 +48,140 @@ extern "C" __global__ void unsplit_heads(
     const size_t j = (n / head_dim) % sequence_length;
     const size_t i = n / head_dim / sequence_length;
 
-    const size_t hidden_dim = num_heads * head_dim;
     const size_t in_index = i * sequence_length * head_dim + j * head_dim + k;
-    const size_t out_index = j * hidden_dim + i * head_dim + k;
+    const size_t out_index = j * width + offset + i * head_dim + k;
 
     q[out_index] = q_split[in_index];
 }
+
+extern "C" __global__ void split_heads_f16(
+    const size_t numel,
+    const __half *q,
+    __half *q_split,
+    const size_t num_heads,
+    const size_t sequence_length,
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
+) {
+    size_t n = blockIdx.x * blockDim.x + threadIdx.x;
+    if (n >= numel) {
+        return;
+    }
+
+    const size_t k = n % head_dim;
+    const size_t j = (n / head_dim) % sequence_length;
+    const size_t i = n / head_dim / sequence_length;
+
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of q, the heads
+    // being repeated for grouped-query attention.
+    const size_t index = j * width + offset + (i / (num_heads / num_kv_heads)) * head_dim + k;
+    const size_t out_index = i * sequence_length * head_dim + j * head_dim + k;
+    q_split[out_index] = q[index];
+}
+
+extern "C" __global__ void unsplit_heads_f16(
+    const size_t numel,
+    const __half *q_split,
+    __half *q,
+    const size_t num_heads,
+    const size_t sequence_length,
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
+) {
+    size_t n = blockIdx.x * blockDim.x + threadIdx.x;
+    if (n >= numel) {
+        return;
+    }
+
+    const size_t k = n % head_dim;
+    const size_t j = (n / head_dim) % sequence_length;
+    const size_t i = n / head_dim / sequence_length;
+
+    const size_t in_index = i * sequence_length * head_dim + j * head_dim + k;
+    const size_t out_index = j * width + offset + i * head_dim + k;
+
+    q[out_index] = q_split[in_index];
+}
+
+extern "C" __global__ void split_heads_bf16(
+    const size_t numel,
+    const __nv_bfloat16 *q,
+    __nv_bfloat16 *q_split,
+    const size_t num_heads,
+    const size_t sequence_length,
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
+) {
+    size_t n = blockIdx.x * blockDim.x + threadIdx.x;
+    if (n >= numel) {
+        return;
+    }
+
+    const size_t k = n % head_dim;
+    const size_t j = (n / head_dim) % sequence_length;
+    const size_t i = n / head_dim / sequence_length;
+
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of q, the heads
+    // being repeated for grouped-query attention.
+    const size_t index = j * width + offset + (i / (num_heads / num_kv_heads)) * head_dim + k;
+    const size_t out_index = i * sequence_length * head_dim + j * head_dim + k;
+    q_split[out_index] = q[index];
+}
+
+extern "C" __global__ void unsplit_heads_bf16(
+    const size_t numel,
+    const __nv_bfloat16 *q_split,
+    __nv_bfloat16 *q,
+    const size_t num_heads,
+    const size_t sequence_length,
+    const size_t head_dim,
+    const size_t num_kv_heads,
+    const size_t offset,
+    const size_t width
+) {
+    size_t n = blockIdx.x * blockDim.x + threadIdx.x;
+    if (n >= numel) {
+        return;
+    }
+
+    const size_t k = n % head_dim;
+    const size_t j = (n / head_dim) % sequence_length;
+    const size_t i = n / head_dim / sequence_length;
+
+    const size_t in_index = i * sequence_length * head_dim + j * head_dim + k;
+    const size_t out_index = j * width + offset + i * head_dim + k;
+
+    q[out_index] = q_split[in_index];
+}
+
+// Adds c2p[h, i, indices[i, j]] + p2c[h, indices[i, j], j] to the scores (num_heads,
+// query_length, key_length) of the disentangled attention of DeBERTa.
+extern "C" __global__ void add_relative_scores(
+    const size_t numel,
+    const float *c2p,
+    const float *p2c,
+    const size_t *indices,
+    float *scores,
+    const size_t query_length,
+    const size_t key_length,
+    const size_t num_positions
+) {
+    size_t n = blockIdx.x * blockDim.x + threadIdx.x;
+    if (n >= numel) {
+        return;
+    }
+
+    const size_t j = n % key_length;
+    const size_t i = (n / key_length) % query_length;
+    const size_t h = n / key_length / query_length;
+
+    const size_t index = indices[i * key_length + j];
+    scores[n] += c2p[(h * query_length + i) * num_positions + index]
+        + p2c[(h * num_positions + index) * key_length + j];
+}
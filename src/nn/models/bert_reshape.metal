@@ -0,0 +1,54 @@
+#include <metal_stdlib>
+using namespace metal;
+
+kernel void split_heads(
+    device const float *q [[buffer(0)]],
+    device float *q_split [[buffer(1)]],
+    constant uint &numel [[buffer(2)]],
+    constant uint &num_heads [[buffer(3)]],
+    constant uint &sequence_length [[buffer(4)]],
+    constant uint &head_dim [[buffer(5)]],
+    constant uint &num_kv_heads [[buffer(6)]],
+    constant uint &offset [[buffer(7)]],
+    constant uint &width [[buffer(8)]],
+    uint n [[thread_position_in_grid]]
+) {
+    if (n >= numel) {
+        return;
+    }
+
+    const uint k = n % head_dim;
+    const uint j = (n / head_dim) % sequence_length;
+    const uint i = n / head_dim / sequence_length;
+
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of q, the heads
+    // being repeated for grouped-query attention.
+    const uint index = j * width + offset + (i / (num_heads / num_kv_heads)) * head_dim + k;
+    const uint out_index = i * sequence_length * head_dim + j * head_dim + k;
+    q_split[out_index] = q[index];
+}
+
+kernel void unsplit_heads(
+    device const float *q_split [[buffer(0)]],
+    device float *q [[buffer(1)]],
+    constant uint &numel [[buffer(2)]],
+    constant uint &num_heads [[buffer(3)]],
+    constant uint &sequence_length [[buffer(4)]],
+    constant uint &head_dim [[buffer(5)]],
+    constant uint &num_kv_heads [[buffer(6)]],
+    constant uint &offset [[buffer(7)]],
+    constant uint &width [[buffer(8)]],
+    uint n [[thread_position_in_grid]]
+) {
+    if (n >= numel) {
+        return;
+    }
+
+    const uint k = n % head_dim;
+    const uint j = (n / head_dim) % sequence_length;
+    const uint i = n / head_dim / sequence_length;
+
+    const uint in_index = i * sequence_length * head_dim + j * head_dim + k;
+    const uint out_index = j * width + offset + i * head_dim + k;
+    q[out_index] = q_split[in_index];
+}
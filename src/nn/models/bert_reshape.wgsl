@@ -0,0 +1,52 @@
+struct Params {
+    numel: u32,
+    num_heads: u32,
+    sequence_length: u32,
+    head_dim: u32,
+    num_kv_heads: u32,
+    offset: u32,
+    width: u32,
+}
+
+@group(0) @binding(0) var<storage, read> src: array<f32>;
+@group(0) @binding(1) var<storage, read_write> dst: array<f32>;
+@group(0) @binding(2) var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn split_heads(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let n = gid.x + gid.y * nwg.x * 64u;
+    if (n >= params.numel) {
+        return;
+    }
+    let head_dim = params.head_dim;
+    let sequence_length = params.sequence_length;
+
+    let k = n % head_dim;
+    let j = (n / head_dim) % sequence_length;
+    let i = n / head_dim / sequence_length;
+
+    // The columns [offset, offset + num_kv_heads * head_dim) of the rows of src, the heads
+    // being repeated for grouped-query attention.
+    let kv_head = i / (params.num_heads / params.num_kv_heads);
+    let index = j * params.width + params.offset + kv_head * head_dim + k;
+    let out_index = i * sequence_length * head_dim + j * head_dim + k;
+    dst[out_index] = src[index];
+}
+
+@compute @workgroup_size(64)
+fn unsplit_heads(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let n = gid.x + gid.y * nwg.x * 64u;
+    if (n >= params.numel) {
+        return;
+    }
+    let head_dim = params.head_dim;
+    let sequence_length = params.sequence_length;
+
+    let k = n % head_dim;
+    let j = (n / head_dim) % sequence_length;
+    let i = n / head_dim / sequence_length;
+
+    let in_index = i * sequence_length * head_dim + j * head_dim + k;
+    let out_index = j * params.width + params.offset + i * head_dim + k;
+    dst[out_index] = src[in_index];
+}
@@ -0,0 +1,606 @@
+use crate::nn::layers::{Dropout, Embedding, LayerNorm, UnbiasedLinear};
+use crate::nn::models::vit::{ViT, ViTLayer, ViTOps};
+use crate::traits::{Activation, Device, Tensor, TensorReduce, TensorUnary};
+use crate::SmeltError;
+
+/// The operations of CLIP: the ones of ViT, and the norms of the embeddings. The cpu,
+/// cuda and backend f32 tensors implement them.
+pub trait ClipOps<T: Tensor>: ViTOps<T> + TensorReduce<T> + TensorUnary<T> {}
+
+impl<T> ClipOps<T> for T where T: Tensor + ViTOps<T> + TensorReduce<T> + TensorUnary<T> {}
+
+/// The text tower of CLIP: the token and position embeddings, the causal layers of ViT
+/// and a layer norm. The sequence is pooled at its end of text token.
+#[derive(Clone)]
+pub struct ClipTextTransformer<T: Tensor> {
+    token_embedding: Embedding<T>,
+    position_embedding: Embedding<T>,
+    layers: Vec<ViTLayer<T>>,
+    final_layer_norm: LayerNorm<T>,
+}
+
+impl<T: Tensor + ClipOps<T>> ClipTextTransformer<T> {
+    /// The tower of the `token_embedding` (vocab_size, hidden_dim) and the
+    /// `position_embedding` (max_position_embeddings, hidden_dim), whose `layers` have a
+    /// causal attention and can't be empty.
+    pub fn new(
+        token_embedding: Embedding<T>,
+        position_embedding: Embedding<T>,
+        layers: Vec<ViTLayer<T>>,
+        final_layer_norm: LayerNorm<T>,
+    ) -> Result<Self, SmeltError> {
+        if layers.is_empty() {
+            return Err(SmeltError::InvalidConfig(
+                "a text tower without layers".to_string(),
+            ));
+        }
+        Ok(Self {
+            token_embedding,
+            position_embedding,
+            layers,
+            final_layer_norm,
+        })
+    }
+
+    /// The width of the hidden states
+    pub fn hidden_dim(&self) -> usize {
+        self.token_embedding.weight().shape()[1]
+    }
+
+    /// Switches the dropouts of every layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
+    /// The hidden states (sequence_length, hidden_dim) of `input_ids`, which start with
+    /// the start of text token and end with the end of text token. Fails with
+    /// [SmeltError::OutOfVocabulary] past the last position, 77 for the checkpoints of
+    /// OpenAI.
+    pub fn forward(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        let shape = vec![input_ids.len(), self.hidden_dim()];
+        let device = self.token_embedding.weight().device();
+        let mut hidden_states = device.zeros(shape.clone())?;
+        let mut positions = device.zeros(shape)?;
+        let position_ids: Vec<usize> = (0..input_ids.len()).collect();
+        self.position_embedding
+            .forward(&position_ids, &mut positions)?;
+        self.token_embedding
+            .forward(input_ids, &mut hidden_states)?;
+        T::add(&positions, &mut hidden_states)?;
+        for layer in &self.layers {
+            layer.forward(&mut hidden_states)?;
+        }
+        self.final_layer_norm.forward(&mut hidden_states)?;
+        Ok(hidden_states)
+    }
+
+    /// The hidden state (1, hidden_dim) of the end of text token of `input_ids`, the
+    /// first largest id like in transformers: it is the last token of the vocabulary.
+    pub fn pooled(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        let eot = input_ids.iter().enumerate().rev().max_by_key(|&(_, id)| id);
+        let Some((index, _)) = eot else {
+            return Err(SmeltError::InvalidConfig("an empty text".to_string()));
+        };
+        let hidden_states = self.forward(input_ids)?;
+        let mut pooled = hidden_states.device().zeros(vec![1, self.hidden_dim()])?;
+        T::select(&[index], &hidden_states, &mut pooled)?;
+        Ok(pooled)
+    }
+}
+
+/// The CLIP dual encoder, like `CLIPModel`: a text tower and a vision tower whose
+/// pooled states are projected to a shared space, where the cosine similarity of a
+/// text and an image is their match. The vision tower is a [ViT] with a
+/// [ViT::with_pre_layernorm] and the CLS token as its pooled state.
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::{Device, Tensor};
+/// use smelte_rs::nn::models::clip::{clip_from, ClipConfig};
+/// use smelte_rs::traits::TensorSoftmax;
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// // openai/clip-vit-base-patch32
+/// let device = Device::cpu();
+/// let model = clip_from(&tensors, &ClipConfig::new(8, 12), &device).unwrap();
+/// // "a photo of a cat" and "a photo of a dog"
+/// let texts = [
+///     vec![49406, 320, 1125, 539, 320, 2368, 49407],
+///     vec![49406, 320, 1125, 539, 320, 1929, 49407],
+/// ];
+/// let image = Tensor::zeros(vec![1, 3, 224, 224], &device).unwrap();
+/// let logits_per_text = model.similarity(&texts, &[image]).unwrap();
+/// // The zero-shot classification of the image among the texts.
+/// let mut probs = logits_per_text.reshape(vec![1, texts.len()]).unwrap();
+/// Tensor::softmax(&mut probs).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ClipModel<T: Tensor> {
+    text_model: ClipTextTransformer<T>,
+    vision_model: ViT<T>,
+    text_projection: UnbiasedLinear<T>,
+    visual_projection: UnbiasedLinear<T>,
+    logit_scale: f32,
+}
+
+impl<T: Tensor + ClipOps<T>> ClipModel<T> {
+    /// The model of the towers and their projections (projection_dim, hidden_dim). The
+    /// similarities are scaled by `exp(logit_scale)`, the `logit_scale` of the
+    /// checkpoint being learned in the log space.
+    pub fn new(
+        text_model: ClipTextTransformer<T>,
+        vision_model: ViT<T>,
+        text_projection: UnbiasedLinear<T>,
+        visual_projection: UnbiasedLinear<T>,
+        logit_scale: f32,
+    ) -> Result<Self, SmeltError> {
+        let text_shape = text_projection.weight().shape();
+        let visual_shape = visual_projection.weight().shape();
+        let hidden_dims = [
+            text_model.hidden_dim(),
+            vision_model.embeddings().hidden_dim(),
+        ];
+        if text_shape.get(1) != Some(&hidden_dims[0]) {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![text_shape[0], hidden_dims[0]],
+                got: text_shape.to_vec(),
+            });
+        }
+        if visual_shape != [text_shape[0], hidden_dims[1]] {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![text_shape[0], hidden_dims[1]],
+                got: visual_shape.to_vec(),
+            });
+        }
+        Ok(Self {
+            text_model,
+            vision_model,
+            text_projection,
+            visual_projection,
+            logit_scale,
+        })
+    }
+
+    /// The text tower
+    pub fn text_model(&self) -> &ClipTextTransformer<T> {
+        &self.text_model
+    }
+
+    /// The vision tower
+    pub fn vision_model(&self) -> &ViT<T> {
+        &self.vision_model
+    }
+
+    /// Switches the dropouts of both towers to train or eval mode, the models being
+    /// built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.text_model.set_training(training);
+        self.vision_model.set_training(training);
+    }
+
+    /// The size of the shared space of the embeddings
+    pub fn projection_dim(&self) -> usize {
+        self.text_projection.weight().shape()[0]
+    }
+
+    /// The projected embedding (1, projection_dim) of the text `input_ids`, normalized
+    /// with [ClipModel::similarity] in mind, for a retrieval by dot products.
+    pub fn text_embeds(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        let pooled = self.text_model.pooled(input_ids)?;
+        let mut embeds = pooled.device().zeros(vec![1, self.projection_dim()])?;
+        self.text_projection.forward(&pooled, &mut embeds)?;
+        normalize(&mut embeds)?;
+        Ok(embeds)
+    }
+
+    /// The projected embedding (1, projection_dim) of the image `pixel_values` (1, 3,
+    /// image_size, image_size), normalized like [ClipModel::text_embeds].
+    pub fn image_embeds(&self, pixel_values: &T) -> Result<T, SmeltError> {
+        let hidden_states = self.vision_model.forward(pixel_values)?;
+        let device = hidden_states.device();
+        let mut pooled = device.zeros(vec![1, hidden_states.shape()[1]])?;
+        T::select(&[0], &hidden_states, &mut pooled)?;
+        let mut embeds = device.zeros(vec![1, self.projection_dim()])?;
+        self.visual_projection.forward(&pooled, &mut embeds)?;
+        normalize(&mut embeds)?;
+        Ok(embeds)
+    }
+
+    /// The scaled cosine similarities (num_texts, num_images) of the `texts`, the ids of
+    /// a text each, and the `images`, the `logits_per_text` of transformers. A softmax
+    /// of the similarities of an image to the texts classifies it among them.
+    pub fn similarity(&self, texts: &[Vec<usize>], images: &[T]) -> Result<T, SmeltError> {
+        let (Some(_), Some(image)) = (texts.first(), images.first()) else {
+            return Err(SmeltError::InvalidConfig(
+                "the similarity of no text or no image".to_string(),
+            ));
+        };
+        let device = image.device();
+        let projection_dim = self.projection_dim();
+        let mut text_embeds = device.zeros(vec![texts.len(), projection_dim])?;
+        for (index, input_ids) in texts.iter().enumerate() {
+            stack(&self.text_embeds(input_ids)?, index, &mut text_embeds)?;
+        }
+        let mut image_embeds = device.zeros(vec![images.len(), projection_dim])?;
+        for (index, pixel_values) in images.iter().enumerate() {
+            stack(&self.image_embeds(pixel_values)?, index, &mut image_embeds)?;
+        }
+        let mut logits = device.zeros(vec![texts.len(), images.len()])?;
+        T::matmul_t(&text_embeds, &image_embeds, &mut logits)?;
+        T::mul_scalar(&mut logits, self.logit_scale.exp())?;
+        Ok(logits)
+    }
+}
+
+/// Divides the rows of `x` (rows, dim) by their euclidean norm.
+fn normalize<T: Tensor + ClipOps<T>>(x: &mut T) -> Result<(), SmeltError> {
+    let shape = x.shape().to_vec();
+    let device = x.device();
+    let mut squares = device.zeros(shape.clone())?;
+    T::copy(x, &mut squares)?;
+    T::mul(x, &mut squares)?;
+    let mut norms = device.zeros(vec![shape[0], 1])?;
+    T::sum(&squares, 1, true, &mut norms)?;
+    T::rsqrt(&mut norms)?;
+    T::broadcast_mul(&norms, x)
+}
+
+/// Adds the row `row` (1, dim) to the row `index` of `out` (rows, dim).
+fn stack<T: Tensor + ClipOps<T>>(row: &T, index: usize, out: &mut T) -> Result<(), SmeltError> {
+    let device = out.device();
+    let mut padded = device.zeros(out.shape().to_vec())?;
+    let after = out.shape()[0] as isize - index as isize - 1;
+    T::pad(row, &[(index as isize, after), (0, 0)], 0.0, &mut padded)?;
+    T::add(&padded, out)
+}
+
+/// The configuration of a CLIP checkpoint, the `text_config` and `vision_config` of its
+/// `config.json`.
+/// ```
+/// use smelte_rs::nn::models::clip::ClipConfig;
+/// use smelte_rs::traits::Activation;
+///
+/// // laion/CLIP-ViT-H-14-laion2B-s32B-b79K
+/// let config = ClipConfig {
+///     activation: Activation::Gelu,
+///     ..ClipConfig::new(16, 16)
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipConfig {
+    /// The number of heads of the text tower, `text_config.num_attention_heads`
+    pub text_num_heads: usize,
+    /// The number of heads of the vision tower, `vision_config.num_attention_heads`
+    pub vision_num_heads: usize,
+    /// The activation of the feed-forward layers, `hidden_act`
+    pub activation: Activation,
+    /// The probability of the dropout of the attention probabilities,
+    /// `attention_dropout`, the only dropout of CLIP
+    pub attention_dropout: f32,
+}
+
+impl ClipConfig {
+    /// The configuration of the checkpoints of OpenAI, with a quick gelu.
+    pub fn new(text_num_heads: usize, vision_num_heads: usize) -> Self {
+        Self {
+            text_num_heads,
+            vision_num_heads,
+            activation: Activation::QuickGelu,
+            attention_dropout: 0.0,
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::clip_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{conv2d_from, load_layer_norm, load_linear, load_tensor, to_f32};
+    use crate::nn::layers::{AttentionConfig, MultiHeadAttention, QkvProjection};
+    use crate::nn::models::vit::ViTEmbeddings;
+    use crate::traits::Conv2dConfig;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of CLIP, the `layer_norm_eps` of its configuration.
+    const LAYER_NORM_EPS: f32 = 1e-5;
+
+    /// Creates a [ClipModel] of the `config` on `device`, from the tensors of a
+    /// `CLIPModel` checkpoint: `text_model.*`, `vision_model.*`, `text_projection`,
+    /// `visual_projection` and `logit_scale`. The patch size and the number of layers
+    /// are the ones of the checkpoint.
+    pub fn clip_from(
+        tensors: &SafeTensors<'_>,
+        config: &ClipConfig,
+        device: &Device,
+    ) -> Result<ClipModel<Tensor>, SmeltError> {
+        let layers = |prefix: &str, num_heads: usize, causal: bool| {
+            let has_layer = |index: usize| {
+                let name = format!("{prefix}.encoder.layers.{index}.self_attn.q_proj.weight");
+                tensors.tensor(&name).is_ok()
+            };
+            let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+            if num_layers == 0 {
+                return Err(SmeltError::MissingTensor(format!(
+                    "{prefix}.encoder.layers.0.self_attn.q_proj.weight"
+                )));
+            }
+            (0..num_layers)
+                .map(|index| {
+                    let prefix = format!("{prefix}.encoder.layers.{index}");
+                    let query =
+                        load_linear(tensors, &format!("{prefix}.self_attn.q_proj"), device)?;
+                    let attention_config = AttentionConfig {
+                        causal,
+                        ..AttentionConfig::new(num_heads, query.shape()[0] / num_heads)
+                    };
+                    let qkv = QkvProjection::Separate {
+                        query,
+                        key: load_linear(tensors, &format!("{prefix}.self_attn.k_proj"), device)?,
+                        value: load_linear(tensors, &format!("{prefix}.self_attn.v_proj"), device)?,
+                    };
+                    let output =
+                        load_linear(tensors, &format!("{prefix}.self_attn.out_proj"), device)?;
+                    let attention = MultiHeadAttention::new(qkv, Some(output), attention_config)?
+                        .with_dropout(Dropout::new(config.attention_dropout)?);
+                    let layer = ViTLayer::new(
+                        load_layer_norm(
+                            tensors,
+                            &format!("{prefix}.layer_norm1"),
+                            LAYER_NORM_EPS,
+                            device,
+                        )?,
+                        attention,
+                        load_layer_norm(
+                            tensors,
+                            &format!("{prefix}.layer_norm2"),
+                            LAYER_NORM_EPS,
+                            device,
+                        )?,
+                        load_linear(tensors, &format!("{prefix}.mlp.fc1"), device)?,
+                        load_linear(tensors, &format!("{prefix}.mlp.fc2"), device)?,
+                    );
+                    Ok(layer.with_activation(config.activation))
+                })
+                .collect::<Result<Vec<_>, SmeltError>>()
+        };
+
+        let prefix = "text_model";
+        let text_model = ClipTextTransformer::new(
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.embeddings.token_embedding.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.embeddings.position_embedding.weight"),
+                device,
+            )?),
+            layers(prefix, config.text_num_heads, true)?,
+            load_layer_norm(
+                tensors,
+                &format!("{prefix}.final_layer_norm"),
+                LAYER_NORM_EPS,
+                device,
+            )?,
+        )?;
+
+        let prefix = "vision_model";
+        let name = format!("{prefix}.embeddings.patch_embedding.weight");
+        let Ok(weight) = tensors.tensor(&name) else {
+            return Err(SmeltError::MissingTensor(name));
+        };
+        let &[hidden_dim, _, patch_height, patch_width] = weight.shape() else {
+            return Err(SmeltError::InvalidRank { expected_rank: 4 });
+        };
+        let conv_config = Conv2dConfig {
+            stride: [patch_height, patch_width],
+            ..Default::default()
+        };
+        let patch_embedding = conv2d_from(
+            tensors,
+            &format!("{prefix}.embeddings.patch_embedding"),
+            conv_config,
+            device,
+        )?;
+        let class_embedding = load_tensor(
+            tensors,
+            &format!("{prefix}.embeddings.class_embedding"),
+            device,
+        )?
+        .reshape(vec![1, hidden_dim])?;
+        let embeddings = ViTEmbeddings::new(
+            patch_embedding,
+            class_embedding,
+            load_tensor(
+                tensors,
+                &format!("{prefix}.embeddings.position_embedding.weight"),
+                device,
+            )?,
+        )?;
+        let vision_model = ViT::new(
+            embeddings,
+            layers(prefix, config.vision_num_heads, false)?,
+            load_layer_norm(
+                tensors,
+                &format!("{prefix}.post_layernorm"),
+                LAYER_NORM_EPS,
+                device,
+            )?,
+        )?
+        // Sic, the name of transformers.
+        .with_pre_layernorm(load_layer_norm(
+            tensors,
+            &format!("{prefix}.pre_layrnorm"),
+            LAYER_NORM_EPS,
+            device,
+        )?);
+
+        let logit_scale = match tensors.tensor("logit_scale") {
+            Ok(view) => to_f32(&view)?.first().copied(),
+            Err(_) => None,
+        }
+        .ok_or_else(|| SmeltError::MissingTensor("logit_scale".to_string()))?;
+        ClipModel::new(
+            text_model,
+            vision_model,
+            UnbiasedLinear::new(load_tensor(tensors, "text_projection.weight", device)?),
+            UnbiasedLinear::new(load_tensor(tensors, "visual_projection.weight", device)?),
+            logit_scale,
+        )
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+    use crate::nn::layers::{AttentionConfig, Conv2d};
+    use crate::nn::models::vit::ViTEmbeddings;
+    use crate::tests::{attention, layer_norm, linear};
+    use crate::traits::Conv2dConfig;
+
+    const HIDDEN_DIM: usize = 4;
+    const VOCAB_SIZE: usize = 6;
+
+    fn layer(seed: u64, causal: bool) -> ViTLayer<Tensor> {
+        let config = AttentionConfig {
+            causal,
+            ..AttentionConfig::new(2, 2)
+        };
+        ViTLayer::new(
+            layer_norm(HIDDEN_DIM, 1e-5),
+            attention(config, seed),
+            layer_norm(HIDDEN_DIM, 1e-5),
+            linear(8, HIDDEN_DIM, seed + 4),
+            linear(HIDDEN_DIM, 8, seed + 5),
+        )
+        .with_activation(Activation::QuickGelu)
+    }
+
+    fn text_model() -> ClipTextTransformer<Tensor> {
+        let tokens = Embedding::new(Tensor::rand_normal(vec![VOCAB_SIZE, HIDDEN_DIM], 0));
+        let positions = Embedding::new(Tensor::rand_normal(vec![5, HIDDEN_DIM], 1));
+        let layers = vec![layer(10, true), layer(20, true)];
+        ClipTextTransformer::new(tokens, positions, layers, layer_norm(HIDDEN_DIM, 1e-5)).unwrap()
+    }
+
+    /// A tower of 2 patches of 2x2 pixels.
+    fn vision_model() -> ViT<Tensor> {
+        let config = Conv2dConfig {
+            stride: [2, 2],
+            ..Default::default()
+        };
+        let weight = Tensor::rand_normal(vec![HIDDEN_DIM, 3, 2, 2], 2);
+        let embeddings = ViTEmbeddings::new(
+            Conv2d::new(weight, None, config),
+            Tensor::rand_normal(vec![1, HIDDEN_DIM], 3),
+            Tensor::rand_normal(vec![3, HIDDEN_DIM], 4),
+        )
+        .unwrap();
+        let layers = vec![layer(30, false)];
+        ViT::new(embeddings, layers, layer_norm(HIDDEN_DIM, 1e-5))
+            .unwrap()
+            .with_pre_layernorm(layer_norm(HIDDEN_DIM, 1e-5))
+    }
+
+    fn model() -> ClipModel<Tensor> {
+        let text_projection = UnbiasedLinear::new(Tensor::rand_normal(vec![3, HIDDEN_DIM], 5));
+        let visual_projection = UnbiasedLinear::new(Tensor::rand_normal(vec![3, HIDDEN_DIM], 6));
+        let logit_scale = 100f32.ln();
+        ClipModel::new(
+            text_model(),
+            vision_model(),
+            text_projection,
+            visual_projection,
+            logit_scale,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_clip_text_pooling() {
+        let text_model = text_model();
+        // The end of text token is the largest id, the causal attention ignores the
+        // padding after it.
+        let pooled = text_model.pooled(&[4, 1, 5]).unwrap();
+        let padded = text_model.pooled(&[4, 1, 5, 0, 0]).unwrap();
+        for (a, b) in pooled.data().iter().zip(padded.data()) {
+            assert!((a - b).abs() < 1e-5, "{a} != {b}");
+        }
+        let hidden_states = text_model.forward(&[4, 1, 5]).unwrap();
+        assert_eq!(pooled.data(), &hidden_states.data()[2 * HIDDEN_DIM..]);
+
+        assert!(text_model.pooled(&[]).is_err());
+        // 5 positions.
+        assert!(text_model.forward(&[0; 6]).is_err());
+    }
+
+    #[test]
+    fn test_clip_similarity() {
+        let model = model();
+        let texts = [vec![4, 1, 5], vec![2, 3, 5], vec![4, 5]];
+        let images = [
+            Tensor::rand_normal(vec![1, 3, 2, 4], 7),
+            Tensor::rand_normal(vec![1, 3, 2, 4], 8),
+        ];
+        let logits = model.similarity(&texts, &images).unwrap();
+        assert_eq!(logits.shape(), [3, 2]);
+
+        // The cosine similarities, scaled by 100.
+        let dot = |a: &Tensor, b: &Tensor| -> f32 {
+            a.data().iter().zip(b.data()).map(|(a, b)| a * b).sum()
+        };
+        for (i, input_ids) in texts.iter().enumerate() {
+            let text_embeds = model.text_embeds(input_ids).unwrap();
+            assert!((dot(&text_embeds, &text_embeds) - 1.0).abs() < 1e-5);
+            for (j, pixel_values) in images.iter().enumerate() {
+                let image_embeds = model.image_embeds(pixel_values).unwrap();
+                let expected = 100.0 * dot(&text_embeds, &image_embeds);
+                let logit = logits.data()[i * 2 + j];
+                assert!((logit - expected).abs() < 1e-3, "{logit} != {expected}");
+            }
+        }
+
+        assert!(model.similarity(&[], &images).is_err());
+        let visual_projection = UnbiasedLinear::new(Tensor::zeros(vec![2, HIDDEN_DIM]));
+        let text_projection = UnbiasedLinear::new(Tensor::zeros(vec![3, HIDDEN_DIM]));
+        let model = ClipModel::new(
+            text_model(),
+            vision_model(),
+            text_projection,
+            visual_projection,
+            0.0,
+        );
+        assert!(model.is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use crate::nn::models::bert::BertClassifier;
+
+/// The DeBERTa-v2 and v3 sequence classifier. Its layers are the ones of BERT with the
+/// disentangled attention of DeBERTa (see
+/// [crate::nn::models::bert::BertAttention::with_relative_embeddings]), its embeddings
+/// have no absolute positions, and its pooler has a gelu instead of a tanh, see
+/// [classifier_from].
+pub type DebertaClassifier<T> = BertClassifier<T>;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::classifier_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_layer_norm, load_linear, load_tensor};
+    use crate::nn::layers::{Dropout, Embedding, Linear, LogBucketPositions};
+    use crate::nn::models::bert::{
+        Bert, BertAttention, BertEmbeddings, BertEncoder, BertLayer, BertPooler, Mlp,
+        RelativeEmbeddings,
+    };
+    use crate::traits::Activation;
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of DeBERTa, the `layer_norm_eps` of its configurations.
+    const LAYER_NORM_EPS: f32 = 1e-7;
+
+    /// The `hidden_dropout_prob` and `attention_probs_dropout_prob` of the
+    /// configurations of DeBERTa, the dropout before the classifier being the former.
+    const HIDDEN_DROPOUT: f32 = 0.1;
+    const ATTENTION_DROPOUT: f32 = 0.1;
+
+    /// Creates a [DebertaClassifier] of `num_heads` heads (the `num_attention_heads` of
+    /// the configuration) and relative `positions` (its `position_buckets` and
+    /// `max_relative_positions`) on `device`, from the tensors of a
+    /// `DebertaV2ForSequenceClassification` checkpoint: `deberta.embeddings.*`,
+    /// `deberta.encoder.layer.N.*`, `deberta.encoder.rel_embeddings`,
+    /// `deberta.encoder.LayerNorm`, `pooler.dense` and `classifier`. The number of
+    /// layers is the one of the checkpoint, the attention has both the `c2p` and `p2c`
+    /// scores of the `pos_att_type` of DeBERTa-v3.
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::Device;
+    /// use smelte_rs::nn::layers::LogBucketPositions;
+    /// use smelte_rs::nn::models::deberta::classifier_from;
+    ///
+    /// # let buffer = vec![];
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// let positions = LogBucketPositions::new(256, 512).unwrap();
+    /// let model = classifier_from(&tensors, 12, positions, &Device::cpu()).unwrap();
+    /// # }
+    /// ```
+    pub fn classifier_from(
+        tensors: &SafeTensors<'_>,
+        num_heads: usize,
+        positions: LogBucketPositions,
+        device: &Device,
+    ) -> Result<DebertaClassifier<Tensor>, SmeltError> {
+        let has_tensor = |name: &str| tensors.tensor(name).is_ok();
+        if has_tensor("deberta.encoder.conv.conv.weight") {
+            return Err(SmeltError::InvalidConfig(
+                "the convolution layer of DeBERTa-v2 is not supported".to_string(),
+            ));
+        }
+
+        // The absolute positions and the token types are disabled in DeBERTa-v3.
+        let prefix = "deberta.embeddings";
+        let embedding = |name: &str| -> Result<Option<Embedding<Tensor>>, SmeltError> {
+            let name = format!("{prefix}.{name}.weight");
+            let weight = has_tensor(&name)
+                .then(|| load_tensor(tensors, &name, device))
+                .transpose()?;
+            Ok(weight.map(Embedding::new))
+        };
+        let input_embeddings = Embedding::new(load_tensor(
+            tensors,
+            &format!("{prefix}.word_embeddings.weight"),
+            device,
+        )?);
+        let layer_norm_embeddings = load_layer_norm(
+            tensors,
+            &format!("{prefix}.LayerNorm"),
+            LAYER_NORM_EPS,
+            device,
+        )?;
+        let type_embeddings = embedding("token_type_embeddings")?;
+        let mut embeddings = match (embedding("position_embeddings")?, type_embeddings) {
+            (Some(position_embeddings), Some(type_embeddings)) => BertEmbeddings::new(
+                input_embeddings,
+                position_embeddings,
+                type_embeddings,
+                layer_norm_embeddings,
+            ),
+            (Some(position_embeddings), None) => BertEmbeddings::without_type_embeddings(
+                input_embeddings,
+                position_embeddings,
+                layer_norm_embeddings,
+            ),
+            (None, type_embeddings) => BertEmbeddings::without_position_embeddings(
+                input_embeddings,
+                type_embeddings,
+                layer_norm_embeddings,
+            ),
+        }
+        .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+        // Only when the `embedding_size` is not the `hidden_size`, without a bias.
+        if has_tensor(&format!("{prefix}.embed_proj.weight")) {
+            let weight = load_tensor(tensors, &format!("{prefix}.embed_proj.weight"), device)?;
+            let bias = Tensor::zeros(vec![weight.shape()[0]], device)?;
+            embeddings = embeddings.with_projection(Linear::new(weight, bias));
+        }
+
+        let mut relative_embeddings =
+            load_tensor(tensors, "deberta.encoder.rel_embeddings.weight", device)?;
+        load_layer_norm(tensors, "deberta.encoder.LayerNorm", LAYER_NORM_EPS, device)?
+            .forward(&mut relative_embeddings)?;
+        let relative = RelativeEmbeddings::new(relative_embeddings, positions)?;
+
+        let has_layer = |index: usize| {
+            has_tensor(&format!(
+                "deberta.encoder.layer.{index}.attention.self.query_proj.weight"
+            ))
+        };
+        let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+        if num_layers == 0 {
+            return Err(SmeltError::MissingTensor(
+                "deberta.encoder.layer.0.attention.self.query_proj.weight".to_string(),
+            ));
+        }
+        let layers = (0..num_layers)
+            .map(|index| {
+                let prefix = format!("deberta.encoder.layer.{index}");
+                let attention = BertAttention::new(
+                    load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.self.query_proj"),
+                        device,
+                    )?,
+                    load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.self.key_proj"),
+                        device,
+                    )?,
+                    load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.self.value_proj"),
+                        device,
+                    )?,
+                    load_linear(tensors, &format!("{prefix}.attention.output.dense"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.attention.output.LayerNorm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                .with_relative_embeddings(relative.clone())
+                .with_dropout(
+                    Dropout::new(ATTENTION_DROPOUT)?,
+                    Dropout::new(HIDDEN_DROPOUT)?,
+                );
+                let mlp = Mlp::new(
+                    load_linear(tensors, &format!("{prefix}.intermediate.dense"), device)?,
+                    load_linear(tensors, &format!("{prefix}.output.dense"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.output.LayerNorm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                .with_activation(Activation::Gelu)
+                .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+                Ok(BertLayer::new(attention, mlp))
+            })
+            .collect::<Result<_, SmeltError>>()?;
+
+        let bert = Bert::new(embeddings, BertEncoder::new(layers));
+        // The `ContextPooler`, on the first token with the `pooler_hidden_act`.
+        let pooler = BertPooler::new(load_linear(tensors, "pooler.dense", device)?)
+            .with_activation(Activation::Gelu);
+        let mut model =
+            DebertaClassifier::new(bert, pooler, load_linear(tensors, "classifier", device)?)
+                .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+        model.set_num_heads(num_heads);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "cpu", feature = "safetensors"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::nn::layers::LogBucketPositions;
+    use crate::tests::{checkpoint, layer_norm_tensors, linear_tensors};
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The tensors of a DeBERTa of `num_layers` layers of width 4 with 2 heads, whose
+    /// embeddings of `embedding_dim` are projected when it is not 4, with absolute
+    /// positions and token types like DeBERTa-v2 when `absolute`.
+    fn deberta(
+        num_layers: usize,
+        embedding_dim: usize,
+        absolute: bool,
+    ) -> Vec<(String, Vec<usize>)> {
+        let prefix = "deberta.embeddings";
+        let mut tensors = vec![(
+            format!("{prefix}.word_embeddings.weight"),
+            vec![10, embedding_dim],
+        )];
+        tensors.extend(layer_norm_tensors(
+            &format!("{prefix}.LayerNorm"),
+            embedding_dim,
+        ));
+        if absolute {
+            tensors.push((
+                format!("{prefix}.position_embeddings.weight"),
+                vec![16, embedding_dim],
+            ));
+            tensors.push((
+                format!("{prefix}.token_type_embeddings.weight"),
+                vec![2, embedding_dim],
+            ));
+        }
+        if embedding_dim != 4 {
+            tensors.push((
+                format!("{prefix}.embed_proj.weight"),
+                vec![4, embedding_dim],
+            ));
+        }
+        tensors.push((
+            "deberta.encoder.rel_embeddings.weight".to_string(),
+            vec![8, 4],
+        ));
+        tensors.extend(layer_norm_tensors("deberta.encoder.LayerNorm", 4));
+        for index in 0..num_layers {
+            let prefix = format!("deberta.encoder.layer.{index}");
+            for name in ["query_proj", "key_proj", "value_proj"] {
+                tensors.extend(linear_tensors(
+                    &format!("{prefix}.attention.self.{name}"),
+                    4,
+                    4,
+                ));
+            }
+            tensors.extend(linear_tensors(
+                &format!("{prefix}.attention.output.dense"),
+                4,
+                4,
+            ));
+            tensors.extend(layer_norm_tensors(
+                &format!("{prefix}.attention.output.LayerNorm"),
+                4,
+            ));
+            tensors.extend(linear_tensors(
+                &format!("{prefix}.intermediate.dense"),
+                8,
+                4,
+            ));
+            tensors.extend(linear_tensors(&format!("{prefix}.output.dense"), 4, 8));
+            tensors.extend(layer_norm_tensors(&format!("{prefix}.output.LayerNorm"), 4));
+        }
+        tensors.extend(linear_tensors("pooler.dense", 4, 4));
+        tensors.extend(linear_tensors("classifier", 3, 4));
+        tensors
+    }
+
+    fn load(tensors: &[(String, Vec<usize>)]) -> Result<DebertaClassifier<Tensor>, SmeltError> {
+        let buffer = checkpoint(tensors);
+        let tensors = SafeTensors::deserialize(&buffer).unwrap();
+        let positions = LogBucketPositions::new(4, 8).unwrap();
+        classifier_from(&tensors, 2, positions, &Device::cpu())
+    }
+
+    #[test]
+    fn test_classifier_from() {
+        // DeBERTa-v3, without absolute positions nor token types.
+        let model = load(&deberta(2, 4, false)).unwrap();
+        let probs = model.run(vec![1, 2, 3], vec![0, 1, 2], vec![0; 3]).unwrap();
+        assert_eq!(probs.shape(), [1, 3]);
+
+        // Every embedding, the smaller ones being projected.
+        let model = load(&deberta(1, 2, true)).unwrap();
+        let mut ctx = model
+            .new_context(vec![1, 2], vec![0, 1], vec![0, 1], 2)
+            .unwrap();
+        model.forward(&mut ctx).unwrap();
+        assert_eq!(ctx.hidden_states().shape(), [2, 4]);
+        assert_eq!(ctx.probs().shape(), [1, 3]);
+    }
+
+    #[test]
+    fn test_classifier_from_missing_tensor() {
+        let missing = |name: &str| {
+            let mut tensors = deberta(2, 4, false);
+            tensors.retain(|(n, _)| n != name);
+            match load(&tensors) {
+                Err(SmeltError::MissingTensor(missing)) => assert_eq!(missing, name),
+                _ => panic!("{name} is required"),
+            }
+        };
+        // The layer norm of the relative embeddings.
+        missing("deberta.encoder.LayerNorm.weight");
+        // The second layer is counted from its query, then loaded whole.
+        missing("deberta.encoder.layer.1.output.dense.bias");
+        missing("deberta.encoder.layer.0.attention.self.query_proj.weight");
+    }
+}
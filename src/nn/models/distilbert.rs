@@ -0,0 +1,258 @@
+use crate::nn::models::bert::BertClassifier;
+
+/// The DistilBERT sequence classifier. Its layers are the ones of BERT, usually 6 of
+/// them, without token type embeddings (the type ids are ignored), and its
+/// `pre_classifier` is the pooler of BERT with a relu instead of a tanh, see
+/// [crate::nn::models::bert::BertPooler::with_activation] and [classifier_from].
+pub type DistilBertClassifier<T> = BertClassifier<T>;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::classifier_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_layer_norm, load_linear, load_tensor};
+    use crate::nn::layers::{Dropout, Embedding};
+    use crate::nn::models::bert::{
+        Bert, BertAttention, BertEmbeddings, BertEncoder, BertLayer, BertPooler, Mlp,
+    };
+    use crate::traits::Activation;
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of DistilBERT, like the `nn.LayerNorm(eps=1e-12)` of transformers.
+    const LAYER_NORM_EPS: f32 = 1e-12;
+
+    /// The `dropout`, `attention_dropout` and `seq_classif_dropout` of the
+    /// configurations of DistilBERT.
+    const DROPOUT: f32 = 0.1;
+    const ATTENTION_DROPOUT: f32 = 0.1;
+    const SEQ_CLASSIF_DROPOUT: f32 = 0.2;
+
+    /// Creates a [DistilBertClassifier] of `num_heads` heads (the `n_heads` of the
+    /// configuration) on `device`, from the tensors of a
+    /// `DistilBertForSequenceClassification` checkpoint: `distilbert.embeddings.*`,
+    /// `distilbert.transformer.layer.N.*`, `pre_classifier` and `classifier`. The number
+    /// of layers is the one of the checkpoint.
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::Device;
+    /// use smelte_rs::nn::ids::Ids;
+    /// use smelte_rs::nn::models::distilbert::classifier_from;
+    ///
+    /// # let buffer = vec![];
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// let model = classifier_from(&tensors, 12, &Device::cpu()).unwrap();
+    /// let input_ids = Ids::new(vec![101, 2023, 102], vec![3]).unwrap();
+    /// let (position_ids, type_ids) = (Ids::positions(vec![3]), Ids::zeros(vec![3]));
+    /// let probs = model.run_ids(&input_ids, &position_ids, &type_ids, None).unwrap();
+    /// # }
+    /// ```
+    pub fn classifier_from(
+        tensors: &SafeTensors<'_>,
+        num_heads: usize,
+        device: &Device,
+    ) -> Result<DistilBertClassifier<Tensor>, SmeltError> {
+        let prefix = "distilbert.embeddings";
+        let embeddings = BertEmbeddings::without_type_embeddings(
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.word_embeddings.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.position_embeddings.weight"),
+                device,
+            )?),
+            load_layer_norm(
+                tensors,
+                &format!("{prefix}.LayerNorm"),
+                LAYER_NORM_EPS,
+                device,
+            )?,
+        )
+        .with_dropout(Dropout::new(DROPOUT)?);
+
+        let has_layer = |index: usize| {
+            let name = format!("distilbert.transformer.layer.{index}.attention.q_lin.weight");
+            tensors.tensor(&name).is_ok()
+        };
+        let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+        if num_layers == 0 {
+            return Err(SmeltError::MissingTensor(
+                "distilbert.transformer.layer.0.attention.q_lin.weight".to_string(),
+            ));
+        }
+        let layers = (0..num_layers)
+            .map(|index| {
+                let prefix = format!("distilbert.transformer.layer.{index}");
+                let attention = BertAttention::new(
+                    load_linear(tensors, &format!("{prefix}.attention.q_lin"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.k_lin"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.v_lin"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.out_lin"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.sa_layer_norm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                // No dropout on the output of the attention, unlike BERT.
+                .with_dropout(Dropout::new(ATTENTION_DROPOUT)?, Dropout::default());
+                let mlp = Mlp::new(
+                    load_linear(tensors, &format!("{prefix}.ffn.lin1"), device)?,
+                    load_linear(tensors, &format!("{prefix}.ffn.lin2"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.output_layer_norm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                .with_activation(Activation::Gelu)
+                .with_dropout(Dropout::new(DROPOUT)?);
+                Ok(BertLayer::new(attention, mlp))
+            })
+            .collect::<Result<_, SmeltError>>()?;
+
+        let bert = Bert::new(embeddings, BertEncoder::new(layers));
+        let pooler = BertPooler::new(load_linear(tensors, "pre_classifier", device)?)
+            .with_activation(Activation::Relu);
+        let mut model =
+            DistilBertClassifier::new(bert, pooler, load_linear(tensors, "classifier", device)?)
+                .with_dropout(Dropout::new(SEQ_CLASSIF_DROPOUT)?);
+        model.set_num_heads(num_heads);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "cpu", feature = "safetensors"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::tests::{checkpoint, layer_norm_tensors, linear_tensors};
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The tensors of a DistilBERT of `num_layers` layers of width 4 and feed forward
+    /// width 8, classifying in 2 classes.
+    fn distilbert(num_layers: usize) -> Vec<(String, Vec<usize>)> {
+        let prefix = "distilbert.embeddings";
+        let mut tensors = vec![
+            (format!("{prefix}.word_embeddings.weight"), vec![10, 4]),
+            (format!("{prefix}.position_embeddings.weight"), vec![16, 4]),
+        ];
+        tensors.extend(layer_norm_tensors(&format!("{prefix}.LayerNorm"), 4));
+        for index in 0..num_layers {
+            let prefix = format!("distilbert.transformer.layer.{index}");
+            for name in ["q_lin", "k_lin", "v_lin", "out_lin"] {
+                tensors.extend(linear_tensors(&format!("{prefix}.attention.{name}"), 4, 4));
+            }
+            tensors.extend(layer_norm_tensors(&format!("{prefix}.sa_layer_norm"), 4));
+            tensors.extend(linear_tensors(&format!("{prefix}.ffn.lin1"), 8, 4));
+            tensors.extend(linear_tensors(&format!("{prefix}.ffn.lin2"), 4, 8));
+            tensors.extend(layer_norm_tensors(
+                &format!("{prefix}.output_layer_norm"),
+                4,
+            ));
+        }
+        tensors.extend(linear_tensors("pre_classifier", 4, 4));
+        tensors.extend(linear_tensors("classifier", 2, 4));
+        tensors
+    }
+
+    fn load(tensors: &[(String, Vec<usize>)]) -> Result<DistilBertClassifier<Tensor>, SmeltError> {
+        let buffer = checkpoint(tensors);
+        let tensors = SafeTensors::deserialize(&buffer).unwrap();
+        classifier_from(&tensors, 2, &Device::cpu())
+    }
+
+    #[test]
+    fn test_classifier_from() {
+        let model = load(&distilbert(2)).unwrap();
+        let mut ctx = model
+            .new_context(vec![1, 2, 3], vec![0, 1, 2], vec![0; 3], 2)
+            .unwrap();
+        model.forward(&mut ctx).unwrap();
+        assert_eq!(ctx.hidden_states().shape(), [3, 4]);
+        assert_eq!(ctx.probs().shape(), [1, 2]);
+        let probs = ctx.probs().cpu_data().unwrap();
+        assert!((probs.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_classifier_from_missing_tensor() {
+        let missing = |name: &str| {
+            let mut tensors = distilbert(2);
+            tensors.retain(|(n, _)| n != name);
+            match load(&tensors) {
+                Err(SmeltError::MissingTensor(missing)) => assert_eq!(missing, name),
+                _ => panic!("{name} is required"),
+            }
+        };
+        missing("distilbert.embeddings.position_embeddings.weight");
+        missing("distilbert.transformer.layer.1.attention.out_lin.bias");
+        missing("distilbert.transformer.layer.1.sa_layer_norm.weight");
+        missing("distilbert.transformer.layer.0.attention.q_lin.weight");
+        missing("pre_classifier.weight");
+    }
+
+    #[test]
+    fn test_split_layers() {
+        let mut model = load(&distilbert(2)).unwrap();
+        let probs = |probs: Vec<Tensor>| -> Vec<Vec<f32>> {
+            probs.iter().map(|p| p.cpu_data().unwrap()).collect()
+        };
+        let batch = vec![
+            (vec![1, 2, 3], vec![0, 1, 2], vec![0; 3]),
+            (vec![4, 5], vec![0, 1], vec![0; 2]),
+            (vec![6, 7, 8], vec![0, 1, 2], vec![0; 3]),
+        ];
+        let expected = probs(model.run_batch(batch.clone(), 1).unwrap());
+
+        // The second layer and the head on another device, the cpu again.
+        model.split_layers(1, &Device::cpu()).unwrap();
+        assert_eq!(probs(model.run_batch(batch.clone(), 1).unwrap()), expected);
+        // Both sides of the split busy at the same time.
+        assert_eq!(probs(model.run_batch(batch, 2).unwrap()), expected);
+
+        assert!(matches!(
+            model.capture(3),
+            Err(SmeltError::Unsupported {
+                backend: "pipeline",
+                ..
+            })
+        ));
+        assert!(matches!(
+            model.split_layers(3, &Device::cpu()),
+            Err(SmeltError::InvalidLength { .. })
+        ));
+    }
+}
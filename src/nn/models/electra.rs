@@ -0,0 +1,268 @@
+use crate::nn::models::bert::BertClassifier;
+
+/// The ELECTRA discriminator sequence classifier. Its layers are the ones of BERT, its
+/// embeddings are projected on the hidden states when they are smaller (electra-small,
+/// see [crate::nn::models::bert::BertEmbeddings::with_projection]), and its head is the
+/// pooler of BERT with a gelu instead of a tanh, see [classifier_from].
+pub type ElectraClassifier<T> = BertClassifier<T>;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::classifier_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_layer_norm, load_linear, load_tensor};
+    use crate::nn::layers::{Dropout, Embedding};
+    use crate::nn::models::bert::{
+        Bert, BertAttention, BertEmbeddings, BertEncoder, BertLayer, BertPooler, Mlp,
+    };
+    use crate::traits::Activation;
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of ELECTRA, the `layer_norm_eps` of its configurations.
+    const LAYER_NORM_EPS: f32 = 1e-12;
+
+    /// The `hidden_dropout_prob` and `attention_probs_dropout_prob` of the
+    /// configurations of ELECTRA, the dropout before the classifier being the former.
+    const HIDDEN_DROPOUT: f32 = 0.1;
+    const ATTENTION_DROPOUT: f32 = 0.1;
+
+    /// Creates an [ElectraClassifier] of `num_heads` heads (the `num_attention_heads` of
+    /// the configuration) on `device`, from the tensors of an
+    /// `ElectraForSequenceClassification` checkpoint: `electra.embeddings.*`,
+    /// `electra.embeddings_project` if any, `electra.encoder.layer.N.*`,
+    /// `classifier.dense` and `classifier.out_proj`. The number of layers is the one of
+    /// the checkpoint.
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::Device;
+    /// use smelte_rs::nn::models::electra::classifier_from;
+    ///
+    /// # let buffer = vec![];
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// // electra-small
+    /// let model = classifier_from(&tensors, 4, &Device::cpu()).unwrap();
+    /// # }
+    /// ```
+    pub fn classifier_from(
+        tensors: &SafeTensors<'_>,
+        num_heads: usize,
+        device: &Device,
+    ) -> Result<ElectraClassifier<Tensor>, SmeltError> {
+        let prefix = "electra.embeddings";
+        let mut embeddings = BertEmbeddings::new(
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.word_embeddings.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.position_embeddings.weight"),
+                device,
+            )?),
+            Embedding::new(load_tensor(
+                tensors,
+                &format!("{prefix}.token_type_embeddings.weight"),
+                device,
+            )?),
+            load_layer_norm(
+                tensors,
+                &format!("{prefix}.LayerNorm"),
+                LAYER_NORM_EPS,
+                device,
+            )?,
+        )
+        .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+        // Only when the `embedding_size` is not the `hidden_size`.
+        if tensors.tensor("electra.embeddings_project.weight").is_ok() {
+            embeddings = embeddings.with_projection(load_linear(
+                tensors,
+                "electra.embeddings_project",
+                device,
+            )?);
+        }
+
+        let has_layer = |index: usize| {
+            let name = format!("electra.encoder.layer.{index}.attention.self.query.weight");
+            tensors.tensor(&name).is_ok()
+        };
+        let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+        if num_layers == 0 {
+            return Err(SmeltError::MissingTensor(
+                "electra.encoder.layer.0.attention.self.query.weight".to_string(),
+            ));
+        }
+        let layers = (0..num_layers)
+            .map(|index| {
+                let prefix = format!("electra.encoder.layer.{index}");
+                let attention = BertAttention::new(
+                    load_linear(tensors, &format!("{prefix}.attention.self.query"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.self.key"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.self.value"), device)?,
+                    load_linear(tensors, &format!("{prefix}.attention.output.dense"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.attention.output.LayerNorm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                .with_dropout(
+                    Dropout::new(ATTENTION_DROPOUT)?,
+                    Dropout::new(HIDDEN_DROPOUT)?,
+                );
+                let mlp = Mlp::new(
+                    load_linear(tensors, &format!("{prefix}.intermediate.dense"), device)?,
+                    load_linear(tensors, &format!("{prefix}.output.dense"), device)?,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.output.LayerNorm"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                )
+                .with_activation(Activation::Gelu)
+                .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+                Ok(BertLayer::new(attention, mlp))
+            })
+            .collect::<Result<_, SmeltError>>()?;
+
+        let bert = Bert::new(embeddings, BertEncoder::new(layers));
+        // The `ElectraClassificationHead`, on the first token like the pooler of BERT.
+        let pooler = BertPooler::new(load_linear(tensors, "classifier.dense", device)?)
+            .with_activation(Activation::Gelu);
+        let classifier = load_linear(tensors, "classifier.out_proj", device)?;
+        let mut model = ElectraClassifier::new(bert, pooler, classifier)
+            .with_dropout(Dropout::new(HIDDEN_DROPOUT)?);
+        model.set_num_heads(num_heads);
+        Ok(model)
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "cpu", feature = "safetensors"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::tests::{checkpoint, layer_norm_tensors, linear_tensors};
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// The tensors of an ELECTRA of 2 layers of width 4 classifying in 3 classes, whose
+    /// embeddings of `embedding_dim` are projected when it is not 4, like electra-small.
+    fn electra(embedding_dim: usize) -> Vec<(String, Vec<usize>)> {
+        let prefix = "electra.embeddings";
+        let mut tensors = vec![
+            (
+                format!("{prefix}.word_embeddings.weight"),
+                vec![10, embedding_dim],
+            ),
+            (
+                format!("{prefix}.position_embeddings.weight"),
+                vec![16, embedding_dim],
+            ),
+            (
+                format!("{prefix}.token_type_embeddings.weight"),
+                vec![2, embedding_dim],
+            ),
+        ];
+        tensors.extend(layer_norm_tensors(
+            &format!("{prefix}.LayerNorm"),
+            embedding_dim,
+        ));
+        if embedding_dim != 4 {
+            tensors.extend(linear_tensors(
+                "electra.embeddings_project",
+                4,
+                embedding_dim,
+            ));
+        }
+        for index in 0..2 {
+            let prefix = format!("electra.encoder.layer.{index}");
+            for name in ["query", "key", "value"] {
+                tensors.extend(linear_tensors(
+                    &format!("{prefix}.attention.self.{name}"),
+                    4,
+                    4,
+                ));
+            }
+            tensors.extend(linear_tensors(
+                &format!("{prefix}.attention.output.dense"),
+                4,
+                4,
+            ));
+            tensors.extend(layer_norm_tensors(
+                &format!("{prefix}.attention.output.LayerNorm"),
+                4,
+            ));
+            tensors.extend(linear_tensors(
+                &format!("{prefix}.intermediate.dense"),
+                8,
+                4,
+            ));
+            tensors.extend(linear_tensors(&format!("{prefix}.output.dense"), 4, 8));
+            tensors.extend(layer_norm_tensors(&format!("{prefix}.output.LayerNorm"), 4));
+        }
+        tensors.extend(linear_tensors("classifier.dense", 4, 4));
+        tensors.extend(linear_tensors("classifier.out_proj", 3, 4));
+        tensors
+    }
+
+    fn load(tensors: &[(String, Vec<usize>)]) -> Result<ElectraClassifier<Tensor>, SmeltError> {
+        let buffer = checkpoint(tensors);
+        let tensors = SafeTensors::deserialize(&buffer).unwrap();
+        classifier_from(&tensors, 2, &Device::cpu())
+    }
+
+    #[test]
+    fn test_classifier_from() {
+        for embedding_dim in [4, 2] {
+            let model = load(&electra(embedding_dim)).unwrap();
+            let mut ctx = model
+                .new_context(vec![1, 2], vec![0, 1], vec![0, 1], 2)
+                .unwrap();
+            model.forward(&mut ctx).unwrap();
+            // The embeddings are projected to the hidden size of the layers.
+            assert_eq!(ctx.hidden_states().shape(), [2, 4]);
+            assert_eq!(ctx.probs().shape(), [1, 3]);
+        }
+
+        let missing = |name: &str| {
+            let mut tensors = electra(2);
+            tensors.retain(|(n, _)| n != name);
+            match load(&tensors) {
+                Err(SmeltError::MissingTensor(missing)) => assert_eq!(missing, name),
+                _ => panic!("{name} is required"),
+            }
+        };
+        missing("electra.embeddings_project.bias");
+        missing("classifier.dense.weight");
+        missing("classifier.out_proj.bias");
+    }
+}
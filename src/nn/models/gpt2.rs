@@ -8,7 +8,19 @@ use crate::gpu::f32 as cuda_f32;
 #[cfg(feature = "cuda")]
 use crate::gpu::f32::Tensor as F32CudaTensor;
 
-use crate::nn::layers::{Embedding, LayerNorm, LinearT, UnbiasedLinear};
+#[cfg(feature = "metal")]
+use crate::gpu::metal::f32::Tensor as F32MetalTensor;
+
+#[cfg(feature = "wgpu")]
+use crate::gpu::wgpu::f32::Tensor as F32WgpuTensor;
+
+#[cfg(feature = "opencl")]
+use crate::gpu::opencl::f32::Tensor as F32OpenClTensor;
+
+#[cfg(feature = "vulkan")]
+use crate::gpu::vulkan::f32::Tensor as F32VulkanTensor;
+
+use crate::nn::layers::{Dropout, Embedding, LayerNorm, LinearT, UnbiasedLinear};
 use crate::traits::{Device, Tensor, TensorOps};
 use crate::SmeltError;
 
@@ -88,6 +100,7 @@ mod cpu {
 
     fn attention(
         qkv_weights: &LinearT<F32Tensor>,
+        _dropout: &Dropout,
         ctx: &mut Gpt2Context<F32Tensor>,
     ) -> Result<(), SmeltError> {
         qkv_weights
@@ -101,9 +114,10 @@ mod cpu {
     impl TensorAttention<F32Tensor> for F32Tensor {
         fn attention(
             qkv: &LinearT<F32Tensor>,
+            dropout: &Dropout,
             ctx: &mut Gpt2Context<F32Tensor>,
         ) -> Result<(), SmeltError> {
-            attention(qkv, ctx)?;
+            attention(qkv, dropout, ctx)?;
             Ok(())
         }
     }
@@ -127,6 +141,7 @@ mod cuda {
 
     fn cuda_attention(
         qkv: &LinearT<F32CudaTensor>,
+        _dropout: &Dropout,
         ctx: &mut Gpt2Context<F32CudaTensor>,
     ) -> Result<(), SmeltError> {
         todo!("cuda gpt2");
@@ -136,9 +151,10 @@ mod cuda {
     impl TensorAttention<F32CudaTensor> for F32CudaTensor {
         fn attention(
             qkv: &LinearT<F32CudaTensor>,
+            dropout: &Dropout,
             ctx: &mut Gpt2Context<F32CudaTensor>,
         ) -> Result<(), SmeltError> {
-            cuda_attention(qkv, ctx)?;
+            cuda_attention(qkv, dropout, ctx)?;
             Ok(())
         }
     }
@@ -152,10 +168,202 @@ mod cuda {
     impl Gpt2Ops<F32CudaTensor> for F32CudaTensor {}
 }
 
+#[cfg(feature = "metal")]
+mod metal {
+    use super::*;
+
+    fn metal_attention(
+        _qkv: &LinearT<F32MetalTensor>,
+        _dropout: &Dropout,
+        _ctx: &mut Gpt2Context<F32MetalTensor>,
+    ) -> Result<(), SmeltError> {
+        // There is no attention kernel for this backend yet.
+        Err(SmeltError::Unsupported {
+            operation: "gpt2 attention",
+            backend: "metal",
+        })
+    }
+
+    impl TensorAttention<F32MetalTensor> for F32MetalTensor {
+        fn attention(
+            qkv: &LinearT<F32MetalTensor>,
+            dropout: &Dropout,
+            ctx: &mut Gpt2Context<F32MetalTensor>,
+        ) -> Result<(), SmeltError> {
+            metal_attention(qkv, dropout, ctx)?;
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<F32MetalTensor> for F32MetalTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl Gpt2Ops<F32MetalTensor> for F32MetalTensor {}
+}
+
+#[cfg(feature = "wgpu")]
+mod wgpu {
+    use super::*;
+
+    fn wgpu_attention(
+        _qkv: &LinearT<F32WgpuTensor>,
+        _dropout: &Dropout,
+        _ctx: &mut Gpt2Context<F32WgpuTensor>,
+    ) -> Result<(), SmeltError> {
+        // There is no attention kernel for this backend yet.
+        Err(SmeltError::Unsupported {
+            operation: "gpt2 attention",
+            backend: "wgpu",
+        })
+    }
+
+    impl TensorAttention<F32WgpuTensor> for F32WgpuTensor {
+        fn attention(
+            qkv: &LinearT<F32WgpuTensor>,
+            dropout: &Dropout,
+            ctx: &mut Gpt2Context<F32WgpuTensor>,
+        ) -> Result<(), SmeltError> {
+            wgpu_attention(qkv, dropout, ctx)?;
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<F32WgpuTensor> for F32WgpuTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl Gpt2Ops<F32WgpuTensor> for F32WgpuTensor {}
+}
+
+#[cfg(feature = "opencl")]
+mod opencl {
+    use super::*;
+
+    fn opencl_attention(
+        _qkv: &LinearT<F32OpenClTensor>,
+        _dropout: &Dropout,
+        _ctx: &mut Gpt2Context<F32OpenClTensor>,
+    ) -> Result<(), SmeltError> {
+        // There is no attention kernel for this backend yet.
+        Err(SmeltError::Unsupported {
+            operation: "gpt2 attention",
+            backend: "opencl",
+        })
+    }
+
+    impl TensorAttention<F32OpenClTensor> for F32OpenClTensor {
+        fn attention(
+            qkv: &LinearT<F32OpenClTensor>,
+            dropout: &Dropout,
+            ctx: &mut Gpt2Context<F32OpenClTensor>,
+        ) -> Result<(), SmeltError> {
+            opencl_attention(qkv, dropout, ctx)?;
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<F32OpenClTensor> for F32OpenClTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl Gpt2Ops<F32OpenClTensor> for F32OpenClTensor {}
+}
+
+#[cfg(feature = "vulkan")]
+mod vulkan {
+    use super::*;
+
+    fn vulkan_attention(
+        _qkv: &LinearT<F32VulkanTensor>,
+        _dropout: &Dropout,
+        _ctx: &mut Gpt2Context<F32VulkanTensor>,
+    ) -> Result<(), SmeltError> {
+        // There is no attention kernel for this backend yet.
+        Err(SmeltError::Unsupported {
+            operation: "gpt2 attention",
+            backend: "vulkan",
+        })
+    }
+
+    impl TensorAttention<F32VulkanTensor> for F32VulkanTensor {
+        fn attention(
+            qkv: &LinearT<F32VulkanTensor>,
+            dropout: &Dropout,
+            ctx: &mut Gpt2Context<F32VulkanTensor>,
+        ) -> Result<(), SmeltError> {
+            vulkan_attention(qkv, dropout, ctx)?;
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<F32VulkanTensor> for F32VulkanTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl Gpt2Ops<F32VulkanTensor> for F32VulkanTensor {}
+}
+
+#[cfg(any(
+    feature = "cpu",
+    feature = "cuda",
+    feature = "metal",
+    feature = "wgpu",
+    feature = "opencl",
+    feature = "vulkan"
+))]
+mod backend {
+    use super::*;
+    use crate::backend::Tensor as BackendTensor;
+
+    fn backend_attention(
+        _qkv: &LinearT<BackendTensor>,
+        _dropout: &Dropout,
+        ctx: &mut Gpt2Context<BackendTensor>,
+    ) -> Result<(), SmeltError> {
+        // None of the concrete backends has a gpt2 attention to forward to yet.
+        Err(SmeltError::Unsupported {
+            operation: "gpt2 attention",
+            backend: ctx.hidden_states.device().name(),
+        })
+    }
+
+    impl TensorAttention<BackendTensor> for BackendTensor {
+        fn attention(
+            qkv: &LinearT<BackendTensor>,
+            dropout: &Dropout,
+            ctx: &mut Gpt2Context<BackendTensor>,
+        ) -> Result<(), SmeltError> {
+            backend_attention(qkv, dropout, ctx)?;
+            Ok(())
+        }
+    }
+
+    impl TensorDebug<BackendTensor> for BackendTensor {
+        fn cpu_data(&self) -> Result<Vec<f32>, SmeltError> {
+            self.cpu_data()
+        }
+    }
+
+    impl Gpt2Ops<BackendTensor> for BackendTensor {}
+}
+
 /// TODO
 pub trait TensorAttention<T: Tensor> {
     /// TODO
-    fn attention(qkv: &LinearT<T>, ctx: &mut Gpt2Context<T>) -> Result<(), SmeltError>;
+    fn attention(
+        qkv: &LinearT<T>,
+        dropout: &Dropout,
+        ctx: &mut Gpt2Context<T>,
+    ) -> Result<(), SmeltError>;
 }
 
 /// TODO
@@ -172,20 +380,44 @@ pub trait Gpt2Ops<T: Tensor>: TensorOps<T> + TensorAttention<T> + TensorDebug<T>
 pub struct Gpt2Attention<T: Tensor> {
     qkv: LinearT<T>,
     output: LinearT<T>,
+    // The dropout of the attention probabilities, then of the output before the residual
+    attention_dropout: Dropout,
+    dropout: Dropout,
 }
 
 impl<T: Tensor + Gpt2Ops<T>> Gpt2Attention<T> {
     /// TODO
     pub fn new(qkv: LinearT<T>, output: LinearT<T>) -> Self {
-        Self { qkv, output }
+        Self {
+            qkv,
+            output,
+            attention_dropout: Dropout::default(),
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the attention probabilities with `attention_dropout` and the output with
+    /// `dropout` in train mode, the `attn_pdrop` and `resid_pdrop` of the
+    /// configurations.
+    pub fn with_dropout(mut self, attention_dropout: Dropout, dropout: Dropout) -> Self {
+        self.attention_dropout = attention_dropout;
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.attention_dropout.set_training(training);
+        self.dropout.set_training(training);
     }
 
     /// TODO
     pub fn forward(&self, ctx: &mut Gpt2Context<T>) -> Result<(), SmeltError> {
-        T::attention(&self.qkv, ctx)?;
+        T::attention(&self.qkv, &self.attention_dropout, ctx)?;
 
         self.output
             .forward(&ctx.hidden_states_attn_output, &mut ctx.hidden_states_copy)?;
+        self.dropout.forward(&mut ctx.hidden_states_copy)?;
         T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
         Ok(())
     }
@@ -196,12 +428,30 @@ impl<T: Tensor + Gpt2Ops<T>> Gpt2Attention<T> {
 pub struct Mlp<T: Tensor> {
     c_fc: LinearT<T>,
     c_proj: LinearT<T>,
+    // The dropout of the output
+    dropout: Dropout,
 }
 
 impl<T: Tensor + Gpt2Ops<T>> Mlp<T> {
     /// TODO
     pub fn new(c_fc: LinearT<T>, c_proj: LinearT<T>) -> Self {
-        Self { c_fc, c_proj }
+        Self {
+            c_fc,
+            c_proj,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the output with `dropout` in train mode, the `resid_pdrop` of the
+    /// configurations.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
     }
 
     /// TODO
@@ -215,6 +465,7 @@ impl<T: Tensor + Gpt2Ops<T>> Mlp<T> {
         debug!("Intermediate (gelu)", ctx.intermediate_states);
         self.c_proj
             .forward(&ctx.intermediate_states, &mut ctx.hidden_states)?;
+        self.dropout.forward(&mut ctx.hidden_states)?;
         debug!("output ln", ctx.hidden_states);
         Ok(())
     }
@@ -245,6 +496,12 @@ impl<T: Tensor + Gpt2Ops<T>> Gpt2Layer<T> {
         }
     }
 
+    /// Switches the dropouts of the attention and the mlp to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.attention.set_training(training);
+        self.mlp.set_training(training);
+    }
+
     /// TODO
     pub fn forward(&self, ctx: &mut Gpt2Context<T>) -> Result<(), SmeltError> {
         T::copy(&ctx.hidden_states, &mut ctx.hidden_states_copy)?;
@@ -271,6 +528,13 @@ impl<T: Tensor + Gpt2Ops<T>> Gpt2Model<T> {
         Self { layers }
     }
 
+    /// Switches the dropouts of every layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
     /// TODO
     pub fn forward(&self, ctx: &mut Gpt2Context<T>) -> Result<(), SmeltError> {
         for layer in &self.layers {
@@ -289,6 +553,8 @@ pub struct Gpt2<T: Tensor + Gpt2Ops<T>> {
     ln_f: LayerNorm<T>,
     lm_head: UnbiasedLinear<T>,
     num_heads: usize,
+    // The dropout of the embeddings
+    dropout: Dropout,
 }
 
 impl<T: Tensor + Gpt2Ops<T>> Gpt2<T> {
@@ -308,9 +574,24 @@ impl<T: Tensor + Gpt2Ops<T>> Gpt2<T> {
             wpe,
             lm_head,
             num_heads,
+            dropout: Dropout::default(),
         }
     }
 
+    /// Drops the sum of the embeddings with `dropout` in train mode, the `embd_pdrop`
+    /// of the configurations.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts of the embeddings and of every layer to train or eval mode,
+    /// the models being built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+        self.h.set_training(training);
+    }
+
     /// TODO
     pub fn set_num_heads(&mut self, num_heads: usize) {
         self.num_heads = num_heads;
@@ -333,6 +614,7 @@ impl<T: Tensor + Gpt2Ops<T>> Gpt2<T> {
             .forward(position_ids, &mut ctx.hidden_states_copy)?;
         debug!("position embeddings", ctx.hidden_states_copy);
         T::add(&ctx.hidden_states_copy, &mut ctx.hidden_states)?;
+        self.dropout.forward(&mut ctx.hidden_states)?;
 
         self.h.forward(ctx)?;
         self.ln_f.forward(&mut ctx.hidden_states)?;
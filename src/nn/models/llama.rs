@@ -0,0 +1,719 @@
+use crate::nn::generation::{generate_causal, CausalLm};
+use crate::nn::layers::{
+    Dropout, Embedding, GatedMlp, KvCache, Linear, MultiHeadAttention, RmsNorm, RopeScaling,
+    RotaryConfig, RotaryEmbedding,
+};
+use crate::traits::{
+    Activation, Device, Tensor, TensorArgmax, TensorHeads, TensorNarrow, TensorOps,
+    TensorPositionMask, TensorQuantize, TensorReduce, TensorReshape, TensorRotary, TensorUnary,
+};
+use crate::SmeltError;
+
+/// The operations of LLaMA, on top of the common ones: the heads of the attention, the
+/// writes and the masks of the [KvCache], the [RotaryEmbedding], the [RmsNorm] and the greedy decoding
+/// of [generate_causal]. The cpu, cuda and backend f32 tensors implement them.
+pub trait LlamaOps<T: Tensor>:
+    TensorOps<T>
+    + TensorHeads<T>
+    + TensorNarrow<T>
+    + TensorReshape<T>
+    + TensorPositionMask<T>
+    + TensorRotary<T>
+    + TensorReduce<T>
+    + TensorUnary<T>
+    + TensorArgmax<T>
+{
+}
+
+impl<T> LlamaOps<T> for T where
+    T: Tensor
+        + TensorOps<T>
+        + TensorHeads<T>
+        + TensorNarrow<T>
+        + TensorReshape<T>
+        + TensorPositionMask<T>
+        + TensorRotary<T>
+        + TensorReduce<T>
+        + TensorUnary<T>
+        + TensorArgmax<T>
+{
+}
+
+/// The configuration of a LLaMA checkpoint, the `config.json` of transformers.
+/// ```
+/// use smelte_rs::nn::models::llama::LlamaConfig;
+///
+/// // Llama-2 70B, 8 heads of keys and values shared by 64 heads of queries.
+/// let config = LlamaConfig {
+///     num_kv_heads: 8,
+///     num_hidden_layers: 80,
+///     rms_norm_eps: 1e-5,
+///     max_position_embeddings: 4096,
+///     ..LlamaConfig::new(64)
+/// };
+/// let config = config.with_rope_scaling("linear", 2.0).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlamaConfig {
+    /// The number of heads of the queries, `num_attention_heads`
+    pub num_heads: usize,
+    /// The number of heads of the keys and values, `num_key_value_heads`
+    pub num_kv_heads: usize,
+    /// The number of decoder layers, `num_hidden_layers`
+    pub num_hidden_layers: usize,
+    /// The epsilon of the [RmsNorm], `rms_norm_eps`
+    pub rms_norm_eps: f32,
+    /// The frequencies of the [RotaryEmbedding], `rope_theta` and `rope_scaling`
+    pub rotary: RotaryConfig,
+    /// The number of positions of the [RotaryEmbedding], `max_position_embeddings`
+    pub max_position_embeddings: usize,
+    /// The activation of the gate of the [GatedMlp], `hidden_act`
+    pub activation: Activation,
+    /// Whether the language modeling head is the embedding, `tie_word_embeddings`
+    pub tie_word_embeddings: bool,
+    /// The tokens ending the generation, `eos_token_id`
+    pub eos_token_ids: Vec<usize>,
+    /// The number of tokens every token attends to, `sliding_window`, like Mistral, see
+    /// [crate::nn::layers::AttentionConfig::sliding_window]
+    pub sliding_window: Option<usize>,
+    /// The probability of the dropout of the attention probabilities,
+    /// `attention_dropout`, the only dropout of LLaMA
+    pub attention_dropout: f32,
+}
+
+impl LlamaConfig {
+    /// The configuration of transformers with `num_heads` heads, without grouped-query
+    /// attention.
+    pub fn new(num_heads: usize) -> Self {
+        Self {
+            num_heads,
+            num_kv_heads: num_heads,
+            num_hidden_layers: 32,
+            rms_norm_eps: 1e-6,
+            rotary: RotaryConfig::default(),
+            max_position_embeddings: 2048,
+            activation: Activation::Silu,
+            tie_word_embeddings: false,
+            eos_token_ids: vec![2],
+            sliding_window: None,
+            attention_dropout: 0.0,
+        }
+    }
+
+    /// The scaling of the positions of the `rope_scaling` of a configuration, of `type`
+    /// "linear". The "dynamic" NTK scaling of transformers, which only rescales the
+    /// frequencies once the sequence is longer than `max_position_embeddings`, is not
+    /// supported: the static [RopeScaling::Ntk] would change the short sequences too.
+    pub fn with_rope_scaling(mut self, kind: &str, factor: f32) -> Result<Self, SmeltError> {
+        self.rotary.scaling = Some(match kind {
+            "linear" => RopeScaling::Linear(factor),
+            kind => {
+                return Err(SmeltError::InvalidConfig(format!(
+                    "unsupported rope scaling {kind}"
+                )))
+            }
+        });
+        Ok(self)
+    }
+}
+
+/// A decoder layer of LLaMA: the causal self-attention of the normed hidden states, then
+/// the [GatedMlp], both pre-normed by a [RmsNorm] and added to their inputs.
+#[derive(Clone)]
+pub struct LlamaLayer<T: Tensor> {
+    input_layernorm: RmsNorm<T>,
+    self_attn: MultiHeadAttention<T>,
+    post_attention_layernorm: RmsNorm<T>,
+    mlp: GatedMlp<T>,
+}
+
+impl<T: Tensor + LlamaOps<T>> LlamaLayer<T> {
+    /// The layer of a causal `self_attn`.
+    pub fn new(
+        input_layernorm: RmsNorm<T>,
+        self_attn: MultiHeadAttention<T>,
+        post_attention_layernorm: RmsNorm<T>,
+        mlp: GatedMlp<T>,
+    ) -> Result<Self, SmeltError> {
+        if !self_attn.config().causal {
+            return Err(SmeltError::InvalidConfig(
+                "the attention of a decoder is causal".to_string(),
+            ));
+        }
+        Ok(Self {
+            input_layernorm,
+            self_attn,
+            post_attention_layernorm,
+            mlp,
+        })
+    }
+
+    /// The attention of the layer
+    pub fn self_attn(&self) -> &MultiHeadAttention<T> {
+        &self.self_attn
+    }
+
+    /// Switches the dropouts of the attention and the mlp to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.self_attn.set_training(training);
+        self.mlp.set_training(training);
+    }
+
+    /// The layer of `hidden_states` (sequence_length, hidden_dim) in place, its keys and
+    /// values being cached in the layer `layer` of `cache`.
+    fn forward(
+        &self,
+        hidden_states: &mut T,
+        rotary: &RotaryEmbedding<T>,
+        cache: &mut KvCache<T>,
+        layer: usize,
+    ) -> Result<(), SmeltError> {
+        let shape = hidden_states.shape().to_vec();
+        let device = hidden_states.device();
+        let mut normed = device.zeros(shape.clone())?;
+        let mut out = device.zeros(shape.clone())?;
+        let mut mlp_ctx = self.mlp.context(shape[0], device)?;
+
+        T::copy(hidden_states, &mut normed)?;
+        self.input_layernorm.forward(&mut normed)?;
+        self.self_attn
+            .forward_rotary(&normed, None, rotary, cache, layer, &mut out)?;
+        T::add(&out, hidden_states)?;
+
+        T::copy(hidden_states, &mut normed)?;
+        self.post_attention_layernorm.forward(&mut normed)?;
+        self.mlp.forward(&normed, &mut mlp_ctx, &mut out)?;
+        T::add(&out, hidden_states)
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> LlamaLayer<T> {
+    /// Quantizes the attention and the mlp, see [Linear::quantize_dynamic].
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.self_attn.quantize_dynamic()?;
+        self.mlp.quantize_dynamic()
+    }
+}
+
+/// The decoder of LLaMA, like `LlamaModel`: the embeddings of the tokens, the
+/// [LlamaLayer]s sharing a [RotaryEmbedding] and the final [RmsNorm].
+#[derive(Clone)]
+pub struct LlamaModel<T: Tensor> {
+    embed_tokens: Embedding<T>,
+    layers: Vec<LlamaLayer<T>>,
+    norm: RmsNorm<T>,
+    rotary: RotaryEmbedding<T>,
+}
+
+impl<T: Tensor + LlamaOps<T>> LlamaModel<T> {
+    /// The decoder of the `embed_tokens` (vocab_size, hidden_dim) and of the `layers`,
+    /// whose heads are rotated by `rotary`.
+    pub fn new(
+        embed_tokens: Embedding<T>,
+        layers: Vec<LlamaLayer<T>>,
+        norm: RmsNorm<T>,
+        rotary: RotaryEmbedding<T>,
+    ) -> Result<Self, SmeltError> {
+        let Some(first) = layers.first() else {
+            return Err(SmeltError::InvalidConfig(
+                "a decoder without layers".to_string(),
+            ));
+        };
+        let head_dim = first.self_attn().config().head_dim;
+        if rotary.head_dim() != head_dim {
+            return Err(SmeltError::InvalidLength {
+                expected: head_dim,
+                got: rotary.head_dim(),
+            });
+        }
+        Ok(Self {
+            embed_tokens,
+            layers,
+            norm,
+            rotary,
+        })
+    }
+
+    /// The embeddings of the tokens
+    pub fn embed_tokens(&self) -> &Embedding<T> {
+        &self.embed_tokens
+    }
+
+    /// The number of layers
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Switches the dropouts of every layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
+    /// The hidden states (sequence_length, hidden_dim) of `input_ids`, which follow the
+    /// tokens of `cache`.
+    pub fn forward(&self, input_ids: &[usize], cache: &mut KvCache<T>) -> Result<T, SmeltError> {
+        let weight = self.embed_tokens.weight();
+        let shape = vec![input_ids.len(), weight.shape()[1]];
+        let mut hidden_states = weight.device().zeros(shape)?;
+        self.embed_tokens.forward(input_ids, &mut hidden_states)?;
+        for (layer, decoder_layer) in self.layers.iter().enumerate() {
+            decoder_layer.forward(&mut hidden_states, &self.rotary, cache, layer)?;
+        }
+        self.norm.forward(&mut hidden_states)?;
+        Ok(hidden_states)
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> LlamaModel<T> {
+    /// Quantizes the layers, see [Linear::quantize_dynamic]. The embeddings and the norms
+    /// stay in full precision.
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.layers
+            .iter_mut()
+            .try_for_each(|layer| layer.quantize_dynamic())
+    }
+}
+
+/// LLaMA with its language modeling head, like `LlamaForCausalLM`, for the text
+/// generation of the Llama-2 checkpoints and of their fine-tunes, see [CausalLm].
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::nn::models::llama::{causal_lm_from, LlamaConfig};
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let mut model = causal_lm_from(&tensors, &LlamaConfig::new(32), &Device::cpu()).unwrap();
+/// // The int8 weights of the cpu.
+/// model.quantize_dynamic().unwrap();
+/// // "<s> The capital of France is"
+/// let output_ids = model.generate(&[1, 450, 7483, 310, 3444, 338], 20).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct LlamaForCausalLM<T: Tensor> {
+    model: LlamaModel<T>,
+    lm_head: Linear<T>,
+    eos_token_ids: Vec<usize>,
+}
+
+impl<T: Tensor + LlamaOps<T>> LlamaForCausalLM<T> {
+    /// The model of the decoder `model` and the `lm_head` (vocab_size, hidden_dim), the
+    /// generation ending with the eos tokens of `config`.
+    pub fn new(
+        model: LlamaModel<T>,
+        lm_head: Linear<T>,
+        config: &LlamaConfig,
+    ) -> Result<Self, SmeltError> {
+        let expected = model.embed_tokens().weight().shape();
+        if lm_head.shape() != expected {
+            return Err(SmeltError::DimensionMismatch {
+                expected: expected.to_vec(),
+                got: lm_head.shape().to_vec(),
+            });
+        }
+        Ok(Self {
+            model,
+            lm_head,
+            eos_token_ids: config.eos_token_ids.clone(),
+        })
+    }
+
+    /// The decoder
+    pub fn model(&self) -> &LlamaModel<T> {
+        &self.model
+    }
+
+    /// Switches the dropouts of the decoder to train or eval mode, the models being
+    /// built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.model.set_training(training);
+    }
+
+    /// The greedy decoding of the tokens following `input_ids`, up to `max_new_tokens`
+    /// tokens or an eos token, see [generate_causal].
+    pub fn generate(
+        &self,
+        input_ids: &[usize],
+        max_new_tokens: usize,
+    ) -> Result<Vec<usize>, SmeltError> {
+        generate_causal(self, input_ids, max_new_tokens, &self.eos_token_ids)
+    }
+}
+
+impl<T: TensorQuantize + TensorOps<T>> LlamaForCausalLM<T> {
+    /// Quantizes the layers and the language modeling head, see
+    /// [Linear::quantize_dynamic]. A tied head leaves the embeddings in full precision.
+    pub fn quantize_dynamic(&mut self) -> Result<(), SmeltError> {
+        self.model.quantize_dynamic()?;
+        self.lm_head.quantize_dynamic()
+    }
+}
+
+impl<T: Tensor + LlamaOps<T>> CausalLm<T> for LlamaForCausalLM<T> {
+    fn new_cache(&self) -> KvCache<T> {
+        KvCache::new(self.model.num_layers())
+    }
+
+    /// Only the last hidden state goes through the language modeling head.
+    fn next_logits(&self, input_ids: &[usize], cache: &mut KvCache<T>) -> Result<T, SmeltError> {
+        let hidden_states = self.model.forward(input_ids, cache)?;
+        let device = hidden_states.device();
+        let mut last = device.zeros(vec![1, hidden_states.shape()[1]])?;
+        T::select(&[input_ids.len() - 1], &hidden_states, &mut last)?;
+        let mut logits = device.zeros(vec![1, self.lm_head.shape()[0]])?;
+        self.lm_head.forward(&last, &mut logits)?;
+        Ok(logits)
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::{causal_lm_from, causal_lm_from_shards};
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::load_tensor;
+    #[cfg(feature = "cpu")]
+    use crate::checkpoint::{int4_linear_from, Int4Format};
+    use crate::nn::layers::{AttentionConfig, QkvProjection};
+    use safetensors::SafeTensors;
+
+    /// The tensors of a LLaMA checkpoint, maybe split in several shards, loaded on a
+    /// device.
+    struct Loader<'a, 'data> {
+        shards: &'a [SafeTensors<'data>],
+        config: &'a LlamaConfig,
+        device: &'a Device,
+    }
+
+    impl<'a, 'data> Loader<'a, 'data> {
+        /// The shard holding the tensor `name`, if any.
+        fn shard(&self, name: &str) -> Option<&'a SafeTensors<'data>> {
+            self.shards.iter().find(|shard| shard.tensor(name).is_ok())
+        }
+
+        fn tensor(&self, name: String) -> Result<Tensor, SmeltError> {
+            match self.shard(&name) {
+                Some(shard) => load_tensor(shard, &name, self.device),
+                None => Err(SmeltError::MissingTensor(name)),
+            }
+        }
+
+        fn has_tensor(&self, name: &str) -> bool {
+            self.shard(name).is_some()
+        }
+
+        /// The projections of LLaMA have no bias, they are given zero biases. The 4-bit
+        /// weights of the GPTQ and AWQ checkpoints are loaded on the cpu, from the shard
+        /// of their `qweight`.
+        fn linear(&self, prefix: &str) -> Result<Linear<Tensor>, SmeltError> {
+            #[cfg(feature = "cpu")]
+            if let Some(shard) = self.shard(&format!("{prefix}.qweight")) {
+                let format = Int4Format::detect(shard, prefix);
+                return int4_linear_from(shard, prefix, format, self.device);
+            }
+            let weight = self.tensor(format!("{prefix}.weight"))?;
+            let bias = if self.has_tensor(&format!("{prefix}.bias")) {
+                self.tensor(format!("{prefix}.bias"))?
+            } else {
+                Tensor::zeros(vec![weight.shape()[0]], self.device)?
+            };
+            Ok(Linear::new(weight, bias))
+        }
+
+        fn norm(&self, prefix: &str) -> Result<RmsNorm<Tensor>, SmeltError> {
+            let weight = self.tensor(format!("{prefix}.weight"))?;
+            RmsNorm::new(weight, self.config.rms_norm_eps)
+        }
+
+        fn layer(&self, index: usize) -> Result<LlamaLayer<Tensor>, SmeltError> {
+            let prefix = format!("model.layers.{index}");
+            let query = self.linear(&format!("{prefix}.self_attn.q_proj"))?;
+            let num_heads = self.config.num_heads;
+            let config = AttentionConfig {
+                num_kv_heads: self.config.num_kv_heads,
+                causal: true,
+                sliding_window: self.config.sliding_window,
+                ..AttentionConfig::new(num_heads, query.shape()[0] / num_heads)
+            };
+            let qkv = QkvProjection::Separate {
+                query,
+                key: self.linear(&format!("{prefix}.self_attn.k_proj"))?,
+                value: self.linear(&format!("{prefix}.self_attn.v_proj"))?,
+            };
+            let output = self.linear(&format!("{prefix}.self_attn.o_proj"))?;
+            let self_attn = MultiHeadAttention::new(qkv, Some(output), config)?
+                .with_dropout(Dropout::new(self.config.attention_dropout)?);
+            let mlp = GatedMlp::new(
+                self.linear(&format!("{prefix}.mlp.gate_proj"))?,
+                self.linear(&format!("{prefix}.mlp.up_proj"))?,
+                self.linear(&format!("{prefix}.mlp.down_proj"))?,
+                self.config.activation,
+            )?;
+            LlamaLayer::new(
+                self.norm(&format!("{prefix}.input_layernorm"))?,
+                self_attn,
+                self.norm(&format!("{prefix}.post_attention_layernorm"))?,
+                mlp,
+            )
+        }
+    }
+
+    /// Creates a [LlamaForCausalLM] of the `config` on `device`, from the tensors of a
+    /// `LlamaForCausalLM` checkpoint: `model.embed_tokens`, the `num_hidden_layers`
+    /// `model.layers.*`, `model.norm` and `lm_head`, unless `tie_word_embeddings`. The
+    /// projections of the GPTQ and AWQ checkpoints keep their 4-bit weights, which need
+    /// the cpu. See [causal_lm_from_shards] for the checkpoints split in several files.
+    pub fn causal_lm_from(
+        tensors: &SafeTensors<'_>,
+        config: &LlamaConfig,
+        device: &Device,
+    ) -> Result<LlamaForCausalLM<Tensor>, SmeltError> {
+        causal_lm_from_shards(std::slice::from_ref(tensors), config, device)
+    }
+
+    /// Same as [causal_lm_from], for a checkpoint split in several `shards`, like the
+    /// files listed by the `model.safetensors.index.json` of the larger models. A tensor
+    /// missing from all the shards is a [SmeltError::MissingTensor].
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::Device;
+    /// use smelte_rs::nn::models::llama::{causal_lm_from_shards, LlamaConfig};
+    ///
+    /// # let (first, second) = (vec![], vec![]);
+    /// let shards = [
+    ///     SafeTensors::deserialize(&first).unwrap(),
+    ///     SafeTensors::deserialize(&second).unwrap(),
+    /// ];
+    /// let config = LlamaConfig::new(32);
+    /// let model = causal_lm_from_shards(&shards, &config, &Device::cpu()).unwrap();
+    /// # }
+    /// ```
+    pub fn causal_lm_from_shards(
+        shards: &[SafeTensors<'_>],
+        config: &LlamaConfig,
+        device: &Device,
+    ) -> Result<LlamaForCausalLM<Tensor>, SmeltError> {
+        let loader = Loader {
+            shards,
+            config,
+            device,
+        };
+        let embed_tokens = Embedding::new(loader.tensor("model.embed_tokens.weight".to_string())?);
+        let layers = (0..config.num_hidden_layers)
+            .map(|index| loader.layer(index))
+            .collect::<Result<Vec<_>, SmeltError>>()?;
+        let head_dim = match layers.first() {
+            Some(layer) => layer.self_attn().config().head_dim,
+            None => {
+                return Err(SmeltError::InvalidConfig(
+                    "a decoder without layers".to_string(),
+                ))
+            }
+        };
+        let rotary = RotaryEmbedding::new(
+            head_dim,
+            config.max_position_embeddings,
+            config.rotary,
+            device,
+        )?;
+        let norm = loader.norm("model.norm")?;
+        let lm_head = if config.tie_word_embeddings {
+            let vocab_size = embed_tokens.weight().shape()[0];
+            Linear::tied(&embed_tokens, Tensor::zeros(vec![vocab_size], device)?)
+        } else {
+            loader.linear("lm_head")?
+        };
+        let model = LlamaModel::new(embed_tokens, layers, norm, rotary)?;
+        LlamaForCausalLM::new(model, lm_head, config)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::{Device, Tensor};
+    use crate::nn::layers::{AttentionConfig, QkvProjection};
+    use crate::tests::{attention, linear, rms_norm};
+
+    const HIDDEN_DIM: usize = 8;
+    const NUM_HEADS: usize = 2;
+    const HEAD_DIM: usize = HIDDEN_DIM / NUM_HEADS;
+    const VOCAB_SIZE: usize = 10;
+
+    fn layer(seed: u64, config: &LlamaConfig) -> LlamaLayer<Tensor> {
+        let config = AttentionConfig {
+            num_kv_heads: config.num_kv_heads,
+            causal: true,
+            sliding_window: config.sliding_window,
+            ..AttentionConfig::new(NUM_HEADS, HEAD_DIM)
+        };
+        let mlp = GatedMlp::new(
+            linear(16, HIDDEN_DIM, seed + 4),
+            linear(16, HIDDEN_DIM, seed + 5),
+            linear(HIDDEN_DIM, 16, seed + 6),
+            Activation::Silu,
+        )
+        .unwrap();
+        let self_attn = attention(config, seed);
+        LlamaLayer::new(rms_norm(HIDDEN_DIM), self_attn, rms_norm(HIDDEN_DIM), mlp).unwrap()
+    }
+
+    fn rotary() -> RotaryEmbedding<Tensor> {
+        RotaryEmbedding::new(HEAD_DIM, 32, RotaryConfig::default(), &Device::new()).unwrap()
+    }
+
+    fn config(num_kv_heads: usize) -> LlamaConfig {
+        LlamaConfig {
+            num_kv_heads,
+            eos_token_ids: vec![],
+            ..LlamaConfig::new(NUM_HEADS)
+        }
+    }
+
+    fn model(config: &LlamaConfig) -> LlamaForCausalLM<Tensor> {
+        let embed_tokens = Embedding::new(Tensor::rand_normal(vec![VOCAB_SIZE, HIDDEN_DIM], 0));
+        let layers = (0..2).map(|i| layer(10 * (i + 1), config)).collect();
+        let model = LlamaModel::new(embed_tokens, layers, rms_norm(HIDDEN_DIM), rotary()).unwrap();
+        LlamaForCausalLM::new(model, linear(VOCAB_SIZE, HIDDEN_DIM, 1), config).unwrap()
+    }
+
+    fn assert_close(a: &Tensor, b: &Tensor, tolerance: f32) {
+        for (a, b) in a.data().iter().zip(b.data()) {
+            assert!((a - b).abs() < tolerance, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn test_llama_cache() {
+        // Without and with grouped-query attention.
+        for num_kv_heads in [2, 1] {
+            let model = model(&config(num_kv_heads));
+            let input_ids = [1, 4, 2, 7];
+            let expected = model
+                .next_logits(&input_ids, &mut model.new_cache())
+                .unwrap();
+            assert_eq!(expected.shape(), [1, VOCAB_SIZE]);
+
+            let mut cache = model.new_cache();
+            model.next_logits(&input_ids[..3], &mut cache).unwrap();
+            let logits = model.next_logits(&input_ids[3..], &mut cache).unwrap();
+            assert_close(&logits, &expected, 1e-4);
+            assert_eq!(cache.len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_llama_generate() {
+        let model = model(&config(1));
+        let output_ids = model.generate(&[3, 5], 4).unwrap();
+        assert_eq!(output_ids.len(), 4);
+
+        // The cached generation follows the one recomputing every token.
+        let mut input_ids = vec![3, 5];
+        for _ in 0..4 {
+            let logits = model
+                .next_logits(&input_ids, &mut model.new_cache())
+                .unwrap();
+            input_ids.push(Tensor::argmax(&logits, 1).unwrap()[0]);
+        }
+        assert_eq!(output_ids, input_ids[2..]);
+    }
+
+    #[test]
+    fn test_llama_sliding_window() {
+        // Mistral, every token attending to the 3 tokens up to it.
+        let config = LlamaConfig {
+            sliding_window: Some(3),
+            ..config(1)
+        };
+        let model = model(&config);
+        let mut cache = model.new_cache();
+        let mut input_ids = vec![3, 5];
+        let mut logits = model.next_logits(&input_ids, &mut cache).unwrap();
+        for _ in 0..4 {
+            let next_id = Tensor::argmax(&logits, 1).unwrap()[0];
+            input_ids.push(next_id);
+            logits = model.next_logits(&[next_id], &mut cache).unwrap();
+            let expected = model
+                .next_logits(&input_ids, &mut model.new_cache())
+                .unwrap();
+            assert_close(&logits, &expected, 1e-4);
+        }
+        // The cache rolls over the window.
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.position(), 6);
+    }
+
+    #[test]
+    fn test_llama_quantize() {
+        let mut model = model(&config(2));
+        let input_ids = [1, 4, 2, 7];
+        let expected = model
+            .next_logits(&input_ids, &mut model.new_cache())
+            .unwrap();
+        model.quantize_dynamic().unwrap();
+        let logits = model
+            .next_logits(&input_ids, &mut model.new_cache())
+            .unwrap();
+        assert_close(&logits, &expected, 0.1);
+    }
+
+    #[test]
+    fn test_llama_errors() {
+        let config = LlamaConfig::new(NUM_HEADS);
+        assert!(config.clone().with_rope_scaling("yarn", 2.0).is_err());
+        assert!(config.clone().with_rope_scaling("dynamic", 2.0).is_err());
+        let config = config.with_rope_scaling("linear", 2.0).unwrap();
+        assert_eq!(config.rotary.scaling, Some(RopeScaling::Linear(2.0)));
+
+        let embed_tokens = || Embedding::new(Tensor::zeros(vec![VOCAB_SIZE, HIDDEN_DIM]));
+        assert!(LlamaModel::new(embed_tokens(), vec![], rms_norm(HIDDEN_DIM), rotary()).is_err());
+        let rotary = RotaryEmbedding::new(2, 32, RotaryConfig::default(), &Device::new()).unwrap();
+        let layers = vec![layer(0, &config)];
+        assert!(LlamaModel::new(embed_tokens(), layers, rms_norm(HIDDEN_DIM), rotary).is_err());
+
+        let qkv = QkvProjection::Fused(linear(3 * HIDDEN_DIM, HIDDEN_DIM, 0));
+        let config = AttentionConfig::new(NUM_HEADS, HEAD_DIM);
+        let attention = MultiHeadAttention::new(qkv, None, config).unwrap();
+        let mlp = GatedMlp::new(
+            linear(16, HIDDEN_DIM, 1),
+            linear(16, HIDDEN_DIM, 2),
+            linear(HIDDEN_DIM, 16, 3),
+            Activation::Silu,
+        )
+        .unwrap();
+        assert!(
+            LlamaLayer::new(rms_norm(HIDDEN_DIM), attention, rms_norm(HIDDEN_DIM), mlp).is_err()
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::nn::models::llama::{LlamaConfig, LlamaForCausalLM};
+
+/// Mistral and its fine-tunes like Zephyr, like `MistralForCausalLM`. Their layers are
+/// the ones of LLaMA, their attention sharing the keys and values by groups of heads
+/// and attending to a sliding window of tokens, so that the cache rolls over the last
+/// tokens of the window. Their checkpoints are named like the ones of LLaMA, they load
+/// with [crate::nn::models::llama::causal_lm_from].
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::nn::models::mistral::{causal_lm_from, mistral_7b};
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let model = causal_lm_from(&tensors, &mistral_7b(), &Device::cpu()).unwrap();
+/// // The tokens of "[INST] What is your favourite condiment? [/INST]"
+/// # let input_ids = vec![1];
+/// let output_ids = model.generate(&input_ids, 64).unwrap();
+/// # }
+/// ```
+pub type MistralForCausalLM<T> = LlamaForCausalLM<T>;
+
+/// The configuration of Mistral-7B v0.1: 32 heads of queries sharing 8 heads of keys
+/// and values, every token attending to the 4096 tokens up to it.
+/// ```
+/// use smelte_rs::nn::models::mistral::mistral_7b;
+///
+/// // Mistral-7B-Instruct v0.2 attends to every token, its rotary base is larger.
+/// let mut config = mistral_7b();
+/// config.sliding_window = None;
+/// config.rotary.theta = 1e6;
+/// ```
+pub fn mistral_7b() -> LlamaConfig {
+    LlamaConfig {
+        num_kv_heads: 8,
+        rms_norm_eps: 1e-5,
+        max_position_embeddings: 32768,
+        sliding_window: Some(4096),
+        ..LlamaConfig::new(32)
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use crate::nn::models::llama::{causal_lm_from, causal_lm_from_shards};
+
+#[cfg(test)]
+#[cfg(all(feature = "cpu", feature = "safetensors"))]
+mod tests {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::nn::generation::CausalLm;
+    use crate::tests::checkpoint;
+    use crate::SmeltError;
+    use safetensors::SafeTensors;
+
+    /// A Mistral of 2 layers of width 8, 2 heads of queries sharing 1 head of keys and
+    /// values, attending to the 2 tokens up to every token unless `sliding_window` says
+    /// otherwise.
+    fn config(sliding_window: Option<usize>) -> LlamaConfig {
+        LlamaConfig {
+            num_heads: 2,
+            num_kv_heads: 1,
+            num_hidden_layers: 2,
+            max_position_embeddings: 32,
+            eos_token_ids: vec![],
+            sliding_window,
+            ..mistral_7b()
+        }
+    }
+
+    /// The tensors of the Mistral of [config], over a vocabulary of 10 tokens.
+    fn mistral() -> Vec<(String, Vec<usize>)> {
+        let mut tensors = vec![("model.embed_tokens.weight".to_string(), vec![10, 8])];
+        for index in 0..2 {
+            let prefix = format!("model.layers.{index}");
+            for (name, shape) in [
+                ("self_attn.q_proj", [8, 8]),
+                ("self_attn.k_proj", [4, 8]),
+                ("self_attn.v_proj", [4, 8]),
+                ("self_attn.o_proj", [8, 8]),
+                ("mlp.gate_proj", [16, 8]),
+                ("mlp.up_proj", [16, 8]),
+                ("mlp.down_proj", [8, 16]),
+            ] {
+                tensors.push((format!("{prefix}.{name}.weight"), shape.to_vec()));
+            }
+            for name in ["input_layernorm", "post_attention_layernorm"] {
+                tensors.push((format!("{prefix}.{name}.weight"), vec![8]));
+            }
+        }
+        tensors.push(("model.norm.weight".to_string(), vec![8]));
+        tensors.push(("lm_head.weight".to_string(), vec![10, 8]));
+        tensors
+    }
+
+    fn load(
+        tensors: &[(String, Vec<usize>)],
+        config: &LlamaConfig,
+    ) -> Result<MistralForCausalLM<Tensor>, SmeltError> {
+        let buffer = checkpoint(tensors);
+        let tensors = SafeTensors::deserialize(&buffer).unwrap();
+        causal_lm_from(&tensors, config, &Device::cpu())
+    }
+
+    fn logits(model: &MistralForCausalLM<Tensor>, input_ids: &[usize]) -> Vec<f32> {
+        let logits = model
+            .next_logits(input_ids, &mut model.new_cache())
+            .unwrap();
+        assert_eq!(logits.shape(), [1, 10]);
+        logits.cpu_data().unwrap()
+    }
+
+    #[test]
+    fn test_mistral_7b() {
+        let config = mistral_7b();
+        assert_eq!(config.num_heads, 32);
+        assert_eq!(config.num_kv_heads, 8);
+        assert_eq!(config.sliding_window, Some(4096));
+        assert_eq!(config.max_position_embeddings, 32768);
+    }
+
+    #[test]
+    fn test_causal_lm_from() {
+        let model = load(&mistral(), &config(Some(2))).unwrap();
+        let full = load(&mistral(), &config(None)).unwrap();
+        // Within the window, every token attends to all the tokens before it.
+        assert_eq!(logits(&model, &[3, 5]), logits(&full, &[3, 5]));
+        // Past it, the first tokens fall out of the window.
+        let input_ids = [3, 5, 1, 7];
+        assert_ne!(logits(&model, &input_ids), logits(&full, &input_ids));
+
+        // The cache rolls over the window.
+        let mut cache = model.new_cache();
+        model.next_logits(&input_ids, &mut cache).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.position(), 4);
+
+        let mut tensors = mistral();
+        tensors.retain(|(name, _)| name != "model.layers.1.self_attn.k_proj.weight");
+        assert!(matches!(
+            load(&tensors, &config(Some(2))),
+            Err(SmeltError::MissingTensor(_))
+        ));
+    }
+}
@@ -1,5 +1,35 @@
 /// The original bert implementation.
 pub mod bert;
 
+/// ALBERT, on the shared layers of bert.
+pub mod albert;
+
+/// BART and mBART, encoder-decoders with learned positions.
+pub mod bart;
+
+/// CLIP, the dual encoder of texts and images, on the layers of vit.
+pub mod clip;
+
+/// DeBERTa-v2 and v3, on the layers of bert with a disentangled attention.
+pub mod deberta;
+
+/// DistilBERT, on the layers of bert.
+pub mod distilbert;
+
+/// ELECTRA, on the layers of bert.
+pub mod electra;
+
 /// The original gpt2 implementation.
 pub mod gpt2;
+
+/// LLaMA and Llama-2, decoders with rotary embeddings and grouped-query attention.
+pub mod llama;
+
+/// Mistral, on the layers of llama with a sliding window attention.
+pub mod mistral;
+
+/// T5, an encoder-decoder with relative position biases.
+pub mod t5;
+
+/// ViT, the vision transformer classifying the patches of an image.
+pub mod vit;
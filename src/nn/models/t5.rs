@@ -0,0 +1,903 @@
+use crate::nn::generation::{generate, GenerationConfig, Seq2Seq};
+use crate::nn::layers::{
+    AttentionConfig, Dropout, Embedding, GatedMlp, KvCache, Linear, MultiHeadAttention, RmsNorm,
+    UnbiasedLinear,
+};
+use crate::traits::{
+    Activation, Device, Tensor, TensorArgmax, TensorHeads, TensorNarrow, TensorOps,
+    TensorPositionMask, TensorReduce, TensorReshape, TensorUnary,
+};
+use crate::SmeltError;
+
+/// The operations of T5, on top of the common ones: the heads of the attention, the
+/// writes and the masks of the [KvCache], the [RmsNorm] and the greedy decoding of [generate]. The cpu, cuda and
+/// backend f32 tensors implement them.
+pub trait T5Ops<T: Tensor>:
+    TensorOps<T>
+    + TensorHeads<T>
+    + TensorNarrow<T>
+    + TensorReshape<T>
+    + TensorPositionMask<T>
+    + TensorReduce<T>
+    + TensorUnary<T>
+    + TensorArgmax<T>
+{
+}
+
+impl<T> T5Ops<T> for T where
+    T: Tensor
+        + TensorOps<T>
+        + TensorHeads<T>
+        + TensorNarrow<T>
+        + TensorReshape<T>
+        + TensorPositionMask<T>
+        + TensorReduce<T>
+        + TensorUnary<T>
+        + TensorArgmax<T>
+{
+}
+
+/// The configuration of a T5 checkpoint, the `config.json` of transformers.
+/// ```
+/// use smelte_rs::nn::models::t5::T5Config;
+/// use smelte_rs::traits::Activation;
+///
+/// // Flan-T5 base
+/// let config = T5Config::new(12).with_feed_forward_proj("gated-gelu").unwrap();
+/// assert!(config.gated);
+/// assert_eq!(config.activation, Activation::GeluNew);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct T5Config {
+    /// The number of heads of every attention, `num_heads`
+    pub num_heads: usize,
+    /// The activation of the feed-forward layers
+    pub activation: Activation,
+    /// Whether the feed-forward layers are gated (`wi_0` and `wi_1`), like T5 v1.1 and
+    /// Flan-T5, or dense (`wi`)
+    pub gated: bool,
+    /// The distance of the last bucket of the relative position bias,
+    /// `relative_attention_max_distance`
+    pub relative_attention_max_distance: usize,
+    /// The epsilon of the [RmsNorm], `layer_norm_epsilon`
+    pub layer_norm_epsilon: f32,
+    /// The probability of the dropouts of the embeddings, the attention probabilities,
+    /// the feed-forward layers and the output of every layer, `dropout_rate`
+    pub dropout_rate: f32,
+    /// Whether the language modeling head is the shared embedding, its inputs being
+    /// scaled by `1 / sqrt(d_model)`, like T5 v1.0, `tie_word_embeddings`
+    pub tie_word_embeddings: bool,
+    /// The first token of the decoder, the pad token of T5
+    pub decoder_start_token_id: usize,
+    /// The token ending the generation
+    pub eos_token_id: usize,
+}
+
+impl T5Config {
+    /// The configuration of the original T5 with `num_heads` heads: dense relu
+    /// feed-forward layers and a tied head.
+    pub fn new(num_heads: usize) -> Self {
+        Self {
+            num_heads,
+            activation: Activation::Relu,
+            gated: false,
+            relative_attention_max_distance: 128,
+            layer_norm_epsilon: 1e-6,
+            dropout_rate: 0.1,
+            tie_word_embeddings: true,
+            decoder_start_token_id: 0,
+            eos_token_id: 1,
+        }
+    }
+
+    /// The feed-forward layers of the `feed_forward_proj` of a configuration, "relu" or
+    /// "gated-gelu" for instance. The gelu of T5 is the tanh approximation.
+    pub fn with_feed_forward_proj(mut self, feed_forward_proj: &str) -> Result<Self, SmeltError> {
+        let (gated, activation) = match feed_forward_proj.strip_prefix("gated-") {
+            Some(activation) => (true, activation),
+            None => (false, feed_forward_proj),
+        };
+        self.gated = gated;
+        self.activation = match activation {
+            "gelu" => Activation::GeluNew,
+            activation => activation.parse()?,
+        };
+        Ok(self)
+    }
+}
+
+/// The feed-forward layer of a [T5Block]
+#[derive(Clone)]
+pub enum T5FeedForward<T: Tensor> {
+    /// `wo(activation(wi(x)))`, like T5 v1.0
+    Dense {
+        /// The projection (d_ff, d_model)
+        wi: Linear<T>,
+        /// The projection (d_model, d_ff)
+        wo: Linear<T>,
+        /// The activation, relu for T5
+        activation: Activation,
+        /// The dropout after the activation
+        dropout: Dropout,
+    },
+    /// The gated layer of T5 v1.1 and Flan-T5, see [GatedMlp]
+    Gated(GatedMlp<T>),
+}
+
+impl<T: Tensor + TensorOps<T>> T5FeedForward<T> {
+    /// The layer of `x` (sequence_length, d_model) into `out` (sequence_length,
+    /// d_model).
+    pub fn forward(&self, x: &T, out: &mut T) -> Result<(), SmeltError> {
+        let sequence_length = x.shape()[0];
+        match self {
+            Self::Dense {
+                wi,
+                wo,
+                activation,
+                dropout,
+            } => {
+                let mut hidden = x.device().zeros(vec![sequence_length, wi.shape()[0]])?;
+                wi.forward(x, &mut hidden)?;
+                T::activation(&mut hidden, *activation)?;
+                dropout.forward(&mut hidden)?;
+                wo.forward(&hidden, out)
+            }
+            Self::Gated(mlp) => {
+                let mut ctx = mlp.context(sequence_length, x.device())?;
+                mlp.forward(x, &mut ctx, out)
+            }
+        }
+    }
+
+    /// Switches the dropout of the layer to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        match self {
+            Self::Dense { dropout, .. } => dropout.set_training(training),
+            Self::Gated(mlp) => mlp.set_training(training),
+        }
+    }
+}
+
+/// A block of the encoder or the decoder of T5: each of the self-attention, the
+/// attention to the encoder states (decoders only) and the feed-forward layer is
+/// applied to the [RmsNorm] of its input, and added to it.
+#[derive(Clone)]
+pub struct T5Block<T: Tensor> {
+    self_attention_norm: RmsNorm<T>,
+    self_attention: MultiHeadAttention<T>,
+    cross_attention: Option<(RmsNorm<T>, MultiHeadAttention<T>)>,
+    feed_forward_norm: RmsNorm<T>,
+    feed_forward: T5FeedForward<T>,
+    // The dropout of the output of every layer, before the residual
+    dropout: Dropout,
+}
+
+impl<T: Tensor + T5Ops<T>> T5Block<T> {
+    /// The block of an encoder. The attentions of T5 don't scale their scores, the
+    /// self-attentions have a [crate::nn::layers::RelativePositionBias].
+    pub fn new(
+        self_attention_norm: RmsNorm<T>,
+        self_attention: MultiHeadAttention<T>,
+        feed_forward_norm: RmsNorm<T>,
+        feed_forward: T5FeedForward<T>,
+    ) -> Self {
+        Self {
+            self_attention_norm,
+            self_attention,
+            cross_attention: None,
+            feed_forward_norm,
+            feed_forward,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the output of every layer with `dropout` in train mode, before it is added
+    /// to the input.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts of the block, its attentions and its feed-forward layer to
+    /// train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.self_attention.set_training(training);
+        if let Some((_, attention)) = &mut self.cross_attention {
+            attention.set_training(training);
+        }
+        self.feed_forward.set_training(training);
+        self.dropout.set_training(training);
+    }
+
+    /// The block of a decoder, attending to the encoder states with `attention` after
+    /// its causal self-attention.
+    pub fn with_cross_attention(
+        mut self,
+        norm: RmsNorm<T>,
+        attention: MultiHeadAttention<T>,
+    ) -> Self {
+        self.cross_attention = Some((norm, attention));
+        self
+    }
+
+    /// Whether the block attends to the encoder states
+    pub fn is_decoder(&self) -> bool {
+        self.cross_attention.is_some()
+    }
+
+    /// The block of `hidden_states` (sequence_length, d_model) in place. A decoder block
+    /// attends to the `encoder_states` (encoder_length, d_model), and caches its keys and
+    /// values in the layer `layer` of `cache`.
+    fn forward(
+        &self,
+        hidden_states: &mut T,
+        encoder_states: Option<&T>,
+        mut cache: Option<(&mut KvCache<T>, usize)>,
+    ) -> Result<(), SmeltError> {
+        let shape = hidden_states.shape().to_vec();
+        let sequence_length = shape[0];
+        let mut normed = hidden_states.device().zeros(shape.clone())?;
+        let mut out = hidden_states.device().zeros(shape)?;
+
+        T::copy(hidden_states, &mut normed)?;
+        self.self_attention_norm.forward(&mut normed)?;
+        match &mut cache {
+            Some((cache, layer)) => {
+                self.self_attention
+                    .forward_cached(&normed, None, cache, *layer, &mut out)?;
+            }
+            None => {
+                let mut ctx = self
+                    .self_attention
+                    .context(sequence_length, normed.device())?;
+                self.self_attention
+                    .forward(&normed, None, &mut ctx, &mut out)?;
+            }
+        }
+        self.dropout.forward(&mut out)?;
+        T::add(&out, hidden_states)?;
+
+        if let Some((norm, attention)) = &self.cross_attention {
+            let Some(encoder_states) = encoder_states else {
+                return Err(SmeltError::InvalidConfig(
+                    "a decoder block attends to the encoder states".to_string(),
+                ));
+            };
+            T::copy(hidden_states, &mut normed)?;
+            norm.forward(&mut normed)?;
+            match &mut cache {
+                Some((cache, layer)) => attention.forward_cross_cached(
+                    &normed,
+                    encoder_states,
+                    None,
+                    cache,
+                    *layer,
+                    &mut out,
+                )?,
+                None => {
+                    let encoder_length = encoder_states.shape()[0];
+                    let mut ctx = attention.cross_context(
+                        sequence_length,
+                        encoder_length,
+                        normed.device(),
+                    )?;
+                    attention.forward_cross(&normed, encoder_states, None, &mut ctx, &mut out)?;
+                }
+            }
+            self.dropout.forward(&mut out)?;
+            T::add(&out, hidden_states)?;
+        }
+
+        T::copy(hidden_states, &mut normed)?;
+        self.feed_forward_norm.forward(&mut normed)?;
+        self.feed_forward.forward(&normed, &mut out)?;
+        self.dropout.forward(&mut out)?;
+        T::add(&out, hidden_states)
+    }
+}
+
+/// The blocks of the encoder or the decoder of T5, followed by their final [RmsNorm].
+#[derive(Clone)]
+pub struct T5Stack<T: Tensor> {
+    blocks: Vec<T5Block<T>>,
+    final_layer_norm: RmsNorm<T>,
+    // The dropout of the embeddings and of the final states
+    dropout: Dropout,
+}
+
+impl<T: Tensor + T5Ops<T>> T5Stack<T> {
+    /// The stack of `blocks`, all of them encoder blocks or decoder blocks.
+    pub fn new(blocks: Vec<T5Block<T>>, final_layer_norm: RmsNorm<T>) -> Result<Self, SmeltError> {
+        let Some(first) = blocks.first() else {
+            return Err(SmeltError::InvalidConfig(
+                "a stack without blocks".to_string(),
+            ));
+        };
+        if blocks
+            .iter()
+            .any(|block| block.is_decoder() != first.is_decoder())
+        {
+            return Err(SmeltError::InvalidConfig(
+                "a stack mixing encoder and decoder blocks".to_string(),
+            ));
+        }
+        Ok(Self {
+            blocks,
+            final_layer_norm,
+            dropout: Dropout::default(),
+        })
+    }
+
+    /// Drops the embeddings before the first block and the states after the final norm
+    /// with `dropout` in train mode.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts of the stack and of every block to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        for block in &mut self.blocks {
+            block.set_training(training);
+        }
+        self.dropout.set_training(training);
+    }
+
+    /// The number of blocks
+    pub fn num_layers(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether the blocks attend to the encoder states
+    pub fn is_decoder(&self) -> bool {
+        self.blocks[0].is_decoder()
+    }
+
+    /// The stack of `hidden_states` (sequence_length, d_model) in place. A decoder
+    /// attends to the `encoder_states` and extends `cache` with the new tokens.
+    pub fn forward(
+        &self,
+        hidden_states: &mut T,
+        encoder_states: Option<&T>,
+        mut cache: Option<&mut KvCache<T>>,
+    ) -> Result<(), SmeltError> {
+        self.dropout.forward(hidden_states)?;
+        for (layer, block) in self.blocks.iter().enumerate() {
+            let cache = cache.as_deref_mut().map(|cache| (cache, layer));
+            block.forward(hidden_states, encoder_states, cache)?;
+        }
+        self.final_layer_norm.forward(hidden_states)?;
+        self.dropout.forward(hidden_states)
+    }
+}
+
+/// The encoder of T5 on its own, like `T5EncoderModel`: the hidden states of the
+/// tokens, pooled into sentence embeddings by [crate::nn::layers::Pooling] for the
+/// sentence-t5 checkpoints.
+#[derive(Clone)]
+pub struct T5Encoder<T: Tensor> {
+    embeddings: Embedding<T>,
+    stack: T5Stack<T>,
+}
+
+impl<T: Tensor + T5Ops<T>> T5Encoder<T> {
+    /// The encoder of the `embeddings` (vocab_size, d_model), shared with the decoder.
+    pub fn new(embeddings: Embedding<T>, stack: T5Stack<T>) -> Result<Self, SmeltError> {
+        if stack.is_decoder() {
+            return Err(SmeltError::InvalidConfig(
+                "an encoder of decoder blocks".to_string(),
+            ));
+        }
+        Ok(Self { embeddings, stack })
+    }
+
+    /// The embeddings of the tokens
+    pub fn embeddings(&self) -> &Embedding<T> {
+        &self.embeddings
+    }
+
+    /// Switches the dropouts of the encoder to train or eval mode, the models being
+    /// built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.stack.set_training(training);
+    }
+
+    /// The hidden states (sequence_length, d_model) of `input_ids`.
+    pub fn forward(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        let weight = self.embeddings.weight();
+        let shape = vec![input_ids.len(), weight.shape()[1]];
+        let mut hidden_states = weight.device().zeros(shape)?;
+        self.embeddings.forward(input_ids, &mut hidden_states)?;
+        self.stack.forward(&mut hidden_states, None, None)?;
+        Ok(hidden_states)
+    }
+}
+
+/// The T5 encoder-decoder with its language modeling head, like
+/// `T5ForConditionalGeneration`, for summarization or translation, see [Seq2Seq].
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::Device;
+/// use smelte_rs::nn::models::t5::{seq2seq_from, T5Config};
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// let model = seq2seq_from(&tensors, &T5Config::new(8), &Device::cpu()).unwrap();
+/// // "translate English to German: That is good."
+/// let input_ids = [13959, 1566, 12, 2968, 10, 466, 19, 207, 5, 1];
+/// let output_ids = model.generate(&input_ids, 20).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct T5ForConditionalGeneration<T: Tensor> {
+    encoder: T5Encoder<T>,
+    decoder: T5Stack<T>,
+    lm_head: UnbiasedLinear<T>,
+    lm_head_scale: Option<f32>,
+    decoder_start_token_id: usize,
+    eos_token_id: usize,
+}
+
+impl<T: Tensor + T5Ops<T>> T5ForConditionalGeneration<T> {
+    /// The model of the `encoder`, the `decoder` using its embeddings, and the `lm_head`
+    /// (vocab_size, d_model). The special tokens are the ones of `config`, the tied head
+    /// of `tie_word_embeddings` scales its inputs.
+    pub fn new(
+        encoder: T5Encoder<T>,
+        decoder: T5Stack<T>,
+        lm_head: UnbiasedLinear<T>,
+        config: &T5Config,
+    ) -> Result<Self, SmeltError> {
+        if !decoder.is_decoder() {
+            return Err(SmeltError::InvalidConfig(
+                "a decoder of encoder blocks".to_string(),
+            ));
+        }
+        let d_model = encoder.embeddings().weight().shape()[1];
+        let lm_head_scale = config
+            .tie_word_embeddings
+            .then(|| 1.0 / (d_model as f32).sqrt());
+        Ok(Self {
+            encoder,
+            decoder,
+            lm_head,
+            lm_head_scale,
+            decoder_start_token_id: config.decoder_start_token_id,
+            eos_token_id: config.eos_token_id,
+        })
+    }
+
+    /// The encoder
+    pub fn encoder(&self) -> &T5Encoder<T> {
+        &self.encoder
+    }
+
+    /// Switches the dropouts of the encoder and the decoder to train or eval mode, the
+    /// models being built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.encoder.set_training(training);
+        self.decoder.set_training(training);
+    }
+
+    /// The greedy decoding of `input_ids`, up to `max_new_tokens` tokens or the eos
+    /// token, see [generate].
+    pub fn generate(
+        &self,
+        input_ids: &[usize],
+        max_new_tokens: usize,
+    ) -> Result<Vec<usize>, SmeltError> {
+        let config = GenerationConfig::new(
+            max_new_tokens,
+            self.decoder_start_token_id,
+            self.eos_token_id,
+        );
+        generate(self, input_ids, &config)
+    }
+}
+
+impl<T: Tensor + T5Ops<T>> Seq2Seq<T> for T5ForConditionalGeneration<T> {
+    fn encode(&self, input_ids: &[usize]) -> Result<T, SmeltError> {
+        self.encoder.forward(input_ids)
+    }
+
+    fn new_cache(&self) -> KvCache<T> {
+        KvCache::new(self.decoder.num_layers())
+    }
+
+    /// The keys and values of the encoder states are projected by the first call after
+    /// [KvCache::clear], then reused from `cache`.
+    fn decode(
+        &self,
+        decoder_input_ids: &[usize],
+        encoder_states: &T,
+        cache: &mut KvCache<T>,
+    ) -> Result<T, SmeltError> {
+        let embeddings = self.encoder.embeddings();
+        let (vocab_size, d_model) = (
+            embeddings.weight().shape()[0],
+            embeddings.weight().shape()[1],
+        );
+        let device = encoder_states.device();
+        let mut hidden_states = device.zeros(vec![decoder_input_ids.len(), d_model])?;
+        let mut logits = device.zeros(vec![decoder_input_ids.len(), vocab_size])?;
+        embeddings.forward(decoder_input_ids, &mut hidden_states)?;
+        self.decoder
+            .forward(&mut hidden_states, Some(encoder_states), Some(cache))?;
+        if let Some(scale) = self.lm_head_scale {
+            T::mul_scalar(&mut hidden_states, scale)?;
+        }
+        self.lm_head.forward(&hidden_states, &mut logits)?;
+        Ok(logits)
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::{encoder_from, seq2seq_from};
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{load_tensor, to_f32};
+    use crate::nn::layers::{QkvProjection, RelativePositionBias};
+    use safetensors::SafeTensors;
+
+    /// The tensors of a T5 checkpoint, loaded on a device.
+    struct Loader<'a, 'data> {
+        tensors: &'a SafeTensors<'data>,
+        config: &'a T5Config,
+        device: &'a Device,
+    }
+
+    impl Loader<'_, '_> {
+        fn tensor(&self, name: String) -> Result<Tensor, SmeltError> {
+            load_tensor(self.tensors, &name, self.device)
+        }
+
+        fn has_tensor(&self, name: &str) -> bool {
+            self.tensors.tensor(name).is_ok()
+        }
+
+        /// The projections of T5 have no bias, they are given zero biases.
+        fn linear(&self, prefix: &str) -> Result<Linear<Tensor>, SmeltError> {
+            let weight = self.tensor(format!("{prefix}.weight"))?;
+            let bias = Tensor::zeros(vec![weight.shape()[0]], self.device)?;
+            Ok(Linear::new(weight, bias))
+        }
+
+        fn dropout(&self) -> Result<Dropout, SmeltError> {
+            Dropout::new(self.config.dropout_rate)
+        }
+
+        fn norm(&self, prefix: &str) -> Result<RmsNorm<Tensor>, SmeltError> {
+            let weight = self.tensor(format!("{prefix}.weight"))?;
+            RmsNorm::new(weight, self.config.layer_norm_epsilon)
+        }
+
+        /// The shared embeddings, also stored as the `embed_tokens` of the encoder.
+        fn embeddings(&self) -> Result<Embedding<Tensor>, SmeltError> {
+            let name = if self.has_tensor("shared.weight") {
+                "shared.weight"
+            } else {
+                "encoder.embed_tokens.weight"
+            };
+            Ok(Embedding::new(self.tensor(name.to_string())?))
+        }
+
+        fn attention(
+            &self,
+            prefix: &str,
+            causal: bool,
+            bias: Option<&RelativePositionBias>,
+        ) -> Result<MultiHeadAttention<Tensor>, SmeltError> {
+            let query = self.linear(&format!("{prefix}.q"))?;
+            let num_heads = self.config.num_heads;
+            let config = AttentionConfig {
+                scale: Some(1.0),
+                causal,
+                ..AttentionConfig::new(num_heads, query.shape()[0] / num_heads)
+            };
+            let qkv = QkvProjection::Separate {
+                query,
+                key: self.linear(&format!("{prefix}.k"))?,
+                value: self.linear(&format!("{prefix}.v"))?,
+            };
+            let output = self.linear(&format!("{prefix}.o"))?;
+            let attention =
+                MultiHeadAttention::new(qkv, Some(output), config)?.with_dropout(self.dropout()?);
+            Ok(match bias {
+                Some(bias) => attention.with_relative_position_bias(bias.clone()),
+                None => attention,
+            })
+        }
+
+        fn feed_forward(&self, prefix: &str) -> Result<T5FeedForward<Tensor>, SmeltError> {
+            let activation = self.config.activation;
+            let wo = self.linear(&format!("{prefix}.wo"))?;
+            if self.config.gated {
+                let gate = self.linear(&format!("{prefix}.wi_0"))?;
+                let up = self.linear(&format!("{prefix}.wi_1"))?;
+                let mlp = GatedMlp::new(gate, up, wo, activation)?.with_dropout(self.dropout()?);
+                return Ok(T5FeedForward::Gated(mlp));
+            }
+            Ok(T5FeedForward::Dense {
+                wi: self.linear(&format!("{prefix}.wi"))?,
+                wo,
+                activation,
+                dropout: self.dropout()?,
+            })
+        }
+
+        /// The blocks of `encoder` or `decoder`, the relative position bias of the first
+        /// block being shared by all of them.
+        fn stack(&self, stack: &str) -> Result<T5Stack<Tensor>, SmeltError> {
+            let is_decoder = stack == "decoder";
+            let name =
+                format!("{stack}.block.0.layer.0.SelfAttention.relative_attention_bias.weight");
+            let Ok(view) = self.tensors.tensor(&name) else {
+                return Err(SmeltError::MissingTensor(name));
+            };
+            let bias = RelativePositionBias::new(
+                to_f32(&view)?.into_owned(),
+                self.config.num_heads,
+                !is_decoder,
+                self.config.relative_attention_max_distance,
+            )?;
+
+            let has_block = |index: usize| {
+                self.has_tensor(&format!(
+                    "{stack}.block.{index}.layer.0.SelfAttention.q.weight"
+                ))
+            };
+            let num_blocks = (0..).take_while(|&index| has_block(index)).count();
+            let blocks = (0..num_blocks)
+                .map(|index| {
+                    let prefix = format!("{stack}.block.{index}.layer");
+                    let self_attention = self.attention(
+                        &format!("{prefix}.0.SelfAttention"),
+                        is_decoder,
+                        Some(&bias),
+                    )?;
+                    let self_attention_norm = self.norm(&format!("{prefix}.0.layer_norm"))?;
+                    // The feed-forward layer follows the cross-attention of the decoders.
+                    let ff = if is_decoder { 2 } else { 1 };
+                    let block = T5Block::new(
+                        self_attention_norm,
+                        self_attention,
+                        self.norm(&format!("{prefix}.{ff}.layer_norm"))?,
+                        self.feed_forward(&format!("{prefix}.{ff}.DenseReluDense"))?,
+                    )
+                    .with_dropout(self.dropout()?);
+                    if !is_decoder {
+                        return Ok(block);
+                    }
+                    Ok(block.with_cross_attention(
+                        self.norm(&format!("{prefix}.1.layer_norm"))?,
+                        self.attention(&format!("{prefix}.1.EncDecAttention"), false, None)?,
+                    ))
+                })
+                .collect::<Result<_, SmeltError>>()?;
+            let final_layer_norm = self.norm(&format!("{stack}.final_layer_norm"))?;
+            Ok(T5Stack::new(blocks, final_layer_norm)?.with_dropout(self.dropout()?))
+        }
+    }
+
+    /// Creates a [T5Encoder] of the `config` on `device`, from the tensors of a
+    /// `T5EncoderModel` or a `T5ForConditionalGeneration` checkpoint: `shared` and
+    /// `encoder.*`. The number of blocks is the one of the checkpoint.
+    /// ```no_run
+    /// # #[cfg(feature = "cpu")]
+    /// # {
+    /// use safetensors::SafeTensors;
+    /// use smelte_rs::backend::{Device, Tensor};
+    /// use smelte_rs::nn::layers::Pooling;
+    /// use smelte_rs::nn::models::t5::{encoder_from, T5Config};
+    ///
+    /// # let buffer = vec![];
+    /// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+    /// let device = Device::cpu();
+    /// let encoder = encoder_from(&tensors, &T5Config::new(12), &device).unwrap();
+    /// let hidden_states = encoder.forward(&[363, 19, 8, 1]).unwrap();
+    /// // The sentence embedding of sentence-t5.
+    /// let mut embedding = Tensor::zeros(vec![1, 768], &device).unwrap();
+    /// Pooling::Mean.forward(&hidden_states, None, &mut embedding).unwrap();
+    /// # }
+    /// ```
+    pub fn encoder_from(
+        tensors: &SafeTensors<'_>,
+        config: &T5Config,
+        device: &Device,
+    ) -> Result<T5Encoder<Tensor>, SmeltError> {
+        let loader = Loader {
+            tensors,
+            config,
+            device,
+        };
+        T5Encoder::new(loader.embeddings()?, loader.stack("encoder")?)
+    }
+
+    /// Creates a [T5ForConditionalGeneration] of the `config` on `device`, from the
+    /// tensors of a `T5ForConditionalGeneration` checkpoint: `shared`, `encoder.*`,
+    /// `decoder.*` and `lm_head`, unless `tie_word_embeddings`.
+    pub fn seq2seq_from(
+        tensors: &SafeTensors<'_>,
+        config: &T5Config,
+        device: &Device,
+    ) -> Result<T5ForConditionalGeneration<Tensor>, SmeltError> {
+        let loader = Loader {
+            tensors,
+            config,
+            device,
+        };
+        let encoder = T5Encoder::new(loader.embeddings()?, loader.stack("encoder")?)?;
+        let lm_head = if config.tie_word_embeddings {
+            UnbiasedLinear::tied(encoder.embeddings())
+        } else {
+            UnbiasedLinear::new(loader.tensor("lm_head.weight".to_string())?)
+        };
+        T5ForConditionalGeneration::new(encoder, loader.stack("decoder")?, lm_head, config)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+    use crate::nn::layers::RelativePositionBias;
+    use crate::tests::{linear, rms_norm};
+
+    const D_MODEL: usize = 4;
+    const NUM_HEADS: usize = 2;
+    const VOCAB_SIZE: usize = 6;
+
+    fn attention(seed: u64, causal: bool, bias: Option<bool>) -> MultiHeadAttention<Tensor> {
+        let config = AttentionConfig {
+            scale: Some(1.0),
+            causal,
+            ..AttentionConfig::new(NUM_HEADS, D_MODEL / NUM_HEADS)
+        };
+        let attention = crate::tests::attention(config, seed);
+        match bias {
+            Some(bidirectional) => {
+                let table = (0..8 * NUM_HEADS).map(|i| (i as f32).sin()).collect();
+                let bias = RelativePositionBias::new(table, NUM_HEADS, bidirectional, 8).unwrap();
+                attention.with_relative_position_bias(bias)
+            }
+            None => attention,
+        }
+    }
+
+    fn feed_forward(seed: u64) -> T5FeedForward<Tensor> {
+        T5FeedForward::Dense {
+            wi: linear(8, D_MODEL, seed),
+            wo: linear(D_MODEL, 8, seed + 1),
+            activation: Activation::Relu,
+            dropout: Dropout::default(),
+        }
+    }
+
+    fn model() -> T5ForConditionalGeneration<Tensor> {
+        let embeddings = Embedding::new(Tensor::rand_normal(vec![VOCAB_SIZE, D_MODEL], 0));
+        let encoder_blocks = (0..2)
+            .map(|i| {
+                let seed = 10 * (i + 1);
+                let attention = attention(seed, false, Some(true));
+                T5Block::new(
+                    rms_norm(D_MODEL),
+                    attention,
+                    rms_norm(D_MODEL),
+                    feed_forward(seed + 5),
+                )
+            })
+            .collect();
+        let encoder_stack = T5Stack::new(encoder_blocks, rms_norm(D_MODEL)).unwrap();
+        let encoder = T5Encoder::new(embeddings, encoder_stack).unwrap();
+        let decoder_blocks = (0..2)
+            .map(|i| {
+                let seed = 100 + 10 * i;
+                let attention = attention(seed, true, Some(false));
+                T5Block::new(
+                    rms_norm(D_MODEL),
+                    attention,
+                    rms_norm(D_MODEL),
+                    feed_forward(seed + 5),
+                )
+                .with_cross_attention(rms_norm(D_MODEL), self::attention(seed + 7, false, None))
+            })
+            .collect();
+        let decoder = T5Stack::new(decoder_blocks, rms_norm(D_MODEL)).unwrap();
+        let lm_head = UnbiasedLinear::tied(encoder.embeddings());
+        T5ForConditionalGeneration::new(encoder, decoder, lm_head, &T5Config::new(NUM_HEADS))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_t5_config() {
+        let config = T5Config::new(8).with_feed_forward_proj("relu").unwrap();
+        assert!(!config.gated);
+        assert_eq!(config.activation, Activation::Relu);
+        let config = T5Config::new(8)
+            .with_feed_forward_proj("gated-silu")
+            .unwrap();
+        assert!(config.gated);
+        assert_eq!(config.activation, Activation::Silu);
+        assert!(T5Config::new(8)
+            .with_feed_forward_proj("gated-mish")
+            .is_err());
+    }
+
+    #[test]
+    fn test_t5_decode() {
+        let model = model();
+        let encoder_states = model.encoder().forward(&[2, 3, 4]).unwrap();
+        assert_eq!(encoder_states.shape(), [3, D_MODEL]);
+
+        // The cached decoding of a token at a time is the decoding of the whole sequence.
+        let decoder_input_ids = [0, 5, 2, 3];
+        let mut cache = model.new_cache();
+        let logits = model
+            .decode(&decoder_input_ids, &encoder_states, &mut cache)
+            .unwrap();
+        assert_eq!(logits.shape(), [4, VOCAB_SIZE]);
+        let mut cache = model.new_cache();
+        for (i, &id) in decoder_input_ids.iter().enumerate() {
+            let step = model.decode(&[id], &encoder_states, &mut cache).unwrap();
+            let expected = &logits.data()[i * VOCAB_SIZE..(i + 1) * VOCAB_SIZE];
+            for (a, b) in step.data().iter().zip(expected) {
+                assert!((a - b).abs() < 1e-4, "{a} != {b}");
+            }
+        }
+        assert_eq!(cache.len(), 4);
+
+        let output_ids = model.generate(&[2, 3, 4], 5).unwrap();
+        assert!(output_ids.len() <= 5);
+        assert!(!output_ids.contains(&1));
+        assert!(model.encoder().forward(&[VOCAB_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_t5_stack_errors() {
+        let encoder_block = T5Block::new(
+            rms_norm(D_MODEL),
+            attention(0, false, None),
+            rms_norm(D_MODEL),
+            feed_forward(5),
+        );
+        let decoder_block = encoder_block
+            .clone()
+            .with_cross_attention(rms_norm(D_MODEL), attention(7, false, None));
+        assert!(T5Stack::new(vec![], rms_norm(D_MODEL)).is_err());
+        let blocks = vec![encoder_block.clone(), decoder_block.clone()];
+        assert!(T5Stack::new(blocks, rms_norm(D_MODEL)).is_err());
+
+        let embeddings = Embedding::new(Tensor::rand_normal(vec![VOCAB_SIZE, D_MODEL], 0));
+        let decoder = T5Stack::new(vec![decoder_block], rms_norm(D_MODEL)).unwrap();
+        assert!(T5Encoder::new(embeddings, decoder.clone()).is_err());
+        // Without the encoder states.
+        let mut hidden_states = Tensor::zeros(vec![1, D_MODEL]);
+        assert!(decoder.forward(&mut hidden_states, None, None).is_err());
+    }
+}
@@ -0,0 +1,578 @@
+use crate::nn::layers::{Conv2d, Dropout, LayerNorm, Linear, MultiHeadAttention};
+use crate::traits::{
+    Activation, Device, Tensor, TensorConv2d, TensorHeads, TensorOps, TensorPad, TensorReshape,
+};
+use crate::SmeltError;
+
+/// The operations of ViT, on top of the common ones: the convolution of the patches,
+/// flattened into tokens by a reshape and the merge of the heads of the attention, and
+/// the padding adding the CLS token. The cpu, cuda and backend f32 tensors implement
+/// them.
+pub trait ViTOps<T: Tensor>:
+    TensorOps<T> + TensorHeads<T> + TensorPad<T> + TensorConv2d<T> + TensorReshape<T>
+{
+}
+
+impl<T> ViTOps<T> for T where
+    T: Tensor + TensorOps<T> + TensorHeads<T> + TensorPad<T> + TensorConv2d<T> + TensorReshape<T>
+{
+}
+
+/// The embeddings of ViT: the patches of the image projected by a convolution whose
+/// stride is its kernel, after the CLS token, plus the learned positions.
+#[derive(Clone)]
+pub struct ViTEmbeddings<T: Tensor> {
+    patch_embeddings: Conv2d<T>,
+    // The CLS token added to the first position (num_patches + 1, hidden_dim).
+    cls_positions: T,
+    dropout: Dropout,
+}
+
+impl<T: Tensor + ViTOps<T>> ViTEmbeddings<T> {
+    /// The embeddings of the `patch_embeddings` (hidden_dim, num_channels, patch_size,
+    /// patch_size), the `cls_token` (1, hidden_dim) and the `position_embeddings`
+    /// (num_patches + 1, hidden_dim).
+    pub fn new(
+        patch_embeddings: Conv2d<T>,
+        cls_token: T,
+        position_embeddings: T,
+    ) -> Result<Self, SmeltError> {
+        let hidden_dim = patch_embeddings.weight().shape()[0];
+        if cls_token.shape() != [1, hidden_dim] {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![1, hidden_dim],
+                got: cls_token.shape().to_vec(),
+            });
+        }
+        let num_positions = match position_embeddings.shape() {
+            &[num_positions, dim] if dim == hidden_dim => num_positions,
+            shape => {
+                return Err(SmeltError::DimensionMismatch {
+                    expected: vec![shape.first().copied().unwrap_or(0), hidden_dim],
+                    got: shape.to_vec(),
+                })
+            }
+        };
+        if num_positions < 2 {
+            return Err(SmeltError::InvalidConfig(
+                "the positions of the CLS token and of the patches".to_string(),
+            ));
+        }
+        let device = position_embeddings.device();
+        let mut cls_positions = device.zeros(vec![num_positions, hidden_dim])?;
+        let padding = [(0, num_positions as isize - 1), (0, 0)];
+        T::pad(&cls_token, &padding, 0.0, &mut cls_positions)?;
+        T::add(&position_embeddings, &mut cls_positions)?;
+        Ok(Self {
+            patch_embeddings,
+            cls_positions,
+            dropout: Dropout::default(),
+        })
+    }
+
+    /// Drops the embeddings with `dropout` in train mode, the `hidden_dropout_prob` of
+    /// the configuration.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropout to train or eval mode, see [Dropout::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.dropout.set_training(training);
+    }
+
+    /// The convolution of the patches
+    pub fn patch_embeddings(&self) -> &Conv2d<T> {
+        &self.patch_embeddings
+    }
+
+    /// The number of patches of an image, without the CLS token
+    pub fn num_patches(&self) -> usize {
+        self.cls_positions.shape()[0] - 1
+    }
+
+    /// The width of the hidden states
+    pub fn hidden_dim(&self) -> usize {
+        self.cls_positions.shape()[1]
+    }
+
+    /// The embeddings (num_patches + 1, hidden_dim) of `pixel_values` (1, num_channels,
+    /// height, width), an image of the size of the checkpoint: the positions aren't
+    /// interpolated to other sizes.
+    pub fn forward(&self, pixel_values: &T) -> Result<T, SmeltError> {
+        let shape = self.patch_embeddings.output_shape(pixel_values.shape())?;
+        let &[1, hidden_dim, height, width] = shape.as_slice() else {
+            return Err(SmeltError::InvalidConfig(format!(
+                "a batch of one image, not {:?}",
+                pixel_values.shape()
+            )));
+        };
+        let num_patches = height * width;
+        if num_patches != self.num_patches() {
+            return Err(SmeltError::InvalidLength {
+                expected: self.num_patches(),
+                got: num_patches,
+            });
+        }
+        let device = pixel_values.device();
+        let mut patches = device.zeros(shape)?;
+        self.patch_embeddings.forward(pixel_values, &mut patches)?;
+        // The channels of the patches are the heads of a head_dim of 1, merged into the
+        // columns of the tokens.
+        let patches = T::reshape(patches, vec![hidden_dim, num_patches, 1])?;
+        let mut tokens = device.zeros(vec![num_patches, hidden_dim])?;
+        T::unsplit_heads(&patches, &mut tokens)?;
+        let mut hidden_states = device.zeros(vec![num_patches + 1, hidden_dim])?;
+        T::pad(&tokens, &[(1, 0), (0, 0)], 0.0, &mut hidden_states)?;
+        T::add(&self.cls_positions, &mut hidden_states)?;
+        self.dropout.forward(&mut hidden_states)?;
+        Ok(hidden_states)
+    }
+}
+
+/// A layer of the encoder of ViT: the self-attention and the feed-forward layer of BERT,
+/// each applied to the layer norm of its input, `layernorm_before` and
+/// `layernorm_after`, and added to it.
+#[derive(Clone)]
+pub struct ViTLayer<T: Tensor> {
+    layernorm_before: LayerNorm<T>,
+    attention: MultiHeadAttention<T>,
+    layernorm_after: LayerNorm<T>,
+    intermediate: Linear<T>,
+    output: Linear<T>,
+    activation: Activation,
+    // The dropout of the output of the attention and of the feed-forward layer
+    dropout: Dropout,
+}
+
+impl<T: Tensor + ViTOps<T>> ViTLayer<T> {
+    /// The layer of the `attention` and of the feed-forward layer
+    /// `output(gelu(intermediate(x)))`.
+    pub fn new(
+        layernorm_before: LayerNorm<T>,
+        attention: MultiHeadAttention<T>,
+        layernorm_after: LayerNorm<T>,
+        intermediate: Linear<T>,
+        output: Linear<T>,
+    ) -> Self {
+        Self {
+            layernorm_before,
+            attention,
+            layernorm_after,
+            intermediate,
+            output,
+            activation: Activation::Gelu,
+            dropout: Dropout::default(),
+        }
+    }
+
+    /// Drops the outputs of the attention and of the feed-forward layer with `dropout`
+    /// in train mode, before they are added to their inputs, the `hidden_dropout_prob`
+    /// of the configuration.
+    pub fn with_dropout(mut self, dropout: Dropout) -> Self {
+        self.dropout = dropout;
+        self
+    }
+
+    /// Switches the dropouts of the layer and its attention to train or eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.attention.set_training(training);
+        self.dropout.set_training(training);
+    }
+
+    /// Replaces the activation of the feed-forward layer, like the `hidden_act` of the
+    /// configuration.
+    pub fn with_activation(mut self, activation: Activation) -> Self {
+        self.activation = activation;
+        self
+    }
+
+    /// The layer of `hidden_states` (sequence_length, hidden_dim) in place.
+    pub fn forward(&self, hidden_states: &mut T) -> Result<(), SmeltError> {
+        let shape = hidden_states.shape().to_vec();
+        let sequence_length = shape[0];
+        let device = hidden_states.device();
+        let mut normed = device.zeros(shape.clone())?;
+        let mut out = device.zeros(shape)?;
+        let mut intermediate = device.zeros(vec![sequence_length, self.intermediate.shape()[0]])?;
+        let mut ctx = self.attention.context(sequence_length, device)?;
+
+        T::copy(hidden_states, &mut normed)?;
+        self.layernorm_before.forward(&mut normed)?;
+        self.attention.forward(&normed, None, &mut ctx, &mut out)?;
+        self.dropout.forward(&mut out)?;
+        T::add(&out, hidden_states)?;
+
+        T::copy(hidden_states, &mut normed)?;
+        self.layernorm_after.forward(&mut normed)?;
+        self.intermediate.forward(&normed, &mut intermediate)?;
+        T::activation(&mut intermediate, self.activation)?;
+        self.output.forward(&intermediate, &mut out)?;
+        self.dropout.forward(&mut out)?;
+        T::add(&out, hidden_states)
+    }
+}
+
+/// The ViT encoder: its embeddings and layers, followed by a layer norm.
+#[derive(Clone)]
+pub struct ViT<T: Tensor> {
+    embeddings: ViTEmbeddings<T>,
+    pre_layernorm: Option<LayerNorm<T>>,
+    layers: Vec<ViTLayer<T>>,
+    layernorm: LayerNorm<T>,
+}
+
+impl<T: Tensor + ViTOps<T>> ViT<T> {
+    /// The encoder of `layers`, which can't be empty.
+    pub fn new(
+        embeddings: ViTEmbeddings<T>,
+        layers: Vec<ViTLayer<T>>,
+        layernorm: LayerNorm<T>,
+    ) -> Result<Self, SmeltError> {
+        if layers.is_empty() {
+            return Err(SmeltError::InvalidConfig(
+                "an encoder without layers".to_string(),
+            ));
+        }
+        Ok(Self {
+            embeddings,
+            pre_layernorm: None,
+            layers,
+            layernorm,
+        })
+    }
+
+    /// Normalizes the embeddings before the first layer with `pre_layernorm`, like the
+    /// vision tower of CLIP.
+    pub fn with_pre_layernorm(mut self, pre_layernorm: LayerNorm<T>) -> Self {
+        self.pre_layernorm = Some(pre_layernorm);
+        self
+    }
+
+    /// The embeddings
+    pub fn embeddings(&self) -> &ViTEmbeddings<T> {
+        &self.embeddings
+    }
+
+    /// The number of layers
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Switches the dropouts of the embeddings and of every layer to train or eval mode,
+    /// the models being built in eval mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.embeddings.set_training(training);
+        for layer in &mut self.layers {
+            layer.set_training(training);
+        }
+    }
+
+    /// The hidden states (num_patches + 1, hidden_dim) of `pixel_values` (1,
+    /// num_channels, height, width), the first one being the CLS token.
+    pub fn forward(&self, pixel_values: &T) -> Result<T, SmeltError> {
+        let mut hidden_states = self.embeddings.forward(pixel_values)?;
+        if let Some(pre_layernorm) = &self.pre_layernorm {
+            pre_layernorm.forward(&mut hidden_states)?;
+        }
+        for layer in &self.layers {
+            layer.forward(&mut hidden_states)?;
+        }
+        self.layernorm.forward(&mut hidden_states)?;
+        Ok(hidden_states)
+    }
+}
+
+/// The image classifier of ViT, like `ViTForImageClassification`: a linear classifier
+/// of the CLS token of the encoder.
+/// ```no_run
+/// # #[cfg(feature = "cpu")]
+/// # {
+/// use safetensors::SafeTensors;
+/// use smelte_rs::backend::{Device, Tensor};
+/// use smelte_rs::nn::models::vit::classifier_from;
+/// use smelte_rs::traits::TensorSoftmax;
+///
+/// # let buffer = vec![];
+/// let tensors = SafeTensors::deserialize(&buffer).unwrap();
+/// // google/vit-base-patch16-224
+/// let device = Device::cpu();
+/// let model = classifier_from(&tensors, 12, &device).unwrap();
+/// let pixel_values = Tensor::zeros(vec![1, 3, 224, 224], &device).unwrap();
+/// let mut probs = model.forward(&pixel_values).unwrap();
+/// Tensor::softmax(&mut probs).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ViTForImageClassification<T: Tensor> {
+    vit: ViT<T>,
+    classifier: Linear<T>,
+}
+
+impl<T: Tensor + ViTOps<T>> ViTForImageClassification<T> {
+    /// The classifier (num_labels, hidden_dim) of the encoder `vit`.
+    pub fn new(vit: ViT<T>, classifier: Linear<T>) -> Result<Self, SmeltError> {
+        let hidden_dim = vit.embeddings().hidden_dim();
+        if classifier.shape().get(1) != Some(&hidden_dim) {
+            return Err(SmeltError::DimensionMismatch {
+                expected: vec![classifier.shape()[0], hidden_dim],
+                got: classifier.shape().to_vec(),
+            });
+        }
+        Ok(Self { vit, classifier })
+    }
+
+    /// The encoder
+    pub fn vit(&self) -> &ViT<T> {
+        &self.vit
+    }
+
+    /// Switches the dropouts of the encoder to train or eval mode, see
+    /// [ViT::set_training].
+    pub fn set_training(&mut self, training: bool) {
+        self.vit.set_training(training);
+    }
+
+    /// The logits (1, num_labels) of `pixel_values` (1, num_channels, height, width),
+    /// normalized like the `preprocessor_config.json` of the checkpoint.
+    pub fn forward(&self, pixel_values: &T) -> Result<T, SmeltError> {
+        let hidden_states = self.vit.forward(pixel_values)?;
+        let num_patches = self.vit.embeddings().num_patches();
+        let device = hidden_states.device();
+        let mut cls = device.zeros(vec![1, hidden_states.shape()[1]])?;
+        T::pad(
+            &hidden_states,
+            &[(0, -(num_patches as isize)), (0, 0)],
+            0.0,
+            &mut cls,
+        )?;
+        let mut logits = device.zeros(vec![1, self.classifier.shape()[0]])?;
+        self.classifier.forward(&cls, &mut logits)?;
+        Ok(logits)
+    }
+}
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+pub use checkpoint::classifier_from;
+
+#[cfg(all(
+    feature = "safetensors",
+    any(
+        feature = "cpu",
+        feature = "cuda",
+        feature = "metal",
+        feature = "wgpu",
+        feature = "opencl",
+        feature = "vulkan"
+    )
+))]
+mod checkpoint {
+    use super::*;
+    use crate::backend::{Device, Tensor};
+    use crate::checkpoint::{conv2d_from, load_layer_norm, load_linear, load_tensor};
+    use crate::nn::layers::{AttentionConfig, QkvProjection};
+    use crate::traits::Conv2dConfig;
+    use safetensors::SafeTensors;
+
+    /// The layer norms of ViT, the `layer_norm_eps` of its configuration.
+    const LAYER_NORM_EPS: f32 = 1e-12;
+
+    /// Creates a [ViTForImageClassification] of `num_heads` heads (the
+    /// `num_attention_heads` of the configuration) on `device`, from the tensors of a
+    /// `ViTForImageClassification` checkpoint: `vit.embeddings.*`,
+    /// `vit.encoder.layer.N.*`, `vit.layernorm` and `classifier`. The patch size and the
+    /// number of layers are the ones of the checkpoint. The model has no dropout, the
+    /// `hidden_dropout_prob` and `attention_probs_dropout_prob` of the checkpoints being
+    /// 0.
+    pub fn classifier_from(
+        tensors: &SafeTensors<'_>,
+        num_heads: usize,
+        device: &Device,
+    ) -> Result<ViTForImageClassification<Tensor>, SmeltError> {
+        let prefix = "vit.embeddings.patch_embeddings.projection";
+        let weight = tensors
+            .tensor(&format!("{prefix}.weight"))
+            .map_err(|_| SmeltError::MissingTensor(format!("{prefix}.weight")))?;
+        let &[hidden_dim, _, patch_height, patch_width] = weight.shape() else {
+            return Err(SmeltError::InvalidRank { expected_rank: 4 });
+        };
+        let config = Conv2dConfig {
+            stride: [patch_height, patch_width],
+            ..Default::default()
+        };
+        let patch_embeddings = conv2d_from(tensors, prefix, config, device)?;
+        // The cls token (1, 1, hidden_dim) and the positions (1, num_patches + 1,
+        // hidden_dim) of the checkpoint have a batch dimension.
+        let cls_token = load_tensor(tensors, "vit.embeddings.cls_token", device)?
+            .reshape(vec![1, hidden_dim])?;
+        let position_embeddings =
+            load_tensor(tensors, "vit.embeddings.position_embeddings", device)?;
+        let num_positions = position_embeddings.shape().iter().product::<usize>() / hidden_dim;
+        let position_embeddings = position_embeddings.reshape(vec![num_positions, hidden_dim])?;
+        let embeddings = ViTEmbeddings::new(patch_embeddings, cls_token, position_embeddings)?;
+
+        let has_layer = |index: usize| {
+            let name = format!("vit.encoder.layer.{index}.attention.attention.query.weight");
+            tensors.tensor(&name).is_ok()
+        };
+        let num_layers = (0..).take_while(|&index| has_layer(index)).count();
+        if num_layers == 0 {
+            return Err(SmeltError::MissingTensor(
+                "vit.encoder.layer.0.attention.attention.query.weight".to_string(),
+            ));
+        }
+        let layers = (0..num_layers)
+            .map(|index| {
+                let prefix = format!("vit.encoder.layer.{index}");
+                let qkv = QkvProjection::Separate {
+                    query: load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.attention.query"),
+                        device,
+                    )?,
+                    key: load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.attention.key"),
+                        device,
+                    )?,
+                    value: load_linear(
+                        tensors,
+                        &format!("{prefix}.attention.attention.value"),
+                        device,
+                    )?,
+                };
+                let config = AttentionConfig::new(num_heads, hidden_dim / num_heads);
+                let output =
+                    load_linear(tensors, &format!("{prefix}.attention.output.dense"), device)?;
+                let attention = MultiHeadAttention::new(qkv, Some(output), config)?;
+                Ok(ViTLayer::new(
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.layernorm_before"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                    attention,
+                    load_layer_norm(
+                        tensors,
+                        &format!("{prefix}.layernorm_after"),
+                        LAYER_NORM_EPS,
+                        device,
+                    )?,
+                    load_linear(tensors, &format!("{prefix}.intermediate.dense"), device)?,
+                    load_linear(tensors, &format!("{prefix}.output.dense"), device)?,
+                ))
+            })
+            .collect::<Result<_, SmeltError>>()?;
+
+        let vit = ViT::new(
+            embeddings,
+            layers,
+            load_layer_norm(tensors, "vit.layernorm", LAYER_NORM_EPS, device)?,
+        )?;
+        ViTForImageClassification::new(vit, load_linear(tensors, "classifier", device)?)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "cpu")]
+mod tests {
+    use super::*;
+    use crate::cpu::f32::Tensor;
+    use crate::nn::layers::AttentionConfig;
+    use crate::tests::{attention, layer_norm, linear};
+    use crate::traits::Conv2dConfig;
+
+    const HIDDEN_DIM: usize = 4;
+
+    fn layer(seed: u64) -> ViTLayer<Tensor> {
+        ViTLayer::new(
+            layer_norm(HIDDEN_DIM, 1e-12),
+            attention(AttentionConfig::new(2, 2), seed),
+            layer_norm(HIDDEN_DIM, 1e-12),
+            linear(8, HIDDEN_DIM, seed + 4),
+            linear(HIDDEN_DIM, 8, seed + 5),
+        )
+    }
+
+    /// 2x2 patches of a 1 channel image, projected to their sum times the channel number.
+    fn embeddings(num_patches: usize) -> ViTEmbeddings<Tensor> {
+        let weight: Vec<f32> = (0..HIDDEN_DIM).flat_map(|c| [c as f32; 4]).collect();
+        let weight = Tensor::new(weight, vec![HIDDEN_DIM, 1, 2, 2]).unwrap();
+        let config = Conv2dConfig {
+            stride: [2, 2],
+            ..Default::default()
+        };
+        let patch_embeddings = Conv2d::new(weight, None, config);
+        let cls_token = Tensor::new(vec![100.0; HIDDEN_DIM], vec![1, HIDDEN_DIM]).unwrap();
+        let positions = Tensor::zeros(vec![num_patches + 1, HIDDEN_DIM]);
+        ViTEmbeddings::new(patch_embeddings, cls_token, positions).unwrap()
+    }
+
+    #[test]
+    fn test_vit_embeddings() {
+        let embeddings = embeddings(2);
+        assert_eq!(embeddings.num_patches(), 2);
+        // The patches of sums 1 + 2 + 5 + 6 = 14 and 3 + 4 + 7 + 8 = 22.
+        let pixel_values: Vec<f32> = (1..=8).map(|v| v as f32).collect();
+        let pixel_values = Tensor::new(pixel_values, vec![1, 1, 2, 4]).unwrap();
+        let hidden_states = embeddings.forward(&pixel_values).unwrap();
+        assert_eq!(hidden_states.shape(), [3, HIDDEN_DIM]);
+        assert_eq!(
+            hidden_states.data(),
+            [100.0, 100.0, 100.0, 100.0, 0.0, 14.0, 28.0, 42.0, 0.0, 22.0, 44.0, 66.0]
+        );
+
+        // Other sizes and batches.
+        assert!(embeddings
+            .forward(&Tensor::zeros(vec![1, 1, 4, 4]))
+            .is_err());
+        assert!(embeddings
+            .forward(&Tensor::zeros(vec![2, 1, 2, 4]))
+            .is_err());
+        let patch_embeddings = embeddings.patch_embeddings().clone();
+        let positions = Tensor::zeros(vec![3, HIDDEN_DIM]);
+        assert!(ViTEmbeddings::new(patch_embeddings, Tensor::zeros(vec![2]), positions).is_err());
+    }
+
+    #[test]
+    fn test_vit_classifier() {
+        let vit = ViT::new(
+            embeddings(2),
+            vec![layer(0), layer(10)],
+            layer_norm(HIDDEN_DIM, 1e-12),
+        )
+        .unwrap();
+        assert_eq!(vit.num_layers(), 2);
+        let model = ViTForImageClassification::new(vit, linear(3, HIDDEN_DIM, 20)).unwrap();
+        let pixel_values = Tensor::rand_normal(vec![1, 1, 2, 4], 30);
+        let hidden_states = model.vit().forward(&pixel_values).unwrap();
+        assert_eq!(hidden_states.shape(), [3, HIDDEN_DIM]);
+
+        // The logits of the CLS token, the first hidden state.
+        let logits = model.forward(&pixel_values).unwrap();
+        assert_eq!(logits.shape(), [1, 3]);
+        let cls = hidden_states.data()[..HIDDEN_DIM].to_vec();
+        let cls = Tensor::new(cls, vec![1, HIDDEN_DIM]).unwrap();
+        let mut expected = Tensor::zeros(vec![1, 3]);
+        linear(3, HIDDEN_DIM, 20)
+            .forward(&cls, &mut expected)
+            .unwrap();
+        assert_eq!(logits.data(), expected.data());
+
+        assert!(ViT::new(embeddings(2), vec![], layer_norm(HIDDEN_DIM, 1e-12)).is_err());
+        let vit = ViT::new(embeddings(2), vec![layer(0)], layer_norm(HIDDEN_DIM, 1e-12)).unwrap();
+        assert!(ViTForImageClassification::new(vit, linear(3, 8, 20)).is_err());
+    }
+}
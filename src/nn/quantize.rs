@@ -0,0 +1,98 @@
+use crate::nn::layers::Linear;
+use crate::traits::{Tensor, TensorOps, TensorQuantize};
+use crate::SmeltError;
+
+/// Models which can be quantized statically by a [Quantizer].
+pub trait Quantize<T: Tensor> {
+    /// The inputs of one calibration run.
+    type Input;
+
+    /// Every [Linear] layer to quantize.
+    fn linears(&mut self) -> Vec<&mut Linear<T>>;
+
+    /// Runs the model on `input`, the outputs are discarded.
+    fn calibrate(&mut self, input: &Self::Input) -> Result<(), SmeltError>;
+}
+
+/// Static int8 quantization: [Quantizer::observe] runs the model on calibration inputs
+/// and records the ranges of the activations of every [Linear] layer, then
+/// [Quantizer::quantize] replaces them with int8 weights and fixed activation scales
+/// (see [Linear::quantize_static]).
+/// ```
+/// use smelte_rs::cpu::f32::Tensor;
+/// use smelte_rs::nn::layers::Linear;
+/// use smelte_rs::nn::quantize::{Quantize, Quantizer};
+/// use smelte_rs::SmeltError;
+///
+/// struct Model(Linear<Tensor>);
+///
+/// impl Quantize<Tensor> for Model {
+///     type Input = Tensor;
+///
+///     fn linears(&mut self) -> Vec<&mut Linear<Tensor>> {
+///         vec![&mut self.0]
+///     }
+///
+///     fn calibrate(&mut self, input: &Tensor) -> Result<(), SmeltError> {
+///         self.0.forward(input, &mut Tensor::zeros(vec![1, 2]))
+///     }
+/// }
+///
+/// let weight = Tensor::new(vec![1.0, 2.0, 3.0, 4.0], vec![2, 2]).unwrap();
+/// let mut model = Model(Linear::new(weight, Tensor::zeros(vec![2])));
+/// let inputs = [Tensor::new(vec![1.0, -1.0], vec![1, 2]).unwrap()];
+///
+/// let mut quantizer = Quantizer::new();
+/// quantizer.observe(&mut model, &inputs).unwrap();
+/// quantizer.quantize(&mut model).unwrap();
+/// assert!(model.0.weight().is_none());
+/// ```
+#[derive(Debug, Default)]
+pub struct Quantizer {
+    observed: usize,
+}
+
+impl Quantizer {
+    /// A quantizer which has not observed anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `model` on every input, recording the ranges of the activations. Can be
+    /// called several times, the ranges accumulate.
+    pub fn observe<T, M>(&mut self, model: &mut M, inputs: &[M::Input]) -> Result<(), SmeltError>
+    where
+        T: TensorQuantize + TensorOps<T>,
+        M: Quantize<T>,
+    {
+        if self.observed == 0 {
+            model.linears().into_iter().for_each(Linear::observe);
+        }
+        for input in inputs {
+            model.calibrate(input)?;
+            self.observed += 1;
+        }
+        Ok(())
+    }
+
+    /// The number of calibration runs so far.
+    pub fn observed(&self) -> usize {
+        self.observed
+    }
+
+    /// Quantizes every [Linear] layer of `model` for the observed ranges. Fails with
+    /// [SmeltError::NotCalibrated] if nothing was observed.
+    pub fn quantize<T, M>(&self, model: &mut M) -> Result<(), SmeltError>
+    where
+        T: TensorQuantize + TensorOps<T>,
+        M: Quantize<T>,
+    {
+        if self.observed == 0 {
+            return Err(SmeltError::NotCalibrated);
+        }
+        for linear in model.linears() {
+            linear.quantize_static()?;
+        }
+        Ok(())
+    }
+}
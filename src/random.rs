@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+static SEED: AtomicU64 = AtomicU64::new(0x5eed);
+
+/// Sets the crate-level seed, the following [next_seed] give the same sequence.
+/// ```
+/// use smelte_rs::random::{manual_seed, next_seed};
+///
+/// manual_seed(42);
+/// let first = next_seed();
+/// manual_seed(42);
+/// assert_eq!(next_seed(), first);
+/// ```
+pub fn manual_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+}
+
+/// A new seed drawn from the crate-level seed, see [manual_seed].
+pub fn next_seed() -> u64 {
+    splitmix64(SEED.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed))
+}
+
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The item `index` of the uniform stream of `seed` in `[0, 1)`. Every item is hashed from
+/// its index (splitmix64), so the cuda kernels give the same items.
+fn uniform(seed: u64, index: u64) -> f32 {
+    let bits = splitmix64(seed.wrapping_add(index.wrapping_mul(GOLDEN_GAMMA)));
+    (bits >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// The item `index` of the standard normal stream of `seed`, Box-Muller on the uniform
+/// items `2 * index` and `2 * index + 1`.
+#[cfg(feature = "cpu")]
+fn normal(seed: u64, index: u64) -> f32 {
+    let u1 = 1.0 - uniform(seed, 2 * index);
+    let u2 = uniform(seed, 2 * index + 1);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+/// Fills `data` with the uniform stream of `seed`, see [uniform].
+pub(crate) fn fill_uniform(data: &mut [f32], seed: u64) {
+    data.iter_mut()
+        .enumerate()
+        .for_each(|(i, v)| *v = uniform(seed, i as u64));
+}
+
+/// Fills `data` with the standard normal stream of `seed`, see [normal].
+#[cfg(feature = "cpu")]
+pub(crate) fn fill_normal(data: &mut [f32], seed: u64) {
+    data.iter_mut()
+        .enumerate()
+        .for_each(|(i, v)| *v = normal(seed, i as u64));
+}
+
+#[cfg(all(test, feature = "cpu"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams() {
+        let items: Vec<f32> = (0..10_000).map(|i| uniform(7, i)).collect();
+        assert!(items.iter().all(|&v| (0.0..1.0).contains(&v)));
+        let mean = items.iter().sum::<f32>() / items.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01);
+        assert_ne!(uniform(7, 0), uniform(8, 0));
+
+        let items: Vec<f32> = (0..10_000).map(|i| normal(7, i)).collect();
+        assert!(items.iter().all(|v| v.is_finite()));
+        let mean = items.iter().sum::<f32>() / items.len() as f32;
+        let var = items.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / items.len() as f32;
+        assert!(mean.abs() < 0.05);
+        assert!((var - 1.0).abs() < 0.05);
+    }
+}
@@ -0,0 +1,780 @@
+use crate::traits::{Conv1dConfig, Conv2dConfig};
+use crate::SmeltError;
+use std::ops::{Bound, RangeBounds};
+
+/// The row major strides of `shape`.
+pub(crate) fn strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for i in (0..shape.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * shape[i + 1];
+    }
+    strides
+}
+
+/// The strides to read `a` as the shape `b`, with the broadcasting rules of NumPy: the
+/// dimensions are aligned on the right, the missing dimensions and the dimensions of 1
+/// of `a` are repeated with a stride of 0. Fails when `a` does not broadcast to `b`.
+pub(crate) fn broadcast_strides(a: &[usize], b: &[usize]) -> Result<Vec<usize>, SmeltError> {
+    expand(a, &strides(a), b)
+}
+
+/// [broadcast_strides] of the items of `shape` read with `strides`, like a view.
+pub(crate) fn expand(
+    shape: &[usize],
+    strides: &[usize],
+    to: &[usize],
+) -> Result<Vec<usize>, SmeltError> {
+    let mismatch = || SmeltError::DimensionMismatch {
+        expected: to.to_vec(),
+        got: shape.to_vec(),
+    };
+    if shape.len() > to.len() {
+        return Err(mismatch());
+    }
+    let offset = to.len() - shape.len();
+    let mut out = vec![0; to.len()];
+    for (i, (&dim, &stride)) in shape.iter().zip(strides).enumerate() {
+        match dim {
+            _ if dim == to[offset + i] => out[offset + i] = stride,
+            1 => (),
+            _ => return Err(mismatch()),
+        }
+    }
+    Ok(out)
+}
+
+/// The shape of `shape` repeated `repeats` times along every dimension, the extra
+/// repeats add dimensions on the left like torch. Also returns the `[shape..., strides...]`
+/// of a view of the contiguous items giving the same items: every dimension preceded by
+/// its repeats, with a stride of 0.
+pub(crate) fn repeat(
+    shape: &[usize],
+    repeats: &[usize],
+) -> Result<(Vec<usize>, Vec<usize>), SmeltError> {
+    if repeats.len() < shape.len() {
+        return Err(SmeltError::InvalidLength {
+            expected: shape.len(),
+            got: repeats.len(),
+        });
+    }
+    let mut padded = vec![1; repeats.len() - shape.len()];
+    padded.extend_from_slice(shape);
+    let out = padded.iter().zip(repeats).map(|(d, r)| d * r).collect();
+    let mut view_shape = Vec::with_capacity(4 * padded.len());
+    let mut view_strides = Vec::with_capacity(2 * padded.len());
+    for ((&dim, stride), &r) in padded.iter().zip(strides(&padded)).zip(repeats) {
+        view_shape.extend([r, dim]);
+        view_strides.extend([0, stride]);
+    }
+    view_shape.extend(view_strides);
+    Ok((out, view_shape))
+}
+
+/// Whether `a` broadcasts to `b` by repeating it as a whole, like a bias over rows.
+pub(crate) fn is_repeated(a: &[usize], b: &[usize]) -> bool {
+    let a = match a.iter().position(|&d| d != 1) {
+        Some(start) => &a[start..],
+        None => &[],
+    };
+    a.len() <= b.len() && b.ends_with(a)
+}
+
+/// Checks that `start..start + len` fits in the dimension `dim` of `shape`, and returns
+/// the sizes `(outer, size, inner)` of the dimensions before `dim`, of `dim` and after it.
+pub(crate) fn narrow(
+    shape: &[usize],
+    dim: usize,
+    start: usize,
+    len: usize,
+) -> Result<(usize, usize, usize), SmeltError> {
+    if dim >= shape.len() {
+        return Err(SmeltError::InsufficientRank {
+            minimum_rank: dim + 1,
+        });
+    }
+    let size = shape[dim];
+    if start + len > size {
+        return Err(SmeltError::OutOfRange {
+            dim,
+            end: start + len,
+            size,
+        });
+    }
+    let outer = shape[..dim].iter().product();
+    let inner = shape[dim + 1..].iter().product();
+    Ok((outer, size, inner))
+}
+
+/// The sizes `(outer, size, inner)` of [narrow] for a reduction of the dimension `dim`,
+/// then the shape of its output: `dim` is removed, or kept with a size of 1.
+pub(crate) fn reduce(
+    shape: &[usize],
+    dim: usize,
+    keepdim: bool,
+) -> Result<(usize, usize, usize, Vec<usize>), SmeltError> {
+    let (outer, size, inner) = narrow(shape, dim, 0, 0)?;
+    let mut out = shape.to_vec();
+    if keepdim {
+        out[dim] = 1;
+    } else {
+        out.remove(dim);
+    }
+    Ok((outer, size, inner, out))
+}
+
+/// The shape of the concatenation of `shapes` along `dim`, they must match on the other
+/// dimensions.
+pub(crate) fn cat(shapes: &[&[usize]], dim: usize) -> Result<Vec<usize>, SmeltError> {
+    let first = shapes
+        .first()
+        .ok_or(SmeltError::VectorTooSmall { minimum: 1 })?;
+    if dim >= first.len() {
+        return Err(SmeltError::InsufficientRank {
+            minimum_rank: dim + 1,
+        });
+    }
+    let mut out = first.to_vec();
+    out[dim] = 0;
+    for shape in shapes {
+        let matches = shape.len() == first.len()
+            && shape
+                .iter()
+                .zip(first.iter())
+                .enumerate()
+                .all(|(d, (a, b))| d == dim || a == b);
+        if !matches {
+            let mut expected = first.to_vec();
+            expected[dim] = shape.get(dim).copied().unwrap_or(0);
+            return Err(SmeltError::DimensionMismatch {
+                expected,
+                got: shape.to_vec(),
+            });
+        }
+        out[dim] += shape[dim];
+    }
+    Ok(out)
+}
+
+/// The `(start, len)` of the parts of `sizes` in the dimension `dim` of `shape`, they
+/// must cover it exactly.
+pub(crate) fn split(
+    shape: &[usize],
+    dim: usize,
+    sizes: &[usize],
+) -> Result<Vec<(usize, usize)>, SmeltError> {
+    let size = *shape.get(dim).ok_or(SmeltError::InsufficientRank {
+        minimum_rank: dim + 1,
+    })?;
+    let total: usize = sizes.iter().sum();
+    if total != size {
+        return Err(SmeltError::InvalidLength {
+            expected: size,
+            got: total,
+        });
+    }
+    let mut start = 0;
+    Ok(sizes
+        .iter()
+        .map(|&len| {
+            start += len;
+            (start - len, len)
+        })
+        .collect())
+}
+
+/// The sizes of `chunks` parts of the same size except the last one, smaller, like torch.
+/// There are less parts when `size` is too small.
+pub(crate) fn chunk(size: usize, chunks: usize) -> Result<Vec<usize>, SmeltError> {
+    if chunks == 0 {
+        return Err(SmeltError::VectorTooSmall { minimum: 1 });
+    }
+    let len = size.div_ceil(chunks).max(1);
+    Ok((0..size)
+        .step_by(len)
+        .map(|start| len.min(size - start))
+        .collect())
+}
+
+/// The shape of `shape` permuted by `dims`, and the strides to read it in that order.
+/// `dims` must contain every dimension once.
+pub(crate) fn permute(
+    shape: &[usize],
+    dims: &[usize],
+) -> Result<(Vec<usize>, Vec<usize>), SmeltError> {
+    if dims.len() != shape.len() {
+        return Err(SmeltError::InvalidRank {
+            expected_rank: dims.len(),
+        });
+    }
+    let mut seen = vec![false; dims.len()];
+    for &d in dims {
+        if d >= dims.len() || seen[d] {
+            return Err(SmeltError::DimensionMismatch {
+                expected: (0..dims.len()).collect(),
+                got: dims.to_vec(),
+            });
+        }
+        seen[d] = true;
+    }
+    let strides = strides(shape);
+    Ok((
+        dims.iter().map(|&d| shape[d]).collect(),
+        dims.iter().map(|&d| strides[d]).collect(),
+    ))
+}
+
+/// The dimensions `0..rank` with `dim0` and `dim1` swapped.
+pub(crate) fn transpose(rank: usize, dim0: usize, dim1: usize) -> Vec<usize> {
+    let mut dims: Vec<usize> = (0..rank).collect();
+    if dim0 < rank && dim1 < rank {
+        dims.swap(dim0, dim1);
+    }
+    dims
+}
+
+/// Whether the items of `shape` read with `strides` are in row major order, the
+/// dimensions of 1 can have any stride.
+pub(crate) fn is_contiguous(shape: &[usize], strides: &[usize]) -> bool {
+    let mut expected = 1;
+    for (&dim, &stride) in shape.iter().zip(strides).rev() {
+        if dim != 1 && stride != expected {
+            return false;
+        }
+        expected *= dim;
+    }
+    true
+}
+
+/// Checks the rotary embedding of `x` (..., sequence_length, head_dim) by the tables
+/// `cos` and `sin` (max_positions, head_dim / 2) from the position `offset`, and returns
+/// `(sequence_length, head_dim / 2)`.
+pub(crate) fn rotary(
+    x: &[usize],
+    cos: &[usize],
+    sin: &[usize],
+    offset: usize,
+) -> Result<(usize, usize), SmeltError> {
+    let rank = x.len();
+    if rank < 2 {
+        return Err(SmeltError::InsufficientRank { minimum_rank: 2 });
+    }
+    let (length, head_dim) = (x[rank - 2], x[rank - 1]);
+    if head_dim % 2 != 0 {
+        return Err(SmeltError::InvalidConfig(format!(
+            "odd head dimension {head_dim}"
+        )));
+    }
+    match cos {
+        &[positions, half] if half == head_dim / 2 && cos == sin => {
+            narrow(cos, 0, offset, length)?;
+            Ok((length, half))
+        }
+        _ => Err(SmeltError::DimensionMismatch {
+            expected: vec![cos.first().copied().unwrap_or(0), head_dim / 2],
+            got: if cos == sin {
+                cos.to_vec()
+            } else {
+                sin.to_vec()
+            },
+        }),
+    }
+}
+
+/// Checks the split of the columns `offset..offset + num_src_heads * head_dim` of `src`
+/// (sequence_length, width) into `heads` (num_heads, sequence_length, head_dim), every
+/// head of `src` being repeated for `num_heads / num_src_heads` heads, and returns
+/// `width`. The merge of `heads` back into `src` is checked with `num_src_heads =
+/// num_heads`.
+pub(crate) fn heads(
+    src: &[usize],
+    offset: usize,
+    num_src_heads: usize,
+    heads: &[usize],
+) -> Result<usize, SmeltError> {
+    let &[num_heads, sequence_length, head_dim] = heads else {
+        return Err(SmeltError::InvalidRank { expected_rank: 3 });
+    };
+    if num_src_heads == 0 || num_heads % num_src_heads != 0 {
+        return Err(SmeltError::InvalidConfig(format!(
+            "{num_heads} heads can't share {num_src_heads} heads"
+        )));
+    }
+    let end = offset + num_src_heads * head_dim;
+    match *src {
+        [length, width] if length == sequence_length && end <= width => Ok(width),
+        _ => Err(SmeltError::DimensionMismatch {
+            expected: vec![sequence_length, end],
+            got: src.to_vec(),
+        }),
+    }
+}
+
+/// Checks the merge of `heads` (num_heads, sequence_length, head_dim) into the columns
+/// starting at `offset` of `dst`, see [heads()], and returns the width of `dst`.
+pub(crate) fn unsplit_heads(
+    heads_shape: &[usize],
+    offset: usize,
+    dst: &[usize],
+) -> Result<usize, SmeltError> {
+    let num_heads = heads_shape.first().copied().unwrap_or(0);
+    heads(dst, offset, num_heads, heads_shape)
+}
+
+/// The number of heads of `src` (sequence_length, num_kv_heads * head_dim), all its
+/// columns being split into `heads` by [heads()].
+pub(crate) fn kv_heads(src: &[usize], heads_shape: &[usize]) -> Result<usize, SmeltError> {
+    let num_heads = heads_shape.first().copied().unwrap_or(0);
+    let head_dim = heads_shape.get(2).copied().unwrap_or(0).max(1);
+    let width = src.get(1).copied().unwrap_or(0);
+    let num_kv_heads = if width % head_dim == 0 {
+        width / head_dim
+    } else {
+        num_heads
+    };
+    if heads(src, 0, num_kv_heads, heads_shape)? != num_kv_heads * head_dim {
+        return Err(SmeltError::DimensionMismatch {
+            expected: vec![src[0], num_heads * head_dim],
+            got: src.to_vec(),
+        });
+    }
+    Ok(num_kv_heads)
+}
+
+/// Checks the shapes of the 1d convolution of `x` by `weight`, see
+/// [crate::traits::TensorConv1d], and returns the shape of the output.
+pub(crate) fn conv1d(
+    x: &[usize],
+    weight: &[usize],
+    bias: Option<&[usize]>,
+    config: &Conv1dConfig,
+) -> Result<Vec<usize>, SmeltError> {
+    if config.stride == 0 || config.dilation == 0 {
+        return Err(SmeltError::InvalidConfig(format!("{config:?}")));
+    }
+    conv_channels(x, weight, bias, config.groups, 3)?;
+    let length = config
+        .output_length(x[2], weight[2])
+        .ok_or_else(|| SmeltError::InvalidConfig(format!("kernel larger than {x:?}")))?;
+    Ok(vec![x[0], weight[0], length])
+}
+
+/// Checks the shapes of the 2d convolution of `x` by `weight`, see
+/// [crate::traits::TensorConv2d], and returns the shape of the output.
+pub(crate) fn conv2d(
+    x: &[usize],
+    weight: &[usize],
+    bias: Option<&[usize]>,
+    config: &Conv2dConfig,
+) -> Result<Vec<usize>, SmeltError> {
+    if config.stride.contains(&0) || config.dilation.contains(&0) {
+        return Err(SmeltError::InvalidConfig(format!("{config:?}")));
+    }
+    conv_channels(x, weight, bias, config.groups, 4)?;
+    let [height, width] = config
+        .output_size([x[2], x[3]], [weight[2], weight[3]])
+        .ok_or_else(|| SmeltError::InvalidConfig(format!("kernel larger than {x:?}")))?;
+    Ok(vec![x[0], weight[0], height, width])
+}
+
+/// Checks that `x` (batch_size, in_channels, ...) and `weight` (out_channels,
+/// in_channels / groups, ...) have the rank `rank`, and `bias` the shape (out_channels).
+fn conv_channels(
+    x: &[usize],
+    weight: &[usize],
+    bias: Option<&[usize]>,
+    groups: usize,
+    rank: usize,
+) -> Result<(), SmeltError> {
+    if x.len() != rank || weight.len() != rank {
+        return Err(SmeltError::InvalidRank {
+            expected_rank: rank,
+        });
+    }
+    if groups == 0 {
+        return Err(SmeltError::InvalidConfig("groups of 0".into()));
+    }
+    let (in_channels, out_channels) = (x[1], weight[0]);
+    let mut expected = weight.to_vec();
+    expected[1] = in_channels / groups;
+    if in_channels % groups != 0 || out_channels % groups != 0 || weight != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: weight.to_vec(),
+        });
+    }
+    match bias {
+        Some(bias) if bias != [out_channels] => Err(SmeltError::DimensionMismatch {
+            expected: vec![out_channels],
+            got: bias.to_vec(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The shape of `shape` padded with `padding`, the `(before, after)` items added to each
+/// of its last `padding.len()` dimensions, negative values cropping instead. Also returns
+/// the items added before every dimension.
+pub(crate) fn pad(
+    shape: &[usize],
+    padding: &[(isize, isize)],
+) -> Result<(Vec<usize>, Vec<isize>), SmeltError> {
+    if padding.len() > shape.len() {
+        return Err(SmeltError::InsufficientRank {
+            minimum_rank: padding.len(),
+        });
+    }
+    let mut full = vec![(0, 0); shape.len() - padding.len()];
+    full.extend_from_slice(padding);
+    let out = shape
+        .iter()
+        .zip(&full)
+        .map(|(&size, &(before, after))| usize::try_from(size as isize + before + after))
+        .collect::<Result<_, _>>()
+        .map_err(|_| SmeltError::InvalidConfig(format!("padding {padding:?} of {shape:?}")))?;
+    Ok((out, full.into_iter().map(|(before, _)| before).collect()))
+}
+
+/// Checks that `new_shape` holds as many items as `shape`.
+pub(crate) fn reshape(shape: &[usize], new_shape: Vec<usize>) -> Result<Vec<usize>, SmeltError> {
+    let numel: usize = shape.iter().product();
+    if numel != new_shape.iter().product::<usize>() {
+        return Err(SmeltError::InvalidBuffer {
+            buffer_size: numel,
+            shape: new_shape,
+        });
+    }
+    Ok(new_shape)
+}
+
+/// Checks that the `indices` exist in the dimension `dim` of `shape`, and returns the
+/// sizes `(outer, size, inner)` like [narrow].
+pub(crate) fn index_select(
+    shape: &[usize],
+    dim: usize,
+    indices: &[usize],
+) -> Result<(usize, usize, usize), SmeltError> {
+    let (outer, size, inner) = narrow(shape, dim, 0, 0)?;
+    if let Some(&index) = indices.iter().find(|&&i| i >= size) {
+        return Err(SmeltError::OutOfRange {
+            dim,
+            end: index + 1,
+            size,
+        });
+    }
+    Ok((outer, size, inner))
+}
+
+/// Checks that `values` fill the items `indices` of the dimension `dim` of `shape`, and
+/// returns the sizes `(outer, size, inner)` like [narrow].
+pub(crate) fn index_put(
+    shape: &[usize],
+    dim: usize,
+    indices: &[usize],
+    values: &[usize],
+) -> Result<(usize, usize, usize), SmeltError> {
+    let sizes = index_select(shape, dim, indices)?;
+    let mut expected = shape.to_vec();
+    expected[dim] = indices.len();
+    if values != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: values.to_vec(),
+        });
+    }
+    Ok(sizes)
+}
+
+/// Checks that `values` fill the items `start..start + values[dim]` of the dimension
+/// `dim` of `shape`, and returns the sizes `(outer, size, inner)` like [narrow].
+pub(crate) fn narrow_put(
+    shape: &[usize],
+    dim: usize,
+    start: usize,
+    values: &[usize],
+) -> Result<(usize, usize, usize), SmeltError> {
+    let len = values.get(dim).copied().unwrap_or(0);
+    let sizes = narrow(shape, dim, start, len)?;
+    let mut expected = shape.to_vec();
+    expected[dim] = len;
+    if values != expected {
+        return Err(SmeltError::DimensionMismatch {
+            expected,
+            got: values.to_vec(),
+        });
+    }
+    Ok(sizes)
+}
+
+/// Checks that `mask` is the trailing dimensions of `shape`.
+pub(crate) fn check_mask(shape: &[usize], mask: &[usize]) -> Result<(), SmeltError> {
+    let rank = mask.len();
+    if rank == 0 || rank > shape.len() || shape[shape.len() - rank..] != *mask {
+        let rank = rank.clamp(1, shape.len().max(1));
+        return Err(SmeltError::DimensionMismatch {
+            expected: shape[shape.len().saturating_sub(rank)..].to_vec(),
+            got: mask.to_vec(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks the heads of a fused attention, `query` and `out` (num_heads, query_length,
+/// head_dim), `key` and `value` (num_heads, key_length, head_dim), the `mask`
+/// (key_length) and the `slopes` (num_heads), and returns (query_length, key_length,
+/// head_dim).
+pub(crate) fn attention(
+    query: &[usize],
+    key: &[usize],
+    value: &[usize],
+    mask: Option<&[usize]>,
+    slopes: Option<&[usize]>,
+    out: &[usize],
+) -> Result<(usize, usize, usize), SmeltError> {
+    let &[num_heads, query_length, head_dim] = query else {
+        return Err(SmeltError::InvalidRank { expected_rank: 3 });
+    };
+    let key_length = key.get(1).copied().unwrap_or(0);
+    let expected = [
+        vec![num_heads, key_length, head_dim],
+        vec![num_heads, key_length, head_dim],
+        query.to_vec(),
+        vec![key_length],
+        vec![num_heads],
+    ];
+    for (expected, got) in
+        expected
+            .into_iter()
+            .zip([Some(key), Some(value), Some(out), mask, slopes])
+    {
+        match got {
+            Some(got) if got != expected => {
+                return Err(SmeltError::DimensionMismatch {
+                    expected,
+                    got: got.to_vec(),
+                })
+            }
+            _ => (),
+        }
+    }
+    Ok((query_length, key_length, head_dim))
+}
+
+/// Checks the relative scores of DeBERTa added to `scores` (num_heads, query_length,
+/// key_length), `c2p` (num_heads, query_length, num_positions), `p2c` (num_heads,
+/// num_positions, key_length) and the `indices` in `0..num_positions` of every query and
+/// key, and returns (query_length, key_length, num_positions).
+pub(crate) fn relative_scores(
+    c2p: &[usize],
+    p2c: &[usize],
+    indices: &[usize],
+    scores: &[usize],
+) -> Result<(usize, usize, usize), SmeltError> {
+    let &[num_heads, query_length, key_length] = scores else {
+        return Err(SmeltError::InvalidRank { expected_rank: 3 });
+    };
+    let num_positions = c2p.get(2).copied().unwrap_or(0);
+    let expected = [
+        vec![num_heads, query_length, num_positions],
+        vec![num_heads, num_positions, key_length],
+    ];
+    for (expected, got) in expected.into_iter().zip([c2p, p2c]) {
+        if got != expected {
+            return Err(SmeltError::DimensionMismatch {
+                expected,
+                got: got.to_vec(),
+            });
+        }
+    }
+    if indices.len() != query_length * key_length {
+        return Err(SmeltError::InvalidLength {
+            expected: query_length * key_length,
+            got: indices.len(),
+        });
+    }
+    if let Some(&id) = indices.iter().find(|&&i| i >= num_positions) {
+        return Err(SmeltError::OutOfVocabulary {
+            vocab_size: num_positions,
+            id,
+        });
+    }
+    Ok((query_length, key_length, num_positions))
+}
+
+/// The `(start, len)` of `range` in a dimension of `size`.
+pub(crate) fn range(range: impl RangeBounds<usize>, size: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end + 1,
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => size,
+    };
+    (start, end.saturating_sub(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast_strides() {
+        assert_eq!(strides(&[2, 3, 4]), [12, 4, 1]);
+        assert_eq!(broadcast_strides(&[3], &[2, 3]).unwrap(), [0, 1]);
+        assert_eq!(broadcast_strides(&[2, 1], &[2, 3]).unwrap(), [1, 0]);
+        assert_eq!(
+            broadcast_strides(&[1, 3, 1], &[2, 3, 4]).unwrap(),
+            [0, 1, 0]
+        );
+        assert!(broadcast_strides(&[2], &[2, 3]).is_err());
+        assert!(broadcast_strides(&[2, 3], &[3]).is_err());
+
+        assert_eq!(expand(&[2, 1], &[1, 3], &[4, 2, 5]).unwrap(), [0, 1, 0]);
+        assert!(expand(&[2, 2], &[2, 1], &[2, 3]).is_err());
+
+        assert!(is_repeated(&[1, 3], &[2, 3]));
+        assert!(is_repeated(&[1], &[2, 3]));
+        assert!(!is_repeated(&[2, 1], &[2, 3]));
+    }
+
+    #[test]
+    fn test_repeat() {
+        let (shape, info) = repeat(&[2, 3], &[4, 1, 2]).unwrap();
+        assert_eq!(shape, [4, 2, 6]);
+        assert_eq!(info, [4, 1, 1, 2, 2, 3, 0, 6, 0, 3, 0, 1]);
+        assert!(repeat(&[2, 3], &[2]).is_err());
+    }
+
+    #[test]
+    fn test_narrow() {
+        assert_eq!(narrow(&[2, 3, 4], 1, 1, 2).unwrap(), (2, 3, 4));
+        assert!(narrow(&[2, 3, 4], 1, 2, 2).is_err());
+        assert!(narrow(&[2, 3, 4], 3, 0, 1).is_err());
+        assert_eq!(range(1..=2, 4), (1, 2));
+        assert_eq!(range(..3, 4), (0, 3));
+        assert_eq!(range(2.., 4), (2, 2));
+    }
+
+    #[test]
+    fn test_pad() {
+        let (out, padding) = pad(&[2, 3], &[(1, -2)]).unwrap();
+        assert_eq!(out, [2, 2]);
+        assert_eq!(padding, [0, 1]);
+        assert_eq!(pad(&[2, 3], &[(1, 1), (0, 2)]).unwrap().0, [4, 5]);
+        assert!(pad(&[2, 3], &[(0, -4)]).is_err());
+        assert!(pad(&[3], &[(0, 0), (0, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_reduce() {
+        assert_eq!(reduce(&[2, 3, 4], 1, false).unwrap(), (2, 3, 4, vec![2, 4]));
+        assert_eq!(reduce(&[2, 3, 4], 2, true).unwrap().3, [2, 3, 1]);
+        assert!(reduce(&[2, 3], 2, true).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split(&[2, 6], 1, &[2, 2, 2]).unwrap(),
+            [(0, 2), (2, 2), (4, 2)]
+        );
+        assert!(matches!(
+            split(&[2, 6], 1, &[2, 2]),
+            Err(SmeltError::InvalidLength {
+                expected: 6,
+                got: 4
+            })
+        ));
+        assert!(split(&[2, 6], 2, &[2]).is_err());
+        assert_eq!(chunk(6, 3).unwrap(), [2, 2, 2]);
+        assert_eq!(chunk(5, 3).unwrap(), [2, 2, 1]);
+        assert_eq!(chunk(2, 3).unwrap(), [1, 1]);
+        assert!(chunk(2, 0).is_err());
+    }
+
+    #[test]
+    fn test_permute() {
+        let (shape, strides) = permute(&[2, 3, 4], &[0, 2, 1]).unwrap();
+        assert_eq!(shape, [2, 4, 3]);
+        assert_eq!(strides, [12, 1, 4]);
+        assert!(permute(&[2, 3, 4], &[0, 1]).is_err());
+        assert!(permute(&[2, 3, 4], &[0, 1, 1]).is_err());
+        assert!(permute(&[2, 3, 4], &[0, 1, 3]).is_err());
+        assert_eq!(transpose(4, 1, 2), [0, 2, 1, 3]);
+
+        assert!(is_contiguous(&[2, 3], &[3, 1]));
+        assert!(is_contiguous(&[2, 1, 3], &[3, 7, 1]));
+        assert!(!is_contiguous(&[2, 3], &[1, 2]));
+        assert!(!is_contiguous(&[2, 2], &[4, 1]));
+    }
+
+    #[test]
+    fn test_conv1d() {
+        let config = Conv1dConfig::default();
+        assert_eq!(
+            conv1d(&[2, 4, 10], &[6, 4, 3], None, &config).unwrap(),
+            [2, 6, 8]
+        );
+        let config = Conv1dConfig {
+            stride: 2,
+            padding: 1,
+            dilation: 2,
+            groups: 2,
+        };
+        assert_eq!(
+            conv1d(&[1, 4, 10], &[6, 2, 3], Some(&[6]), &config).unwrap(),
+            [1, 6, 4]
+        );
+        assert!(conv1d(&[1, 4, 10], &[6, 4, 3], None, &config).is_err());
+        assert!(conv1d(&[1, 4, 10], &[6, 2, 3], Some(&[4]), &config).is_err());
+        assert!(conv1d(&[1, 4, 2], &[6, 2, 5], None, &config).is_err());
+        let config = Conv1dConfig {
+            stride: 0,
+            ..Default::default()
+        };
+        assert!(matches!(
+            conv1d(&[1, 4, 10], &[6, 4, 3], None, &config),
+            Err(SmeltError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let config = Conv2dConfig {
+            stride: [16, 16],
+            ..Default::default()
+        };
+        assert_eq!(
+            conv2d(&[1, 3, 224, 224], &[768, 3, 16, 16], Some(&[768]), &config).unwrap(),
+            [1, 768, 14, 14]
+        );
+        let config = Conv2dConfig {
+            stride: [1, 2],
+            padding: [1, 0],
+            dilation: [2, 1],
+            groups: 3,
+        };
+        assert_eq!(
+            conv2d(&[2, 3, 8, 9], &[6, 1, 3, 3], None, &config).unwrap(),
+            [2, 6, 6, 4]
+        );
+        assert!(conv2d(&[2, 3, 8, 9], &[6, 3, 3, 3], None, &config).is_err());
+        assert!(conv2d(&[2, 3, 8], &[6, 1, 3, 3], None, &config).is_err());
+        assert!(conv2d(&[2, 3, 2, 9], &[6, 1, 3, 3], None, &config).is_err());
+    }
+
+    #[test]
+    fn test_cat() {
+        assert_eq!(cat(&[&[2, 3], &[2, 1]], 1).unwrap(), [2, 4]);
+        assert_eq!(cat(&[&[2, 3], &[1, 3]], 0).unwrap(), [3, 3]);
+        assert!(matches!(
+            cat(&[&[2, 3], &[1, 3]], 1),
+            Err(SmeltError::DimensionMismatch { .. })
+        ));
+        assert!(cat(&[&[2, 3], &[2, 3, 1]], 0).is_err());
+        assert!(cat(&[&[2, 3]], 2).is_err());
+        assert!(cat(&[], 0).is_err());
+    }
+}
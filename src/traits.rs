@@ -1,4 +1,5 @@
 use crate::SmeltError;
+use std::sync::Arc;
 
 /// TODO
 pub trait Tensor: Clone {
@@ -16,21 +17,127 @@ pub trait Device {
     type Tensor: Tensor;
     /// TODO
     fn zeros(&self, shape: Vec<usize>) -> Result<Self::Tensor, SmeltError>;
+    /// A tensor from f32 cpu data, in the dtype of the device, like the tables the layers
+    /// precompute.
+    fn tensor(&self, data: &[f32], shape: Vec<usize>) -> Result<Self::Tensor, SmeltError>;
 }
 
-/// All common tensor operations
+/// A recorded sequence of operations, see [DeviceCapture].
+pub type Replay = Box<dyn Fn() -> Result<(), SmeltError> + Send>;
+
+/// Devices able to record the operations queued on them once, and to replay them on the
+/// same tensors without the launch overhead of every kernel (cuda graphs).
+pub trait DeviceCapture: Device {
+    /// Records the operations launched by `f`, without running them.
+    fn capture(&self, f: &mut dyn FnMut() -> Result<(), SmeltError>) -> Result<Replay, SmeltError>;
+}
+
+/// Tensors able to move between the devices of their backend (pipeline parallelism).
+pub trait TensorToDevice: Tensor {
+    /// A copy of the tensor on `device`.
+    fn to_device(&self, device: &Self::Device) -> Result<Self, SmeltError>;
+    /// Copies `src` into `dst`, which can live on another device. Defaults to replacing
+    /// `dst` with a new copy.
+    fn copy_to_device(src: &Self, dst: &mut Self) -> Result<(), SmeltError> {
+        *dst = src.to_device(dst.device())?;
+        Ok(())
+    }
+}
+
+/// The quantized weight of a [crate::nn::layers::Linear], applied to tensors `T`.
+pub trait QuantizedWeight<T>: Send + Sync {
+    /// The shape of the original weight, (out_features, in_features).
+    fn shape(&self) -> &[usize];
+    /// out = matmul(x, W.T) + bias, the bias being broadcasted over the rows. The static
+    /// weights hold their own quantized bias and ignore `bias`.
+    fn addmm(&self, x: &T, bias: &T, out: &mut T) -> Result<(), SmeltError>;
+}
+
+/// Records the ranges of the inputs and outputs of a [crate::nn::layers::Linear] over
+/// calibration runs, see [crate::nn::quantize::Quantizer].
+pub trait RangeObserver<T>: Send + Sync {
+    /// Records the largest absolute values of `input` and `output`.
+    fn observe(&self, input: &T, output: &T) -> Result<(), SmeltError>;
+    /// The largest absolute values seen so far, (input, output).
+    fn ranges(&self) -> (f32, f32);
+}
+
+/// Tensors whose matmul weights can be quantized to int8.
+pub trait TensorQuantize: Tensor {
+    /// Int8 weights with one scale per row. The activations are quantized on the fly,
+    /// row by row, and the outputs dequantized.
+    fn quantize_dynamic(weight: &Self) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError>;
+    /// A new observer of the activation ranges.
+    fn observer() -> Arc<dyn RangeObserver<Self>>;
+    /// Int8 weights, and the bias in i32, for inputs and outputs within the observed
+    /// `ranges`. The inputs are quantized with a fixed scale, the i32 accumulators are
+    /// requantized to int8 with the scale of the outputs.
+    fn quantize_static(
+        weight: &Self,
+        bias: &Self,
+        ranges: (f32, f32),
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError>;
+}
+
+/// The precision of the matmuls of the [crate::nn::layers::Linear] layers, see
+/// [TensorPrecision].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    /// The precision of the tensors
+    #[default]
+    F32,
+    /// Half precision
+    F16,
+    /// bfloat16
+    BF16,
+}
+
+/// Tensors whose matmul weights can run in a reduced [Precision], like the autocast of
+/// PyTorch: the inputs are rounded to `precision` for the gemm, the outputs are converted
+/// back and everything else (bias, layer norms, softmax) stays in the precision of `Self`.
+pub trait TensorPrecision: Tensor {
+    /// A copy of `weight` in `precision` (not [Precision::F32]).
+    fn cast_weight(
+        weight: &Self,
+        precision: Precision,
+    ) -> Result<Arc<dyn QuantizedWeight<Self>>, SmeltError>;
+}
+
+/// All common tensor operations, implemented by every backend tensor. The layers and
+/// models of [crate::nn] only use these, so they run on any backend and dtype.
+/// The composite operations have default implementations, backends can override them
+/// with fused kernels.
 pub trait TensorOps<T>:
     TensorCopy<T>
     + TensorMatmul<T>
     + TensorMatmulT<T>
     + TensorAdd<T>
     + TensorMul<T>
+    + TensorMulScalar<T>
     + TensorNormalize<T>
     + TensorSelect<T>
     + TensorGelu<T>
+    + TensorActivation<T>
     + TensorTanh<T>
     + TensorSoftmax<T>
 {
+    /// out = matmul(a, b.T) + bias, the bias being broadcasted over the rows.
+    fn addmm(a: &T, b: &T, bias: &T, out: &mut T) -> Result<(), SmeltError> {
+        Self::matmul_t(a, b, out)?;
+        Self::broadcast_add(bias, out)
+    }
+
+    /// x = normalize(x) * weight + bias on the last dimension.
+    fn layer_norm(x: &mut T, weight: &T, bias: &T, epsilon: f32) -> Result<(), SmeltError> {
+        Self::normalize(x, epsilon)?;
+        Self::broadcast_mul(weight, x)?;
+        Self::broadcast_add(bias, x)
+    }
+
+    /// Gathers the rows `ids` of `weight` into `out` (an embedding lookup).
+    fn gather(ids: &[usize], weight: &T, out: &mut T) -> Result<(), SmeltError> {
+        Self::select(ids, weight, out)
+    }
 }
 
 /// TODO
@@ -59,6 +166,14 @@ pub trait TensorAdd<T> {
     fn broadcast_add(a: &T, b: &mut T) -> Result<(), SmeltError>;
 }
 
+/// Elementwise subtraction, in place
+pub trait TensorSub<T> {
+    /// b -= a
+    fn sub(a: &T, b: &mut T) -> Result<(), SmeltError>;
+    /// b -= a, `a` being broadcasted to the shape of `b` like in NumPy
+    fn broadcast_sub(a: &T, b: &mut T) -> Result<(), SmeltError>;
+}
+
 /// TODO
 pub trait TensorMul<T> {
     /// TODO
@@ -67,6 +182,12 @@ pub trait TensorMul<T> {
     fn broadcast_mul(a: &T, b: &mut T) -> Result<(), SmeltError>;
 }
 
+/// Scaling of a whole tensor.
+pub trait TensorMulScalar<T> {
+    /// x = x * factor
+    fn mul_scalar(x: &mut T, factor: f32) -> Result<(), SmeltError>;
+}
+
 /// TODO
 pub trait TensorNormalize<T> {
     /// TODO
@@ -85,6 +206,56 @@ pub trait TensorGelu<T> {
     fn gelu(x: &mut T) -> Result<(), SmeltError>;
 }
 
+/// The activation functions of the feed-forward layers, parsed from the `hidden_act` of
+/// the transformers configurations.
+/// ```
+/// use smelte_rs::traits::Activation;
+///
+/// let activation: Activation = "gelu_pytorch_tanh".parse().unwrap();
+/// assert_eq!(activation, Activation::GeluNew);
+/// assert!("mish".parse::<Activation>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    /// The exact gelu `x * (1 + erf(x / sqrt(2))) / 2`, "gelu" (BERT)
+    Gelu,
+    /// The tanh approximation of gelu of [TensorGelu::gelu], "gelu_new" (GPT-2) or
+    /// "gelu_pytorch_tanh"
+    GeluNew,
+    /// The tanh approximation of gelu, with a faster tanh on the cpu, "gelu_fast"
+    GeluFast,
+    /// max(x, 0), "relu"
+    Relu,
+    /// x * sigmoid(x), "silu" or "swish" (LLaMA)
+    Silu,
+    /// x * sigmoid(1.702 * x), the sigmoid approximation of gelu, "quick_gelu" (CLIP)
+    QuickGelu,
+}
+
+impl std::str::FromStr for Activation {
+    type Err = SmeltError;
+
+    fn from_str(hidden_act: &str) -> Result<Self, Self::Err> {
+        match hidden_act {
+            "gelu" | "gelu_python" => Ok(Self::Gelu),
+            "gelu_new" | "gelu_pytorch_tanh" => Ok(Self::GeluNew),
+            "gelu_fast" => Ok(Self::GeluFast),
+            "relu" => Ok(Self::Relu),
+            "silu" | "swish" => Ok(Self::Silu),
+            "quick_gelu" => Ok(Self::QuickGelu),
+            _ => Err(SmeltError::InvalidConfig(format!(
+                "unsupported activation {hidden_act}"
+            ))),
+        }
+    }
+}
+
+/// The [Activation] functions
+pub trait TensorActivation<T> {
+    /// Applies `activation` to every item of `x`.
+    fn activation(x: &mut T, activation: Activation) -> Result<(), SmeltError>;
+}
+
 /// TODO
 pub trait TensorTanh<T> {
     /// TODO
@@ -96,3 +267,380 @@ pub trait TensorSoftmax<T> {
     /// TODO
     fn softmax(x: &mut T) -> Result<(), SmeltError>;
 }
+
+/// Softmax along any dimension, see [TensorSoftmax] for the last one
+pub trait TensorSoftmaxDim<T> {
+    /// x = softmax(x) along the dimension `dim`
+    fn softmax_dim(x: &mut T, dim: usize) -> Result<(), SmeltError>;
+    /// x = softmax(x / temperature) along the dimension `dim`. A temperature above 1
+    /// flattens the probabilities, below 1 it sharpens them.
+    fn softmax_t(x: &mut T, dim: usize, temperature: f32) -> Result<(), SmeltError>;
+}
+
+/// Cumulative sum along a dimension
+pub trait TensorCumsum<T> {
+    /// x = cumsum(x) along the dimension `dim`, every item becomes the sum of itself and
+    /// of the items before it.
+    fn cumsum(x: &mut T, dim: usize) -> Result<(), SmeltError>;
+}
+
+/// Padding with a constant value
+pub trait TensorPad<T> {
+    /// out = `x` padded with `value`, `padding` holding the `(before, after)` items added
+    /// to each of the last `padding.len()` dimensions. Negative values crop `x`.
+    fn pad(x: &T, padding: &[(isize, isize)], value: f32, out: &mut T) -> Result<(), SmeltError>;
+}
+
+/// The items of a contiguous tensor in another shape, like the patches of a convolution
+/// flattened into tokens
+pub trait TensorReshape<T> {
+    /// `x` with the shape `shape`, which must hold as many items. Nothing is copied.
+    fn reshape(x: T, shape: Vec<usize>) -> Result<T, SmeltError>;
+}
+
+/// Copies of a range of a dimension, like the tokens of a preallocated
+/// [crate::nn::layers::KvCache]
+pub trait TensorNarrow<T> {
+    /// The items `start..start + len` of the dimension `dim` of `x` copied in a new tensor,
+    /// the other dimensions being kept whole.
+    fn narrow(x: &T, dim: usize, start: usize, len: usize) -> Result<T, SmeltError>;
+    /// Writes `values` into the items `start..start + values.shape()[dim]` of the
+    /// dimension `dim` of `x`, the other dimensions of `values` being the ones of `x`.
+    fn narrow_put(x: &mut T, dim: usize, start: usize, values: &T) -> Result<(), SmeltError>;
+}
+
+/// Reductions along a dimension, which is removed from the shape of `out` or kept with
+/// a size of 1 when `keepdim` is set.
+pub trait TensorReduce<T> {
+    /// out = sum(x) along `dim`
+    fn sum(x: &T, dim: usize, keepdim: bool, out: &mut T) -> Result<(), SmeltError>;
+    /// out = mean(x) along `dim`
+    fn mean(x: &T, dim: usize, keepdim: bool, out: &mut T) -> Result<(), SmeltError>;
+    /// out = max(x) along `dim`
+    fn max(x: &T, dim: usize, keepdim: bool, out: &mut T) -> Result<(), SmeltError>;
+    /// out = var(x) along `dim`, the population variance (divided by the size of `dim`).
+    fn var(x: &T, dim: usize, keepdim: bool, out: &mut T) -> Result<(), SmeltError>;
+}
+
+/// Clamping of every item into a range
+pub trait TensorClamp<T> {
+    /// x = min(max(x, min), max), every item becomes `max` when `min > max` like in
+    /// torch.
+    fn clamp(x: &mut T, min: f32, max: f32) -> Result<(), SmeltError>;
+    /// x = max(x, min)
+    fn clamp_min(x: &mut T, min: f32) -> Result<(), SmeltError> {
+        Self::clamp(x, min, f32::INFINITY)
+    }
+    /// x = min(x, max)
+    fn clamp_max(x: &mut T, max: f32) -> Result<(), SmeltError> {
+        Self::clamp(x, f32::NEG_INFINITY, max)
+    }
+}
+
+/// Rotary position embeddings of the queries and keys
+pub trait TensorRotary<T> {
+    /// Rotates the pairs of items `(i, i + head_dim / 2)` of `x` (..., sequence_length,
+    /// head_dim) in place: the position `p` by the angles of the row `offset + p` of the
+    /// tables `cos` and `sin` (max_positions, head_dim / 2).
+    fn rotary(x: &mut T, cos: &T, sin: &T, offset: usize) -> Result<(), SmeltError>;
+}
+
+/// The masks and biases of a [TensorFusedAttention], computed from the positions of the
+/// queries and keys instead of read from (num_heads, query_length, key_length) tensors.
+/// The queries are the last `query_length` keys, the query `i` at the position
+/// `key_length - query_length + i`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FusedAttentionConfig {
+    /// The factor of the dot products of the queries and keys
+    pub scale: f32,
+    /// Whether the queries only attend to the keys up to their position
+    pub causal: bool,
+}
+
+impl Default for FusedAttentionConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            causal: false,
+        }
+    }
+}
+
+/// Scaled dot-product attention fused in a single pass over the keys, like flash
+/// attention: the scores of the queries are computed by tiles of keys with an online
+/// softmax, the (query_length, key_length) scores are never stored.
+pub trait TensorFusedAttention<T> {
+    /// out = softmax(scale * query key.T + bias) value, for `query` and `out` (num_heads,
+    /// query_length, head_dim) and `key` and `value` (num_heads, key_length, head_dim).
+    /// The bias of the query at the position `p` and the key `j` of the head `h` sums
+    /// the optional additive `mask` (key_length) of the keys, like the -inf of the pad
+    /// tokens, the [crate::nn::layers::Alibi] bias `-slopes[h] * |p - j|` of the optional
+    /// `slopes` (num_heads) and the causal mask of `config`.
+    fn fused_attention(
+        query: &T,
+        key: &T,
+        value: &T,
+        mask: Option<&T>,
+        slopes: Option<&T>,
+        config: &FusedAttentionConfig,
+        out: &mut T,
+    ) -> Result<(), SmeltError>;
+}
+
+/// The reshapes between the hidden states (sequence_length, width) and the attention
+/// heads (num_heads, sequence_length, head_dim), implemented for every backend next to
+/// the attention of [crate::nn::models::bert].
+pub trait TensorHeads<T: Tensor> {
+    /// Splits the columns `[offset, offset + num_src_heads * head_dim)` of `src` into the
+    /// heads of `dst`, like the queries of a fused projection. Every head of `src` is
+    /// repeated for `num_heads / num_src_heads` consecutive heads of `dst`, the keys and
+    /// values being shared by groups of queries (grouped-query attention).
+    fn split_heads_at(
+        src: &T,
+        offset: usize,
+        num_src_heads: usize,
+        dst: &mut T,
+    ) -> Result<(), SmeltError>;
+    /// Merges the heads of `src` into the columns starting at `offset` of `dst`.
+    fn unsplit_heads_at(src: &T, offset: usize, dst: &mut T) -> Result<(), SmeltError>;
+    /// Splits all the columns of `src` (sequence_length, num_kv_heads * head_dim) into the
+    /// heads of `dst`, see [TensorHeads::split_heads_at].
+    fn split_heads(src: &T, dst: &mut T) -> Result<(), SmeltError> {
+        let num_kv_heads = crate::shape::kv_heads(src.shape(), dst.shape())?;
+        Self::split_heads_at(src, 0, num_kv_heads, dst)
+    }
+    /// Merges the heads of `src` back into `dst` (sequence_length, hidden_dim).
+    fn unsplit_heads(src: &T, dst: &mut T) -> Result<(), SmeltError> {
+        Self::unsplit_heads_at(src, 0, dst)
+    }
+}
+
+/// Nucleus (top-p) filtering of the logits before sampling
+pub trait TensorTopP<T> {
+    /// Keeps the smallest set of the largest logits of every row (the last dimension)
+    /// whose softmax sums to at least `p`, the others become `-inf`. The largest logit is
+    /// always kept, equal logits are kept in order.
+    fn top_p(x: &mut T, p: f32) -> Result<(), SmeltError>;
+}
+
+/// Top-k filtering of the logits, before sampling or to route the tokens to experts
+pub trait TensorTopK<T> {
+    /// Keeps the `k` largest logits of every row (the last dimension), the others become
+    /// `-inf`. Equal logits are kept in order.
+    fn top_k(x: &mut T, k: usize) -> Result<(), SmeltError>;
+}
+
+/// The indices of the largest items, like the greedy decoding of the logits
+pub trait TensorArgmax<T> {
+    /// The index of the largest item along the dimension `dim`, for every other index.
+    /// Only the indices are copied back to the host, the first maximum wins.
+    fn argmax(x: &T, dim: usize) -> Result<Vec<usize>, SmeltError>;
+}
+
+/// Comparisons into the masks of [TensorMask], and the selection by a mask
+pub trait TensorCompare<T>: TensorMask<T> {
+    /// out = a > b, `a` and `b` being broadcasted to the shape of `out` like in NumPy.
+    fn gt(a: &T, b: &T, out: &mut Self::Mask) -> Result<(), SmeltError>;
+    /// out = a < b, see [TensorCompare::gt].
+    fn lt(a: &T, b: &T, out: &mut Self::Mask) -> Result<(), SmeltError>;
+    /// out = a == b, see [TensorCompare::gt].
+    fn eq(a: &T, b: &T, out: &mut Self::Mask) -> Result<(), SmeltError>;
+    /// out = a wherever `mask` is true, b elsewhere, everything being broadcasted to the
+    /// shape of `out`.
+    fn where_cond(mask: &Self::Mask, a: &T, b: &T, out: &mut T) -> Result<(), SmeltError>;
+}
+
+/// Elementwise math functions, applied in place to every item. The hyperbolic
+/// tangent is [TensorTanh].
+pub trait TensorUnary<T> {
+    /// x = exp(x)
+    fn exp(x: &mut T) -> Result<(), SmeltError>;
+    /// x = ln(x), the natural logarithm
+    fn log(x: &mut T) -> Result<(), SmeltError>;
+    /// x = sqrt(x)
+    fn sqrt(x: &mut T) -> Result<(), SmeltError>;
+    /// x = 1 / sqrt(x)
+    fn rsqrt(x: &mut T) -> Result<(), SmeltError>;
+    /// x = 1 / (1 + exp(-x))
+    fn sigmoid(x: &mut T) -> Result<(), SmeltError>;
+    /// x = erf(x), the error function of the exact gelu
+    fn erf(x: &mut T) -> Result<(), SmeltError>;
+    /// x = -x
+    fn neg(x: &mut T) -> Result<(), SmeltError>;
+    /// x = |x|
+    fn abs(x: &mut T) -> Result<(), SmeltError>;
+}
+
+/// The parameters of a [TensorConv1d], like the `nn.Conv1d` of torch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conv1dConfig {
+    /// The step between two positions of the kernel
+    pub stride: usize,
+    /// The zeros added on both sides of the input
+    pub padding: usize,
+    /// The step between two items of the kernel
+    pub dilation: usize,
+    /// The number of groups of channels convolved separately
+    pub groups: usize,
+}
+
+impl Default for Conv1dConfig {
+    fn default() -> Self {
+        Self {
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            groups: 1,
+        }
+    }
+}
+
+impl Conv1dConfig {
+    /// The length of the output for an input of `length` items, `None` when the kernel
+    /// is longer than the padded input.
+    /// ```
+    /// use smelte_rs::traits::Conv1dConfig;
+    ///
+    /// let config = Conv1dConfig { stride: 2, padding: 1, ..Default::default() };
+    /// assert_eq!(config.output_length(10, 3), Some(5));
+    /// ```
+    pub fn output_length(&self, length: usize, kernel_size: usize) -> Option<usize> {
+        let span = self.dilation * kernel_size.checked_sub(1)? + 1;
+        let length = (length + 2 * self.padding).checked_sub(span)?;
+        Some(length / self.stride.max(1) + 1)
+    }
+}
+
+/// 1d convolutions
+pub trait TensorConv1d<T> {
+    /// out = conv1d(x, weight) + bias, `x` being (batch_size, in_channels, length),
+    /// `weight` (out_channels, in_channels / groups, kernel_size), the optional `bias`
+    /// (out_channels) and `out` (batch_size, out_channels, output_length).
+    fn conv1d(
+        x: &T,
+        weight: &T,
+        bias: Option<&T>,
+        config: &Conv1dConfig,
+        out: &mut T,
+    ) -> Result<(), SmeltError>;
+}
+
+/// The parameters of a [TensorConv2d], like the `nn.Conv2d` of torch. The pairs are
+/// (height, width).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conv2dConfig {
+    /// The step between two positions of the kernel
+    pub stride: [usize; 2],
+    /// The zeros added on both sides of the input
+    pub padding: [usize; 2],
+    /// The step between two items of the kernel
+    pub dilation: [usize; 2],
+    /// The number of groups of channels convolved separately
+    pub groups: usize,
+}
+
+impl Default for Conv2dConfig {
+    fn default() -> Self {
+        Self {
+            stride: [1, 1],
+            padding: [0, 0],
+            dilation: [1, 1],
+            groups: 1,
+        }
+    }
+}
+
+impl From<Conv1dConfig> for Conv2dConfig {
+    /// The 1d convolution as a 2d one of height 1.
+    fn from(config: Conv1dConfig) -> Self {
+        Self {
+            stride: [1, config.stride],
+            padding: [0, config.padding],
+            dilation: [1, config.dilation],
+            groups: config.groups,
+        }
+    }
+}
+
+impl Conv2dConfig {
+    /// The parameters of the axis `i`, 0 for the height and 1 for the width.
+    pub fn axis(&self, i: usize) -> Conv1dConfig {
+        Conv1dConfig {
+            stride: self.stride[i],
+            padding: self.padding[i],
+            dilation: self.dilation[i],
+            groups: self.groups,
+        }
+    }
+
+    /// The size of the output for an input of `size`, see [Conv1dConfig::output_length].
+    /// ```
+    /// use smelte_rs::traits::Conv2dConfig;
+    ///
+    /// // The patches of ViT-B/16.
+    /// let config = Conv2dConfig { stride: [16, 16], ..Default::default() };
+    /// assert_eq!(config.output_size([224, 224], [16, 16]), Some([14, 14]));
+    /// ```
+    pub fn output_size(&self, size: [usize; 2], kernel_size: [usize; 2]) -> Option<[usize; 2]> {
+        Some([
+            self.axis(0).output_length(size[0], kernel_size[0])?,
+            self.axis(1).output_length(size[1], kernel_size[1])?,
+        ])
+    }
+}
+
+/// 2d convolutions
+pub trait TensorConv2d<T> {
+    /// out = conv2d(x, weight) + bias, `x` being (batch_size, in_channels, height,
+    /// width), `weight` (out_channels, in_channels / groups, kernel_height,
+    /// kernel_width), the optional `bias` (out_channels) and `out` (batch_size,
+    /// out_channels, output_height, output_width).
+    fn conv2d(
+        x: &T,
+        weight: &T,
+        bias: Option<&T>,
+        config: &Conv2dConfig,
+        out: &mut T,
+    ) -> Result<(), SmeltError>;
+}
+
+/// Masking with the boolean tensors of the backend, like [crate::cpu::Mask]
+pub trait TensorMask<T> {
+    /// The mask tensor of the backend
+    type Mask;
+    /// x = value wherever `mask` is true, the mask being repeated over the leading
+    /// dimensions of `x`.
+    fn masked_fill(x: &mut T, mask: &Self::Mask, value: f32) -> Result<(), SmeltError>;
+    /// Softmax on the last dimension, the masked positions getting a probability of 0.
+    fn masked_softmax(x: &mut T, mask: &Self::Mask) -> Result<(), SmeltError>;
+    /// The (length, length) mask of a causal attention, true above the diagonal where
+    /// the positions are in the future. Backends with a device memory cache it per
+    /// length.
+    fn causal_mask(length: usize, device: &Self::Device) -> Result<Self::Mask, SmeltError>
+    where
+        Self: Tensor;
+}
+
+/// Triangular parts of the matrices of the last two dimensions
+pub trait TensorTriangle<T> {
+    /// Zeroes the items (i, j) with `j - i > diagonal`, keeping the lower triangle.
+    fn tril(x: &mut T, diagonal: isize) -> Result<(), SmeltError>;
+    /// Zeroes the items (i, j) with `j - i < diagonal`, keeping the upper triangle.
+    fn triu(x: &mut T, diagonal: isize) -> Result<(), SmeltError>;
+}
+
+/// Masking of the attention scores from the positions of their queries and keys,
+/// computed by the kernels instead of read from a mask
+pub trait TensorPositionMask<T> {
+    /// x = -inf for the keys (the last dimension of `x`) hidden from the queries (the
+    /// dimension before it), the query `i` being at the position `offset + i` of the keys:
+    /// the keys from `length` on, the keys after it when `causal`, and the keys at
+    /// `sliding_window` positions or more from it.
+    fn position_mask(
+        x: &mut T,
+        offset: usize,
+        length: usize,
+        causal: bool,
+        sliding_window: Option<usize>,
+    ) -> Result<(), SmeltError>;
+}